@@ -2,6 +2,17 @@
 #include <common.glsl>
 
 layout(constant_id = 0) const float gamma = 2.2;
+// Quality settings, see QualitySettings
+layout(constant_id = 1) const int max_point_lights = 128;
+layout(constant_id = 2) const bool fog = true;
+// What to show instead of the lit scene, see DebugView
+layout(constant_id = 3) const int debug_view = 0;
+const int DEBUG_VIEW_LIT = 0;
+const int DEBUG_VIEW_ALBEDO = 1;
+const int DEBUG_VIEW_NORMALS = 2;
+const int DEBUG_VIEW_DEPTH = 3;
+const int DEBUG_VIEW_OVERDRAW = 4;
+const int DEBUG_VIEW_LIGHT_COUNT = 5;
 
 layout(location = 0) in vec3 v_normal;
 layout(location = 1) in vec3 v_frag_pos;
@@ -10,6 +21,8 @@ layout(location = 2) in vec3 v_view_pos;
 layout(location = 0) out vec4 f_color;
 
 layout(set = 1, binding = 0) uniform Lights {
+	// Scene wide ambient light, rgb is the color and a the intensity
+	vec4 ambient;
 	DirectionalLight dir_light;
 } lights;
 
@@ -19,6 +32,17 @@ layout(set = 1, binding = 1) readonly buffer PointLights {
 
 const float AMBIENT_STRENGHT = 0.2;
 
+// Distance fog, fading to the clear color
+const vec3 FOG_COLOR = vec3(0.0, 0.0, 0.0);
+const float FOG_DENSITY = 0.02;
+
+// Light count shown as red in the heatmap
+const float HEATMAP_MAX_LIGHTS = 16.0;
+// How quickly the depth view fades to black with distance
+const float DEPTH_VIEW_FALLOFF = 0.05;
+// Added for every fragment drawn in the overdraw view, so ten layers are white
+const float OVERDRAW_STEP = 0.1;
+
 const Material MATERIAL = Material(
 	vec3(1.0, 1.0, 1.0),	// Diffuse
 	vec3(1.0),				// Specular
@@ -35,11 +59,17 @@ vec3 calc_directional_light(DirectionalLight light, vec3 normal, vec3 view_dir)
 	vec3 reflect_dir = reflect(-light_dir, normal);
 	float spec = pow(max(dot(view_dir, reflect_dir), 0.0), MATERIAL.shininess);
 
-	vec3 ambient = light.ambient * AMBIENT_STRENGHT * MATERIAL.diffuse;
 	vec3 diffuse = light.diffuse * brightness * MATERIAL.diffuse;
 	vec3 specular = light.specular * spec * MATERIAL.specular;
 
-	return (ambient + diffuse + specular) * 0.5;
+	return (diffuse + specular) * 0.5;
+}
+
+// Blue for no lights, through green and yellow, to red for HEATMAP_MAX_LIGHTS or more
+vec3 heatmap(int count) {
+	float t = clamp(float(count) / HEATMAP_MAX_LIGHTS, 0.0, 1.0);
+	vec3 cold = mix(vec3(0.0, 0.0, 0.5), vec3(0.0, 1.0, 0.0), clamp(t * 2.0, 0.0, 1.0));
+	return mix(cold, vec3(1.0, 0.0, 0.0), clamp(t * 2.0 - 1.0, 0.0, 1.0));
 }
 
 vec3 calc_point_light(PointLight light, vec3 normal, vec3 view_dir, vec3 frag_pos) {
@@ -52,9 +82,11 @@ vec3 calc_point_light(PointLight light, vec3 normal, vec3 view_dir, vec3 frag_po
 	vec3 reflect_dir = reflect(-light_dir, normal);
 	float spec = pow(max(dot(view_dir, reflect_dir), 0.0), MATERIAL.shininess);
 
-	// Attenuation
+	// Attenuation, windowed so it reaches zero at the range of the light
 	float dist = length(light.position - frag_pos);
 	float attenuation = 1.0 / (light.constant + light.linear * dist + light.quadratic * (dist * dist));
+	float window = clamp(1.0 - pow(dist / light.range, 4.0), 0.0, 1.0);
+	attenuation *= window * window;
 
 	vec3 ambient = light.ambient * AMBIENT_STRENGHT * MATERIAL.diffuse * attenuation;
 	vec3 diffuse = light.diffuse * brightness * MATERIAL.diffuse * attenuation;
@@ -67,15 +99,48 @@ void main() {
 	vec3 view_dir = normalize(v_view_pos - v_frag_pos);
 	vec3 normal = normalize(v_normal);
 
-	vec3 color = vec3(0.0);
+	// Views that do not need the lighting
+	if (debug_view == DEBUG_VIEW_ALBEDO) {
+		f_color = vec4(MATERIAL.diffuse, 1.0);
+		return;
+	} else if (debug_view == DEBUG_VIEW_NORMALS) {
+		f_color = vec4(normal * 0.5 + 0.5, 1.0);
+		return;
+	} else if (debug_view == DEBUG_VIEW_DEPTH) {
+		float dist = length(v_view_pos - v_frag_pos);
+		f_color = vec4(vec3(exp(-dist * DEPTH_VIEW_FALLOFF)), 1.0);
+		return;
+	} else if (debug_view == DEBUG_VIEW_OVERDRAW) {
+		f_color = vec4(vec3(OVERDRAW_STEP), 1.0);
+		return;
+	}
+
+	vec3 color = lights.ambient.rgb * lights.ambient.a * MATERIAL.diffuse;
 
 	// Directinal light
 	color += calc_directional_light(lights.dir_light, normal, view_dir);
 
 	// Point lights
-	int num_point_lights = point_lights.lights.length();
-	for (int i = 0; i < num_point_lights; i++)
-		color += calc_point_light(point_lights.lights[i], normal, view_dir, v_frag_pos);
+	int num_point_lights = min(point_lights.lights.length(), max_point_lights);
+	int lights_in_range = 0;
+	for (int i = 0; i < num_point_lights; i++) {
+		PointLight light = point_lights.lights[i];
+		if (distance(light.position, v_frag_pos) < light.range) {
+			color += calc_point_light(light, normal, view_dir, v_frag_pos);
+			lights_in_range++;
+		}
+	}
+
+	if (debug_view == DEBUG_VIEW_LIGHT_COUNT) {
+		f_color = vec4(heatmap(lights_in_range), 1.0);
+		return;
+	}
+
+	if (fog) {
+		float dist = length(v_view_pos - v_frag_pos);
+		float visibility = exp(-pow(dist * FOG_DENSITY, 2.0));
+		color = mix(FOG_COLOR, color, visibility);
+	}
 
 	f_color = vec4(color, 1.0);
 }
@@ -10,6 +10,7 @@ struct Material {
 struct DirectionalLight {
     vec3 direction;
 
+    // Unused, ambient light comes from the Lights uniform
     vec3 ambient;
     vec3 diffuse;
     vec3 specular;
@@ -18,6 +19,8 @@ struct DirectionalLight {
 struct PointLight {
     vec3 position;
 
+    // Attenuation is 1 / (constant + linear * d + quadratic * d^2), inverse square lights only
+    // have a small constant term, and their intensity in the colors
     float constant;
     float linear;
     float quadratic;
@@ -26,6 +29,22 @@ struct PointLight {
     vec3 diffuse;
     vec3 specular;
 
-	// Padding
-	bool hh;
+	// Distance at which the light has faded out completely
+	float range;
+};
+
+// Bounding sphere of an object in world space (xyz = center, w = radius)
+// draw is the index count, first index, vertex offset and first instance to draw if the object is visible
+struct CullObject {
+    vec4 sphere;
+    uvec4 draw;
+};
+
+// Matches VkDrawIndexedIndirectCommand
+struct DrawIndexedIndirectCommand {
+    uint index_count;
+    uint instance_count;
+    uint first_index;
+    int vertex_offset;
+    uint first_instance;
 };
@@ -0,0 +1,10 @@
+#version 450
+#include <post.glsl>
+
+layout(location = 0) in vec2 v_uv;
+
+layout(location = 0) out vec4 f_color;
+
+void main() {
+	f_color = vec4(encode_output(grade(expose(texture(scene, v_uv).rgb))), 1.0);
+}
@@ -0,0 +1,58 @@
+#version 450
+#include <post.glsl>
+
+layout(location = 0) in vec2 v_uv;
+
+layout(location = 0) out vec4 f_color;
+
+const float REDUCE_MIN = 1.0 / 128.0;
+const float REDUCE_MUL = 1.0 / 8.0;
+const float SPAN_MAX = 8.0;
+
+// The scene is not tonemapped, so exposed colors are clamped to what ends up on screen
+vec3 fetch(vec2 uv) {
+	return clamp(expose(texture(scene, uv).rgb), 0.0, 1.0);
+}
+
+float luma(vec3 color) {
+	return dot(color, vec3(0.299, 0.587, 0.114));
+}
+
+void main() {
+	vec2 px = pc.inverse_size;
+
+	vec3 rgb_m = fetch(v_uv);
+	float luma_nw = luma(fetch(v_uv + vec2(-1.0, -1.0) * px));
+	float luma_ne = luma(fetch(v_uv + vec2(1.0, -1.0) * px));
+	float luma_sw = luma(fetch(v_uv + vec2(-1.0, 1.0) * px));
+	float luma_se = luma(fetch(v_uv + vec2(1.0, 1.0) * px));
+	float luma_m = luma(rgb_m);
+
+	float luma_min = min(luma_m, min(min(luma_nw, luma_ne), min(luma_sw, luma_se)));
+	float luma_max = max(luma_m, max(max(luma_nw, luma_ne), max(luma_sw, luma_se)));
+
+	// Blur along the edge, which is perpendicular to the luma gradient
+	vec2 dir = vec2(
+		-((luma_nw + luma_ne) - (luma_sw + luma_se)),
+		(luma_nw + luma_sw) - (luma_ne + luma_se)
+	);
+
+	float dir_reduce = max((luma_nw + luma_ne + luma_sw + luma_se) * (0.25 * REDUCE_MUL), REDUCE_MIN);
+	float rcp_dir_min = 1.0 / (min(abs(dir.x), abs(dir.y)) + dir_reduce);
+	dir = clamp(dir * rcp_dir_min, vec2(-SPAN_MAX), vec2(SPAN_MAX)) * px;
+
+	vec3 rgb_a = 0.5 * (
+		fetch(v_uv + dir * (1.0 / 3.0 - 0.5)) +
+		fetch(v_uv + dir * (2.0 / 3.0 - 0.5)));
+	vec3 rgb_b = rgb_a * 0.5 + 0.25 * (
+		fetch(v_uv + dir * -0.5) +
+		fetch(v_uv + dir * 0.5));
+
+	// The wider blur went past the edge if it is outside the local luma range
+	float luma_b = luma(rgb_b);
+	if (luma_b < luma_min || luma_b > luma_max) {
+		f_color = vec4(encode_output(grade(rgb_a)), 1.0);
+	} else {
+		f_color = vec4(encode_output(grade(rgb_b)), 1.0);
+	}
+}
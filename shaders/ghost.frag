@@ -0,0 +1,21 @@
+#version 450
+#include <common.glsl>
+
+layout(location = 0) in vec3 v_normal;
+layout(location = 1) in vec3 v_frag_pos;
+layout(location = 2) in vec3 v_view_pos;
+
+layout(location = 0) out vec4 f_color;
+
+const vec3 GHOST_COLOR = vec3(0.4, 0.7, 1.0);
+const float GHOST_ALPHA = 0.35;
+
+void main() {
+	vec3 view_dir = normalize(v_view_pos - v_frag_pos);
+	vec3 normal = normalize(v_normal);
+
+	// Brighten the edges a bit so the shape is readable without any lighting
+	float rim = 1.0 - abs(dot(normal, view_dir));
+
+	f_color = vec4(GHOST_COLOR * (0.6 + 0.4 * rim), GHOST_ALPHA);
+}
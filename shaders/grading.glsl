@@ -0,0 +1,11 @@
+// Color grading with a 3D lookup table
+
+layout(set = 0, binding = 1) uniform sampler3D lut;
+
+// Samples between the centers of the outermost texels, so the LUT is interpolated correctly
+vec3 grade(vec3 color) {
+	float size = float(textureSize(lut, 0).x);
+	vec3 uvw = clamp(color, 0.0, 1.0) * ((size - 1.0) / size) + 0.5 / size;
+
+	return texture(lut, uvw).rgb;
+}
@@ -0,0 +1,60 @@
+// The Hi-Z pyramid, for passes reading it after it has been built, see HiZPyramid
+//
+// The levels are images of their own, bound as an array of samplers with one per level, largest
+// first. Define HI_Z_SET and HI_Z_BINDING before including this.
+
+// Matches HI_Z_MAX_LEVELS, enough for depth buffers up to 16384 texels across. Shaders compiled at
+// runtime get it defined from there
+#ifndef HI_Z_MAX_LEVELS
+#define HI_Z_MAX_LEVELS 14
+#endif
+
+layout(set = HI_Z_SET, binding = HI_Z_BINDING) uniform sampler2D hi_z[HI_Z_MAX_LEVELS];
+
+// The farthest depth of the texels of a level within a rectangle of uv coordinates, which has to
+// be at most a texel of the level across
+float hi_z_farthest_in(sampler2D level, vec2 uv_min, vec2 uv_max, bool reversed) {
+	ivec2 size = textureSize(level, 0);
+	ivec2 first = clamp(ivec2(uv_min * vec2(size)), ivec2(0), size - 1);
+	ivec2 last = clamp(ivec2(uv_max * vec2(size)), ivec2(0), size - 1);
+
+	float a = texelFetch(level, first, 0).r;
+	float b = texelFetch(level, ivec2(last.x, first.y), 0).r;
+	float c = texelFetch(level, ivec2(first.x, last.y), 0).r;
+	float d = texelFetch(level, last, 0).r;
+
+	return reversed ? min(min(a, b), min(c, d)) : max(max(a, b), max(c, d));
+}
+
+// Levels are picked with constant indices, as indexing the array with anything else needs
+// device features that are not enabled
+#define HI_Z_LEVEL(i) case i: return hi_z_farthest_in(hi_z[i], uv_min, uv_max, reversed);
+
+// The farthest depth within a rectangle of uv coordinates at a level of the pyramid
+float hi_z_farthest(int level, vec2 uv_min, vec2 uv_max, bool reversed) {
+	switch (level) {
+	HI_Z_LEVEL(0)
+	HI_Z_LEVEL(1)
+	HI_Z_LEVEL(2)
+	HI_Z_LEVEL(3)
+	HI_Z_LEVEL(4)
+	HI_Z_LEVEL(5)
+	HI_Z_LEVEL(6)
+	HI_Z_LEVEL(7)
+	HI_Z_LEVEL(8)
+	HI_Z_LEVEL(9)
+	HI_Z_LEVEL(10)
+	HI_Z_LEVEL(11)
+	HI_Z_LEVEL(12)
+	HI_Z_LEVEL(13)
+	}
+
+	// Nothing is behind the far plane
+	return reversed ? 0.0 : 1.0;
+}
+
+// The first level of the pyramid at which a rectangle of uv coordinates is at most a texel across
+int hi_z_level(vec2 uv_min, vec2 uv_max) {
+	vec2 size = (uv_max - uv_min) * vec2(textureSize(hi_z[0], 0));
+	return int(ceil(log2(max(max(size.x, size.y), 1.0))));
+}
@@ -0,0 +1,75 @@
+#version 450
+
+// Screen space light shafts, "Volumetric Light Scattering as a Post-Process" by Mitchell in GPU
+// Gems 3. Every pixel marches towards the sun on the screen, and gathers the light of the sky
+// it passes over, which is scattered towards the camera by the air.
+
+layout(location = 0) in vec2 v_uv;
+
+layout(location = 0) out vec4 f_color;
+
+layout(set = 0, binding = 0) uniform sampler2D depth;
+
+layout(push_constant) uniform LightShaftsPushConstants {
+	// Where the view is in the depth buffer, as x, y, width and height in uv coordinates
+	vec4 viewport;
+	// Towards the sun in view space, w is unused
+	vec4 sun;
+	// Color of the directional light, a is unused
+	vec4 light;
+	// Where the sun is in the view, in uv coordinates of the view
+	vec2 sun_uv;
+	// Tangent of half the field of view, horizontally and vertically
+	vec2 tan_half_fov;
+	// How much light the air scatters towards the camera
+	float density;
+	// Henyey-Greenstein asymmetry, how much of the light is scattered forward
+	float scattering;
+	// The depth of the sky, what the depth buffer was cleared to
+	float far_depth;
+	// Steps towards the sun
+	uint samples;
+} pc;
+
+const float PI = 3.14159265;
+// How much every step weighs less than the one before it, so far away sky counts less
+const float DECAY = 0.97;
+
+float henyey_greenstein(float cos_theta, float g) {
+	float g2 = g * g;
+	return (1.0 - g2) / (4.0 * PI * pow(1.0 + g2 - 2.0 * g * cos_theta, 1.5));
+}
+
+void main() {
+	// Nothing to march towards with the sun behind the camera, which looks along -z
+	if (pc.sun.z >= 0.0 || pc.samples == 0) {
+		f_color = vec4(0.0);
+		return;
+	}
+
+	// The march stays inside the view, even when the sun is outside of it
+	vec2 step = (pc.sun_uv - v_uv) / float(pc.samples);
+	vec2 uv = v_uv;
+
+	float sky = 0.0;
+	float weight = 1.0;
+	float total = 0.0;
+	for (uint i = 0; i < pc.samples; i++) {
+		uv += step;
+		vec2 depth_uv = pc.viewport.xy + clamp(uv, 0.0, 1.0) * pc.viewport.zw;
+
+		float d = texture(depth, depth_uv).r;
+		sky += abs(d - pc.far_depth) < 1e-6 ? weight : 0.0;
+		total += weight;
+		weight *= DECAY;
+	}
+	sky /= total;
+
+	// The view direction of the pixel, with y flipped by the projection
+	vec2 ndc = v_uv * 2.0 - 1.0;
+	vec3 view_dir = normalize(vec3(ndc.x * pc.tan_half_fov.x, -ndc.y * pc.tan_half_fov.y, -1.0));
+	float phase = henyey_greenstein(dot(view_dir, pc.sun.xyz), pc.scattering);
+
+	// Added to the scene by the blending
+	f_color = vec4(pc.light.rgb * sky * phase * pc.density, 0.0);
+}
@@ -0,0 +1,22 @@
+#version 450
+#include <common.glsl>
+
+layout(location = 0) in vec3 position;
+layout(location = 1) in vec3 color;
+
+layout(location = 0) out vec3 v_color;
+
+layout(push_constant) uniform PushConstants {
+	mat4 view;
+	mat4 proj;
+} pc;
+
+layout(set = 0, binding = 0) uniform MVP {
+	mat4 model;
+} mvp;
+
+void main() {
+	v_color = color;
+
+    gl_Position = pc.proj * pc.view * mvp.model * vec4(position, 1.0);
+}
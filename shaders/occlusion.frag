@@ -0,0 +1,5 @@
+#version 450
+
+// Bounding boxes are only depth tested, nothing is written
+void main() {
+}
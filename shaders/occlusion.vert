@@ -0,0 +1,24 @@
+#version 450
+
+layout(push_constant) uniform OcclusionPushConstants {
+	// From a cube spanning -1 to 1 to clip space, for the bounding box of the mesh
+	mat4 mvp;
+} pc;
+
+// Two triangles for each face of the cube, as corners where bit 0 is x, bit 1 is y and bit 2 is z
+const int CORNERS[36] = int[36](
+	0, 2, 6, 0, 6, 4,
+	1, 3, 7, 1, 7, 5,
+	0, 1, 5, 0, 5, 4,
+	2, 3, 7, 2, 7, 6,
+	0, 1, 3, 0, 3, 2,
+	4, 5, 7, 4, 7, 6
+);
+
+// The bounding box of a mesh, drawn without any vertex buffer
+void main() {
+	int corner = CORNERS[gl_VertexIndex];
+	vec3 position = vec3(corner & 1, (corner >> 1) & 1, (corner >> 2) & 1) * 2.0 - 1.0;
+
+	gl_Position = pc.mvp * vec4(position, 1.0);
+}
@@ -0,0 +1,14 @@
+#version 450
+
+layout(location = 0) out vec4 f_color;
+
+layout(push_constant) uniform OutlinePushConstants {
+	mat4 view;
+	mat4 proj;
+	vec4 color;
+	vec2 thickness;
+} pc;
+
+void main() {
+	f_color = pc.color;
+}
@@ -0,0 +1,31 @@
+#version 450
+#include <common.glsl>
+
+layout(location = 0) in vec3 position;
+layout(location = 1) in vec3 normal;
+
+layout(push_constant) uniform OutlinePushConstants {
+	mat4 view;
+	mat4 proj;
+	vec4 color;
+	// Width of the outline in normalized device coordinates, along x and y
+	vec2 thickness;
+} pc;
+
+layout(set = 0, binding = 0) uniform MVP {
+	mat4 model;
+} mvp;
+
+void main() {
+	mat4 view_model = pc.view * mvp.model;
+	vec3 view_normal = mat3(transpose(inverse(view_model))) * normal;
+	vec2 screen_normal = (pc.proj * vec4(view_normal, 0.0)).xy;
+
+	gl_Position = pc.proj * view_model * vec4(position, 1.0);
+
+	// Pushed out along the normal on the screen, multiplied by w so the outline is the same number
+	// of pixels wide at any distance
+	if (length(screen_normal) > 0.0) {
+		gl_Position.xy += normalize(screen_normal) * pc.thickness * gl_Position.w;
+	}
+}
@@ -0,0 +1,18 @@
+#version 450
+#include <transfer.glsl>
+
+// How the swapchain is encoded, as for the post processing shaders
+layout(constant_id = 0) const int output_transfer = TRANSFER_GAMMA;
+layout(constant_id = 1) const float output_paper_white = 200.0;
+
+layout(set = 0, binding = 0) uniform sampler2DArray atlas;
+
+layout(location = 0) in vec3 v_uv;
+layout(location = 1) in vec4 v_color;
+
+layout(location = 0) out vec4 f_color;
+
+void main() {
+	vec4 color = texture(atlas, v_uv) * v_color;
+	f_color = vec4(encode_transfer(color.rgb, output_transfer, output_paper_white), color.a);
+}
@@ -0,0 +1,25 @@
+#version 450
+
+// In pixels from the top left of the window
+layout(location = 0) in vec2 position;
+layout(location = 1) in vec2 uv;
+// The page of the atlas
+layout(location = 2) in float layer;
+// Gamma encoded, like the scene
+layout(location = 3) in vec4 color;
+
+layout(location = 0) out vec3 v_uv;
+layout(location = 1) out vec4 v_color;
+
+layout(push_constant) uniform OverlayPushConstants {
+	// The size of a pixel in uv coordinates
+	vec2 inverse_size;
+} pc;
+
+void main() {
+	v_uv = vec3(uv, layer);
+	v_color = color;
+
+	// Vulkan's y points down already
+	gl_Position = vec4(position * pc.inverse_size * 2.0 - 1.0, 0.0, 1.0);
+}
@@ -0,0 +1,27 @@
+// Shared by the post processing shaders
+
+#include <grading.glsl>
+#include <transfer.glsl>
+
+layout(set = 0, binding = 0) uniform sampler2D scene;
+
+layout(push_constant) uniform PostPushConstants {
+	// The size of a pixel in uv coordinates
+	vec2 inverse_size;
+	// How the final color is encoded, see OutputTransfer
+	int transfer;
+	// How bright white is in nits, for HDR output
+	float paper_white;
+	// The exposure of the camera whose view is drawn
+	float exposure;
+} pc;
+
+// Scales the linear brightness of a gamma encoded scene color by the exposure
+vec3 expose(vec3 color) {
+	return color * pow(pc.exposure, 1.0 / GAMMA);
+}
+
+// Encodes a gamma encoded scene color for the swapchain
+vec3 encode_output(vec3 color) {
+	return encode_transfer(color, pc.transfer, pc.paper_white);
+}
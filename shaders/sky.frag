@@ -0,0 +1,101 @@
+#version 450
+
+// The Preetham daylight model, "A Practical Analytic Model for Daylight" by Preetham, Shirley
+// and Smits. Sky brightness and color are fitted to turbidity and the angles to the sun.
+
+layout(location = 0) in vec2 v_uv;
+
+layout(location = 0) out vec4 f_color;
+
+layout(push_constant) uniform SkyPushConstants {
+	// From normalized device coordinates to world space directions, the inverse of the
+	// projection and the rotation of the view
+	mat4 inverse_view_proj;
+	// Towards the sun, w is unused
+	vec4 sun_direction;
+	// Haziness of the air, from about 2 for a clear sky to 10 for a hazy one
+	float turbidity;
+	// Scales the luminance of the model, in kcd/m², to the brightness of the scene
+	float brightness;
+} pc;
+
+const float PI = 3.14159265;
+// Angular radius of the sun, a little larger than the real one
+const float SUN_RADIUS = 0.01;
+const float SUN_INTENSITY = 20.0;
+const vec3 GROUND_COLOR = vec3(0.08, 0.07, 0.06);
+
+// The distribution of the sky, for the angle from the zenith and the angle to the sun
+vec3 perez(float theta, float gamma, vec3 A, vec3 B, vec3 C, vec3 D, vec3 E) {
+	float cos_gamma = cos(gamma);
+	return (1.0 + A * exp(B / max(cos(theta), 0.01)))
+		* (1.0 + C * exp(D * gamma) + E * cos_gamma * cos_gamma);
+}
+
+// Luminance and chromaticity at the zenith
+vec3 zenith(float T, float theta_s) {
+	float chi = (4.0 / 9.0 - T / 120.0) * (PI - 2.0 * theta_s);
+	float Y = (4.0453 * T - 4.9710) * tan(chi) - 0.2155 * T + 2.4192;
+
+	vec3 t = vec3(theta_s * theta_s * theta_s, theta_s * theta_s, theta_s);
+	float x = T * T * dot(vec4(0.00166, -0.00375, 0.00209, 0.0), vec4(t, 1.0))
+		+ T * dot(vec4(-0.02903, 0.06377, -0.03202, 0.00394), vec4(t, 1.0))
+		+ dot(vec4(0.11693, -0.21196, 0.06052, 0.25886), vec4(t, 1.0));
+	float y = T * T * dot(vec4(0.00275, -0.00610, 0.00317, 0.0), vec4(t, 1.0))
+		+ T * dot(vec4(-0.04214, 0.08970, -0.04153, 0.00516), vec4(t, 1.0))
+		+ dot(vec4(0.15346, -0.26756, 0.06670, 0.26688), vec4(t, 1.0));
+
+	return vec3(Y, x, y);
+}
+
+vec3 yxy_to_rgb(vec3 Yxy) {
+	float Y = Yxy.x;
+	float X = Yxy.y / Yxy.z * Y;
+	float Z = (1.0 - Yxy.y - Yxy.z) / Yxy.z * Y;
+
+	return vec3(
+		3.2406 * X - 1.5372 * Y - 0.4986 * Z,
+		-0.9689 * X + 1.8758 * Y + 0.0415 * Z,
+		0.0557 * X - 0.2040 * Y + 1.0570 * Z
+	);
+}
+
+vec3 sky(vec3 direction, vec3 sun) {
+	float T = pc.turbidity;
+
+	vec3 A = vec3(0.1787 * T - 1.4630, -0.0193 * T - 0.2592, -0.0167 * T - 0.2608);
+	vec3 B = vec3(-0.3554 * T + 0.4275, -0.0665 * T + 0.0008, -0.0950 * T + 0.0092);
+	vec3 C = vec3(-0.0227 * T + 5.3251, -0.0004 * T + 0.2125, -0.0079 * T + 0.2102);
+	vec3 D = vec3(0.1206 * T - 2.5771, -0.0641 * T - 0.8989, -0.0441 * T - 1.6537);
+	vec3 E = vec3(-0.0670 * T + 0.3703, -0.0033 * T + 0.0452, -0.0109 * T + 0.0529);
+
+	// The model only holds for the sun above the horizon, below it the sky fades to night
+	float theta_s = min(acos(clamp(sun.y, -1.0, 1.0)), PI / 2.0 - 0.01);
+	float theta = acos(clamp(direction.y, 0.0, 1.0));
+	float gamma = acos(clamp(dot(direction, sun), -1.0, 1.0));
+
+	vec3 Yxy = zenith(T, theta_s)
+		* perez(theta, gamma, A, B, C, D, E)
+		/ perez(0.0, theta_s, A, B, C, D, E);
+
+	vec3 color = max(yxy_to_rgb(Yxy), 0.0) * pc.brightness;
+	if (gamma < SUN_RADIUS) {
+		color += SUN_INTENSITY * pc.brightness;
+	}
+
+	return color * smoothstep(-0.1, 0.05, sun.y);
+}
+
+void main() {
+	vec4 point = pc.inverse_view_proj * vec4(v_uv * 2.0 - 1.0, 0.5, 1.0);
+	vec3 direction = normalize(point.xyz / point.w);
+	vec3 sun = normalize(pc.sun_direction.xyz);
+
+	// The horizon blends into the ground, so the sky does not end in a hard line
+	vec3 horizon = sky(normalize(vec3(direction.x, 0.0, direction.z) + vec3(0.0, 0.001, 0.0)), sun);
+	vec3 color = direction.y >= 0.0
+		? sky(direction, sun)
+		: mix(horizon, GROUND_COLOR * horizon, smoothstep(0.0, 0.1, -direction.y));
+
+	f_color = vec4(color, 1.0);
+}
@@ -0,0 +1,45 @@
+// Encoding colors for the swapchain, shared by the post processing and overlay shaders
+
+const int TRANSFER_LINEAR = 0;
+const int TRANSFER_GAMMA = 1;
+const int TRANSFER_SCRGB = 2;
+const int TRANSFER_PQ = 3;
+
+// The gamma the scene was encoded with by the lighting shaders
+const float GAMMA = 2.2;
+
+const mat3 BT709_TO_BT2020 = mat3(
+	0.6274, 0.0691, 0.0164,
+	0.3293, 0.9195, 0.0880,
+	0.0433, 0.0114, 0.8956
+);
+
+// SMPTE ST 2084, for linear colors where 1.0 is 10000 nits
+vec3 pq(vec3 color) {
+	const float m1 = 0.1593017578125;
+	const float m2 = 78.84375;
+	const float c1 = 0.8359375;
+	const float c2 = 18.8515625;
+	const float c3 = 18.6875;
+
+	vec3 p = pow(max(color, 0.0), vec3(m1));
+	return pow((c1 + c2 * p) / (1.0 + c3 * p), vec3(m2));
+}
+
+// Encodes a gamma encoded color for a swapchain with the transfer function `transfer`, see
+// OutputTransfer, and white `paper_white` nits bright for HDR output
+vec3 encode_transfer(vec3 color, int transfer, float paper_white) {
+	if (transfer == TRANSFER_GAMMA) {
+		return color;
+	}
+
+	vec3 linear = pow(max(color, 0.0), vec3(GAMMA));
+
+	if (transfer == TRANSFER_SCRGB) {
+		return linear * (paper_white / 80.0);
+	} else if (transfer == TRANSFER_PQ) {
+		return pq(BT709_TO_BT2020 * linear * (paper_white / 10000.0));
+	}
+
+	return linear;
+}
@@ -0,0 +1,30 @@
+#version 450
+
+// The corner in world space, or the position of the entity for billboards
+layout(location = 0) in vec3 position;
+// Of the corner from the entity, along the axes of the camera, for billboards
+layout(location = 1) in vec2 offset;
+layout(location = 2) in vec2 uv;
+// The page of the atlas
+layout(location = 3) in float layer;
+layout(location = 4) in vec4 color;
+
+layout(location = 0) out vec3 v_uv;
+layout(location = 1) out vec4 v_color;
+
+layout(push_constant) uniform PushConstants {
+	mat4 view;
+	mat4 proj;
+} pc;
+
+void main() {
+	v_uv = vec3(uv, layer);
+	v_color = color;
+
+	// The rows of the view matrix are the axes of the camera in world space
+	vec3 right = vec3(pc.view[0][0], pc.view[1][0], pc.view[2][0]);
+	vec3 up = vec3(pc.view[0][1], pc.view[1][1], pc.view[2][1]);
+	vec3 world = position + right * offset.x + up * offset.y;
+
+	gl_Position = pc.proj * pc.view * vec4(world, 1.0);
+}
@@ -0,0 +1,136 @@
+use nalgebra::{Matrix3, Vector3};
+
+/// A direction along one of the axes of a coordinate system
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Axis {
+    PosX,
+    NegX,
+    PosY,
+    NegY,
+    PosZ,
+    NegZ,
+}
+
+impl Axis {
+    pub fn to_vector(self) -> Vector3<f32> {
+        match self {
+            Axis::PosX => Vector3::x(),
+            Axis::NegX => -Vector3::x(),
+            Axis::PosY => Vector3::y(),
+            Axis::NegY => -Vector3::y(),
+            Axis::PosZ => Vector3::z(),
+            Axis::NegZ => -Vector3::z(),
+        }
+    }
+}
+
+/// How a tool or file format lays out its coordinates, for converting assets into the engine's
+///
+/// The engine is right handed, with +x to the right, +y up and a camera looking along -z, in
+/// meters. `forward` is the direction a camera looks in, which tools showing a model from the
+/// front look at it from. Left handed systems are converted by mirroring, which also turns the
+/// winding of triangles around.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CoordinateSystem {
+    pub right: Axis,
+    pub up: Axis,
+    pub forward: Axis,
+    /// Meters per unit
+    pub unit_scale: f32,
+}
+
+impl CoordinateSystem {
+    /// The engine's own coordinates, which glTF uses as well
+    pub const ENGINE: CoordinateSystem = CoordinateSystem {
+        right: Axis::PosX,
+        up: Axis::PosY,
+        forward: Axis::NegZ,
+        unit_scale: 1.0,
+    };
+
+    /// Z up and right handed, like Blender and 3ds Max
+    pub const Z_UP: CoordinateSystem = CoordinateSystem {
+        right: Axis::PosX,
+        up: Axis::PosZ,
+        forward: Axis::PosY,
+        unit_scale: 1.0,
+    };
+
+    /// Y up and left handed, like Unity
+    pub const LEFT_HANDED_Y_UP: CoordinateSystem = CoordinateSystem {
+        right: Axis::PosX,
+        up: Axis::PosY,
+        forward: Axis::PosZ,
+        unit_scale: 1.0,
+    };
+
+    /// The same axes, with units of this many meters, like 0.01 for centimeters
+    pub fn with_unit_scale(mut self, unit_scale: f32) -> Self {
+        self.unit_scale = unit_scale;
+        self
+    }
+
+    /// Maps directions in this system to the engine's, without the unit scale
+    ///
+    /// Panics if two of the axes are the same, or opposite.
+    pub fn to_engine(&self) -> Matrix3<f32> {
+        let source = Matrix3::from_columns(&[
+            self.right.to_vector(),
+            self.up.to_vector(),
+            self.forward.to_vector(),
+        ]);
+        assert!(
+            source.determinant().abs() > 0.5,
+            "Coordinate system with a repeated axis: {:?}",
+            self
+        );
+
+        let engine = Matrix3::from_columns(&[Vector3::x(), Vector3::y(), -Vector3::z()]);
+
+        // The axes are orthonormal, so the transpose is the inverse
+        engine * source.transpose()
+    }
+
+    /// Whether converting mirrors, so triangles have to be wound the other way
+    pub fn flips_winding(&self) -> bool {
+        self.to_engine().determinant() < 0.0
+    }
+
+    /// A position in this system, in the engine's
+    pub fn convert_point(&self, point: [f32; 3]) -> [f32; 3] {
+        (self.to_engine() * Vector3::from(point) * self.unit_scale).into()
+    }
+
+    /// A direction in this system, like a normal, in the engine's
+    pub fn convert_direction(&self, direction: [f32; 3]) -> [f32; 3] {
+        (self.to_engine() * Vector3::from(direction)).into()
+    }
+}
+
+impl Default for CoordinateSystem {
+    fn default() -> Self {
+        CoordinateSystem::ENGINE
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::CoordinateSystem;
+    use nalgebra::Matrix3;
+
+    #[test]
+    fn conversions() {
+        assert_eq!(CoordinateSystem::ENGINE.to_engine(), Matrix3::identity());
+
+        // Z up comes in with z as y, and y pointing into the screen
+        let z_up = CoordinateSystem::Z_UP.with_unit_scale(0.01);
+        assert_eq!(z_up.convert_point([100.0, 200.0, 300.0]), [1.0, 3.0, -2.0]);
+        assert_eq!(z_up.convert_direction([0.0, 0.0, 1.0]), [0.0, 1.0, 0.0]);
+        assert!(!z_up.flips_winding());
+
+        // Left handed systems are mirrored along z
+        let left = CoordinateSystem::LEFT_HANDED_Y_UP;
+        assert_eq!(left.convert_point([1.0, 2.0, 3.0]), [1.0, 2.0, -3.0]);
+        assert!(left.flips_winding());
+    }
+}
@@ -1,10 +1,46 @@
+mod coordinates;
 mod transform;
+mod visibility;
 
-pub use crate::components::transform::{GlobalTransform, Transform};
+pub use crate::components::coordinates::CoordinateSystem;
+pub use crate::components::transform::{
+    damp, damp_rotation, damp_spring, damping, render_matrix, render_transform, DirtyEntities,
+    GlobalTransform, PreviousGlobalTransform, Transform, TransformQuery, TransformStorageExt,
+};
+pub use crate::components::visibility::{Hidden, HiddenEntities, VisibilityInherit};
 
+use crate::inspector::Inspect;
 use specs::prelude::*;
+use specs_derive::Component;
 use specs_hierarchy::Parent;
 
+/// Ties an entity, like a camera, to one of the local players
+///
+/// Player 0 is controlled by the keyboard and mouse, as well as the first controller.
+#[derive(Component, Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+#[storage(HashMapStorage)]
+pub struct PlayerId(pub u32);
+
+/// A name to find an entity by, for the console and the inspector
+#[derive(Component, Debug, Clone, PartialEq)]
+#[storage(HashMapStorage)]
+pub struct Name(pub String);
+
+impl Inspect for Name {
+    fn fields(&self) -> Vec<(&'static str, String)> {
+        vec![("name", self.0.clone())]
+    }
+
+    fn set_field(&mut self, field: &str, value: &str) -> Result<(), String> {
+        match field {
+            "name" => self.0 = value.to_string(),
+            _ => return Err(format!("Name has no field {:?}", field)),
+        }
+
+        Ok(())
+    }
+}
+
 /// Component defining a link in a hierarchy of components
 #[derive(Debug, Copy, Clone)]
 pub struct Link {
@@ -1,7 +1,24 @@
-use nalgebra::{zero, Isometry3, Matrix4, Translation3, UnitQuaternion, Vector3};
-use specs::prelude::*;
+use crate::{
+    components::Link,
+    inspector::{format_vector3, parse_vector3, Inspect},
+};
+use nalgebra::{zero, Isometry3, Matrix4, Point3, Translation3, UnitQuaternion, Vector3};
+use specs::{prelude::*, storage::GenericReadStorage};
+use specs_hierarchy::Parent;
 use std::ops::{AddAssign, Deref, DerefMut};
 
+/// Entities whose GlobalTransform changed this frame
+///
+/// Filled by the TransformSystem, which also clears it at the start of the next frame, so every
+/// system running after it sees each change exactly once.
+#[derive(Debug, Default)]
+pub struct DirtyEntities {
+    pub dirty: BitSet,
+    /// Every entity that changed in the steps since the renderer last took them, which can be
+    /// more than one when the simulation runs at a fixed rate
+    pub moved: BitSet,
+}
+
 /// A Wrapper around the local and the global transform
 #[derive(Clone, Debug, Default, PartialEq)]
 pub struct GlobalTransform {
@@ -26,12 +43,132 @@ impl DerefMut for GlobalTransform {
     }
 }
 
+impl GlobalTransform {
+    /// A point in the local space of the entity, in world space
+    pub fn local_to_world(&self, point: &Point3<f32>) -> Point3<f32> {
+        self.iso * Point3::from(point.coords.component_mul(self.scale()))
+    }
+
+    /// A point in world space, in the local space of the entity
+    pub fn world_to_local(&self, point: &Point3<f32>) -> Point3<f32> {
+        Point3::from(
+            (self.iso.inverse() * point)
+                .coords
+                .component_div(self.scale()),
+        )
+    }
+}
+
 impl From<Transform> for GlobalTransform {
     fn from(global: Transform) -> Self {
         Self { global }
     }
 }
 
+/// Resolves the global transforms of entities on demand, from their Transforms and Links
+///
+/// GlobalTransforms are only synced once a frame, by the TransformSystem. This walks up the
+/// hierarchy from the local transforms instead, so it also sees entities moved earlier in the
+/// frame, at the cost of composing every ancestor on each call.
+pub struct TransformQuery<T, L> {
+    transforms: T,
+    links: L,
+}
+
+impl<T, L> TransformQuery<T, L>
+where
+    T: GenericReadStorage<Component = Transform>,
+    L: GenericReadStorage<Component = Link>,
+{
+    /// Takes the storages, or references to them, from a system's data
+    pub fn new(transforms: T, links: L) -> Self {
+        Self { transforms, links }
+    }
+
+    /// The global transform of an entity, composed the same way the TransformSystem does
+    ///
+    /// Entities without a Transform have the global transform of their parent, like in the
+    /// TransformSystem. Returns None if neither they nor any ancestor have one.
+    pub fn global(&self, entity: Entity) -> Option<Transform> {
+        let parent = self
+            .links
+            .get(entity)
+            .and_then(|link| self.global(link.parent_entity()));
+
+        match (self.transforms.get(entity), parent) {
+            (Some(transform), Some(parent)) => {
+                let mut global = transform.clone();
+                global += parent;
+                Some(global)
+            }
+            (Some(transform), None) => Some(transform.clone()),
+            (None, parent) => parent,
+        }
+    }
+
+    /// A point in the local space of the entity, in world space
+    pub fn local_to_world(&self, entity: Entity, point: &Point3<f32>) -> Option<Point3<f32>> {
+        self.global(entity)
+            .map(|global| GlobalTransform::from(global).local_to_world(point))
+    }
+
+    /// A point in world space, in the local space of the entity
+    pub fn world_to_local(&self, entity: Entity, point: &Point3<f32>) -> Option<Point3<f32>> {
+        self.global(entity)
+            .map(|global| GlobalTransform::from(global).world_to_local(point))
+    }
+}
+
+impl<'a> TransformQuery<ReadStorage<'a, Transform>, ReadStorage<'a, Link>> {
+    /// Queries the world directly, outside of systems
+    pub fn from_world(world: &'a World) -> Self {
+        Self::new(world.read_storage(), world.read_storage())
+    }
+}
+
+/// The GlobalTransform an entity had before the last simulation step
+///
+/// Only entities that moved in the last step have one, so the renderer only interpolates those.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PreviousGlobalTransform {
+    pub global: Transform,
+}
+
+impl Component for PreviousGlobalTransform {
+    type Storage = DenseVecStorage<Self>;
+}
+
+impl PreviousGlobalTransform {
+    /// The transform to render with, `alpha` of the way from the previous to the current one
+    pub fn interpolated(&self, current: &Transform, alpha: f32) -> Transform {
+        self.global.interpolate(current, alpha)
+    }
+}
+
+/// The global transform to render an entity with, interpolated if it moved in the last step
+pub fn render_transform(
+    global: &GlobalTransform,
+    previous: Option<&PreviousGlobalTransform>,
+    alpha: f32,
+) -> Transform {
+    match previous {
+        Some(previous) if alpha < 1.0 => previous.interpolated(global, alpha),
+        _ => global.global.clone(),
+    }
+}
+
+/// The model matrix to render an entity with, interpolated if it moved in the last step
+pub fn render_matrix(
+    global: &GlobalTransform,
+    previous: Option<&PreviousGlobalTransform>,
+    alpha: f32,
+) -> Matrix4<f32> {
+    match previous {
+        Some(previous) if alpha < 1.0 => previous.interpolated(global, alpha).to_matrix(),
+        _ => global.to_matrix(),
+    }
+}
+
 /// Transform (translation, rotation, scale)
 #[derive(Clone, Debug, PartialEq)]
 pub struct Transform {
@@ -76,6 +213,27 @@ impl Transform {
         &self.scale
     }
 
+    /// Linear interpolation of translation and scale, and spherical interpolation of rotation
+    pub fn interpolate(&self, other: &Transform, t: f32) -> Transform {
+        let translation = self.translation() + (other.translation() - self.translation()) * t;
+        let rotation = self.rotation().slerp(other.rotation(), t);
+        let scale = self.scale + (other.scale - self.scale) * t;
+
+        Transform::from_parts(translation, rotation, scale)
+    }
+
+    pub fn set_translation(&mut self, translation: Vector3<f32>) {
+        self.iso.translation.vector = translation;
+    }
+
+    pub fn set_rotation(&mut self, rotation: UnitQuaternion<f32>) {
+        self.iso.rotation = rotation;
+    }
+
+    pub fn set_scale(&mut self, scale: Vector3<f32>) {
+        self.scale = scale;
+    }
+
     pub fn translate(&mut self, t: Vector3<f32>) {
         if t != zero() {
             self.iso.translation.vector += self.iso.rotation * t;
@@ -109,15 +267,99 @@ impl Component for Transform {
     type Storage = FlaggedStorage<Self, VecStorage<Self>>;
 }
 
+/// Change detection aware ways of writing transforms
+///
+/// Every mutable access to a flagged storage counts as a modification, even if nothing changed,
+/// and any direct write that skips the storage is never seen at all. These only touch the
+/// storage when the transform actually changes, so it is flagged if and only if it changed.
+pub trait TransformStorageExt {
+    /// Applies `f` to a copy of the transform and writes it back if it changed
+    ///
+    /// Returns whether the transform changed.
+    fn modify<F: FnOnce(&mut Transform)>(&mut self, entity: Entity, f: F) -> bool;
+
+    /// Replaces the transform, returning whether it changed
+    fn set(&mut self, entity: Entity, transform: Transform) -> bool {
+        self.modify(entity, |t| *t = transform)
+    }
+
+    fn set_translation(&mut self, entity: Entity, translation: Vector3<f32>) -> bool {
+        self.modify(entity, |t| t.set_translation(translation))
+    }
+
+    fn set_rotation(&mut self, entity: Entity, rotation: UnitQuaternion<f32>) -> bool {
+        self.modify(entity, |t| t.set_rotation(rotation))
+    }
+
+    fn set_scale(&mut self, entity: Entity, scale: Vector3<f32>) -> bool {
+        self.modify(entity, |t| t.set_scale(scale))
+    }
+}
+
+impl<'a> TransformStorageExt for WriteStorage<'a, Transform> {
+    fn modify<F: FnOnce(&mut Transform)>(&mut self, entity: Entity, f: F) -> bool {
+        let mut transform = match self.get(entity) {
+            Some(transform) => transform.clone(),
+            None => return false,
+        };
+
+        f(&mut transform);
+
+        if Some(&transform) == self.get(entity) {
+            return false;
+        }
+
+        // get_mut is what flags the modification
+        *self.get_mut(entity).unwrap() = transform;
+        true
+    }
+}
+
+impl Inspect for Transform {
+    /// Rotation is given as roll, pitch and yaw in degrees
+    fn fields(&self) -> Vec<(&'static str, String)> {
+        let (roll, pitch, yaw) = self.rotation().euler_angles();
+
+        vec![
+            ("translation", format_vector3(self.translation())),
+            (
+                "rotation",
+                format_vector3(&Vector3::new(roll, pitch, yaw).map(f32::to_degrees)),
+            ),
+            ("scale", format_vector3(self.scale())),
+        ]
+    }
+
+    fn set_field(&mut self, field: &str, value: &str) -> Result<(), String> {
+        let vector = parse_vector3(value)?;
+
+        match field {
+            "translation" => self.set_translation(vector),
+            "rotation" => {
+                let radians = vector.map(f32::to_radians);
+                self.set_rotation(UnitQuaternion::from_euler_angles(
+                    radians.x, radians.y, radians.z,
+                ));
+            }
+            "scale" => self.set_scale(vector),
+            _ => return Err(format!("Transform has no field {:?}", field)),
+        }
+
+        Ok(())
+    }
+}
+
+/// Puts a local transform into the space of its parent, `local += parent_global`
+///
+/// The same as multiplying their matrices as long as the parent is scaled the same along every
+/// axis. A rotated child of a parent that is not would be sheared, which a Transform can't hold,
+/// so it is scaled along its own axes instead.
 impl AddAssign<Transform> for Transform {
-    fn add_assign(&mut self, other: Transform) {
-        self.iso.translation.vector += other.iso.translation.vector;
-        self.iso.rotation *= other.iso.rotation;
-        self.scale = Vector3::new(
-            self.scale.x * other.scale.x,
-            self.scale.y * other.scale.y,
-            self.scale.z * other.scale.z,
-        );
+    fn add_assign(&mut self, parent: Transform) {
+        let offset = parent.scale.component_mul(&self.iso.translation.vector);
+        self.iso.translation.vector = parent.iso.translation.vector + parent.iso.rotation * offset;
+        self.iso.rotation = parent.iso.rotation * self.iso.rotation;
+        self.scale = parent.scale.component_mul(&self.scale);
     }
 }
 
@@ -140,3 +382,100 @@ impl Default for Transform {
         }
     }
 }
+
+/// The fraction of the way to a target to move over `delta` seconds, closing in at `rate`
+///
+/// Moving a fixed fraction every frame gets there faster at higher frame rates, this gets just as
+/// close in the same time at any frame rate. About 63% of the way is covered in `1 / rate`
+/// seconds. An infinite rate snaps straight to the target, and 0 never moves.
+pub fn damping(rate: f32, delta: f32) -> f32 {
+    if rate == std::f32::INFINITY {
+        return 1.0;
+    }
+
+    1.0 - (-rate * delta.max(0.0)).exp()
+}
+
+/// `current` moved towards `target` at `rate` over `delta` seconds, see `damping`
+pub fn damp(current: f32, target: f32, rate: f32, delta: f32) -> f32 {
+    current + (target - current) * damping(rate, delta)
+}
+
+/// A rotation turned towards `target` at `rate` over `delta` seconds, see `damping`
+///
+/// Turning to the exact opposite has no one way to go, so it snaps to the target instead.
+pub fn damp_rotation(
+    current: &UnitQuaternion<f32>,
+    target: &UnitQuaternion<f32>,
+    rate: f32,
+    delta: f32,
+) -> UnitQuaternion<f32> {
+    current
+        .try_slerp(target, damping(rate, delta), 1e-6)
+        .unwrap_or(*target)
+}
+
+/// A position moved towards `target` over `delta` seconds, on a critically damped spring
+///
+/// The spring catches up as fast as it can without overshooting the target when starting from
+/// rest, and carries `velocity` from one step to the next. It gets half way in about
+/// `1.7 / stiffness` seconds. This is the exact solution of the spring, so it is stable and the
+/// same at any frame rate.
+pub fn damp_spring(
+    current: &Vector3<f32>,
+    target: &Vector3<f32>,
+    velocity: &mut Vector3<f32>,
+    stiffness: f32,
+    delta: f32,
+) -> Vector3<f32> {
+    let offset = current - target;
+    let decay = (-stiffness * delta).exp();
+    let change = (*velocity + offset * stiffness) * delta;
+
+    *velocity = (*velocity - change * stiffness) * decay;
+    target + (offset + change) * decay
+}
+
+#[cfg(test)]
+mod test {
+    use super::{damp, damp_rotation, damp_spring, damping};
+    use nalgebra::{UnitQuaternion, Vector3};
+
+    #[test]
+    fn frame_rate_independent() {
+        // The same time in more steps gets just as far
+        let once = damp(0.0, 10.0, 3.0, 0.1);
+        let twice = damp(damp(0.0, 10.0, 3.0, 0.05), 10.0, 3.0, 0.05);
+        assert!((once - twice).abs() < 1e-4);
+
+        // Snaps with an infinite rate, and stays put without time
+        assert_eq!(damping(std::f32::INFINITY, 0.0), 1.0);
+        assert_eq!(damping(5.0, 0.0), 0.0);
+        assert!((damping(1.0, 1.0) - 0.632).abs() < 1e-3);
+
+        let target = UnitQuaternion::from_axis_angle(&Vector3::y_axis(), 1.0);
+        let turned = damp_rotation(&UnitQuaternion::identity(), &target, 1.0, 1.0);
+        assert!((turned.angle() - 0.632).abs() < 1e-3);
+    }
+
+    #[test]
+    fn spring() {
+        let target = Vector3::new(10.0, 0.0, 0.0);
+        let mut position = Vector3::zeros();
+        let mut velocity = Vector3::zeros();
+
+        // Closes in without ever overshooting
+        let mut last = position.x;
+        for _ in 0..120 {
+            position = damp_spring(&position, &target, &mut velocity, 4.0, 1.0 / 60.0);
+            assert!(position.x >= last && position.x <= target.x + 1e-4);
+            last = position.x;
+        }
+        assert!((position - target).norm() < 0.5);
+
+        // The same place in one long step as in many short ones
+        let mut long_velocity = Vector3::zeros();
+        let long = damp_spring(&Vector3::zeros(), &target, &mut long_velocity, 4.0, 2.0);
+        assert!((long - position).norm() < 1e-3);
+    }
+}
@@ -0,0 +1,22 @@
+use specs::prelude::*;
+use specs_derive::Component;
+
+/// Keeps an entity from being drawn, without removing its mesh
+#[derive(Component, Debug, Default)]
+#[storage(NullStorage)]
+pub struct Hidden;
+
+/// Hides an entity whenever its parent is hidden, through any number of parents that inherit too
+///
+/// Without it, children are drawn whether their parent is or not.
+#[derive(Component, Debug, Default)]
+#[storage(NullStorage)]
+pub struct VisibilityInherit;
+
+/// Entities the renderer skips, either Hidden or inheriting it from a parent
+///
+/// Filled by the VisibilitySystem every frame.
+#[derive(Debug, Default)]
+pub struct HiddenEntities {
+    pub hidden: BitSet,
+}
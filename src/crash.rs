@@ -0,0 +1,135 @@
+use backtrace::Backtrace;
+use log::error;
+use sdl2::{
+    messagebox::{show_simple_message_box, MessageBoxFlag},
+    sys::{self, SDL_bool},
+    video::Window,
+};
+use std::{
+    fmt::Write,
+    fs,
+    panic::{self, PanicInfo},
+    path::{Path, PathBuf},
+    process, thread,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// The message a panic was started with
+fn panic_message(info: &PanicInfo) -> String {
+    let payload = info.payload();
+
+    payload
+        .downcast_ref::<&str>()
+        .map(|message| message.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "Box<Any>".to_string())
+}
+
+fn crash_report(info: &PanicInfo) -> String {
+    let mut report = String::new();
+
+    let thread = thread::current();
+    writeln!(
+        report,
+        "Thread '{}' panicked: {}",
+        thread.name().unwrap_or("<unnamed>"),
+        panic_message(info)
+    )
+    .unwrap();
+
+    if let Some(location) = info.location() {
+        writeln!(
+            report,
+            "At {}:{}:{}",
+            location.file(),
+            location.line(),
+            location.column()
+        )
+        .unwrap();
+    }
+
+    writeln!(report, "\n{:?}", Backtrace::new()).unwrap();
+    report
+}
+
+/// Writes a report to a new file in `dir`, returning the path of the file
+fn write_report(dir: &Path, report: &str) -> Option<PathBuf> {
+    let seconds = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_secs())
+        .unwrap_or(0);
+    let path = dir.join(format!("crash-{}.txt", seconds));
+
+    fs::create_dir_all(dir).ok()?;
+    fs::write(&path, report).ok()?;
+    Some(path)
+}
+
+/// Lets go of the mouse, which would otherwise stay grabbed by the dead window
+fn release_mouse() {
+    unsafe {
+        sys::SDL_SetRelativeMouseMode(SDL_bool::SDL_FALSE);
+
+        let window = sys::SDL_GetGrabbedWindow();
+        if !window.is_null() {
+            sys::SDL_SetWindowGrab(window, SDL_bool::SDL_FALSE);
+        }
+
+        sys::SDL_ShowCursor(1);
+    }
+}
+
+/// Makes panics write a crash report with a backtrace to `dir`, release the mouse and abort
+///
+/// With `message_box`, a message box telling where the report went is shown before aborting, for
+/// when the engine was not started from a terminal. The report is logged as well.
+pub fn install_panic_hook(dir: PathBuf, message_box: bool) {
+    panic::set_hook(Box::new(move |info| {
+        let report = crash_report(info);
+        error!("{}", report);
+
+        let path = write_report(&dir, &report);
+        release_mouse();
+
+        if message_box {
+            let message = match &path {
+                Some(path) => format!(
+                    "{}\n\nA crash report was written to {}",
+                    panic_message(info),
+                    path.display()
+                ),
+                None => panic_message(info),
+            };
+
+            let _ = show_simple_message_box(
+                MessageBoxFlag::ERROR,
+                "vkengine crashed",
+                &message,
+                None::<&Window>,
+            );
+        }
+
+        // Unwinding would run into whatever state the panic left behind
+        process::abort();
+    }));
+}
+
+#[cfg(test)]
+mod test {
+    use super::write_report;
+    use std::{env, fs};
+
+    #[test]
+    fn report_file() {
+        let dir = env::temp_dir().join(format!("vkengine-crash-test-{}", std::process::id()));
+
+        let path = write_report(&dir, "Thread 'main' panicked: test").unwrap();
+        assert!(path.starts_with(&dir));
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "Thread 'main' panicked: test"
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,429 @@
+use crate::{
+    components::{Link, Name, Transform, TransformQuery, TransformStorageExt},
+    renderer::lights::PointLightComponent,
+    resources::{ActionEvent, ActionEvents},
+};
+use log::{info, warn};
+use nalgebra::{Point3, Vector3};
+use shrev::ReaderId;
+use specs::prelude::*;
+
+const USAGE: &str = "Usage: inspect <entity> [<component> <field> <value>]";
+
+/// Components whose fields can be read and written as text
+///
+/// Field values are written the way `parse_*` below read them, so whatever `fields` returns can
+/// be edited and handed back to `set_field`.
+pub trait Inspect {
+    fn fields(&self) -> Vec<(&'static str, String)>;
+
+    fn set_field(&mut self, field: &str, value: &str) -> Result<(), String>;
+}
+
+pub fn parse_f32(value: &str) -> Result<f32, String> {
+    value
+        .trim()
+        .parse()
+        .map_err(|_| format!("Expected a number, got {:?}", value))
+}
+
+/// Reads three numbers separated by spaces or commas
+pub fn parse_vector3(value: &str) -> Result<Vector3<f32>, String> {
+    let parts = value
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|part| !part.is_empty())
+        .map(parse_f32)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    match parts.as_slice() {
+        [x, y, z] => Ok(Vector3::new(*x, *y, *z)),
+        _ => Err(format!("Expected three numbers, got {:?}", value)),
+    }
+}
+
+pub fn format_vector3(vector: &Vector3<f32>) -> String {
+    format!("{} {} {}", vector.x, vector.y, vector.z)
+}
+
+type ReadFields = Box<dyn Fn(&World, Entity) -> Option<Vec<(&'static str, String)>> + Send + Sync>;
+type WriteField = Box<dyn Fn(&World, Entity, &str, &str) -> Result<(), String> + Send + Sync>;
+
+/// Reads and writes the fields of one kind of component on any entity
+pub struct ComponentEditor {
+    pub name: &'static str,
+    read: ReadFields,
+    write: WriteField,
+}
+
+/// Resource with an editor for every component that can be tweaked at runtime
+///
+/// Meant for the console and debug UI, which look entities up by id or Name and then list or
+/// change their fields as key/value pairs. Until there is one, inspect commands are bound to keys
+/// in the bindings file, see `apply`.
+pub struct Inspector {
+    editors: Vec<ComponentEditor>,
+}
+
+impl Inspector {
+    pub fn new() -> Self {
+        Self {
+            editors: Vec::new(),
+        }
+    }
+
+    /// Registers an editor from a pair of closures
+    pub fn register<R, W>(&mut self, name: &'static str, read: R, write: W)
+    where
+        R: Fn(&World, Entity) -> Option<Vec<(&'static str, String)>> + Send + Sync + 'static,
+        W: Fn(&World, Entity, &str, &str) -> Result<(), String> + Send + Sync + 'static,
+    {
+        self.editors.push(ComponentEditor {
+            name,
+            read: Box::new(read),
+            write: Box::new(write),
+        });
+    }
+
+    /// Registers an editor for a component implementing Inspect
+    pub fn register_inspect<T>(&mut self, name: &'static str)
+    where
+        T: Component + Inspect,
+    {
+        self.register(
+            name,
+            |world, entity| world.read_storage::<T>().get(entity).map(T::fields),
+            |world, entity, field, value| {
+                let mut storage = world.write_storage::<T>();
+                let component = storage
+                    .get_mut(entity)
+                    .ok_or_else(|| "Entity does not have this component".to_string())?;
+                component.set_field(field, value)
+            },
+        );
+    }
+
+    /// Finds an entity by its id, or failing that by its Name
+    pub fn find(&self, world: &World, target: &str) -> Option<Entity> {
+        let entities = world.entities();
+
+        if let Ok(id) = target.parse() {
+            let entity = entities.entity(id);
+            return if entities.is_alive(entity) {
+                Some(entity)
+            } else {
+                None
+            };
+        }
+
+        let names = world.read_storage::<Name>();
+        (&entities, &names)
+            .join()
+            .find(|(_, name)| name.0 == target)
+            .map(|(entity, _)| entity)
+    }
+
+    /// Every registered component the entity has, with its fields
+    pub fn inspect(
+        &self,
+        world: &World,
+        entity: Entity,
+    ) -> Vec<(&'static str, Vec<(&'static str, String)>)> {
+        self.editors
+            .iter()
+            .filter_map(|editor| (editor.read)(world, entity).map(|fields| (editor.name, fields)))
+            .collect()
+    }
+
+    /// Sets a single field of a component on the entity
+    pub fn set(
+        &self,
+        world: &World,
+        entity: Entity,
+        component: &str,
+        field: &str,
+        value: &str,
+    ) -> Result<(), String> {
+        let editor = self
+            .editors
+            .iter()
+            .find(|editor| editor.name == component)
+            .ok_or_else(|| format!("No editor for component {:?}", component))?;
+
+        (editor.write)(world, entity, field, value)
+    }
+
+    /// Runs the arguments of an "inspect" command
+    ///
+    /// "<entity>" lists the fields of an entity, and "<entity> <component> <field> <value>" sets
+    /// one, with the entity given by id or Name. Returns a line to show.
+    pub fn command(&self, world: &World, args: &str) -> Result<String, String> {
+        let args = args.split_whitespace().collect::<Vec<_>>();
+        let target = match args.first() {
+            Some(target) => *target,
+            None => return Err(USAGE.to_string()),
+        };
+        let entity = self
+            .find(world, target)
+            .ok_or_else(|| format!("No entity {:?}", target))?;
+
+        match args.len() {
+            1 => {
+                let components = self
+                    .inspect(world, entity)
+                    .into_iter()
+                    .map(|(component, fields)| {
+                        let fields = fields
+                            .iter()
+                            .map(|(field, value)| format!("{}: {}", field, value))
+                            .collect::<Vec<_>>();
+                        format!("{} {{ {} }}", component, fields.join(", "))
+                    })
+                    .collect::<Vec<_>>();
+                Ok(format!("{} {}", target, components.join(" ")))
+            }
+            // Vectors are written with spaces, so the value is the rest of the line
+            len if len >= 4 => {
+                let value = args[3..].join(" ");
+                self.set(world, entity, args[1], args[2], &value)?;
+                Ok(format!(
+                    "Set {} {} of {} to {}",
+                    args[1], args[2], target, value
+                ))
+            }
+            _ => Err(USAGE.to_string()),
+        }
+    }
+}
+
+/// Runs the inspect commands bound as actions since the last call, see `Inspector::command`
+///
+/// Called between frames, as the editors need the whole world.
+pub fn apply(world: &World, reader: &mut ReaderId<ActionEvent>) {
+    let commands = world
+        .read_resource::<ActionEvents>()
+        .read(reader)
+        .filter_map(|ActionEvent(action)| {
+            if action.starts_with("inspect ") {
+                Some(action["inspect ".len()..].to_string())
+            } else {
+                None
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let inspector = world.read_resource::<Inspector>();
+    for args in commands {
+        match inspector.command(world, &args) {
+            Ok(line) => info!("{}", line),
+            Err(err) => warn!("{}", err),
+        }
+    }
+}
+
+impl Default for Inspector {
+    /// An inspector with editors for the built in components
+    fn default() -> Self {
+        let mut inspector = Self::new();
+
+        // Transforms are written through TransformStorageExt, so only real changes are flagged
+        inspector.register(
+            "transform",
+            |world, entity| {
+                world
+                    .read_storage::<Transform>()
+                    .get(entity)
+                    .map(Inspect::fields)
+            },
+            |world, entity, field, value| {
+                let mut transforms = world.write_storage::<Transform>();
+                let mut transform = transforms
+                    .get(entity)
+                    .cloned()
+                    .ok_or_else(|| "Entity does not have this component".to_string())?;
+
+                transform.set_field(field, value)?;
+                transforms.set(entity, transform);
+                Ok(())
+            },
+        );
+        // The translation in world space, written into the space of the parent
+        inspector.register(
+            "world",
+            |world, entity| {
+                TransformQuery::from_world(world)
+                    .global(entity)
+                    .map(|global| vec![("translation", format_vector3(global.translation()))])
+            },
+            |world, entity, field, value| {
+                if field != "translation" {
+                    return Err(format!("world has no field {:?}", field));
+                }
+
+                let translation = parse_vector3(value)?;
+                let parent = world
+                    .read_storage::<Link>()
+                    .get(entity)
+                    .map(|link| link.parent_entity());
+                let local = parent
+                    .and_then(|parent| {
+                        TransformQuery::from_world(world)
+                            .world_to_local(parent, &Point3::from(translation))
+                    })
+                    .map(|point| point.coords)
+                    .unwrap_or(translation);
+
+                let mut transforms = world.write_storage::<Transform>();
+                if !transforms.contains(entity) {
+                    return Err("Entity does not have this component".to_string());
+                }
+                transforms.set_translation(entity, local);
+                Ok(())
+            },
+        );
+        inspector.register_inspect::<PointLightComponent>("point_light");
+        inspector.register_inspect::<Name>("name");
+
+        inspector
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_vector3, Inspector};
+    use crate::{
+        components::{Link, Name, Transform},
+        renderer::lights::PointLightComponent,
+    };
+    use nalgebra::Vector3;
+    use specs::prelude::*;
+
+    #[test]
+    fn vectors() {
+        assert_eq!(parse_vector3("1 2 3"), Ok(Vector3::new(1.0, 2.0, 3.0)));
+        assert_eq!(parse_vector3("1, 2,3"), Ok(Vector3::new(1.0, 2.0, 3.0)));
+        assert!(parse_vector3("1 2").is_err());
+        assert!(parse_vector3("1 2 x").is_err());
+    }
+
+    #[test]
+    fn edit_by_name() {
+        let mut world = World::new();
+        world.register::<Transform>();
+        world.register::<Link>();
+        world.register::<Name>();
+        world.register::<PointLightComponent>();
+
+        let entity = world
+            .create_entity()
+            .with(Transform::default())
+            .with(Name("box".to_string()))
+            .build();
+
+        let inspector = Inspector::default();
+        assert_eq!(inspector.find(&world, "box"), Some(entity));
+        assert_eq!(
+            inspector.find(&world, &entity.id().to_string()),
+            Some(entity)
+        );
+        assert_eq!(inspector.find(&world, "nothing"), None);
+
+        inspector
+            .set(&world, entity, "transform", "translation", "1 2 3")
+            .unwrap();
+        assert_eq!(
+            world
+                .read_storage::<Transform>()
+                .get(entity)
+                .unwrap()
+                .translation(),
+            &Vector3::new(1.0, 2.0, 3.0)
+        );
+
+        // Only components the entity has are listed
+        let components = inspector.inspect(&world, entity);
+        assert_eq!(
+            components.iter().map(|(name, _)| *name).collect::<Vec<_>>(),
+            vec!["transform", "name"]
+        );
+
+        assert!(inspector
+            .set(&world, entity, "transform", "size", "1")
+            .is_err());
+        assert!(inspector
+            .set(&world, entity, "point_light", "linear", "1")
+            .is_err());
+    }
+
+    #[test]
+    fn commands() {
+        let mut world = World::new();
+        world.register::<Transform>();
+        world.register::<Link>();
+        world.register::<Name>();
+        world.register::<PointLightComponent>();
+
+        let entity = world
+            .create_entity()
+            .with(Transform::default())
+            .with(Name("box".to_string()))
+            .build();
+
+        let inspector = Inspector::default();
+        inspector
+            .command(&world, "box transform translation 1 2 3")
+            .unwrap();
+        assert_eq!(
+            world
+                .read_storage::<Transform>()
+                .get(entity)
+                .unwrap()
+                .translation(),
+            &Vector3::new(1.0, 2.0, 3.0)
+        );
+
+        let line = inspector.command(&world, "box").unwrap();
+        assert!(line.contains("transform {"));
+        assert!(line.contains("name {"));
+
+        assert!(inspector.command(&world, "").is_err());
+        assert!(inspector.command(&world, "nothing").is_err());
+        assert!(inspector.command(&world, "box transform").is_err());
+    }
+
+    #[test]
+    fn world_translation() {
+        let mut world = World::new();
+        world.register::<Transform>();
+        world.register::<Link>();
+        world.register::<Name>();
+        world.register::<PointLightComponent>();
+
+        let mut scaled = Transform::from(Vector3::new(1.0, 0.0, 0.0));
+        scaled.set_scale(Vector3::new(2.0, 2.0, 2.0));
+        let parent = world.create_entity().with(scaled).build();
+        let child = world
+            .create_entity()
+            .with(Transform::default())
+            .with(Link::new(parent))
+            .build();
+
+        let inspector = Inspector::default();
+        inspector
+            .set(&world, child, "world", "translation", "5 2 0")
+            .unwrap();
+        assert_eq!(
+            world
+                .read_storage::<Transform>()
+                .get(child)
+                .unwrap()
+                .translation(),
+            &Vector3::new(2.0, 1.0, 0.0)
+        );
+
+        let fields = inspector.inspect(&world, child);
+        assert!(fields.contains(&("world", vec![("translation", "5 2 0".to_string())])));
+        assert!(inspector
+            .set(&world, child, "world", "scale", "1 1 1")
+            .is_err());
+    }
+}
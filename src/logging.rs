@@ -0,0 +1,411 @@
+use log::{warn, LevelFilter, Log, Metadata, Record};
+use serde::Deserialize;
+use std::{
+    collections::BTreeMap,
+    env,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{Arc, Mutex, RwLock},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Logging settings as they are written in the logging file
+///
+/// Levels are "off", "error", "warn", "info", "debug" or "trace".
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct LogSettings {
+    /// Level of every module without a level of its own
+    pub level: String,
+    /// Levels of modules and everything in them, by module path like "vkengine::renderer"
+    pub modules: BTreeMap<String, String>,
+    /// Also writes the log to this file
+    pub file: Option<PathBuf>,
+    /// Bytes written to the log file before it is rotated
+    pub max_file_size: u64,
+    /// Rotated log files kept around, as `<file>.1` being the newest
+    pub max_files: usize,
+}
+
+impl Default for LogSettings {
+    fn default() -> Self {
+        Self {
+            level: "error".to_string(),
+            modules: BTreeMap::new(),
+            file: None,
+            max_file_size: 1024 * 1024,
+            max_files: 3,
+        }
+    }
+}
+
+/// The level of every module, changeable at runtime through LogLevels
+#[derive(Debug, Clone, PartialEq)]
+struct LevelFilters {
+    default: LevelFilter,
+    modules: BTreeMap<String, LevelFilter>,
+}
+
+impl LevelFilters {
+    fn from_settings(settings: &LogSettings) -> Result<Self, String> {
+        let mut filters = Self {
+            default: parse_level(&settings.level)?,
+            modules: BTreeMap::new(),
+        };
+
+        for (module, level) in settings.modules.iter() {
+            filters.modules.insert(module.clone(), parse_level(level)?);
+        }
+
+        Ok(filters)
+    }
+
+    /// The level of the most specific module a log target is in
+    fn level(&self, target: &str) -> LevelFilter {
+        self.modules
+            .iter()
+            .filter(|(module, _)| {
+                target == module.as_str()
+                    || (target.starts_with(module.as_str())
+                        && target[module.len()..].starts_with("::"))
+            })
+            .max_by_key(|(module, _)| module.len())
+            .map(|(_, level)| *level)
+            .unwrap_or(self.default)
+    }
+
+    /// The most verbose level of any module, which the log macros check before anything else
+    fn max(&self) -> LevelFilter {
+        self.modules
+            .values()
+            .cloned()
+            .fold(self.default, |max, level| max.max(level))
+    }
+
+    /// Applies directives like RUST_LOG's, "info,vkengine::renderer=debug"
+    fn apply_directives(&mut self, directives: &str) -> Result<(), String> {
+        for directive in directives.split(',').map(str::trim) {
+            let mut parts = directive.splitn(2, '=');
+            match (parts.next(), parts.next()) {
+                (Some(""), None) => (),
+                (Some(level), None) => self.default = parse_level(level)?,
+                (Some(module), Some(level)) => {
+                    self.modules.insert(module.to_string(), parse_level(level)?);
+                }
+                _ => unreachable!(),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn parse_level(level: &str) -> Result<LevelFilter, String> {
+    LevelFilter::from_str(level.trim()).map_err(|_| format!("Unknown log level {:?}", level))
+}
+
+/// A log file that is moved aside once it grows too large
+struct RotatingFile {
+    path: PathBuf,
+    max_size: u64,
+    max_files: usize,
+    file: File,
+    size: u64,
+}
+
+impl RotatingFile {
+    fn open(path: PathBuf, max_size: u64, max_files: usize) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+
+        Ok(Self {
+            path,
+            max_size,
+            max_files,
+            file,
+            size,
+        })
+    }
+
+    /// The path of the rotated file `index` rotations old
+    fn rotated(&self, index: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{}", index));
+        PathBuf::from(path)
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+
+        if self.max_files == 0 {
+            self.file = File::create(&self.path)?;
+        } else {
+            // The oldest file is overwritten
+            for index in (1..self.max_files).rev() {
+                let from = self.rotated(index);
+                if from.exists() {
+                    fs::rename(from, self.rotated(index + 1))?;
+                }
+            }
+            fs::rename(&self.path, self.rotated(1))?;
+            self.file = File::create(&self.path)?;
+        }
+
+        self.size = 0;
+        Ok(())
+    }
+
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        if self.size > 0 && self.size + line.len() as u64 > self.max_size {
+            self.rotate()?;
+        }
+
+        self.file.write_all(line.as_bytes())?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+}
+
+struct Logger {
+    filters: Arc<RwLock<LevelFilters>>,
+    /// Formats records to stderr, filtered by the levels above instead of by itself
+    stderr: env_logger::Logger,
+    file: Option<Mutex<RotatingFile>>,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.filters.read().unwrap().level(metadata.target())
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        self.stderr.log(record);
+
+        if let Some(file) = &self.file {
+            let seconds = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|time| time.as_secs())
+                .unwrap_or(0);
+            let line = format!(
+                "{} {:<5} {}: {}\n",
+                seconds,
+                record.level(),
+                record.target(),
+                record.args()
+            );
+
+            // Nowhere left to report failing to log to
+            let _ = file.lock().unwrap().write_line(&line);
+        }
+    }
+
+    fn flush(&self) {
+        self.stderr.flush();
+        if let Some(file) = &self.file {
+            let _ = file.lock().unwrap().file.flush();
+        }
+    }
+}
+
+/// Resource for changing log levels while the engine runs
+#[derive(Clone)]
+pub struct LogLevels {
+    filters: Arc<RwLock<LevelFilters>>,
+}
+
+impl LogLevels {
+    /// Sets the level of a module and everything in it, or of every other module if None
+    pub fn set(&self, module: Option<&str>, level: LevelFilter) {
+        let mut filters = self.filters.write().unwrap();
+        match module {
+            Some(module) => {
+                filters.modules.insert(module.to_string(), level);
+            }
+            None => filters.default = level,
+        }
+        log::set_max_level(filters.max());
+    }
+
+    /// Runs the arguments of a "log" command, "[module] level"
+    ///
+    /// Bound to keys as actions starting with "log ", see DebugToggleSystem. Returns a line to
+    /// show.
+    pub fn command(&self, args: &str) -> Result<String, String> {
+        let args = args.split_whitespace().collect::<Vec<_>>();
+        match args.as_slice() {
+            [level] => {
+                let level = parse_level(level)?;
+                self.set(None, level);
+                Ok(format!("Logging at {}", level))
+            }
+            [module, level] => {
+                let level = parse_level(level)?;
+                self.set(Some(*module), level);
+                Ok(format!("Logging {} at {}", module, level))
+            }
+            _ => Err("Usage: log [module] <level>".to_string()),
+        }
+    }
+}
+
+/// Installs the logger, with the settings from a ron file and the directives in RUST_LOG
+///
+/// Settings that can't be read are warned about once the logger is up, falling back to logging
+/// errors to stderr.
+pub fn init<P: AsRef<Path>>(path: P) -> LogLevels {
+    let path = path.as_ref();
+    let mut problems = Vec::new();
+
+    let settings = File::open(path)
+        .map_err(|err| err.to_string())
+        .and_then(|file| ron::de::from_reader(file).map_err(|err| err.to_string()))
+        .unwrap_or_else(|err| {
+            problems.push(format!(
+                "Failed to load log settings from {}: {}",
+                path.display(),
+                err
+            ));
+            LogSettings::default()
+        });
+
+    let mut filters = LevelFilters::from_settings(&settings).unwrap_or_else(|err| {
+        problems.push(format!("Invalid log settings: {}", err));
+        LevelFilters::from_settings(&LogSettings::default()).unwrap()
+    });
+
+    if let Ok(directives) = env::var("RUST_LOG") {
+        if let Err(err) = filters.apply_directives(&directives) {
+            problems.push(format!("Invalid RUST_LOG: {}", err));
+        }
+    }
+
+    let file = settings.file.as_ref().and_then(|file| {
+        RotatingFile::open(file.clone(), settings.max_file_size, settings.max_files)
+            .map_err(|err| {
+                problems.push(format!(
+                    "Failed to open log file {}: {}",
+                    file.display(),
+                    err
+                ))
+            })
+            .ok()
+    });
+
+    let max = filters.max();
+    let filters = Arc::new(RwLock::new(filters));
+    let logger = Logger {
+        filters: filters.clone(),
+        stderr: env_logger::Builder::new()
+            .filter_level(LevelFilter::Trace)
+            .build(),
+        file: file.map(Mutex::new),
+    };
+
+    log::set_boxed_logger(Box::new(logger)).expect("Logger installed twice");
+    log::set_max_level(max);
+
+    for problem in problems {
+        warn!("{}", problem);
+    }
+
+    LogLevels { filters }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{LevelFilters, LogLevels, LogSettings, RotatingFile};
+    use log::LevelFilter;
+    use std::{
+        env, fs,
+        sync::{Arc, RwLock},
+    };
+
+    #[test]
+    fn module_levels() {
+        let mut settings = LogSettings::default();
+        settings.level = "info".to_string();
+        settings
+            .modules
+            .insert("vkengine::renderer".to_string(), "warn".to_string());
+        let mut filters = LevelFilters::from_settings(&settings).unwrap();
+
+        assert_eq!(filters.level("vkengine::systems"), LevelFilter::Info);
+        assert_eq!(filters.level("vkengine::renderer"), LevelFilter::Warn);
+        assert_eq!(
+            filters.level("vkengine::renderer::debug"),
+            LevelFilter::Warn
+        );
+        // Only whole modules match
+        assert_eq!(filters.level("vkengine::renderers"), LevelFilter::Info);
+
+        // The most specific module wins
+        filters
+            .apply_directives("error,vkengine::renderer::debug=trace")
+            .unwrap();
+        assert_eq!(filters.level("vkengine::systems"), LevelFilter::Error);
+        assert_eq!(
+            filters.level("vkengine::renderer::debug"),
+            LevelFilter::Trace
+        );
+        assert_eq!(
+            filters.level("vkengine::renderer::batch"),
+            LevelFilter::Warn
+        );
+        assert_eq!(filters.max(), LevelFilter::Trace);
+
+        assert!(filters.apply_directives("vkengine=loud").is_err());
+    }
+
+    #[test]
+    fn commands() {
+        let filters = LevelFilters::from_settings(&LogSettings::default()).unwrap();
+        let log_levels = LogLevels {
+            filters: Arc::new(RwLock::new(filters)),
+        };
+        let level = |module| log_levels.filters.read().unwrap().level(module);
+
+        assert!(log_levels.command("debug").is_ok());
+        assert_eq!(level("vkengine::systems"), LevelFilter::Debug);
+
+        assert!(log_levels.command("vkengine::renderer error").is_ok());
+        assert_eq!(level("vkengine::renderer::debug"), LevelFilter::Error);
+        assert_eq!(level("vkengine::systems"), LevelFilter::Debug);
+
+        assert!(log_levels.command("loud").is_err());
+        assert!(log_levels.command("").is_err());
+        assert_eq!(level("vkengine::systems"), LevelFilter::Debug);
+    }
+
+    #[test]
+    fn rotation() {
+        let dir = env::temp_dir().join(format!("vkengine-log-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("test.log");
+
+        let mut file = RotatingFile::open(path.clone(), 10, 2).unwrap();
+        for line in &["first\n", "second\n", "third\n", "fourth\n"] {
+            file.write_line(line).unwrap();
+        }
+        drop(file);
+
+        // Every line went over the size of the file, the oldest fell off the end
+        assert_eq!(fs::read_to_string(&path).unwrap(), "fourth\n");
+        assert_eq!(
+            fs::read_to_string(dir.join("test.log.1")).unwrap(),
+            "third\n"
+        );
+        assert_eq!(
+            fs::read_to_string(dir.join("test.log.2")).unwrap(),
+            "second\n"
+        );
+        assert!(!dir.join("test.log.3").exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,27 +1,68 @@
 mod components;
+mod crash;
+mod inspector;
+mod logging;
 mod renderer;
 mod resources;
+mod scene;
 mod systems;
 
 use crate::{
-    components::{GlobalTransform, Link, Transform},
+    components::{
+        GlobalTransform, Hidden, Link, Name, PlayerId, PreviousGlobalTransform, Transform,
+        VisibilityInherit,
+    },
+    inspector::Inspector,
     renderer::{
-        camera::{ActiveCamera, Camera},
-        geometry::{MeshBuilder, MeshComponent, Shape},
-        lights::{DirectionalLightRes, PointLightComponent},
-        RenderEvents, Renderer,
+        batch::BatchedMesh,
+        camera::{ActiveCamera, Camera, CameraEffects, Viewport},
+        config::RendererConfig,
+        csg::CsgOp,
+        geometry::{Bounds, Ghost, MeshBuilder, MeshComponent, PendingMesh, Shape},
+        grading::ColorGrading,
+        lights::{AmbientLight, DirectionalLightRes, PointLightComponent},
+        minimap::Minimap,
+        normals::NormalLines,
+        overlay::Overlay,
+        settings::RenderSettings,
+        stats::RenderStats,
+        world_text::WorldTextComponent,
+        MeshReadyEvents, RenderEvent, RenderEvents, Renderer,
+    },
+    resources::{
+        ActionEvents, Clipboard, CursorState, DirtyEntities, FileDropEvents, FocusGained,
+        KeyboardEvents, KeyboardState, Rng, ShouldClose, SoundEffects, TextInput, TextInputEvents,
+        Time, WindowMode, WindowTitle, WindowVisible,
     },
-    resources::{DirtyEntities, FocusGained, KeyboardEvents, ShouldClose, Time},
+    scene::{InScene, Persistent, Scenes},
     systems::{
-        FlyControlSystem, GameInput, GameInputSystem, PlacerSystem, SDLSystem, TimeSystem,
-        TransformSystem,
+        crosshair, Anchor, AssetLoaderSystem, AssetStats, AudioSystem, AutoExposureSystem,
+        AxisSmoothing, BenchmarkConfig, BenchmarkSystem, CameraEffectsSystem, CameraPath,
+        CameraPathSystem, CharacterControlSystem, ChunkStreamingSystem, DebugToggleSystem,
+        DeterminismConfig, EditHistory, EngineState, EngineStateSystem, FileDropLoaderSystem,
+        FixedStep, FlyControlSystem, FlySettings, FollowSystem, FollowTarget, FrameStatsSystem,
+        GameInputSystem, GameInputs, HierarchyCleanupSystem, InStates, InputBindings, Keyframe,
+        LightGizmo, LightGizmoSystem, LoadMesh, ManipulatorSystem, MeshReloadSystem, MeshSource,
+        MinimapSystem, MouseSettings, PathGizmoSystem, Placed, PlacerSystem, SDLSystem, ScaleMode,
+        ScreenLabel, ScreenPosition, ScreenProjectionSystem, Selected, SpatialIndexSystem, Stage,
+        StagedDispatcherBuilder, StreamingSettings, TimeSystem, TransformSystem, UiAnchor,
+        UiLayoutSystem, UiSprite, UiText, VisibilitySystem,
     },
 };
-use nalgebra::UnitQuaternion;
-use nalgebra::Vector3;
+use log::info;
+use nalgebra::{Point3, UnitQuaternion, Vector3};
 use specs::prelude::*;
 use specs_hierarchy::HierarchySystem;
-use std::f32::consts::FRAC_PI_2;
+use std::{
+    env,
+    f32::consts::FRAC_PI_2,
+    path::PathBuf,
+    thread,
+    time::{Duration, Instant},
+};
+
+/// How long a frame takes at least while the window is hidden, 10 Hz
+const HIDDEN_FRAME_TIME: Duration = Duration::from_millis(100);
 
 //TODO Mesh loading
 //TODO Use glyph-brush for text
@@ -29,10 +70,37 @@ use std::f32::consts::FRAC_PI_2;
 //TODO Serialize scenes from file
 
 fn main() {
-    env_logger::init();
+    let log_levels = logging::init("logging.ron");
+
+    // Release builds are usually not started from a terminal, so they tell about crashes in a box
+    crash::install_panic_hook(PathBuf::from("crashes"), !cfg!(debug_assertions));
+
+    // Plays the camera path with a fixed timestep and writes a report, see BenchmarkConfig
+    let benchmark = BenchmarkConfig::from_args(env::args());
+    // Seeds the Rng, and fixes the timestep with --deterministic, see DeterminismConfig
+    let determinism = DeterminismConfig::from_args(env::args());
+    info!(
+        "Seed {}{}",
+        determinism.seed,
+        if determinism.deterministic {
+            ", deterministic"
+        } else {
+            ""
+        }
+    );
+    // --sim-rate steps the simulation at its own rate and interpolates in between, see FixedStep
+    let sim_step = match benchmark {
+        Some(_) => None,
+        None => determinism.fixed_step(),
+    };
 
     let sdl = SDLSystem::new();
-    let renderer = Renderer::new(sdl.window());
+    // --hdr presents in an HDR color space, if the display has one
+    let config = RendererConfig {
+        hdr: env::args().any(|arg| arg == "--hdr"),
+        ..RendererConfig::default()
+    };
+    let renderer = Renderer::new(sdl.window(), config);
 
     // ECS World
     let mut world = World::new();
@@ -41,21 +109,93 @@ fn main() {
     world.register::<Link>();
     world.register::<Transform>();
     world.register::<GlobalTransform>();
+    world.register::<PreviousGlobalTransform>();
     world.register::<MeshComponent>();
     world.register::<MeshBuilder>();
+    world.register::<Bounds>();
+    world.register::<BatchedMesh>();
+    world.register::<Ghost>();
+    world.register::<PendingMesh>();
+    world.register::<NormalLines>();
+    world.register::<WorldTextComponent>();
     world.register::<ActiveCamera>();
     world.register::<Camera>();
+    world.register::<CameraEffects>();
+    world.register::<Viewport>();
     world.register::<PointLightComponent>();
+    world.register::<Placed>();
+    world.register::<LightGizmo>();
+    world.register::<PlayerId>();
+    world.register::<Hidden>();
+    world.register::<VisibilityInherit>();
+    world.register::<Name>();
+    world.register::<MeshSource>();
+    world.register::<LoadMesh>();
+    world.register::<ScreenLabel>();
+    world.register::<ScreenPosition>();
+    world.register::<CameraPath>();
+    world.register::<FollowTarget>();
+    world.register::<Persistent>();
+    world.register::<InScene>();
+    world.register::<Selected>();
+    world.register::<Minimap>();
+    world.register::<UiAnchor>();
+    world.register::<UiText>();
+    world.register::<UiSprite>();
 
     // Add resources
+    world.add_resource(log_levels);
+    world.add_resource(Rng::new(determinism.seed));
     world.add_resource(Time::default());
+    world.add_resource(EngineState::default());
     world.add_resource(ShouldClose::default());
     world.add_resource(FocusGained::default());
-    world.add_resource(GameInput::default());
+    world.add_resource(CursorState::default());
+    world.add_resource(WindowMode::default());
+    world.add_resource(WindowVisible::default());
+    world.add_resource(GameInputs::default());
+    world.add_resource(FlySettings {
+        collision: true,
+        ..FlySettings::default()
+    });
+    world.add_resource(MouseSettings::default());
+    world.add_resource(AxisSmoothing::default());
+    world.add_resource(InputBindings::load("bindings.ron"));
+    world.add_resource(ActionEvents::default());
+    world.add_resource(EditHistory::default());
+    world.add_resource(TextInput::default());
+    world.add_resource(Clipboard::default());
+    world.add_resource(FileDropEvents::default());
+    world.add_resource(TextInputEvents::default());
     world.add_resource(RenderEvents::default());
+    world.add_resource(MeshReadyEvents::default());
     world.add_resource(KeyboardEvents::default());
+    world.add_resource(KeyboardState::default());
+    world.add_resource(SoundEffects::default());
+    world.add_resource(AmbientLight::default());
     world.add_resource(DirectionalLightRes::default());
+    world.add_resource(ColorGrading::load_dir(
+        PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap())
+            .join("resources")
+            .join("luts"),
+    ));
     world.add_resource(DirtyEntities::default());
+    world.add_resource(Inspector::default());
+    world.add_resource(Scenes::default());
+
+    let mut overlay = Overlay::default();
+    overlay.fonts_mut().load_dir(
+        PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap())
+            .join("resources")
+            .join("fonts"),
+    );
+    overlay.add_sprite("crosshair", &crosshair(9, 1));
+    world.add_resource(overlay);
+
+    world.add_resource(RenderSettings::default());
+    world.add_resource(RenderStats::default());
+    world.add_resource(WindowTitle::default());
+    world.add_resource(AssetStats::default());
 
     // Create entities
     world.create_entity().with(Transform::default()).build();
@@ -66,23 +206,40 @@ fn main() {
         .build();
 
     // Sphere
-    world
+    let sphere = world
         .create_entity()
         .with(Link::new(parent))
         .with(Transform::default())
         .with(
-            MeshBuilder::new()
-                // .with_shape(Shape::Sphere(100, 100))
-                .with_gltf_file("glTF-Sample-Models/2.0/Suzanne/glTF/Suzanne.gltf"), // .with_gltf_file("glTF-Sample-Models/2.0/Sponza/glTF/Sponza.gltf")
+            LoadMesh::resource("glTF-Sample-Models/2.0/Suzanne/glTF/Suzanne.gltf"), // LoadMesh::resource("glTF-Sample-Models/2.0/Sponza/glTF/Sponza.gltf")
         )
         .build();
 
     // Cylinder
-    world
+    let cylinder = world
         .create_entity()
         .with(Transform::from(Vector3::new(5.0, 1.0, -7.0)))
         .with(MeshBuilder::new().with_shape(Shape::Cylinder(40)))
-        .with(PointLightComponent::from_color(Vector3::new(0.0, 0.0, 1.0)))
+        .with(PointLightComponent::from_lumens(
+            Vector3::new(0.0, 0.0, 1.0),
+            120.0,
+        ))
+        .with(ScreenLabel::above(1.5).with_text("Cylinder"))
+        .with(Name("cylinder".to_string()))
+        .build();
+
+    // Sign above the cylinder, hidden along with it
+    world
+        .create_entity()
+        .with(Link::new(cylinder))
+        .with(VisibilityInherit)
+        .with(Transform::from(Vector3::new(0.0, 2.0, 0.0)))
+        .with(
+            WorldTextComponent::new("Cylinder")
+                .with_size(0.6)
+                .with_color([1.0, 0.9, 0.6, 1.0])
+                .billboard(),
+        )
         .build();
 
     // Cube
@@ -92,6 +249,38 @@ fn main() {
         .with(MeshBuilder::new().with_shape(Shape::Cube))
         .build();
 
+    // Cube with a dent in the top
+    world
+        .create_entity()
+        .with(Transform::from(Vector3::new(-4.0, -4.0, 5.0)))
+        .with(
+            MeshBuilder::new().with_shape(Shape::Cube).with_csg(
+                CsgOp::Subtract,
+                MeshBuilder::new()
+                    .with_shape(Shape::IcoSphere(2))
+                    .transformed(&Transform::from(Vector3::new(0.0, 0.5, 0.0))),
+            ),
+        )
+        .build();
+
+    // Cube with rounded corners, where it sticks out of a sphere
+    world
+        .create_entity()
+        .with(Transform::from(Vector3::new(-6.0, -4.0, 5.0)))
+        .with(
+            MeshBuilder::new().with_shape(Shape::Cube).with_csg(
+                CsgOp::Intersect,
+                MeshBuilder::new()
+                    .with_shape(Shape::IcoSphere(2))
+                    .transformed(&Transform::from_parts(
+                        Vector3::zeros(),
+                        UnitQuaternion::identity(),
+                        Vector3::new(1.4, 1.4, 1.4),
+                    )),
+            ),
+        )
+        .build();
+
     // Plane
     world
         .create_entity()
@@ -100,44 +289,253 @@ fn main() {
             UnitQuaternion::from_axis_angle(&Vector3::x_axis(), FRAC_PI_2),
             Vector3::new(100.0, 100.0, 1.0),
         ))
-        .with(MeshBuilder::new().with_shape(Shape::Quad(4, 4)))
+        .with(MeshBuilder::new().with_shape(Shape::Quad(4, 4)).batched())
         .build();
 
-    // Camera
+    // Camera, with a path circling the scene, which can follow the sphere instead of flying
+    let center = Point3::new(0.0, -4.0, -2.0);
     world
         .create_entity()
         .with(Transform::default())
         .with(Camera::default())
+        .with(CameraEffects::default())
+        .with(FollowTarget::new(sphere).flown())
         .with(ActiveCamera)
+        .with(Persistent)
+        .with(
+            CameraPath::new(vec![
+                Keyframe::look_at(0.0, Point3::new(0.0, 0.0, 10.0), center),
+                Keyframe::look_at(4.0, Point3::new(12.0, 2.0, 0.0), center),
+                Keyframe::look_at(8.0, Point3::new(0.0, 4.0, -14.0), center),
+                Keyframe::look_at(12.0, Point3::new(-12.0, 2.0, 0.0), center),
+                Keyframe::look_at(16.0, Point3::new(0.0, 0.0, 10.0), center),
+            ])
+            .looping(),
+        )
+        .build();
+
+    // Minimap in the top right corner, following the camera from above
+    world
+        .create_entity()
+        .with(Transform::default())
+        .with(Camera::orthographic(1.0, 25.0, 1.0, 200.0))
+        .with(Minimap::default())
+        .with(Persistent)
+        .build();
+
+    // Name in the bottom right corner, as big on any screen
+    world
+        .create_entity()
+        .with(
+            UiAnchor::new(Anchor::BottomRight)
+                .with_offset([-8.0, -8.0])
+                .with_scale_mode(ScaleMode::WithHeight(720.0)),
+        )
+        .with(UiText::new("vkengine", 16.0))
+        .with(Persistent)
+        .build();
+
+    // Crosshair in the middle of the screen
+    world
+        .create_entity()
+        .with(UiAnchor::new(Anchor::Center).with_scale_mode(ScaleMode::WithHeight(720.0)))
+        .with(UiSprite::new("crosshair", [9.0, 9.0]))
+        .with(Persistent)
         .build();
 
     // Create dispatcher
-    let mut dispatcher = DispatcherBuilder::new()
-        .with(TimeSystem::default(), "time", &[])
-        .with(HierarchySystem::<Link>::new(), "hierarchy", &[])
-        .with(TransformSystem::default(), "transform", &["hierarchy"])
-        .with(GameInputSystem::default(), "input", &[])
-        .with(FlyControlSystem, "fly", &["time", "input"])
-        .with(PlacerSystem, "placer", &["input"])
-        .with(renderer, "renderer", &["time", "transform", "fly"])
-        .with_barrier()
-        .with_thread_local(sdl)
+    // Systems are grouped into stages, so whole stages can be paused
+    let mut dispatcher = StagedDispatcherBuilder::new()
+        .with_stage(Stage::Input, |builder| {
+            let time = match &benchmark {
+                Some(benchmark) => TimeSystem::fixed(benchmark.timestep),
+                None if determinism.deterministic => TimeSystem::fixed(determinism.timestep),
+                None => sim_step.map(TimeSystem::fixed).unwrap_or_default(),
+            };
+
+            builder
+                .with(time, "time", &[])
+                .with(GameInputSystem::default(), "input", &["time"])
+                .with(DebugToggleSystem::default(), "debug_toggle", &["input"])
+                .with(EngineStateSystem::default(), "engine_state", &["input"])
+                .with_thread_local(sdl)
+        })
+        .with_stage(Stage::Simulation, |builder| {
+            builder
+                .with(
+                    InStates::new(
+                        FlyControlSystem,
+                        &[EngineState::Running, EngineState::Editor],
+                    ),
+                    "fly",
+                    &[],
+                )
+                .with(
+                    InStates::new(
+                        CharacterControlSystem::default(),
+                        &[EngineState::Running, EngineState::Editor],
+                    ),
+                    "character",
+                    // Looks with the mouse motion filtered by the fly system
+                    &["fly"],
+                )
+                .with(
+                    InStates::new(
+                        FollowSystem::default(),
+                        &[EngineState::Running, EngineState::Editor],
+                    ),
+                    "follow",
+                    &["fly", "character"],
+                )
+                .with(
+                    InStates::new(
+                        CameraPathSystem::default(),
+                        &[EngineState::Running, EngineState::Editor],
+                    ),
+                    "camera_path",
+                    &["fly", "character", "follow"],
+                )
+                .with(
+                    InStates::new(
+                        PlacerSystem::default(),
+                        &[EngineState::Running, EngineState::Editor],
+                    ),
+                    "placer",
+                    &[],
+                )
+                .with(
+                    MinimapSystem::default(),
+                    "minimap",
+                    &["fly", "character", "camera_path"],
+                )
+                // Keeps track of the state itself, to deselect outside of the editor
+                .with(
+                    ManipulatorSystem::default(),
+                    "manipulator",
+                    &["fly", "character", "camera_path"],
+                )
+                .with(
+                    InStates::new(
+                        FileDropLoaderSystem::default(),
+                        &[EngineState::Running, EngineState::Editor],
+                    ),
+                    "file_drop_loader",
+                    &[],
+                )
+        })
+        .with_stage(Stage::PostSimulation, |builder| {
+            builder
+                .with(MeshReloadSystem::default(), "mesh_reload", &[])
+                .with(AssetLoaderSystem::default(), "asset_loader", &[])
+                .with(AutoExposureSystem::default(), "auto_exposure", &[])
+                .with(CameraEffectsSystem::default(), "camera_effects", &[])
+                .with(LightGizmoSystem::default(), "light_gizmos", &[])
+                .with(PathGizmoSystem::default(), "path_gizmos", &[])
+                .with(UiLayoutSystem::default(), "ui_layout", &[])
+                .with(
+                    HierarchySystem::<Link>::new(),
+                    "hierarchy",
+                    &["light_gizmos"],
+                )
+                .with(
+                    HierarchyCleanupSystem::default(),
+                    "hierarchy_cleanup",
+                    &["hierarchy"],
+                )
+                .with(
+                    TransformSystem::default(),
+                    "transform",
+                    &["hierarchy_cleanup"],
+                )
+                .with(
+                    VisibilitySystem::default(),
+                    "visibility",
+                    &["hierarchy_cleanup"],
+                )
+                .with(
+                    ScreenProjectionSystem::default(),
+                    "screen_projection",
+                    &["transform"],
+                )
+                .with(
+                    SpatialIndexSystem::default(),
+                    "spatial_index",
+                    &["transform"],
+                )
+                .with(
+                    ChunkStreamingSystem::new(StreamingSettings {
+                        dir: PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap())
+                            .join("resources")
+                            .join("chunks"),
+                        ..StreamingSettings::default()
+                    }),
+                    "chunk_streaming",
+                    &["transform"],
+                )
+        })
+        .with_stage(Stage::Render, |builder| {
+            let builder = builder
+                .with(renderer, "renderer", &[])
+                // Optional, shows fps and other stats in the window title
+                .with(FrameStatsSystem::default(), "frame_stats", &["renderer"])
+                .with(
+                    AudioSystem::new(
+                        PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap())
+                            .join("resources")
+                            .join("sounds"),
+                    ),
+                    "audio",
+                    &[],
+                );
+
+            match benchmark {
+                Some(benchmark) => {
+                    builder.with(BenchmarkSystem::new(benchmark), "benchmark", &["renderer"])
+                }
+                None => builder,
+            }
+        })
         .build();
 
     // Setup the systems
     dispatcher.setup(&mut world.res);
+    let mut inspect_read_id = world.write_resource::<ActionEvents>().register_reader();
+    let mut fixed_step = sim_step.map(FixedStep::new);
 
     // The gameloop dispatches the systems and checks if the game should close
     'gameloop: loop {
-        dispatcher.dispatch(&world.res);
+        let frame_start = Instant::now();
+
+        match &mut fixed_step {
+            Some(fixed_step) => dispatcher.dispatch_fixed(&mut world, fixed_step),
+            None => dispatcher.dispatch(&world.res),
+        }
         world.maintain();
 
-        world.exec(|mut dirty_entities: Write<DirtyEntities>| {
-            dirty_entities.dirty.clear();
-        });
+        // Scenes are loaded and unloaded between frames, see Scenes
+        scene::apply(&mut world);
+        inspector::apply(&world, &mut inspect_read_id);
 
         if world.read_resource::<ShouldClose>().0 {
             break 'gameloop;
         }
+
+        // Nothing is drawn while the window is hidden, so there is no point in spinning
+        if !world.read_resource::<WindowVisible>().0 {
+            if let Some(rest) = HIDDEN_FRAME_TIME.checked_sub(frame_start.elapsed()) {
+                thread::sleep(rest);
+            }
+        }
     }
+
+    // Shutdown
+    // Let the renderer wait for the GPU before anything it might still be using is dropped
+    world
+        .write_resource::<RenderEvents>()
+        .single_write(RenderEvent::Shutdown);
+    dispatcher.dispatch_stage(Stage::Render, &world.res);
+
+    // The systems, the renderer among them, go before the world and the meshes in it
+    drop(dispatcher);
+    drop(world);
 }
@@ -0,0 +1,220 @@
+use std::{collections::HashMap, hash::Hash};
+
+/// Empty pixels left around every region, so filtering at its edges does not pick up its
+/// neighbours
+const PADDING: u32 = 1;
+
+/// Where an image was packed into an Atlas
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AtlasRegion {
+    /// The page, which is the layer of the atlas texture
+    pub page: u32,
+    pub uv_min: [f32; 2],
+    pub uv_max: [f32; 2],
+    /// In pixels
+    pub size: [u32; 2],
+}
+
+/// A row of regions of at most the same height, filled from left to right
+#[derive(Debug)]
+struct Shelf {
+    y: u32,
+    height: u32,
+    x: u32,
+}
+
+#[derive(Debug)]
+struct Page {
+    texels: Vec<[u8; 4]>,
+    shelves: Vec<Shelf>,
+}
+
+impl Page {
+    fn new(size: u32) -> Self {
+        Self {
+            texels: vec![[0; 4]; (size * size) as usize],
+            shelves: Vec::new(),
+        }
+    }
+
+    /// Finds room for a padded rectangle, on the flattest shelf it fits on or on a new one
+    fn allocate(&mut self, size: u32, width: u32, height: u32) -> Option<(u32, u32)> {
+        let shelf = self
+            .shelves
+            .iter_mut()
+            .filter(|shelf| shelf.height >= height && size - shelf.x >= width)
+            .min_by_key(|shelf| shelf.height);
+
+        if let Some(shelf) = shelf {
+            let x = shelf.x;
+            shelf.x += width;
+            return Some((x, shelf.y));
+        }
+
+        let y = self
+            .shelves
+            .last()
+            .map_or(0, |shelf| shelf.y + shelf.height);
+        if size - y < height {
+            return None;
+        }
+
+        self.shelves.push(Shelf {
+            y,
+            height,
+            x: width,
+        });
+        Some((0, y))
+    }
+}
+
+/// Packs many small images, like glyphs and sprites, into the pages of one texture
+///
+/// Images are packed onto shelves as they are added, and never moved or removed, so regions stay
+/// valid. A new page is started when an image fits on none of the others. The renderer uploads
+/// the pages as the layers of an array texture, so everything drawn from one atlas can be drawn
+/// together.
+#[derive(Debug)]
+pub struct Atlas<K> {
+    size: u32,
+    pages: Vec<Page>,
+    regions: HashMap<K, AtlasRegion>,
+    dirty: bool,
+}
+
+impl<K: Hash + Eq> Atlas<K> {
+    /// An empty atlas with square pages of `size` pixels
+    pub fn new(size: u32) -> Self {
+        Self {
+            size,
+            pages: Vec::new(),
+            regions: HashMap::new(),
+            dirty: false,
+        }
+    }
+
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    pub fn page_count(&self) -> u32 {
+        self.pages.len() as u32
+    }
+
+    pub fn get(&self, key: &K) -> Option<AtlasRegion> {
+        self.regions.get(key).cloned()
+    }
+
+    /// Packs an image of `width` by `height` pixels, stored row by row, and returns where
+    ///
+    /// If the key is already in the atlas, the image is not packed again. Panics if the image is
+    /// larger than a page.
+    pub fn insert(&mut self, key: K, width: u32, height: u32, texels: &[[u8; 4]]) -> AtlasRegion {
+        if let Some(region) = self.regions.get(&key) {
+            return *region;
+        }
+
+        assert_eq!(texels.len(), (width * height) as usize);
+        let (padded_width, padded_height) = (width + PADDING * 2, height + PADDING * 2);
+        assert!(
+            padded_width <= self.size && padded_height <= self.size,
+            "A {}x{} image does not fit into an atlas of {} pixels",
+            width,
+            height,
+            self.size
+        );
+
+        let size = self.size;
+        let allocation = self
+            .pages
+            .iter_mut()
+            .enumerate()
+            .find_map(|(i, page)| Some((i, page.allocate(size, padded_width, padded_height)?)));
+
+        let (page, (x, y)) = match allocation {
+            Some(allocation) => allocation,
+            None => {
+                let mut page = Page::new(size);
+                let position = page.allocate(size, padded_width, padded_height).unwrap();
+                self.pages.push(page);
+                (self.pages.len() - 1, position)
+            }
+        };
+
+        let (x, y) = (x + PADDING, y + PADDING);
+        let page_texels = &mut self.pages[page].texels;
+        for row in 0..height {
+            let start = ((y + row) * size + x) as usize;
+            let source = (row * width) as usize;
+            page_texels[start..start + width as usize]
+                .copy_from_slice(&texels[source..source + width as usize]);
+        }
+
+        let region = AtlasRegion {
+            page: page as u32,
+            uv_min: [x as f32 / size as f32, y as f32 / size as f32],
+            uv_max: [
+                (x + width) as f32 / size as f32,
+                (y + height) as f32 / size as f32,
+            ],
+            size: [width, height],
+        };
+
+        self.regions.insert(key, region);
+        self.dirty = true;
+        region
+    }
+
+    /// The texels of every page, one page after the other
+    pub fn texels(&self) -> impl ExactSizeIterator<Item = [u8; 4]> + '_ {
+        self.pages
+            .iter()
+            .flat_map(|page| page.texels.iter().cloned())
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+
+    /// Whether anything was packed since the last call, and the texture has to be uploaded again
+    pub fn take_dirty(&mut self) -> bool {
+        let dirty = self.dirty;
+        self.dirty = false;
+        dirty
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Atlas;
+
+    #[test]
+    fn packing() {
+        let mut atlas = Atlas::new(16);
+
+        // Padded to 6x6, so two fit next to each other
+        let a = atlas.insert("a", 4, 4, &[[255; 4]; 16]);
+        let b = atlas.insert("b", 4, 4, &[[128; 4]; 16]);
+        assert_eq!(a.page, 0);
+        assert_eq!(a.uv_min, [1.0 / 16.0, 1.0 / 16.0]);
+        assert_eq!(a.uv_max, [5.0 / 16.0, 5.0 / 16.0]);
+        assert_eq!(b.uv_min, [7.0 / 16.0, 1.0 / 16.0]);
+        assert!(atlas.take_dirty());
+        assert!(!atlas.take_dirty());
+
+        // Adding the same key again finds the old region
+        assert_eq!(atlas.insert("a", 4, 4, &[[0; 4]; 16]), a);
+        assert!(!atlas.take_dirty());
+
+        // A new shelf below, and a new page once the first is full
+        let c = atlas.insert("c", 8, 8, &[[64; 4]; 64]);
+        assert_eq!((c.page, c.uv_min), (0, [1.0 / 16.0, 7.0 / 16.0]));
+        let d = atlas.insert("d", 8, 8, &[[32; 4]; 64]);
+        assert_eq!(d.page, 1);
+        assert_eq!(atlas.page_count(), 2);
+
+        let texels = atlas.texels().collect::<Vec<_>>();
+        assert_eq!(texels.len(), 2 * 16 * 16);
+        assert_eq!(texels[16 + 1], [255; 4]);
+        assert_eq!(texels[16 + 7], [128; 4]);
+        assert_eq!(texels[0], [0; 4]);
+    }
+}
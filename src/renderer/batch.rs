@@ -0,0 +1,350 @@
+use crate::{
+    components::{render_matrix, GlobalTransform, PreviousGlobalTransform},
+    renderer::{
+        frame::FRAMES_IN_FLIGHT,
+        geometry::{Bounds, Vertex},
+        memory::{BufferAllocator, MemoryUse, SubBuffer},
+        shaders::{CullObject, PushConstants},
+    },
+};
+use log::info;
+use nalgebra::Matrix4;
+use specs::prelude::*;
+use specs_derive::Component;
+use std::sync::Arc;
+use vulkano::{
+    buffer::{BufferSlice, BufferUsage, CpuAccessibleBuffer, DeviceLocalBuffer},
+    command_buffer::{AutoCommandBufferBuilder, DrawIndexedIndirectCommand, DynamicState},
+    descriptor::{descriptor_set::PersistentDescriptorSet, DescriptorSet},
+    device::Device,
+    pipeline::GraphicsPipelineAbstract,
+};
+
+/// Marks an entity whose mesh lives in the renderer's static batch
+#[derive(Component, Debug, Default)]
+#[storage(NullStorage)]
+pub struct BatchedMesh;
+
+/// A mesh in the batch and where its data is in the shared buffers
+struct BatchEntry {
+    entity: Entity,
+    vertex_data: Vec<Vertex>,
+    index_data: Vec<u32>,
+    first_index: u32,
+    vertex_offset: u32,
+}
+
+/// The model matrices of the batch for one frame in flight
+struct BatchModels {
+    buffer: Arc<CpuAccessibleBuffer<[[[f32; 4]; 4]]>>,
+    descriptor_set: Arc<DescriptorSet + Send + Sync>,
+    // Entities whose model matrix in this frame is out of date
+    stale: BitSet,
+}
+
+/// The GPU side of the batch
+struct BatchBuffers {
+    vertex_buffer: Arc<SubBuffer<[Vertex]>>,
+    index_buffer: Arc<SubBuffer<[u32]>>,
+    models: Vec<BatchModels>,
+}
+
+/// Meshes sharing the same pipeline, packed into one vertex and one index buffer
+///
+/// Every mesh gets a draw command with its own offsets into the shared buffers, and the index of
+/// its model matrix as the first instance, so the whole batch is drawn with one indirect call.
+pub struct MeshBatch {
+    device: Arc<Device>,
+    memory: BufferAllocator,
+    pipeline: Arc<GraphicsPipelineAbstract + Send + Sync>,
+    entries: Vec<BatchEntry>,
+    buffers: Option<BatchBuffers>,
+}
+
+impl MeshBatch {
+    pub fn new(
+        device: Arc<Device>,
+        memory: BufferAllocator,
+        pipeline: Arc<GraphicsPipelineAbstract + Send + Sync>,
+    ) -> Self {
+        Self {
+            device,
+            memory,
+            pipeline,
+            entries: Vec::new(),
+            buffers: None,
+        }
+    }
+
+    pub fn pipeline(&self) -> Arc<GraphicsPipelineAbstract + Send + Sync> {
+        self.pipeline.clone()
+    }
+
+    /// Draws the batch with another pipeline, whose descriptor set layouts have to be the same
+    pub fn set_pipeline(&mut self, pipeline: Arc<GraphicsPipelineAbstract + Send + Sync>) {
+        self.pipeline = pipeline;
+    }
+
+    /// Number of draws in the batch
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Adds a mesh to the batch. The buffers are rebuilt on the next call to `prepare`
+    ///
+    /// An entity that is already in the batch has its mesh replaced.
+    pub fn push(&mut self, entity: Entity, vertex_data: Vec<Vertex>, index_data: Vec<u32>) {
+        if let Some(i) = self.entries.iter().position(|entry| entry.entity == entity) {
+            self.entries.remove(i);
+            self.repack();
+        }
+
+        let (first_index, vertex_offset) = self
+            .entries
+            .last()
+            .map(|last| {
+                (
+                    last.first_index + last.index_data.len() as u32,
+                    last.vertex_offset + last.vertex_data.len() as u32,
+                )
+            })
+            .unwrap_or((0, 0));
+
+        self.entries.push(BatchEntry {
+            entity,
+            vertex_data,
+            index_data,
+            first_index,
+            vertex_offset,
+        });
+
+        self.buffers = None;
+    }
+
+    /// Removes meshes whose entities are no longer batched, and repacks the rest
+    pub fn retain(&mut self, entities: &Entities<'_>, batched: &WriteStorage<'_, BatchedMesh>) {
+        let len = self.entries.len();
+        self.entries
+            .retain(|entry| entities.is_alive(entry.entity) && batched.contains(entry.entity));
+
+        if self.entries.len() != len {
+            self.repack();
+        }
+    }
+
+    /// Packs the entries back to back again after some have been removed
+    fn repack(&mut self) {
+        let mut first_index = 0;
+        let mut vertex_offset = 0;
+        for entry in self.entries.iter_mut() {
+            entry.first_index = first_index;
+            entry.vertex_offset = vertex_offset;
+            first_index += entry.index_data.len() as u32;
+            vertex_offset += entry.vertex_data.len() as u32;
+        }
+
+        self.buffers = None;
+    }
+
+    /// Rebuilds the shared buffers if meshes have been added or removed since last frame
+    pub fn prepare(&mut self, globals: &ReadStorage<'_, GlobalTransform>) {
+        if self.buffers.is_some() || self.entries.is_empty() {
+            return;
+        }
+
+        info!("Rebuilding mesh batch with {} meshes", self.entries.len());
+
+        let vertex_buffer = self
+            .memory
+            .sub_array(
+                MemoryUse::Mesh,
+                self.entries
+                    .iter()
+                    .flat_map(|entry| entry.vertex_data.iter().cloned()),
+            )
+            .expect("Failed to create batch vertex buffer");
+
+        let index_buffer = self
+            .memory
+            .sub_array(
+                MemoryUse::Mesh,
+                self.entries
+                    .iter()
+                    .flat_map(|entry| entry.index_data.iter().cloned()),
+            )
+            .expect("Failed to create batch index buffer");
+
+        let models = (0..FRAMES_IN_FLIGHT)
+            .map(|_| {
+                let buffer = self
+                    .memory
+                    .from_iter(
+                        MemoryUse::Scene,
+                        BufferUsage {
+                            storage_buffer: true,
+                            ..BufferUsage::none()
+                        },
+                        self.entries.iter().map(|entry| {
+                            globals
+                                .get(entry.entity)
+                                .map(|global| global.to_matrix().into())
+                                .unwrap_or_else(|| Matrix4::identity().into())
+                        }),
+                    )
+                    .expect("Failed to create batch model buffer");
+
+                let descriptor_set = Arc::new(
+                    PersistentDescriptorSet::start(self.pipeline.clone(), 0)
+                        .add_buffer(buffer.clone())
+                        .unwrap()
+                        .build()
+                        .unwrap(),
+                );
+
+                BatchModels {
+                    buffer,
+                    descriptor_set,
+                    stale: BitSet::new(),
+                }
+            })
+            .collect();
+
+        self.buffers = Some(BatchBuffers {
+            vertex_buffer,
+            index_buffer,
+            models,
+        });
+    }
+
+    /// Writes the model matrices of the current frame, whose fence has to have been waited on
+    ///
+    /// Moved entities are remembered for the other frames in flight, and updated once those
+    /// frames come around.
+    pub fn update_models(
+        &mut self,
+        frame_index: usize,
+        globals: &ReadStorage<'_, GlobalTransform>,
+        previous: &ReadStorage<'_, PreviousGlobalTransform>,
+        alpha: f32,
+        moved: &BitSet,
+    ) {
+        let buffers = match self.buffers.as_mut() {
+            Some(buffers) => buffers,
+            None => return,
+        };
+
+        for models in buffers.models.iter_mut() {
+            models.stale |= moved;
+        }
+
+        let models = &mut buffers.models[frame_index];
+        let mut data = models
+            .buffer
+            .write()
+            .expect("Batch models written while the GPU is using them");
+
+        for (i, entry) in self.entries.iter().enumerate() {
+            if !models.stale.contains(entry.entity.id()) {
+                continue;
+            }
+
+            if let Some(global) = globals.get(entry.entity) {
+                data[i] = render_matrix(global, previous.get(entry.entity), alpha).into();
+            }
+        }
+
+        models.stale.clear();
+    }
+
+    /// The culling input for every mesh in the batch, in draw order
+    pub fn cull_objects(
+        &self,
+        bounds: &WriteStorage<'_, Bounds>,
+        globals: &ReadStorage<'_, GlobalTransform>,
+        hidden: &BitSet,
+    ) -> Vec<CullObject> {
+        self.entries
+            .iter()
+            .enumerate()
+            .map(|(i, entry)| {
+                let sphere = match (bounds.get(entry.entity), globals.get(entry.entity)) {
+                    (Some(bounds), Some(global)) => {
+                        let sphere = bounds.world_sphere(global);
+                        let center = sphere.center();
+                        [center.x, center.y, center.z, sphere.radius()]
+                    }
+                    // Without bounds we can not cull the mesh, so make sure it is always visible
+                    _ => [0.0, 0.0, 0.0, std::f32::INFINITY],
+                };
+
+                // Hidden meshes keep their place in the batch, but draw no indices
+                let index_count = if hidden.contains(entry.entity.id()) {
+                    0
+                } else {
+                    entry.index_data.len() as u32
+                };
+
+                CullObject {
+                    sphere,
+                    draw: [
+                        index_count,
+                        entry.first_index,
+                        entry.vertex_offset,
+                        i as u32,
+                    ],
+                }
+            })
+            .collect()
+    }
+
+    /// Records the draw of the whole batch into a secondary command buffer
+    ///
+    /// The draw commands for the batch are expected to start at `first` in `indirect_buffer`.
+    pub fn draw(
+        &self,
+        builder: AutoCommandBufferBuilder,
+        frame_index: usize,
+        dynamic_state: &DynamicState,
+        indirect_buffer: Arc<DeviceLocalBuffer<[DrawIndexedIndirectCommand]>>,
+        first: usize,
+        shared_descriptor_set: Arc<DescriptorSet + Send + Sync>,
+        pc: PushConstants,
+    ) -> AutoCommandBufferBuilder {
+        let buffers = match self.buffers.as_ref() {
+            Some(buffers) => buffers,
+            None => return builder,
+        };
+
+        let descriptor_sets = vec![
+            buffers.models[frame_index].descriptor_set.clone(),
+            shared_descriptor_set,
+        ];
+
+        let draw = |builder: AutoCommandBufferBuilder, range: std::ops::Range<usize>| {
+            builder
+                .draw_indexed_indirect(
+                    self.pipeline.clone(),
+                    dynamic_state,
+                    vec![buffers.vertex_buffer.clone()],
+                    buffers.index_buffer.clone(),
+                    BufferSlice::from_typed_buffer_access(indirect_buffer.clone())
+                        .slice(range)
+                        .unwrap(),
+                    descriptor_sets.clone(),
+                    pc,
+                )
+                .unwrap()
+        };
+
+        // Without multi draw indirect, every draw command has to be submitted on its own
+        if self.device.enabled_features().multi_draw_indirect {
+            draw(builder, first..first + self.len())
+        } else {
+            (first..first + self.len()).fold(builder, |builder, i| draw(builder, i..i + 1))
+        }
+    }
+}
@@ -1,9 +1,17 @@
-use nalgebra::{Matrix4, Perspective3};
+use crate::components::damp;
+use nalgebra::{
+    Isometry3, Matrix4, Orthographic3, Perspective3, Translation3, UnitQuaternion, Vector3,
+};
 use specs::{Component, HashMapStorage, NullStorage};
 use specs_derive::Component;
+use vulkano::{
+    command_buffer::DynamicState,
+    pipeline::viewport::{Scissor, Viewport as PixelViewport},
+};
 
-static CLIP_NEAR: f32 = 0.01f32;
-static CLIP_FAR: f32 = 100f32;
+/// Clip planes of cameras made without any
+pub const DEFAULT_NEAR: f32 = 0.01;
+pub const DEFAULT_FAR: f32 = 100.0;
 
 #[derive(Component, Default)]
 #[storage(NullStorage)]
@@ -12,30 +20,144 @@ pub struct ActiveCamera;
 #[derive(Component)]
 #[storage(HashMapStorage)]
 pub struct Camera {
-    pub projection: Perspective3<f32>,
     pub scale: Matrix4<f32>,
+    /// Scales how bright the scene looks, before it is color graded
+    ///
+    /// The scene is rendered in HDR, so parts brighter than white can be brought back into range
+    /// with an exposure below 1.
+    pub exposure: f32,
+    aspect: f32,
     fovy: f32,
+    near: f32,
+    far: Option<f32>,
+    /// Half the height of the view of an orthographic camera, in world units, or None for a
+    /// perspective camera
+    half_height: Option<f32>,
 }
 
 impl Camera {
     pub fn new(aspect: f32, fovy: f32) -> Self {
-        let projection = Perspective3::new(aspect, fovy, CLIP_NEAR, CLIP_FAR);
+        Self::with_clip_planes(aspect, fovy, DEFAULT_NEAR, Some(DEFAULT_FAR))
+    }
 
+    /// A camera seeing from `near` to `far`, or without a far plane if it is None
+    ///
+    /// Nothing is clipped in the distance without a far plane, and depth precision only depends
+    /// on the near plane, so it should be as far out as the scene allows.
+    pub fn with_clip_planes(aspect: f32, fovy: f32, near: f32, far: Option<f32>) -> Self {
         let scale = Matrix4::new_scaling(1.0);
 
         Self {
-            projection,
             scale,
+            exposure: 1.0,
+            aspect,
             fovy,
+            near,
+            far,
+            half_height: None,
+        }
+    }
+
+    /// A camera without perspective, seeing `half_height` world units above and below its center
+    /// at any distance
+    ///
+    /// Orthographic cameras always have a far plane, and their depth is spread evenly between the
+    /// planes.
+    pub fn orthographic(aspect: f32, half_height: f32, near: f32, far: f32) -> Self {
+        Self {
+            half_height: Some(half_height),
+            ..Self::with_clip_planes(aspect, std::f32::consts::FRAC_PI_2, near, Some(far))
+        }
+    }
+
+    /// Half the height of the view of an orthographic camera, None if it has perspective
+    pub fn half_height(&self) -> Option<f32> {
+        self.half_height
+    }
+
+    /// Zooms an orthographic camera, does nothing to a perspective one
+    pub fn set_half_height(&mut self, half_height: f32) {
+        if self.half_height.is_some() {
+            self.half_height = Some(half_height);
         }
     }
 
     pub fn update_aspect(&mut self, aspect: f32) {
-        self.projection = Perspective3::new(aspect, self.fovy, CLIP_NEAR, CLIP_FAR);
+        self.aspect = aspect;
+    }
+
+    pub fn near(&self) -> f32 {
+        self.near
+    }
+
+    /// None if the camera has no far plane
+    pub fn far(&self) -> Option<f32> {
+        self.far
+    }
+
+    pub fn set_clip_planes(&mut self, near: f32, far: Option<f32>) {
+        self.near = near;
+        self.far = far;
+    }
+
+    fn projection_matrix(&self) -> Matrix4<f32> {
+        if let Some(h) = self.half_height {
+            let far = self.far.unwrap_or(DEFAULT_FAR);
+            let w = h * self.aspect;
+            return Orthographic3::new(-w, w, -h, h, self.near, far).into_inner();
+        }
+
+        match self.far {
+            Some(far) => Perspective3::new(self.aspect, self.fovy, self.near, far).into_inner(),
+            None => {
+                // The limit of the perspective projection as the far plane goes to infinity
+                let mut m = Perspective3::new(self.aspect, self.fovy, self.near, self.near * 2.0)
+                    .into_inner();
+                m[(2, 2)] = -1.0;
+                m[(2, 3)] = -2.0 * self.near;
+                m
+            }
+        }
     }
 
     pub fn projection(&self) -> [[f32; 4]; 4] {
-        let mut p: [[f32; 4]; 4] = self.projection.into_inner().into();
+        let mut p: [[f32; 4]; 4] = self.projection_matrix().into();
+
+        // Flip the y-axis
+        p[1][1] *= -1.0;
+
+        p
+    }
+
+    /// The projection the scene is rendered with
+    ///
+    /// With `reversed_z`, the near plane is mapped to a depth of 1 and the far plane to 0, instead
+    /// of the -1 to 1 of `projection`, which culling and everything else on the CPU keep using.
+    pub fn depth_projection(&self, reversed_z: bool) -> [[f32; 4]; 4] {
+        if !reversed_z {
+            return self.projection();
+        }
+
+        // Depth is near / distance without a far plane, and reaches 0 at the far plane otherwise.
+        // Orthographic depth is linear in the distance instead, with w staying 1
+        let (a, b) = match (self.half_height, self.far) {
+            (Some(_), far) => {
+                let far = far.unwrap_or(DEFAULT_FAR);
+                let a = 1.0 / (far - self.near);
+                (a, a * far)
+            }
+            (None, Some(far)) => {
+                let a = self.near / (far - self.near);
+                (a, a * far)
+            }
+            (None, None) => (0.0, self.near),
+        };
+
+        let mut m = self.projection_matrix();
+        m[(2, 2)] = a;
+        m[(2, 3)] = b;
+
+        let mut p: [[f32; 4]; 4] = m.into();
 
         // Flip the y-axis
         p[1][1] *= -1.0;
@@ -49,3 +171,440 @@ impl Default for Camera {
         Self::new(16. / 9., std::f32::consts::FRAC_PI_2)
     }
 }
+
+/// Adapts the exposure of the camera to how bright the scene it sees is, like an eye would
+///
+/// The renderer measures the average luminance of the camera's view, and the
+/// AutoExposureSystem moves the exposure towards the one that brings it to `key`.
+#[derive(Component, Debug, Clone)]
+#[storage(HashMapStorage)]
+pub struct AutoExposure {
+    /// The average luminance the scene is exposed to, middle grey by default
+    pub key: f32,
+    pub min_exposure: f32,
+    pub max_exposure: f32,
+    /// How fast the exposure follows the scene, higher is faster
+    pub speed: f32,
+    /// The average luminance of the last measured frame, before exposure
+    pub luminance: Option<f32>,
+}
+
+impl AutoExposure {
+    /// The exposure bringing the measured luminance to the key, within the limits
+    pub fn target(&self) -> Option<f32> {
+        self.luminance.map(|luminance| {
+            (self.key / luminance.max(std::f32::EPSILON))
+                .max(self.min_exposure)
+                .min(self.max_exposure)
+        })
+    }
+
+    /// Moves `exposure` towards the target over `delta` seconds
+    ///
+    /// The exposure is adapted in log space, so getting brighter and darker take equally long.
+    pub fn adapt(&self, exposure: f32, delta: f32) -> f32 {
+        let target = match self.target() {
+            Some(target) => target,
+            None => return exposure,
+        };
+
+        let log_exposure = exposure.max(std::f32::EPSILON).ln();
+
+        damp(log_exposure, target.ln(), self.speed, delta).exp()
+    }
+}
+
+impl Default for AutoExposure {
+    fn default() -> Self {
+        Self {
+            key: 0.18,
+            min_exposure: 0.1,
+            max_exposure: 10.0,
+            speed: 1.5,
+            luminance: None,
+        }
+    }
+}
+
+/// Shakes and sways the view of a camera, without moving the camera itself
+///
+/// The renderer applies the effects on top of the view, so gameplay and anything else reading
+/// the Transform of the camera is not disturbed. Shake comes from trauma, added with
+/// `add_trauma` and wearing off over time, and grows with its square, so small hits barely shake
+/// at all. Sway is a slow drift, like a camera held by hand. Both follow smooth noise, advanced by
+/// the CameraEffectsSystem.
+#[derive(Component, Debug, Clone)]
+#[storage(HashMapStorage)]
+pub struct CameraEffects {
+    /// How much the camera shakes, from 0 for not at all to 1
+    pub trauma: f32,
+    /// Trauma lost per second
+    pub recovery: f32,
+    /// The largest rotation of a shake around x, y and z, in radians
+    pub max_angles: Vector3<f32>,
+    /// The largest offset of a shake along x, y and z, in world units
+    pub max_offset: Vector3<f32>,
+    /// How fast the camera shakes, in noise cycles per second
+    pub frequency: f32,
+    /// The largest rotation of the sway around x and y, in radians, 0 for none
+    pub sway: f32,
+    /// How fast the camera sways, in noise cycles per second
+    pub sway_frequency: f32,
+    /// Seconds the effects have run for, where the noise is sampled
+    time: f32,
+}
+
+impl CameraEffects {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_sway(mut self, sway: f32, frequency: f32) -> Self {
+        self.sway = sway;
+        self.sway_frequency = frequency;
+        self
+    }
+
+    /// Shakes the camera more, up to a trauma of 1
+    pub fn add_trauma(&mut self, trauma: f32) {
+        self.trauma = (self.trauma + trauma).max(0.0).min(1.0);
+    }
+
+    /// Moves the noise along and lets trauma wear off, over `delta` seconds
+    pub fn advance(&mut self, delta: f32) {
+        self.time += delta;
+        self.trauma = (self.trauma - self.recovery * delta).max(0.0);
+    }
+
+    /// The shake and sway, as a move of the camera in its own space
+    pub fn offset(&self) -> Isometry3<f32> {
+        let shake = self.trauma * self.trauma;
+        let t = self.time * self.frequency;
+        let sway_t = self.time * self.sway_frequency;
+
+        // Every axis follows noise of its own
+        let angles = Vector3::new(
+            noise(t, 0) * self.max_angles.x * shake + noise(sway_t, 3) * self.sway,
+            noise(t, 1) * self.max_angles.y * shake + noise(sway_t, 4) * self.sway,
+            noise(t, 2) * self.max_angles.z * shake,
+        );
+        let offset = Vector3::new(
+            noise(t, 5) * self.max_offset.x,
+            noise(t, 6) * self.max_offset.y,
+            noise(t, 7) * self.max_offset.z,
+        ) * shake;
+
+        Isometry3::from_parts(
+            Translation3::from(offset),
+            UnitQuaternion::from_euler_angles(angles.x, angles.y, angles.z),
+        )
+    }
+
+    /// `view` with the effects applied, as if the camera had moved by `offset`
+    pub fn apply(&self, view: &Matrix4<f32>) -> Matrix4<f32> {
+        self.offset().inverse().to_homogeneous() * view
+    }
+}
+
+impl Default for CameraEffects {
+    fn default() -> Self {
+        Self {
+            trauma: 0.0,
+            recovery: 1.0,
+            max_angles: Vector3::new(0.05, 0.05, 0.1),
+            max_offset: Vector3::new(0.1, 0.1, 0.0),
+            frequency: 15.0,
+            sway: 0.0,
+            sway_frequency: 0.3,
+            time: 0.0,
+        }
+    }
+}
+
+/// Smooth gradient noise from -1 to 1, the same for the same `x` and `seed`, and 0 at every whole
+/// `x`
+fn noise(x: f32, seed: u32) -> f32 {
+    // A gradient from -1 to 1 for every whole x, hashed from it and the seed
+    let gradient = |cell: f32| {
+        let mut h = (cell as i32 as u32).wrapping_mul(0x27d4_eb2d) ^ seed.wrapping_mul(0x1656_67b1);
+        h ^= h >> 15;
+        h = h.wrapping_mul(0x85eb_ca6b);
+        h ^= h >> 13;
+
+        h as f32 / std::u32::MAX as f32 * 2.0 - 1.0
+    };
+
+    let cell = x.floor();
+    let t = x - cell;
+    let a = gradient(cell) * t;
+    let b = gradient(cell + 1.0) * (t - 1.0);
+    let fade = t * t * t * (t * (t * 6.0 - 15.0) + 10.0);
+
+    // The noise reaches at most 0.5 between two gradients, so it is scaled up to 1
+    (a + (b - a) * fade) * 2.0
+}
+
+/// The part of the screen a camera renders to, in normalized coordinates
+///
+/// (0, 0) is the top left corner of the screen and (1, 1) the bottom right. Cameras without a
+/// Viewport cover the whole screen.
+///
+/// NOTE: Every view shares the one depth buffer, which is cleared once a frame, so the viewports
+/// of views should not overlap. A smaller view inside a larger one can be cut out of it with
+/// scissors instead.
+#[derive(Component, Debug, Clone, Copy, PartialEq)]
+#[storage(HashMapStorage)]
+pub struct Viewport {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+    /// The part of the screen drawn to, as x, y, width and height in the same coordinates as the
+    /// viewport, or all of the viewport if None
+    pub scissor: Option<[f32; 4]>,
+}
+
+impl Viewport {
+    pub fn new(x: f32, y: f32, width: f32, height: f32) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+            scissor: None,
+        }
+    }
+
+    /// Only draws to part of the screen, without changing the projection
+    pub fn with_scissor(mut self, x: f32, y: f32, width: f32, height: f32) -> Self {
+        self.scissor = Some([x, y, width, height]);
+        self
+    }
+
+    /// The viewport of player `index` when `count` players share the screen
+    ///
+    /// Two players are split side by side, three and four get a quarter of the screen each and so
+    /// on.
+    pub fn split(index: u32, count: u32) -> Self {
+        let columns = (count as f32).sqrt().ceil().max(1.0) as u32;
+        let rows = (count + columns - 1) / columns;
+
+        let width = 1.0 / columns as f32;
+        let height = 1.0 / rows.max(1) as f32;
+
+        Self::new(
+            (index % columns) as f32 * width,
+            (index / columns) as f32 * height,
+            width,
+            height,
+        )
+    }
+
+    /// Width over height of the viewport on a surface of the given size
+    pub fn aspect(&self, dimensions: [u32; 2]) -> f32 {
+        (self.width * dimensions[0] as f32) / (self.height * dimensions[1] as f32)
+    }
+
+    /// The viewport in pixels on a surface of the given size
+    pub fn to_pixels(&self, dimensions: [u32; 2]) -> PixelViewport {
+        let (w, h) = (dimensions[0] as f32, dimensions[1] as f32);
+
+        PixelViewport {
+            origin: [self.x * w, self.y * h],
+            dimensions: [self.width * w, self.height * h],
+            depth_range: 0.0..1.0,
+        }
+    }
+
+    /// The scissor in pixels on a surface of the given size, clamped to the surface
+    pub fn to_scissor(&self, dimensions: [u32; 2]) -> Scissor {
+        let [x, y, width, height] =
+            self.scissor
+                .unwrap_or([self.x, self.y, self.width, self.height]);
+        let (w, h) = (dimensions[0] as f32, dimensions[1] as f32);
+
+        let left = (x * w).round().max(0.0).min(w);
+        let top = (y * h).round().max(0.0).min(h);
+        let right = ((x + width) * w).round().max(left).min(w);
+        let bottom = ((y + height) * h).round().max(top).min(h);
+
+        Scissor {
+            origin: [left as i32, top as i32],
+            dimensions: [(right - left) as u32, (bottom - top) as u32],
+        }
+    }
+
+    /// The dynamic state of the pipelines drawing the view of a camera with this viewport
+    pub fn to_dynamic_state(&self, dimensions: [u32; 2]) -> DynamicState {
+        DynamicState {
+            line_width: None,
+            viewports: Some(vec![self.to_pixels(dimensions)]),
+            scissors: Some(vec![self.to_scissor(dimensions)]),
+        }
+    }
+}
+
+impl Default for Viewport {
+    fn default() -> Self {
+        Self::new(0.0, 0.0, 1.0, 1.0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{noise, AutoExposure, Camera, CameraEffects, Viewport};
+    use nalgebra::{Matrix4, Point3, Vector3};
+
+    /// Depth in normalized device coordinates of a point straight ahead
+    fn ndc_depth(camera: &Camera, distance: f32) -> f32 {
+        let p =
+            Matrix4::from(camera.projection()).transform_point(&Point3::new(0.0, 0.0, -distance));
+        p.z
+    }
+
+    #[test]
+    fn clip_planes() {
+        let mut camera = Camera::with_clip_planes(1.0, 1.0, 0.5, Some(50.0));
+        assert!((ndc_depth(&camera, 0.5) + 1.0).abs() < 1e-4);
+        assert!((ndc_depth(&camera, 50.0) - 1.0).abs() < 1e-4);
+
+        // The planes stay where they are when the window is resized
+        camera.update_aspect(2.0);
+        assert_eq!(camera.near(), 0.5);
+        assert_eq!(camera.far(), Some(50.0));
+        assert!((ndc_depth(&camera, 50.0) - 1.0).abs() < 1e-4);
+
+        // Without a far plane, everything in front of the camera is in front of it
+        camera.set_clip_planes(0.5, None);
+        assert!((ndc_depth(&camera, 0.5) + 1.0).abs() < 1e-4);
+        let far = ndc_depth(&camera, 1e6);
+        assert!(far < 1.0 && far > 0.99);
+    }
+
+    #[test]
+    fn reversed_z() {
+        let depth = |camera: &Camera, distance: f32| {
+            Matrix4::from(camera.depth_projection(true))
+                .transform_point(&Point3::new(0.0, 0.0, -distance))
+                .z
+        };
+
+        let mut camera = Camera::with_clip_planes(1.0, 1.0, 0.5, Some(50.0));
+        assert!((depth(&camera, 0.5) - 1.0).abs() < 1e-4);
+        assert!(depth(&camera, 50.0).abs() < 1e-4);
+        assert!(depth(&camera, 5.0) > depth(&camera, 10.0));
+
+        // Only the depth changes
+        let regular = camera.depth_projection(false);
+        let reversed = camera.depth_projection(true);
+        assert_eq!(regular, camera.projection());
+        assert_eq!(regular[0], reversed[0]);
+        assert_eq!(regular[1], reversed[1]);
+
+        // Without a far plane, depth approaches 0 in the distance
+        camera.set_clip_planes(0.5, None);
+        assert!((depth(&camera, 0.5) - 1.0).abs() < 1e-4);
+        let far = depth(&camera, 1e6);
+        assert!(far > 0.0 && far < 1e-4);
+    }
+
+    #[test]
+    fn orthographic() {
+        let mut camera = Camera::orthographic(2.0, 10.0, 1.0, 101.0);
+        let project = |camera: &Camera, reversed_z, point| {
+            Matrix4::from(camera.depth_projection(reversed_z)).transform_point(&point)
+        };
+
+        // The same size at any distance
+        let near = project(&camera, false, Point3::new(20.0, 10.0, -1.0));
+        let far = project(&camera, false, Point3::new(20.0, 10.0, -101.0));
+        assert!((near.x - 1.0).abs() < 1e-4 && (far.x - 1.0).abs() < 1e-4);
+        assert!((near.y + 1.0).abs() < 1e-4 && (far.y + 1.0).abs() < 1e-4);
+
+        // Reversed depth is linear from 1 at the near plane to 0 at the far plane
+        let depth = |distance| project(&camera, true, Point3::new(0.0, 0.0, -distance)).z;
+        assert!((depth(1.0) - 1.0).abs() < 1e-4);
+        assert!((depth(51.0) - 0.5).abs() < 1e-4);
+        assert!(depth(101.0).abs() < 1e-4);
+
+        // Zooming out shows more
+        camera.set_half_height(20.0);
+        let zoomed = project(&camera, false, Point3::new(20.0, 10.0, -1.0));
+        assert!((zoomed.x - 0.5).abs() < 1e-4);
+        assert_eq!(Camera::default().half_height(), None);
+    }
+
+    #[test]
+    fn auto_exposure() {
+        let mut auto = AutoExposure::default();
+
+        // Nothing to adapt to until the scene has been measured
+        assert_eq!(auto.adapt(1.0, 1.0), 1.0);
+
+        auto.luminance = Some(0.36);
+        assert_eq!(auto.target(), Some(0.5));
+
+        let exposure = auto.adapt(1.0, 0.1);
+        assert!(exposure < 1.0 && exposure > 0.5);
+        assert!((auto.adapt(exposure, 100.0) - 0.5).abs() < 1e-4);
+
+        // Very dark scenes are not exposed beyond the limit
+        auto.luminance = Some(0.0);
+        assert_eq!(auto.target(), Some(auto.max_exposure));
+    }
+
+    #[test]
+    fn camera_effects() {
+        // Calm without trauma or sway
+        let mut effects = CameraEffects::default();
+        effects.advance(0.37);
+        let view = Matrix4::new_translation(&Vector3::new(1.0, 2.0, 3.0));
+        assert!((effects.apply(&view) - view).norm() < 1e-6);
+
+        // Shakes with trauma, which wears off
+        effects.add_trauma(2.0);
+        assert_eq!(effects.trauma, 1.0);
+        assert!((effects.apply(&view) - view).norm() > 1e-4);
+        effects.advance(0.5);
+        assert!((effects.trauma - 0.5).abs() < 1e-6);
+        effects.advance(10.0);
+        assert_eq!(effects.trauma, 0.0);
+
+        // The noise is smooth, in range, and the same every time
+        for i in 0..1000 {
+            let x = i as f32 * 0.013 - 5.0;
+            assert!(noise(x, 1).abs() <= 1.0);
+            assert!((noise(x, 1) - noise(x + 0.001, 1)).abs() < 0.02);
+            assert_eq!(noise(x, 1), noise(x, 1));
+        }
+        assert_eq!(noise(3.0, 1), 0.0);
+    }
+
+    #[test]
+    fn scissors() {
+        let dimensions = [1000, 500];
+
+        // Without a scissor, the whole viewport is drawn to
+        let viewport = Viewport::split(1, 2);
+        let scissor = viewport.to_scissor(dimensions);
+        assert_eq!(scissor.origin, [500, 0]);
+        assert_eq!(scissor.dimensions, [500, 500]);
+
+        // A picture in picture view in the corner of the screen
+        let viewport = Viewport::new(0.75, 0.0, 0.25, 0.25).with_scissor(0.75, 0.0, 0.25, 0.25);
+        let state = viewport.to_dynamic_state(dimensions);
+        let pixels = &state.viewports.as_ref().unwrap()[0];
+        assert_eq!(pixels.origin, [750.0, 0.0]);
+        assert_eq!(pixels.dimensions, [250.0, 125.0]);
+        let scissor = &state.scissors.as_ref().unwrap()[0];
+        assert_eq!(scissor.origin, [750, 0]);
+        assert_eq!(scissor.dimensions, [250, 125]);
+
+        // Scissors never reach outside of the screen
+        let scissor = Viewport::default()
+            .with_scissor(-0.5, 0.5, 2.0, 1.0)
+            .to_scissor(dimensions);
+        assert_eq!(scissor.origin, [0, 250]);
+        assert_eq!(scissor.dimensions, [1000, 250]);
+    }
+}
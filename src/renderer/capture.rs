@@ -0,0 +1,293 @@
+use crate::renderer::{
+    frame::FRAMES_IN_FLIGHT,
+    memory::{BufferAllocator, MemoryUse},
+    Window,
+};
+use log::{error, info, warn};
+use std::{
+    fs,
+    io::Write,
+    mem,
+    path::PathBuf,
+    process::{Child, Command, Stdio},
+    sync::{
+        mpsc::{channel, Receiver, Sender},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use vulkano::{
+    buffer::{BufferUsage, CpuAccessibleBuffer, TypedBufferAccess},
+    command_buffer::AutoCommandBufferBuilder,
+    format::Format,
+    image::SwapchainImage,
+};
+
+/// Where recorded frames go
+#[derive(Debug, Clone)]
+pub enum CaptureOutput {
+    /// Numbered PNGs, in a new directory under this one for every recording
+    Png(PathBuf),
+    /// Raw RGBA frames piped to the standard input of a command, like an ffmpeg encoder
+    ///
+    /// `{width}` and `{height}` in the command are replaced by the size of the frames.
+    Command(String),
+}
+
+impl Default for CaptureOutput {
+    fn default() -> Self {
+        CaptureOutput::Png(PathBuf::from("captures"))
+    }
+}
+
+/// A frame copied back from the GPU
+struct CapturedFrame {
+    number: u64,
+    dimensions: [u32; 2],
+    /// RGBA, 8 bits per channel
+    pixels: Vec<u8>,
+}
+
+type CaptureBuffer = Arc<CpuAccessibleBuffer<[[u8; 4]]>>;
+
+/// Records the presented frames, for capturing footage straight from the engine
+///
+/// Each frame is copied from the swapchain image into a buffer at the end of the post pass. Once
+/// the fence of the frame has been waited on, the buffer is read and handed to a worker thread,
+/// which writes the frame out, so encoding never holds up rendering.
+///
+/// Only 8 bit swapchain formats can be captured.
+pub struct FrameCapture {
+    memory: BufferAllocator,
+    supported: bool,
+    /// Whether the swapchain stores blue first, in which case the worker swaps red and blue
+    bgra: bool,
+    output: CaptureOutput,
+    buffers: Vec<Option<CaptureBuffer>>,
+    /// Copies recorded for a frame index, with their size and frame number
+    pending: Vec<Option<(CaptureBuffer, [u32; 2], u64)>>,
+    worker: Option<(Sender<CapturedFrame>, JoinHandle<()>)>,
+    frame_number: u64,
+}
+
+impl FrameCapture {
+    /// `transfer_source` is whether the swapchain images can be copied from
+    pub fn new(
+        memory: BufferAllocator,
+        format: Format,
+        transfer_source: bool,
+        output: CaptureOutput,
+    ) -> Self {
+        let bgra = match format {
+            Format::B8G8R8A8Unorm | Format::B8G8R8A8Srgb => Some(true),
+            Format::R8G8B8A8Unorm | Format::R8G8B8A8Srgb => Some(false),
+            _ => None,
+        };
+
+        let supported = transfer_source && bgra.is_some();
+        if !supported {
+            warn!(
+                "Frames can not be captured from a {:?} swapchain that does{} allow copies",
+                format,
+                if transfer_source { "" } else { " not" }
+            );
+        }
+
+        Self {
+            memory,
+            supported,
+            bgra: bgra.unwrap_or(false),
+            output,
+            buffers: (0..FRAMES_IN_FLIGHT).map(|_| None).collect(),
+            pending: (0..FRAMES_IN_FLIGHT).map(|_| None).collect(),
+            worker: None,
+            frame_number: 0,
+        }
+    }
+
+    /// Sends the frame recorded with this index to the worker
+    ///
+    /// The fence of the frame index has to have been waited on.
+    pub fn collect(&mut self, frame_index: usize) {
+        let (buffer, dimensions, number) = match self.pending[frame_index].take() {
+            Some(pending) => pending,
+            None => return,
+        };
+
+        let pixels = {
+            let content = buffer.read().unwrap();
+            let mut pixels = Vec::with_capacity(content.len() * 4);
+            for texel in content.iter() {
+                if self.bgra {
+                    pixels.extend_from_slice(&[texel[2], texel[1], texel[0], texel[3]]);
+                } else {
+                    pixels.extend_from_slice(texel);
+                }
+            }
+            pixels
+        };
+
+        if let Some((sender, _)) = &self.worker {
+            let _ = sender.send(CapturedFrame {
+                number,
+                dimensions,
+                pixels,
+            });
+        }
+    }
+
+    /// Copies the swapchain image into a buffer at the end of the command buffer, while recording
+    pub fn record(
+        &mut self,
+        builder: AutoCommandBufferBuilder,
+        frame_index: usize,
+        image: Arc<SwapchainImage<Window>>,
+        recording: bool,
+    ) -> AutoCommandBufferBuilder {
+        if !recording || !self.supported {
+            // Whatever is still on its way is written before the worker is stopped
+            if self.worker.is_some() && self.pending.iter().all(Option::is_none) {
+                self.stop();
+            }
+            return builder;
+        }
+
+        if self.worker.is_none() {
+            self.start();
+
+            if self.worker.is_none() {
+                return builder;
+            }
+        }
+
+        let dimensions = image.dimensions();
+        let len = (dimensions[0] * dimensions[1]) as usize;
+
+        // The buffer of the frame index is reused until the window changes size
+        let buffer = match self.buffers[frame_index].clone() {
+            Some(buffer) if buffer.len() == len => buffer,
+            _ => {
+                let buffer = unsafe {
+                    self.memory
+                        .uninitialized_array(
+                            MemoryUse::Transient,
+                            len,
+                            BufferUsage::transfer_destination(),
+                        )
+                        .unwrap()
+                };
+                self.buffers[frame_index] = Some(buffer.clone());
+                buffer
+            }
+        };
+
+        self.pending[frame_index] = Some((buffer.clone(), dimensions, self.frame_number));
+        self.frame_number += 1;
+
+        builder.copy_image_to_buffer(image, buffer).unwrap()
+    }
+
+    /// Writes out every frame that has been recorded and stops the worker
+    ///
+    /// The GPU has to be done with all frames.
+    pub fn finish(&mut self) {
+        for frame_index in 0..FRAMES_IN_FLIGHT {
+            self.collect(frame_index);
+        }
+        self.stop();
+    }
+
+    fn start(&mut self) {
+        let output = match &self.output {
+            CaptureOutput::Png(dir) => {
+                let secs = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|time| time.as_secs())
+                    .unwrap_or(0);
+                let dir = dir.join(format!("recording_{}", secs));
+
+                if let Err(err) = fs::create_dir_all(&dir) {
+                    error!("Failed to create {}: {}", dir.display(), err);
+                    self.supported = false;
+                    return;
+                }
+
+                CaptureOutput::Png(dir)
+            }
+            output => output.clone(),
+        };
+
+        info!("Recording frames to {:?}", output);
+
+        let (sender, receiver) = channel();
+        let worker = thread::spawn(move || write_frames(receiver, output));
+
+        self.frame_number = 0;
+        self.worker = Some((sender, worker));
+    }
+
+    fn stop(&mut self) {
+        if let Some((sender, worker)) = self.worker.take() {
+            // Closing the channel lets the worker finish the frames it has and return
+            mem::drop(sender);
+            let _ = worker.join();
+            info!("Recording stopped after {} frames", self.frame_number);
+        }
+    }
+}
+
+/// Runs on the worker thread until the channel is closed
+fn write_frames(receiver: Receiver<CapturedFrame>, output: CaptureOutput) {
+    let mut encoder: Option<Child> = None;
+
+    for frame in receiver {
+        let [width, height] = frame.dimensions;
+
+        match &output {
+            CaptureOutput::Png(dir) => {
+                let path = dir.join(format!("{:06}.png", frame.number));
+                if let Err(err) =
+                    image::save_buffer(&path, &frame.pixels, width, height, image::RGBA(8))
+                {
+                    error!("Failed to write {}: {}", path.display(), err);
+                }
+            }
+            CaptureOutput::Command(command) => {
+                if encoder.is_none() {
+                    let command = command
+                        .replace("{width}", &width.to_string())
+                        .replace("{height}", &height.to_string());
+                    let mut args = command.split_whitespace();
+
+                    let child = args.next().and_then(|program| {
+                        Command::new(program)
+                            .args(args)
+                            .stdin(Stdio::piped())
+                            .spawn()
+                            .map_err(|err| error!("Failed to run {:?}: {}", command, err))
+                            .ok()
+                    });
+
+                    match child {
+                        Some(child) => encoder = Some(child),
+                        // Nothing to write to, the rest of the frames are dropped
+                        None => return,
+                    }
+                }
+
+                let stdin = encoder.as_mut().unwrap().stdin.as_mut().unwrap();
+                if let Err(err) = stdin.write_all(&frame.pixels) {
+                    error!("Failed to write frame to the encoder: {}", err);
+                    break;
+                }
+            }
+        }
+    }
+
+    if let Some(mut encoder) = encoder {
+        // Closing stdin tells the encoder there are no more frames
+        mem::drop(encoder.stdin.take());
+        let _ = encoder.wait();
+    }
+}
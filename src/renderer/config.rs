@@ -0,0 +1,200 @@
+use crate::renderer::capture::CaptureOutput;
+use vulkano::{format::Format, swapchain::ColorSpace};
+
+/// How the post pass encodes the final colors for the display
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputTransfer {
+    /// Colors are written linear, and encoded by an sRGB swapchain format
+    Linear = 0,
+    /// Colors are gamma encoded by the shader, for UNORM swapchain formats
+    Gamma = 1,
+    /// Linear extended sRGB (scRGB), where 1.0 is 80 nits and brighter colors go above it
+    ScRgb = 2,
+    /// The SMPTE ST 2084 perceptual quantizer with BT.2020 primaries, as used by HDR10
+    Pq = 3,
+}
+
+impl OutputTransfer {
+    /// Whether colors brighter than white reach the display
+    pub fn is_hdr(self) -> bool {
+        match self {
+            OutputTransfer::ScRgb | OutputTransfer::Pq => true,
+            OutputTransfer::Linear | OutputTransfer::Gamma => false,
+        }
+    }
+}
+
+/// A swapchain format and color space, along with how colors have to be encoded for it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SurfaceFormat {
+    pub format: Format,
+    pub color_space: ColorSpace,
+    pub transfer: OutputTransfer,
+}
+
+/// Options the renderer is created with
+#[derive(Debug, Clone)]
+pub struct RendererConfig {
+    /// Prefer an HDR swapchain if the surface supports one
+    pub hdr: bool,
+    /// How bright white in the scene is in nits, when the output is HDR
+    pub paper_white: f32,
+    /// Where frames go while recording
+    pub capture: CaptureOutput,
+    /// Most generated meshes uploaded in a frame, so a scene loading in doesn't stall a frame
+    pub meshes_per_frame: usize,
+    /// Most bytes of meshes and images uploaded in a frame, see `UploadScheduler`
+    pub upload_budget: usize,
+    /// Fewest swapchain images to use, 2 for double buffering
+    pub min_image_count: u32,
+    /// Swapchain images to use if the surface allows it. Triple buffering keeps the GPU busy at
+    /// the cost of a frame of latency
+    pub desired_image_count: u32,
+}
+
+impl Default for RendererConfig {
+    fn default() -> Self {
+        Self {
+            hdr: false,
+            paper_white: 200.0,
+            capture: CaptureOutput::default(),
+            meshes_per_frame: 16,
+            upload_budget: 16 * 1024 * 1024,
+            min_image_count: 2,
+            desired_image_count: 3,
+        }
+    }
+}
+
+/// Picks the swapchain format among those supported by the surface
+///
+/// With `hdr`, extended sRGB is preferred over HDR10. Otherwise, or if neither is supported, the
+/// first format in the sRGB color space is used.
+pub fn choose_surface_format(supported: &[(Format, ColorSpace)], hdr: bool) -> SurfaceFormat {
+    let find = |predicate: &dyn Fn(Format, ColorSpace) -> bool| {
+        supported
+            .iter()
+            .find(|(format, color_space)| predicate(*format, *color_space))
+            .cloned()
+    };
+
+    let hdr_format = if hdr {
+        find(&|format, color_space| {
+            format == Format::R16G16B16A16Sfloat && color_space == ColorSpace::ExtendedSrgbLinear
+        })
+        .map(|pair| (pair, OutputTransfer::ScRgb))
+        .or_else(|| {
+            find(&|format, color_space| {
+                (format == Format::A2B10G10R10UnormPack32
+                    || format == Format::A2R10G10B10UnormPack32)
+                    && color_space == ColorSpace::Hdr10St2084
+            })
+            .map(|pair| (pair, OutputTransfer::Pq))
+        })
+    } else {
+        None
+    };
+
+    let ((format, color_space), transfer) = hdr_format.unwrap_or_else(|| {
+        let pair = find(&|_, color_space| color_space == ColorSpace::SrgbNonLinear)
+            .unwrap_or(supported[0]);
+
+        let transfer = if is_srgb(pair.0) {
+            OutputTransfer::Linear
+        } else {
+            OutputTransfer::Gamma
+        };
+
+        (pair, transfer)
+    });
+
+    SurfaceFormat {
+        format,
+        color_space,
+        transfer,
+    }
+}
+
+/// Picks how many swapchain images to use, as configured and within what the surface supports
+///
+/// `max_supported` is None if the surface has no upper limit.
+pub fn choose_image_count(
+    config: &RendererConfig,
+    min_supported: u32,
+    max_supported: Option<u32>,
+) -> u32 {
+    let count = config
+        .desired_image_count
+        .max(config.min_image_count)
+        .max(min_supported);
+
+    match max_supported {
+        Some(max_supported) => count.min(max_supported),
+        None => count,
+    }
+}
+
+/// Does the format encode to sRGB when written to?
+fn is_srgb(format: Format) -> bool {
+    match format {
+        Format::B8G8R8A8Srgb | Format::R8G8B8A8Srgb | Format::A8B8G8R8SrgbPack32 => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{choose_image_count, choose_surface_format, OutputTransfer, RendererConfig};
+    use vulkano::{format::Format, swapchain::ColorSpace};
+
+    const SUPPORTED: [(Format, ColorSpace); 4] = [
+        (Format::B8G8R8A8Unorm, ColorSpace::SrgbNonLinear),
+        (Format::B8G8R8A8Srgb, ColorSpace::SrgbNonLinear),
+        (Format::A2B10G10R10UnormPack32, ColorSpace::Hdr10St2084),
+        (Format::R16G16B16A16Sfloat, ColorSpace::ExtendedSrgbLinear),
+    ];
+
+    #[test]
+    fn sdr() {
+        let chosen = choose_surface_format(&SUPPORTED, false);
+        assert_eq!(chosen.format, Format::B8G8R8A8Unorm);
+        assert_eq!(chosen.transfer, OutputTransfer::Gamma);
+
+        let chosen = choose_surface_format(&SUPPORTED[1..], false);
+        assert_eq!(chosen.transfer, OutputTransfer::Linear);
+    }
+
+    #[test]
+    fn hdr() {
+        let chosen = choose_surface_format(&SUPPORTED, true);
+        assert_eq!(chosen.color_space, ColorSpace::ExtendedSrgbLinear);
+        assert_eq!(chosen.transfer, OutputTransfer::ScRgb);
+        assert!(chosen.transfer.is_hdr());
+
+        let chosen = choose_surface_format(&SUPPORTED[..3], true);
+        assert_eq!(chosen.color_space, ColorSpace::Hdr10St2084);
+        assert_eq!(chosen.transfer, OutputTransfer::Pq);
+
+        // Falls back to SDR
+        let chosen = choose_surface_format(&SUPPORTED[..2], true);
+        assert_eq!(chosen.color_space, ColorSpace::SrgbNonLinear);
+        assert!(!chosen.transfer.is_hdr());
+    }
+
+    #[test]
+    fn image_count() {
+        let triple = RendererConfig::default();
+        assert_eq!(choose_image_count(&triple, 2, Some(8)), 3);
+        assert_eq!(choose_image_count(&triple, 2, None), 3);
+        assert_eq!(choose_image_count(&triple, 2, Some(2)), 2);
+        assert_eq!(choose_image_count(&triple, 4, Some(8)), 4);
+
+        // The minimum wins over a lower desired count
+        let double = RendererConfig {
+            min_image_count: 2,
+            desired_image_count: 1,
+            ..RendererConfig::default()
+        };
+        assert_eq!(choose_image_count(&double, 1, None), 2);
+    }
+}
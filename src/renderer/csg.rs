@@ -0,0 +1,449 @@
+//! Constructive solid geometry on triangle meshes
+//!
+//! Meshes are turned into BSP trees of polygons, which are then clipped against each other. This
+//! is a port of the well known csg.js algorithm by Evan Wallace.
+//!
+//! The meshes should be closed, like those made by the Shape generators. The result is not
+//! indexed in any useful way, every triangle gets its own vertices.
+
+use crate::renderer::geometry::Vertex;
+use nalgebra::Vector3;
+use std::mem;
+
+/// Tolerance used to decide if a point is on a plane
+const EPSILON: f32 = 1e-5;
+
+/// Boolean operations between two meshes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CsgOp {
+    /// Everything that is inside either mesh
+    Union,
+    /// Everything that is inside the first mesh, but not the second
+    Subtract,
+    /// Everything that is inside both meshes
+    Intersect,
+}
+
+/// Applies an operation to two indexed meshes, returning a new indexed mesh
+pub fn apply(
+    op: CsgOp,
+    (a_vertices, a_indices): (&[Vertex], &[u32]),
+    (b_vertices, b_indices): (&[Vertex], &[u32]),
+) -> (Vec<Vertex>, Vec<u32>) {
+    let mut a = Node::new(to_polygons(a_vertices, a_indices));
+    let mut b = Node::new(to_polygons(b_vertices, b_indices));
+
+    match op {
+        CsgOp::Union => {
+            a.clip_to(&b);
+            b.clip_to(&a);
+            b.invert();
+            b.clip_to(&a);
+            b.invert();
+            a.build(b.all_polygons());
+        }
+        CsgOp::Subtract => {
+            a.invert();
+            a.clip_to(&b);
+            b.clip_to(&a);
+            b.invert();
+            b.clip_to(&a);
+            b.invert();
+            a.build(b.all_polygons());
+            a.invert();
+        }
+        CsgOp::Intersect => {
+            a.invert();
+            b.clip_to(&a);
+            b.invert();
+            a.clip_to(&b);
+            b.clip_to(&a);
+            a.build(b.all_polygons());
+            a.invert();
+        }
+    }
+
+    from_polygons(a.all_polygons())
+}
+
+#[derive(Debug, Clone)]
+struct CsgVertex {
+    position: Vector3<f32>,
+    normal: Vector3<f32>,
+}
+
+impl CsgVertex {
+    fn flip(&mut self) {
+        self.normal = -self.normal;
+    }
+
+    fn interpolate(&self, other: &CsgVertex, t: f32) -> CsgVertex {
+        CsgVertex {
+            position: self.position.lerp(&other.position, t),
+            normal: self.normal.lerp(&other.normal, t).normalize(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Plane {
+    normal: Vector3<f32>,
+    w: f32,
+}
+
+// Classification of a vertex or polygon relative to a plane
+const COPLANAR: u8 = 0;
+const FRONT: u8 = 1;
+const BACK: u8 = 2;
+const SPANNING: u8 = 3;
+
+impl Plane {
+    /// None if the points are on a line
+    fn from_points(a: &Vector3<f32>, b: &Vector3<f32>, c: &Vector3<f32>) -> Option<Self> {
+        let normal = (b - a).cross(&(c - a));
+        if normal.norm() < EPSILON * EPSILON {
+            return None;
+        }
+
+        let normal = normal.normalize();
+        Some(Self {
+            w: normal.dot(a),
+            normal,
+        })
+    }
+
+    fn flip(&mut self) {
+        self.normal = -self.normal;
+        self.w = -self.w;
+    }
+
+    fn classify(&self, point: &Vector3<f32>) -> u8 {
+        let t = self.normal.dot(point) - self.w;
+
+        if t < -EPSILON {
+            BACK
+        } else if t > EPSILON {
+            FRONT
+        } else {
+            COPLANAR
+        }
+    }
+
+    /// Puts the polygon, or the parts of it on either side of the plane, in the right list
+    fn split_polygon(
+        &self,
+        polygon: Polygon,
+        coplanar_front: &mut Vec<Polygon>,
+        coplanar_back: &mut Vec<Polygon>,
+        front: &mut Vec<Polygon>,
+        back: &mut Vec<Polygon>,
+    ) {
+        let types = polygon
+            .vertices
+            .iter()
+            .map(|vertex| self.classify(&vertex.position))
+            .collect::<Vec<_>>();
+        let polygon_type = types.iter().fold(COPLANAR, |acc, t| acc | t);
+
+        match polygon_type {
+            COPLANAR => {
+                if self.normal.dot(&polygon.plane.normal) > 0.0 {
+                    coplanar_front.push(polygon);
+                } else {
+                    coplanar_back.push(polygon);
+                }
+            }
+            FRONT => front.push(polygon),
+            BACK => back.push(polygon),
+            _ => {
+                let mut f = Vec::new();
+                let mut b = Vec::new();
+
+                let count = polygon.vertices.len();
+                for i in 0..count {
+                    let j = (i + 1) % count;
+                    let (ti, tj) = (types[i], types[j]);
+                    let (vi, vj) = (&polygon.vertices[i], &polygon.vertices[j]);
+
+                    if ti != BACK {
+                        f.push(vi.clone());
+                    }
+                    if ti != FRONT {
+                        b.push(vi.clone());
+                    }
+                    if ti | tj == SPANNING {
+                        let t = (self.w - self.normal.dot(&vi.position))
+                            / self.normal.dot(&(vj.position - vi.position));
+                        let v = vi.interpolate(vj, t);
+
+                        f.push(v.clone());
+                        b.push(v);
+                    }
+                }
+
+                front.extend(Polygon::new(f));
+                back.extend(Polygon::new(b));
+            }
+        }
+    }
+}
+
+/// Convex polygon
+#[derive(Debug, Clone)]
+struct Polygon {
+    vertices: Vec<CsgVertex>,
+    plane: Plane,
+}
+
+impl Polygon {
+    /// None if the polygon has no area
+    fn new(vertices: Vec<CsgVertex>) -> Option<Self> {
+        if vertices.len() < 3 {
+            return None;
+        }
+
+        let plane = Plane::from_points(
+            &vertices[0].position,
+            &vertices[1].position,
+            &vertices[2].position,
+        )?;
+
+        Some(Self { vertices, plane })
+    }
+
+    fn flip(&mut self) {
+        self.vertices.reverse();
+        self.vertices.iter_mut().for_each(CsgVertex::flip);
+        self.plane.flip();
+    }
+}
+
+/// Node in a BSP tree, holding the polygons on its plane
+#[derive(Debug, Default)]
+struct Node {
+    plane: Option<Plane>,
+    front: Option<Box<Node>>,
+    back: Option<Box<Node>>,
+    polygons: Vec<Polygon>,
+}
+
+impl Node {
+    fn new(polygons: Vec<Polygon>) -> Self {
+        let mut node = Self::default();
+        node.build(polygons);
+        node
+    }
+
+    /// Swaps solid and empty space
+    fn invert(&mut self) {
+        self.polygons.iter_mut().for_each(Polygon::flip);
+
+        if let Some(plane) = self.plane.as_mut() {
+            plane.flip();
+        }
+        if let Some(front) = self.front.as_mut() {
+            front.invert();
+        }
+        if let Some(back) = self.back.as_mut() {
+            back.invert();
+        }
+
+        mem::swap(&mut self.front, &mut self.back);
+    }
+
+    /// Removes the parts of the polygons that are inside this tree
+    fn clip_polygons(&self, polygons: Vec<Polygon>) -> Vec<Polygon> {
+        let plane = match self.plane.as_ref() {
+            Some(plane) => plane,
+            None => return polygons,
+        };
+
+        let mut coplanar_front = Vec::new();
+        let mut coplanar_back = Vec::new();
+        let mut front = Vec::new();
+        let mut back = Vec::new();
+
+        for polygon in polygons {
+            plane.split_polygon(
+                polygon,
+                &mut coplanar_front,
+                &mut coplanar_back,
+                &mut front,
+                &mut back,
+            );
+        }
+
+        front.append(&mut coplanar_front);
+        back.append(&mut coplanar_back);
+
+        let mut front = match self.front.as_ref() {
+            Some(node) => node.clip_polygons(front),
+            None => front,
+        };
+        let back = match self.back.as_ref() {
+            Some(node) => node.clip_polygons(back),
+            None => Vec::new(),
+        };
+
+        front.extend(back);
+        front
+    }
+
+    /// Removes the parts of this tree that are inside the other one
+    fn clip_to(&mut self, other: &Node) {
+        self.polygons = other.clip_polygons(mem::replace(&mut self.polygons, Vec::new()));
+
+        if let Some(front) = self.front.as_mut() {
+            front.clip_to(other);
+        }
+        if let Some(back) = self.back.as_mut() {
+            back.clip_to(other);
+        }
+    }
+
+    fn all_polygons(&self) -> Vec<Polygon> {
+        let mut polygons = self.polygons.clone();
+
+        if let Some(front) = self.front.as_ref() {
+            polygons.extend(front.all_polygons());
+        }
+        if let Some(back) = self.back.as_ref() {
+            polygons.extend(back.all_polygons());
+        }
+
+        polygons
+    }
+
+    /// Adds polygons to the tree, splitting them where needed
+    fn build(&mut self, polygons: Vec<Polygon>) {
+        if polygons.is_empty() {
+            return;
+        }
+
+        let plane = self
+            .plane
+            .get_or_insert_with(|| polygons[0].plane.clone())
+            .clone();
+
+        let mut coplanar_front = Vec::new();
+        let mut coplanar_back = Vec::new();
+        let mut front = Vec::new();
+        let mut back = Vec::new();
+
+        for polygon in polygons {
+            plane.split_polygon(
+                polygon,
+                &mut coplanar_front,
+                &mut coplanar_back,
+                &mut front,
+                &mut back,
+            );
+        }
+
+        self.polygons.append(&mut coplanar_front);
+        self.polygons.append(&mut coplanar_back);
+
+        if !front.is_empty() {
+            self.front.get_or_insert_with(Default::default).build(front);
+        }
+        if !back.is_empty() {
+            self.back.get_or_insert_with(Default::default).build(back);
+        }
+    }
+}
+
+/// Triangles without any area are dropped
+fn to_polygons(vertices: &[Vertex], indices: &[u32]) -> Vec<Polygon> {
+    indices
+        .chunks(3)
+        .filter_map(|triangle| {
+            let vertices = triangle
+                .iter()
+                .map(|i| {
+                    let vertex = &vertices[*i as usize];
+
+                    CsgVertex {
+                        position: Vector3::from(vertex.position),
+                        normal: Vector3::from(vertex.normal),
+                    }
+                })
+                .collect();
+
+            Polygon::new(vertices)
+        })
+        .collect()
+}
+
+/// Polygons are convex, so they are split into triangle fans
+fn from_polygons(polygons: Vec<Polygon>) -> (Vec<Vertex>, Vec<u32>) {
+    let mut vertices = Vec::new();
+    let mut indices = Vec::new();
+
+    for polygon in polygons {
+        let first = vertices.len() as u32;
+
+        for i in 2..polygon.vertices.len() as u32 {
+            indices.extend_from_slice(&[first, first + i - 1, first + i]);
+        }
+
+        vertices.extend(polygon.vertices.into_iter().map(|vertex| Vertex {
+            position: vertex.position.into(),
+            normal: vertex.normal.into(),
+        }));
+    }
+
+    (vertices, indices)
+}
+
+#[cfg(test)]
+mod test {
+    use super::CsgOp;
+    use crate::{
+        components::Transform,
+        renderer::geometry::{MeshBuilder, Shape},
+    };
+    use nalgebra::{Point3, Vector3};
+
+    fn assert_close(a: &Point3<f32>, b: Point3<f32>) {
+        assert!((a - b).norm() < 1e-5, "{} != {}", a, b);
+    }
+
+    fn cube_at(x: f32) -> MeshBuilder {
+        MeshBuilder::new()
+            .with_shape(Shape::Cube)
+            .transformed(&Transform::from(Vector3::new(x, 0.0, 0.0)))
+    }
+
+    #[test]
+    fn union() {
+        let mesh = cube_at(0.0)
+            .with_csg(CsgOp::Union, cube_at(0.5))
+            .generated();
+        let aabb = mesh.bounds().aabb;
+
+        assert_close(aabb.mins(), Point3::new(-0.5, -0.5, -0.5));
+        assert_close(aabb.maxs(), Point3::new(1.0, 0.5, 0.5));
+    }
+
+    #[test]
+    fn subtract() {
+        let mesh = cube_at(0.0)
+            .with_csg(CsgOp::Subtract, cube_at(0.5))
+            .generated();
+        let aabb = mesh.bounds().aabb;
+
+        assert_close(aabb.mins(), Point3::new(-0.5, -0.5, -0.5));
+        assert_close(aabb.maxs(), Point3::new(0.0, 0.5, 0.5));
+    }
+
+    #[test]
+    fn intersect() {
+        let mesh = cube_at(0.0)
+            .with_csg(CsgOp::Intersect, cube_at(0.5))
+            .generated();
+        let aabb = mesh.bounds().aabb;
+
+        assert_close(aabb.mins(), Point3::new(0.0, -0.5, -0.5));
+        assert_close(aabb.maxs(), Point3::new(0.5, 0.5, 0.5));
+    }
+}
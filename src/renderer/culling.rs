@@ -0,0 +1,354 @@
+use crate::renderer::{
+    frame::FRAMES_IN_FLIGHT,
+    hiz::{HiZPyramid, HI_Z_MAX_LEVELS},
+    memory::{BufferAllocator, MemoryUse},
+    pools::CommandPools,
+    shaders::{CullObject, CullPushConstants, CullView, ShaderSet},
+};
+use nalgebra::{Matrix4, Point3, Vector4};
+use ncollide3d::bounding_volume::BoundingSphere;
+use std::sync::Arc;
+use vulkano::{
+    buffer::{cpu_pool::CpuBufferPool, BufferUsage, DeviceLocalBuffer},
+    command_buffer::{AutoCommandBuffer, DrawIndexedIndirectCommand},
+    descriptor::descriptor_set::FixedSizeDescriptorSetsPool,
+    device::{Device, Queue},
+    pipeline::{ComputePipeline, ComputePipelineAbstract},
+};
+
+/// The number of invocations in a single work group of the culling shader
+const WORK_GROUP_SIZE: u32 = 64;
+
+/// View frustum as six normalized planes (left, right, bottom, top, near, far)
+///
+/// A point p is inside a plane if dot(plane.xyz, p) + plane.w >= 0
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frustum {
+    pub planes: [Vector4<f32>; 6],
+}
+
+impl Frustum {
+    /// Extracts the frustum planes from a combined projection * view matrix
+    pub fn from_matrix(m: &Matrix4<f32>) -> Self {
+        let row = |i: usize| Vector4::new(m[(i, 0)], m[(i, 1)], m[(i, 2)], m[(i, 3)]);
+        let (r0, r1, r2, r3) = (row(0), row(1), row(2), row(3));
+
+        // The far plane of an infinite projection has no normal, and nothing is outside of it
+        let normalize = |p: Vector4<f32>| {
+            let length = p.xyz().norm();
+            if length > std::f32::EPSILON {
+                p / length
+            } else {
+                Vector4::new(0.0, 0.0, 0.0, 1.0)
+            }
+        };
+
+        Self {
+            planes: [
+                normalize(r3 + r0),
+                normalize(r3 - r0),
+                normalize(r3 + r1),
+                normalize(r3 - r1),
+                normalize(r3 + r2),
+                normalize(r3 - r2),
+            ],
+        }
+    }
+
+    /// Is any part of the sphere inside the frustum?
+    pub fn intersects_sphere(&self, sphere: &BoundingSphere<f32>) -> bool {
+        let center = sphere.center().coords;
+
+        self.planes
+            .iter()
+            .all(|plane| plane.xyz().dot(&center) + plane.w >= -sphere.radius())
+    }
+
+    fn to_push_constants(
+        &self,
+        object_count: u32,
+        command_offset: u32,
+        view: u32,
+        hi_z_levels: u32,
+        reversed_z: bool,
+    ) -> CullPushConstants {
+        let mut planes = [[0.0; 4]; 6];
+        for (dst, src) in planes.iter_mut().zip(self.planes.iter()) {
+            *dst = (*src).into();
+        }
+
+        CullPushConstants {
+            planes,
+            object_count,
+            command_offset,
+            view,
+            hi_z_levels,
+            reversed_z: reversed_z as u32,
+        }
+    }
+}
+
+impl CullObject {
+    pub fn bounding_sphere(&self) -> BoundingSphere<f32> {
+        let [x, y, z, radius] = self.sphere;
+        BoundingSphere::new(Point3::new(x, y, z), radius)
+    }
+}
+
+/// The draw commands written by the culling shader for one frame in flight
+struct IndirectBuffer {
+    buffer: Arc<DeviceLocalBuffer<[DrawIndexedIndirectCommand]>>,
+    capacity: usize,
+}
+
+/// GPU frustum culling, and occlusion culling against the Hi-Z pyramid
+///
+/// Object bounds are uploaded to a storage buffer each frame, and a compute shader writes one
+/// indexed indirect draw command per object. Culled objects get an instance count of zero, so
+/// the main pass can draw every object with draw_indexed_indirect without knowing the result.
+///
+/// With the pyramid, objects are also culled if they were hidden behind the depth of the last
+/// frame the pyramid was built in, as seen from the views of that frame. Objects coming out from
+/// behind something are drawn a frame late.
+///
+/// Every frame in flight has its own indirect buffer, so culling the next frame never writes to
+/// the commands the GPU is still drawing from.
+///
+/// With more than one view, like in split-screen, the objects are culled once per view. The
+/// commands of each view follow those of the previous one.
+pub struct CullingPass {
+    memory: BufferAllocator,
+    pools: CommandPools,
+    queue: Arc<Queue>,
+    graphics_queue: Arc<Queue>,
+    pipeline: Arc<dyn ComputePipelineAbstract + Send + Sync>,
+    descriptor_set_pool:
+        FixedSizeDescriptorSetsPool<Arc<dyn ComputePipelineAbstract + Send + Sync>>,
+    object_pool: CpuBufferPool<CullObject>,
+    view_pool: CpuBufferPool<CullView>,
+    indirect_buffers: Vec<IndirectBuffer>,
+}
+
+impl CullingPass {
+    /// Creates the culling pass, running on `queue` and drawing on `graphics_queue`
+    pub fn new(
+        device: Arc<Device>,
+        memory: BufferAllocator,
+        pools: CommandPools,
+        queue: Arc<Queue>,
+        graphics_queue: Arc<Queue>,
+        shaders: &ShaderSet,
+    ) -> Self {
+        let pipeline = Arc::new(
+            ComputePipeline::new(device.clone(), &shaders.cull.main_entry_point(), &())
+                .expect("Failed to create culling pipeline"),
+        ) as Arc<dyn ComputePipelineAbstract + Send + Sync>;
+
+        let descriptor_set_pool = FixedSizeDescriptorSetsPool::new(pipeline.clone(), 0);
+
+        let storage = BufferUsage {
+            storage_buffer: true,
+            ..BufferUsage::none()
+        };
+        let object_pool = CpuBufferPool::new(device.clone(), storage);
+        let view_pool = CpuBufferPool::new(device, storage);
+
+        let indirect_buffers = (0..FRAMES_IN_FLIGHT)
+            .map(|_| {
+                let capacity = 64;
+                let buffer = new_indirect_buffer(&memory, &queue, &graphics_queue, capacity);
+
+                IndirectBuffer { buffer, capacity }
+            })
+            .collect();
+
+        Self {
+            memory,
+            pools,
+            queue,
+            graphics_queue,
+            pipeline,
+            descriptor_set_pool,
+            object_pool,
+            view_pool,
+            indirect_buffers,
+        }
+    }
+
+    /// The queue the culling command buffer has to be executed on
+    pub fn queue(&self) -> Arc<Queue> {
+        self.queue.clone()
+    }
+
+    /// The buffer the draw commands of a frame are written to, one per object and view in the
+    /// order they were given
+    pub fn indirect_buffer(
+        &self,
+        frame_index: usize,
+    ) -> Arc<DeviceLocalBuffer<[DrawIndexedIndirectCommand]>> {
+        self.indirect_buffers[frame_index].buffer.clone()
+    }
+
+    /// Builds the command buffer that culls `objects` against each of the `frustums`
+    ///
+    /// With `occlusion`, objects are culled against `hi_z` as well, in the views it was built
+    /// with. Views it has not seen, like the minimap, are only frustum culled. The indirect buffer
+    /// grows if needed, so it should be fetched after calling this.
+    pub fn build_command_buffer(
+        &mut self,
+        frame_index: usize,
+        frustums: &[Frustum],
+        objects: Vec<CullObject>,
+        hi_z: &HiZPyramid,
+        occlusion: bool,
+    ) -> AutoCommandBuffer {
+        let object_count = objects.len();
+        let command_count = object_count * frustums.len();
+
+        let indirect_buffer = &mut self.indirect_buffers[frame_index];
+        if command_count > indirect_buffer.capacity {
+            indirect_buffer.capacity = command_count.next_power_of_two();
+            indirect_buffer.buffer = new_indirect_buffer(
+                &self.memory,
+                &self.queue,
+                &self.graphics_queue,
+                indirect_buffer.capacity,
+            );
+        }
+        let indirect_buffer = indirect_buffer.buffer.clone();
+
+        let objects = self.object_pool.chunk(objects).unwrap();
+
+        let hi_z_views = if occlusion { hi_z.views() } else { &[] };
+        let mut views: Vec<_> = hi_z_views
+            .iter()
+            .map(|view| CullView {
+                view_projection: view.view_projection.into(),
+                viewport: view.viewport,
+            })
+            .collect();
+        // The buffer can't be empty, even when nothing reads it
+        if views.is_empty() {
+            views.push(CullView {
+                view_projection: Matrix4::identity().into(),
+                viewport: [0.0; 4],
+            });
+        }
+        let views = self.view_pool.chunk(views).unwrap();
+
+        let mut levels = self
+            .descriptor_set_pool
+            .next()
+            .add_buffer(objects)
+            .unwrap()
+            .add_buffer(indirect_buffer)
+            .unwrap()
+            .add_buffer(views)
+            .unwrap()
+            .enter_array()
+            .unwrap();
+        for level in hi_z.bound_levels() {
+            levels = levels.add_sampled_image(level, hi_z.sampler()).unwrap();
+        }
+        let descriptor_set = Arc::new(levels.leave_array().unwrap().build().unwrap());
+
+        let hi_z_levels = hi_z.levels().len().min(HI_Z_MAX_LEVELS) as u32;
+
+        let work_groups = (object_count as u32 + WORK_GROUP_SIZE - 1) / WORK_GROUP_SIZE;
+
+        let builder = self.pools.primary(&self.queue);
+
+        frustums
+            .iter()
+            .enumerate()
+            .fold(builder, |builder, (view, frustum)| {
+                let command_offset = (view * object_count) as u32;
+                let levels = if view < hi_z_views.len() {
+                    hi_z_levels
+                } else {
+                    0
+                };
+                let pc = frustum.to_push_constants(
+                    object_count as u32,
+                    command_offset,
+                    view as u32,
+                    levels,
+                    hi_z.reversed_z(),
+                );
+
+                builder
+                    .dispatch(
+                        [work_groups, 1, 1],
+                        self.pipeline.clone(),
+                        descriptor_set.clone(),
+                        pc,
+                    )
+                    .unwrap()
+            })
+            .build()
+            .unwrap()
+    }
+}
+
+/// Creates the indirect buffer shared between the culling queue and the graphics queue
+fn new_indirect_buffer(
+    memory: &BufferAllocator,
+    queue: &Arc<Queue>,
+    graphics_queue: &Arc<Queue>,
+    len: usize,
+) -> Arc<DeviceLocalBuffer<[DrawIndexedIndirectCommand]>> {
+    let usage = BufferUsage {
+        storage_buffer: true,
+        indirect_buffer: true,
+        ..BufferUsage::none()
+    };
+
+    let mut families = vec![queue.family()];
+    if graphics_queue.family().id() != queue.family().id() {
+        families.push(graphics_queue.family());
+    }
+
+    memory
+        .device_local_array(MemoryUse::Scene, len, usage, families)
+        .expect("Failed to create indirect draw buffer")
+}
+
+#[cfg(test)]
+mod test {
+    use super::Frustum;
+    use crate::renderer::camera::Camera;
+    use nalgebra::{Matrix4, Perspective3, Point3};
+    use ncollide3d::bounding_volume::BoundingSphere;
+
+    #[test]
+    fn sphere_culling() {
+        let proj = Perspective3::new(1.0, std::f32::consts::FRAC_PI_2, 0.1, 100.0);
+        let frustum = Frustum::from_matrix(&proj.into_inner());
+
+        // In front of the camera
+        let inside = BoundingSphere::new(Point3::new(0.0, 0.0, -10.0), 1.0);
+        // Behind the camera
+        let behind = BoundingSphere::new(Point3::new(0.0, 0.0, 10.0), 1.0);
+        // Outside the far plane
+        let far = BoundingSphere::new(Point3::new(0.0, 0.0, -200.0), 1.0);
+        // Center is outside the left plane, but the radius reaches in
+        let touching = BoundingSphere::new(Point3::new(-11.0, 0.0, -10.0), 2.0);
+
+        assert!(frustum.intersects_sphere(&inside));
+        assert!(!frustum.intersects_sphere(&behind));
+        assert!(!frustum.intersects_sphere(&far));
+        assert!(frustum.intersects_sphere(&touching));
+    }
+
+    #[test]
+    fn infinite_far_plane() {
+        let camera = Camera::with_clip_planes(1.0, std::f32::consts::FRAC_PI_2, 0.1, None);
+        let frustum = Frustum::from_matrix(&Matrix4::from(camera.projection()));
+
+        let distant = BoundingSphere::new(Point3::new(0.0, 0.0, -1e6), 1.0);
+        let behind = BoundingSphere::new(Point3::new(0.0, 0.0, 10.0), 1.0);
+
+        assert!(frustum.intersects_sphere(&distant));
+        assert!(!frustum.intersects_sphere(&behind));
+    }
+}
@@ -0,0 +1,127 @@
+use crate::renderer::{
+    frame::FRAMES_IN_FLIGHT,
+    memory::{BufferAllocator, MemoryUse},
+    shaders::{LuminancePushConstants, ShaderSet},
+};
+use specs::Entity;
+use std::sync::Arc;
+use vulkano::{
+    buffer::{BufferUsage, CpuAccessibleBuffer},
+    command_buffer::AutoCommandBufferBuilder,
+    descriptor::descriptor_set::FixedSizeDescriptorSetsPool,
+    device::Device,
+    image::AttachmentImage,
+    pipeline::{viewport::Viewport, ComputePipeline, ComputePipelineAbstract},
+    sampler::Sampler,
+};
+
+type LuminanceBuffer = Arc<CpuAccessibleBuffer<[f32]>>;
+
+/// Measures the average luminance of the views of cameras with AutoExposure
+///
+/// A single work group per view samples a grid of pixels of the scene image and reduces them to
+/// their log average, which is written to a buffer the CPU reads back once the fence of the frame
+/// has been waited on. The result is a couple of frames old by then, which auto exposure smooths
+/// over anyway.
+pub struct LuminancePass {
+    memory: BufferAllocator,
+    pipeline: Arc<dyn ComputePipelineAbstract + Send + Sync>,
+    descriptor_set_pool:
+        FixedSizeDescriptorSetsPool<Arc<dyn ComputePipelineAbstract + Send + Sync>>,
+    sampler: Arc<Sampler>,
+    /// The buffer written for a frame index, with the camera of every value in it
+    pending: Vec<Option<(LuminanceBuffer, Vec<Entity>)>>,
+}
+
+impl LuminancePass {
+    pub fn new(device: Arc<Device>, memory: BufferAllocator, shaders: &ShaderSet) -> Self {
+        let pipeline = Arc::new(
+            ComputePipeline::new(device.clone(), &shaders.luminance.main_entry_point(), &())
+                .expect("Failed to create luminance pipeline"),
+        ) as Arc<dyn ComputePipelineAbstract + Send + Sync>;
+
+        let descriptor_set_pool = FixedSizeDescriptorSetsPool::new(pipeline.clone(), 0);
+
+        // Pixels are fetched directly, the sampler is only needed for the binding
+        let sampler = Sampler::simple_repeat_linear_no_mipmap(device);
+
+        Self {
+            memory,
+            pipeline,
+            descriptor_set_pool,
+            sampler,
+            pending: (0..FRAMES_IN_FLIGHT).map(|_| None).collect(),
+        }
+    }
+
+    /// The luminances measured in the frame with this index, by camera
+    ///
+    /// The fence of the frame index has to have been waited on.
+    pub fn collect(&mut self, frame_index: usize) -> Vec<(Entity, f32)> {
+        let (buffer, cameras) = match self.pending[frame_index].take() {
+            Some(pending) => pending,
+            None => return Vec::new(),
+        };
+
+        let averages = buffer.read().unwrap();
+        cameras.into_iter().zip(averages.iter().cloned()).collect()
+    }
+
+    /// Records measuring the scene in the viewport of each camera
+    ///
+    /// Has to be recorded after the scene's render pass has been executed.
+    pub fn record(
+        &mut self,
+        builder: AutoCommandBufferBuilder,
+        frame_index: usize,
+        scene: Arc<AttachmentImage>,
+        views: &[(Entity, Viewport)],
+    ) -> AutoCommandBufferBuilder {
+        if views.is_empty() {
+            return builder;
+        }
+
+        let buffer = self
+            .memory
+            .from_iter(
+                MemoryUse::Transient,
+                BufferUsage {
+                    storage_buffer: true,
+                    ..BufferUsage::none()
+                },
+                views.iter().map(|_| 0.0f32),
+            )
+            .unwrap();
+
+        let descriptor_set = Arc::new(
+            self.descriptor_set_pool
+                .next()
+                .add_sampled_image(scene, self.sampler.clone())
+                .unwrap()
+                .add_buffer(buffer.clone())
+                .unwrap()
+                .build()
+                .unwrap(),
+        );
+
+        let builder = views
+            .iter()
+            .enumerate()
+            .fold(builder, |builder, (index, (_, viewport))| {
+                let pc = LuminancePushConstants {
+                    origin: [viewport.origin[0] as i32, viewport.origin[1] as i32],
+                    size: [viewport.dimensions[0] as i32, viewport.dimensions[1] as i32],
+                    index: index as u32,
+                };
+
+                builder
+                    .dispatch([1, 1, 1], self.pipeline.clone(), descriptor_set.clone(), pc)
+                    .unwrap()
+            });
+
+        let cameras = views.iter().map(|(camera, _)| *camera).collect();
+        self.pending[frame_index] = Some((buffer, cameras));
+
+        builder
+    }
+}
@@ -0,0 +1,418 @@
+use crate::{
+    components::{render_matrix, GlobalTransform, PreviousGlobalTransform},
+    renderer::{
+        geometry::MeshComponent,
+        memory::{BufferAllocator, MemoryUse},
+        ring::{FrameRing, RingSlot},
+        shaders::{Lights, PointLight, VertexInput},
+    },
+};
+use log::error;
+use specs::prelude::*;
+use std::{mem, sync::Arc};
+use vulkano::{
+    buffer::{BufferUsage, CpuAccessibleBuffer},
+    descriptor::{descriptor_set::FixedSizeDescriptorSetsPool, DescriptorSet},
+    device::Device,
+    pipeline::GraphicsPipelineAbstract,
+    sync::{self, FenceSignalFuture, FlushError, GpuFuture},
+};
+
+/// The number of frames the CPU can record ahead of the GPU
+pub const FRAMES_IN_FLIGHT: usize = 2;
+
+pub type FrameFence = Arc<FenceSignalFuture<Box<GpuFuture + Send + Sync>>>;
+
+pub type DescriptorSetsPool =
+    FixedSizeDescriptorSetsPool<Arc<GraphicsPipelineAbstract + Send + Sync>>;
+
+/// Descriptor resources owned by a single frame in flight
+///
+/// Nothing in here is touched while the GPU might still be reading it for an older frame. Changes
+/// are recorded as stale and applied the next time the frame comes around.
+struct FrameDescriptors {
+    lights_buffer: Arc<CpuAccessibleBuffer<Lights>>,
+    point_lights_buffer: Arc<CpuAccessibleBuffer<[PointLight]>>,
+    shared_descriptor_set: Arc<DescriptorSet + Send + Sync>,
+    shared_descriptor_set_pool: DescriptorSetsPool,
+    // Meshes whose uniforms for this frame are out of date
+    stale_meshes: BitSet,
+    lights_stale: bool,
+    point_lights_stale: bool,
+}
+
+/// Frame indexed descriptor sets and the buffers they reference
+///
+/// Set 0 holds the per mesh uniforms and set 1 the lights shared by all meshes. Every frame in
+/// flight has its own copy of both, so updating lights or uniforms never writes to a buffer the
+/// GPU is reading. The uniforms of all meshes share a frame ring, and are written through its
+/// mapping instead of with transfer commands.
+pub struct FrameDescriptorSets {
+    memory: BufferAllocator,
+    frames: Vec<FrameDescriptors>,
+    mesh_uniforms: FrameRing<VertexInput>,
+    mesh_descriptor_set_pools: Vec<DescriptorSetsPool>,
+    current: usize,
+    // The latest light data, uploaded to each frame when it becomes current
+    lights: Lights,
+    point_lights: Vec<PointLight>,
+}
+
+impl FrameDescriptorSets {
+    pub fn new(
+        memory: BufferAllocator,
+        pipeline: Arc<GraphicsPipelineAbstract + Send + Sync>,
+        lights: Lights,
+    ) -> Self {
+        // The storage buffer can not be empty, so we start with a light that does nothing
+        let point_lights = vec![PointLight::none()];
+
+        let frames = (0..FRAMES_IN_FLIGHT)
+            .map(|_| {
+                let lights_buffer = memory
+                    .from_data(MemoryUse::Scene, BufferUsage::uniform_buffer(), lights)
+                    .unwrap();
+
+                let point_lights_buffer = new_point_lights_buffer(&memory, &point_lights);
+
+                let mut shared_descriptor_set_pool =
+                    FixedSizeDescriptorSetsPool::new(pipeline.clone(), 1);
+
+                let shared_descriptor_set = Arc::new(
+                    shared_descriptor_set_pool
+                        .next()
+                        .add_buffer(lights_buffer.clone())
+                        .unwrap()
+                        .add_buffer(point_lights_buffer.clone())
+                        .unwrap()
+                        .build()
+                        .unwrap(),
+                );
+
+                FrameDescriptors {
+                    lights_buffer,
+                    point_lights_buffer,
+                    shared_descriptor_set,
+                    shared_descriptor_set_pool,
+                    stale_meshes: BitSet::new(),
+                    lights_stale: false,
+                    point_lights_stale: false,
+                }
+            })
+            .collect();
+
+        let mesh_uniforms = FrameRing::new(memory.clone(), BufferUsage::uniform_buffer(), 64);
+
+        let mesh_descriptor_set_pools = (0..FRAMES_IN_FLIGHT)
+            .map(|_| FixedSizeDescriptorSetsPool::new(pipeline.clone(), 0))
+            .collect();
+
+        Self {
+            memory,
+            frames,
+            mesh_uniforms,
+            mesh_descriptor_set_pools,
+            current: 0,
+            lights,
+            point_lights,
+        }
+    }
+
+    /// Index of the frame currently being recorded
+    pub fn index(&self) -> usize {
+        self.current
+    }
+
+    /// Moves on to the next frame in flight
+    pub fn next_frame(&mut self) {
+        self.current = (self.current + 1) % FRAMES_IN_FLIGHT;
+    }
+
+    /// Makes room for the uniforms of `additional` more meshes
+    ///
+    /// If the ring has to grow, the descriptor sets of the existing meshes are rebuilt, and their
+    /// uniforms are written again as each frame comes around.
+    pub fn reserve_meshes(
+        &mut self,
+        additional: usize,
+        meshes: &mut WriteStorage<'_, MeshComponent>,
+    ) {
+        if !self.mesh_uniforms.reserve(additional) {
+            return;
+        }
+
+        for mesh in (&mut *meshes).join() {
+            mesh.descriptor_sets = self.mesh_descriptor_sets(&mesh.uniform_slot);
+        }
+
+        for frame in self.frames.iter_mut() {
+            frame.stale_meshes |= meshes.mask();
+        }
+    }
+
+    /// A uniform slot for a new mesh, with a descriptor set binding it for every frame in flight
+    ///
+    /// The slot has to have been reserved. Its uniforms are written as each frame comes around.
+    pub fn new_mesh_uniforms(
+        &mut self,
+        entity: Entity,
+    ) -> (RingSlot, Vec<Arc<DescriptorSet + Send + Sync>>) {
+        let slot = self.mesh_uniforms.allocate();
+        let descriptor_sets = self.mesh_descriptor_sets(&slot);
+
+        for frame in self.frames.iter_mut() {
+            frame.stale_meshes.add(entity.id());
+        }
+
+        (slot, descriptor_sets)
+    }
+
+    fn mesh_descriptor_sets(&mut self, slot: &RingSlot) -> Vec<Arc<DescriptorSet + Send + Sync>> {
+        let mesh_uniforms = &self.mesh_uniforms;
+
+        self.mesh_descriptor_set_pools
+            .iter_mut()
+            .enumerate()
+            .map(|(frame_index, pool)| {
+                Arc::new(
+                    pool.next()
+                        .add_buffer(mesh_uniforms.slice(frame_index, slot))
+                        .unwrap()
+                        .build()
+                        .unwrap(),
+                ) as Arc<DescriptorSet + Send + Sync>
+            })
+            .collect()
+    }
+
+    /// The lights descriptor set of the current frame
+    pub fn shared_descriptor_set(&self) -> Arc<DescriptorSet + Send + Sync> {
+        self.frames[self.current].shared_descriptor_set.clone()
+    }
+
+    /// Marks the uniforms of these meshes as out of date in every frame
+    pub fn mark_meshes_stale(&mut self, dirty: &BitSet) {
+        for frame in self.frames.iter_mut() {
+            frame.stale_meshes |= dirty;
+        }
+    }
+
+    pub fn set_lights(&mut self, lights: Lights) {
+        self.lights = lights;
+        for frame in self.frames.iter_mut() {
+            frame.lights_stale = true;
+        }
+    }
+
+    pub fn set_point_lights(&mut self, mut point_lights: Vec<PointLight>) {
+        if point_lights.is_empty() {
+            point_lights.push(PointLight::none());
+        }
+
+        self.point_lights = point_lights;
+        for frame in self.frames.iter_mut() {
+            frame.point_lights_stale = true;
+        }
+    }
+
+    /// Brings the resources of the current frame up to date
+    ///
+    /// The fence of the frame has to have been waited on. Meshes that moved in the last step are
+    /// interpolated by `alpha`.
+    pub fn update_current(
+        &mut self,
+        meshes: &WriteStorage<'_, MeshComponent>,
+        globals: &ReadStorage<'_, GlobalTransform>,
+        previous: &ReadStorage<'_, PreviousGlobalTransform>,
+        alpha: f32,
+    ) {
+        let index = self.current;
+        let frame = &mut self.frames[index];
+
+        // Uniforms
+        let uniforms = (meshes, globals, previous.maybe(), &frame.stale_meshes)
+            .join()
+            .map(|(mesh, global, previous, _)| {
+                let vertex = VertexInput {
+                    model: render_matrix(global, previous, alpha).into(),
+                };
+
+                (&mesh.uniform_slot, vertex)
+            });
+        self.mesh_uniforms.write(index, uniforms);
+        frame.stale_meshes.clear();
+
+        // Directional light
+        if frame.lights_stale {
+            frame.lights_stale = false;
+
+            *frame
+                .lights_buffer
+                .write()
+                .expect("Lights written while the GPU is using them") = self.lights;
+        }
+
+        // Point lights
+        // The number of lights might have changed, so we need a new buffer and descriptor set
+        if frame.point_lights_stale {
+            frame.point_lights_stale = false;
+
+            frame.point_lights_buffer = new_point_lights_buffer(&self.memory, &self.point_lights);
+
+            frame.shared_descriptor_set = Arc::new(
+                frame
+                    .shared_descriptor_set_pool
+                    .next()
+                    .add_buffer(frame.lights_buffer.clone())
+                    .unwrap()
+                    .add_buffer(frame.point_lights_buffer.clone())
+                    .unwrap()
+                    .build()
+                    .unwrap(),
+            );
+        }
+    }
+}
+
+fn new_point_lights_buffer(
+    memory: &BufferAllocator,
+    point_lights: &[PointLight],
+) -> Arc<CpuAccessibleBuffer<[PointLight]>> {
+    let usage = BufferUsage {
+        storage_buffer: true,
+        ..BufferUsage::none()
+    };
+
+    memory
+        .from_iter(MemoryUse::Scene, usage, point_lights.iter().cloned())
+        .unwrap()
+}
+
+/// Entities whose GlobalTransform changed since the last frame that was rendered
+///
+/// The renderer takes DirtyEntities::moved at the start of every frame, and frames that are
+/// skipped would lose it otherwise, leaving the uniforms of anything that moved meanwhile stale.
+#[derive(Default)]
+pub struct MovedEntities {
+    moved: BitSet,
+}
+
+impl MovedEntities {
+    /// Remembers the entities that moved this frame, before anything can skip it
+    pub fn remember(&mut self, dirty: &BitSet) {
+        self.moved |= dirty;
+    }
+
+    /// Takes every entity that moved since the last frame that was rendered
+    pub fn take(&mut self) -> BitSet {
+        mem::replace(&mut self.moved, BitSet::new())
+    }
+}
+
+/// The fences of the frames in flight, and the future the work of every frame starts from
+///
+/// This is the one place frames are paced. Waiting on the fence of a frame index before recording
+/// into it is what keeps the CPU at most FRAMES_IN_FLIGHT frames ahead of the GPU, while still
+/// letting it record frame N + 1 while the GPU executes frame N. Every frame starts after the last
+/// frame that was submitted, which stays the last one when a frame is skipped or fails to submit,
+/// so the GPU work is never cut loose from what came before it.
+///
+/// The semaphores between the submissions of a frame, and between acquiring and presenting its
+/// image, are taken from the device's semaphore pool by vulkano's futures. They go back to the
+/// pool once the fence of their frame has been waited on, which cleans up the futures holding
+/// them.
+///
+/// Command buffers come from the CommandPools, one per queue family rather than per frame. The
+/// pools recycle the buffers of a frame once its fence has been waited on and its futures cleaned
+/// up, the same way as the semaphores.
+pub struct FrameSync {
+    device: Arc<Device>,
+    fences: Vec<Option<FrameFence>>,
+    /// The fence of the frame submitted last
+    last: Option<FrameFence>,
+}
+
+impl FrameSync {
+    pub fn new(device: Arc<Device>) -> Self {
+        Self {
+            device,
+            fences: (0..FRAMES_IN_FLIGHT).map(|_| None).collect(),
+            last: None,
+        }
+    }
+
+    /// Blocks until the GPU has finished the last frame recorded with this index, and returns the
+    /// future the work of the frame has to start from
+    pub fn begin(&mut self, frame_index: usize) -> Box<GpuFuture + Send + Sync> {
+        self.wait(frame_index);
+
+        match &self.last {
+            Some(last) => {
+                let mut last = last.clone();
+                last.cleanup_finished();
+                Box::new(last)
+            }
+            None => Box::new(sync::now(self.device.clone())),
+        }
+    }
+
+    /// Signals the fence of a frame once its work is done, and submits it
+    ///
+    /// If the submission fails, the next frame starts after the last frame that was submitted.
+    pub fn submit(
+        &mut self,
+        frame_index: usize,
+        future: Box<GpuFuture + Send + Sync>,
+    ) -> Result<(), FlushError> {
+        let fence = Arc::new(future.then_signal_fence_and_flush()?);
+
+        self.fences[frame_index] = Some(fence.clone());
+        self.last = Some(fence);
+        Ok(())
+    }
+
+    /// Blocks until the GPU has finished every frame in flight
+    pub fn wait_all(&mut self) {
+        for frame_index in 0..FRAMES_IN_FLIGHT {
+            self.wait(frame_index);
+        }
+
+        self.last = None;
+    }
+
+    fn wait(&mut self, frame_index: usize) {
+        if let Some(fence) = self.fences[frame_index].take() {
+            if let Err(err) = fence.wait(None) {
+                error!("Failed to wait for frame {}: {:?}", frame_index, err);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::MovedEntities;
+    use specs::prelude::*;
+
+    #[test]
+    fn skipped_frames() {
+        let mut moved = MovedEntities::default();
+
+        // A frame that is skipped remembers what moved without taking it
+        let mut dirty = BitSet::new();
+        dirty.add(1);
+        moved.remember(&dirty);
+
+        // The next TransformSystem run has replaced DirtyEntities by then
+        let mut dirty = BitSet::new();
+        dirty.add(2);
+        moved.remember(&dirty);
+
+        let taken = moved.take();
+        assert!(taken.contains(1));
+        assert!(taken.contains(2));
+
+        // Once rendered, the moves are not applied again
+        assert!((&moved.take()).join().next().is_none());
+    }
+}
@@ -1,29 +1,35 @@
-use crate::renderer::shaders::VertexInput;
+use crate::{
+    components::{CoordinateSystem, Transform},
+    renderer::{
+        csg::{self, CsgOp},
+        memory::BufferAllocator,
+        ring::RingSlot,
+        vertex::{IndexBuffer, VertexBuffer},
+    },
+};
 use gltf;
 use log::info;
-use nalgebra::Vector3;
-use ncollide3d::procedural;
-use specs::{Component, DenseVecStorage, HashMapStorage};
+use nalgebra::{Isometry3, Point3, Translation3, Vector3, U3};
+use ncollide3d::query::{self, Ray, RayCast, RayIntersection};
+use ncollide3d::{
+    bounding_volume::{self, BoundingSphere, AABB},
+    procedural,
+    shape::{Ball, Capsule, Cuboid, Shape},
+};
+use specs::{Component, DenseVecStorage, HashMapStorage, NullStorage};
 use specs_derive::Component;
-use std::env;
-use std::path::PathBuf;
+use std::collections::HashMap;
+use std::f32::consts::PI;
+use std::mem;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use vulkano::{
-    buffer::{
-        cpu_pool::{CpuBufferPool, CpuBufferPoolSubbuffer},
-        BufferUsage, CpuAccessibleBuffer,
-    },
-    descriptor::descriptor_set::{DescriptorSet, FixedSizeDescriptorSetsPool},
-    device::Device,
-    impl_vertex,
-    memory::pool::StdMemoryPool,
-    pipeline::GraphicsPipelineAbstract,
-};
+use vulkano::{descriptor::descriptor_set::DescriptorSet, impl_vertex};
 
+#[repr(C)]
 #[derive(Debug, Clone, PartialEq)]
 pub struct Vertex {
-    position: [f32; 3],
-    normal: [f32; 3],
+    pub position: [f32; 3],
+    pub normal: [f32; 3],
 }
 
 impl_vertex!(Vertex, position, normal);
@@ -44,14 +50,38 @@ pub enum Shape {
     Quad(u32, u32),
     /// Capsule, number of subdivides around and across the capsule
     Capsule(u32, u32),
+    /// Torus around the y axis, major radius, minor radius, number of segments around both circles
+    Torus(f32, f32, u32),
+    /// Sphere made by subdividing an icosahedron, number of subdivisions
+    IcoSphere(u32),
+    /// Plane on the xz axis with heights along y, number of subdivisions along x and z, and a
+    /// function giving the height at a local position
+    Heightfield(u32, u32, fn(f32, f32) -> f32),
+}
+
+/// A step of generating a mesh, which is only taken once the mesh is generated
+#[derive(Debug, Clone)]
+enum MeshOp {
+    Shape(Shape),
+    Transform(Transform),
+    Csg(CsgOp, MeshBuilder),
+    Convert(CoordinateSystem),
 }
 
 /// MeshBuilder created by gameplay systems or from prefab and then built by the renderer
-#[derive(Component, Default, Debug)]
+///
+/// Shapes, transforms and CSG operations are only recorded, and applied in order when the mesh is
+/// generated, which the renderer leaves to a worker thread.
+#[derive(Component, Default, Debug, Clone)]
 #[storage(HashMapStorage)]
 pub struct MeshBuilder {
     vertex_data: Vec<Vertex>,
     index_data: Vec<u32>,
+    ops: Vec<MeshOp>,
+    batched: bool,
+    quantized: bool,
+    source: Option<PathBuf>,
+    coordinates: CoordinateSystem,
 }
 
 impl MeshBuilder {
@@ -59,19 +89,139 @@ impl MeshBuilder {
         Self {
             vertex_data: Vec::new(),
             index_data: Vec::new(),
+            ops: Vec::new(),
+            batched: false,
+            quantized: false,
+            source: None,
+            coordinates: CoordinateSystem::ENGINE,
         }
     }
 
+    /// Put the mesh in the renderer's static batch instead of giving it its own buffers
+    ///
+    /// Batched meshes are drawn with a single indirect draw call, which is a lot cheaper for large
+    /// static scenes, but adding or removing a batched mesh rebuilds the whole batch.
+    pub fn batched(mut self) -> Self {
+        self.batched = true;
+        self
+    }
+
+    pub fn is_batched(&self) -> bool {
+        self.batched
+    }
+
+    /// Store the normals as half floats, which is plenty for lighting and saves a fifth of the
+    /// memory of each vertex
+    ///
+    /// Batched meshes always use full vertices.
+    pub fn quantized(mut self) -> Self {
+        self.quantized = true;
+        self
+    }
+
+    pub fn is_quantized(&self) -> bool {
+        self.quantized
+    }
+
+    /// The file the mesh was loaded from, if any
+    pub fn source(&self) -> Option<&Path> {
+        self.source.as_ref().map(PathBuf::as_path)
+    }
+
+    /// The coordinate system the mesh was converted from
+    pub fn coordinates(&self) -> &CoordinateSystem {
+        &self.coordinates
+    }
+
+    /// Converts a mesh from another tool's coordinate system into the engine's
+    ///
+    /// Meant for meshes as they were imported, converting twice converts the converted mesh again.
+    /// Mirroring systems also have the winding of their triangles turned around.
+    pub fn converted_from(mut self, coordinates: &CoordinateSystem) -> Self {
+        self.ops.push(MeshOp::Convert(*coordinates));
+        self.coordinates = *coordinates;
+        self
+    }
+
+    /// Takes every recorded step, leaving the mesh data of the finished mesh
+    ///
+    /// Shapes and CSG are expensive, so this is best done off the render thread.
+    pub fn generated(mut self) -> Self {
+        for op in mem::replace(&mut self.ops, Vec::new()) {
+            self = match op {
+                MeshOp::Shape(shape) => self.apply_shape(shape),
+                MeshOp::Transform(transform) => self.apply_transform(&transform),
+                MeshOp::Csg(op, other) => self.apply_csg(op, other.generated()),
+                MeshOp::Convert(coordinates) => self.apply_conversion(&coordinates),
+            };
+        }
+
+        self
+    }
+
+    fn apply_conversion(mut self, coordinates: &CoordinateSystem) -> Self {
+        for vertex in self.vertex_data.iter_mut() {
+            vertex.position = coordinates.convert_point(vertex.position);
+            vertex.normal = coordinates.convert_direction(vertex.normal);
+        }
+
+        if coordinates.flips_winding() {
+            for triangle in self.index_data.chunks_mut(3) {
+                triangle.swap(1, 2);
+            }
+        }
+
+        self
+    }
+
+    /// How many bytes the buffers of the generated mesh take up
+    pub fn size_in_bytes(&self) -> usize {
+        self.vertex_data.len() * mem::size_of::<Vertex>()
+            + self.index_data.len() * mem::size_of::<u32>()
+    }
+
+    /// Consumes the builder, returning the raw vertex and index data of the generated mesh
+    pub fn into_data(self) -> (Vec<Vertex>, Vec<u32>) {
+        debug_assert!(self.ops.is_empty(), "The mesh has not been generated");
+        (self.vertex_data, self.index_data)
+    }
+
+    /// Replaces the mesh with a primitive shape
     pub fn with_shape(mut self, shape: Shape) -> Self {
-        let mut trimesh = match shape {
+        self.ops.push(MeshOp::Shape(shape));
+        self
+    }
+
+    fn apply_shape(mut self, shape: Shape) -> Self {
+        let trimesh = match shape {
             Shape::Sphere(u, v) => procedural::sphere(1.0, u, v, false),
             Shape::Cone(u) => procedural::cone(1.0, 1.0, u),
             Shape::Cube => procedural::cuboid(&Vector3::new(1.0, 1.0, 1.0)),
             Shape::Cylinder(u) => procedural::cylinder(1.0, 1.0, u),
             Shape::Quad(u, v) => procedural::quad(1.0, 1.0, u as usize, v as usize),
             Shape::Capsule(u, v) => procedural::capsule(&1.0, &1.0, u, v),
+            // Shapes ncollide does not have are built here, with exact normals
+            Shape::Torus(major, minor, segments) => {
+                let (vertex_data, index_data) = torus(major, minor, segments);
+                self.vertex_data = vertex_data;
+                self.index_data = index_data;
+                return self;
+            }
+            Shape::IcoSphere(subdivisions) => {
+                let (vertex_data, index_data) = icosphere(subdivisions);
+                self.vertex_data = vertex_data;
+                self.index_data = index_data;
+                return self;
+            }
+            Shape::Heightfield(width, depth, sampler) => {
+                let (vertex_data, index_data) = heightfield(width, depth, sampler);
+                self.vertex_data = vertex_data;
+                self.index_data = index_data;
+                return self;
+            }
         };
 
+        let mut trimesh = trimesh;
         trimesh.unify_index_buffer();
         trimesh.recompute_normals();
 
@@ -91,101 +241,592 @@ impl MeshBuilder {
         self
     }
 
-    pub fn with_gltf_file(mut self, file: &str) -> Self {
-        let file = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap())
-            .join("resources")
-            .join(file);
+    /// Moves the mesh data by a transform, for placing meshes relative to each other before
+    /// combining them
+    pub fn transformed(mut self, transform: &Transform) -> Self {
+        self.ops.push(MeshOp::Transform(transform.clone()));
+        self
+    }
+
+    fn apply_transform(mut self, transform: &Transform) -> Self {
+        let matrix = transform.to_matrix();
+        let scale = transform.scale();
+
+        for vertex in self.vertex_data.iter_mut() {
+            let [x, y, z] = vertex.position;
+            let position = matrix.transform_point(&Point3::new(x, y, z));
+            // Normals are scaled by the inverse scale to stay perpendicular to the surface
+            let normal = transform.rotation() * Vector3::from(vertex.normal).component_div(scale);
+
+            vertex.position = position.coords.into();
+            vertex.normal = normal.normalize().into();
+        }
+
+        self
+    }
+
+    /// Combines the mesh with another one
+    ///
+    /// Both meshes should be closed. Use `transformed` to place the other mesh first.
+    pub fn with_csg(mut self, op: CsgOp, other: MeshBuilder) -> Self {
+        self.ops.push(MeshOp::Csg(op, other));
+        self
+    }
 
-        println!("Loading file: {:?}", file);
+    fn apply_csg(mut self, op: CsgOp, other: MeshBuilder) -> Self {
+        let (vertex_data, index_data) = csg::apply(
+            op,
+            (&self.vertex_data, &self.index_data),
+            (&other.vertex_data, &other.index_data),
+        );
+
+        self.vertex_data = vertex_data;
+        self.index_data = index_data;
+        self
+    }
 
-        let (gltf, buffers, _) = gltf::import(file).expect("Failed to import gltf document");
+    /// Loads a glTF file from anywhere on disk
+    ///
+    /// Decoding large files takes a while, `LoadMesh` does it on a worker thread instead.
+    pub fn try_with_gltf_path(mut self, file: &Path) -> Result<Self, String> {
+        // The file is appended to the mesh generated so far
+        self = self.generated();
 
-        println!("Parsing file");
+        let (gltf, buffers, _) = gltf::import(file)
+            .map_err(|err| format!("Failed to import {}: {}", file.display(), err))?;
 
         // Get the first scene
-        let scene = gltf.scenes().next().unwrap();
+        let scene = gltf
+            .scenes()
+            .next()
+            .ok_or_else(|| format!("{} has no scenes", file.display()))?;
 
         // FIXME Only supports one mesh
         // Go through the nodes and add the meshes to vertex_data
-        scene.nodes().for_each(|node| {
-            if let Some(mesh) = node.mesh() {
-                println!("Node: {:?}, has a mesh", node.index());
-
-                mesh.primitives().for_each(|primitive| {
-                    let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
-
-                    if let (Some(positions), Some(normals)) =
-                        (reader.read_positions(), reader.read_normals())
-                    {
-                        println!("Writing vertex and index data");
-
-                        self.vertex_data = positions
-                            .zip(normals)
-                            .map(|(position, normal)| Vertex { position, normal })
-                            .collect();
-
-                        self.index_data = reader.read_indices().unwrap().into_u32().collect();
-                    }
-                });
+        for mesh in scene.nodes().filter_map(|node| node.mesh()) {
+            for primitive in mesh.primitives() {
+                let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+
+                if let (Some(positions), Some(normals)) =
+                    (reader.read_positions(), reader.read_normals())
+                {
+                    let indices = reader
+                        .read_indices()
+                        .ok_or_else(|| format!("{} has a mesh without indices", file.display()))?;
+
+                    self.vertex_data = positions
+                        .zip(normals)
+                        .map(|(position, normal)| Vertex { position, normal })
+                        .collect();
+
+                    self.index_data = indices.into_u32().collect();
+                }
             }
-        });
+        }
 
-        self
+        self.source = Some(file.to_path_buf());
+        Ok(self)
     }
 
+    /// Computes the local space bounds of the generated mesh data
+    pub fn bounds(&self) -> Bounds {
+        debug_assert!(self.ops.is_empty(), "The mesh has not been generated");
+
+        let points = self
+            .vertex_data
+            .iter()
+            .map(|v| Point3::new(v.position[0], v.position[1], v.position[2]))
+            .collect::<Vec<_>>();
+
+        // An empty mesh still needs some bounds
+        if points.is_empty() {
+            let origin = Point3::origin();
+            return Bounds {
+                aabb: AABB::new(origin, origin),
+                sphere: BoundingSphere::new(origin, 0.0),
+            };
+        }
+
+        Bounds {
+            aabb: bounding_volume::point_cloud_aabb(&Isometry3::identity(), &points),
+            sphere: bounding_volume::point_cloud_bounding_sphere(&points),
+        }
+    }
+
+    /// Creates the buffers of the mesh, with the uniforms and descriptor sets it is drawn with
     pub fn build(
         self,
-        device: Arc<Device>,
-        vertex_input_pool: &CpuBufferPool<VertexInput>,
-        vertex_input: VertexInput,
-        descriptor_set_pool: &mut FixedSizeDescriptorSetsPool<
-            Arc<GraphicsPipelineAbstract + Send + Sync>,
-        >,
+        memory: &BufferAllocator,
+        uniform_slot: RingSlot,
+        descriptor_sets: Vec<Arc<DescriptorSet + Send + Sync>>,
     ) -> MeshComponent {
+        debug_assert!(self.ops.is_empty(), "The mesh has not been generated");
         info!(
             "Building mesh from: Vertices: {:?}, Indices: {:?}",
             self.vertex_data, self.index_data
         );
 
-        let vertex_buffer = CpuAccessibleBuffer::from_iter(
-            device.clone(),
-            BufferUsage::vertex_buffer(),
-            self.vertex_data.into_iter(),
-        )
-        .expect("Failed to create vertex buffer");
-
-        let index_buffer = CpuAccessibleBuffer::from_iter(
-            device.clone(),
-            BufferUsage::index_buffer(),
-            self.index_data.into_iter(),
-        )
-        .expect("Failed to create index buffer");
-
-        let vertex_uniforms = Arc::new(vertex_input_pool.next(vertex_input).unwrap());
-
-        let descriptor_set = Arc::new(
-            descriptor_set_pool
-                .next()
-                .add_buffer(vertex_uniforms.clone())
-                .unwrap()
-                .build()
-                .unwrap(),
-        );
+        let index_buffer = IndexBuffer::new(memory, self.index_data, self.vertex_data.len());
+
+        let vertex_buffer = VertexBuffer::new(memory, self.vertex_data, self.quantized);
 
         MeshComponent {
             vertex_buffer,
             index_buffer,
-            vertex_uniforms,
-            descriptor_set,
+            uniform_slot,
+            descriptor_sets,
         }
     }
 }
 
+/// Torus around the y axis
+///
+/// The seams are shared by wrapping the indices, so every vertex is unique.
+fn torus(major: f32, minor: f32, segments: u32) -> (Vec<Vertex>, Vec<u32>) {
+    let segments = segments.max(3);
+    let step = 2.0 * PI / segments as f32;
+
+    let mut vertices = Vec::with_capacity((segments * segments) as usize);
+    for i in 0..segments {
+        let (sin_u, cos_u) = (i as f32 * step).sin_cos();
+
+        for j in 0..segments {
+            let (sin_v, cos_v) = (j as f32 * step).sin_cos();
+
+            let normal = Vector3::new(cos_v * cos_u, sin_v, cos_v * sin_u);
+            let position = Vector3::new(major * cos_u, 0.0, major * sin_u) + normal * minor;
+
+            vertices.push(Vertex {
+                position: position.into(),
+                normal: normal.into(),
+            });
+        }
+    }
+
+    let index = |i: u32, j: u32| (i % segments) * segments + j % segments;
+
+    let mut indices = Vec::with_capacity((segments * segments * 6) as usize);
+    for i in 0..segments {
+        for j in 0..segments {
+            let (a, b, c, d) = (
+                index(i, j),
+                index(i + 1, j),
+                index(i + 1, j + 1),
+                index(i, j + 1),
+            );
+
+            indices.extend_from_slice(&[a, c, b, a, d, c]);
+        }
+    }
+
+    (vertices, indices)
+}
+
+/// Sphere with a diameter of 1, like Shape::Sphere, made from a subdivided icosahedron
+///
+/// The triangles are much more even than those of a uv sphere, and there are no poles.
+fn icosphere(subdivisions: u32) -> (Vec<Vertex>, Vec<u32>) {
+    let t = (1.0 + 5f32.sqrt()) / 2.0;
+
+    let mut points = vec![
+        Vector3::new(-1.0, t, 0.0),
+        Vector3::new(1.0, t, 0.0),
+        Vector3::new(-1.0, -t, 0.0),
+        Vector3::new(1.0, -t, 0.0),
+        Vector3::new(0.0, -1.0, t),
+        Vector3::new(0.0, 1.0, t),
+        Vector3::new(0.0, -1.0, -t),
+        Vector3::new(0.0, 1.0, -t),
+        Vector3::new(t, 0.0, -1.0),
+        Vector3::new(t, 0.0, 1.0),
+        Vector3::new(-t, 0.0, -1.0),
+        Vector3::new(-t, 0.0, 1.0),
+    ]
+    .into_iter()
+    .map(|p| p.normalize())
+    .collect::<Vec<_>>();
+
+    let mut faces: Vec<[u32; 3]> = vec![
+        [0, 11, 5],
+        [0, 5, 1],
+        [0, 1, 7],
+        [0, 7, 10],
+        [0, 10, 11],
+        [1, 5, 9],
+        [5, 11, 4],
+        [11, 10, 2],
+        [10, 7, 6],
+        [7, 1, 8],
+        [3, 9, 4],
+        [3, 4, 2],
+        [3, 2, 6],
+        [3, 6, 8],
+        [3, 8, 9],
+        [4, 9, 5],
+        [2, 4, 11],
+        [6, 2, 10],
+        [8, 6, 7],
+        [9, 8, 1],
+    ];
+
+    for _ in 0..subdivisions {
+        // Edges are shared between two faces, so their midpoints are only added once
+        let mut midpoints = HashMap::new();
+        let mut midpoint = |a: u32, b: u32, points: &mut Vec<Vector3<f32>>| {
+            let key = (a.min(b), a.max(b));
+
+            *midpoints.entry(key).or_insert_with(|| {
+                let p = (points[a as usize] + points[b as usize]).normalize();
+                points.push(p);
+                points.len() as u32 - 1
+            })
+        };
+
+        faces = faces
+            .iter()
+            .flat_map(|&[a, b, c]| {
+                let ab = midpoint(a, b, &mut points);
+                let bc = midpoint(b, c, &mut points);
+                let ca = midpoint(c, a, &mut points);
+
+                vec![[a, ab, ca], [b, bc, ab], [c, ca, bc], [ab, bc, ca]]
+            })
+            .collect();
+    }
+
+    let vertices = points
+        .into_iter()
+        .map(|p| Vertex {
+            position: (p * 0.5).into(),
+            normal: p.into(),
+        })
+        .collect();
+
+    let indices = faces.iter().flat_map(|face| face.iter().cloned()).collect();
+
+    (vertices, indices)
+}
+
+/// Plane of 1 by 1 on the xz axis, with heights from the sampler
+///
+/// The sampler is given the local x and z of each vertex, both in -0.5..0.5. Normals are found
+/// from the heights of the neighbouring vertices.
+fn heightfield(width: u32, depth: u32, sampler: fn(f32, f32) -> f32) -> (Vec<Vertex>, Vec<u32>) {
+    let (width, depth) = (width.max(1), depth.max(1));
+    let columns = width + 1;
+
+    let x_at = |i: u32| i as f32 / width as f32 - 0.5;
+    let z_at = |j: u32| j as f32 / depth as f32 - 0.5;
+
+    let heights = (0..=depth)
+        .flat_map(|j| (0..=width).map(move |i| sampler(x_at(i), z_at(j))))
+        .collect::<Vec<_>>();
+    let height = |i: u32, j: u32| heights[(j * columns + i) as usize];
+
+    let mut vertices = Vec::with_capacity(heights.len());
+    for j in 0..=depth {
+        for i in 0..=width {
+            // Central differences, one sided at the edges
+            let (left, right) = (i.saturating_sub(1), (i + 1).min(width));
+            let (back, front) = (j.saturating_sub(1), (j + 1).min(depth));
+
+            let dx = (height(right, j) - height(left, j)) / (x_at(right) - x_at(left));
+            let dz = (height(i, front) - height(i, back)) / (z_at(front) - z_at(back));
+
+            vertices.push(Vertex {
+                position: [x_at(i), height(i, j), z_at(j)],
+                normal: Vector3::new(-dx, 1.0, -dz).normalize().into(),
+            });
+        }
+    }
+
+    let mut indices = Vec::with_capacity((width * depth * 6) as usize);
+    for j in 0..depth {
+        for i in 0..width {
+            let a = j * columns + i;
+            let (b, c, d) = (a + 1, a + columns + 1, a + columns);
+
+            indices.extend_from_slice(&[a, d, c, a, c, b]);
+        }
+    }
+
+    (vertices, indices)
+}
+
 /// Generic mesh component
 #[derive(Component)]
 pub struct MeshComponent {
-    pub vertex_buffer: Arc<CpuAccessibleBuffer<[Vertex]>>,
-    pub index_buffer: Arc<CpuAccessibleBuffer<[u32]>>,
-    pub vertex_uniforms: Arc<CpuBufferPoolSubbuffer<VertexInput, Arc<StdMemoryPool>>>,
-    pub descriptor_set: Arc<DescriptorSet + Send + Sync>,
+    pub vertex_buffer: VertexBuffer,
+    pub index_buffer: IndexBuffer,
+    /// Where the uniforms of the mesh are in the renderer's frame ring
+    pub uniform_slot: RingSlot,
+    // Indexed by frame in flight
+    pub descriptor_sets: Vec<Arc<DescriptorSet + Send + Sync>>,
+}
+
+/// Local space bounding volumes of a mesh, inserted by the renderer when the mesh is built
+#[derive(Component, Clone, Debug)]
+pub struct Bounds {
+    pub aabb: AABB<f32>,
+    pub sphere: BoundingSphere<f32>,
+}
+
+impl Bounds {
+    /// The bounding sphere in world space
+    ///
+    /// Nonuniform scale is handled conservatively by scaling the radius by the largest axis.
+    pub fn world_sphere(&self, global: &Transform) -> BoundingSphere<f32> {
+        let center = global.to_matrix().transform_point(self.sphere.center());
+        let scale = global.scale().abs();
+        let max_scale = scale.x.max(scale.y).max(scale.z);
+
+        BoundingSphere::new(center, self.sphere.radius() * max_scale)
+    }
+
+    /// The world space box around the rotated and scaled bounding box
+    pub fn world_aabb(&self, global: &Transform) -> AABB<f32> {
+        let matrix = global.to_matrix();
+        let center = matrix.transform_point(&self.aabb.center());
+
+        // Each world axis of the box is covered by the extents of every rotated local axis
+        let linear = matrix.fixed_slice::<U3, U3>(0, 0).abs();
+        let half_extents = linear * self.aabb.half_extents();
+
+        AABB::new(center - half_extents, center + half_extents)
+    }
+
+    /// Casts a world space ray against the bounding box
+    ///
+    /// The ray is moved into the local space of the box, so the time of impact is in the units of
+    /// the world space ray, and the normal is returned in world space.
+    pub fn cast_ray(&self, global: &Transform, ray: &Ray<f32>) -> Option<RayIntersection<f32>> {
+        let inverse = global.iso.inverse();
+        let scale = global.scale();
+
+        let local_ray = Ray::new(
+            Point3::from((inverse * ray.origin).coords.component_div(scale)),
+            (inverse * ray.dir).component_div(scale),
+        );
+
+        self.aabb
+            .toi_and_normal_with_ray(&Isometry3::identity(), &local_ray, true)
+            .map(|hit| {
+                let normal = (global.rotation() * hit.normal.component_div(scale)).normalize();
+                RayIntersection::new(hit.toi, normal, hit.feature)
+            })
+    }
+
+    /// Sweeps a world space sphere along `motion` against the bounding box
+    ///
+    /// Returns the fraction of the motion made before the sphere touches the box, or None if it
+    /// does not within the motion. Spheres starting inside the box pass through it, so they can
+    /// always get out.
+    pub fn cast_sphere(
+        &self,
+        global: &Transform,
+        center: &Point3<f32>,
+        radius: f32,
+        motion: &Vector3<f32>,
+    ) -> Option<f32> {
+        let iso = Isometry3::new(center.coords, Vector3::zeros());
+        self.cast_shape(global, &iso, &Ball::new(radius), motion)
+    }
+
+    /// Sweeps an upright world space capsule along `motion` against the bounding box, like
+    /// `cast_sphere`
+    ///
+    /// The capsule is the segment `half_height` above and below `center`, rounded by `radius`.
+    pub fn cast_capsule(
+        &self,
+        global: &Transform,
+        center: &Point3<f32>,
+        half_height: f32,
+        radius: f32,
+        motion: &Vector3<f32>,
+    ) -> Option<f32> {
+        let iso = Isometry3::new(center.coords, Vector3::zeros());
+        self.cast_shape(global, &iso, &Capsule::new(half_height, radius), motion)
+    }
+
+    fn cast_shape(
+        &self,
+        global: &Transform,
+        iso: &Isometry3<f32>,
+        shape: &dyn Shape<f32>,
+        motion: &Vector3<f32>,
+    ) -> Option<f32> {
+        let half_extents = self
+            .aabb
+            .half_extents()
+            .component_mul(&global.scale().abs());
+        let box_center = global.to_matrix().transform_point(&self.aabb.center());
+        let box_iso =
+            Isometry3::from_parts(Translation3::from(box_center.coords), *global.rotation());
+        let cuboid = Cuboid::new(half_extents);
+
+        if query::distance(iso, shape, &box_iso, &cuboid) <= 0.0 {
+            return None;
+        }
+
+        query::time_of_impact(iso, motion, shape, &box_iso, &Vector3::zeros(), &cuboid)
+            .filter(|toi| *toi <= 1.0)
+    }
+}
+
+/// Draws the mesh as a translucent ghost, used for previews
+#[derive(Component, Debug, Default)]
+#[storage(NullStorage)]
+pub struct Ghost;
+
+/// Marks entities whose MeshBuilder is being generated by the renderer, and has no mesh yet
+#[derive(Component, Debug, Default)]
+#[storage(NullStorage)]
+pub struct PendingMesh;
+
+#[cfg(test)]
+mod test {
+    use super::{heightfield, icosphere, torus, Bounds, MeshBuilder, Shape, Vertex};
+    use crate::components::Transform;
+    use nalgebra::{Point3, UnitQuaternion, Vector3};
+    use ncollide3d::bounding_volume::{BoundingSphere, AABB};
+
+    /// Checks that the indices are valid, the normals are unit length, and every triangle is wound
+    /// counter clockwise when seen from the side its vertex normals point to
+    fn check_mesh(vertices: &[Vertex], indices: &[u32]) {
+        assert_eq!(indices.len() % 3, 0);
+        assert!(indices.iter().all(|i| (*i as usize) < vertices.len()));
+
+        for vertex in vertices {
+            let normal = Vector3::from(vertex.normal);
+            assert!((normal.norm() - 1.0).abs() < 1e-4);
+        }
+
+        for triangle in indices.chunks(3) {
+            let [a, b, c] = [
+                &vertices[triangle[0] as usize],
+                &vertices[triangle[1] as usize],
+                &vertices[triangle[2] as usize],
+            ];
+
+            let (pa, pb, pc) = (
+                Vector3::from(a.position),
+                Vector3::from(b.position),
+                Vector3::from(c.position),
+            );
+            let face_normal = (pb - pa).cross(&(pc - pa));
+            let vertex_normal =
+                Vector3::from(a.normal) + Vector3::from(b.normal) + Vector3::from(c.normal);
+
+            assert!(face_normal.dot(&vertex_normal) > 0.0);
+        }
+    }
+
+    #[test]
+    fn torus_mesh() {
+        let (vertices, indices) = torus(1.0, 0.25, 16);
+        check_mesh(&vertices, &indices);
+    }
+
+    #[test]
+    fn icosphere_mesh() {
+        let (vertices, indices) = icosphere(2);
+        check_mesh(&vertices, &indices);
+
+        // 20 faces, each split into 4 per subdivision
+        assert_eq!(indices.len(), 20 * 4 * 4 * 3);
+        // Shared edges are only split once
+        assert_eq!(vertices.len(), 162);
+    }
+
+    #[test]
+    fn heightfield_mesh() {
+        let (vertices, indices) = heightfield(8, 4, |x, z| (x * 5.0).sin() * z);
+        check_mesh(&vertices, &indices);
+
+        assert_eq!(vertices.len(), 9 * 5);
+    }
+
+    #[test]
+    fn sphere_cast() {
+        let bounds = Bounds {
+            aabb: AABB::new(Point3::new(-1.0, -1.0, -1.0), Point3::new(1.0, 1.0, 1.0)),
+            sphere: BoundingSphere::new(Point3::origin(), 3.0f32.sqrt()),
+        };
+        let global = Transform::from(Vector3::new(5.0, 0.0, 0.0));
+        let origin = Point3::origin();
+
+        let toi = bounds.cast_sphere(&global, &origin, 0.5, &Vector3::new(10.0, 0.0, 0.0));
+        assert!((toi.unwrap() - 0.35).abs() < 1e-4);
+
+        // Stopping short of the box, or moving away from it
+        assert!(bounds
+            .cast_sphere(&global, &origin, 0.5, &Vector3::new(1.0, 0.0, 0.0))
+            .is_none());
+        assert!(bounds
+            .cast_sphere(&global, &origin, 0.5, &Vector3::new(-10.0, 0.0, 0.0))
+            .is_none());
+
+        // Starting inside the box
+        let inside = Point3::new(5.0, 0.0, 0.0);
+        assert!(bounds
+            .cast_sphere(&global, &inside, 0.5, &Vector3::new(10.0, 0.0, 0.0))
+            .is_none());
+
+        // The box is scaled with the mesh
+        let scaled = Transform::from_parts(
+            Vector3::new(5.0, 0.0, 0.0),
+            UnitQuaternion::identity(),
+            Vector3::new(2.0, 1.0, 1.0),
+        );
+        let toi = bounds.cast_sphere(&scaled, &origin, 0.5, &Vector3::new(10.0, 0.0, 0.0));
+        assert!((toi.unwrap() - 0.25).abs() < 1e-4);
+    }
+
+    #[test]
+    fn world_aabb() {
+        let bounds = Bounds {
+            aabb: AABB::new(Point3::new(-1.0, -1.0, -1.0), Point3::new(1.0, 1.0, 1.0)),
+            sphere: BoundingSphere::new(Point3::origin(), 3.0f32.sqrt()),
+        };
+
+        // Rotated 45 degrees around y after being stretched along x
+        let global = Transform::from_parts(
+            Vector3::new(5.0, 0.0, 0.0),
+            UnitQuaternion::from_axis_angle(&Vector3::y_axis(), std::f32::consts::FRAC_PI_4),
+            Vector3::new(2.0, 1.0, 1.0),
+        );
+        let aabb = bounds.world_aabb(&global);
+
+        let extent = 3.0 * std::f32::consts::FRAC_1_SQRT_2;
+        assert!((aabb.maxs().x - (5.0 + extent)).abs() < 1e-4);
+        assert!((aabb.maxs().y - 1.0).abs() < 1e-4);
+        assert!((aabb.maxs().z - extent).abs() < 1e-4);
+        assert!((aabb.mins().x - (5.0 - extent)).abs() < 1e-4);
+    }
+
+    #[test]
+    fn generated_in_order() {
+        let scale = Transform::from_parts(
+            Vector3::zeros(),
+            UnitQuaternion::identity(),
+            Vector3::new(2.0, 2.0, 2.0),
+        );
+        let offset = Transform::from_parts(
+            Vector3::new(1.0, 0.0, 0.0),
+            UnitQuaternion::identity(),
+            Vector3::new(1.0, 1.0, 1.0),
+        );
+
+        // Nothing is generated until asked for
+        let builder = MeshBuilder::new()
+            .with_shape(Shape::Cube)
+            .transformed(&scale)
+            .transformed(&offset);
+        assert!(builder.vertex_data.is_empty());
+
+        let aabb = builder.generated().bounds().aabb;
+        assert!((aabb.mins().x - 0.0).abs() < 1e-4);
+        assert!((aabb.maxs().x - 2.0).abs() < 1e-4);
+        assert!((aabb.maxs().y - 1.0).abs() < 1e-4);
+    }
 }
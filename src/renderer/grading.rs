@@ -0,0 +1,276 @@
+use image::{Pixel, RgbaImage};
+use log::{info, warn};
+use std::{fs, path::Path, sync::Arc};
+
+/// A 3D color lookup table, mapping every color on screen to a graded one
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lut {
+    size: u32,
+    /// Red changes fastest, then green, then blue
+    texels: Vec<[u8; 4]>,
+}
+
+impl Lut {
+    /// A LUT leaving every color as it is
+    pub fn identity(size: u32) -> Self {
+        let max = (size - 1) as f32;
+
+        Self::from_fn(size, |r, g, b| {
+            [r as f32 / max, g as f32 / max, b as f32 / max]
+        })
+    }
+
+    /// Loads a LUT from an Adobe .cube file, or a .png strip of blue slices laid out side by side
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        let path = path.as_ref();
+
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("cube") => {
+                let text = fs::read_to_string(path).map_err(|err| err.to_string())?;
+                Self::from_cube(&text)
+            }
+            Some("png") => {
+                let image = image::open(path).map_err(|err| err.to_string())?;
+                Self::from_strip(&image.to_rgba())
+            }
+            _ => Err("Unsupported LUT format".to_string()),
+        }
+    }
+
+    /// Parses the text of a .cube file
+    ///
+    /// Only 3D LUTs over the default domain of 0 to 1 are supported.
+    pub fn from_cube(text: &str) -> Result<Self, String> {
+        let mut size = None;
+        let mut colors = Vec::new();
+
+        for line in text.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut words = line.split_whitespace();
+            match words.next() {
+                Some("LUT_3D_SIZE") => {
+                    let value = words.next().ok_or("Missing LUT_3D_SIZE value")?;
+                    size = Some(value.parse::<u32>().map_err(|err| err.to_string())?);
+                }
+                Some("LUT_1D_SIZE") => return Err("1D LUTs are not supported".to_string()),
+                Some("TITLE") | Some("DOMAIN_MIN") | Some("DOMAIN_MAX") => (),
+                Some(_) => {
+                    let color = line
+                        .split_whitespace()
+                        .map(|value| value.parse::<f32>().map_err(|err| err.to_string()))
+                        .collect::<Result<Vec<_>, _>>()?;
+
+                    if color.len() != 3 {
+                        return Err(format!("Expected an RGB triplet, found {:?}", line));
+                    }
+
+                    colors.push([color[0], color[1], color[2]]);
+                }
+                None => (),
+            }
+        }
+
+        let size = size.ok_or("Missing LUT_3D_SIZE")?;
+        if size < 2 || colors.len() != (size * size * size) as usize {
+            return Err(format!(
+                "Expected {} colors for a LUT of size {}, found {}",
+                size * size * size,
+                size,
+                colors.len()
+            ));
+        }
+
+        let texels = colors.into_iter().map(to_texel).collect();
+
+        Ok(Self { size, texels })
+    }
+
+    /// Reads a strip of `size` slices of `size` by `size` pixels, one slice per blue value
+    pub fn from_strip(image: &RgbaImage) -> Result<Self, String> {
+        let size = image.height();
+        if size < 2 || image.width() != size * size {
+            return Err(format!(
+                "Expected a strip of {0} by {0} slices, found an image of {1} by {0}",
+                size,
+                image.width()
+            ));
+        }
+
+        let texels = (0..size * size * size)
+            .map(|i| {
+                let (r, g, b) = (i % size, i / size % size, i / (size * size));
+                let channels = image.get_pixel(b * size + r, g).channels();
+
+                [channels[0], channels[1], channels[2], 255]
+            })
+            .collect();
+
+        Ok(Self { size, texels })
+    }
+
+    /// The number of texels along each side of the LUT
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    pub fn texels(&self) -> &[[u8; 4]] {
+        &self.texels
+    }
+
+    fn from_fn<F>(size: u32, f: F) -> Self
+    where
+        F: Fn(u32, u32, u32) -> [f32; 3],
+    {
+        let texels = (0..size * size * size)
+            .map(|i| to_texel(f(i % size, i / size % size, i / (size * size))))
+            .collect();
+
+        Self { size, texels }
+    }
+}
+
+fn to_texel(color: [f32; 3]) -> [u8; 4] {
+    let channel = |value: f32| (value.max(0.0).min(1.0) * 255.0).round() as u8;
+
+    [channel(color[0]), channel(color[1]), channel(color[2]), 255]
+}
+
+/// Resource with the color grading LUTs, of which one at a time is applied to the final image
+///
+/// Switching LUTs at runtime changes the mood of the scene. Without an active LUT, colors are left
+/// as they are.
+#[derive(Debug, Default)]
+pub struct ColorGrading {
+    luts: Vec<(String, Arc<Lut>)>,
+    active: Option<usize>,
+    pub dirty: bool,
+}
+
+impl ColorGrading {
+    /// Loads every LUT in a directory, sorted by file name. Files that fail to load are skipped
+    pub fn load_dir<P: AsRef<Path>>(path: P) -> Self {
+        let path = path.as_ref();
+        let mut grading = Self::default();
+
+        let mut paths = match fs::read_dir(path) {
+            Ok(entries) => entries
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .collect::<Vec<_>>(),
+            Err(err) => {
+                warn!("Failed to read LUTs from {}: {}", path.display(), err);
+                return grading;
+            }
+        };
+        paths.sort();
+
+        for path in paths {
+            let name = match path.file_stem().and_then(|stem| stem.to_str()) {
+                Some(name) => name.to_string(),
+                None => continue,
+            };
+
+            match Lut::load(&path) {
+                Ok(lut) => {
+                    info!("Loaded LUT {}", path.display());
+                    grading.add(name, lut);
+                }
+                Err(err) => warn!("Failed to load LUT {}: {}", path.display(), err),
+            }
+        }
+
+        grading
+    }
+
+    /// Adds a LUT that can be selected by the returned index
+    pub fn add(&mut self, name: String, lut: Lut) -> usize {
+        self.luts.push((name, Arc::new(lut)));
+        self.luts.len() - 1
+    }
+
+    /// Makes the LUT at `index` the active one, or turns grading off with None
+    pub fn select(&mut self, index: Option<usize>) {
+        self.active = index.filter(|index| *index < self.luts.len());
+        self.dirty = true;
+    }
+
+    /// Selects the next LUT, with grading turned off after the last one
+    pub fn cycle(&mut self) {
+        let next = match self.active {
+            Some(index) => index + 1,
+            None => 0,
+        };
+
+        self.select(Some(next));
+    }
+
+    /// The name and LUT of the active LUT, if any
+    pub fn active(&self) -> Option<(&str, &Arc<Lut>)> {
+        self.active
+            .map(|index| &self.luts[index])
+            .map(|(name, lut)| (name.as_str(), lut))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ColorGrading, Lut};
+    use image::{Pixel, Rgba, RgbaImage};
+
+    #[test]
+    fn cube_identity() {
+        let text = "\
+            # Comment\n\
+            TITLE \"Identity\"\n\
+            LUT_3D_SIZE 2\n\
+            DOMAIN_MIN 0.0 0.0 0.0\n\
+            DOMAIN_MAX 1.0 1.0 1.0\n\
+            0.0 0.0 0.0\n\
+            1.0 0.0 0.0\n\
+            0.0 1.0 0.0\n\
+            1.0 1.0 0.0\n\
+            0.0 0.0 1.0\n\
+            1.0 0.0 1.0\n\
+            0.0 1.0 1.0\n\
+            1.0 1.0 1.0\n";
+
+        assert_eq!(Lut::from_cube(text).unwrap(), Lut::identity(2));
+    }
+
+    #[test]
+    fn cube_errors() {
+        assert!(Lut::from_cube("0.0 0.0 0.0").is_err());
+        assert!(Lut::from_cube("LUT_3D_SIZE 2\n0.0 0.0 0.0").is_err());
+        assert!(Lut::from_cube("LUT_1D_SIZE 2").is_err());
+    }
+
+    #[test]
+    fn strip_identity() {
+        let size = 4;
+        let scale = |value: u32| (value * 255 / (size - 1)) as u8;
+
+        let image = RgbaImage::from_fn(size * size, size, |x, y| {
+            Rgba::from_channels(scale(x % size), scale(y), scale(x / size), 255)
+        });
+
+        assert_eq!(Lut::from_strip(&image).unwrap(), Lut::identity(size));
+        assert!(Lut::from_strip(&RgbaImage::new(5, 4)).is_err());
+    }
+
+    #[test]
+    fn cycle() {
+        let mut grading = ColorGrading::default();
+        grading.add("a".to_string(), Lut::identity(2));
+        grading.add("b".to_string(), Lut::identity(3));
+
+        grading.cycle();
+        assert_eq!(grading.active().unwrap().0, "a");
+        grading.cycle();
+        assert_eq!(grading.active().unwrap().0, "b");
+        grading.cycle();
+        assert!(grading.active().is_none());
+        assert!(grading.dirty);
+    }
+}
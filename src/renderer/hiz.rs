@@ -0,0 +1,301 @@
+use crate::renderer::shaders::{HiZLevel, ShaderSet};
+use nalgebra::Matrix4;
+use std::sync::Arc;
+use vulkano::{
+    command_buffer::AutoCommandBufferBuilder,
+    descriptor::{descriptor_set::FixedSizeDescriptorSetsPool, DescriptorSet},
+    device::{Device, Queue},
+    format::Format,
+    image::{AttachmentImage, Dimensions, ImageUsage, StorageImage},
+    pipeline::{viewport::Viewport, ComputePipeline, ComputePipelineAbstract},
+    sampler::{Filter, MipmapMode, Sampler, SamplerAddressMode},
+};
+
+/// The format of every level of the pyramid
+pub const HI_Z_FORMAT: Format = Format::R32Sfloat;
+
+/// The levels shaders bind the pyramid as, see `shaders/hiz.glsl`
+pub const HI_Z_MAX_LEVELS: usize = 14;
+
+const WORK_GROUP_SIZE: u32 = 8;
+
+/// The size of every level of a pyramid over a depth buffer, from half its size down to 1x1
+fn level_sizes(dimensions: [u32; 2]) -> Vec<[u32; 2]> {
+    let mut sizes = Vec::new();
+    let [mut width, mut height] = dimensions;
+
+    while width > 1 || height > 1 {
+        width = ((width + 1) / 2).max(1);
+        height = ((height + 1) / 2).max(1);
+        sizes.push([width, height]);
+    }
+
+    sizes
+}
+
+/// A view of the frame the pyramid was built in
+#[derive(Debug, Clone, PartialEq)]
+pub struct HiZView {
+    /// The projection and view matrix of the view, with depth as written to the depth buffer
+    pub view_projection: Matrix4<f32>,
+    /// Where the view is in the depth buffer, as the offset and size in uv coordinates
+    pub viewport: [f32; 4],
+}
+
+/// A hierarchical Z-buffer, rebuilt from the depth buffer after the main pass of every frame
+/// while `RenderSettings::hi_z` is on
+///
+/// Each level holds the farthest depth of the texels it covers in the level above, the first
+/// level covering the depth buffer itself, where the farthest depth is the smallest if depth is
+/// reversed. A bounding box whose nearest depth is behind the level texels it overlaps is
+/// hidden, which the culling shader uses to skip objects hidden in the last frame, and which is
+/// useful for ray marching in screen space effects too.
+///
+/// vulkano has no storage images with mipmaps, so the levels are images of their own. Shaders
+/// bind them as an array of HI_Z_MAX_LEVELS samplers by including `shaders/hiz.glsl`, filled by
+/// `bound_levels`. Passes reading the levels in the same frame have to be recorded after the
+/// pyramid, and passes reading them in the next frame see the views of `views`.
+pub struct HiZPyramid {
+    device: Arc<Device>,
+    queue: Arc<Queue>,
+    /// The queue of the culling pass, which reads the levels
+    reader_queue: Arc<Queue>,
+    pipeline: Arc<dyn ComputePipelineAbstract + Send + Sync>,
+    descriptor_set_pool:
+        FixedSizeDescriptorSetsPool<Arc<dyn ComputePipelineAbstract + Send + Sync>>,
+    sampler: Arc<Sampler>,
+    /// The size of the depth buffer the levels were made for
+    dimensions: [u32; 2],
+    levels: Vec<Arc<StorageImage<Format>>>,
+    /// Bound in place of levels that have not been made yet, and never read
+    placeholder: Arc<StorageImage<Format>>,
+    /// The views of the last frame the pyramid was built in, none if it has not been built
+    views: Vec<HiZView>,
+    reversed_z: bool,
+}
+
+impl HiZPyramid {
+    /// Creates the pyramid, built on `queue` and read on `reader_queue` as well
+    pub fn new(
+        device: Arc<Device>,
+        queue: Arc<Queue>,
+        reader_queue: Arc<Queue>,
+        shaders: &ShaderSet,
+    ) -> Self {
+        let pipeline = Arc::new(
+            ComputePipeline::new(device.clone(), &shaders.hi_z.main_entry_point(), &())
+                .expect("Failed to create Hi-Z pipeline"),
+        ) as Arc<dyn ComputePipelineAbstract + Send + Sync>;
+
+        let descriptor_set_pool = FixedSizeDescriptorSetsPool::new(pipeline.clone(), 0);
+
+        // Texels are fetched directly, so neither filtering nor wrapping happens
+        let sampler = Sampler::new(
+            device.clone(),
+            Filter::Nearest,
+            Filter::Nearest,
+            MipmapMode::Nearest,
+            SamplerAddressMode::ClampToEdge,
+            SamplerAddressMode::ClampToEdge,
+            SamplerAddressMode::ClampToEdge,
+            0.0,
+            1.0,
+            0.0,
+            0.0,
+        )
+        .unwrap();
+
+        let placeholder = new_level(&device, &queue, &reader_queue, [1, 1]);
+
+        Self {
+            device,
+            queue,
+            reader_queue,
+            pipeline,
+            descriptor_set_pool,
+            sampler,
+            dimensions: [0, 0],
+            levels: Vec::new(),
+            placeholder,
+            views: Vec::new(),
+            reversed_z: false,
+        }
+    }
+
+    /// The levels of the pyramid, from half the size of the depth buffer down to 1x1
+    pub fn levels(&self) -> &[Arc<StorageImage<Format>>] {
+        &self.levels
+    }
+
+    /// The levels as bound by shaders including `shaders/hiz.glsl`, always HI_Z_MAX_LEVELS of
+    /// them
+    ///
+    /// Levels past the last are filled in with it, and levels past HI_Z_MAX_LEVELS are left out.
+    pub fn bound_levels(&self) -> Vec<Arc<StorageImage<Format>>> {
+        let last = self.levels.last().unwrap_or(&self.placeholder);
+
+        (0..HI_Z_MAX_LEVELS)
+            .map(|i| self.levels.get(i).unwrap_or(last).clone())
+            .collect()
+    }
+
+    /// Samples the levels texel by texel, without filtering
+    pub fn sampler(&self) -> Arc<Sampler> {
+        self.sampler.clone()
+    }
+
+    /// The views of the last frame the pyramid was built in
+    pub fn views(&self) -> &[HiZView] {
+        &self.views
+    }
+
+    /// Whether the depth in the pyramid is reversed
+    pub fn reversed_z(&self) -> bool {
+        self.reversed_z
+    }
+
+    /// Forgets the views the pyramid was built with, so nothing reads it until it is built again
+    pub fn invalidate(&mut self) {
+        self.views.clear();
+    }
+
+    /// Records building every level from `depth`, which has to have been written already
+    ///
+    /// The levels are remade whenever the depth buffer changes size. `reversed_z` has to match
+    /// how the depth buffer was written, and `views` are the projection and view matrices of the
+    /// views drawn into it, with their viewports.
+    pub fn record(
+        &mut self,
+        builder: AutoCommandBufferBuilder,
+        depth: Arc<AttachmentImage>,
+        reversed_z: bool,
+        views: &[(Matrix4<f32>, Viewport)],
+    ) -> AutoCommandBufferBuilder {
+        let dimensions = depth.dimensions();
+        if dimensions != self.dimensions {
+            self.recreate_levels(dimensions);
+        }
+
+        if self.levels.is_empty() {
+            self.views.clear();
+            return builder;
+        }
+
+        let [width, height] = [dimensions[0] as f32, dimensions[1] as f32];
+        self.views = views
+            .iter()
+            .map(|(view_projection, viewport)| HiZView {
+                view_projection: *view_projection,
+                viewport: [
+                    viewport.origin[0] / width,
+                    viewport.origin[1] / height,
+                    viewport.dimensions[0] / width,
+                    viewport.dimensions[1] / height,
+                ],
+            })
+            .collect();
+        self.reversed_z = reversed_z;
+
+        // The first level is built from the depth buffer, and every other from the one before it
+        let mut builder = builder;
+        let mut src_size = dimensions;
+        for (i, level) in self.levels.iter().enumerate() {
+            let descriptor_set = if i == 0 {
+                Arc::new(
+                    self.descriptor_set_pool
+                        .next()
+                        .add_sampled_image(depth.clone(), self.sampler.clone())
+                        .unwrap()
+                        .add_image(level.clone())
+                        .unwrap()
+                        .build()
+                        .unwrap(),
+                ) as Arc<dyn DescriptorSet + Send + Sync>
+            } else {
+                Arc::new(
+                    self.descriptor_set_pool
+                        .next()
+                        .add_sampled_image(self.levels[i - 1].clone(), self.sampler.clone())
+                        .unwrap()
+                        .add_image(level.clone())
+                        .unwrap()
+                        .build()
+                        .unwrap(),
+                ) as Arc<dyn DescriptorSet + Send + Sync>
+            };
+
+            let dst_size = level.dimensions().width_height();
+            let pc = HiZLevel {
+                src_size: [src_size[0] as i32, src_size[1] as i32],
+                dst_size: [dst_size[0] as i32, dst_size[1] as i32],
+                reversed_z: reversed_z as u32,
+            };
+
+            let work_groups = [
+                (dst_size[0] + WORK_GROUP_SIZE - 1) / WORK_GROUP_SIZE,
+                (dst_size[1] + WORK_GROUP_SIZE - 1) / WORK_GROUP_SIZE,
+                1,
+            ];
+
+            builder = builder
+                .dispatch(work_groups, self.pipeline.clone(), descriptor_set, pc)
+                .unwrap();
+
+            src_size = dst_size;
+        }
+
+        builder
+    }
+
+    fn recreate_levels(&mut self, dimensions: [u32; 2]) {
+        self.levels = level_sizes(dimensions)
+            .into_iter()
+            .map(|size| new_level(&self.device, &self.queue, &self.reader_queue, size))
+            .collect();
+
+        self.dimensions = dimensions;
+        self.views.clear();
+    }
+}
+
+/// Creates a level, shared by the queue building the pyramid and the queue reading it
+fn new_level(
+    device: &Arc<Device>,
+    queue: &Arc<Queue>,
+    reader_queue: &Arc<Queue>,
+    [width, height]: [u32; 2],
+) -> Arc<StorageImage<Format>> {
+    let usage = ImageUsage {
+        storage: true,
+        sampled: true,
+        ..ImageUsage::none()
+    };
+
+    let mut families = vec![queue.family()];
+    if reader_queue.family().id() != queue.family().id() {
+        families.push(reader_queue.family());
+    }
+
+    StorageImage::with_usage(
+        device.clone(),
+        Dimensions::Dim2d { width, height },
+        HI_Z_FORMAT,
+        usage,
+        families,
+    )
+    .expect("Failed to create Hi-Z level")
+}
+
+#[cfg(test)]
+mod test {
+    use super::level_sizes;
+
+    #[test]
+    fn levels() {
+        assert_eq!(level_sizes([8, 4]), vec![[4, 2], [2, 1], [1, 1]]);
+
+        // Odd sizes round up, so every texel is covered by the next level
+        assert_eq!(level_sizes([5, 1]), vec![[3, 1], [2, 1], [1, 1]]);
+        assert!(level_sizes([1, 1]).is_empty());
+    }
+}
@@ -0,0 +1,93 @@
+use std::{
+    ffi::{CStr, CString},
+    mem,
+    os::raw::{c_char, c_void},
+    ptr,
+    sync::Arc,
+};
+use vulkano::{
+    device::Device,
+    instance::{Instance, RawInstanceExtensions},
+    VulkanObject,
+};
+
+const DEBUG_UTILS: &str = "VK_EXT_debug_utils";
+const STRUCTURE_TYPE_DEBUG_UTILS_LABEL_EXT: u32 = 1_000_128_002;
+
+/// VkDebugUtilsLabelEXT, which vk-sys does not define
+#[repr(C)]
+struct DebugUtilsLabel {
+    s_type: u32,
+    p_next: *const c_void,
+    p_label_name: *const c_char,
+    color: [f32; 4],
+}
+
+type CmdBeginDebugUtilsLabel =
+    unsafe extern "system" fn(vk_sys::CommandBuffer, *const DebugUtilsLabel);
+type CmdEndDebugUtilsLabel = unsafe extern "system" fn(vk_sys::CommandBuffer);
+
+/// The instance extension needed for labels, if the debug-labels feature is enabled and the
+/// extension is supported
+pub fn instance_extension() -> Option<CString> {
+    if !cfg!(feature = "debug-labels") {
+        return None;
+    }
+
+    let extension = CString::new(DEBUG_UTILS).unwrap();
+    let supported = RawInstanceExtensions::supported_by_core().ok()?;
+
+    if supported.iter().any(|ext| ext == extension.as_c_str()) {
+        Some(extension)
+    } else {
+        None
+    }
+}
+
+/// Names sections of command buffers with VK_EXT_debug_utils labels, so captures in RenderDoc or
+/// Nsight show what each section is doing
+pub struct DebugLabels {
+    begin: CmdBeginDebugUtilsLabel,
+    end: CmdEndDebugUtilsLabel,
+}
+
+impl DebugLabels {
+    /// Loads the label commands, if the instance was created with the extension
+    pub fn new(instance: &Arc<Instance>, device: &Arc<Device>) -> Option<Self> {
+        instance_extension()?;
+
+        let load = |name: &[u8]| unsafe {
+            let name = CStr::from_bytes_with_nul(name).unwrap();
+            instance
+                .pointers()
+                .GetDeviceProcAddr(device.internal_object(), name.as_ptr())
+        };
+
+        // The extension is enabled, so the commands exist
+        unsafe {
+            Some(Self {
+                begin: mem::transmute(load(b"vkCmdBeginDebugUtilsLabelEXT\0")),
+                end: mem::transmute(load(b"vkCmdEndDebugUtilsLabelEXT\0")),
+            })
+        }
+    }
+
+    /// Records the start of a labeled section into a raw command buffer
+    pub unsafe fn begin(&self, command_buffer: vk_sys::CommandBuffer, name: &str, color: [f32; 4]) {
+        let name = CString::new(name).unwrap();
+        let label = DebugUtilsLabel {
+            s_type: STRUCTURE_TYPE_DEBUG_UTILS_LABEL_EXT,
+            p_next: ptr::null(),
+            p_label_name: name.as_ptr(),
+            color,
+        };
+
+        (self.begin)(command_buffer, &label);
+    }
+
+    /// Records the end of the innermost labeled section, which may have been started in an
+    /// earlier command buffer on the same queue
+    pub unsafe fn end(&self, command_buffer: vk_sys::CommandBuffer) {
+        (self.end)(command_buffer);
+    }
+}
@@ -0,0 +1,118 @@
+//! Checks of the memory layout of the structs shared with the shaders
+//!
+//! The structs in `shaders` are generated from the GLSL, padding included, and the engine fills
+//! them from its own types. If a shader struct changes, the engine would keep writing the old
+//! layout into the buffers, and the shader would read garbage without any error. These checks
+//! pin down the layout the engine was written against, so a change shows up as a failed
+//! assertion instead.
+
+use crate::renderer::shaders::{CullObject, DirectionalLight, Lights, PointLight};
+use std::mem;
+
+/// The offset of a field in a struct, in bytes
+macro_rules! offset_of {
+    ($ty:ty, $field:ident) => {{
+        // Every shader struct is plain numbers, for which all zeroes is a valid value
+        let value: $ty = unsafe { mem::zeroed() };
+        &value.$field as *const _ as usize - &value as *const $ty as usize
+    }};
+}
+
+/// Asserts the offset of every listed field of a struct
+macro_rules! check_offsets {
+    ($ty:ident { $($field:ident: $offset:expr),* $(,)* }) => {
+        $(
+            assert_eq!(
+                offset_of!($ty, $field),
+                $offset,
+                concat!(
+                    "Offset of ",
+                    stringify!($ty),
+                    "::",
+                    stringify!($field),
+                    " changed, update the code filling it"
+                )
+            );
+        )*
+    };
+}
+
+/// Panics if a shader struct is laid out differently than the engine expects
+///
+/// vec3s are aligned to 16 bytes, and a struct in an array is padded to 16 bytes too, by both
+/// std140 and std430. The sizes of structs in arrays are checked as well, since they are the
+/// stride of the array.
+pub fn check_layouts() {
+    check_offsets!(PointLight {
+        position: 0,
+        constant: 12,
+        linear: 16,
+        quadratic: 20,
+        ambient: 32,
+        diffuse: 48,
+        specular: 64,
+        range: 76,
+    });
+    assert_eq!(
+        mem::size_of::<PointLight>(),
+        80,
+        "Size of PointLight changed"
+    );
+
+    check_offsets!(DirectionalLight {
+        direction: 0,
+        ambient: 16,
+        diffuse: 32,
+        specular: 48,
+    });
+
+    check_offsets!(Lights {
+        ambient: 0,
+        dir_light: 16,
+    });
+
+    check_offsets!(CullObject {
+        sphere: 0,
+        draw: 16,
+    });
+    assert_eq!(
+        mem::size_of::<CullObject>(),
+        32,
+        "Size of CullObject changed"
+    );
+}
+
+#[cfg(test)]
+mod test {
+    use super::check_layouts;
+    use crate::renderer::{lights::PointLightComponent, shaders::PointLight};
+    use nalgebra::Vector3;
+    use std::{mem, slice};
+
+    #[test]
+    fn layouts() {
+        check_layouts();
+    }
+
+    #[test]
+    fn point_light_bytes() {
+        let light = PointLightComponent::from_color(Vector3::new(0.5, 0.25, 1.0))
+            .with_range(10.0)
+            .to_point_light(Vector3::new(1.0, 2.0, 3.0));
+
+        // Read back as the shader sees it, a float at every 4 bytes
+        let floats = unsafe {
+            slice::from_raw_parts(
+                &light as *const PointLight as *const f32,
+                mem::size_of::<PointLight>() / 4,
+            )
+        };
+
+        assert_eq!(&floats[0..3], &[1.0, 2.0, 3.0]);
+        assert_eq!(&floats[3..6], &[1.0, 0.09, 0.032]);
+        assert_eq!(&floats[8..11], &[0.5, 0.25, 1.0]);
+        assert_eq!(&floats[12..15], &[0.5, 0.25, 1.0]);
+        assert_eq!(&floats[16..19], &[1.0, 1.0, 1.0]);
+        assert_eq!(floats[19], 10.0);
+    }
+}
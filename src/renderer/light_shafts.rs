@@ -0,0 +1,269 @@
+use crate::renderer::{
+    post::SCENE_FORMAT,
+    settings::LightShaftSettings,
+    shaders::{LightShaftsPushConstants, PushConstants, ShaderSet},
+};
+use nalgebra::{Matrix4, Vector3, Vector4};
+use std::sync::Arc;
+use vulkano::{
+    command_buffer::{AutoCommandBufferBuilder, DynamicState},
+    descriptor::{descriptor_set::PersistentDescriptorSet, DescriptorSet},
+    device::Device,
+    format::ClearValue,
+    framebuffer::{Framebuffer, FramebufferAbstract, RenderPassAbstract, Subpass},
+    image::attachment::AttachmentImage,
+    pipeline::{
+        blend::{AttachmentBlend, BlendFactor, BlendOp},
+        vertex::{BufferlessDefinition, BufferlessVertices},
+        GraphicsPipeline, GraphicsPipelineAbstract,
+    },
+    sampler::{Filter, MipmapMode, Sampler, SamplerAddressMode},
+    single_pass_renderpass,
+};
+
+/// Light shafts from the directional light, added onto the lit scene after the main pass
+///
+/// Every pixel marches over the depth buffer towards the sun on the screen, and counts how much
+/// of the way is sky. Pixels near the edge of something in front of the sun pass over both, which
+/// draws shafts of light around it. The air scatters the light by the Henyey-Greenstein phase
+/// function, so the shafts are brightest looking towards the sun and fade looking away.
+pub struct LightShaftsPass {
+    render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+    pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+    sampler: Arc<Sampler>,
+    /// The scene color, drawn onto
+    framebuffer: Option<Arc<dyn FramebufferAbstract + Send + Sync>>,
+    /// The depth buffer of the main pass, marched over
+    descriptor_set: Option<Arc<dyn DescriptorSet + Send + Sync>>,
+}
+
+impl LightShaftsPass {
+    pub fn new(device: Arc<Device>, shaders: &ShaderSet) -> Self {
+        // The scene is drawn onto, so it is kept
+        let render_pass = Arc::new(
+            single_pass_renderpass!(device.clone(),
+                attachments: {
+                    color: {
+                        load: Load,
+                        store: Store,
+                        format: SCENE_FORMAT,
+                        samples: 1,
+                    }
+                },
+                pass: {
+                    color: [color],
+                    depth_stencil: {}
+                }
+            )
+            .unwrap(),
+        ) as Arc<dyn RenderPassAbstract + Send + Sync>;
+
+        let additive = AttachmentBlend {
+            enabled: true,
+            color_op: BlendOp::Add,
+            color_source: BlendFactor::One,
+            color_destination: BlendFactor::One,
+            alpha_op: BlendOp::Add,
+            alpha_source: BlendFactor::Zero,
+            alpha_destination: BlendFactor::One,
+            ..AttachmentBlend::pass_through()
+        };
+
+        let pipeline = Arc::new(
+            GraphicsPipeline::start()
+                .vertex_input(BufferlessDefinition)
+                .vertex_shader(shaders.fullscreen_vertex.main_entry_point(), ())
+                .triangle_list()
+                .viewports_scissors_dynamic(1)
+                .fragment_shader(shaders.light_shafts_fragment.main_entry_point(), ())
+                .blend_collective(additive)
+                .render_pass(Subpass::from(render_pass.clone(), 0).unwrap())
+                .build(device.clone())
+                .unwrap(),
+        ) as Arc<dyn GraphicsPipelineAbstract + Send + Sync>;
+
+        // Depth is compared exactly, so it is never filtered
+        let sampler = Sampler::new(
+            device,
+            Filter::Nearest,
+            Filter::Nearest,
+            MipmapMode::Nearest,
+            SamplerAddressMode::ClampToEdge,
+            SamplerAddressMode::ClampToEdge,
+            SamplerAddressMode::ClampToEdge,
+            0.0,
+            1.0,
+            0.0,
+            0.0,
+        )
+        .unwrap();
+
+        Self {
+            render_pass,
+            pipeline,
+            sampler,
+            framebuffer: None,
+            descriptor_set: None,
+        }
+    }
+
+    /// Recreates the framebuffer and descriptor set, after the scene images have been recreated
+    pub fn recreate_framebuffers(
+        &mut self,
+        scene: Arc<AttachmentImage>,
+        depth_buffer: Arc<AttachmentImage>,
+    ) {
+        self.framebuffer = Some(Arc::new(
+            Framebuffer::start(self.render_pass.clone())
+                .add(scene)
+                .unwrap()
+                .build()
+                .unwrap(),
+        ));
+
+        self.descriptor_set = Some(Arc::new(
+            PersistentDescriptorSet::start(self.pipeline.clone(), 0)
+                .add_sampled_image(depth_buffer, self.sampler.clone())
+                .unwrap()
+                .build()
+                .unwrap(),
+        ));
+    }
+
+    /// Records drawing the light shafts of every view, with the light shining in
+    /// `light_direction`
+    ///
+    /// `dimensions` is the size of the scene, and `far_depth` what the depth buffer was cleared
+    /// to. Nothing is drawn into views looking away from the sun.
+    pub fn draw(
+        &self,
+        builder: AutoCommandBufferBuilder,
+        views: &[(&DynamicState, PushConstants)],
+        dimensions: [u32; 2],
+        far_depth: f32,
+        light_direction: &Vector3<f32>,
+        light_color: &Vector3<f32>,
+        settings: &LightShaftSettings,
+    ) -> AutoCommandBufferBuilder {
+        let (framebuffer, descriptor_set) = match (&self.framebuffer, &self.descriptor_set) {
+            (Some(framebuffer), Some(descriptor_set)) => (framebuffer, descriptor_set),
+            _ => return builder,
+        };
+
+        let mut builder = builder
+            .begin_render_pass(framebuffer.clone(), false, vec![ClearValue::None])
+            .unwrap();
+
+        let [width, height] = [dimensions[0] as f32, dimensions[1] as f32];
+        let vertices = || BufferlessVertices {
+            vertices: 3,
+            instances: 1,
+        };
+
+        for (dynamic_state, pc) in views {
+            let sun = match sun_on_screen(pc, &-light_direction) {
+                Some(sun) => sun,
+                None => continue,
+            };
+
+            let viewport = &dynamic_state.viewports.as_ref().unwrap()[0];
+            let pc = LightShaftsPushConstants {
+                viewport: [
+                    viewport.origin[0] / width,
+                    viewport.origin[1] / height,
+                    viewport.dimensions[0] / width,
+                    viewport.dimensions[1] / height,
+                ],
+                sun: [sun.direction.x, sun.direction.y, sun.direction.z, 0.0],
+                light: [light_color.x, light_color.y, light_color.z, 0.0],
+                sun_uv: sun.uv,
+                tan_half_fov: sun.tan_half_fov,
+                density: settings.density,
+                scattering: settings.scattering,
+                far_depth,
+                samples: settings.samples,
+            };
+
+            builder = builder
+                .draw(
+                    self.pipeline.clone(),
+                    dynamic_state,
+                    vertices(),
+                    descriptor_set.clone(),
+                    pc,
+                )
+                .unwrap();
+        }
+
+        builder.end_render_pass().unwrap()
+    }
+}
+
+/// Where the sun is for a view
+#[derive(Debug, Clone, Copy)]
+struct Sun {
+    /// Towards the sun in view space
+    direction: Vector3<f32>,
+    /// Where the sun is in the view, in uv coordinates, which may be outside of it
+    uv: [f32; 2],
+    /// Tangent of half the field of view, horizontally and vertically
+    tan_half_fov: [f32; 2],
+}
+
+/// Where the sun, in world space direction `towards_sun`, is for the view of `pc`
+///
+/// None if the sun is behind the camera, or the projection is not perspective.
+fn sun_on_screen(pc: &PushConstants, towards_sun: &Vector3<f32>) -> Option<Sun> {
+    let view = Matrix4::from(pc.view);
+    let proj = Matrix4::from(pc.proj);
+
+    let direction = view.transform_vector(towards_sun).normalize();
+    if direction.z >= 0.0 || proj[(3, 2)] == 0.0 {
+        return None;
+    }
+
+    // A direction is a point infinitely far away, which w = 0 projects
+    let clip = proj * Vector4::new(direction.x, direction.y, direction.z, 0.0);
+    let ndc = [clip.x / clip.w, clip.y / clip.w];
+
+    Some(Sun {
+        direction,
+        uv: [ndc[0] * 0.5 + 0.5, ndc[1] * 0.5 + 0.5],
+        tan_half_fov: [1.0 / proj[(0, 0)], 1.0 / proj[(1, 1)].abs()],
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::sun_on_screen;
+    use crate::{
+        components::Transform,
+        renderer::{camera::Camera, shaders::PushConstants},
+    };
+    use nalgebra::Vector3;
+
+    #[test]
+    fn sun_position() {
+        let camera = Camera::new(1.0, std::f32::consts::FRAC_PI_2);
+        let transform = Transform::default();
+
+        for &reversed_z in &[false, true] {
+            let pc = PushConstants {
+                view: transform.to_view_matrix().into(),
+                proj: camera.depth_projection(reversed_z),
+            };
+
+            // Straight ahead is the middle of the view
+            let sun = sun_on_screen(&pc, &-Vector3::z()).unwrap();
+            assert!((sun.uv[0] - 0.5).abs() < 1e-4 && (sun.uv[1] - 0.5).abs() < 1e-4);
+            assert!((sun.tan_half_fov[0] - 1.0).abs() < 1e-4);
+
+            // Up and to the right is the top right, with y flipped by the projection
+            let sun = sun_on_screen(&pc, &Vector3::new(0.5, 0.5, -1.0)).unwrap();
+            assert!((sun.uv[0] - 0.75).abs() < 1e-4 && (sun.uv[1] - 0.25).abs() < 1e-4);
+
+            // Behind the camera
+            assert!(sun_on_screen(&pc, &Vector3::z()).is_none());
+        }
+    }
+}
@@ -1,6 +1,67 @@
-use crate::renderer::shaders::{DirectionalLight, PointLight};
+use crate::{
+    inspector::{format_vector3, parse_f32, parse_vector3, Inspect},
+    renderer::shaders::{DirectionalLight, PointLight},
+};
 use nalgebra::Vector3;
 use specs::prelude::*;
+use std::f32::consts::PI;
+
+/// How much of a light's intensity is left at the edge of its range, when the range is derived
+/// from its attenuation
+const RANGE_CUTOFF: f32 = 1.0 / 256.0;
+
+/// The distance at which the attenuation of a light with this intensity falls to RANGE_CUTOFF
+///
+/// Solves `intensity / (constant + linear * d + quadratic * d^2) = RANGE_CUTOFF` for d.
+pub fn attenuation_range(constant: f32, linear: f32, quadratic: f32, intensity: f32) -> f32 {
+    // Dark lights do not reach anywhere
+    if intensity <= 0.0 {
+        return 0.0;
+    }
+
+    let c = constant - intensity / RANGE_CUTOFF;
+
+    if quadratic > 0.0 {
+        (-linear + (linear * linear - 4.0 * quadratic * c).sqrt()) / (2.0 * quadratic)
+    } else if linear > 0.0 {
+        -c / linear
+    } else {
+        // Never fades, so it reaches everywhere
+        std::f32::MAX
+    }
+}
+
+/// Resource for the light reaching every surface equally, from no direction in particular
+///
+/// Set `dirty` after changing it, so the renderer uploads it again.
+#[derive(Debug)]
+pub struct AmbientLight {
+    pub color: Vector3<f32>,
+    pub intensity: f32,
+    pub dirty: bool,
+}
+
+impl Default for AmbientLight {
+    fn default() -> Self {
+        // As bright as the ambient term of the default directional light used to be
+        Self::new(Vector3::new(1.0, 1.0, 1.0), 0.1)
+    }
+}
+
+impl AmbientLight {
+    pub fn new(color: Vector3<f32>, intensity: f32) -> Self {
+        Self {
+            color,
+            intensity,
+            dirty: true,
+        }
+    }
+
+    /// Color in rgb and intensity in a, as the shader wants it
+    pub fn to_vec4(&self) -> [f32; 4] {
+        [self.color.x, self.color.y, self.color.z, self.intensity]
+    }
+}
 
 #[derive(Debug)]
 pub struct DirectionalLightRes {
@@ -38,6 +99,21 @@ impl DirectionalLightRes {
         }
     }
 
+    /// The direction the light shines in
+    pub fn direction(&self) -> Vector3<f32> {
+        self.direction
+    }
+
+    /// The color of the light, as it lights surfaces
+    pub fn color(&self) -> Vector3<f32> {
+        self.diffuse
+    }
+
+    pub fn set_direction(&mut self, direction: Vector3<f32>) {
+        self.direction = direction.normalize();
+        self.dirty = true;
+    }
+
     pub fn to_directional_light(&self) -> DirectionalLight {
         DirectionalLight {
             direction: self.direction.into(),
@@ -51,16 +127,54 @@ impl DirectionalLightRes {
     }
 }
 
-#[derive(Debug)]
+/// How the light of a point light falls off with distance, before it is windowed to its range
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Falloff {
+    /// Physically based, the intensity is in candela and falls off with the square of the
+    /// distance in meters
+    InverseSquare,
+    /// `1 / (constant + linear * d + quadratic * d^2)`, from before lights had units
+    Polynomial {
+        constant: f32,
+        linear: f32,
+        quadratic: f32,
+    },
+}
+
+impl Falloff {
+    /// The terms of the polynomial the shader attenuates with
+    fn terms(self) -> (f32, f32, f32) {
+        match self {
+            // Clamped near the light, so surfaces touching it are not infinitely bright
+            Falloff::InverseSquare => (MIN_DISTANCE * MIN_DISTANCE, 0.0, 1.0),
+            Falloff::Polynomial {
+                constant,
+                linear,
+                quadratic,
+            } => (constant, linear, quadratic),
+        }
+    }
+}
+
+/// Distance from an inverse square light, in meters, within which it stops getting brighter
+const MIN_DISTANCE: f32 = 0.1;
+
+/// A light shining equally in every direction from the position of its entity
+///
+/// The renderer treats an illuminance of one lux as a color value of one, so a light of one
+/// candela lights a white surface a meter away as brightly as the default sun. Every light fades
+/// smoothly to nothing at its range, which is derived from its intensity unless it is set.
+#[derive(Debug, Clone)]
 pub struct PointLightComponent {
-    // These determine the distance multiplier
-    constant: f32,
-    linear: f32,
-    quadratic: f32,
+    /// In candela for inverse square falloff, a multiplier of the color otherwise
+    intensity: f32,
+    falloff: Falloff,
     // The color of the light
     ambient: Vector3<f32>,
     diffuse: Vector3<f32>,
     specular: Vector3<f32>,
+    /// Where the light has faded out completely, so it can be skipped beyond
+    range: f32,
 }
 
 impl Component for PointLightComponent {
@@ -68,32 +182,217 @@ impl Component for PointLightComponent {
 }
 
 impl PointLightComponent {
+    /// A light with an intensity in candela, falling off with the square of the distance
+    pub fn new(color: Vector3<f32>, candela: f32) -> Self {
+        Self::with_falloff(color, candela, Falloff::InverseSquare)
+    }
+
+    /// A light with an output in lumens, spread evenly over every direction
+    pub fn from_lumens(color: Vector3<f32>, lumens: f32) -> Self {
+        Self::new(color, lumens / (4.0 * PI))
+    }
+
+    /// A light with the attenuation every light used to have, for scenes tuned with it
     pub fn from_color(color: Vector3<f32>) -> Self {
-        Self {
-            // Distance of 50
+        let falloff = Falloff::Polynomial {
             constant: 1.0,
             linear: 0.09,
             quadratic: 0.032,
-            // Scale the diffuse color for ambient
+        };
+
+        Self::with_falloff(color, 1.0, falloff)
+    }
+
+    fn with_falloff(color: Vector3<f32>, intensity: f32, falloff: Falloff) -> Self {
+        let mut light = Self {
+            intensity,
+            falloff,
             ambient: color,
             diffuse: color,
             specular: Vector3::new(1.0, 1.0, 1.0),
+            range: 0.0,
+        };
+        light.range = light.derived_range();
+        light
+    }
+
+    /// The distance at which the light has faded to RANGE_CUTOFF of its color
+    fn derived_range(&self) -> f32 {
+        let (constant, linear, quadratic) = self.falloff.terms();
+        let brightest = self.intensity * self.diffuse.amax();
+
+        attenuation_range(constant, linear, quadratic, brightest)
+    }
+
+    /// Sets the range by hand, the light fades smoothly to nothing at this distance
+    pub fn with_range(mut self, range: f32) -> Self {
+        self.range = range;
+        self
+    }
+
+    /// The same light in a scene scaled by `scale`, like a scene imported in other units
+    ///
+    /// Inverse square lights get brighter with the square of the scale, so surfaces at the scaled
+    /// distances are lit the same as before. The range is scaled along with the scene.
+    pub fn scaled(mut self, scale: f32) -> Self {
+        if self.falloff == Falloff::InverseSquare {
+            self.intensity *= scale * scale;
         }
+        self.range *= scale;
+        self
+    }
+
+    pub fn range(&self) -> f32 {
+        self.range
     }
 
     pub fn to_point_light(&self, position: Vector3<f32>) -> PointLight {
+        let (constant, linear, quadratic) = self.falloff.terms();
+
         PointLight {
             position: position.into(),
-            constant: self.constant,
-            linear: self.linear,
-            quadratic: self.quadratic,
+            constant,
+            linear,
+            quadratic,
             _dummy0: [0; 8],
-            ambient: self.ambient.into(),
-            diffuse: self.diffuse.into(),
-            specular: self.specular.into(),
+            ambient: (self.ambient * self.intensity).into(),
+            diffuse: (self.diffuse * self.intensity).into(),
+            specular: (self.specular * self.intensity).into(),
             _dummy1: [0; 4],
-            hh: 0,
+            range: self.range,
             _dummy2: [0; 4],
         }
     }
 }
+
+impl Inspect for PointLightComponent {
+    /// Falloff is `inverse_square`, or the constant, linear and quadratic terms
+    fn fields(&self) -> Vec<(&'static str, String)> {
+        let falloff = match self.falloff {
+            Falloff::InverseSquare => "inverse_square".to_string(),
+            Falloff::Polynomial {
+                constant,
+                linear,
+                quadratic,
+            } => format_vector3(&Vector3::new(constant, linear, quadratic)),
+        };
+
+        vec![
+            ("intensity", self.intensity.to_string()),
+            ("falloff", falloff),
+            ("ambient", format_vector3(&self.ambient)),
+            ("diffuse", format_vector3(&self.diffuse)),
+            ("specular", format_vector3(&self.specular)),
+            ("range", self.range.to_string()),
+        ]
+    }
+
+    /// Changing what the range is derived from derives it again, dropping a range set by hand
+    fn set_field(&mut self, field: &str, value: &str) -> Result<(), String> {
+        match field {
+            "intensity" => self.intensity = parse_f32(value)?,
+            "falloff" if value.trim() == "inverse_square" => self.falloff = Falloff::InverseSquare,
+            "falloff" => {
+                let terms = parse_vector3(value)?;
+                self.falloff = Falloff::Polynomial {
+                    constant: terms.x,
+                    linear: terms.y,
+                    quadratic: terms.z,
+                };
+            }
+            "ambient" => self.ambient = parse_vector3(value)?,
+            "diffuse" => self.diffuse = parse_vector3(value)?,
+            "specular" => self.specular = parse_vector3(value)?,
+            "range" => self.range = parse_f32(value)?,
+            _ => return Err(format!("PointLightComponent has no field {:?}", field)),
+        }
+
+        if let "intensity" | "falloff" | "diffuse" = field {
+            self.range = self.derived_range();
+        }
+
+        Ok(())
+    }
+}
+
+impl PointLight {
+    /// A light that does not contribute anything, for when the shader needs at least one light
+    pub fn none() -> Self {
+        PointLightComponent::from_color(Vector3::new(0.0, 0.0, 0.0))
+            .to_point_light(Vector3::new(0.0, 0.0, 0.0))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{attenuation_range, PointLightComponent, RANGE_CUTOFF};
+    use crate::inspector::Inspect;
+    use nalgebra::Vector3;
+    use std::f32::consts::PI;
+
+    fn attenuation(constant: f32, linear: f32, quadratic: f32, dist: f32) -> f32 {
+        1.0 / (constant + linear * dist + quadratic * dist * dist)
+    }
+
+    #[test]
+    fn range_reaches_cutoff() {
+        let range = attenuation_range(1.0, 0.09, 0.032, 1.0);
+        assert!((attenuation(1.0, 0.09, 0.032, range) - RANGE_CUTOFF).abs() < 1e-6);
+
+        // Brighter lights reach further
+        assert!(attenuation_range(1.0, 0.09, 0.032, 2.0) > range);
+
+        // Without a quadratic term
+        let range = attenuation_range(1.0, 0.5, 0.0, 1.0);
+        assert!((attenuation(1.0, 0.5, 0.0, range) - RANGE_CUTOFF).abs() < 1e-6);
+    }
+
+    /// Illuminance of a white surface facing the light, as the shader computes it
+    fn illuminance(light: &PointLightComponent, dist: f32) -> f32 {
+        let light = light.to_point_light(Vector3::zeros());
+        let falloff = 1.0 - (dist / light.range).powi(4);
+        let window = falloff.max(0.0).min(1.0);
+
+        light.diffuse[0]
+            * attenuation(light.constant, light.linear, light.quadratic, dist)
+            * window
+            * window
+    }
+
+    #[test]
+    fn inverse_square() {
+        let white = Vector3::new(1.0, 1.0, 1.0);
+        let light = PointLightComponent::new(white, 100.0);
+
+        // A quarter as bright at twice the distance, until the window closes in on the range
+        let ratio = illuminance(&light, 2.0) / illuminance(&light, 4.0);
+        assert!((ratio - 4.0).abs() < 0.01);
+        assert!((illuminance(&light, 2.0) - 25.0).abs() < 0.1);
+        assert_eq!(illuminance(&light, light.range()), 0.0);
+
+        let lumens = PointLightComponent::from_lumens(white, 400.0 * PI);
+        assert!((illuminance(&lumens, 2.0) - 25.0).abs() < 0.1);
+
+        // Twice the scene, the same light at twice the distance
+        let scaled = light.clone().scaled(2.0);
+        assert!((illuminance(&scaled, 4.0) - illuminance(&light, 2.0)).abs() < 0.1);
+        assert!((scaled.range() - light.range() * 2.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn inspected_range() {
+        let white = Vector3::new(1.0, 1.0, 1.0);
+        let mut light = PointLightComponent::new(white, 100.0).with_range(1.0);
+
+        light.set_field("intensity", "400").unwrap();
+        let range = PointLightComponent::new(white, 400.0).range();
+        assert!((light.range() - range).abs() < 1e-3);
+
+        light.set_field("falloff", "1, 0.09, 0.032").unwrap();
+        assert!(light.range() > range);
+        assert!((illuminance(&light, 0.0) - 400.0).abs() < 1e-2);
+
+        light.set_field("range", "5").unwrap();
+        assert_eq!(light.range(), 5.0);
+    }
+}
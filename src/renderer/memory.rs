@@ -0,0 +1,712 @@
+use crate::renderer::frame::FRAMES_IN_FLIGHT;
+use std::{
+    marker::PhantomData,
+    mem,
+    ops::Range,
+    ptr,
+    sync::{Arc, Mutex, Weak},
+};
+use vulkano::{
+    buffer::{
+        sys::{BufferCreationError, SparseLevel, UnsafeBuffer},
+        BufferAccess, BufferInner, BufferUsage, CpuAccessibleBuffer, DeviceLocalBuffer,
+        TypedBufferAccess,
+    },
+    device::{Device, DeviceOwned, Queue},
+    instance::QueueFamily,
+    memory::{CpuAccess, DeviceMemory, DeviceMemoryAllocError, MappedDeviceMemory},
+    sync::{AccessError, Sharing},
+};
+
+/// Size of the blocks meshes are sub-allocated from
+const BLOCK_SIZE: usize = 32 * 1024 * 1024;
+
+/// Size of the chunks of the per-frame arenas
+const ARENA_CHUNK_SIZE: usize = 4 * 1024 * 1024;
+
+/// Every sub-allocation starts at a multiple of this, which is the largest offset alignment
+/// Vulkan allows a device to ask for, for any kind of buffer
+const ALIGNMENT: usize = 256;
+
+/// What a buffer holds, to break the memory usage down by
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryUse {
+    /// Vertices and indices of meshes, the static batch and normal lines
+    Mesh,
+    /// Data kept around for the scene, like lights, batch transforms and draw commands
+    Scene,
+    /// Staging and readback buffers, only needed until the frame that made them is done
+    Transient,
+}
+
+/// Buffer memory in use, as of the last frame
+#[derive(Debug, Default, Clone)]
+pub struct MemoryStats {
+    /// Buffers that are still alive, sub-allocated or not
+    pub buffers: usize,
+    pub mesh_bytes: usize,
+    pub scene_bytes: usize,
+    pub transient_bytes: usize,
+    /// Blocks allocated for sub-allocating meshes from
+    pub blocks: usize,
+    pub block_bytes: usize,
+    /// Bytes of the blocks that are not sub-allocated
+    pub free_bytes: usize,
+    /// The largest sub-allocation the blocks have room for
+    pub largest_free: usize,
+    /// Bytes of the chunks of every per-frame arena
+    pub arena_bytes: usize,
+}
+
+impl MemoryStats {
+    pub fn total_bytes(&self) -> usize {
+        self.mesh_bytes + self.scene_bytes + self.transient_bytes
+    }
+
+    /// How much of the free memory in the blocks is split into smaller ranges, from 0 when it is
+    /// all in one range to almost 1 when it is scattered
+    pub fn fragmentation(&self) -> f32 {
+        if self.free_bytes == 0 {
+            0.0
+        } else {
+            1.0 - self.largest_free as f32 / self.free_bytes as f32
+        }
+    }
+}
+
+struct Allocation {
+    memory_use: MemoryUse,
+    size: usize,
+    buffer: Weak<dyn BufferAccess + Send + Sync>,
+}
+
+/// Creates every buffer of the renderer, and counts the memory they use by MemoryUse
+///
+/// Mesh data is sub-allocated from large blocks of host visible memory, device local when the
+/// device has any, which are shared by every mesh. Per-frame data like staging buffers and
+/// streamed vertices comes from the arena of the frame being recorded, which is reset once the
+/// fence of that frame has been waited on. Everything else, like buffers the CPU writes to or
+/// reads back later, still gets memory of its own from vulkano's standard pool.
+///
+/// A buffer is counted until its last reference is dropped, wherever that happens.
+#[derive(Clone)]
+pub struct BufferAllocator {
+    device: Arc<Device>,
+    allocations: Arc<Mutex<Vec<Allocation>>>,
+    blocks: Arc<Mutex<Vec<Arc<Block>>>>,
+    arenas: Arc<Mutex<FrameArenas>>,
+}
+
+impl BufferAllocator {
+    pub fn new(device: Arc<Device>) -> Self {
+        Self {
+            device,
+            allocations: Arc::new(Mutex::new(Vec::new())),
+            blocks: Arc::new(Mutex::new(Vec::new())),
+            arenas: Arc::new(Mutex::new(FrameArenas::new())),
+        }
+    }
+
+    /// A host visible buffer filled from an iterator
+    pub fn from_iter<T, I>(
+        &self,
+        memory_use: MemoryUse,
+        usage: BufferUsage,
+        data: I,
+    ) -> Result<Arc<CpuAccessibleBuffer<[T]>>, DeviceMemoryAllocError>
+    where
+        T: Send + Sync + 'static,
+        I: ExactSizeIterator<Item = T>,
+    {
+        let buffer = CpuAccessibleBuffer::from_iter(self.device.clone(), usage, data)?;
+        self.track(memory_use, buffer.clone());
+        Ok(buffer)
+    }
+
+    /// A host visible buffer holding a single value
+    pub fn from_data<T>(
+        &self,
+        memory_use: MemoryUse,
+        usage: BufferUsage,
+        data: T,
+    ) -> Result<Arc<CpuAccessibleBuffer<T>>, DeviceMemoryAllocError>
+    where
+        T: Send + Sync + 'static,
+    {
+        let buffer = CpuAccessibleBuffer::from_data(self.device.clone(), usage, data)?;
+        self.track(memory_use, buffer.clone());
+        Ok(buffer)
+    }
+
+    /// A host visible array whose contents are undefined until written, by the GPU usually
+    pub unsafe fn uninitialized_array<T>(
+        &self,
+        memory_use: MemoryUse,
+        len: usize,
+        usage: BufferUsage,
+    ) -> Result<Arc<CpuAccessibleBuffer<[T]>>, DeviceMemoryAllocError>
+    where
+        T: Send + Sync + 'static,
+    {
+        let buffer = CpuAccessibleBuffer::uninitialized_array(self.device.clone(), len, usage)?;
+        self.track(memory_use, buffer.clone());
+        Ok(buffer)
+    }
+
+    /// A device local array, shared by the given queue families
+    pub fn device_local_array<'a, T, I>(
+        &self,
+        memory_use: MemoryUse,
+        len: usize,
+        usage: BufferUsage,
+        queue_families: I,
+    ) -> Result<Arc<DeviceLocalBuffer<[T]>>, DeviceMemoryAllocError>
+    where
+        T: Send + Sync + 'static,
+        I: IntoIterator<Item = QueueFamily<'a>>,
+    {
+        let buffer = DeviceLocalBuffer::array(self.device.clone(), len, usage, queue_families)?;
+        self.track(memory_use, buffer.clone());
+        Ok(buffer)
+    }
+
+    /// An array sub-allocated from a shared block, filled from an iterator and never written
+    /// again
+    ///
+    /// Transient arrays come from the arena of the frame being recorded, and everything else from
+    /// the blocks meshes are kept in. The range goes back to its block when the buffer is dropped.
+    /// Blocks can be used as any kind of buffer, within the queue family of the graphics queue.
+    pub fn sub_array<T, I>(
+        &self,
+        memory_use: MemoryUse,
+        data: I,
+    ) -> Result<Arc<SubBuffer<[T]>>, DeviceMemoryAllocError>
+    where
+        T: Send + Sync + 'static,
+        I: ExactSizeIterator<Item = T>,
+    {
+        let size = data.len() * mem::size_of::<T>();
+
+        let (block, range) = match memory_use {
+            MemoryUse::Transient => self.arenas.lock().unwrap().allocate(&self.device, size)?,
+            MemoryUse::Mesh | MemoryUse::Scene => self.allocate_from_blocks(size)?,
+        };
+
+        // Nothing else refers to the range yet, and the memory is host coherent
+        if size > 0 {
+            unsafe {
+                let mut mapping = block
+                    .memory
+                    .read_write::<[T]>(range.start..range.start + size);
+                for (slot, value) in mapping.iter_mut().zip(data) {
+                    ptr::write(slot, value);
+                }
+            }
+        }
+
+        let buffer = Arc::new(SubBuffer {
+            block,
+            range,
+            size,
+            gpu_lock: Mutex::new(GpuLock::None),
+            marker: PhantomData,
+        });
+
+        self.track(memory_use, buffer.clone());
+        Ok(buffer)
+    }
+
+    /// Starts recording a frame, whose fence has to have been waited on
+    ///
+    /// The chunks of the frame's arena are reused from the start, except for those still
+    /// referred to by buffers made for an older frame. Those are left to be freed with their
+    /// buffers. Blocks with nothing sub-allocated from them are freed as well, except for one
+    /// kept for the next meshes.
+    pub fn begin_frame(&self, frame_index: usize) {
+        self.arenas.lock().unwrap().begin(frame_index);
+
+        let mut kept_empty = false;
+        self.blocks.lock().unwrap().retain(|block| {
+            let empty = block.ranges.lock().unwrap().is_empty();
+            let keep = !empty || !kept_empty;
+            kept_empty |= empty;
+            keep
+        });
+    }
+
+    /// The memory used by the buffers that are still alive
+    ///
+    /// Buffers that have been dropped since the last call are forgotten.
+    pub fn stats(&self) -> MemoryStats {
+        let mut allocations = self.allocations.lock().unwrap();
+        allocations.retain(|allocation| allocation.buffer.upgrade().is_some());
+
+        let mut stats = allocations
+            .iter()
+            .fold(MemoryStats::default(), |mut stats, allocation| {
+                stats.buffers += 1;
+                match allocation.memory_use {
+                    MemoryUse::Mesh => stats.mesh_bytes += allocation.size,
+                    MemoryUse::Scene => stats.scene_bytes += allocation.size,
+                    MemoryUse::Transient => stats.transient_bytes += allocation.size,
+                }
+                stats
+            });
+
+        for block in self.blocks.lock().unwrap().iter() {
+            let ranges = block.ranges.lock().unwrap();
+            stats.blocks += 1;
+            stats.block_bytes += block.size;
+            stats.free_bytes += ranges.free_bytes();
+            stats.largest_free = stats.largest_free.max(ranges.largest_free());
+        }
+
+        stats.arena_bytes = self.arenas.lock().unwrap().bytes();
+        stats
+    }
+
+    /// Takes a range out of the first block with room for it, or out of a new block
+    fn allocate_from_blocks(
+        &self,
+        size: usize,
+    ) -> Result<(Arc<Block>, Range<usize>), DeviceMemoryAllocError> {
+        let aligned = align(size.max(1));
+        let mut blocks = self.blocks.lock().unwrap();
+
+        for block in blocks.iter() {
+            if let Some(start) = block.ranges.lock().unwrap().allocate(aligned) {
+                return Ok((block.clone(), start..start + aligned));
+            }
+        }
+
+        // Meshes bigger than a block get a block of their own
+        let block = Arc::new(Block::new(
+            &self.device,
+            BLOCK_SIZE.max(aligned),
+            BlockRanges::Free(FreeList::new(BLOCK_SIZE.max(aligned))),
+            true,
+        )?);
+        let start = block.ranges.lock().unwrap().allocate(aligned).unwrap();
+        blocks.push(block.clone());
+
+        Ok((block, start..start + aligned))
+    }
+
+    fn track<B>(&self, memory_use: MemoryUse, buffer: Arc<B>)
+    where
+        B: BufferAccess + Send + Sync + 'static,
+    {
+        let size = buffer.size();
+        let buffer = Arc::downgrade(&(buffer as Arc<dyn BufferAccess + Send + Sync>));
+
+        self.allocations.lock().unwrap().push(Allocation {
+            memory_use,
+            size,
+            buffer,
+        });
+    }
+}
+
+/// A large buffer bound to memory of its own, which buffers are sub-allocated from
+struct Block {
+    // Dropped before the memory it is bound to
+    buffer: UnsafeBuffer,
+    memory: MappedDeviceMemory,
+    size: usize,
+    ranges: Mutex<BlockRanges>,
+}
+
+/// How the ranges of a block are handed out
+enum BlockRanges {
+    /// Ranges are taken from a free list and given back when their buffer is dropped
+    Free(FreeList),
+    /// Ranges follow each other, and are all given back at once when the arena is reset
+    Arena { next: usize },
+}
+
+impl BlockRanges {
+    fn allocate(&mut self, size: usize) -> Option<usize> {
+        match self {
+            BlockRanges::Free(free) => free.allocate(size),
+            BlockRanges::Arena { next } => {
+                let start = *next;
+                *next += size;
+                Some(start)
+            }
+        }
+    }
+
+    fn free(&mut self, range: Range<usize>) {
+        if let BlockRanges::Free(free) = self {
+            free.free(range);
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        match self {
+            BlockRanges::Free(free) => free.is_empty(),
+            BlockRanges::Arena { next } => *next == 0,
+        }
+    }
+
+    fn free_bytes(&self) -> usize {
+        match self {
+            BlockRanges::Free(free) => free.free_bytes(),
+            BlockRanges::Arena { .. } => 0,
+        }
+    }
+
+    fn largest_free(&self) -> usize {
+        match self {
+            BlockRanges::Free(free) => free.largest_free(),
+            BlockRanges::Arena { .. } => 0,
+        }
+    }
+}
+
+impl Block {
+    /// Allocates a block of `size` bytes of host visible and coherent memory
+    ///
+    /// With `prefer_device_local`, memory that is device local as well is used if there is any.
+    fn new(
+        device: &Arc<Device>,
+        size: usize,
+        ranges: BlockRanges,
+        prefer_device_local: bool,
+    ) -> Result<Self, DeviceMemoryAllocError> {
+        let (buffer, requirements) = unsafe {
+            match UnsafeBuffer::new(
+                device.clone(),
+                size,
+                BufferUsage::all(),
+                Sharing::Exclusive::<std::iter::Empty<u32>>,
+                SparseLevel::none(),
+            ) {
+                Ok(buffer) => buffer,
+                Err(BufferCreationError::AllocError(err)) => return Err(err),
+                Err(err) => panic!("Failed to create memory block buffer: {:?}", err),
+            }
+        };
+
+        let physical = device.physical_device();
+        let candidates = || {
+            physical.memory_types().filter(|memory_type| {
+                requirements.memory_type_bits & (1 << memory_type.id()) != 0
+                    && memory_type.is_host_visible()
+                    && memory_type.is_host_coherent()
+            })
+        };
+
+        let memory_type = candidates()
+            .find(|memory_type| memory_type.is_device_local() == prefer_device_local)
+            .or_else(|| candidates().next())
+            .expect("No host visible and coherent memory for buffers");
+
+        let memory = DeviceMemory::alloc_and_map(device.clone(), memory_type, requirements.size)?;
+        unsafe {
+            buffer.bind_memory(memory.as_ref(), 0)?;
+        }
+
+        Ok(Self {
+            buffer,
+            memory,
+            size,
+            ranges: Mutex::new(ranges),
+        })
+    }
+}
+
+/// The chunks transient buffers are sub-allocated from, one set per frame in flight
+struct FrameArenas {
+    frames: Vec<Vec<Arc<Block>>>,
+    current: usize,
+}
+
+impl FrameArenas {
+    fn new() -> Self {
+        Self {
+            frames: (0..FRAMES_IN_FLIGHT).map(|_| Vec::new()).collect(),
+            current: 0,
+        }
+    }
+
+    fn begin(&mut self, frame_index: usize) {
+        self.current = frame_index;
+
+        // Chunks bigger than usual were made for a single big buffer, and are not kept around
+        self.frames[frame_index]
+            .retain(|chunk| Arc::strong_count(chunk) == 1 && chunk.size == ARENA_CHUNK_SIZE);
+
+        for chunk in &self.frames[frame_index] {
+            *chunk.ranges.lock().unwrap() = BlockRanges::Arena { next: 0 };
+        }
+    }
+
+    fn allocate(
+        &mut self,
+        device: &Arc<Device>,
+        size: usize,
+    ) -> Result<(Arc<Block>, Range<usize>), DeviceMemoryAllocError> {
+        let aligned = align(size.max(1));
+        let chunks = &mut self.frames[self.current];
+
+        for chunk in chunks.iter() {
+            let mut ranges = chunk.ranges.lock().unwrap();
+            if let BlockRanges::Arena { next } = *ranges {
+                if next + aligned <= chunk.size {
+                    let start = ranges.allocate(aligned).unwrap();
+                    return Ok((chunk.clone(), start..start + aligned));
+                }
+            }
+        }
+
+        let chunk = Arc::new(Block::new(
+            device,
+            ARENA_CHUNK_SIZE.max(aligned),
+            BlockRanges::Arena { next: aligned },
+            false,
+        )?);
+        chunks.push(chunk.clone());
+
+        Ok((chunk, 0..aligned))
+    }
+
+    fn bytes(&self) -> usize {
+        self.frames
+            .iter()
+            .flat_map(|chunks| chunks.iter())
+            .map(|chunk| chunk.size)
+            .sum()
+    }
+}
+
+/// The free ranges of a block, sorted by where they start and merged with their neighbours
+#[derive(Debug, Clone, PartialEq)]
+struct FreeList {
+    size: usize,
+    ranges: Vec<Range<usize>>,
+}
+
+impl FreeList {
+    fn new(size: usize) -> Self {
+        Self {
+            size,
+            ranges: vec![0..size],
+        }
+    }
+
+    /// Takes `size` bytes from the start of the first range big enough for them
+    fn allocate(&mut self, size: usize) -> Option<usize> {
+        let index = self
+            .ranges
+            .iter()
+            .position(|range| range.end - range.start >= size)?;
+
+        let start = self.ranges[index].start;
+        self.ranges[index].start += size;
+        if self.ranges[index].start == self.ranges[index].end {
+            self.ranges.remove(index);
+        }
+
+        Some(start)
+    }
+
+    /// Gives a range back, merging it with the free ranges on either side of it
+    fn free(&mut self, range: Range<usize>) {
+        let index = self
+            .ranges
+            .iter()
+            .position(|free| free.start > range.start)
+            .unwrap_or_else(|| self.ranges.len());
+
+        let merges_before = index > 0 && self.ranges[index - 1].end == range.start;
+        let merges_after = index < self.ranges.len() && self.ranges[index].start == range.end;
+
+        match (merges_before, merges_after) {
+            (true, true) => {
+                self.ranges[index - 1].end = self.ranges[index].end;
+                self.ranges.remove(index);
+            }
+            (true, false) => self.ranges[index - 1].end = range.end,
+            (false, true) => self.ranges[index].start = range.start,
+            (false, false) => self.ranges.insert(index, range),
+        }
+    }
+
+    /// Is nothing allocated from the block?
+    fn is_empty(&self) -> bool {
+        self.ranges == [0..self.size]
+    }
+
+    fn free_bytes(&self) -> usize {
+        self.ranges
+            .iter()
+            .map(|range| range.end - range.start)
+            .sum()
+    }
+
+    fn largest_free(&self) -> usize {
+        self.ranges
+            .iter()
+            .map(|range| range.end - range.start)
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+/// Rounds a size up to the alignment of every sub-allocation
+fn align(size: usize) -> usize {
+    (size + ALIGNMENT - 1) / ALIGNMENT * ALIGNMENT
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum GpuLock {
+    None,
+    Shared(usize),
+    Exclusive(usize),
+}
+
+/// A buffer sub-allocated from a block by the BufferAllocator
+///
+/// Its range of the block is locked for the GPU on its own, so buffers sharing a block can be used
+/// by different submissions at once.
+pub struct SubBuffer<T: ?Sized> {
+    block: Arc<Block>,
+    /// The range taken from the block, which may be longer than the buffer
+    range: Range<usize>,
+    size: usize,
+    gpu_lock: Mutex<GpuLock>,
+    marker: PhantomData<Box<T>>,
+}
+
+impl<T> SubBuffer<[T]> {
+    /// Maps the contents for reading
+    ///
+    /// Sub-allocated buffers are only written when they are created, so reading is always safe.
+    pub fn read(&self) -> CpuAccess<'_, [T]> {
+        unsafe {
+            self.block
+                .memory
+                .read_write::<[T]>(self.range.start..self.range.start + self.size)
+        }
+    }
+}
+
+impl<T: ?Sized> Drop for SubBuffer<T> {
+    fn drop(&mut self) {
+        self.block.ranges.lock().unwrap().free(self.range.clone());
+    }
+}
+
+unsafe impl<T: ?Sized> BufferAccess for SubBuffer<T>
+where
+    T: Send + Sync,
+{
+    fn inner(&self) -> BufferInner {
+        BufferInner {
+            buffer: &self.block.buffer,
+            offset: self.range.start,
+        }
+    }
+
+    fn size(&self) -> usize {
+        self.size
+    }
+
+    fn conflict_key(&self) -> (u64, usize) {
+        (self.block.buffer.key(), self.range.start)
+    }
+
+    fn try_gpu_lock(&self, exclusive_access: bool, _: &Queue) -> Result<(), AccessError> {
+        let mut lock = self.gpu_lock.lock().unwrap();
+
+        *lock = match (*lock, exclusive_access) {
+            (GpuLock::None, false) => GpuLock::Shared(1),
+            (GpuLock::None, true) => GpuLock::Exclusive(1),
+            (GpuLock::Shared(count), false) => GpuLock::Shared(count + 1),
+            _ => return Err(AccessError::AlreadyInUse),
+        };
+
+        Ok(())
+    }
+
+    unsafe fn increase_gpu_lock(&self) {
+        let mut lock = self.gpu_lock.lock().unwrap();
+
+        *lock = match *lock {
+            GpuLock::Shared(count) => GpuLock::Shared(count + 1),
+            GpuLock::Exclusive(count) => GpuLock::Exclusive(count + 1),
+            GpuLock::None => panic!("Increased the GPU lock of an unlocked buffer"),
+        };
+    }
+
+    unsafe fn unlock(&self) {
+        let mut lock = self.gpu_lock.lock().unwrap();
+
+        *lock = match *lock {
+            GpuLock::Shared(1) | GpuLock::Exclusive(1) => GpuLock::None,
+            GpuLock::Shared(count) => GpuLock::Shared(count - 1),
+            GpuLock::Exclusive(count) => GpuLock::Exclusive(count - 1),
+            GpuLock::None => panic!("Unlocked a buffer that was not locked"),
+        };
+    }
+}
+
+unsafe impl<T: ?Sized> TypedBufferAccess for SubBuffer<T>
+where
+    T: Send + Sync,
+{
+    type Content = T;
+}
+
+unsafe impl<T: ?Sized> DeviceOwned for SubBuffer<T> {
+    fn device(&self) -> &Arc<Device> {
+        self.block.buffer.device()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{align, FreeList, MemoryStats, ALIGNMENT};
+
+    #[test]
+    fn free_list() {
+        let mut free = FreeList::new(1024);
+        assert_eq!(free.allocate(256), Some(0));
+        assert_eq!(free.allocate(256), Some(256));
+        assert_eq!(free.allocate(256), Some(512));
+        assert_eq!(free.allocate(512), None);
+
+        // A hole is reused by the next allocation that fits in it
+        free.free(256..512);
+        assert_eq!(free.free_bytes(), 512);
+        assert_eq!(free.largest_free(), 256);
+        assert_eq!(free.allocate(256), Some(256));
+
+        // Freed ranges are merged with their neighbours on both sides
+        free.free(0..256);
+        free.free(512..768);
+        assert_eq!(free.largest_free(), 256);
+        free.free(256..512);
+        assert!(free.is_empty());
+        assert_eq!(free.allocate(1024), Some(0));
+    }
+
+    #[test]
+    fn alignment() {
+        assert_eq!(align(1), ALIGNMENT);
+        assert_eq!(align(ALIGNMENT), ALIGNMENT);
+        assert_eq!(align(ALIGNMENT + 1), 2 * ALIGNMENT);
+    }
+
+    #[test]
+    fn fragmentation() {
+        let stats = MemoryStats {
+            free_bytes: 1024,
+            largest_free: 256,
+            ..MemoryStats::default()
+        };
+        assert_eq!(stats.fragmentation(), 0.75);
+        assert_eq!(MemoryStats::default().fragmentation(), 0.0);
+    }
+}
@@ -0,0 +1,129 @@
+use crate::renderer::geometry::{Bounds, MeshBuilder};
+use specs::{world::EntitiesRes, Entity};
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::mpsc::{channel, Receiver, Sender},
+    thread::{self, JoinHandle},
+};
+
+/// A mesh sent to the worker, and the sequence number it was sent with
+type Job = (Entity, u64, MeshBuilder);
+
+/// A generated mesh, ready to be uploaded
+type Generated = (Entity, u64, MeshBuilder, Bounds);
+
+/// Generates the meshes of MeshBuilders on a worker thread
+///
+/// Generating big shapes and CSG operations can take several frames, so the renderer sends
+/// builders here and uploads whatever has finished at the start of later frames. If an entity is
+/// given a new builder before its last one is done, the older mesh is thrown away.
+pub struct MeshWorker {
+    sender: Sender<Job>,
+    receiver: Receiver<Generated>,
+    /// Finished meshes that did not fit into the frame they were finished in
+    ready: VecDeque<Generated>,
+    /// The sequence number of the latest builder of every entity waiting on the worker
+    pending: HashMap<Entity, u64>,
+    sequence: u64,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl MeshWorker {
+    pub fn new() -> Self {
+        let (sender, jobs) = channel::<Job>();
+        let (results, receiver) = channel();
+
+        // Returns once the worker, and with it the sender, is dropped
+        let worker = thread::spawn(move || {
+            for (entity, sequence, builder) in jobs {
+                let builder = builder.generated();
+                let bounds = builder.bounds();
+
+                if results.send((entity, sequence, builder, bounds)).is_err() {
+                    return;
+                }
+            }
+        });
+
+        Self {
+            sender,
+            receiver,
+            ready: VecDeque::new(),
+            pending: HashMap::new(),
+            sequence: 0,
+            worker: Some(worker),
+        }
+    }
+
+    /// Queues a builder to be generated, replacing any builder of the entity still in flight
+    pub fn submit(&mut self, entity: Entity, builder: MeshBuilder) {
+        self.sequence += 1;
+        self.pending.insert(entity, self.sequence);
+
+        self.sender
+            .send((entity, self.sequence, builder))
+            .expect("The mesh worker has stopped");
+    }
+
+    /// Forgets the meshes of entities that have been deleted, including those still in flight
+    pub fn forget_dead(&mut self, entities: &EntitiesRes) {
+        self.pending.retain(|entity, _| entities.is_alive(*entity));
+        self.ready
+            .retain(|(entity, _, _, _)| entities.is_alive(*entity));
+    }
+
+    /// Takes up to `max` generated meshes, in the order they were finished
+    ///
+    /// Stops early at the first mesh `fits` returns false for, which is kept for a later frame.
+    pub fn take<F>(&mut self, max: usize, mut fits: F) -> Vec<(Entity, MeshBuilder, Bounds)>
+    where
+        F: FnMut(&MeshBuilder) -> bool,
+    {
+        self.ready.extend(self.receiver.try_iter());
+
+        let mut taken = Vec::new();
+
+        while taken.len() < max {
+            let (entity, sequence) = match self.ready.front() {
+                Some((entity, sequence, _, _)) => (*entity, *sequence),
+                None => break,
+            };
+
+            // Replaced by a newer builder, which is still on its way
+            if self.pending.get(&entity) != Some(&sequence) {
+                self.ready.pop_front();
+                continue;
+            }
+
+            if !fits(&self.ready.front().unwrap().2) {
+                break;
+            }
+
+            let (entity, _, builder, bounds) = self.ready.pop_front().unwrap();
+            self.pending.remove(&entity);
+            taken.push((entity, builder, bounds));
+        }
+
+        taken
+    }
+}
+
+impl Default for MeshWorker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for MeshWorker {
+    /// Waits for the worker, so it does not outlive the renderer
+    fn drop(&mut self) {
+        // Closing both channels stops the worker after the mesh it is generating, instead of
+        // after every queued one
+        self.sender = channel().0;
+        self.receiver = channel().1;
+
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
@@ -0,0 +1,137 @@
+use crate::renderer::camera::Viewport;
+use specs::{Component, HashMapStorage};
+use specs_derive::Component;
+use std::sync::Arc;
+use vulkano::{
+    device::Device,
+    framebuffer::{Framebuffer, FramebufferAbstract, RenderPassAbstract},
+    image::attachment::AttachmentImage,
+};
+
+/// Renders the view of a camera into the minimap, shown in a corner of the screen over the
+/// active cameras
+///
+/// The camera should be orthographic, and is turned to look straight down by the
+/// MinimapSystem, which keeps it over the active camera while following. Only the first camera
+/// with a minimap is rendered.
+#[derive(Component, Debug, Clone)]
+#[storage(HashMapStorage)]
+pub struct Minimap {
+    /// Where the minimap is shown on the screen. The scissor is ignored
+    pub corner: Viewport,
+    /// Half the height of the area shown, in world units, smaller zooms in
+    pub zoom: f32,
+    /// Whether the minimap follows the active camera around, or stays where it is
+    pub follow: bool,
+    /// How high above the active camera the minimap looks down from while following
+    pub height: f32,
+}
+
+impl Minimap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_corner(mut self, corner: Viewport) -> Self {
+        self.corner = corner;
+        self
+    }
+
+    pub fn with_zoom(mut self, zoom: f32) -> Self {
+        self.zoom = zoom;
+        self
+    }
+
+    /// Stays where the camera is put, instead of following the active camera
+    pub fn fixed(mut self) -> Self {
+        self.follow = false;
+        self
+    }
+}
+
+impl Default for Minimap {
+    fn default() -> Self {
+        Self {
+            corner: Viewport::new(0.78, 0.02, 0.2, 0.2),
+            zoom: 25.0,
+            follow: true,
+            height: 50.0,
+        }
+    }
+}
+
+/// The offscreen images the minimap is rendered to, with the render pass of the scene
+///
+/// The images have the size of the corner of the screen the minimap is shown in, so it is drawn
+/// there pixel for pixel.
+pub struct MinimapTarget {
+    color: Arc<AttachmentImage>,
+    framebuffer: Arc<dyn FramebufferAbstract + Send + Sync>,
+    dimensions: [u32; 2],
+}
+
+impl MinimapTarget {
+    pub fn new(
+        device: Arc<Device>,
+        render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+        dimensions: [u32; 2],
+        reversed_z: bool,
+    ) -> Self {
+        let color = super::new_scene_color(device.clone(), dimensions);
+        let depth = super::new_depth_buffer(device, dimensions, reversed_z);
+
+        let framebuffer = Arc::new(
+            Framebuffer::start(render_pass)
+                .add(color.clone())
+                .unwrap()
+                .add(depth)
+                .unwrap()
+                .build()
+                .unwrap(),
+        );
+
+        Self {
+            color,
+            framebuffer,
+            dimensions,
+        }
+    }
+
+    pub fn color(&self) -> Arc<AttachmentImage> {
+        self.color.clone()
+    }
+
+    pub fn framebuffer(&self) -> Arc<dyn FramebufferAbstract + Send + Sync> {
+        self.framebuffer.clone()
+    }
+
+    pub fn dimensions(&self) -> [u32; 2] {
+        self.dimensions
+    }
+}
+
+/// The size in pixels of a minimap shown in `corner` of a surface, at least one pixel
+pub fn minimap_dimensions(corner: &Viewport, dimensions: [u32; 2]) -> [u32; 2] {
+    let pixels = corner.to_pixels(dimensions);
+
+    [
+        (pixels.dimensions[0].round() as u32).max(1),
+        (pixels.dimensions[1].round() as u32).max(1),
+    ]
+}
+
+#[cfg(test)]
+mod test {
+    use super::minimap_dimensions;
+    use crate::renderer::camera::Viewport;
+
+    #[test]
+    fn dimensions() {
+        let corner = Viewport::new(0.75, 0.0, 0.25, 0.5);
+        assert_eq!(minimap_dimensions(&corner, [1000, 500]), [250, 250]);
+
+        // Never empty, which no image can be
+        let corner = Viewport::new(0.0, 0.0, 0.0, 0.001);
+        assert_eq!(minimap_dimensions(&corner, [1000, 500]), [1, 1]);
+    }
+}
@@ -1,30 +1,94 @@
+pub mod atlas;
+pub mod batch;
 pub mod camera;
+pub mod capture;
+pub mod config;
+pub mod csg;
+pub mod culling;
 pub mod geometry;
+pub mod grading;
 pub mod lights;
+pub mod mesh_worker;
+pub mod minimap;
+pub mod normals;
+pub mod overlay;
+pub mod settings;
+pub mod stats;
+pub mod text;
+pub mod vertex;
+pub mod world_text;
 
 mod debug;
+mod exposure;
+mod frame;
+mod hiz;
+mod labels;
+mod layout;
+mod light_shafts;
+mod memory;
+mod occlusion;
+mod outline;
+mod pipelines;
+mod pools;
+mod post;
+mod profiler;
 mod queues;
+#[cfg(test)]
+mod reference;
+mod ring;
+#[cfg(feature = "runtime-shaders")]
+mod shader_compiler;
 mod shaders;
+mod sky;
+mod upload;
 
 use crate::{
-    components::GlobalTransform,
+    components::{render_transform, GlobalTransform, PreviousGlobalTransform, Transform},
     renderer::{
-        camera::{ActiveCamera, Camera},
+        batch::{BatchedMesh, MeshBatch},
+        camera::{ActiveCamera, AutoExposure, Camera, CameraEffects, Viewport},
+        capture::FrameCapture,
+        config::{choose_image_count, choose_surface_format, RendererConfig, SurfaceFormat},
+        culling::{CullingPass, Frustum},
         debug::Debug,
-        geometry::{MeshBuilder, MeshComponent, Vertex},
-        lights::{DirectionalLightRes, PointLightComponent},
+        exposure::LuminancePass,
+        frame::{FrameDescriptorSets, FrameSync, MovedEntities},
+        geometry::{Bounds, Ghost, MeshBuilder, MeshComponent, PendingMesh, Vertex},
+        grading::{ColorGrading, Lut},
+        hiz::HiZPyramid,
+        labels::DebugLabels,
+        light_shafts::LightShaftsPass,
+        lights::{AmbientLight, DirectionalLightRes, PointLightComponent},
+        memory::BufferAllocator,
+        mesh_worker::MeshWorker,
+        minimap::{minimap_dimensions, Minimap, MinimapTarget},
+        normals::{LineVertex, NormalLines},
+        occlusion::{OcclusionQueries, OcclusionTest, MIN_QUERY_RADIUS},
+        outline::OutlinePass,
+        overlay::{Overlay, OverlayPass},
+        pipelines::{MaterialFeatures, MeshPass, PipelineCache, PipelineKey, VertexLayout},
+        pools::CommandPools,
+        post::{PostMinimap, PostPass, PostView, SCENE_FORMAT},
+        profiler::{GpuProfiler, Pass},
         queues::{QueueFamilyIds, QueueFamilyTypes},
-        shaders::{Lights, PointLight, PushConstants, ShaderSet, VertexInput},
+        settings::{DebugView, QualityPreset, RenderSettings, ShadingConstants},
+        shaders::{CullObject, Lights, PointLight, PushConstants, ShaderSet},
+        sky::SkyPass,
+        stats::{PassCounts, RenderStats},
+        upload::UploadScheduler,
+        vertex::MeshVertexDefinition,
+        world_text::{WorldTextComponent, WorldTextPass},
     },
-    resources::DirtyEntities,
+    resources::{DirtyEntities, HiddenEntities, Time, WindowMode},
+    systems::{Selected, SpatialIndex},
 };
 use log::{error, info, log_enabled, warn, Level};
-use nalgebra::Vector3;
+use nalgebra::{Matrix4, Vector3};
 use sdl2::video::{Window as SdlWindow, WindowContext};
 use shrev::{EventChannel, ReaderId};
-use specs::{join::JoinIter, prelude::*};
+use specs::{join::JoinIter, prelude::*, rayon::prelude::*};
 use std::{
-    cmp::{max, min},
+    collections::HashSet,
     mem,
     ops::{Deref, DerefMut},
     rc::Rc,
@@ -32,23 +96,27 @@ use std::{
 };
 use vulkano::{
     app_info_from_cargo_toml,
-    buffer::{cpu_pool::CpuBufferPool, BufferUsage, CpuAccessibleBuffer},
+    blend::{AttachmentBlend, BlendFactor, BlendOp},
+    buffer::{BufferSlice, TypedBufferAccess},
     command_buffer::{AutoCommandBufferBuilder, DynamicState},
-    descriptor::{
-        descriptor_set::{FixedSizeDescriptorSetsPool, PersistentDescriptorSet},
-        DescriptorSet,
-    },
     device::{Device, DeviceExtensions, Features, Queue},
     format::Format,
     framebuffer::{Framebuffer, RenderPassAbstract, Subpass},
     image::{attachment::AttachmentImage, ImageUsage, SwapchainImage},
-    instance::{self, Instance, InstanceExtensions, PhysicalDevice, PhysicalDeviceType},
-    pipeline::{viewport::Viewport, GraphicsPipeline, GraphicsPipelineAbstract},
+    instance::{
+        self, Instance, InstanceExtensions, PhysicalDevice, PhysicalDeviceType,
+        RawInstanceExtensions,
+    },
+    pipeline::{
+        depth_stencil::{Compare, DepthStencil},
+        GraphicsPipeline, GraphicsPipelineAbstract,
+    },
     single_pass_renderpass,
     swapchain::{
-        self, AcquireError, CompositeAlpha, PresentMode, Swapchain, SwapchainCreationError,
+        self, AcquireError, Capabilities, CompositeAlpha, PresentMode, Swapchain,
+        SwapchainCreationError,
     },
-    sync::{self, FlushError, GpuFuture, SharingMode},
+    sync::{FlushError, GpuFuture, SharingMode},
     VulkanObject,
 };
 
@@ -87,9 +155,16 @@ impl VulkanoWindow for SdlWindow {
 
 #[derive(Debug)]
 pub enum RenderEvent {
-    WindowResized,
+    /// The drawable size of the window has changed, to this many pixels
+    WindowResized(u32, u32),
+    /// The window has moved to another display
+    DisplayChanged,
     StopRendering,
     StartRendering,
+    /// The game is closing, wait for the GPU and stop rendering for good
+    Shutdown,
+    /// A scene has been unloaded, forget everything kept about the entities that were deleted
+    SceneUnloaded,
 }
 
 /// Resource for sharing the event channel for render events
@@ -110,44 +185,106 @@ impl DerefMut for RenderEvents {
     }
 }
 
+/// The MeshBuilder of an entity has been built, so it has a MeshComponent now, or is part of the
+/// batch if the builder was batched
+///
+/// Sent by the renderer, which runs last, so systems see it on the frame after.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MeshReadyEvent(pub Entity);
+
+/// Resource for sharing the event channel for mesh ready events
+#[derive(Default)]
+pub struct MeshReadyEvents(EventChannel<MeshReadyEvent>);
+
+impl Deref for MeshReadyEvents {
+    type Target = EventChannel<MeshReadyEvent>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl DerefMut for MeshReadyEvents {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+/// What the renderer needs to draw what one of the active cameras sees
+struct View {
+    camera: Entity,
+    position: Vector3<f32>,
+    pc: PushConstants,
+    frustum: Frustum,
+    // The viewport and scissor of the camera
+    dynamic_state: DynamicState,
+    exposure: f32,
+    auto_exposure: bool,
+}
+
 /// The main renderer
 pub struct Renderer {
     pub device: Arc<Device>,
     queues: queues::Queues,
+    pools: CommandPools,
+    memory: BufferAllocator,
+    config: RendererConfig,
+    shaders: ShaderSet,
     surface: Surface,
+    surface_format: SurfaceFormat,
     swapchain: Arc<Swapchain<Window>>,
     images: Vec<Arc<SwapchainImage<Window>>>,
-    framebuffers: Option<
-        Vec<
-            Arc<
-                Framebuffer<
-                    Arc<dyn RenderPassAbstract + Sync + Send>,
-                    (((), Arc<SwapchainImage<Window>>), Arc<AttachmentImage>),
-                >,
+    framebuffer: Option<
+        Arc<
+            Framebuffer<
+                Arc<dyn RenderPassAbstract + Sync + Send>,
+                (((), Arc<AttachmentImage>), Arc<AttachmentImage>),
             >,
         >,
     >,
 
     render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
-    graphics_pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
-    dynamic_state: DynamicState,
+    mesh_pipelines: PipelineCache,
+    normals_pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
 
+    scene_color: Arc<AttachmentImage>,
     depth_buffer: Arc<AttachmentImage>,
-    vertex_input_pool: CpuBufferPool<VertexInput>,
-    lights_buffer: Arc<CpuAccessibleBuffer<Lights>>,
-    point_lights_buffer: Arc<CpuAccessibleBuffer<[PointLight]>>,
-    descriptor_set_pool: FixedSizeDescriptorSetsPool<Arc<GraphicsPipelineAbstract + Send + Sync>>,
-    shared_descriptor_set: Arc<DescriptorSet + Send + Sync>,
-
-    previous_frame_end: Box<GpuFuture + Send + Sync>,
+    /// Whether the render pass and pipelines were made for reversed depth, see `RenderSettings`
+    reversed_z: bool,
+    /// The preset the pipelines were specialized for
+    quality: QualityPreset,
+    /// The view the lighting pipelines were specialized for
+    debug_view: DebugView,
+    descriptor_sets: FrameDescriptorSets,
+    culling: CullingPass,
+    batch: MeshBatch,
+    mesh_worker: MeshWorker,
+    luminance: LuminancePass,
+    occlusion: OcclusionQueries,
+    hi_z: HiZPyramid,
+    post: PostPass,
+    overlay: OverlayPass,
+    world_text: WorldTextPass,
+    outline: OutlinePass,
+    sky: SkyPass,
+    light_shafts: LightShaftsPass,
+    /// What the minimap is rendered to, while there is one
+    minimap: Option<MinimapTarget>,
+    uploads: UploadScheduler,
+    profiler: GpuProfiler,
+    capture: FrameCapture,
+
+    frame_sync: FrameSync,
+    moved: MovedEntities,
     event_reader: Option<ReaderId<RenderEvent>>,
     point_lights_reader_id: Option<ReaderId<ComponentEvent>>,
     should_render: bool,
+    shut_down: bool,
     _debug: Debug,
 }
 
 impl Renderer {
-    pub fn new(window: &SdlWindow) -> Self {
+    pub fn new(window: &SdlWindow, config: RendererConfig) -> Self {
         let instance = new_instance();
 
         // We register the debug callback early in case something happens during init
@@ -157,105 +294,286 @@ impl Renderer {
 
         let (device, queues) = new_device_and_queues(instance.clone(), surface.clone());
 
-        let (swapchain, images) =
-            new_swapchain_and_images(device.clone(), surface.clone(), queues.present.clone());
+        let (swapchain, images, surface_format) = new_swapchain_and_images(
+            device.clone(),
+            surface.clone(),
+            queues.present.clone(),
+            &config,
+            None,
+        );
 
-        let framebuffers = None;
+        let framebuffer = None;
 
-        let depth_buffer =
-            AttachmentImage::transient(device.clone(), swapchain.dimensions(), Format::D16Unorm)
-                .unwrap();
-        let dynamic_state = DynamicState {
-            line_width: None,
-            viewports: Some(vec![Viewport {
-                origin: [0.0, 0.0],
-                dimensions: [
-                    swapchain.dimensions()[0] as f32,
-                    swapchain.dimensions()[1] as f32,
-                ],
-                depth_range: 0.0..1.0,
-            }]),
-            scissors: None,
+        let scene_color = new_scene_color(device.clone(), swapchain.dimensions());
+        // Settings are only read once rendering starts, which switches to reversed depth then
+        let reversed_z = false;
+        let quality = QualityPreset::default();
+        let debug_view = DebugView::default();
+        let shading = ShadingConstants {
+            quality: quality.settings(),
+            debug_view,
         };
-
+        let depth_buffer = new_depth_buffer(device.clone(), swapchain.dimensions(), reversed_z);
         let shaders = ShaderSet::new(device.clone());
+        layout::check_layouts();
+
+        let render_pass = build_render_pass(device.clone(), SCENE_FORMAT, reversed_z);
+
+        let mesh_pipelines =
+            PipelineCache::new(device.clone(), render_pass.clone(), reversed_z, shading);
+        mesh_pipelines.warm_up(PipelineKey::common(), &shaders);
 
-        let render_pass = build_render_pass(device.clone(), swapchain.format());
+        let normals_pipeline =
+            build_normals_pipeline(device.clone(), render_pass.clone(), &shaders, reversed_z);
 
-        let graphics_pipeline =
-            build_graphics_pipeline(device.clone(), render_pass.clone(), &shaders);
+        let memory = BufferAllocator::new(device.clone());
 
-        let vertex_input_pool = CpuBufferPool::<VertexInput>::new(
+        let batch = MeshBatch::new(
             device.clone(),
-            BufferUsage::uniform_buffer_transfer_destination(),
+            memory.clone(),
+            build_batch_pipeline(
+                device.clone(),
+                render_pass.clone(),
+                &shaders,
+                reversed_z,
+                shading,
+            ),
         );
 
-        let dir_light = DirectionalLightRes::default().to_directional_light();
+        let pools = CommandPools::new(device.clone(), &queues);
 
-        let lights = Lights { dir_light };
-
-        let lights_buffer = CpuAccessibleBuffer::from_data(
+        let culling = CullingPass::new(
             device.clone(),
-            BufferUsage::uniform_buffer_transfer_destination(),
-            lights,
-        )
-        .unwrap();
+            memory.clone(),
+            pools.clone(),
+            queues.compute.clone(),
+            queues.present.clone(),
+            &shaders,
+        );
 
-        let point_lights_buffer = {
-            let usage = BufferUsage {
-                storage_buffer: true,
-                ..BufferUsage::none()
-            };
+        let lights = Lights {
+            ambient: AmbientLight::default().to_vec4(),
+            dir_light: DirectionalLightRes::default().to_directional_light(),
+        };
 
-            let point_lights = [PointLightComponent::from_color(Vector3::new(0.0, 0.0, 0.0))
-                .to_point_light(Vector3::new(0.0, 0.0, 0.0))];
+        let descriptor_sets = FrameDescriptorSets::new(
+            memory.clone(),
+            mesh_pipelines.get(
+                PipelineKey::new(
+                    VertexLayout::Full,
+                    MaterialFeatures::default(),
+                    MeshPass::Main,
+                ),
+                &shaders,
+            ),
+            lights,
+        );
 
-            CpuAccessibleBuffer::from_iter(device.clone(), usage, point_lights.iter().cloned())
-                .unwrap()
-        };
+        let luminance = LuminancePass::new(device.clone(), memory.clone(), &shaders);
+        let occlusion =
+            OcclusionQueries::new(device.clone(), render_pass.clone(), &shaders, reversed_z);
+        let hi_z = HiZPyramid::new(
+            device.clone(),
+            queues.present.clone(),
+            queues.compute.clone(),
+            &shaders,
+        );
 
-        let descriptor_set_pool = FixedSizeDescriptorSetsPool::new(graphics_pipeline.clone(), 0);
+        let mut uploads =
+            UploadScheduler::new(pools.clone(), queues.transfer.clone(), config.upload_budget);
 
-        let shared_descriptor_set = Arc::new(
-            PersistentDescriptorSet::start(graphics_pipeline.clone(), 1)
-                .add_buffer(lights_buffer.clone())
-                .unwrap()
-                .add_buffer(point_lights_buffer.clone())
-                .unwrap()
-                .build()
-                .unwrap(),
+        let post = PostPass::new(
+            device.clone(),
+            memory.clone(),
+            surface_format,
+            config.paper_white,
+            &shaders,
+            &mut uploads,
         );
+        let overlay = OverlayPass::new(
+            device.clone(),
+            memory.clone(),
+            post.render_pass(),
+            post.overlay_constants(),
+            &shaders,
+        );
+        let world_text = WorldTextPass::new(
+            device.clone(),
+            memory.clone(),
+            render_pass.clone(),
+            &shaders,
+            reversed_z,
+        );
+        let outline = OutlinePass::new(device.clone(), render_pass.clone(), &shaders, reversed_z);
+        let sky = SkyPass::new(device.clone(), render_pass.clone(), &shaders);
+        let light_shafts = LightShaftsPass::new(device.clone(), &shaders);
 
-        let previous_frame_end = Box::new(sync::now(device.clone())) as Box<_>;
+        let transfer_source = surface
+            .capabilities(device.physical_device())
+            .unwrap()
+            .supported_usage_flags
+            .transfer_source;
+        let capture = FrameCapture::new(
+            memory.clone(),
+            surface_format.format,
+            transfer_source,
+            config.capture.clone(),
+        );
+
+        let labels = DebugLabels::new(&instance, &device);
+        let profiler = GpuProfiler::new(device.clone(), labels);
 
         let should_render = true;
 
         Self {
             device,
             queues,
+            pools,
+            memory,
+            config,
+            shaders,
             surface,
+            surface_format,
             swapchain,
             images,
-            framebuffers,
+            framebuffer,
             render_pass,
-            graphics_pipeline,
-            dynamic_state,
+            mesh_pipelines,
+            normals_pipeline,
 
+            scene_color,
             depth_buffer,
-            vertex_input_pool,
-            lights_buffer,
-            point_lights_buffer,
-            descriptor_set_pool,
-            shared_descriptor_set,
-
-            previous_frame_end,
+            reversed_z,
+            quality,
+            debug_view,
+            descriptor_sets,
+            culling,
+            batch,
+            mesh_worker: MeshWorker::new(),
+            luminance,
+            occlusion,
+            hi_z,
+            post,
+            overlay,
+            world_text,
+            outline,
+            sky,
+            light_shafts,
+            minimap: None,
+            uploads,
+            profiler,
+            capture,
+
+            frame_sync: FrameSync::new(device.clone()),
+            moved: MovedEntities::default(),
             event_reader: None,
             point_lights_reader_id: None,
             should_render,
+            shut_down: false,
             _debug,
         }
     }
 
+    /// Waits until the GPU has finished all submitted work, and releases the per frame resources
+    ///
+    /// Nothing is rendered after this. Everything else is dropped along with the Renderer, which
+    /// is safe now that the GPU no longer uses any of it.
+    pub fn shutdown(&mut self) {
+        self.should_render = false;
+        self.shut_down = true;
+
+        self.wait_for_frames();
+
+        // Frames still being recorded are written out
+        self.capture.finish();
+
+        // The framebuffers reference the swapchain images, so they go first
+        self.framebuffer = None;
+        self.post.release_framebuffers();
+
+        info!("Renderer shut down");
+    }
+
+    /// Waits for every frame in flight to finish on the GPU
+    fn wait_for_frames(&mut self) {
+        self.frame_sync.wait_all();
+
+        // The renderer is the only thing submitting work
+        unsafe {
+            self.device.wait().unwrap();
+        }
+    }
+
+    /// Picks the swapchain format and present mode again, after the window has moved to another
+    /// display, which may support different ones
+    ///
+    /// The swapchain is only recreated if either of them has changed. A new format also rebuilds
+    /// the post pass, which draws to the swapchain images.
+    pub fn renegotiate_surface(&mut self) {
+        if self.shut_down {
+            return;
+        }
+
+        let capabilities = self
+            .surface
+            .capabilities(self.device.physical_device())
+            .unwrap();
+
+        let surface_format = select_surface_format(&capabilities, &self.config);
+        let present_mode = select_present_mode(&capabilities);
+
+        if surface_format == self.surface_format && present_mode == self.swapchain.present_mode() {
+            return;
+        }
+
+        info!(
+            "Renegotiated surface format {:?} with present mode {:?}",
+            surface_format, present_mode
+        );
+
+        // Frames in flight still use the old swapchain images, and the capture its format
+        self.wait_for_frames();
+        self.capture.finish();
+
+        self.framebuffer = None;
+        self.post.release_framebuffers();
+
+        let (swapchain, images, surface_format) = new_swapchain_and_images(
+            self.device.clone(),
+            self.surface.clone(),
+            self.queues.present.clone(),
+            &self.config,
+            Some(&self.swapchain),
+        );
+        let dimensions = swapchain.dimensions();
+
+        self.swapchain = swapchain;
+        self.images = images;
+
+        if surface_format != self.surface_format {
+            self.surface_format = surface_format;
+            self.post.set_surface_format(surface_format, &self.shaders);
+            self.overlay.set_render_pass(
+                self.post.render_pass(),
+                self.post.overlay_constants(),
+                &self.shaders,
+            );
+
+            let transfer_source = capabilities.supported_usage_flags.transfer_source;
+            self.capture = FrameCapture::new(
+                self.memory.clone(),
+                surface_format.format,
+                transfer_source,
+                self.config.capture.clone(),
+            );
+        }
+
+        self.scene_color = new_scene_color(self.device.clone(), dimensions);
+        self.depth_buffer = new_depth_buffer(self.device.clone(), dimensions, self.reversed_z);
+
+        self.recreate_framebuffers();
+    }
+
     /// Recreates the swapchain from the old one, in case it is invalid
     pub fn recreate_swapchain(&mut self) -> Result<(), SwapchainCreationError> {
         let dimensions = {
@@ -277,17 +595,8 @@ impl Renderer {
 
         let (new_swapchain, new_images) = self.swapchain.recreate_with_dimension(dimensions)?;
 
-        self.depth_buffer =
-            AttachmentImage::transient(self.device.clone(), dimensions, Format::D16Unorm).unwrap();
-
-        // Converts from [i32; 2] to [f32; 2]
-        let dimensions = [dimensions[0] as f32, dimensions[1] as f32];
-
-        self.dynamic_state.viewports = Some(vec![Viewport {
-            origin: [0.0, 0.0],
-            dimensions: dimensions,
-            depth_range: 0.0..1.0,
-        }]);
+        self.scene_color = new_scene_color(self.device.clone(), dimensions);
+        self.depth_buffer = new_depth_buffer(self.device.clone(), dimensions, self.reversed_z);
 
         mem::replace(&mut self.swapchain, new_swapchain);
         mem::replace(&mut self.images, new_images);
@@ -299,32 +608,98 @@ impl Renderer {
         Ok(())
     }
 
-    /// Recreates the framebuffers backing the swapchain images inplace
+    /// Switches the main pass to or from reversed depth
+    ///
+    /// The depth buffer changes format and the depth tests their direction, so the render pass
+    /// and every pipeline drawing into it are rebuilt. Frames in flight keep the old ones alive.
+    fn set_reversed_z(&mut self, reversed_z: bool) {
+        self.reversed_z = reversed_z;
+        self.render_pass = build_render_pass(self.device.clone(), SCENE_FORMAT, reversed_z);
+
+        self.rebuild_pipelines();
+        self.occlusion
+            .set_render_pass(self.render_pass.clone(), &self.shaders, reversed_z);
+        self.world_text
+            .set_render_pass(self.render_pass.clone(), &self.shaders, reversed_z);
+        self.outline
+            .set_render_pass(self.render_pass.clone(), &self.shaders, reversed_z);
+        self.sky
+            .set_render_pass(self.render_pass.clone(), &self.shaders);
+        // Recreated with a depth buffer of the new format the next time it is rendered
+        self.minimap = None;
+
+        self.depth_buffer =
+            new_depth_buffer(self.device.clone(), self.swapchain.dimensions(), reversed_z);
+        self.recreate_framebuffers();
+    }
+
+    /// Switches the pipelines to another quality preset, rebuilding them with its specialization
+    /// constants
+    fn set_quality(&mut self, quality: QualityPreset) {
+        self.quality = quality;
+        self.rebuild_pipelines();
+    }
+
+    /// Switches the lighting pipelines to another debug view, which they are specialized for
+    fn set_debug_view(&mut self, debug_view: DebugView) {
+        self.debug_view = debug_view;
+        self.rebuild_pipelines();
+    }
+
+    /// The constants the lighting pipelines are specialized with
+    fn shading(&self) -> ShadingConstants {
+        ShadingConstants {
+            quality: self.quality.settings(),
+            debug_view: self.debug_view,
+        }
+    }
+
+    /// Rebuilds the pipelines drawing into the main render pass
+    fn rebuild_pipelines(&mut self) {
+        let (device, render_pass, shaders) = (&self.device, &self.render_pass, &self.shaders);
+        let (reversed_z, shading) = (self.reversed_z, self.shading());
+
+        // The variants used so far are likely to be needed again
+        let mesh_pipelines =
+            PipelineCache::new(device.clone(), render_pass.clone(), reversed_z, shading);
+        mesh_pipelines.warm_up(self.mesh_pipelines.keys(), shaders);
+        self.mesh_pipelines = mesh_pipelines;
+        self.normals_pipeline =
+            build_normals_pipeline(device.clone(), render_pass.clone(), shaders, reversed_z);
+        self.batch.set_pipeline(build_batch_pipeline(
+            device.clone(),
+            render_pass.clone(),
+            shaders,
+            reversed_z,
+            shading,
+        ));
+    }
+
+    /// Recreates the framebuffer the scene is rendered to, and those of the swapchain images,
+    /// inplace
     pub fn recreate_framebuffers(&mut self) {
-        let new_framebuffers = Some(
-            self.images
-                .iter()
-                .map(|image| {
-                    Arc::new(
-                        Framebuffer::start(self.render_pass.clone())
-                            .add(image.clone())
-                            .unwrap()
-                            .add(self.depth_buffer.clone())
-                            .unwrap()
-                            .build()
-                            .unwrap(),
-                    )
-                })
-                .collect::<Vec<_>>(),
-        );
+        let new_framebuffer = Some(Arc::new(
+            Framebuffer::start(self.render_pass.clone())
+                .add(self.scene_color.clone())
+                .unwrap()
+                .add(self.depth_buffer.clone())
+                .unwrap()
+                .build()
+                .unwrap(),
+        ));
+
+        mem::replace(&mut self.framebuffer, new_framebuffer);
 
-        mem::replace(&mut self.framebuffers, new_framebuffers);
+        self.post
+            .recreate_framebuffers(&self.images, self.scene_color.clone());
+        self.light_shafts
+            .recreate_framebuffers(self.scene_color.clone(), self.depth_buffer.clone());
 
         warn!("Framebuffers recreated");
     }
 
-    /// Creates a new buffer for point lights and a new descriptor set that includes it, and
-    /// replace the old ones on the renderer
+    /// Collects the point lights and hands them to the frame descriptor sets, which upload
+    /// them to each frame in flight when it is safe to do so
     fn upload_point_lights(
         &mut self,
         iter: JoinIter<(
@@ -332,30 +707,11 @@ impl Renderer {
             &ReadStorage<'_, GlobalTransform>,
         )>,
     ) {
-        let usage = BufferUsage {
-            storage_buffer: true,
-            ..BufferUsage::none()
-        };
-
         let lights = iter
             .map(|(light, global)| light.to_point_light(global.translation().clone()))
             .collect::<Vec<PointLight>>();
 
-        let buffer =
-            CpuAccessibleBuffer::from_iter(self.device.clone(), usage, lights.into_iter()).unwrap();
-
-        let descriptor_set = Arc::new(
-            PersistentDescriptorSet::start(self.graphics_pipeline.clone(), 1)
-                .add_buffer(self.lights_buffer.clone())
-                .unwrap()
-                .add_buffer(buffer.clone())
-                .unwrap()
-                .build()
-                .unwrap(),
-        );
-
-        self.point_lights_buffer = buffer;
-        self.shared_descriptor_set = descriptor_set;
+        self.descriptor_sets.set_point_lights(lights);
     }
 }
 
@@ -363,14 +719,38 @@ impl<'a> System<'a> for Renderer {
     type SystemData = (
         Entities<'a>,
         Read<'a, RenderEvents>,
-        Read<'a, DirtyEntities>,
+        Write<'a, DirtyEntities>,
+        Read<'a, HiddenEntities>,
+        Read<'a, Time>,
+        Read<'a, RenderSettings>,
+        Read<'a, SpatialIndex>,
+        (
+            Write<'a, WindowMode>,
+            Write<'a, Overlay>,
+            ReadStorage<'a, WorldTextComponent>,
+            Write<'a, MeshReadyEvents>,
+            WriteStorage<'a, PendingMesh>,
+            ReadStorage<'a, Selected>,
+            ReadStorage<'a, Minimap>,
+            ReadStorage<'a, CameraEffects>,
+        ),
+        Write<'a, RenderStats>,
+        Write<'a, AmbientLight>,
         Write<'a, DirectionalLightRes>,
+        Write<'a, ColorGrading>,
         ReadStorage<'a, PointLightComponent>,
         ReadStorage<'a, GlobalTransform>,
+        ReadStorage<'a, PreviousGlobalTransform>,
         ReadStorage<'a, ActiveCamera>,
+        ReadStorage<'a, Ghost>,
+        ReadStorage<'a, Viewport>,
         WriteStorage<'a, MeshComponent>,
         WriteStorage<'a, MeshBuilder>,
+        WriteStorage<'a, Bounds>,
+        WriteStorage<'a, BatchedMesh>,
         WriteStorage<'a, Camera>,
+        WriteStorage<'a, AutoExposure>,
+        WriteStorage<'a, NormalLines>,
     );
 
     /// The main draw/render function
@@ -379,23 +759,42 @@ impl<'a> System<'a> for Renderer {
         (
             entities,
             render_events,
-            dirty_entities,
+            mut dirty_entities,
+            hidden,
+            time,
+            settings,
+            index,
+            (
+                mut window_mode,
+                mut overlay,
+                world_texts,
+                mut mesh_ready,
+                mut pending,
+                selected,
+                minimaps,
+                camera_effects,
+            ),
+            mut stats,
+            mut ambient_light,
             mut directional_light,
+            mut color_grading,
             point_lights,
             globals,
+            previous_globals,
             active_cameras,
+            ghosts,
+            viewports,
             mut meshes,
             mut mesh_builders,
+            mut bounds,
+            mut batched,
             mut cameras,
+            mut auto_exposures,
+            mut normal_lines,
         ): Self::SystemData,
     ) {
-        // Cleanup
-        self.previous_frame_end.cleanup_finished();
-
-        // FIXME This seems like a hack and not the proper way to do this
-        // Swap the GpuFuture out of the Renderer
-        let mut frame_future = Box::new(sync::now(self.device.clone())) as Box<_>;
-        mem::swap(&mut frame_future, &mut self.previous_frame_end);
+        self.moved.remember(&dirty_entities.moved);
+        dirty_entities.moved.clear();
 
         // Handle render events
         // -----------------------------------------------------------------------------------------------------------------------------------------------------------
@@ -405,28 +804,214 @@ impl<'a> System<'a> for Renderer {
             .for_each(|event| {
                 warn!("Render event: {:?}", event);
                 match event {
-                    RenderEvent::WindowResized => {
+                    RenderEvent::WindowResized(..) => {
                         self.recreate_swapchain().unwrap();
                     }
+                    RenderEvent::DisplayChanged => {
+                        self.renegotiate_surface();
+                    }
                     RenderEvent::StopRendering => {
                         self.should_render = false;
                     }
                     RenderEvent::StartRendering => {
-                        self.should_render = true;
+                        self.should_render = !self.shut_down;
+                    }
+                    RenderEvent::Shutdown => {
+                        self.shutdown();
+                    }
+                    RenderEvent::SceneUnloaded => {
+                        self.mesh_worker.forget_dead(&entities);
+                        self.occlusion.clear();
+                        self.upload_point_lights((&point_lights, &globals).join());
                     }
                     // _ => (),
                 }
             });
 
+        // Quads added this frame, which are dropped if it is not drawn
+        let overlay_vertices = overlay.take_vertices();
+        overlay.set_dimensions(self.swapchain.dimensions());
+
+        // Nothing is drawn until the SDLSystem has changed the window mode
+        if window_mode.is_pending() {
+            self.wait_for_frames();
+            window_mode.set_gpu_idle();
+            return;
+        }
+
         if !self.should_render {
             return;
         }
 
+        self.descriptor_sets.next_frame();
+        let frame_index = self.descriptor_sets.index();
+
+        // Make sure the GPU is done with the resources of this frame index before we touch them
+        let frame_future = self.frame_sync.begin(frame_index);
+        self.memory.begin_frame(frame_index);
+        self.profiler.read(frame_index, &mut stats.gpu_times);
+        self.capture.collect(frame_index);
+
+        if settings.occlusion_queries {
+            self.occlusion.read(frame_index);
+        } else {
+            self.occlusion.clear();
+        }
+
+        for (camera, luminance) in self.luminance.collect(frame_index) {
+            if let Some(auto_exposure) = auto_exposures.get_mut(camera) {
+                auto_exposure.luminance = Some(luminance);
+            }
+        }
+
+        if settings.reversed_z != self.reversed_z {
+            self.set_reversed_z(settings.reversed_z);
+        }
+        if settings.quality != self.quality {
+            self.set_quality(settings.quality);
+        }
+        if settings.debug_view != self.debug_view {
+            self.set_debug_view(settings.debug_view);
+        }
+
         // TODO Find out if this is only needed for init or if we need to check for this each frame
-        if self.framebuffers.is_none() {
+        if self.framebuffer.is_none() {
             self.recreate_framebuffers();
         }
 
+        // Cameras
+        // -----------------------------------------------------------------------------------------------------------------------------------------------------------
+
+        let dimensions = self.swapchain.dimensions();
+        let reversed_z = self.reversed_z;
+
+        // How far the frame is between the last two simulation steps, see Time::alpha
+        let alpha = time.alpha();
+
+        let view = |camera: Entity,
+                    camera_c: &Camera,
+                    camera_t: &Transform,
+                    dynamic_state: DynamicState,
+                    auto_exposure: bool| {
+            // Shake and sway only move the view, the camera stays where it is
+            let view_matrix = match camera_effects.get(camera) {
+                Some(effects) => effects.apply(&camera_t.to_view_matrix()),
+                None => camera_t.to_view_matrix(),
+            };
+
+            let pc = PushConstants {
+                view: view_matrix.into(),
+                proj: camera_c.depth_projection(reversed_z),
+            };
+
+            // The frustum is the same either way, but its planes are found in -1 to 1
+            View {
+                camera,
+                position: *camera_t.translation(),
+                frustum: Frustum::from_matrix(
+                    &(Matrix4::from(camera_c.projection()) * view_matrix),
+                ),
+                dynamic_state,
+                exposure: camera_c.exposure,
+                auto_exposure,
+                pc,
+            }
+        };
+
+        // Every active camera renders its own view into its part of the screen
+        let mut views = (
+            &entities,
+            &mut cameras,
+            &globals,
+            previous_globals.maybe(),
+            &active_cameras,
+            viewports.maybe(),
+            auto_exposures.maybe(),
+        )
+            .join()
+            .map(
+                |(entity, camera, camera_t, previous, _, viewport, auto_exposure)| {
+                    let viewport = viewport.cloned().unwrap_or_default();
+                    camera.update_aspect(viewport.aspect(dimensions));
+
+                    view(
+                        entity,
+                        camera,
+                        &render_transform(camera_t, previous, alpha),
+                        viewport.to_dynamic_state(dimensions),
+                        auto_exposure.is_some(),
+                    )
+                },
+            )
+            .collect::<Vec<_>>();
+
+        if views.is_empty() {
+            warn!("No active camera to render from");
+            return;
+        }
+
+        // The minimap is rendered as one more view, into its own images instead of the screen
+        let screen_views = views.len();
+        let minimap = (
+            &entities,
+            &mut cameras,
+            &globals,
+            previous_globals.maybe(),
+            &minimaps,
+        )
+            .join()
+            .next()
+            .map(|(entity, camera, camera_t, previous, minimap)| {
+                let camera_t = render_transform(camera_t, previous, alpha);
+                let target = minimap_dimensions(&minimap.corner, dimensions);
+                camera.update_aspect(target[0] as f32 / target[1] as f32);
+
+                let dynamic_state = Viewport::default().to_dynamic_state(target);
+                let corner = Viewport {
+                    scissor: None,
+                    ..minimap.corner.clone()
+                };
+
+                (
+                    view(entity, camera, &camera_t, dynamic_state, false),
+                    corner,
+                    target,
+                )
+            });
+
+        let minimap = match minimap {
+            Some((minimap_view, corner, target)) => {
+                let outdated = self
+                    .minimap
+                    .as_ref()
+                    .map_or(true, |minimap| minimap.dimensions() != target);
+
+                if outdated {
+                    self.minimap = Some(MinimapTarget::new(
+                        self.device.clone(),
+                        self.render_pass.clone(),
+                        target,
+                        self.reversed_z,
+                    ));
+                }
+
+                let minimap = PostMinimap {
+                    viewport: corner.to_pixels(dimensions),
+                    scissor: corner.to_scissor(dimensions),
+                    exposure: minimap_view.exposure,
+                };
+
+                views.push(minimap_view);
+                Some(minimap)
+            }
+            None => {
+                self.minimap = None;
+                None
+            }
+        };
+        self.post
+            .set_minimap(self.minimap.as_ref().map(|minimap| minimap.color()));
+
         // Acquire image to draw final frame to
         // -----------------------------------------------------------------------------------------------------------------------------------------------------------
 
@@ -442,240 +1027,785 @@ impl<'a> System<'a> for Renderer {
                 Err(err) => panic!("Error occurred while acquiring next image: {:?}", err),
             };
 
-        // Camera
+        // Mesh building
+        // -----------------------------------------------------------------------------------------------------------------------------------------------------------
+
+        {
+            // Drop batched meshes that have been removed
+            self.batch.retain(&entities, &batched);
+
+            // Send new mesh builders off to be generated
+            (&entities, &globals, &mesh_builders.mask().clone())
+                .join()
+                .for_each(|(entity, _, _)| {
+                    let builder = mesh_builders.remove(entity).unwrap();
+                    self.mesh_worker.submit(entity, builder);
+                    pending.insert(entity, PendingMesh).unwrap();
+                });
+
+            // Build mesh components from the meshes that are done, a few at a time, and only as
+            // many bytes as the upload budget has room for
+            let uploads = &mut self.uploads;
+            let generated = self
+                .mesh_worker
+                .take(self.config.meshes_per_frame, |builder| {
+                    uploads.reserve(builder.size_in_bytes())
+                })
+                .into_iter()
+                .filter(|(entity, _, _)| entities.is_alive(*entity))
+                .collect::<Vec<_>>();
+
+            let new_meshes = generated
+                .iter()
+                .filter(|(_, builder, _)| !builder.is_batched())
+                .count();
+            self.descriptor_sets.reserve_meshes(new_meshes, &mut meshes);
+
+            generated
+                .into_iter()
+                .for_each(|(entity, builder, mesh_bounds)| {
+                    pending.remove(entity);
+                    bounds.insert(entity, mesh_bounds).unwrap();
+
+                    // Batched meshes share buffers instead of getting a MeshComponent
+                    if builder.is_batched() {
+                        let (vertex_data, index_data) = builder.into_data();
+                        self.batch.push(entity, vertex_data, index_data);
+                        batched.insert(entity, BatchedMesh).unwrap();
+                        mesh_ready.single_write(MeshReadyEvent(entity));
+                        return;
+                    }
+
+                    let (uniform_slot, descriptor_sets) =
+                        self.descriptor_sets.new_mesh_uniforms(entity);
+
+                    let mesh = builder.build(&self.memory, uniform_slot, descriptor_sets);
+
+                    meshes.insert(entity, mesh).unwrap();
+                    mesh_ready.single_write(MeshReadyEvent(entity));
+                });
+
+            self.batch.prepare(&globals);
+        }
+
+        // Normals
+        // -----------------------------------------------------------------------------------------------------------------------------------------------------------
+
+        // The lines are only kept around while they are shown
+        if settings.show_normals {
+            (&entities, &meshes, !normal_lines.mask().clone())
+                .join()
+                .for_each(|(entity, mesh, _)| {
+                    let lines = NormalLines::from_mesh(&self.memory, mesh);
+                    normal_lines.insert(entity, lines).unwrap();
+                });
+        } else {
+            normal_lines.clear();
+        }
+
+        // Point lights
+        // -----------------------------------------------------------------------------------------------------------------------------------------------------------
+
+        {
+            let mut should_update = false;
+
+            // TODO Only upload changes.
+            // Insert and Remove events should be handled as is, but modified events do not require
+            // a new buffer, we can simply write the changes to the buffer
+            point_lights
+                .channel()
+                .read(self.point_lights_reader_id.as_mut().unwrap())
+                .for_each(|event| match *event {
+                    _ => {
+                        should_update = true;
+                    }
+                });
+
+            if should_update {
+                self.upload_point_lights((&point_lights, &globals).join());
+            }
+        }
+
+        // Ambient and directional light
+        // -----------------------------------------------------------------------------------------------------------------------------------------------------------
+
+        if directional_light.dirty || ambient_light.dirty {
+            directional_light.dirty = false;
+            ambient_light.dirty = false;
+
+            self.descriptor_sets.set_lights(Lights {
+                ambient: ambient_light.to_vec4(),
+                dir_light: directional_light.to_directional_light(),
+            });
+        }
+
+        // Color grading
+        // -----------------------------------------------------------------------------------------------------------------------------------------------------------
+
+        if color_grading.dirty {
+            color_grading.dirty = false;
+
+            match color_grading.active() {
+                Some((_, lut)) => self.post.set_lut(&mut self.uploads, lut),
+                None => self.post.set_lut(&mut self.uploads, &Lut::identity(2)),
+            }
+        }
+
+        // World text
+        // -----------------------------------------------------------------------------------------------------------------------------------------------------------
+
+        // Laid out before the atlas is uploaded, so new glyphs are in it this frame
+        let world_text_vertices = (&world_texts, &globals, !&hidden.hidden)
+            .join()
+            .flat_map(|(text, global, _)| world_text::layout(&mut overlay, text, global))
+            .collect::<Vec<_>>();
+
+        // Overlay atlas
+        // -----------------------------------------------------------------------------------------------------------------------------------------------------------
+
+        self.overlay
+            .update_atlas(&mut self.uploads, overlay.atlas_mut());
+        self.world_text.set_atlas(self.overlay.atlas());
+
+        // Update buffers
         // -----------------------------------------------------------------------------------------------------------------------------------------------------------
 
-        let (camera, camera_t) = {
-            // FIXME What if there is more than one camera?
-            let (camera, camera_t, _) = (&mut cameras, &globals, &active_cameras)
-                .join()
-                .next()
-                .unwrap();
+        // Changes are applied to the current frame now, and to the other frames in flight once
+        // they come around.
+        // Entities that moved in the last step are interpolated, so they change every frame
+        let mut moved = self.moved.take();
+        if alpha < 1.0 {
+            moved |= previous_globals.mask();
+        }
+
+        self.descriptor_sets.mark_meshes_stale(&moved);
+
+        self.descriptor_sets
+            .update_current(&meshes, &globals, &previous_globals, alpha);
+
+        self.batch
+            .update_models(frame_index, &globals, &previous_globals, alpha, &moved);
+
+        // Flush and submit uploads
+        // -----------------------------------------------------------------------------------------------------------------------------------------------------------
+
+        // Everything after this waits on the semaphore signaled by the uploads
+        let frame_future = self.uploads.flush(frame_future);
+
+        // Culling
+        // -----------------------------------------------------------------------------------------------------------------------------------------------------------
+
+        // The order of this list decides which indirect draw command belongs to which mesh.
+        // The draw commands of the batch come after these
+        let mut draws = (&entities, &meshes, &bounds, &globals, ghosts.maybe())
+            .join()
+            .filter(|(entity, _, _, _, _)| !hidden.hidden.contains(entity.id()))
+            .collect::<Vec<_>>();
+
+        // Ghosts are blended over everything else, so they have to be drawn last
+        draws.sort_by_key(|(_, _, _, _, ghost)| ghost.is_some());
+        let ghost_count = draws
+            .iter()
+            .filter(|(_, _, _, _, ghost)| ghost.is_some())
+            .count();
+
+        let mut objects = draws
+            .iter()
+            .map(|(_, mesh, bounds, global, _)| {
+                let sphere = bounds.world_sphere(global);
+                let center = sphere.center();
+
+                CullObject {
+                    sphere: [center.x, center.y, center.z, sphere.radius()],
+                    draw: [mesh.index_buffer.index_count() as u32, 0, 0, 0],
+                }
+            })
+            .collect::<Vec<_>>();
+
+        objects.extend(self.batch.cull_objects(&bounds, &globals, &hidden.hidden));
+
+        // Which objects the culling shader keeps in each view, for the stats
+        let in_frustum = views
+            .iter()
+            .map(|view| {
+                objects
+                    .iter()
+                    .map(|object| view.frustum.intersects_sphere(&object.bounding_sphere()))
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+
+        let objects_len = objects.len();
+        let frame_future = if objects.is_empty() {
+            stats.triangles = 0;
+
+            Box::new(frame_future) as Box<GpuFuture + Send + Sync>
+        } else {
+            stats.triangles = objects
+                .iter()
+                .map(|object| u64::from(object.draw[0]) / 3)
+                .sum::<u64>()
+                * views.len() as u64;
+
+            let frustums = views
+                .iter()
+                .map(|view| view.frustum.clone())
+                .collect::<Vec<_>>();
+
+            let cull_command_buffer = self.culling.build_command_buffer(
+                frame_index,
+                &frustums,
+                objects,
+                &self.hi_z,
+                settings.hi_z,
+            );
+
+            let queue = self.culling.queue();
+            let future = frame_future
+                .then_execute(
+                    queue.clone(),
+                    self.profiler.begin(frame_index, Pass::Culling, &queue),
+                )
+                .unwrap()
+                .then_execute(queue.clone(), cull_command_buffer)
+                .unwrap()
+                .then_execute(
+                    queue.clone(),
+                    self.profiler.end(frame_index, Pass::Culling, &queue),
+                )
+                .unwrap()
+                .then_signal_semaphore_and_flush()
+                .unwrap();
+
+            Box::new(future) as Box<_>
+        };
+
+        let indirect_buffer = self.culling.indirect_buffer(frame_index);
+
+        stats.meshes = draws.len();
+        stats.batched_meshes = self.batch.len();
+        stats.point_lights = (&point_lights, &globals).join().count();
+
+        let opaque_range = 0..draws.len() - ghost_count;
+        let ghost_range = draws.len() - ghost_count..draws.len();
+        let batch_range = draws.len()..objects_len;
+
+        let pass_counts = |range: std::ops::Range<usize>, lights| PassCounts {
+            submitted: range.len() * views.len(),
+            frustum_culled: in_frustum
+                .iter()
+                .map(|kept| kept[range.clone()].iter().filter(|kept| !**kept).count())
+                .sum(),
+            occlusion_culled: 0,
+            lights,
+        };
+        let lit = stats
+            .point_lights
+            .min(self.quality.settings().max_point_lights as usize);
+        stats.opaque_pass = pass_counts(opaque_range.clone(), lit);
+        stats.batch_pass = pass_counts(batch_range, lit);
+        stats.ghost_pass = pass_counts(ghost_range.clone(), 0);
+
+        // Drawing
+        // -----------------------------------------------------------------------------------------------------------------------------------------------------------
+
+        // Build a primary command buffer builder
+        let command_buffer = self
+            .pools
+            .primary(&self.queues.present)
+            .begin_render_pass(
+                self.framebuffer.clone().unwrap(),
+                true, // This makes it so that we can execute secondary command buffers
+                vec![
+                    [0.0, 0.0, 0.0, 1.0].into(),
+                    far_depth(self.reversed_z).into(),
+                ],
+            )
+            .unwrap();
+
+        // Every view has one draw command per mesh, followed by those of the batch
+        let commands_per_view = draws.len() + self.batch.len();
+
+        // Build the secondary command buffer drawing mesh i into a view
+        let draw_mesh = |(v, i): (usize, usize)| {
+            let view = &views[v];
+            let (_, mesh, _, _, ghost) = &draws[i];
+            let command = v * commands_per_view + i;
+
+            // Ghosts are unlit, so they only need the mesh descriptor set
+            let layout = VertexLayout::of(&mesh.vertex_buffer);
+            let key = |pass| PipelineKey::new(layout, MaterialFeatures::default(), pass);
+            let (pipeline, descriptor_sets) = if ghost.is_some() {
+                (
+                    self.mesh_pipelines.get(key(MeshPass::Ghost), &self.shaders),
+                    vec![mesh.descriptor_sets[frame_index].clone()],
+                )
+            } else {
+                (
+                    self.mesh_pipelines.get(key(MeshPass::Main), &self.shaders),
+                    vec![
+                        mesh.descriptor_sets[frame_index].clone(),
+                        self.descriptor_sets.shared_descriptor_set(),
+                    ],
+                )
+            };
+
+            let builder = self
+                .pools
+                .secondary_graphics(&self.queues.present, pipeline.clone().subpass());
+
+            mesh.index_buffer
+                .draw_indexed_indirect(
+                    builder,
+                    pipeline,
+                    &view.dynamic_state,
+                    mesh.vertex_buffer.buffer(),
+                    BufferSlice::from_typed_buffer_access(indirect_buffer.clone())
+                        .slice(command..command + 1)
+                        .unwrap(),
+                    descriptor_sets,
+                    view.pc,
+                )
+                .build()
+                .unwrap()
+        };
+
+        // Meshes hidden in a view at their last occlusion query are not drawn into it
+        let visible =
+            |&(v, i): &(usize, usize)| !self.occlusion.is_occluded(views[v].camera, draws[i].0);
+        stats.occluded_meshes = (0..views.len())
+            .flat_map(|v| (0..draws.len()).map(move |i| (v, i)))
+            .filter(|draw| !visible(draw))
+            .count();
+
+        // Only those the culling shader kept, so every object is culled at most once
+        let occlusion_culled = |range: std::ops::Range<usize>| {
+            (0..views.len())
+                .flat_map(|v| range.clone().map(move |i| (v, i)))
+                .filter(|&(v, i)| in_frustum[v][i] && !visible(&(v, i)))
+                .count()
+        };
+        stats.opaque_pass.occlusion_culled = occlusion_culled(opaque_range);
+        stats.ghost_pass.occlusion_culled = occlusion_culled(ghost_range);
+
+        // Draws a range of meshes into every view
+        let draw_meshes = |range: std::ops::Range<usize>| {
+            (0..views.len())
+                .flat_map(|v| range.clone().map(move |i| (v, i)))
+                .filter(visible)
+                .collect::<Vec<_>>()
+                .into_par_iter()
+                .map(|draw| (draw.0, draw_mesh(draw)))
+                .collect::<Vec<_>>()
+        };
+
+        // The sky is behind everything, so it goes first. The debug views show the meshes alone
+        let mut secondary_command_buffers = Vec::new();
+        if settings.sky.enabled && settings.debug_view == DebugView::Lit {
+            for (v, view) in views.iter().enumerate() {
+                let builder = self
+                    .pools
+                    .secondary_graphics(&self.queues.present, self.sky.pipeline().subpass());
+
+                let secondary_command_buffer = self
+                    .sky
+                    .draw(
+                        builder,
+                        &view.dynamic_state,
+                        view.pc,
+                        &directional_light.direction(),
+                        &settings.sky,
+                    )
+                    .build()
+                    .unwrap();
+
+                secondary_command_buffers.push((v, secondary_command_buffer));
+            }
+        }
+
+        // Opaque meshes next
+        secondary_command_buffers.extend(draw_meshes(0..draws.len() - ghost_count));
+        let mut ghost_command_buffers = draw_meshes(draws.len() - ghost_count..draws.len());
+
+        // World text is blended like the ghosts, and drawn after them
+        if let Some(vertex_buffer) = self.world_text.upload(world_text_vertices) {
+            for (v, view) in views.iter().enumerate() {
+                let builder = self
+                    .pools
+                    .secondary_graphics(&self.queues.present, self.world_text.pipeline().subpass());
 
-            let dimensions = self.swapchain.dimensions();
-            camera.update_aspect({ dimensions[0] as f32 / dimensions[1] as f32 });
+                let secondary_command_buffer = self
+                    .world_text
+                    .draw(builder, &view.dynamic_state, vertex_buffer.clone(), view.pc)
+                    .build()
+                    .unwrap();
 
-            (camera, camera_t)
-        };
+                ghost_command_buffers.push((v, secondary_command_buffer));
+            }
+        }
 
-        // Mesh building
-        // -----------------------------------------------------------------------------------------------------------------------------------------------------------
+        // The whole batch is drawn from a single secondary command buffer per view
+        if !self.batch.is_empty() {
+            for (v, view) in views.iter().enumerate() {
+                let builder = self
+                    .pools
+                    .secondary_graphics(&self.queues.present, self.batch.pipeline().subpass());
+
+                let secondary_command_buffer = self
+                    .batch
+                    .draw(
+                        builder,
+                        frame_index,
+                        &view.dynamic_state,
+                        indirect_buffer.clone(),
+                        v * commands_per_view + draws.len(),
+                        self.descriptor_sets.shared_descriptor_set(),
+                        view.pc,
+                    )
+                    .build()
+                    .unwrap();
 
-        {
-            // Build mesh components from mesh builders
-            (&entities, &globals, &mesh_builders.mask().clone())
-                .join()
-                .for_each(|(entity, global, _)| {
-                    let builder = mesh_builders.remove(entity).unwrap();
+                secondary_command_buffers.push((v, secondary_command_buffer));
+            }
+        }
 
-                    let vertex = VertexInput {
-                        // model: global.to_view_matrix().into(),
-                        model: global.to_matrix().into(),
-                    };
+        // Normal lines, for every mesh with its own buffers. Batched meshes have none
+        if settings.show_normals {
+            for (v, view) in views.iter().enumerate() {
+                let builder = self.pools.secondary_graphics(
+                    &self.queues.present,
+                    self.normals_pipeline.clone().subpass(),
+                );
 
-                    let mesh = builder.build(
-                        self.device.clone(),
-                        &self.vertex_input_pool,
-                        vertex,
-                        &mut self.descriptor_set_pool,
-                    );
+                let secondary_command_buffer = (&meshes, &normal_lines, !&hidden.hidden)
+                    .join()
+                    .fold(builder, |builder, (mesh, lines, _)| {
+                        builder
+                            .draw(
+                                self.normals_pipeline.clone(),
+                                &view.dynamic_state,
+                                vec![lines.vertex_buffer.clone()],
+                                vec![mesh.descriptor_sets[frame_index].clone()],
+                                view.pc,
+                            )
+                            .unwrap()
+                    })
+                    .build()
+                    .unwrap();
 
-                    meshes.insert(entity, mesh).unwrap();
-                });
+                secondary_command_buffers.push((v, secondary_command_buffer));
+            }
         }
 
-        // Update buffers
-        // -----------------------------------------------------------------------------------------------------------------------------------------------------------
-
-        let buffer_update_command_buffer = {
-            let mut builder = AutoCommandBufferBuilder::primary_one_time_submit(
-                self.device.clone(),
-                self.queues.present.family(),
-            )
-            .unwrap();
+        // Selected meshes are outlined over the opaque meshes around them
+        let outlined = draws
+            .iter()
+            .filter(|(entity, _, _, _, _)| selected.contains(*entity))
+            .map(|(_, mesh, _, _, _)| *mesh)
+            .collect::<Vec<_>>();
 
-            // Uniforms
-            // -----------------------------------------------------------------------------------------------------------------------------------------------------------
+        if !outlined.is_empty() {
+            for (v, view) in views.iter().enumerate() {
+                let builder = self
+                    .pools
+                    .secondary_graphics(&self.queues.present, self.outline.pipeline().subpass());
+
+                let secondary_command_buffer = self
+                    .outline
+                    .draw(
+                        builder,
+                        &view.dynamic_state,
+                        &outlined,
+                        frame_index,
+                        view.pc,
+                        &settings.outline,
+                    )
+                    .build()
+                    .unwrap();
 
-            builder = (&meshes, &globals, &dirty_entities.dirty).join().fold(
-                builder,
-                |builder, (mesh, global, _)| {
-                    let vertex = VertexInput {
-                        // model: global.to_view_matrix().into(),
-                        model: global.to_matrix().into(),
-                    };
+                secondary_command_buffers.push((v, secondary_command_buffer));
+            }
+        }
 
-                    builder
-                        .update_buffer(mesh.vertex_uniforms.clone(), vertex)
+        // The views on the screen are drawn in the main pass, the minimap after it
+        let (secondary_command_buffers, minimap_command_buffers): (Vec<_>, Vec<_>) =
+            secondary_command_buffers
+                .into_iter()
+                .partition(|(v, _)| *v < screen_views);
+        let (ghost_command_buffers, minimap_ghost_command_buffers): (Vec<_>, Vec<_>) =
+            ghost_command_buffers
+                .into_iter()
+                .partition(|(v, _)| *v < screen_views);
+
+        let execute_all = |command_buffer, secondary_command_buffers: Vec<(usize, _)>| {
+            secondary_command_buffers.into_iter().fold(
+                command_buffer,
+                |command_buffer: AutoCommandBufferBuilder, (_, secondary_command_buffer)| unsafe {
+                    command_buffer
+                        .execute_commands(secondary_command_buffer)
                         .unwrap()
                 },
-            );
-
-            // Directional light
-            // -----------------------------------------------------------------------------------------------------------------------------------------------------------
+            )
+        };
 
-            // Update the lights buffer if the directional light has changed
-            if directional_light.dirty {
-                directional_light.dirty = false;
+        let command_buffer = execute_all(command_buffer, secondary_command_buffers);
 
-                let lights = Lights {
-                    dir_light: directional_light.to_directional_light(),
-                };
+        // The bounding boxes of large opaque meshes are tested against everything opaque
+        let command_buffer = if settings.occlusion_queries {
+            // The minimap has a depth buffer of its own, which the queries are not drawn into
+            let tests = views[..screen_views]
+                .iter()
+                .flat_map(|view| {
+                    // Meshes outside of the view are culled anyway, so they need no query
+                    let in_view = index
+                        .frustum(&view.frustum)
+                        .into_iter()
+                        .collect::<HashSet<_>>();
+
+                    draws[..draws.len() - ghost_count]
+                        .iter()
+                        .filter(move |(entity, _, _, _, _)| in_view.contains(entity))
+                        .filter_map(move |(entity, _, bounds, global, _)| {
+                            let sphere = bounds.world_sphere(global);
+
+                            // A box around the camera is clipped away, and would never be visible
+                            let distance = (sphere.center().coords - view.position).norm();
+                            if sphere.radius() < MIN_QUERY_RADIUS
+                                || distance < sphere.radius() + 1.0
+                            {
+                                return None;
+                            }
+
+                            let aabb = &bounds.aabb;
+                            let model = global.to_matrix()
+                                * Matrix4::new_translation(&aabb.center().coords)
+                                * Matrix4::new_nonuniform_scaling(&aabb.half_extents());
+
+                            Some(OcclusionTest {
+                                camera: view.camera,
+                                mesh: *entity,
+                                mvp: Matrix4::from(view.pc.proj)
+                                    * Matrix4::from(view.pc.view)
+                                    * model,
+                                viewport: view.dynamic_state.viewports.as_ref().unwrap()[0].clone(),
+                            })
+                        })
+                })
+                .collect::<Vec<_>>();
 
-                builder = builder
-                    .update_buffer(self.lights_buffer.clone(), lights)
-                    .unwrap();
-            }
+            stats.occlusion_queries = tests.len();
 
-            builder.build().unwrap()
+            let queries = self
+                .occlusion
+                .draw(frame_index, &self.queues.present, tests);
+            unsafe { command_buffer.execute_commands(queries).unwrap() }
+        } else {
+            stats.occlusion_queries = 0;
+            command_buffer
         };
 
-        // Flush and submit command buffers
-        // -----------------------------------------------------------------------------------------------------------------------------------------------------------
-
-        let frame_future = frame_future
-            .then_execute(self.queues.present.clone(), buffer_update_command_buffer)
-            .unwrap()
-            .then_signal_semaphore_and_flush()
+        // Ghosts are blended over everything else
+        let command_buffer = execute_all(command_buffer, ghost_command_buffers)
+            .end_render_pass()
             .unwrap();
 
-        // Point lights
-        // -----------------------------------------------------------------------------------------------------------------------------------------------------------
-
-        {
-            let mut should_update = false;
-
-            // TODO Only upload changes.
-            // Insert and Remove events should be handled as is, but modified events do not require
-            // a new buffer, we can simply write the changes to the buffer
-            point_lights
-                .channel()
-                .read(self.point_lights_reader_id.as_mut().unwrap())
-                .for_each(|event| match *event {
-                    _ => {
-                        should_update = true;
-                    }
-                });
+        // The minimap is drawn the same way, into its own images
+        let command_buffer = match &self.minimap {
+            Some(minimap) if screen_views < views.len() => {
+                let command_buffer = command_buffer
+                    .begin_render_pass(
+                        minimap.framebuffer(),
+                        true,
+                        vec![
+                            [0.0, 0.0, 0.0, 1.0].into(),
+                            far_depth(self.reversed_z).into(),
+                        ],
+                    )
+                    .unwrap();
+                let command_buffer = execute_all(command_buffer, minimap_command_buffers);
 
-            if should_update {
-                self.upload_point_lights((&point_lights, &globals).join());
+                execute_all(command_buffer, minimap_ghost_command_buffers)
+                    .end_render_pass()
+                    .unwrap()
             }
+            _ => command_buffer,
         }
+        .build()
+        .unwrap();
 
-        // Push constants
+        // Light shafts
         // -----------------------------------------------------------------------------------------------------------------------------------------------------------
 
-        let pc = PushConstants {
-            view: camera_t.to_view_matrix().into(),
-            proj: camera.projection(),
-        };
+        // Always recorded, so the pass has a timing of its own even when empty. Only the lit scene
+        // has a sky for the shafts to come from
+        let light_shafts_command_buffer = self.pools.primary(&self.queues.present);
+        let light_shafts_command_buffer =
+            if settings.light_shafts.enabled && settings.debug_view == DebugView::Lit {
+                let shaft_views = views[..screen_views]
+                    .iter()
+                    .map(|view| (&view.dynamic_state, view.pc))
+                    .collect::<Vec<_>>();
+
+                self.light_shafts.draw(
+                    light_shafts_command_buffer,
+                    &shaft_views,
+                    self.scene_color.dimensions(),
+                    far_depth(self.reversed_z),
+                    &directional_light.direction(),
+                    &directional_light.color(),
+                    &settings.light_shafts,
+                )
+            } else {
+                light_shafts_command_buffer
+            }
+            .build()
+            .unwrap();
 
-        // Drawing
+        // Post processing
         // -----------------------------------------------------------------------------------------------------------------------------------------------------------
 
-        // Build a primary command buffer builder
-        let command_buffer = AutoCommandBufferBuilder::primary_one_time_submit(
-            self.device.clone(),
-            self.queues.present.family(),
-        )
-        .unwrap()
-        .begin_render_pass(
-            self.framebuffers.as_ref().unwrap()[image_number].clone(),
-            true, // This makes it so that we can execute secondary command buffers
-            vec![[0.0, 0.0, 0.0, 1.0].into(), 1f32.into()],
-        )
-        .unwrap();
+        // The scene is measured before exposure, for the cameras adapting to it
+        let measured = views
+            .iter()
+            .filter(|view| view.auto_exposure)
+            .map(|view| {
+                let viewport = view.dynamic_state.viewports.as_ref().unwrap()[0].clone();
+                (view.camera, viewport)
+            })
+            .collect::<Vec<_>>();
+        let post_command_buffer = self.pools.primary(&self.queues.present);
+        // Only the screen views are in the depth buffer, the minimap has its own
+        let post_command_buffer = if settings.hi_z {
+            let hi_z_views = views[..screen_views]
+                .iter()
+                .map(|view| {
+                    let view_projection = Matrix4::from(view.pc.proj) * Matrix4::from(view.pc.view);
+                    let viewport = view.dynamic_state.viewports.as_ref().unwrap()[0].clone();
+                    (view_projection, viewport)
+                })
+                .collect::<Vec<_>>();
 
-        // Build secondary command buffers and execute them in the primary command buffer.
-        // Then build the primary command buffer
-        let secondary_command_buffers = (&meshes)
-            .par_join()
-            .map(|mesh| {
-                let descriptor_sets = vec![
-                    mesh.descriptor_set.clone(),
-                    self.shared_descriptor_set.clone(),
-                ];
-
-                let secondary_command_buffer =
-                    AutoCommandBufferBuilder::secondary_graphics_one_time_submit(
-                        self.device.clone(),
-                        self.queues.present.family(),
-                        self.graphics_pipeline.clone().subpass(),
-                    )
-                    .unwrap()
-                    // .draw(
-                    //     self.graphics_pipeline.clone(),
-                    //     &self.dynamic_state,
-                    //     vec![mesh.vertex_buffer.clone()],
-                    //     descriptor_sets,
-                    //     pc,
-                    // )
-                    .draw_indexed(
-                        self.graphics_pipeline.clone(),
-                        &self.dynamic_state,
-                        vec![mesh.vertex_buffer.clone()],
-                        mesh.index_buffer.clone(),
-                        descriptor_sets,
-                        pc,
-                    )
-                    .unwrap()
-                    .build()
-                    .unwrap();
+            self.hi_z.record(
+                post_command_buffer,
+                self.depth_buffer.clone(),
+                self.reversed_z,
+                &hi_z_views,
+            )
+        } else {
+            self.hi_z.invalidate();
+            post_command_buffer
+        };
+        let post_command_buffer = self.luminance.record(
+            post_command_buffer,
+            frame_index,
+            self.scene_color.clone(),
+            &measured,
+        );
 
-                secondary_command_buffer
+        let post_views = views[..screen_views]
+            .iter()
+            .map(|view| PostView {
+                scissor: view.dynamic_state.scissors.as_ref().unwrap()[0].clone(),
+                exposure: view.exposure,
             })
             .collect::<Vec<_>>();
 
-        let command_buffer = secondary_command_buffers
-            .into_iter()
-            .fold(
-                command_buffer,
-                |command_buffer, secondary_command_buffer| {
-                    let command_buffer = unsafe {
-                        command_buffer
-                            .execute_commands(secondary_command_buffer)
-                            .unwrap()
-                    };
+        // Recorded separately from the main pass so the two can be timed on their own
+        let dimensions = self.swapchain.dimensions();
+        let overlay_pass = &self.overlay;
+        let post_command_buffer = self.post.draw(
+            post_command_buffer,
+            image_number,
+            dimensions,
+            &post_views,
+            minimap.as_ref(),
+            settings.aa_mode,
+            |builder| overlay_pass.draw(builder, dimensions, &overlay_vertices),
+        );
 
-                    command_buffer
-                },
+        // The finished frame is copied out while recording
+        let post_command_buffer = self
+            .capture
+            .record(
+                post_command_buffer,
+                frame_index,
+                self.images[image_number].clone(),
+                settings.recording,
             )
-            .end_render_pass()
-            .unwrap()
             .build()
             .unwrap();
 
         // Presenting
         // -----------------------------------------------------------------------------------------------------------------------------------------------------------
 
-        let frame_future = {
-            let present_future = frame_future
-                .join(acquired_future)
-                .then_execute(self.queues.present.clone(), command_buffer)
-                .unwrap()
-                .then_swapchain_present(
-                    self.queues.present.clone(),
-                    self.swapchain.clone(),
-                    image_number,
-                )
-                .then_signal_fence_and_flush();
-
-            match present_future {
-                Ok(future) => Box::new(future) as Box<GpuFuture + Send + Sync>,
+        {
+            let queue = self.queues.present.clone();
+
+            // The semaphores between the passes make the scene image written by each pass visible
+            // to the next
+            let present_future = Box::new(
+                frame_future
+                    .join(acquired_future)
+                    .then_execute(queue.clone(), self.occlusion.reset(frame_index, &queue))
+                    .unwrap()
+                    .then_execute(
+                        queue.clone(),
+                        self.profiler.begin(frame_index, Pass::Main, &queue),
+                    )
+                    .unwrap()
+                    .then_execute(queue.clone(), command_buffer)
+                    .unwrap()
+                    .then_execute(
+                        queue.clone(),
+                        self.profiler.end(frame_index, Pass::Main, &queue),
+                    )
+                    .unwrap()
+                    .then_signal_semaphore()
+                    .then_execute(
+                        queue.clone(),
+                        self.profiler.begin(frame_index, Pass::LightShafts, &queue),
+                    )
+                    .unwrap()
+                    .then_execute(queue.clone(), light_shafts_command_buffer)
+                    .unwrap()
+                    .then_execute(
+                        queue.clone(),
+                        self.profiler.end(frame_index, Pass::LightShafts, &queue),
+                    )
+                    .unwrap()
+                    .then_signal_semaphore()
+                    .then_execute(
+                        queue.clone(),
+                        self.profiler.begin(frame_index, Pass::Post, &queue),
+                    )
+                    .unwrap()
+                    .then_execute(queue.clone(), post_command_buffer)
+                    .unwrap()
+                    .then_execute(
+                        queue.clone(),
+                        self.profiler.end(frame_index, Pass::Post, &queue),
+                    )
+                    .unwrap()
+                    .then_swapchain_present(
+                        self.queues.present.clone(),
+                        self.swapchain.clone(),
+                        image_number,
+                    ),
+            ) as Box<GpuFuture + Send + Sync>;
+
+            match self.frame_sync.submit(frame_index, present_future) {
+                Ok(()) => (),
                 Err(FlushError::OutOfDate) => {
                     error!("Swapchain out of date");
                     self.recreate_swapchain().unwrap();
-                    Box::new(sync::now(self.device.clone())) as Box<_>
-                }
-                Err(err) => {
-                    error!("{:?}", err);
-                    Box::new(sync::now(self.device.clone())) as Box<_>
                 }
+                Err(err) => error!("{:?}", err),
             }
-        };
+        }
 
-        // Store the GpuFuture in Renderer again
-        mem::replace(&mut self.previous_frame_end, frame_future);
+        stats.memory = self.memory.stats();
+        stats.swapchain_images = self.images.len();
+        stats.frames += 1;
     }
 
     fn setup(&mut self, res: &mut Resources) {
@@ -703,6 +1833,8 @@ impl<'a> System<'a> for Renderer {
 
 /// Creates a vulkan instance based on desired extensions and layers
 ///
+/// With the debug-labels feature, VK_EXT_debug_utils is enabled too if it is available.
+///
 /// # Panics
 ///
 /// - Panics if desired layer is not available
@@ -735,13 +1867,23 @@ fn new_instance() -> Arc<instance::Instance> {
             // Debugging
             ext_debug_report: true,
 
+            // Color spaces for HDR output
+            ext_swapchain_colorspace: true,
+
             ..InstanceExtensions::none()
         };
 
         let supported = InstanceExtensions::supported_by_core()
             .expect("Failed to load supported instance extensions");
 
-        supported.intersection(&desired)
+        let mut extensions = RawInstanceExtensions::from(&supported.intersection(&desired));
+
+        // Not known to vulkano, so it is added by name
+        if let Some(debug_utils) = labels::instance_extension() {
+            extensions.insert(debug_utils);
+        }
+
+        extensions
     };
 
     let layers = {
@@ -784,7 +1926,7 @@ fn new_instance() -> Arc<instance::Instance> {
         desired
     };
 
-    instance::Instance::new(Some(&info), &extensions, layers)
+    instance::Instance::new(Some(&info), extensions, layers)
         .expect("Failed to create vulkan instance")
 }
 
@@ -866,10 +2008,11 @@ fn new_device_and_queues(
         // Adds 4 general queues or 1 general, 1 graphics, 1 compute and 1 present queue
         // All of this is more to experiment with vulkan and implamentations than anything else
         // we could probably just stick to one queue, but this is more fun :)
+        // A fifth general queue, if there is one, is only used for uploads
         if let Some(id) = queue_family_ids.general {
             let qf = physical.queue_family_by_id(id).unwrap();
 
-            for _ in 0..min(4, qf.queues_count()) {
+            for _ in 0..min(5, qf.queues_count()) {
                 queues.push((qf, 1.0f32));
                 queue_types.push(QueueFamilyTypes::General);
             }
@@ -904,6 +2047,8 @@ fn new_device_and_queues(
         // TODO: Check for minimum required features
         let required_features = Features {
             fill_mode_non_solid: true,
+            // Batched draws use the first instance to index their model matrix
+            draw_indirect_first_instance: true,
             ..Features::none()
         };
 
@@ -962,11 +2107,20 @@ fn new_device_and_queues(
             general.clone()
         };
 
+        // Uploads get their own queue if the general family has one to spare, so they can run
+        // next to drawing and culling. Otherwise they share the general queue
+        let transfer = if queue_types.get(4) == Some(&QueueFamilyTypes::General) {
+            queues[4].clone()
+        } else {
+            general.clone()
+        };
+
         queues::Queues {
             general,
             compute,
             graphics,
             present,
+            transfer,
         }
     };
 
@@ -983,31 +2137,43 @@ fn new_swapchain_and_images(
     device: Arc<Device>,
     surface: Surface,
     queue: Arc<Queue>,
-) -> (Arc<Swapchain<Window>>, Vec<Arc<SwapchainImage<Window>>>) {
+    config: &RendererConfig,
+    old_swapchain: Option<&Arc<Swapchain<Window>>>,
+) -> (
+    Arc<Swapchain<Window>>,
+    Vec<Arc<SwapchainImage<Window>>>,
+    SurfaceFormat,
+) {
     let capabilities = surface
         .capabilities(device.physical_device())
         .expect("Failed to get surface capabilities");
 
     info!("Surface capabilities: {:?}\n", capabilities);
 
-    let buffer_count = max(
+    let buffer_count = choose_image_count(
+        config,
         capabilities.min_image_count,
-        capabilities
-            .max_image_count
-            .unwrap_or(capabilities.min_image_count),
+        capabilities.max_image_count,
     );
 
-    // First available format
-    let format = capabilities.supported_formats[0].0;
-    // info!("Supported formats: {:?}", capabilities.supported_formats);
+    info!("Swapchain images: {}", buffer_count);
+
+    info!("Supported formats: {:?}", capabilities.supported_formats);
+
+    let surface_format = select_surface_format(&capabilities, config);
+
+    info!("Surface format chosen: {:?}", surface_format);
+
+    let format = surface_format.format;
 
     // Current extent seems to be the screen res normaly
     // FIXME The dimensions dont match the inner window size
     let dimensions = capabilities.current_extent.unwrap_or([1600, 900]);
 
-    // We will only use this image for color
+    // We will only use this image for color, and copy from it when capturing frames
     let image_usage = ImageUsage {
         color_attachment: true,
+        transfer_source: capabilities.supported_usage_flags.transfer_source,
         ..ImageUsage::none()
     };
 
@@ -1036,34 +2202,157 @@ fn new_swapchain_and_images(
             .unwrap()
     };
 
-    // We prefer Mailbox, then Fifo
-    let present_mode = if capabilities.present_modes.supports(PresentMode::Mailbox) {
+    let present_mode = select_present_mode(&capabilities);
+
+    // HDR formats are only presented as HDR in their own color space
+    let color_space = surface_format.color_space;
+
+    let (swapchain, images) = match old_swapchain {
+        Some(old_swapchain) => Swapchain::with_old_swapchain(
+            device.clone(),
+            surface.clone(),
+            buffer_count,
+            format,
+            dimensions,
+            1,
+            image_usage,
+            sharing_mode,
+            transform,
+            alpha_composite,
+            present_mode,
+            true,
+            color_space,
+            old_swapchain.clone(),
+        ),
+        None => Swapchain::new(
+            device.clone(),
+            surface.clone(),
+            buffer_count,
+            format,
+            dimensions,
+            1,
+            image_usage,
+            sharing_mode,
+            transform,
+            alpha_composite,
+            present_mode,
+            true,
+            color_space,
+        ),
+    }
+    .expect("Failed to create swapchain");
+
+    (swapchain, images, surface_format)
+}
+
+/// Picks the swapchain format among those the surface supports, as configured
+fn select_surface_format(capabilities: &Capabilities, config: &RendererConfig) -> SurfaceFormat {
+    let chosen = choose_surface_format(&capabilities.supported_formats, config.hdr);
+
+    if config.hdr && !chosen.transfer.is_hdr() {
+        warn!("HDR output was asked for, but the surface has no HDR format");
+    }
+
+    chosen
+}
+
+/// We prefer Mailbox, then Fifo
+fn select_present_mode(capabilities: &Capabilities) -> PresentMode {
+    if capabilities.present_modes.supports(PresentMode::Mailbox) {
         PresentMode::Mailbox
     } else if capabilities.present_modes.supports(PresentMode::Fifo) {
         PresentMode::Fifo
     } else {
         capabilities.present_modes.iter().next().unwrap()
+    }
+}
+
+/// Creates the image the scene is rendered to, which is sampled when drawing it to the swapchain
+fn new_scene_color(device: Arc<Device>, dimensions: [u32; 2]) -> Arc<AttachmentImage> {
+    let usage = ImageUsage {
+        color_attachment: true,
+        sampled: true,
+        ..ImageUsage::none()
     };
 
-    Swapchain::new(
-        device.clone(),
-        surface.clone(),
-        buffer_count,
-        format,
-        dimensions,
-        1,
-        image_usage,
-        sharing_mode,
-        transform,
-        alpha_composite,
-        present_mode,
-        true,
-        None,
-    )
-    .expect("Failed to create swapchain")
+    AttachmentImage::with_usage(device, dimensions, SCENE_FORMAT, usage).unwrap()
+}
+
+/// The format of the depth buffer, which has to be float for reversed depth to be any more
+/// precise
+fn depth_format(reversed_z: bool) -> Format {
+    if reversed_z {
+        Format::D32Sfloat
+    } else {
+        Format::D16Unorm
+    }
+}
+
+/// The depth the depth buffer is cleared to, that of the far plane
+fn far_depth(reversed_z: bool) -> f32 {
+    if reversed_z {
+        0.0
+    } else {
+        1.0
+    }
+}
+
+/// The depth test and blending of the lighting pipelines
+///
+/// The overdraw view adds up every fragment drawn, hidden or not. It does not write depth
+/// either, so occlusion culling sees an empty scene while it is on.
+fn lighting_output(shading: ShadingConstants, reversed_z: bool) -> (DepthStencil, AttachmentBlend) {
+    match shading.debug_view {
+        DebugView::Overdraw => {
+            let additive = AttachmentBlend {
+                enabled: true,
+                color_op: BlendOp::Add,
+                color_source: BlendFactor::One,
+                color_destination: BlendFactor::One,
+                alpha_op: BlendOp::Add,
+                alpha_source: BlendFactor::One,
+                alpha_destination: BlendFactor::One,
+                ..AttachmentBlend::pass_through()
+            };
+
+            (DepthStencil::disabled(), additive)
+        }
+        _ => (depth_test(reversed_z), AttachmentBlend::pass_through()),
+    }
+}
+
+/// Depth testing and writing, passing fragments nearer than what has been drawn already
+fn depth_test(reversed_z: bool) -> DepthStencil {
+    DepthStencil {
+        depth_compare: if reversed_z {
+            Compare::Greater
+        } else {
+            Compare::Less
+        },
+        ..DepthStencil::simple_depth_test()
+    }
+}
+
+/// Creates the depth buffer of the main pass, which is sampled when building the Hi-Z pyramid
+fn new_depth_buffer(
+    device: Arc<Device>,
+    dimensions: [u32; 2],
+    reversed_z: bool,
+) -> Arc<AttachmentImage> {
+    let usage = ImageUsage {
+        depth_stencil_attachment: true,
+        sampled: true,
+        ..ImageUsage::none()
+    };
+
+    AttachmentImage::with_usage(device, dimensions, depth_format(reversed_z), usage).unwrap()
 }
 
-fn build_render_pass(device: Arc<Device>, format: Format) -> Arc<RenderPassAbstract + Send + Sync> {
+fn build_render_pass(
+    device: Arc<Device>,
+    format: Format,
+    reversed_z: bool,
+) -> Arc<RenderPassAbstract + Send + Sync> {
     Arc::new(
         single_pass_renderpass!(device.clone(),
             attachments: {
@@ -1074,10 +2363,11 @@ fn build_render_pass(device: Arc<Device>, format: Format) -> Arc<RenderPassAbstr
                     format: format,
                     samples: 1,
                 },
+                // Kept for the Hi-Z pyramid
                 depth: {
                     load: Clear,
-                    store: DontCare,
-                    format: Format::D16Unorm,
+                    store: Store,
+                    format: depth_format(reversed_z),
                     samples: 1,
                 }
             },
@@ -1090,23 +2380,141 @@ fn build_render_pass(device: Arc<Device>, format: Format) -> Arc<RenderPassAbstr
     )
 }
 
+/// Builds the variant of a mesh pipeline for a key
+fn build_mesh_pipeline(
+    device: Arc<Device>,
+    render_pass: Arc<RenderPassAbstract + Send + Sync>,
+    shaders: &ShaderSet,
+    key: PipelineKey,
+    reversed_z: bool,
+    shading: ShadingConstants,
+) -> Arc<GraphicsPipelineAbstract + Send + Sync> {
+    let vertex_input = key.layout.definition();
+
+    match key.pass {
+        MeshPass::Main => build_graphics_pipeline(
+            device,
+            render_pass,
+            shaders,
+            vertex_input,
+            reversed_z,
+            shading,
+        ),
+        MeshPass::Ghost => {
+            build_ghost_pipeline(device, render_pass, shaders, vertex_input, reversed_z)
+        }
+    }
+}
+
+/// The specialization constants of the lighting shader
+fn fragment_constants(shading: ShadingConstants) -> shaders::FragSC {
+    shaders::FragSC {
+        gamma: 2.2,
+        max_point_lights: shading.quality.max_point_lights as i32,
+        fog: shading.quality.fog as u32,
+        debug_view: shading.debug_view as i32,
+    }
+}
+
 fn build_graphics_pipeline(
     device: Arc<Device>,
     render_pass: Arc<RenderPassAbstract + Send + Sync>,
     shaders: &ShaderSet,
+    vertex_input: MeshVertexDefinition,
+    reversed_z: bool,
+    shading: ShadingConstants,
 ) -> Arc<GraphicsPipelineAbstract + Send + Sync> {
-    let sc = shaders::FragSC { gamma: 2.2 };
+    let sc = fragment_constants(shading);
+    let (depth_stencil, blend) = lighting_output(shading, reversed_z);
 
     Arc::new(
         GraphicsPipeline::start()
-            .vertex_input_single_buffer::<Vertex>()
+            .vertex_input(vertex_input)
             .vertex_shader(shaders.vertex.main_entry_point(), ())
             .triangle_list()
             //.polygon_mode_line()
-            .viewports_dynamic_scissors_irrelevant(1)
+            .viewports_scissors_dynamic(1)
             // .cull_mode_back()
             .fragment_shader(shaders.fragment.main_entry_point(), sc)
-            .depth_stencil_simple_depth()
+            .depth_stencil(depth_stencil)
+            .blend_collective(blend)
+            .render_pass(Subpass::from(render_pass, 0).unwrap())
+            .build(device.clone())
+            .unwrap(),
+    )
+}
+
+/// Pipeline for translucent, unlit ghost meshes
+///
+/// Ghosts are depth tested against the scene, but do not write depth themselves, so they never
+/// hide what is behind them.
+fn build_ghost_pipeline(
+    device: Arc<Device>,
+    render_pass: Arc<RenderPassAbstract + Send + Sync>,
+    shaders: &ShaderSet,
+    vertex_input: MeshVertexDefinition,
+    reversed_z: bool,
+) -> Arc<GraphicsPipelineAbstract + Send + Sync> {
+    let depth_stencil = DepthStencil {
+        depth_write: false,
+        ..depth_test(reversed_z)
+    };
+
+    Arc::new(
+        GraphicsPipeline::start()
+            .vertex_input(vertex_input)
+            .vertex_shader(shaders.vertex.main_entry_point(), ())
+            .triangle_list()
+            .viewports_scissors_dynamic(1)
+            .fragment_shader(shaders.ghost_fragment.main_entry_point(), ())
+            .depth_stencil(depth_stencil)
+            .blend_alpha_blending()
+            .render_pass(Subpass::from(render_pass, 0).unwrap())
+            .build(device.clone())
+            .unwrap(),
+    )
+}
+
+/// Draws the normal lines of a mesh, using the same uniforms as the mesh itself
+fn build_normals_pipeline(
+    device: Arc<Device>,
+    render_pass: Arc<RenderPassAbstract + Send + Sync>,
+    shaders: &ShaderSet,
+    reversed_z: bool,
+) -> Arc<GraphicsPipelineAbstract + Send + Sync> {
+    Arc::new(
+        GraphicsPipeline::start()
+            .vertex_input_single_buffer::<LineVertex>()
+            .vertex_shader(shaders.normals_vertex.main_entry_point(), ())
+            .line_list()
+            .viewports_scissors_dynamic(1)
+            .fragment_shader(shaders.normals_fragment.main_entry_point(), ())
+            .depth_stencil(depth_test(reversed_z))
+            .render_pass(Subpass::from(render_pass, 0).unwrap())
+            .build(device.clone())
+            .unwrap(),
+    )
+}
+
+fn build_batch_pipeline(
+    device: Arc<Device>,
+    render_pass: Arc<RenderPassAbstract + Send + Sync>,
+    shaders: &ShaderSet,
+    reversed_z: bool,
+    shading: ShadingConstants,
+) -> Arc<GraphicsPipelineAbstract + Send + Sync> {
+    let sc = fragment_constants(shading);
+    let (depth_stencil, blend) = lighting_output(shading, reversed_z);
+
+    Arc::new(
+        GraphicsPipeline::start()
+            .vertex_input_single_buffer::<Vertex>()
+            .vertex_shader(shaders.batch_vertex.main_entry_point(), ())
+            .triangle_list()
+            .viewports_scissors_dynamic(1)
+            .fragment_shader(shaders.fragment.main_entry_point(), sc)
+            .depth_stencil(depth_stencil)
+            .blend_collective(blend)
             .render_pass(Subpass::from(render_pass, 0).unwrap())
             .build(device.clone())
             .unwrap(),
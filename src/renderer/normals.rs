@@ -0,0 +1,63 @@
+use crate::renderer::{
+    geometry::MeshComponent,
+    memory::{BufferAllocator, MemoryUse, SubBuffer},
+};
+use specs::{Component, HashMapStorage};
+use specs_derive::Component;
+use std::sync::Arc;
+use vulkano::impl_vertex;
+
+/// Length of the normal lines in model space
+const NORMAL_LENGTH: f32 = 0.1;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LineVertex {
+    position: [f32; 3],
+    color: [f32; 3],
+}
+
+impl_vertex!(LineVertex, position, color);
+
+/// One line per vertex of a mesh, pointing along its normal
+///
+/// The color of a line is its normal mapped to rgb, so normals that point the wrong way stand out
+/// from their neighbours. Created by the renderer while normals are shown.
+#[derive(Component)]
+#[storage(HashMapStorage)]
+pub struct NormalLines {
+    pub vertex_buffer: Arc<SubBuffer<[LineVertex]>>,
+}
+
+impl NormalLines {
+    /// Generates the lines from the vertex data of a mesh
+    pub fn from_mesh(memory: &BufferAllocator, mesh: &MeshComponent) -> Self {
+        let vertices = mesh.vertex_buffer.read();
+
+        let lines = vertices
+            .iter()
+            .flat_map(|vertex| {
+                let (p, n) = (vertex.position, vertex.normal);
+                let color = [n[0] * 0.5 + 0.5, n[1] * 0.5 + 0.5, n[2] * 0.5 + 0.5];
+                let tip = [
+                    p[0] + n[0] * NORMAL_LENGTH,
+                    p[1] + n[1] * NORMAL_LENGTH,
+                    p[2] + n[2] * NORMAL_LENGTH,
+                ];
+
+                vec![
+                    LineVertex { position: p, color },
+                    LineVertex {
+                        position: tip,
+                        color,
+                    },
+                ]
+            })
+            .collect::<Vec<_>>();
+
+        let vertex_buffer = memory
+            .sub_array(MemoryUse::Mesh, lines.into_iter())
+            .expect("Failed to create normal line buffer");
+
+        Self { vertex_buffer }
+    }
+}
@@ -0,0 +1,298 @@
+use crate::renderer::{
+    frame::FRAMES_IN_FLIGHT,
+    profiler::MarkerCommandBuffer,
+    shaders::{OcclusionPushConstants, ShaderSet},
+};
+use log::warn;
+use nalgebra::Matrix4;
+use specs::Entity;
+use std::{collections::HashSet, mem, sync::Arc};
+use vulkano::{
+    blend::AttachmentBlend,
+    command_buffer::sys::{Kind, KindOcclusionQuery, KindSecondaryRenderPass},
+    descriptor::PipelineLayoutAbstract,
+    device::{Device, Queue},
+    framebuffer::{FramebufferAbstract, RenderPassAbstract, Subpass},
+    pipeline::{
+        depth_stencil::{Compare, DepthStencil},
+        vertex::BufferlessDefinition,
+        viewport::Viewport,
+        GraphicsPipeline, GraphicsPipelineAbstract,
+    },
+    query::{QueryPipelineStatisticFlags, QueryType, UnsafeQueryPool},
+    VulkanObject,
+};
+
+/// Meshes with a bounding sphere at least this large in world space get occlusion queries
+///
+/// Smaller meshes are about as cheap to draw as their bounding box.
+pub const MIN_QUERY_RADIUS: f32 = 1.0;
+/// Queries available to each frame in flight
+const MAX_QUERIES: u32 = 512;
+
+/// A mesh whose bounding box is tested against the depth buffer of one view
+pub struct OcclusionTest {
+    pub camera: Entity,
+    pub mesh: Entity,
+    /// From a cube spanning -1 to 1 to clip space
+    pub mvp: Matrix4<f32>,
+    pub viewport: Viewport,
+}
+
+/// Skips drawing large meshes that were hidden behind others in an earlier frame
+///
+/// After the opaque meshes are drawn, the bounding boxes of the large ones are depth tested with
+/// an occlusion query around each. The results are read once the fence of the frame has been
+/// waited on, and meshes whose box had no visible samples are skipped in that view until a
+/// later query sees them again. Their boxes are still tested every frame, so they reappear a
+/// frame or two late at worst.
+///
+/// vulkano can not record queries, so the boxes are drawn by a raw secondary command buffer.
+pub struct OcclusionQueries {
+    device: Arc<Device>,
+    pool: UnsafeQueryPool,
+    pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+    subpass: Subpass<Arc<dyn RenderPassAbstract + Send + Sync>>,
+    /// The camera and mesh of every query of each frame in flight, that have not been read yet
+    pending: Vec<Vec<(Entity, Entity)>>,
+    /// The camera and mesh pairs whose last query found nothing visible
+    occluded: HashSet<(Entity, Entity)>,
+}
+
+impl OcclusionQueries {
+    /// Creates the queries for drawing boxes into the first subpass of `render_pass`
+    pub fn new(
+        device: Arc<Device>,
+        render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+        shaders: &ShaderSet,
+        reversed_z: bool,
+    ) -> Self {
+        let pool = UnsafeQueryPool::new(
+            device.clone(),
+            QueryType::Occlusion,
+            MAX_QUERIES * FRAMES_IN_FLIGHT as u32,
+        )
+        .unwrap();
+
+        let subpass = Subpass::from(render_pass, 0).unwrap();
+        let pipeline = Self::build_pipeline(device.clone(), subpass.clone(), shaders, reversed_z);
+
+        Self {
+            device,
+            pool,
+            pipeline,
+            subpass,
+            pending: vec![Vec::new(); FRAMES_IN_FLIGHT],
+            occluded: HashSet::new(),
+        }
+    }
+
+    /// Draws the boxes into a new render pass, whose depth is reversed if `reversed_z` is set
+    ///
+    /// Every result is forgotten, as they were tested against the old depth.
+    pub fn set_render_pass(
+        &mut self,
+        render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+        shaders: &ShaderSet,
+        reversed_z: bool,
+    ) {
+        self.subpass = Subpass::from(render_pass, 0).unwrap();
+        self.pipeline = Self::build_pipeline(
+            self.device.clone(),
+            self.subpass.clone(),
+            shaders,
+            reversed_z,
+        );
+        self.clear();
+    }
+
+    fn build_pipeline(
+        device: Arc<Device>,
+        subpass: Subpass<Arc<dyn RenderPassAbstract + Send + Sync>>,
+        shaders: &ShaderSet,
+        reversed_z: bool,
+    ) -> Arc<dyn GraphicsPipelineAbstract + Send + Sync> {
+        // Only tested against the depth of the scene, neither depth nor colors are written
+        let depth_stencil = DepthStencil {
+            depth_write: false,
+            depth_compare: if reversed_z {
+                Compare::GreaterOrEqual
+            } else {
+                Compare::LessOrEqual
+            },
+            ..DepthStencil::simple_depth_test()
+        };
+        let blend = AttachmentBlend {
+            mask_red: false,
+            mask_green: false,
+            mask_blue: false,
+            mask_alpha: false,
+            ..AttachmentBlend::pass_through()
+        };
+
+        Arc::new(
+            GraphicsPipeline::start()
+                .vertex_input(BufferlessDefinition)
+                .vertex_shader(shaders.occlusion_vertex.main_entry_point(), ())
+                .triangle_list()
+                .viewports_dynamic_scissors_irrelevant(1)
+                .fragment_shader(shaders.occlusion_fragment.main_entry_point(), ())
+                .depth_stencil(depth_stencil)
+                .blend_collective(blend)
+                .render_pass(subpass)
+                .build(device)
+                .unwrap(),
+        )
+    }
+
+    /// Whether the mesh was hidden in the view of the camera at its last query
+    pub fn is_occluded(&self, camera: Entity, mesh: Entity) -> bool {
+        self.occluded.contains(&(camera, mesh))
+    }
+
+    /// Forgets every result, so everything is drawn again
+    pub fn clear(&mut self) {
+        for pending in &mut self.pending {
+            pending.clear();
+        }
+        self.occluded.clear();
+    }
+
+    /// Reads the results of the queries of a frame, which has to be finished on the GPU
+    ///
+    /// Only meshes queried in that frame are occluded afterwards.
+    pub fn read(&mut self, frame_index: usize) {
+        let pending = mem::replace(&mut self.pending[frame_index], Vec::new());
+        if pending.is_empty() {
+            self.occluded.clear();
+            return;
+        }
+
+        let mut samples = vec![0u64; pending.len()];
+
+        // The queries are only reset and written by command buffers that have finished
+        let result = unsafe {
+            let vk = self.device.pointers();
+            vk.GetQueryPoolResults(
+                self.device.internal_object(),
+                self.pool.internal_object(),
+                Self::first_query(frame_index),
+                pending.len() as u32,
+                samples.len() * mem::size_of::<u64>(),
+                samples.as_mut_ptr() as *mut _,
+                mem::size_of::<u64>() as u64,
+                vk_sys::QUERY_RESULT_64_BIT,
+            )
+        };
+
+        self.occluded = if result == vk_sys::SUCCESS {
+            pending
+                .into_iter()
+                .zip(samples)
+                .filter(|(_, samples)| *samples == 0)
+                .map(|(pair, _)| pair)
+                .collect()
+        } else {
+            HashSet::new()
+        };
+    }
+
+    /// A command buffer resetting the queries of a frame, to be executed before the render pass
+    pub fn reset(&self, frame_index: usize, queue: &Queue) -> MarkerCommandBuffer {
+        MarkerCommandBuffer::record(
+            self.device.clone(),
+            queue,
+            Kind::primary(),
+            |command_buffer| unsafe {
+                let vk = self.device.pointers();
+                vk.CmdResetQueryPool(
+                    command_buffer,
+                    self.pool.internal_object(),
+                    Self::first_query(frame_index),
+                    MAX_QUERIES,
+                );
+            },
+        )
+    }
+
+    /// A secondary command buffer drawing the bounding box of every test inside a query
+    ///
+    /// Has to be executed in the render pass after the opaque meshes, and after the queries of the
+    /// frame have been reset.
+    pub fn draw(
+        &mut self,
+        frame_index: usize,
+        queue: &Queue,
+        mut tests: Vec<OcclusionTest>,
+    ) -> MarkerCommandBuffer {
+        if tests.len() > MAX_QUERIES as usize {
+            warn!(
+                "{} occlusion queries needed, only the first {} are made",
+                tests.len(),
+                MAX_QUERIES
+            );
+            tests.truncate(MAX_QUERIES as usize);
+        }
+
+        let kind = Kind::Secondary {
+            render_pass: Some(KindSecondaryRenderPass {
+                subpass: self.subpass.clone(),
+                framebuffer: None::<Arc<dyn FramebufferAbstract + Send + Sync>>,
+            }),
+            occlusion_query: KindOcclusionQuery::Forbidden,
+            query_statistics_flags: QueryPipelineStatisticFlags::none(),
+        };
+
+        let first_query = Self::first_query(frame_index);
+        let command_buffer = MarkerCommandBuffer::record(
+            self.device.clone(),
+            queue,
+            kind,
+            |command_buffer| unsafe {
+                let vk = self.device.pointers();
+                vk.CmdBindPipeline(
+                    command_buffer,
+                    vk_sys::PIPELINE_BIND_POINT_GRAPHICS,
+                    self.pipeline.inner().internal_object(),
+                );
+
+                for (i, test) in tests.iter().enumerate() {
+                    let viewport = vk_sys::Viewport {
+                        x: test.viewport.origin[0],
+                        y: test.viewport.origin[1],
+                        width: test.viewport.dimensions[0],
+                        height: test.viewport.dimensions[1],
+                        minDepth: 0.0,
+                        maxDepth: 1.0,
+                    };
+                    vk.CmdSetViewport(command_buffer, 0, 1, &viewport);
+
+                    let pc = OcclusionPushConstants {
+                        mvp: test.mvp.into(),
+                    };
+                    vk.CmdPushConstants(
+                        command_buffer,
+                        self.pipeline.sys().internal_object(),
+                        vk_sys::SHADER_STAGE_VERTEX_BIT,
+                        0,
+                        mem::size_of_val(&pc) as u32,
+                        &pc as *const OcclusionPushConstants as *const _,
+                    );
+
+                    let query = first_query + i as u32;
+                    vk.CmdBeginQuery(command_buffer, self.pool.internal_object(), query, 0);
+                    vk.CmdDraw(command_buffer, 36, 1, 0, 0);
+                    vk.CmdEndQuery(command_buffer, self.pool.internal_object(), query);
+                }
+            },
+        );
+
+        self.pending[frame_index] = tests.iter().map(|test| (test.camera, test.mesh)).collect();
+
+        command_buffer
+    }
+
+    fn first_query(frame_index: usize) -> u32 {
+        frame_index as u32 * MAX_QUERIES
+    }
+}
@@ -0,0 +1,134 @@
+use crate::renderer::{
+    geometry::MeshComponent,
+    pipelines::VertexLayout,
+    settings::OutlineSettings,
+    shaders::{OutlinePushConstants, PushConstants, ShaderSet},
+};
+use std::{collections::HashMap, sync::Arc};
+use vulkano::{
+    command_buffer::{AutoCommandBufferBuilder, DynamicState},
+    device::Device,
+    framebuffer::{RenderPassAbstract, Subpass},
+    pipeline::{
+        depth_stencil::{Compare, DepthStencil},
+        GraphicsPipeline, GraphicsPipelineAbstract,
+    },
+};
+
+/// Draws an outline around meshes, for the selected entities
+///
+/// Every outlined mesh is drawn again pushed out along its normals, with only its back faces,
+/// after the opaque meshes. The mesh itself covers all of that but a rim around its edges, which
+/// is the outline. Outlines are hidden behind other meshes, like the meshes they are around.
+///
+/// NOTE: This needs the triangles of a mesh to face outwards, or the outline covers the mesh.
+pub struct OutlinePass {
+    device: Arc<Device>,
+    pipelines: HashMap<VertexLayout, Arc<dyn GraphicsPipelineAbstract + Send + Sync>>,
+}
+
+impl OutlinePass {
+    pub fn new(
+        device: Arc<Device>,
+        render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+        shaders: &ShaderSet,
+        reversed_z: bool,
+    ) -> Self {
+        let pipelines = build_pipelines(device.clone(), render_pass, shaders, reversed_z);
+
+        Self { device, pipelines }
+    }
+
+    /// Rebuilds the pipelines, after the main render pass has been rebuilt
+    pub fn set_render_pass(
+        &mut self,
+        render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+        shaders: &ShaderSet,
+        reversed_z: bool,
+    ) {
+        self.pipelines = build_pipelines(self.device.clone(), render_pass, shaders, reversed_z);
+    }
+
+    /// Any of the pipelines, which all draw into the same subpass
+    pub fn pipeline(&self) -> Arc<dyn GraphicsPipelineAbstract + Send + Sync> {
+        self.pipelines[&VertexLayout::Full].clone()
+    }
+
+    /// Records drawing the outlines of meshes into a view
+    pub fn draw(
+        &self,
+        builder: AutoCommandBufferBuilder,
+        dynamic_state: &DynamicState,
+        meshes: &[&MeshComponent],
+        frame_index: usize,
+        pc: PushConstants,
+        settings: &OutlineSettings,
+    ) -> AutoCommandBufferBuilder {
+        let [width, height] = dynamic_state.viewports.as_ref().unwrap()[0].dimensions;
+
+        // Normalized device coordinates are 2 wide
+        let pc = OutlinePushConstants {
+            view: pc.view,
+            proj: pc.proj,
+            color: settings.color,
+            thickness: [
+                settings.thickness * 2.0 / width,
+                settings.thickness * 2.0 / height,
+            ],
+        };
+
+        meshes.iter().fold(builder, |builder, mesh| {
+            let pipeline = self.pipelines[&VertexLayout::of(&mesh.vertex_buffer)].clone();
+
+            mesh.index_buffer.draw_indexed(
+                builder,
+                pipeline,
+                dynamic_state,
+                mesh.vertex_buffer.buffer(),
+                vec![mesh.descriptor_sets[frame_index].clone()],
+                pc,
+            )
+        })
+    }
+}
+
+/// A pipeline for every vertex layout
+fn build_pipelines(
+    device: Arc<Device>,
+    render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+    shaders: &ShaderSet,
+    reversed_z: bool,
+) -> HashMap<VertexLayout, Arc<dyn GraphicsPipelineAbstract + Send + Sync>> {
+    // Depth tested against the scene, without hiding anything drawn after it
+    let depth_stencil = DepthStencil {
+        depth_write: false,
+        depth_compare: if reversed_z {
+            Compare::Greater
+        } else {
+            Compare::Less
+        },
+        ..DepthStencil::simple_depth_test()
+    };
+
+    VertexLayout::ALL
+        .iter()
+        .map(|&layout| {
+            let pipeline = Arc::new(
+                GraphicsPipeline::start()
+                    .vertex_input(layout.definition())
+                    .vertex_shader(shaders.outline_vertex.main_entry_point(), ())
+                    .triangle_list()
+                    .viewports_scissors_dynamic(1)
+                    // Only the back faces, the front faces would cover the mesh
+                    .cull_mode_front()
+                    .fragment_shader(shaders.outline_fragment.main_entry_point(), ())
+                    .depth_stencil(depth_stencil.clone())
+                    .render_pass(Subpass::from(render_pass.clone(), 0).unwrap())
+                    .build(device.clone())
+                    .unwrap(),
+            ) as Arc<dyn GraphicsPipelineAbstract + Send + Sync>;
+
+            (layout, pipeline)
+        })
+        .collect()
+}
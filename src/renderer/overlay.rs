@@ -0,0 +1,389 @@
+use crate::renderer::{
+    atlas::{Atlas, AtlasRegion},
+    memory::{BufferAllocator, MemoryUse},
+    shaders::{OverlayPushConstants, OverlaySC, ShaderSet},
+    text::{Fonts, PlacedGlyph},
+    upload::UploadScheduler,
+};
+use image::RgbaImage;
+use std::sync::Arc;
+use vulkano::{
+    command_buffer::{AutoCommandBufferBuilder, DynamicState},
+    descriptor::{descriptor_set::PersistentDescriptorSet, DescriptorSet},
+    device::Device,
+    format::Format,
+    framebuffer::{RenderPassAbstract, Subpass},
+    image::{Dimensions, ImageLayout, ImageUsage, ImmutableImage, MipmapsCount},
+    impl_vertex,
+    pipeline::{viewport::Viewport, GraphicsPipeline, GraphicsPipelineAbstract},
+    sampler::{Filter, MipmapMode, Sampler, SamplerAddressMode},
+};
+
+/// Width and height of the pages of the overlay atlas
+const ATLAS_SIZE: u32 = 1024;
+
+/// An image in the overlay atlas
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum OverlayImage {
+    /// A single white texel, for quads of a solid color
+    Solid,
+    Sprite(String),
+    /// A glyph of one of the Fonts, rasterized `size` pixels high
+    Glyph {
+        font: usize,
+        size: u32,
+        glyph: u16,
+    },
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Default)]
+pub struct OverlayVertex {
+    /// In pixels from the top left of the window
+    pub position: [f32; 2],
+    pub uv: [f32; 2],
+    /// The page of the atlas
+    pub layer: f32,
+    /// Gamma encoded, like the scene
+    pub color: [f32; 4],
+}
+
+impl_vertex!(OverlayVertex, position, uv, layer, color);
+
+/// Resource collecting the quads drawn over the screen this frame, with the atlas they sample
+///
+/// Quads are drawn over everything else, after post processing, in the order they were added.
+/// They are all drawn with a single draw call, and cleared once the renderer has taken them.
+/// Positions are in pixels from the top left of the window.
+pub struct Overlay {
+    atlas: Atlas<OverlayImage>,
+    fonts: Fonts,
+    solid: AtlasRegion,
+    vertices: Vec<OverlayVertex>,
+    /// The size of the window in pixels, as of the last frame
+    dimensions: [f32; 2],
+}
+
+impl Overlay {
+    pub fn new() -> Self {
+        let mut atlas = Atlas::new(ATLAS_SIZE);
+        let solid = atlas.insert(OverlayImage::Solid, 1, 1, &[[255; 4]]);
+
+        Self {
+            atlas,
+            fonts: Fonts::default(),
+            solid,
+            vertices: Vec::new(),
+            dimensions: [0.0, 0.0],
+        }
+    }
+
+    /// The size of the window in pixels, as of the last frame, or zero before the first one
+    pub fn dimensions(&self) -> [f32; 2] {
+        self.dimensions
+    }
+
+    /// Set by the renderer, from the dimensions of the swapchain
+    pub fn set_dimensions(&mut self, dimensions: [u32; 2]) {
+        self.dimensions = [dimensions[0] as f32, dimensions[1] as f32];
+    }
+
+    pub fn atlas_mut(&mut self) -> &mut Atlas<OverlayImage> {
+        &mut self.atlas
+    }
+
+    /// The fonts text is drawn with, see `Fonts`
+    pub fn fonts_mut(&mut self) -> &mut Fonts {
+        &mut self.fonts
+    }
+
+    /// Packs an image into the atlas, to be drawn with `sprite`
+    pub fn add_sprite(&mut self, name: &str, image: &RgbaImage) -> AtlasRegion {
+        let texels = image.pixels().map(|pixel| pixel.data).collect::<Vec<_>>();
+        self.atlas.insert(
+            OverlayImage::Sprite(name.to_string()),
+            image.width(),
+            image.height(),
+            &texels,
+        )
+    }
+
+    /// A rectangle of a solid color
+    pub fn rect(&mut self, position: [f32; 2], size: [f32; 2], color: [f32; 4]) {
+        let solid = self.solid;
+        self.quad(position, size, &solid, color);
+    }
+
+    /// A sprite added with `add_sprite`, tinted by `color`
+    ///
+    /// Returns false, and draws nothing, if there is no sprite with the name.
+    pub fn sprite(
+        &mut self,
+        name: &str,
+        position: [f32; 2],
+        size: [f32; 2],
+        color: [f32; 4],
+    ) -> bool {
+        match self.atlas.get(&OverlayImage::Sprite(name.to_string())) {
+            Some(region) => {
+                self.quad(position, size, &region, color);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Lines of text `size` pixels high, with the top left at `position`, and returns their size
+    ///
+    /// Nothing is drawn if no fonts have been loaded.
+    pub fn text(&mut self, text: &str, position: [f32; 2], size: f32, color: [f32; 4]) -> [f32; 2] {
+        let (glyphs, extent) = self.fonts.layout(&mut self.atlas, text, position, size);
+        for glyph in glyphs {
+            self.quad(glyph.position, glyph.size, &glyph.region, color);
+        }
+
+        extent
+    }
+
+    /// The size of text drawn by `text`, without drawing it
+    pub fn measure_text(&mut self, text: &str, size: f32) -> [f32; 2] {
+        self.layout_text(text, size).1
+    }
+
+    /// The glyphs of text drawn by `text` at the top left of the window, for drawing it elsewhere
+    pub fn layout_text(&mut self, text: &str, size: f32) -> (Vec<PlacedGlyph>, [f32; 2]) {
+        self.fonts.layout(&mut self.atlas, text, [0.0, 0.0], size)
+    }
+
+    /// A quad showing a region of the atlas
+    pub fn quad(
+        &mut self,
+        position: [f32; 2],
+        size: [f32; 2],
+        region: &AtlasRegion,
+        color: [f32; 4],
+    ) {
+        let [x, y] = position;
+        let [width, height] = size;
+        let [u0, v0] = region.uv_min;
+        let [u1, v1] = region.uv_max;
+        let layer = region.page as f32;
+
+        let vertex = |position, uv| OverlayVertex {
+            position,
+            uv,
+            layer,
+            color,
+        };
+
+        let top_left = vertex([x, y], [u0, v0]);
+        let top_right = vertex([x + width, y], [u1, v0]);
+        let bottom_left = vertex([x, y + height], [u0, v1]);
+        let bottom_right = vertex([x + width, y + height], [u1, v1]);
+
+        self.vertices.extend_from_slice(&[
+            top_left.clone(),
+            bottom_left.clone(),
+            top_right.clone(),
+            top_right,
+            bottom_left,
+            bottom_right,
+        ]);
+    }
+
+    /// Takes the vertices of every quad added this frame
+    pub fn take_vertices(&mut self) -> Vec<OverlayVertex> {
+        std::mem::replace(&mut self.vertices, Vec::new())
+    }
+}
+
+impl Default for Overlay {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Draws the quads of the Overlay, as part of the post pass
+///
+/// The atlas is uploaded again as a whole whenever something was packed into it, as an array
+/// texture with a layer per page.
+pub struct OverlayPass {
+    device: Arc<Device>,
+    memory: BufferAllocator,
+    pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+    sampler: Arc<Sampler>,
+    atlas: Option<Arc<ImmutableImage<Format>>>,
+    descriptor_set: Option<Arc<dyn DescriptorSet + Send + Sync>>,
+}
+
+impl OverlayPass {
+    /// Creates the pass, drawing in the first subpass of `render_pass`
+    pub fn new(
+        device: Arc<Device>,
+        memory: BufferAllocator,
+        render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+        sc: OverlaySC,
+        shaders: &ShaderSet,
+    ) -> Self {
+        let pipeline = build_pipeline(device.clone(), render_pass, sc, shaders);
+
+        // Sprites are scaled, but should not bleed into their neighbours
+        let sampler = Sampler::new(
+            device.clone(),
+            Filter::Linear,
+            Filter::Linear,
+            MipmapMode::Nearest,
+            SamplerAddressMode::ClampToEdge,
+            SamplerAddressMode::ClampToEdge,
+            SamplerAddressMode::ClampToEdge,
+            0.0,
+            1.0,
+            0.0,
+            0.0,
+        )
+        .unwrap();
+
+        Self {
+            device,
+            memory,
+            pipeline,
+            sampler,
+            atlas: None,
+            descriptor_set: None,
+        }
+    }
+
+    /// The uploaded atlas, for the WorldTextPass
+    pub fn atlas(&self) -> Option<Arc<ImmutableImage<Format>>> {
+        self.atlas.clone()
+    }
+
+    /// Rebuilds the pipeline, after the render pass it draws in has been rebuilt
+    pub fn set_render_pass(
+        &mut self,
+        render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+        sc: OverlaySC,
+        shaders: &ShaderSet,
+    ) {
+        self.pipeline = build_pipeline(self.device.clone(), render_pass, sc, shaders);
+        self.update_descriptor_set();
+    }
+
+    /// Uploads the atlas, if anything was packed into it since the last upload
+    pub fn update_atlas(&mut self, uploads: &mut UploadScheduler, atlas: &mut Atlas<OverlayImage>) {
+        if !atlas.take_dirty() {
+            return;
+        }
+
+        let dimensions = Dimensions::Dim2dArray {
+            width: atlas.size(),
+            height: atlas.size(),
+            array_layers: atlas.page_count(),
+        };
+
+        let usage = ImageUsage {
+            transfer_destination: true,
+            sampled: true,
+            ..ImageUsage::none()
+        };
+
+        let (image, init) = ImmutableImage::uninitialized(
+            self.device.clone(),
+            dimensions,
+            Format::R8G8B8A8Unorm,
+            MipmapsCount::One,
+            usage,
+            ImageLayout::ShaderReadOnlyOptimal,
+            self.device.active_queue_families(),
+        )
+        .unwrap();
+
+        let texels = self
+            .memory
+            .sub_array(MemoryUse::Transient, atlas.texels())
+            .unwrap();
+
+        uploads.copy_buffer_to_image(texels, init);
+
+        self.atlas = Some(image);
+        self.update_descriptor_set();
+    }
+
+    fn update_descriptor_set(&mut self) {
+        let atlas = match self.atlas.clone() {
+            Some(atlas) => atlas,
+            None => return,
+        };
+
+        self.descriptor_set = Some(Arc::new(
+            PersistentDescriptorSet::start(self.pipeline.clone(), 0)
+                .add_sampled_image(atlas, self.sampler.clone())
+                .unwrap()
+                .build()
+                .unwrap(),
+        ));
+    }
+
+    /// Records drawing the quads in a single draw call, inside the render pass of the pipeline
+    pub fn draw(
+        &self,
+        builder: AutoCommandBufferBuilder,
+        dimensions: [u32; 2],
+        vertices: &[OverlayVertex],
+    ) -> AutoCommandBufferBuilder {
+        let descriptor_set = match &self.descriptor_set {
+            Some(descriptor_set) if !vertices.is_empty() => descriptor_set.clone(),
+            _ => return builder,
+        };
+
+        let [width, height] = dimensions;
+        let dynamic_state = DynamicState {
+            line_width: None,
+            viewports: Some(vec![Viewport {
+                origin: [0.0, 0.0],
+                dimensions: [width as f32, height as f32],
+                depth_range: 0.0..1.0,
+            }]),
+            scissors: None,
+        };
+
+        let vertex_buffer = self
+            .memory
+            .sub_array(MemoryUse::Transient, vertices.iter().cloned())
+            .unwrap();
+
+        let pc = OverlayPushConstants {
+            inverse_size: [1.0 / width as f32, 1.0 / height as f32],
+        };
+
+        builder
+            .draw(
+                self.pipeline.clone(),
+                &dynamic_state,
+                vec![vertex_buffer],
+                descriptor_set,
+                pc,
+            )
+            .unwrap()
+    }
+}
+
+fn build_pipeline(
+    device: Arc<Device>,
+    render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+    sc: OverlaySC,
+    shaders: &ShaderSet,
+) -> Arc<dyn GraphicsPipelineAbstract + Send + Sync> {
+    Arc::new(
+        GraphicsPipeline::start()
+            .vertex_input_single_buffer::<OverlayVertex>()
+            .vertex_shader(shaders.overlay_vertex.main_entry_point(), ())
+            .triangle_list()
+            .viewports_dynamic_scissors_irrelevant(1)
+            .fragment_shader(shaders.overlay_fragment.main_entry_point(), sc)
+            .blend_alpha_blending()
+            .render_pass(Subpass::from(render_pass, 0).unwrap())
+            .build(device)
+            .unwrap(),
+    )
+}
@@ -0,0 +1,173 @@
+use crate::renderer::{
+    settings::ShadingConstants,
+    shaders::ShaderSet,
+    vertex::{MeshVertexDefinition, VertexBuffer},
+};
+use log::debug;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+use vulkano::{
+    device::Device, framebuffer::RenderPassAbstract, pipeline::GraphicsPipelineAbstract,
+};
+
+/// The layout of the vertices in a mesh's vertex buffer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum VertexLayout {
+    Full,
+    Quantized,
+}
+
+impl VertexLayout {
+    pub const ALL: [VertexLayout; 2] = [VertexLayout::Full, VertexLayout::Quantized];
+
+    pub fn of(vertex_buffer: &VertexBuffer) -> Self {
+        if vertex_buffer.is_quantized() {
+            VertexLayout::Quantized
+        } else {
+            VertexLayout::Full
+        }
+    }
+
+    pub fn definition(self) -> MeshVertexDefinition {
+        MeshVertexDefinition::new(self == VertexLayout::Quantized)
+    }
+}
+
+/// The features of a material that need a pipeline of their own, as a set of bits
+///
+/// No material has any yet. Textures, skinning and instancing each get a bit here, which the
+/// pipelines turn into shader permutations or specialization constants.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct MaterialFeatures(pub u32);
+
+/// What a mesh pipeline draws
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MeshPass {
+    /// Opaque, lit meshes
+    Main,
+    /// Translucent, unlit ghost meshes
+    Ghost,
+}
+
+/// Everything a mesh pipeline is specialized for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PipelineKey {
+    pub layout: VertexLayout,
+    pub features: MaterialFeatures,
+    pub pass: MeshPass,
+}
+
+impl PipelineKey {
+    pub fn new(layout: VertexLayout, features: MaterialFeatures, pass: MeshPass) -> Self {
+        Self {
+            layout,
+            features,
+            pass,
+        }
+    }
+
+    /// The pipelines every scene needs, built before the first frame instead of during it
+    pub fn common() -> Vec<Self> {
+        [MeshPass::Main, MeshPass::Ghost]
+            .iter()
+            .flat_map(|&pass| {
+                VertexLayout::ALL
+                    .iter()
+                    .map(move |&layout| Self::new(layout, MaterialFeatures::default(), pass))
+            })
+            .collect()
+    }
+}
+
+/// The mesh pipelines drawing into the main render pass, built the first time they are needed
+///
+/// Building a pipeline takes long enough to stall a frame, so the ones known to be needed are
+/// built by `warm_up` while loading. Meshes are recorded on several threads at once, which all
+/// share the cache.
+pub struct PipelineCache {
+    device: Arc<Device>,
+    render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+    reversed_z: bool,
+    shading: ShadingConstants,
+    pipelines: Mutex<HashMap<PipelineKey, Arc<dyn GraphicsPipelineAbstract + Send + Sync>>>,
+}
+
+impl PipelineCache {
+    /// An empty cache for pipelines drawing into `render_pass`
+    pub fn new(
+        device: Arc<Device>,
+        render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+        reversed_z: bool,
+        shading: ShadingConstants,
+    ) -> Self {
+        Self {
+            device,
+            render_pass,
+            reversed_z,
+            shading,
+            pipelines: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The pipeline for a key, building it if it is not in the cache yet
+    pub fn get(
+        &self,
+        key: PipelineKey,
+        shaders: &ShaderSet,
+    ) -> Arc<dyn GraphicsPipelineAbstract + Send + Sync> {
+        let mut pipelines = self.pipelines.lock().unwrap();
+
+        pipelines
+            .entry(key)
+            .or_insert_with(|| {
+                debug!("Building pipeline {:?}", key);
+                super::build_mesh_pipeline(
+                    self.device.clone(),
+                    self.render_pass.clone(),
+                    shaders,
+                    key,
+                    self.reversed_z,
+                    self.shading,
+                )
+            })
+            .clone()
+    }
+
+    /// Builds the pipelines for the keys ahead of time
+    pub fn warm_up<I>(&self, keys: I, shaders: &ShaderSet)
+    where
+        I: IntoIterator<Item = PipelineKey>,
+    {
+        for key in keys {
+            self.get(key, shaders);
+        }
+    }
+
+    /// The keys of the pipelines built so far
+    pub fn keys(&self) -> Vec<PipelineKey> {
+        self.pipelines.lock().unwrap().keys().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{MaterialFeatures, MeshPass, PipelineKey, VertexLayout};
+    use std::collections::HashSet;
+
+    #[test]
+    fn common_keys() {
+        let keys = PipelineKey::common();
+        assert_eq!(keys.len(), 4);
+
+        // Every combination of pass and layout, once
+        let unique = keys.iter().cloned().collect::<HashSet<_>>();
+        assert_eq!(unique.len(), keys.len());
+        assert!(unique.contains(&PipelineKey::new(
+            VertexLayout::Quantized,
+            MaterialFeatures::default(),
+            MeshPass::Ghost
+        )));
+    }
+}
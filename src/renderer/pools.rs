@@ -0,0 +1,78 @@
+use crate::renderer::queues::Queues;
+use std::{collections::HashMap, sync::Arc};
+use vulkano::{
+    command_buffer::{pool::standard::StandardCommandPool, AutoCommandBufferBuilder},
+    device::{Device, Queue},
+    framebuffer::{RenderPassAbstract, Subpass},
+};
+
+/// The command pools of every queue family the renderer submits to
+///
+/// Command buffers have to be allocated from a pool of the family of the queue they are
+/// submitted to, so the helpers here take the queue and pick the pool from it, instead of taking
+/// a family that could be the wrong one. Each standard pool hands out per thread pools on its own,
+/// so builders can be made from any thread.
+///
+/// The device only keeps weak references to its standard pools, holding them here keeps them
+/// from being destroyed and recreated between frames.
+#[derive(Clone)]
+pub struct CommandPools {
+    device: Arc<Device>,
+    pools: HashMap<u32, Arc<StandardCommandPool>>,
+}
+
+impl CommandPools {
+    pub fn new(device: Arc<Device>, queues: &Queues) -> Self {
+        let pools = [
+            &queues.general,
+            &queues.compute,
+            &queues.graphics,
+            &queues.present,
+            &queues.transfer,
+        ]
+        .iter()
+        .map(|queue| {
+            let family = queue.family();
+            (family.id(), Device::standard_command_pool(&device, family))
+        })
+        .collect();
+
+        Self { device, pools }
+    }
+
+    fn check_family(&self, queue: &Queue) {
+        debug_assert!(
+            self.pools.contains_key(&queue.family().id()),
+            "No command pool for queue family {}",
+            queue.family().id()
+        );
+    }
+
+    /// A primary command buffer, to be submitted once to `queue`
+    pub fn primary(&self, queue: &Queue) -> AutoCommandBufferBuilder {
+        self.check_family(queue);
+
+        AutoCommandBufferBuilder::primary_one_time_submit(self.device.clone(), queue.family())
+            .unwrap()
+    }
+
+    /// A secondary command buffer drawing in `subpass`, to be executed once by a primary command
+    /// buffer submitted to `queue`
+    pub fn secondary_graphics<R>(
+        &self,
+        queue: &Queue,
+        subpass: Subpass<R>,
+    ) -> AutoCommandBufferBuilder
+    where
+        R: RenderPassAbstract + Clone + Send + Sync + 'static,
+    {
+        self.check_family(queue);
+
+        AutoCommandBufferBuilder::secondary_graphics_one_time_submit(
+            self.device.clone(),
+            queue.family(),
+            subpass,
+        )
+        .unwrap()
+    }
+}
@@ -0,0 +1,413 @@
+use crate::renderer::{
+    config::SurfaceFormat,
+    grading::Lut,
+    memory::{BufferAllocator, MemoryUse},
+    settings::AaMode,
+    shaders::{OverlaySC, PostPushConstants, ShaderSet},
+    upload::UploadScheduler,
+    Window,
+};
+use std::sync::Arc;
+use vulkano::{
+    command_buffer::{AutoCommandBufferBuilder, DynamicState},
+    descriptor::{descriptor_set::PersistentDescriptorSet, DescriptorSet},
+    device::Device,
+    format::{ClearValue, Format},
+    framebuffer::{Framebuffer, FramebufferAbstract, RenderPassAbstract, Subpass},
+    image::{
+        attachment::AttachmentImage, Dimensions, ImageLayout, ImageUsage, ImmutableImage,
+        MipmapsCount, SwapchainImage,
+    },
+    pipeline::{
+        vertex::{BufferlessDefinition, BufferlessVertices},
+        viewport::{Scissor, Viewport},
+        GraphicsPipeline, GraphicsPipelineAbstract,
+    },
+    sampler::{Filter, MipmapMode, Sampler, SamplerAddressMode},
+    single_pass_renderpass,
+};
+
+/// The format the scene is rendered in, before it is drawn to the swapchain
+pub const SCENE_FORMAT: Format = Format::R16G16B16A16Sfloat;
+
+/// The part of the screen showing what one camera sees, with the exposure of the camera
+#[derive(Debug, Clone)]
+pub struct PostView {
+    pub scissor: Scissor,
+    pub exposure: f32,
+}
+
+/// Where the minimap is drawn on the screen, with the exposure of its camera
+#[derive(Debug, Clone)]
+pub struct PostMinimap {
+    pub viewport: Viewport,
+    pub scissor: Scissor,
+    pub exposure: f32,
+}
+
+/// Draws the rendered scene to a swapchain image, applying the anti-aliasing of the RenderSettings
+///
+/// The scene image is sampled by a single fullscreen triangle, either copied as is or filtered by
+/// FXAA. Either way the result is color graded by a 3D LUT, and encoded for the swapchain's format
+/// and color space last.
+pub struct PostPass {
+    device: Arc<Device>,
+    memory: BufferAllocator,
+    render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+    copy_pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+    fxaa_pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+    sampler: Arc<Sampler>,
+    surface_format: SurfaceFormat,
+    paper_white: f32,
+    scene: Option<Arc<AttachmentImage>>,
+    lut: Arc<ImmutableImage<Format>>,
+    descriptor_set: Option<Arc<dyn DescriptorSet + Send + Sync>>,
+    minimap: Option<Arc<AttachmentImage>>,
+    minimap_descriptor_set: Option<Arc<dyn DescriptorSet + Send + Sync>>,
+    framebuffers: Vec<Arc<dyn FramebufferAbstract + Send + Sync>>,
+}
+
+impl PostPass {
+    /// Creates the pass, drawing to swapchain images of `surface_format`
+    ///
+    /// Colors are left as they are until another LUT is set.
+    pub fn new(
+        device: Arc<Device>,
+        memory: BufferAllocator,
+        surface_format: SurfaceFormat,
+        paper_white: f32,
+        shaders: &ShaderSet,
+        uploads: &mut UploadScheduler,
+    ) -> Self {
+        let (render_pass, copy_pipeline, fxaa_pipeline) =
+            build_pipelines(device.clone(), surface_format.format, shaders);
+
+        // FXAA samples between pixels, and neither it nor the LUT should wrap around at the edges
+        let sampler = Sampler::new(
+            device.clone(),
+            Filter::Linear,
+            Filter::Linear,
+            MipmapMode::Nearest,
+            SamplerAddressMode::ClampToEdge,
+            SamplerAddressMode::ClampToEdge,
+            SamplerAddressMode::ClampToEdge,
+            0.0,
+            1.0,
+            0.0,
+            0.0,
+        )
+        .unwrap();
+
+        let lut = upload_lut(device.clone(), &memory, uploads, &Lut::identity(2));
+
+        Self {
+            device,
+            memory,
+            render_pass,
+            copy_pipeline,
+            fxaa_pipeline,
+            sampler,
+            surface_format,
+            paper_white,
+            scene: None,
+            lut,
+            descriptor_set: None,
+            minimap: None,
+            minimap_descriptor_set: None,
+            framebuffers: Vec::new(),
+        }
+    }
+
+    /// Rebuilds the pass for swapchain images of another format
+    ///
+    /// The framebuffers are dropped, and have to be recreated for the new swapchain images.
+    pub fn set_surface_format(&mut self, surface_format: SurfaceFormat, shaders: &ShaderSet) {
+        let (render_pass, copy_pipeline, fxaa_pipeline) =
+            build_pipelines(self.device.clone(), surface_format.format, shaders);
+
+        self.render_pass = render_pass;
+        self.copy_pipeline = copy_pipeline;
+        self.fxaa_pipeline = fxaa_pipeline;
+        self.surface_format = surface_format;
+        self.framebuffers.clear();
+    }
+
+    /// The render pass drawing to the swapchain images, which the overlay is drawn in as well
+    pub fn render_pass(&self) -> Arc<dyn RenderPassAbstract + Send + Sync> {
+        self.render_pass.clone()
+    }
+
+    /// Specializes the overlay for the encoding of the swapchain images
+    pub fn overlay_constants(&self) -> OverlaySC {
+        OverlaySC {
+            output_transfer: self.surface_format.transfer as i32,
+            output_paper_white: self.paper_white,
+        }
+    }
+
+    /// Replaces the color grading LUT
+    pub fn set_lut(&mut self, uploads: &mut UploadScheduler, lut: &Lut) {
+        self.lut = upload_lut(self.device.clone(), &self.memory, uploads, lut);
+        self.update_descriptor_set();
+    }
+
+    /// Recreates the framebuffers of the swapchain images, and binds the new scene image
+    pub fn recreate_framebuffers(
+        &mut self,
+        images: &[Arc<SwapchainImage<Window>>],
+        scene: Arc<AttachmentImage>,
+    ) {
+        self.framebuffers = images
+            .iter()
+            .map(|image| {
+                Arc::new(
+                    Framebuffer::start(self.render_pass.clone())
+                        .add(image.clone())
+                        .unwrap()
+                        .build()
+                        .unwrap(),
+                ) as Arc<dyn FramebufferAbstract + Send + Sync>
+            })
+            .collect();
+
+        self.scene = Some(scene);
+        self.update_descriptor_set();
+    }
+
+    /// Drops the framebuffers, and with them the references to the swapchain images
+    pub fn release_framebuffers(&mut self) {
+        self.framebuffers.clear();
+    }
+
+    /// Binds the image the minimap was rendered to, or None while there is no minimap
+    pub fn set_minimap(&mut self, minimap: Option<Arc<AttachmentImage>>) {
+        let changed = match (&self.minimap, &minimap) {
+            (Some(old), Some(new)) => !Arc::ptr_eq(old, new),
+            (None, None) => false,
+            _ => true,
+        };
+
+        if changed {
+            self.minimap = minimap;
+            self.update_descriptor_set();
+        }
+    }
+
+    fn update_descriptor_set(&mut self) {
+        // Both pipelines share the same layout, so one set works for either
+        let descriptor_set = |image| {
+            Arc::new(
+                PersistentDescriptorSet::start(self.fxaa_pipeline.clone(), 0)
+                    .add_sampled_image(image, self.sampler.clone())
+                    .unwrap()
+                    .add_sampled_image(self.lut.clone(), self.sampler.clone())
+                    .unwrap()
+                    .build()
+                    .unwrap(),
+            ) as Arc<dyn DescriptorSet + Send + Sync>
+        };
+
+        self.minimap_descriptor_set = self.minimap.clone().map(descriptor_set);
+
+        if let Some(scene) = self.scene.clone() {
+            self.descriptor_set = Some(descriptor_set(scene));
+        }
+    }
+
+    /// Records drawing the scene to swapchain image `image_number`
+    ///
+    /// Each view is drawn on its own, cut out by a scissor, so every camera gets its own exposure.
+    /// The minimap is drawn over the views, and `overlay` records drawing over all of them,
+    /// before the render pass ends.
+    ///
+    /// Has to be executed after the scene's render pass, with a semaphore in between so the
+    /// scene image is visible to this pass.
+    pub fn draw<F>(
+        &self,
+        builder: AutoCommandBufferBuilder,
+        image_number: usize,
+        dimensions: [u32; 2],
+        views: &[PostView],
+        minimap: Option<&PostMinimap>,
+        aa_mode: AaMode,
+        overlay: F,
+    ) -> AutoCommandBufferBuilder
+    where
+        F: FnOnce(AutoCommandBufferBuilder) -> AutoCommandBufferBuilder,
+    {
+        let [width, height] = dimensions;
+
+        let descriptor_set = self.descriptor_set.clone().unwrap();
+
+        let pipeline = match aa_mode {
+            AaMode::None => self.copy_pipeline.clone(),
+            AaMode::Fxaa => self.fxaa_pipeline.clone(),
+        };
+
+        let builder = builder
+            .begin_render_pass(
+                self.framebuffers[image_number].clone(),
+                false,
+                vec![ClearValue::None],
+            )
+            .unwrap();
+
+        let vertices = || BufferlessVertices {
+            vertices: 3,
+            instances: 1,
+        };
+
+        let builder = views.iter().fold(builder, |builder, view| {
+            // The triangle still covers the whole screen, so the uvs match the scene image
+            let dynamic_state = DynamicState {
+                line_width: None,
+                viewports: Some(vec![Viewport {
+                    origin: [0.0, 0.0],
+                    dimensions: [width as f32, height as f32],
+                    depth_range: 0.0..1.0,
+                }]),
+                scissors: Some(vec![view.scissor.clone()]),
+            };
+
+            let pc = PostPushConstants {
+                inverse_size: [1.0 / width as f32, 1.0 / height as f32],
+                transfer: self.surface_format.transfer as i32,
+                paper_white: self.paper_white,
+                exposure: view.exposure,
+            };
+
+            builder
+                .draw(
+                    pipeline.clone(),
+                    &dynamic_state,
+                    vertices(),
+                    descriptor_set.clone(),
+                    pc,
+                )
+                .unwrap()
+        });
+
+        // The triangle covers just the minimap's viewport here, so all of its image is drawn
+        // into it. It is copied without FXAA, which would blur it against the scene behind it
+        let builder = match (minimap, &self.minimap_descriptor_set) {
+            (Some(minimap), Some(minimap_descriptor_set)) => {
+                let viewport = &minimap.viewport;
+                let dynamic_state = DynamicState {
+                    line_width: None,
+                    viewports: Some(vec![viewport.clone()]),
+                    scissors: Some(vec![minimap.scissor.clone()]),
+                };
+
+                let pc = PostPushConstants {
+                    inverse_size: [1.0 / viewport.dimensions[0], 1.0 / viewport.dimensions[1]],
+                    transfer: self.surface_format.transfer as i32,
+                    paper_white: self.paper_white,
+                    exposure: minimap.exposure,
+                };
+
+                builder
+                    .draw(
+                        self.copy_pipeline.clone(),
+                        &dynamic_state,
+                        vertices(),
+                        minimap_descriptor_set.clone(),
+                        pc,
+                    )
+                    .unwrap()
+            }
+            _ => builder,
+        };
+
+        overlay(builder).end_render_pass().unwrap()
+    }
+}
+
+/// Creates the render pass drawing to a swapchain image of `format`, and both pipelines of the
+/// pass
+fn build_pipelines(
+    device: Arc<Device>,
+    format: Format,
+    shaders: &ShaderSet,
+) -> (
+    Arc<dyn RenderPassAbstract + Send + Sync>,
+    Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+    Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+) {
+    let render_pass = Arc::new(
+        single_pass_renderpass!(device.clone(),
+            attachments: {
+                // Every pixel is overwritten, so the old contents are not needed
+                color: {
+                    load: DontCare,
+                    store: Store,
+                    format: format,
+                    samples: 1,
+                }
+            },
+            pass: {
+                color: [color],
+                depth_stencil: {}
+            }
+        )
+        .unwrap(),
+    ) as Arc<dyn RenderPassAbstract + Send + Sync>;
+
+    let build_pipeline = |fragment_shader| {
+        Arc::new(
+            GraphicsPipeline::start()
+                .vertex_input(BufferlessDefinition)
+                .vertex_shader(shaders.fullscreen_vertex.main_entry_point(), ())
+                .triangle_list()
+                .viewports_scissors_dynamic(1)
+                .fragment_shader(fragment_shader, ())
+                .render_pass(Subpass::from(render_pass.clone(), 0).unwrap())
+                .build(device.clone())
+                .unwrap(),
+        ) as Arc<dyn GraphicsPipelineAbstract + Send + Sync>
+    };
+
+    let copy_pipeline = build_pipeline(shaders.copy_fragment.main_entry_point());
+    let fxaa_pipeline = build_pipeline(shaders.fxaa_fragment.main_entry_point());
+
+    (render_pass, copy_pipeline, fxaa_pipeline)
+}
+
+/// Creates a 3D image for a LUT, filled by the next flush of `uploads`
+fn upload_lut(
+    device: Arc<Device>,
+    memory: &BufferAllocator,
+    uploads: &mut UploadScheduler,
+    lut: &Lut,
+) -> Arc<ImmutableImage<Format>> {
+    let size = lut.size();
+    let dimensions = Dimensions::Dim3d {
+        width: size,
+        height: size,
+        depth: size,
+    };
+
+    let usage = ImageUsage {
+        transfer_destination: true,
+        sampled: true,
+        ..ImageUsage::none()
+    };
+
+    let (image, init) = ImmutableImage::uninitialized(
+        device.clone(),
+        dimensions,
+        Format::R8G8B8A8Unorm,
+        MipmapsCount::One,
+        usage,
+        ImageLayout::ShaderReadOnlyOptimal,
+        device.active_queue_families(),
+    )
+    .unwrap();
+
+    let texels = memory
+        .sub_array(MemoryUse::Transient, lut.texels().iter().cloned())
+        .unwrap();
+
+    uploads.copy_buffer_to_image(texels, init);
+
+    image
+}
@@ -0,0 +1,281 @@
+use crate::renderer::{frame::FRAMES_IN_FLIGHT, labels::DebugLabels, stats::PassTimes};
+use log::warn;
+use std::{mem, sync::Arc};
+use vulkano::{
+    buffer::BufferAccess,
+    command_buffer::{
+        pool::{
+            CommandPool, CommandPoolBuilderAlloc, StandardCommandPoolAlloc,
+            StandardCommandPoolBuilder,
+        },
+        sys::{Flags, Kind, UnsafeCommandBuffer, UnsafeCommandBufferBuilder},
+        CommandBuffer, CommandBufferExecError,
+    },
+    device::{Device, DeviceOwned, Queue},
+    framebuffer::{FramebufferAbstract, RenderPassAbstract},
+    image::{ImageAccess, ImageLayout},
+    query::{QueryType, UnsafeQueryPool},
+    sync::{AccessCheckError, AccessFlagBits, GpuFuture, PipelineStages},
+    VulkanObject,
+};
+
+/// The passes timed on the GPU
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pass {
+    Culling = 0,
+    Main = 1,
+    Post = 2,
+    LightShafts = 3,
+}
+
+impl Pass {
+    /// The name of the pass in debug labels
+    fn name(self) -> &'static str {
+        match self {
+            Pass::Culling => "culling",
+            Pass::Main => "mesh draws",
+            Pass::Post => "post",
+            Pass::LightShafts => "light shafts",
+        }
+    }
+
+    /// The color of the pass in debug labels
+    fn color(self) -> [f32; 4] {
+        match self {
+            Pass::Culling => [0.2, 0.6, 1.0, 1.0],
+            Pass::Main => [0.2, 1.0, 0.4, 1.0],
+            Pass::Post => [1.0, 0.6, 0.2, 1.0],
+            Pass::LightShafts => [1.0, 0.9, 0.5, 1.0],
+        }
+    }
+}
+
+const PASS_COUNT: u32 = 4;
+/// A timestamp at the start and at the end of every pass
+const QUERIES_PER_FRAME: u32 = PASS_COUNT * 2;
+
+/// Times the passes of each frame with timestamp queries, and labels them for debuggers
+///
+/// vulkano can not record queries or labels into its command buffers, so they are written by a
+/// tiny command buffer of its own, executed on the same queue right before or after the pass.
+/// The timestamps of a frame are read once its fence has been waited on, when they are known to
+/// be available.
+///
+/// Labels are only recorded with the debug-labels feature.
+pub struct GpuProfiler {
+    device: Arc<Device>,
+    pool: UnsafeQueryPool,
+    /// Nanoseconds per timestamp tick
+    period: f32,
+    enabled: bool,
+    labels: Option<DebugLabels>,
+    /// The passes timed in each frame in flight, that have not been read yet
+    pending: Vec<Vec<Pass>>,
+}
+
+impl GpuProfiler {
+    pub fn new(device: Arc<Device>, labels: Option<DebugLabels>) -> Self {
+        let limits = device.physical_device().limits();
+        let period = limits.timestamp_period();
+        let enabled = limits.timestamp_compute_and_graphics() != 0;
+
+        if !enabled {
+            warn!("Timestamp queries are not supported, GPU timings are unavailable");
+        }
+
+        let pool = UnsafeQueryPool::new(
+            device.clone(),
+            QueryType::Timestamp,
+            QUERIES_PER_FRAME * FRAMES_IN_FLIGHT as u32,
+        )
+        .unwrap();
+
+        Self {
+            device,
+            pool,
+            period,
+            enabled,
+            labels,
+            pending: vec![Vec::new(); FRAMES_IN_FLIGHT],
+        }
+    }
+
+    /// A command buffer marking the start of `pass`
+    pub fn begin(&mut self, frame_index: usize, pass: Pass, queue: &Queue) -> MarkerCommandBuffer {
+        self.marker(
+            Self::query(frame_index, pass),
+            queue,
+            |labels, command_buffer| unsafe {
+                labels.begin(command_buffer, pass.name(), pass.color());
+            },
+        )
+    }
+
+    /// A command buffer marking the end of `pass`
+    pub fn end(&mut self, frame_index: usize, pass: Pass, queue: &Queue) -> MarkerCommandBuffer {
+        self.pending[frame_index].push(pass);
+        self.marker(
+            Self::query(frame_index, pass) + 1,
+            queue,
+            |labels, command_buffer| unsafe {
+                labels.end(command_buffer);
+            },
+        )
+    }
+
+    /// Reads the timings of the passes of a frame, which has to be finished on the GPU
+    ///
+    /// Passes that were not timed in the frame keep their previous timings.
+    pub fn read(&mut self, frame_index: usize, times: &mut PassTimes) {
+        for pass in self.pending[frame_index].drain(..) {
+            if !self.enabled {
+                continue;
+            }
+
+            let mut timestamps = [0u64; 2];
+
+            // The queries are only reset and written by command buffers that have finished
+            let result = unsafe {
+                let vk = self.device.pointers();
+                vk.GetQueryPoolResults(
+                    self.device.internal_object(),
+                    self.pool.internal_object(),
+                    Self::query(frame_index, pass),
+                    2,
+                    mem::size_of_val(&timestamps),
+                    timestamps.as_mut_ptr() as *mut _,
+                    mem::size_of::<u64>() as u64,
+                    vk_sys::QUERY_RESULT_64_BIT,
+                )
+            };
+
+            if result != vk_sys::SUCCESS {
+                continue;
+            }
+
+            let ms = timestamps[1].saturating_sub(timestamps[0]) as f32 * self.period / 1_000_000.0;
+
+            match pass {
+                Pass::Culling => times.culling = ms,
+                Pass::Main => times.main = ms,
+                Pass::Post => times.post = ms,
+                Pass::LightShafts => times.light_shafts = ms,
+            }
+        }
+    }
+
+    fn query(frame_index: usize, pass: Pass) -> u32 {
+        frame_index as u32 * QUERIES_PER_FRAME + pass as u32 * 2
+    }
+
+    /// Records a command buffer writing timestamp `index`, along with a label command
+    fn marker<F>(&self, index: u32, queue: &Queue, label: F) -> MarkerCommandBuffer
+    where
+        F: FnOnce(&DebugLabels, vk_sys::CommandBuffer),
+    {
+        MarkerCommandBuffer::record(
+            self.device.clone(),
+            queue,
+            Kind::primary(),
+            |command_buffer| unsafe {
+                if self.enabled {
+                    let vk = self.device.pointers();
+                    vk.CmdResetQueryPool(command_buffer, self.pool.internal_object(), index, 1);
+                    vk.CmdWriteTimestamp(
+                        command_buffer,
+                        vk_sys::PIPELINE_STAGE_BOTTOM_OF_PIPE_BIT,
+                        self.pool.internal_object(),
+                        index,
+                    );
+                }
+
+                if let Some(labels) = &self.labels {
+                    label(labels, command_buffer);
+                }
+            },
+        )
+    }
+}
+
+/// A command buffer of raw commands vulkano does not wrap, like timestamps, queries and labels
+///
+/// It touches no buffers or images, so it never has to wait for or lock any of them.
+pub struct MarkerCommandBuffer {
+    inner: UnsafeCommandBuffer<StandardCommandPoolAlloc>,
+    _alloc: StandardCommandPoolAlloc,
+    device: Arc<Device>,
+}
+
+impl MarkerCommandBuffer {
+    /// Records the commands written by `record` to the raw handle, for submitting to `queue`
+    ///
+    /// A secondary `kind` makes a command buffer to be executed by a primary one.
+    pub fn record<R, F, C>(device: Arc<Device>, queue: &Queue, kind: Kind<R, F>, record: C) -> Self
+    where
+        R: RenderPassAbstract,
+        F: FramebufferAbstract,
+        C: FnOnce(vk_sys::CommandBuffer),
+    {
+        let secondary = match kind {
+            Kind::Primary => false,
+            Kind::Secondary { .. } => true,
+        };
+
+        let pool = Device::standard_command_pool(&device, queue.family());
+        let alloc: StandardCommandPoolBuilder = pool.alloc(secondary, 1).unwrap().next().unwrap();
+
+        let inner = unsafe {
+            let builder =
+                UnsafeCommandBufferBuilder::new(&alloc, kind, Flags::OneTimeSubmit).unwrap();
+
+            record(builder.internal_object());
+
+            builder.build().unwrap()
+        };
+
+        MarkerCommandBuffer {
+            inner,
+            _alloc: alloc.into_alloc(),
+            device,
+        }
+    }
+}
+
+unsafe impl DeviceOwned for MarkerCommandBuffer {
+    fn device(&self) -> &Arc<Device> {
+        &self.device
+    }
+}
+
+unsafe impl CommandBuffer for MarkerCommandBuffer {
+    type PoolAlloc = StandardCommandPoolAlloc;
+
+    fn inner(&self) -> &UnsafeCommandBuffer<StandardCommandPoolAlloc> {
+        &self.inner
+    }
+
+    fn lock_submit(&self, _: &GpuFuture, _: &Queue) -> Result<(), CommandBufferExecError> {
+        Ok(())
+    }
+
+    unsafe fn unlock(&self) {}
+
+    fn check_buffer_access(
+        &self,
+        _: &BufferAccess,
+        _: bool,
+        _: &Queue,
+    ) -> Result<Option<(PipelineStages, AccessFlagBits)>, AccessCheckError> {
+        Err(AccessCheckError::Unknown)
+    }
+
+    fn check_image_access(
+        &self,
+        _: &ImageAccess,
+        _: ImageLayout,
+        _: bool,
+        _: &Queue,
+    ) -> Result<Option<(PipelineStages, AccessFlagBits)>, AccessCheckError> {
+        Err(AccessCheckError::Unknown)
+    }
+}
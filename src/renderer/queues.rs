@@ -10,6 +10,9 @@ pub struct Queues {
     pub compute: Arc<Queue>,
     pub graphics: Arc<Queue>,
     pub present: Arc<Queue>,
+    /// Used for uploads. Its own queue when the general family has a fifth one, the general queue
+    /// otherwise. Always from the same family as the present queue, see UploadScheduler
+    pub transfer: Arc<Queue>,
 }
 
 #[derive(Copy, Clone, Debug, PartialEq)]
@@ -0,0 +1,283 @@
+//! Renders known scenes and compares them against reference images
+//!
+//! References live in `resources/references`. When one is missing, or a render does not match
+//! it, the test fails and the render is written next to it as `<name>.new.png`, to look at and
+//! commit as the reference if it is right.
+//!
+//! Scenes are rendered into a hidden window, which needs a Vulkan device and a video driver, so
+//! the scene tests are skipped unless `VKENGINE_SCENE_TESTS` is set. With it set, a machine that
+//! can't render fails them instead.
+
+use image::{Pixel, RgbaImage};
+use std::{env, fs, path::PathBuf};
+
+/// How far a render is from its reference
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ImageDiff {
+    /// Pixels with a channel further off than the tolerance
+    pub mismatched: usize,
+    /// Largest difference of any channel of any pixel
+    pub max_difference: u8,
+    pub pixels: usize,
+}
+
+impl ImageDiff {
+    /// Whether at most this fraction of the pixels are mismatched
+    pub fn within(&self, fraction: f32) -> bool {
+        self.mismatched as f32 <= self.pixels as f32 * fraction
+    }
+}
+
+/// Compares two images channel by channel, allowing every channel to be `tolerance` off
+///
+/// Returns None if the images are not the same size.
+pub fn compare(rendered: &RgbaImage, reference: &RgbaImage, tolerance: u8) -> Option<ImageDiff> {
+    if rendered.dimensions() != reference.dimensions() {
+        return None;
+    }
+
+    let mut diff = ImageDiff {
+        mismatched: 0,
+        max_difference: 0,
+        pixels: (rendered.width() * rendered.height()) as usize,
+    };
+
+    for (a, b) in rendered.pixels().zip(reference.pixels()) {
+        let difference = a
+            .channels()
+            .iter()
+            .zip(b.channels())
+            .map(|(a, b)| (i16::from(*a) - i16::from(*b)).abs() as u8)
+            .max()
+            .unwrap_or(0);
+
+        diff.max_difference = diff.max_difference.max(difference);
+        if difference > tolerance {
+            diff.mismatched += 1;
+        }
+    }
+
+    Some(diff)
+}
+
+fn references_dir() -> PathBuf {
+    PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap())
+        .join("resources")
+        .join("references")
+}
+
+/// Panics unless a render matches the reference image of the same name
+///
+/// Every channel may be off by a little, to allow for differences between GPUs and drivers, and
+/// a few pixels may be off by more, along the edges of meshes.
+pub fn assert_matches_reference(name: &str, rendered: &RgbaImage) {
+    let dir = references_dir();
+    let path = dir.join(format!("{}.png", name));
+    let new_path = dir.join(format!("{}.new.png", name));
+    fs::create_dir_all(&dir).unwrap();
+
+    if !path.exists() {
+        rendered.save(&new_path).unwrap();
+        panic!(
+            "No reference image {}, the render was written to {}",
+            path.display(),
+            new_path.display()
+        );
+    }
+
+    let reference = image::open(&path).unwrap().to_rgba();
+
+    match compare(rendered, &reference, 8) {
+        Some(diff) if diff.within(0.001) => (),
+        diff => {
+            rendered.save(&new_path).unwrap();
+            panic!(
+                "Render of {} does not match its reference: {:?}, the render was written to {}",
+                name,
+                diff,
+                new_path.display()
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{assert_matches_reference, compare};
+    use crate::{
+        components::{Link, Transform},
+        renderer::{
+            camera::{ActiveCamera, Camera},
+            capture::CaptureOutput,
+            config::RendererConfig,
+            geometry::{MeshBuilder, Shape},
+            lights::PointLightComponent,
+            settings::{AaMode, RenderSettings},
+            RenderEvent, RenderEvents, Renderer,
+        },
+        systems::{Keyframe, SDLSystem, SpatialIndexSystem, TransformSystem},
+    };
+    use image::{Rgba, RgbaImage};
+    use nalgebra::{Point3, Vector3};
+    use specs::prelude::*;
+    use specs_hierarchy::HierarchySystem;
+    use std::{
+        env, fs,
+        path::{Path, PathBuf},
+        process,
+    };
+    use vulkano::instance::{Instance, InstanceExtensions, PhysicalDevice};
+
+    /// Frames rendered before the one that is compared, so every upload has landed
+    const WARM_UP_FRAMES: u32 = 10;
+
+    /// The size of the scene renders, and so of the reference images
+    const DIMENSIONS: [u32; 2] = [320, 240];
+
+    /// Runs the scene tests when set to anything
+    const SCENE_TESTS_VAR: &str = "VKENGINE_SCENE_TESTS";
+
+    /// Whether there is a Vulkan device, and a video driver to make the hidden window with
+    fn can_render() -> bool {
+        let gpu = Instance::new(None, &InstanceExtensions::none(), None)
+            .map(|instance| PhysicalDevice::enumerate(&instance).next().is_some())
+            .unwrap_or(false);
+
+        // Dropped again right away, as there can only be one SDL context at a time
+        gpu && sdl2::init().and_then(|sdl| sdl.video()).is_ok()
+    }
+
+    /// Renders a scene from a camera at `eye` looking at the origin, and returns the last frame
+    ///
+    /// Returns None if the scene tests are skipped, and panics if they are not but there is nothing
+    /// to render with, see `can_render`.
+    fn render_scene<F>(eye: Point3<f32>, build: F) -> Option<RgbaImage>
+    where
+        F: FnOnce(&mut World),
+    {
+        if env::var_os(SCENE_TESTS_VAR).is_none() {
+            return None;
+        }
+        if !can_render() {
+            panic!(
+                "{} is set, but there is no Vulkan device or video driver to render with",
+                SCENE_TESTS_VAR
+            );
+        }
+
+        let dir = env::temp_dir().join(format!("vkengine-reference-{}", process::id()));
+
+        let sdl = SDLSystem::hidden(DIMENSIONS[0], DIMENSIONS[1]);
+        let config = RendererConfig {
+            capture: CaptureOutput::Png(dir.clone()),
+            ..RendererConfig::default()
+        };
+        let renderer = Renderer::new(sdl.window(), config);
+
+        let mut world = World::new();
+        let mut dispatcher = DispatcherBuilder::new()
+            .with(HierarchySystem::<Link>::new(), "hierarchy", &[])
+            .with(TransformSystem::default(), "transform", &["hierarchy"])
+            .with(
+                SpatialIndexSystem::default(),
+                "spatial_index",
+                &["transform"],
+            )
+            .with_thread_local(sdl)
+            .with_thread_local(renderer)
+            .build();
+        dispatcher.setup(&mut world.res);
+
+        // No filtering that would blur the comparison
+        {
+            let mut settings = world.write_resource::<RenderSettings>();
+            settings.aa_mode = AaMode::None;
+            settings.recording = true;
+        }
+
+        world
+            .create_entity()
+            .with(Keyframe::look_at(0.0, eye, Point3::origin()).transform)
+            .with(Camera::default())
+            .with(ActiveCamera)
+            .build();
+        build(&mut world);
+
+        for _ in 0..WARM_UP_FRAMES {
+            dispatcher.dispatch(&world.res);
+            world.maintain();
+        }
+
+        // Shutting down writes out the frames still being captured
+        world
+            .write_resource::<RenderEvents>()
+            .single_write(RenderEvent::Shutdown);
+        dispatcher.dispatch(&world.res);
+        drop(dispatcher);
+        drop(world);
+
+        let last = newest_png(&dir).expect("No frame was captured");
+        let frame = image::open(&last).unwrap().to_rgba();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!([frame.width(), frame.height()], DIMENSIONS);
+        Some(frame)
+    }
+
+    /// The last frame of the recording in `dir`
+    fn newest_png(dir: &Path) -> Option<PathBuf> {
+        let mut frames = fs::read_dir(dir)
+            .ok()?
+            .filter_map(Result::ok)
+            .flat_map(|recording| fs::read_dir(recording.path()).into_iter().flatten())
+            .filter_map(Result::ok)
+            .map(|frame| frame.path())
+            .filter(|path| path.extension().map_or(false, |ext| ext == "png"))
+            .collect::<Vec<_>>();
+
+        frames.sort();
+        frames.pop()
+    }
+
+    #[test]
+    fn comparison() {
+        let reference = RgbaImage::from_pixel(4, 4, Rgba([100, 100, 100, 255]));
+
+        let mut rendered = reference.clone();
+        rendered.put_pixel(0, 0, Rgba([104, 100, 96, 255]));
+        let diff = compare(&rendered, &reference, 8).unwrap();
+        assert_eq!(diff.mismatched, 0);
+        assert_eq!(diff.max_difference, 4);
+
+        rendered.put_pixel(1, 1, Rgba([200, 100, 100, 255]));
+        let diff = compare(&rendered, &reference, 8).unwrap();
+        assert_eq!(diff.mismatched, 1);
+        assert_eq!(diff.max_difference, 100);
+        assert!(diff.within(1.0 / 16.0));
+        assert!(!diff.within(0.0));
+
+        assert!(compare(&RgbaImage::new(2, 2), &reference, 8).is_none());
+    }
+
+    #[test]
+    fn lit_cube() {
+        let frame = render_scene(Point3::new(3.0, 3.0, 3.0), |world| {
+            world
+                .create_entity()
+                .with(Transform::default())
+                .with(MeshBuilder::new().with_shape(Shape::Cube))
+                .build();
+
+            world
+                .create_entity()
+                .with(Transform::from(Vector3::new(2.0, 2.0, 0.5)))
+                .with(PointLightComponent::from_color(Vector3::new(1.0, 0.8, 0.6)))
+                .build();
+        });
+
+        // Skipped without VKENGINE_SCENE_TESTS
+        if let Some(frame) = frame {
+            assert_matches_reference("lit_cube", &frame);
+        }
+    }
+}
@@ -0,0 +1,160 @@
+use crate::renderer::{
+    frame::FRAMES_IN_FLIGHT,
+    memory::{BufferAllocator, MemoryUse},
+};
+use std::sync::{Arc, Mutex};
+use vulkano::buffer::{BufferSlice, BufferUsage, CpuAccessibleBuffer};
+
+/// A value padded to 256 bytes, the largest uniform buffer offset alignment Vulkan allows, so
+/// every slot of a ring can be bound at its own offset on any device
+#[repr(C, align(256))]
+#[derive(Clone, Copy)]
+pub struct Aligned<T>(T);
+
+pub type RingBuffer<T> = Arc<CpuAccessibleBuffer<[Aligned<T>]>>;
+
+/// A slot in a FrameRing, handed back to the ring when dropped
+pub struct RingSlot {
+    index: usize,
+    free: Arc<Mutex<Vec<usize>>>,
+}
+
+impl RingSlot {
+    pub fn index(&self) -> usize {
+        self.index
+    }
+}
+
+impl Drop for RingSlot {
+    fn drop(&mut self) {
+        self.free.lock().unwrap().push(self.index);
+    }
+}
+
+/// Persistently mapped memory for data that is rewritten every frame, like model matrices
+///
+/// Every frame in flight has its own region, holding a value for every slot handed out. The CPU
+/// writes the region of a frame directly once the fence of that frame has been waited on, and
+/// descriptor sets bind single slots at their offset, so nothing is allocated or copied per frame.
+///
+/// vulkano locks a whole buffer while the GPU uses it, so the regions are separate buffers.
+pub struct FrameRing<T> {
+    memory: BufferAllocator,
+    usage: BufferUsage,
+    buffers: Vec<RingBuffer<T>>,
+    /// Slots handed out so far, including the freed ones
+    len: usize,
+    free: Arc<Mutex<Vec<usize>>>,
+}
+
+impl<T> FrameRing<T>
+where
+    T: Copy + Send + Sync + 'static,
+{
+    pub fn new(memory: BufferAllocator, usage: BufferUsage, capacity: usize) -> Self {
+        let buffers = Self::new_buffers(&memory, usage, capacity);
+
+        Self {
+            memory,
+            usage,
+            buffers,
+            len: 0,
+            free: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    fn new_buffers(
+        memory: &BufferAllocator,
+        usage: BufferUsage,
+        capacity: usize,
+    ) -> Vec<RingBuffer<T>> {
+        (0..FRAMES_IN_FLIGHT)
+            .map(|_| unsafe {
+                // Slots are written before any frame reads them
+                memory
+                    .uninitialized_array(MemoryUse::Scene, capacity, usage)
+                    .expect("Failed to create frame ring buffer")
+            })
+            .collect()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.buffers[0].len()
+    }
+
+    /// Makes room for `additional` more slots
+    ///
+    /// Returns whether the buffers were replaced, in which case every slot has to be bound and
+    /// written again. Frames already submitted keep the old buffers alive until they are done.
+    pub fn reserve(&mut self, additional: usize) -> bool {
+        let free = self.free.lock().unwrap().len();
+        let needed = (self.len + additional).saturating_sub(free);
+
+        if needed <= self.capacity() {
+            return false;
+        }
+
+        let capacity = needed.next_power_of_two();
+        self.buffers = Self::new_buffers(&self.memory, self.usage, capacity);
+        true
+    }
+
+    /// Hands out a slot, which has to have been reserved
+    pub fn allocate(&mut self) -> RingSlot {
+        let index = match self.free.lock().unwrap().pop() {
+            Some(index) => index,
+            None => {
+                assert!(self.len < self.capacity(), "Frame ring slot not reserved");
+                self.len += 1;
+                self.len - 1
+            }
+        };
+
+        RingSlot {
+            index,
+            free: self.free.clone(),
+        }
+    }
+
+    /// The part of a frame's buffer holding one slot, to be bound to a descriptor set
+    pub fn slice(
+        &self,
+        frame_index: usize,
+        slot: &RingSlot,
+    ) -> BufferSlice<[Aligned<T>], RingBuffer<T>> {
+        BufferSlice::from_typed_buffer_access(self.buffers[frame_index].clone())
+            .slice(slot.index..slot.index + 1)
+            .unwrap()
+    }
+
+    /// Writes values to slots of a frame's region
+    ///
+    /// The fence of the frame index has to have been waited on.
+    pub fn write<'a, I>(&self, frame_index: usize, values: I)
+    where
+        I: IntoIterator<Item = (&'a RingSlot, T)>,
+    {
+        let mut data = self.buffers[frame_index]
+            .write()
+            .expect("Frame ring written while the GPU is using it");
+
+        for (slot, value) in values {
+            data[slot.index] = Aligned(value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Aligned;
+    use std::mem;
+
+    #[test]
+    fn aligned_slots() {
+        assert_eq!(mem::size_of::<Aligned<[[f32; 4]; 4]>>(), 256);
+        assert_eq!(mem::align_of::<Aligned<f32>>(), 256);
+
+        // Larger values take up whole multiples of the alignment
+        assert_eq!(mem::size_of::<Aligned<[f32; 65]>>(), 512);
+    }
+}
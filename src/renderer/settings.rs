@@ -0,0 +1,228 @@
+/// How edges are anti-aliased
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AaMode {
+    /// Edges are left as they are rasterized
+    None,
+    /// Fast approximate anti-aliasing, a post pass blurring along edges found by their contrast
+    Fxaa,
+}
+
+impl AaMode {
+    /// The mode after this one, for cycling through them
+    pub fn next(self) -> Self {
+        match self {
+            AaMode::None => AaMode::Fxaa,
+            AaMode::Fxaa => AaMode::None,
+        }
+    }
+}
+
+impl Default for AaMode {
+    fn default() -> Self {
+        AaMode::Fxaa
+    }
+}
+
+/// How much shading quality is traded for speed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QualityPreset {
+    Low,
+    Medium,
+    High,
+}
+
+impl QualityPreset {
+    /// The preset after this one, for cycling through them
+    pub fn next(self) -> Self {
+        match self {
+            QualityPreset::Low => QualityPreset::Medium,
+            QualityPreset::Medium => QualityPreset::High,
+            QualityPreset::High => QualityPreset::Low,
+        }
+    }
+
+    pub fn settings(self) -> QualitySettings {
+        match self {
+            QualityPreset::Low => QualitySettings {
+                max_point_lights: 8,
+                fog: false,
+            },
+            QualityPreset::Medium => QualitySettings {
+                max_point_lights: 32,
+                fog: true,
+            },
+            QualityPreset::High => QualitySettings {
+                max_point_lights: 128,
+                fog: true,
+            },
+        }
+    }
+}
+
+impl Default for QualityPreset {
+    fn default() -> Self {
+        QualityPreset::High
+    }
+}
+
+/// What a QualityPreset sets, which the lighting shader gets as specialization constants
+///
+/// The pipelines are specialized for these, so changing them rebuilds the pipelines.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QualitySettings {
+    /// Point lights shaded per fragment, the rest are skipped
+    pub max_point_lights: u32,
+    /// Fade distant fragments into the clear color
+    pub fog: bool,
+}
+
+/// What the lighting pipelines show instead of the lit scene, for every mesh at once
+///
+/// The values are those of the `debug_view` specialization constant of the lighting shader.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DebugView {
+    /// Lit and shaded as usual
+    Lit = 0,
+    /// The diffuse color of the material, unlit
+    Albedo = 1,
+    /// World space normals, with each axis from -1 to 1 as a color channel from 0 to 1
+    Normals = 2,
+    /// Distance from the camera, from white up close to black far away
+    Depth = 3,
+    /// Brighter where more fragments are drawn over each other, without depth testing
+    Overdraw = 4,
+    /// The number of point lights reaching every fragment, from blue for none to red for many
+    ///
+    /// Fragments are shaded by every light in range, so this is where lighting costs the most.
+    LightCount = 5,
+}
+
+impl DebugView {
+    /// The view after this one, for cycling through them
+    pub fn next(self) -> Self {
+        match self {
+            DebugView::Lit => DebugView::Albedo,
+            DebugView::Albedo => DebugView::Normals,
+            DebugView::Normals => DebugView::Depth,
+            DebugView::Depth => DebugView::Overdraw,
+            DebugView::Overdraw => DebugView::LightCount,
+            DebugView::LightCount => DebugView::Lit,
+        }
+    }
+}
+
+impl Default for DebugView {
+    fn default() -> Self {
+        DebugView::Lit
+    }
+}
+
+/// Everything the lighting shader is specialized for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ShadingConstants {
+    pub quality: QualitySettings,
+    pub debug_view: DebugView,
+}
+
+/// How the selected entities are outlined
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OutlineSettings {
+    /// Linear, like the scene
+    pub color: [f32; 4],
+    /// Width of the outline in pixels
+    pub thickness: f32,
+}
+
+impl Default for OutlineSettings {
+    fn default() -> Self {
+        Self {
+            color: [1.0, 0.5, 0.05, 1.0],
+            thickness: 3.0,
+        }
+    }
+}
+
+/// The procedural sky drawn behind the scene
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SkySettings {
+    /// Draw the sky, or leave the background black
+    pub enabled: bool,
+    /// Haziness of the air, from about 2 for a clear sky to 10 for a hazy one
+    pub turbidity: f32,
+    /// Scales the luminance of the sky to the brightness of the lit scene
+    pub brightness: f32,
+}
+
+impl Default for SkySettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            turbidity: 2.5,
+            brightness: 0.08,
+        }
+    }
+}
+
+/// Screen space light shafts from the directional light, composited over the lit scene
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LightShaftSettings {
+    /// Draw the light shafts
+    pub enabled: bool,
+    /// How much light the air scatters towards the camera, 0 for clear air
+    pub density: f32,
+    /// How much of the light is scattered forward, from 0 for every direction alike to almost 1
+    /// for shafts only when looking towards the sun
+    pub scattering: f32,
+    /// Steps from every pixel towards the sun, more are smoother and slower
+    pub samples: u32,
+}
+
+impl Default for LightShaftSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            density: 0.4,
+            scattering: 0.7,
+            samples: 48,
+        }
+    }
+}
+
+/// Resource with renderer options that can be changed at runtime
+#[derive(Debug, Default)]
+pub struct RenderSettings {
+    /// Draw the vertex normals of every mesh as short lines
+    pub show_normals: bool,
+    /// Anti-aliasing applied when the scene is drawn to the screen
+    pub aa_mode: AaMode,
+    /// Show the lights in the scene as gizmos
+    pub show_light_gizmos: bool,
+    /// Show the camera paths in the scene as trails of beads
+    pub show_path_gizmos: bool,
+    /// Write every presented frame out, see `CaptureOutput`
+    pub recording: bool,
+    /// Skip drawing large meshes hidden behind others, found with occlusion queries
+    pub occlusion_queries: bool,
+    /// Store depth from 1 at the near plane to 0 at the far plane, in a float depth buffer
+    ///
+    /// Float depth is most precise close to 0, which this spends on the distance, where a
+    /// regular depth buffer has the least precision left and far geometry starts z-fighting.
+    pub reversed_z: bool,
+    /// Shading quality, see `QualitySettings`
+    pub quality: QualityPreset,
+    /// Replaces the shading of every mesh, see `DebugView`
+    pub debug_view: DebugView,
+    /// Show the frame and pass statistics over the scene
+    pub show_stats: bool,
+    /// The outline drawn around selected entities
+    pub outline: OutlineSettings,
+    /// The sky behind the scene, lit by the directional light
+    pub sky: SkySettings,
+    /// Light shafts from the directional light, see `LightShaftSettings`
+    pub light_shafts: LightShaftSettings,
+    /// Build the Hi-Z pyramid of every frame, see `HiZPyramid`
+    ///
+    /// The culling pass skips objects hidden in the pyramid of the last frame, which draws them a
+    /// frame late when they come into view.
+    pub hi_z: bool,
+}
@@ -0,0 +1,228 @@
+use log::warn;
+use shaderc::{
+    CompileOptions, Compiler, IncludeCallbackResult, IncludeType, ResolvedInclude, ShaderKind,
+};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+use vulkano::{device::Device, pipeline::shader::ShaderModule};
+
+/// Compiles GLSL shaders to SPIR-V while the engine runs, instead of when it is built
+///
+/// `#include <file>` is looked up in the include directory, and `#include "file"` next to the
+/// file including it first. Every shader is compiled with the defines of the compiler, and a
+/// permutation of a shader with defines of its own on top. Modules are kept by their path and
+/// the whole set of defines, so each permutation is only compiled once.
+pub struct ShaderCompiler {
+    include_dir: PathBuf,
+    defines: Defines,
+    modules: Mutex<HashMap<(PathBuf, Defines), Arc<ShaderModule>>>,
+}
+
+/// Macros by name, with their value if they have one
+///
+/// Ordered, so the same defines given in any order are the same permutation.
+type Defines = BTreeMap<String, Option<String>>;
+
+impl ShaderCompiler {
+    pub fn new<P: Into<PathBuf>>(include_dir: P) -> Self {
+        Self {
+            include_dir: include_dir.into(),
+            defines: Defines::new(),
+            modules: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Defines a macro in every shader compiled, as `#define name value` would
+    pub fn define(mut self, name: &str, value: Option<&str>) -> Self {
+        self.defines
+            .insert(name.to_string(), value.map(str::to_string));
+        self
+    }
+
+    /// Compiles the shader at `path` to SPIR-V words, with every define in `defines`
+    fn compile(
+        &self,
+        path: &Path,
+        kind: ShaderKind,
+        defines: &Defines,
+    ) -> Result<Vec<u32>, String> {
+        let source = fs::read_to_string(path)
+            .map_err(|err| format!("Failed to read shader {}: {}", path.display(), err))?;
+
+        let mut compiler = Compiler::new().ok_or("Failed to create shader compiler")?;
+        let mut options = CompileOptions::new().ok_or("Failed to create shader compile options")?;
+
+        for (name, value) in defines.iter() {
+            options.add_macro_definition(name, value.as_ref().map(String::as_str));
+        }
+
+        let include_dir = self.include_dir.clone();
+        options.set_include_callback(move |requested, include_type, requesting, _depth| {
+            resolve_include(&include_dir, requested, include_type, requesting)
+        });
+
+        let artifact = compiler
+            .compile_into_spirv(
+                &source,
+                kind,
+                &path.to_string_lossy(),
+                "main",
+                Some(&options),
+            )
+            .map_err(|err| format!("Failed to compile shader {}: {}", path.display(), err))?;
+
+        if artifact.get_num_warnings() > 0 {
+            warn!(
+                "Shader {} compiled with warnings:\n{}",
+                path.display(),
+                artifact.get_warning_messages()
+            );
+        }
+
+        Ok(artifact.as_binary().to_vec())
+    }
+
+    /// Compiles the shader at `path` and creates a module of it
+    pub fn load<P: AsRef<Path>>(
+        &self,
+        device: Arc<Device>,
+        path: P,
+        kind: ShaderKind,
+    ) -> Result<Arc<ShaderModule>, String> {
+        self.load_permutation(device, path, kind, &[])
+    }
+
+    /// Compiles the permutation of the shader at `path` with `defines` on top of the ones of the
+    /// compiler, and creates a module of it
+    ///
+    /// The module is reused if the same permutation was loaded before.
+    pub fn load_permutation<P: AsRef<Path>>(
+        &self,
+        device: Arc<Device>,
+        path: P,
+        kind: ShaderKind,
+        defines: &[(&str, Option<&str>)],
+    ) -> Result<Arc<ShaderModule>, String> {
+        let path = path.as_ref();
+        let key = (path.to_path_buf(), merge_defines(&self.defines, defines));
+
+        if let Some(module) = self.modules.lock().unwrap().get(&key) {
+            return Ok(module.clone());
+        }
+
+        let words = self.compile(path, kind, &key.1)?;
+
+        // The SPIR-V came straight from shaderc, which only outputs valid modules
+        let module = unsafe { ShaderModule::from_words(device, &words) }.map_err(|err| {
+            format!(
+                "Failed to create shader module of {}: {:?}",
+                path.display(),
+                err
+            )
+        })?;
+
+        self.modules.lock().unwrap().insert(key, module.clone());
+        Ok(module)
+    }
+}
+
+/// The defines of a permutation, where its own replace the compiler's of the same name
+fn merge_defines(defines: &Defines, permutation: &[(&str, Option<&str>)]) -> Defines {
+    let mut merged = defines.clone();
+    for (name, value) in permutation {
+        merged.insert(name.to_string(), value.map(str::to_string));
+    }
+    merged
+}
+
+/// Finds and reads an included file
+fn resolve_include(
+    include_dir: &Path,
+    requested: &str,
+    include_type: IncludeType,
+    requesting: &str,
+) -> IncludeCallbackResult {
+    let relative = Path::new(requesting)
+        .parent()
+        .map(|dir| dir.join(requested))
+        .filter(|path| include_type == IncludeType::Relative && path.is_file());
+    let path = relative.unwrap_or_else(|| include_dir.join(requested));
+
+    let content = fs::read_to_string(&path).map_err(|err| {
+        format!(
+            "Failed to include {} in {}: {}",
+            path.display(),
+            requesting,
+            err
+        )
+    })?;
+
+    Ok(ResolvedInclude {
+        resolved_name: path.to_string_lossy().into_owned(),
+        content,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{merge_defines, resolve_include, Defines};
+    use shaderc::IncludeType;
+    use std::path::Path;
+
+    #[test]
+    fn permutations() {
+        let mut defines = Defines::new();
+        defines.insert("SHADOWS".to_string(), None);
+        defines.insert("QUALITY".to_string(), Some("1".to_string()));
+
+        // The same defines in any order are the same permutation
+        let a = merge_defines(&defines, &[("NORMAL_MAP", None), ("LIGHTS", Some("4"))]);
+        let b = merge_defines(&defines, &[("LIGHTS", Some("4")), ("NORMAL_MAP", None)]);
+        assert_eq!(a, b);
+        assert_eq!(a.len(), 4);
+
+        // A permutation's own defines replace the compiler's
+        let high = merge_defines(&defines, &[("QUALITY", Some("2"))]);
+        assert_eq!(high["QUALITY"], Some("2".to_string()));
+        assert_ne!(high, merge_defines(&defines, &[]));
+    }
+
+    #[test]
+    fn includes() {
+        let crate_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+        let include_dir = crate_dir.join("shaders");
+        let include_dir = include_dir.as_path();
+
+        let resolved = resolve_include(
+            include_dir,
+            "common.glsl",
+            IncludeType::Standard,
+            "basic.frag",
+        )
+        .unwrap();
+        assert_eq!(
+            Path::new(&resolved.resolved_name),
+            include_dir.join("common.glsl")
+        );
+        assert!(!resolved.content.is_empty());
+
+        // Relative includes are found next to the file including them
+        let resolved = resolve_include(
+            &crate_dir.join("nowhere"),
+            "grading.glsl",
+            IncludeType::Relative,
+            &include_dir.join("post.glsl").to_string_lossy(),
+        )
+        .unwrap();
+        assert_eq!(
+            Path::new(&resolved.resolved_name),
+            include_dir.join("grading.glsl")
+        );
+
+        assert!(resolve_include(include_dir, "missing.glsl", IncludeType::Standard, "").is_err());
+    }
+}
@@ -1,3 +1,7 @@
+#[cfg(feature = "runtime-shaders")]
+use crate::renderer::{hiz::HI_Z_MAX_LEVELS, shader_compiler::ShaderCompiler};
+#[cfg(feature = "runtime-shaders")]
+use log::error;
 use std::sync::Arc;
 use vulkano::device::Device;
 
@@ -11,25 +15,178 @@ pub use self::fragment::ty::{Lights, PointLights};
 
 pub use self::vertex::ty::PushConstants;
 
+// Structs and push constants from the culling compute shader
+pub use self::cull::ty::{CullObject, CullView, Frustum as CullPushConstants};
+
+pub use self::fxaa_fragment::ty::PostPushConstants;
+
+// Push constants of the luminance measuring compute shader
+pub use self::luminance::ty::Region as LuminancePushConstants;
+
+// Push constants of the bounding boxes drawn for occlusion queries
+pub use self::occlusion_vertex::ty::OcclusionPushConstants;
+
+// Push constants of the Hi-Z pyramid compute shader
+pub use self::hi_z::ty::HiZLevel;
+
+// Push constants of the outline around selected meshes
+pub use self::outline_vertex::ty::OutlinePushConstants;
+
+// Push constants of the procedural sky
+pub use self::sky_fragment::ty::SkyPushConstants;
+// Push constants of the light shafts
+pub use self::light_shafts_fragment::ty::LightShaftsPushConstants;
+
+// Push and specialization constants of the overlay
+pub use self::overlay_fragment::SpecializationConstants as OverlaySC;
+pub use self::overlay_vertex::ty::OverlayPushConstants;
+
 pub use self::{
     fragment::SpecializationConstants as FragSC, vertex::SpecializationConstants as VertexSC,
 };
 
 pub struct ShaderSet {
     pub vertex: vertex::Shader,
+    pub batch_vertex: batch_vertex::Shader,
     pub fragment: fragment::Shader,
+    pub ghost_fragment: ghost_fragment::Shader,
+    pub normals_vertex: normals_vertex::Shader,
+    pub normals_fragment: normals_fragment::Shader,
+    pub cull: cull::Shader,
+    pub fullscreen_vertex: fullscreen_vertex::Shader,
+    pub copy_fragment: copy_fragment::Shader,
+    pub fxaa_fragment: fxaa_fragment::Shader,
+    pub luminance: luminance::Shader,
+    pub occlusion_vertex: occlusion_vertex::Shader,
+    pub occlusion_fragment: occlusion_fragment::Shader,
+    pub hi_z: hi_z::Shader,
+    pub overlay_vertex: overlay_vertex::Shader,
+    pub overlay_fragment: overlay_fragment::Shader,
+    pub world_text_vertex: world_text_vertex::Shader,
+    pub world_text_fragment: world_text_fragment::Shader,
+    pub outline_vertex: outline_vertex::Shader,
+    pub outline_fragment: outline_fragment::Shader,
+    pub sky_fragment: sky_fragment::Shader,
+    pub light_shafts_fragment: light_shafts_fragment::Shader,
 }
 
 impl ShaderSet {
+    /// Loads the shaders compiled into the binary
+    ///
+    /// With the runtime-shaders feature, the shaders are compiled from the shaders directory of
+    /// the crate instead, falling back to the compiled in ones if that fails. The shaders on disk have to
+    /// keep the inputs, outputs, descriptors and push constants of the compiled in ones, since
+    /// the pipelines are built for those.
     pub fn new(device: Arc<Device>) -> Self {
-        let vertex = vertex::Shader::load(device.clone()).expect("Failed to create shader module");
-        let fragment =
-            fragment::Shader::load(device.clone()).expect("Failed to create shader module");
+        // Constants shared with the Rust side are defined from it, instead of kept in sync by hand
+        #[cfg(feature = "runtime-shaders")]
+        let compiler = ShaderCompiler::new(concat!(env!("CARGO_MANIFEST_DIR"), "/shaders"))
+            .define("HI_Z_MAX_LEVELS", Some(&HI_Z_MAX_LEVELS.to_string()));
+
+        macro_rules! load {
+            ($module:ident) => {{
+                #[cfg(feature = "runtime-shaders")]
+                let shader = $module::Shader::compile(device.clone(), &compiler)
+                    .map_err(|err| error!("{}", err))
+                    .ok();
+                #[cfg(not(feature = "runtime-shaders"))]
+                let shader = None;
+
+                shader.unwrap_or_else(|| {
+                    $module::Shader::load(device.clone()).expect("Failed to create shader module")
+                })
+            }};
+        }
 
-        Self { vertex, fragment }
+        let vertex = load!(vertex);
+        let batch_vertex = load!(batch_vertex);
+        let fragment = load!(fragment);
+        let ghost_fragment = load!(ghost_fragment);
+        let normals_vertex = load!(normals_vertex);
+        let normals_fragment = load!(normals_fragment);
+        let cull = load!(cull);
+        let fullscreen_vertex = load!(fullscreen_vertex);
+        let copy_fragment = load!(copy_fragment);
+        let fxaa_fragment = load!(fxaa_fragment);
+        let luminance = load!(luminance);
+        let occlusion_vertex = load!(occlusion_vertex);
+        let occlusion_fragment = load!(occlusion_fragment);
+        let hi_z = load!(hi_z);
+        let overlay_vertex = load!(overlay_vertex);
+        let overlay_fragment = load!(overlay_fragment);
+        let world_text_vertex = load!(world_text_vertex);
+        let world_text_fragment = load!(world_text_fragment);
+        let outline_vertex = load!(outline_vertex);
+        let outline_fragment = load!(outline_fragment);
+        let sky_fragment = load!(sky_fragment);
+        let light_shafts_fragment = load!(light_shafts_fragment);
+
+        Self {
+            vertex,
+            batch_vertex,
+            fragment,
+            ghost_fragment,
+            normals_vertex,
+            normals_fragment,
+            cull,
+            fullscreen_vertex,
+            copy_fragment,
+            fxaa_fragment,
+            luminance,
+            occlusion_vertex,
+            occlusion_fragment,
+            hi_z,
+            overlay_vertex,
+            overlay_fragment,
+            world_text_vertex,
+            world_text_fragment,
+            outline_vertex,
+            outline_fragment,
+            sky_fragment,
+            light_shafts_fragment,
+        }
     }
 }
 
+/// Adds `Shader::compile` to a shader module made by the shader macro, which compiles the shader
+/// from its source at runtime instead of using the SPIR-V compiled into the binary
+///
+/// Lives inside the shader's module, where the module field of the macro's Shader is visible.
+/// Permutations with defines of their own only exist on this path, as the shader macro can't be
+/// given defines, so the compiled in shader is always the one without them.
+#[cfg(feature = "runtime-shaders")]
+macro_rules! runtime_compile {
+    ($path:expr, $kind:ident) => {
+        impl Shader {
+            pub fn compile(
+                device: ::std::sync::Arc<::vulkano::device::Device>,
+                compiler: &crate::renderer::shader_compiler::ShaderCompiler,
+            ) -> Result<Self, String> {
+                Self::compile_permutation(device, compiler, &[])
+            }
+
+            pub fn compile_permutation(
+                device: ::std::sync::Arc<::vulkano::device::Device>,
+                compiler: &crate::renderer::shader_compiler::ShaderCompiler,
+                defines: &[(&str, Option<&str>)],
+            ) -> Result<Self, String> {
+                let shader = compiler.load_permutation(
+                    device,
+                    concat!(env!("CARGO_MANIFEST_DIR"), "/", $path),
+                    ::shaderc::ShaderKind::$kind,
+                    defines,
+                )?;
+                Ok(Shader { shader })
+            }
+        }
+    };
+}
+
+#[cfg(not(feature = "runtime-shaders"))]
+macro_rules! runtime_compile {
+    ($path:expr, $kind:ident) => {};
+}
+
 mod vertex {
     use vulkano_shaders::shader;
 
@@ -38,6 +195,20 @@ mod vertex {
         include: ["shaders"],
         path: "shaders/basic.vert",
     }
+
+    runtime_compile!("shaders/basic.vert", Vertex);
+}
+
+mod batch_vertex {
+    use vulkano_shaders::shader;
+
+    shader! {
+        ty: "vertex",
+        include: ["shaders"],
+        path: "shaders/batch.vert",
+    }
+
+    runtime_compile!("shaders/batch.vert", Vertex);
 }
 
 mod fragment {
@@ -48,4 +219,234 @@ mod fragment {
         include: ["shaders"],
         path: "shaders/basic.frag",
     }
+
+    runtime_compile!("shaders/basic.frag", Fragment);
+}
+
+mod ghost_fragment {
+    use vulkano_shaders::shader;
+
+    shader! {
+        ty: "fragment",
+        include: ["shaders"],
+        path: "shaders/ghost.frag",
+    }
+
+    runtime_compile!("shaders/ghost.frag", Fragment);
+}
+
+mod normals_vertex {
+    use vulkano_shaders::shader;
+
+    shader! {
+        ty: "vertex",
+        include: ["shaders"],
+        path: "shaders/normals.vert",
+    }
+
+    runtime_compile!("shaders/normals.vert", Vertex);
+}
+
+mod normals_fragment {
+    use vulkano_shaders::shader;
+
+    shader! {
+        ty: "fragment",
+        include: ["shaders"],
+        path: "shaders/normals.frag",
+    }
+
+    runtime_compile!("shaders/normals.frag", Fragment);
+}
+
+mod cull {
+    use vulkano_shaders::shader;
+
+    shader! {
+        ty: "compute",
+        include: ["shaders"],
+        path: "shaders/cull.comp",
+    }
+
+    runtime_compile!("shaders/cull.comp", Compute);
+}
+
+mod fullscreen_vertex {
+    use vulkano_shaders::shader;
+
+    shader! {
+        ty: "vertex",
+        include: ["shaders"],
+        path: "shaders/fullscreen.vert",
+    }
+
+    runtime_compile!("shaders/fullscreen.vert", Vertex);
+}
+
+mod copy_fragment {
+    use vulkano_shaders::shader;
+
+    shader! {
+        ty: "fragment",
+        include: ["shaders"],
+        path: "shaders/copy.frag",
+    }
+
+    runtime_compile!("shaders/copy.frag", Fragment);
+}
+
+mod fxaa_fragment {
+    use vulkano_shaders::shader;
+
+    shader! {
+        ty: "fragment",
+        include: ["shaders"],
+        path: "shaders/fxaa.frag",
+    }
+
+    runtime_compile!("shaders/fxaa.frag", Fragment);
+}
+
+mod luminance {
+    use vulkano_shaders::shader;
+
+    shader! {
+        ty: "compute",
+        include: ["shaders"],
+        path: "shaders/luminance.comp",
+    }
+
+    runtime_compile!("shaders/luminance.comp", Compute);
+}
+
+mod occlusion_vertex {
+    use vulkano_shaders::shader;
+
+    shader! {
+        ty: "vertex",
+        include: ["shaders"],
+        path: "shaders/occlusion.vert",
+    }
+
+    runtime_compile!("shaders/occlusion.vert", Vertex);
+}
+
+mod occlusion_fragment {
+    use vulkano_shaders::shader;
+
+    shader! {
+        ty: "fragment",
+        include: ["shaders"],
+        path: "shaders/occlusion.frag",
+    }
+
+    runtime_compile!("shaders/occlusion.frag", Fragment);
+}
+
+mod hi_z {
+    use vulkano_shaders::shader;
+
+    shader! {
+        ty: "compute",
+        include: ["shaders"],
+        path: "shaders/hiz.comp",
+    }
+
+    runtime_compile!("shaders/hiz.comp", Compute);
+}
+
+mod overlay_vertex {
+    use vulkano_shaders::shader;
+
+    shader! {
+        ty: "vertex",
+        include: ["shaders"],
+        path: "shaders/overlay.vert",
+    }
+
+    runtime_compile!("shaders/overlay.vert", Vertex);
+}
+
+mod overlay_fragment {
+    use vulkano_shaders::shader;
+
+    shader! {
+        ty: "fragment",
+        include: ["shaders"],
+        path: "shaders/overlay.frag",
+    }
+
+    runtime_compile!("shaders/overlay.frag", Fragment);
+}
+
+mod world_text_vertex {
+    use vulkano_shaders::shader;
+
+    shader! {
+        ty: "vertex",
+        include: ["shaders"],
+        path: "shaders/world_text.vert",
+    }
+
+    runtime_compile!("shaders/world_text.vert", Vertex);
+}
+
+mod world_text_fragment {
+    use vulkano_shaders::shader;
+
+    shader! {
+        ty: "fragment",
+        include: ["shaders"],
+        path: "shaders/world_text.frag",
+    }
+
+    runtime_compile!("shaders/world_text.frag", Fragment);
+}
+
+mod outline_vertex {
+    use vulkano_shaders::shader;
+
+    shader! {
+        ty: "vertex",
+        include: ["shaders"],
+        path: "shaders/outline.vert",
+    }
+
+    runtime_compile!("shaders/outline.vert", Vertex);
+}
+
+mod outline_fragment {
+    use vulkano_shaders::shader;
+
+    shader! {
+        ty: "fragment",
+        include: ["shaders"],
+        path: "shaders/outline.frag",
+    }
+
+    runtime_compile!("shaders/outline.frag", Fragment);
+}
+
+mod sky_fragment {
+    use vulkano_shaders::shader;
+
+    shader! {
+        ty: "fragment",
+        include: ["shaders"],
+        path: "shaders/sky.frag",
+    }
+
+    runtime_compile!("shaders/sky.frag", Fragment);
+}
+
+mod light_shafts_fragment {
+    use vulkano_shaders::shader;
+
+    shader! {
+        ty: "fragment",
+        include: ["shaders"],
+        path: "shaders/light_shafts.frag",
+    }
+
+    runtime_compile!("shaders/light_shafts.frag", Fragment);
 }
@@ -0,0 +1,148 @@
+use crate::renderer::{
+    settings::SkySettings,
+    shaders::{PushConstants, ShaderSet, SkyPushConstants},
+};
+use nalgebra::{Matrix4, Vector3};
+use std::sync::Arc;
+use vulkano::{
+    command_buffer::{AutoCommandBufferBuilder, DynamicState},
+    device::Device,
+    framebuffer::{RenderPassAbstract, Subpass},
+    pipeline::{
+        depth_stencil::DepthStencil,
+        vertex::{BufferlessDefinition, BufferlessVertices},
+        GraphicsPipeline, GraphicsPipelineAbstract,
+    },
+};
+
+/// Draws a procedural sky behind the scene, lit by the directional light
+///
+/// The sky is a single fullscreen triangle per view, drawn before anything else without touching
+/// the depth buffer, so every mesh is drawn over it. Its color comes from the Preetham daylight
+/// model, for the angle between the sun and every pixel's view direction.
+pub struct SkyPass {
+    device: Arc<Device>,
+    pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+}
+
+impl SkyPass {
+    pub fn new(
+        device: Arc<Device>,
+        render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+        shaders: &ShaderSet,
+    ) -> Self {
+        let pipeline = build_pipeline(device.clone(), render_pass, shaders);
+
+        Self { device, pipeline }
+    }
+
+    /// Rebuilds the pipeline, after the main render pass has been rebuilt
+    pub fn set_render_pass(
+        &mut self,
+        render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+        shaders: &ShaderSet,
+    ) {
+        self.pipeline = build_pipeline(self.device.clone(), render_pass, shaders);
+    }
+
+    pub fn pipeline(&self) -> Arc<dyn GraphicsPipelineAbstract + Send + Sync> {
+        self.pipeline.clone()
+    }
+
+    /// Records drawing the sky into a view, with the light shining in `light_direction`
+    pub fn draw(
+        &self,
+        builder: AutoCommandBufferBuilder,
+        dynamic_state: &DynamicState,
+        pc: PushConstants,
+        light_direction: &Vector3<f32>,
+        settings: &SkySettings,
+    ) -> AutoCommandBufferBuilder {
+        let sun = -light_direction;
+
+        let pc = SkyPushConstants {
+            inverse_view_proj: sky_rays(&pc).into(),
+            sun_direction: [sun.x, sun.y, sun.z, 0.0],
+            turbidity: settings.turbidity,
+            brightness: settings.brightness,
+        };
+
+        let vertices = BufferlessVertices {
+            vertices: 3,
+            instances: 1,
+        };
+
+        builder
+            .draw(self.pipeline.clone(), dynamic_state, vertices, (), pc)
+            .unwrap()
+    }
+}
+
+/// From normalized device coordinates to points along the view ray through them
+///
+/// The translation of the view is left out, so the points are directions from the camera.
+fn sky_rays(pc: &PushConstants) -> Matrix4<f32> {
+    let mut view = Matrix4::from(pc.view);
+    view[(0, 3)] = 0.0;
+    view[(1, 3)] = 0.0;
+    view[(2, 3)] = 0.0;
+
+    (Matrix4::from(pc.proj) * view)
+        .try_inverse()
+        .unwrap_or_else(Matrix4::identity)
+}
+
+fn build_pipeline(
+    device: Arc<Device>,
+    render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+    shaders: &ShaderSet,
+) -> Arc<dyn GraphicsPipelineAbstract + Send + Sync> {
+    Arc::new(
+        GraphicsPipeline::start()
+            .vertex_input(BufferlessDefinition)
+            .vertex_shader(shaders.fullscreen_vertex.main_entry_point(), ())
+            .triangle_list()
+            .viewports_scissors_dynamic(1)
+            .fragment_shader(shaders.sky_fragment.main_entry_point(), ())
+            .depth_stencil(DepthStencil::disabled())
+            .render_pass(Subpass::from(render_pass, 0).unwrap())
+            .build(device)
+            .unwrap(),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::sky_rays;
+    use crate::{
+        components::Transform,
+        renderer::{camera::Camera, shaders::PushConstants},
+    };
+    use nalgebra::{Point3, UnitQuaternion, Vector3};
+
+    #[test]
+    fn view_directions() {
+        let camera = Camera::new(1.0, std::f32::consts::FRAC_PI_2);
+        let transform = Transform::from_parts(
+            Vector3::new(10.0, 5.0, 3.0),
+            UnitQuaternion::from_axis_angle(&Vector3::y_axis(), std::f32::consts::FRAC_PI_2),
+            Vector3::new(1.0, 1.0, 1.0),
+        );
+
+        for &reversed_z in &[false, true] {
+            let pc = PushConstants {
+                view: transform.to_view_matrix().into(),
+                proj: camera.depth_projection(reversed_z),
+            };
+            let rays = sky_rays(&pc);
+
+            // The center of the screen is straight ahead, which is -x after turning left
+            let forward = rays.transform_point(&Point3::new(0.0, 0.0, 0.5)).coords;
+            assert!((forward.normalize() - -Vector3::x()).norm() < 1e-4);
+
+            // The top of the screen is up, with y flipped by the projection
+            let up = rays.transform_point(&Point3::new(0.0, -1.0, 0.5)).coords;
+            assert!(up.normalize().y > 0.5);
+        }
+    }
+}
@@ -0,0 +1,111 @@
+use crate::renderer::memory::MemoryStats;
+
+/// GPU time spent in each pass of a frame, in milliseconds
+#[derive(Debug, Default, Clone)]
+pub struct PassTimes {
+    pub culling: f32,
+    pub main: f32,
+    pub post: f32,
+    pub light_shafts: f32,
+}
+
+impl PassTimes {
+    pub fn total(&self) -> f32 {
+        self.culling + self.main + self.post + self.light_shafts
+    }
+}
+
+/// What became of the objects of one pass in the last frame, counted over every view
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct PassCounts {
+    /// Objects handed to the culling shader
+    pub submitted: usize,
+    /// Outside of the frustum of the view, as tested on the CPU the same way the GPU does
+    pub frustum_culled: usize,
+    /// In the frustum, but skipped as they were occluded at their last query
+    pub occlusion_culled: usize,
+    /// Point lights every fragment of the pass is shaded with, at most
+    pub lights: usize,
+}
+
+impl PassCounts {
+    pub fn drawn(&self) -> usize {
+        self.submitted - self.frustum_culled - self.occlusion_culled
+    }
+
+    /// The counts as one line of text, for the stats overlay
+    pub fn overlay_line(&self, pass: &str) -> String {
+        format!(
+            "{}: {} submitted, {} frustum culled, {} occlusion culled, {} drawn, {} lights",
+            pass,
+            self.submitted,
+            self.frustum_culled,
+            self.occlusion_culled,
+            self.drawn(),
+            self.lights,
+        )
+    }
+}
+
+/// Resource with statistics about the last frame the renderer drew
+#[derive(Debug, Default, Clone)]
+pub struct RenderStats {
+    /// Number of frames drawn since startup
+    pub frames: u64,
+    /// Meshes with their own buffers submitted for drawing
+    pub meshes: usize,
+    /// Meshes submitted as part of the static batch
+    pub batched_meshes: usize,
+    /// Point lights uploaded to the GPU
+    pub point_lights: usize,
+    /// Triangles submitted for drawing in every view, before culling
+    pub triangles: u64,
+    /// Bounding boxes tested for occlusion in every view
+    pub occlusion_queries: usize,
+    /// Mesh draws skipped in every view, as the meshes were occluded at their last query
+    pub occluded_meshes: usize,
+    /// GPU timings of the latest frame the GPU has finished, which lags a few frames behind
+    pub gpu_times: PassTimes,
+    /// Buffer memory in use by the renderer
+    pub memory: MemoryStats,
+    /// Images in the swapchain, which may differ from what was asked for in the RendererConfig
+    pub swapchain_images: usize,
+    /// Opaque meshes with their own buffers
+    pub opaque_pass: PassCounts,
+    /// Meshes in the static batch
+    pub batch_pass: PassCounts,
+    /// Ghost meshes, which are unlit
+    pub ghost_pass: PassCounts,
+}
+
+impl RenderStats {
+    /// One line per pass, for the stats overlay
+    pub fn overlay_lines(&self) -> Vec<String> {
+        vec![
+            self.opaque_pass.overlay_line("opaque"),
+            self.batch_pass.overlay_line("batch"),
+            self.ghost_pass.overlay_line("ghosts"),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::PassCounts;
+
+    #[test]
+    fn overlay_line() {
+        let counts = PassCounts {
+            submitted: 10,
+            frustum_culled: 4,
+            occlusion_culled: 1,
+            lights: 3,
+        };
+
+        assert_eq!(counts.drawn(), 5);
+        assert_eq!(
+            counts.overlay_line("opaque"),
+            "opaque: 10 submitted, 4 frustum culled, 1 occlusion culled, 5 drawn, 3 lights"
+        );
+    }
+}
@@ -0,0 +1,184 @@
+use crate::renderer::{
+    atlas::{Atlas, AtlasRegion},
+    overlay::OverlayImage,
+};
+use log::{info, warn};
+use rusttype::{point, Font, Scale};
+use std::{collections::HashMap, fs, path::Path};
+
+/// A glyph rasterized into the atlas, at one size
+#[derive(Debug, Clone, Copy)]
+struct CachedGlyph {
+    region: AtlasRegion,
+    /// From the pen position on the baseline to the top left of the region, in pixels
+    offset: [f32; 2],
+}
+
+/// Where to draw a glyph of laid out text
+#[derive(Debug, Clone, Copy)]
+pub struct PlacedGlyph {
+    /// Of the top left corner, in pixels
+    pub position: [f32; 2],
+    pub size: [f32; 2],
+    pub region: AtlasRegion,
+}
+
+/// TTF and OTF fonts loaded at runtime, tried in order for every character
+///
+/// Characters missing from the first font, like those of other scripts, are taken from the first
+/// font after it that has them. Glyphs are rasterized the first time they are drawn at a size,
+/// and packed into the overlay atlas for every later use.
+#[derive(Default)]
+pub struct Fonts {
+    fonts: Vec<Font<'static>>,
+    /// None for glyphs without any pixels, like spaces
+    glyphs: HashMap<(usize, u32, u16), Option<CachedGlyph>>,
+}
+
+impl Fonts {
+    /// Adds a font file to the end of the fallback chain
+    pub fn load<P: AsRef<Path>>(&mut self, path: P) -> Result<(), String> {
+        let data = fs::read(path.as_ref()).map_err(|err| err.to_string())?;
+        let font = Font::from_bytes(data).map_err(|err| err.to_string())?;
+        self.fonts.push(font);
+        Ok(())
+    }
+
+    /// Adds every font file in a directory, in the order of their names, and returns how many
+    pub fn load_dir<P: AsRef<Path>>(&mut self, path: P) -> usize {
+        let path = path.as_ref();
+
+        let mut paths = match fs::read_dir(path) {
+            Ok(entries) => entries
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| {
+                    path.extension()
+                        .and_then(|ext| ext.to_str())
+                        .map_or(false, |ext| ext == "ttf" || ext == "otf")
+                })
+                .collect::<Vec<_>>(),
+            Err(err) => {
+                warn!("Failed to read fonts from {}: {}", path.display(), err);
+                return 0;
+            }
+        };
+        paths.sort();
+
+        let before = self.fonts.len();
+        for path in paths {
+            match self.load(&path) {
+                Ok(()) => info!("Loaded font {}", path.display()),
+                Err(err) => warn!("Failed to load font {}: {}", path.display(), err),
+            }
+        }
+
+        let loaded = self.fonts.len() - before;
+        if loaded == 0 {
+            warn!("No fonts in {}, text will not be drawn", path.display());
+        }
+
+        loaded
+    }
+
+    /// The first font with a glyph for a character, or the first font, which draws its missing
+    /// glyph box
+    fn font_for(&self, character: char) -> usize {
+        self.fonts
+            .iter()
+            .position(|font| font.glyph(character).id().0 != 0)
+            .unwrap_or(0)
+    }
+
+    /// Lays out lines of text `size` pixels high, with the top left at `position`
+    ///
+    /// Returns the glyphs to draw, rasterizing those that are not in the atlas yet, and the size
+    /// of the text. Nothing is laid out without any fonts.
+    pub fn layout(
+        &mut self,
+        atlas: &mut Atlas<OverlayImage>,
+        text: &str,
+        position: [f32; 2],
+        size: f32,
+    ) -> (Vec<PlacedGlyph>, [f32; 2]) {
+        if self.fonts.is_empty() {
+            return (Vec::new(), [0.0, 0.0]);
+        }
+
+        // Rasterized at whole pixel sizes, so slightly different sizes share glyphs
+        let pixels = size.round().max(1.0) as u32;
+        let scale = Scale::uniform(pixels as f32);
+
+        // Lines are spaced by the first font, so fallbacks don't move them around
+        let v_metrics = self.fonts[0].v_metrics(scale);
+        let line_height = v_metrics.ascent - v_metrics.descent + v_metrics.line_gap;
+
+        let mut placed = Vec::new();
+        let mut baseline = position[1] + v_metrics.ascent;
+        let mut x = position[0];
+        let mut width = 0.0f32;
+        let mut previous = None;
+
+        for character in text.chars() {
+            if character == '\n' {
+                x = position[0];
+                baseline += line_height;
+                previous = None;
+                continue;
+            }
+
+            let font_index = self.font_for(character);
+            let font = &self.fonts[font_index];
+            let glyph = font.glyph(character).scaled(scale);
+            let id = glyph.id();
+
+            if let Some((previous_font, previous_id)) = previous {
+                if previous_font == font_index {
+                    x += font.pair_kerning(scale, previous_id, id);
+                }
+            }
+
+            let advance = glyph.h_metrics().advance_width;
+            let cached = *self
+                .glyphs
+                .entry((font_index, pixels, id.0))
+                .or_insert_with(|| {
+                    let positioned = glyph.positioned(point(0.0, 0.0));
+                    let bounds = positioned.pixel_bounding_box()?;
+                    let (width, height) = (bounds.width() as u32, bounds.height() as u32);
+
+                    // White, with the coverage as alpha, so text is tinted by the quad's color
+                    let mut texels = vec![[255, 255, 255, 0]; (width * height) as usize];
+                    positioned.draw(|x, y, coverage| {
+                        texels[(y * width + x) as usize][3] = (coverage * 255.0).round() as u8;
+                    });
+
+                    let key = OverlayImage::Glyph {
+                        font: font_index,
+                        size: pixels,
+                        glyph: id.0,
+                    };
+
+                    Some(CachedGlyph {
+                        region: atlas.insert(key, width, height, &texels),
+                        offset: [bounds.min.x as f32, bounds.min.y as f32],
+                    })
+                });
+
+            if let Some(cached) = cached {
+                let [width, height] = cached.region.size;
+                placed.push(PlacedGlyph {
+                    position: [x + cached.offset[0], baseline + cached.offset[1]],
+                    size: [width as f32, height as f32],
+                    region: cached.region,
+                });
+            }
+
+            x += advance;
+            width = width.max(x - position[0]);
+            previous = Some((font_index, id));
+        }
+
+        let height = baseline - v_metrics.descent - position[1];
+        (placed, [width, height])
+    }
+}
@@ -0,0 +1,104 @@
+use crate::renderer::pools::CommandPools;
+use std::{mem, sync::Arc};
+use vulkano::{
+    buffer::TypedBufferAccess,
+    command_buffer::AutoCommandBufferBuilder,
+    device::Queue,
+    format::{AcceptsPixels, Format},
+    image::ImageAccess,
+    sync::GpuFuture,
+};
+
+/// Collects the image uploads of a frame, and submits them together in one command buffer
+///
+/// The uploads run on their own queue when the device has one to spare, and on the general queue
+/// otherwise. Either way the semaphore signaled when they are done is waited on by whatever is
+/// submitted after them. The queue is from the same family as the graphics queue, as vulkano does
+/// not transfer buffer ownership between queue families.
+///
+/// Uploads that can wait, like new meshes, ask for room in a per-frame budget of bytes first, so
+/// a burst of them is spread over several frames instead of stalling one.
+pub struct UploadScheduler {
+    pools: CommandPools,
+    queue: Arc<Queue>,
+    builder: Option<AutoCommandBufferBuilder>,
+    /// Bytes that can be uploaded in a frame
+    budget: usize,
+    /// Bytes uploaded since the last flush
+    used: usize,
+}
+
+impl UploadScheduler {
+    pub fn new(pools: CommandPools, queue: Arc<Queue>, budget: usize) -> Self {
+        Self {
+            pools,
+            queue,
+            builder: None,
+            budget,
+            used: 0,
+        }
+    }
+
+    /// Takes `bytes` out of the budget of this frame, or returns false if they don't fit
+    ///
+    /// The first upload of a frame always fits, so uploads bigger than the whole budget still go
+    /// through, one per frame.
+    pub fn reserve(&mut self, bytes: usize) -> bool {
+        if self.used > 0 && self.used + bytes > self.budget {
+            return false;
+        }
+
+        self.used += bytes;
+        true
+    }
+
+    /// Schedules a copy of the pixels in `source` to the whole of `destination`
+    ///
+    /// Images are needed right away, so they are always uploaded, but count against the budget.
+    pub fn copy_buffer_to_image<S, D, Px>(&mut self, source: S, destination: D)
+    where
+        S: TypedBufferAccess<Content = [Px]> + Send + Sync + 'static,
+        D: ImageAccess + Send + Sync + 'static,
+        Format: AcceptsPixels<Px>,
+    {
+        self.used += source.len() * mem::size_of::<Px>();
+        self.record(|builder| builder.copy_buffer_to_image(source, destination).unwrap());
+    }
+
+    /// Records into the command buffer of the pending uploads, starting a new one if needed
+    fn record<F>(&mut self, record: F)
+    where
+        F: FnOnce(AutoCommandBufferBuilder) -> AutoCommandBufferBuilder,
+    {
+        let builder = match self.builder.take() {
+            Some(builder) => builder,
+            None => self.pools.primary(&self.queue),
+        };
+
+        self.builder = Some(record(builder));
+    }
+
+    /// Submits every scheduled upload after `future`
+    ///
+    /// Anything executed after the returned future waits for the uploads to finish. If nothing
+    /// was scheduled, nothing is submitted.
+    pub fn flush<F>(&mut self, future: F) -> Box<GpuFuture + Send + Sync>
+    where
+        F: GpuFuture + Send + Sync + 'static,
+    {
+        self.used = 0;
+
+        let builder = match self.builder.take() {
+            Some(builder) => builder,
+            None => return Box::new(future),
+        };
+
+        let future = future
+            .then_execute(self.queue.clone(), builder.build().unwrap())
+            .unwrap()
+            .then_signal_semaphore_and_flush()
+            .unwrap();
+
+        Box::new(future)
+    }
+}
@@ -0,0 +1,280 @@
+use crate::renderer::{
+    geometry::Vertex,
+    memory::{BufferAllocator, MemoryUse, SubBuffer},
+    shaders::PushConstants,
+};
+use half::f16;
+use std::{mem, sync::Arc};
+use vulkano::{
+    buffer::{BufferAccess, BufferSlice, DeviceLocalBuffer, TypedBufferAccess},
+    command_buffer::{AutoCommandBufferBuilder, DrawIndexedIndirectCommand, DynamicState},
+    descriptor::descriptor_set::DescriptorSet,
+    format::Format,
+    pipeline::{
+        shader::ShaderInterfaceDef,
+        vertex::{
+            AttributeInfo, IncompatibleVertexDefinitionError, InputRate, VertexDefinition,
+            VertexSource,
+        },
+        GraphicsPipelineAbstract,
+    },
+};
+
+/// Vertex with its normal stored as half floats
+///
+/// The normal is padded to four components, as three component 16 bit formats are rarely
+/// supported for vertex buffers.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct QuantizedVertex {
+    pub position: [f32; 3],
+    pub normal: [u16; 4],
+}
+
+impl From<&Vertex> for QuantizedVertex {
+    fn from(vertex: &Vertex) -> Self {
+        let half = |x: f32| f16::from_f32(x).to_bits();
+        let n = vertex.normal;
+
+        Self {
+            position: vertex.position,
+            normal: [half(n[0]), half(n[1]), half(n[2]), 0],
+        }
+    }
+}
+
+impl From<&QuantizedVertex> for Vertex {
+    fn from(vertex: &QuantizedVertex) -> Self {
+        let full = |x: u16| f16::from_bits(x).to_f32();
+        let n = vertex.normal;
+
+        Vertex {
+            position: vertex.position,
+            normal: [full(n[0]), full(n[1]), full(n[2])],
+        }
+    }
+}
+
+/// Vertex input of the mesh pipelines, for either full or quantized vertices
+///
+/// The formats of a SingleBufferDefinition come from the shader, which reads the normal as a vec3
+/// of floats. This definition gives the format of the data in the buffer instead, and the GPU
+/// converts it.
+#[derive(Debug, Clone, Copy)]
+pub struct MeshVertexDefinition {
+    quantized: bool,
+}
+
+impl MeshVertexDefinition {
+    pub fn new(quantized: bool) -> Self {
+        Self { quantized }
+    }
+
+    fn stride(&self) -> usize {
+        if self.quantized {
+            mem::size_of::<QuantizedVertex>()
+        } else {
+            mem::size_of::<Vertex>()
+        }
+    }
+}
+
+unsafe impl<I> VertexDefinition<I> for MeshVertexDefinition
+where
+    I: ShaderInterfaceDef,
+{
+    type BuffersIter = std::vec::IntoIter<(u32, usize, InputRate)>;
+    type AttribsIter = std::vec::IntoIter<(u32, u32, AttributeInfo)>;
+
+    fn definition(
+        &self,
+        interface: &I,
+    ) -> Result<(Self::BuffersIter, Self::AttribsIter), IncompatibleVertexDefinitionError> {
+        let attributes = interface
+            .elements()
+            .map(|element| {
+                let name = element
+                    .name
+                    .as_ref()
+                    .map(|name| name.as_ref())
+                    .unwrap_or("");
+
+                // Both vertex types start with the position, followed by the normal
+                let format = match name {
+                    "position" => Format::R32G32B32Sfloat,
+                    "normal" if self.quantized => Format::R16G16B16A16Sfloat,
+                    "normal" => Format::R32G32B32Sfloat,
+                    _ => {
+                        return Err(IncompatibleVertexDefinitionError::MissingAttribute {
+                            attribute: name.to_owned(),
+                        })
+                    }
+                };
+                let offset = if name == "position" { 0 } else { 12 };
+
+                Ok((element.location.start, 0, AttributeInfo { offset, format }))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let buffers = vec![(0, self.stride(), InputRate::Vertex)];
+
+        Ok((buffers.into_iter(), attributes.into_iter()))
+    }
+}
+
+unsafe impl VertexSource<Vec<Arc<BufferAccess + Send + Sync>>> for MeshVertexDefinition {
+    fn decode(
+        &self,
+        mut source: Vec<Arc<BufferAccess + Send + Sync>>,
+    ) -> (Vec<Box<BufferAccess + Send + Sync>>, usize, usize) {
+        assert_eq!(source.len(), 1);
+
+        let vertices = source[0].size() / self.stride();
+        (vec![Box::new(source.remove(0))], vertices, 1)
+    }
+}
+
+/// The vertex buffer of a mesh
+pub enum VertexBuffer {
+    Full(Arc<SubBuffer<[Vertex]>>),
+    Quantized(Arc<SubBuffer<[QuantizedVertex]>>),
+}
+
+impl VertexBuffer {
+    pub fn new(memory: &BufferAllocator, vertices: Vec<Vertex>, quantized: bool) -> Self {
+        if quantized {
+            let buffer = memory
+                .sub_array(MemoryUse::Mesh, vertices.iter().map(QuantizedVertex::from))
+                .expect("Failed to create vertex buffer");
+
+            VertexBuffer::Quantized(buffer)
+        } else {
+            let buffer = memory
+                .sub_array(MemoryUse::Mesh, vertices.into_iter())
+                .expect("Failed to create vertex buffer");
+
+            VertexBuffer::Full(buffer)
+        }
+    }
+
+    pub fn is_quantized(&self) -> bool {
+        match self {
+            VertexBuffer::Full(_) => false,
+            VertexBuffer::Quantized(_) => true,
+        }
+    }
+
+    pub fn buffer(&self) -> Arc<BufferAccess + Send + Sync> {
+        match self {
+            VertexBuffer::Full(buffer) => buffer.clone(),
+            VertexBuffer::Quantized(buffer) => buffer.clone(),
+        }
+    }
+
+    /// Reads the vertices back, with full precision normals
+    pub fn read(&self) -> Vec<Vertex> {
+        match self {
+            VertexBuffer::Full(buffer) => buffer.read().to_vec(),
+            VertexBuffer::Quantized(buffer) => buffer.read().iter().map(Vertex::from).collect(),
+        }
+    }
+}
+
+/// The index buffer of a mesh, using 16 bit indices whenever the vertices allow it
+pub enum IndexBuffer {
+    U16(Arc<SubBuffer<[u16]>>),
+    U32(Arc<SubBuffer<[u32]>>),
+}
+
+impl IndexBuffer {
+    pub fn new(memory: &BufferAllocator, indices: Vec<u32>, vertex_count: usize) -> Self {
+        if vertex_count <= std::u16::MAX as usize + 1 {
+            let buffer = memory
+                .sub_array(MemoryUse::Mesh, indices.into_iter().map(|i| i as u16))
+                .expect("Failed to create index buffer");
+
+            IndexBuffer::U16(buffer)
+        } else {
+            let buffer = memory
+                .sub_array(MemoryUse::Mesh, indices.into_iter())
+                .expect("Failed to create index buffer");
+
+            IndexBuffer::U32(buffer)
+        }
+    }
+
+    pub fn index_count(&self) -> usize {
+        match self {
+            IndexBuffer::U16(buffer) => buffer.len(),
+            IndexBuffer::U32(buffer) => buffer.len(),
+        }
+    }
+
+    /// Records a draw of every index in this buffer, whatever its index type is
+    pub fn draw_indexed<Pc>(
+        &self,
+        builder: AutoCommandBufferBuilder,
+        pipeline: Arc<GraphicsPipelineAbstract + Send + Sync>,
+        dynamic_state: &DynamicState,
+        vertex_buffer: Arc<BufferAccess + Send + Sync>,
+        descriptor_sets: Vec<Arc<DescriptorSet + Send + Sync>>,
+        pc: Pc,
+    ) -> AutoCommandBufferBuilder {
+        match self {
+            IndexBuffer::U16(buffer) => builder.draw_indexed(
+                pipeline,
+                dynamic_state,
+                vec![vertex_buffer],
+                buffer.clone(),
+                descriptor_sets,
+                pc,
+            ),
+            IndexBuffer::U32(buffer) => builder.draw_indexed(
+                pipeline,
+                dynamic_state,
+                vec![vertex_buffer],
+                buffer.clone(),
+                descriptor_sets,
+                pc,
+            ),
+        }
+        .unwrap()
+    }
+
+    /// Records an indirect draw with this index buffer, whatever its index type is
+    pub fn draw_indexed_indirect(
+        &self,
+        builder: AutoCommandBufferBuilder,
+        pipeline: Arc<GraphicsPipelineAbstract + Send + Sync>,
+        dynamic_state: &DynamicState,
+        vertex_buffer: Arc<BufferAccess + Send + Sync>,
+        indirect_buffer: BufferSlice<
+            [DrawIndexedIndirectCommand],
+            Arc<DeviceLocalBuffer<[DrawIndexedIndirectCommand]>>,
+        >,
+        descriptor_sets: Vec<Arc<DescriptorSet + Send + Sync>>,
+        pc: PushConstants,
+    ) -> AutoCommandBufferBuilder {
+        match self {
+            IndexBuffer::U16(buffer) => builder.draw_indexed_indirect(
+                pipeline,
+                dynamic_state,
+                vec![vertex_buffer],
+                buffer.clone(),
+                indirect_buffer,
+                descriptor_sets,
+                pc,
+            ),
+            IndexBuffer::U32(buffer) => builder.draw_indexed_indirect(
+                pipeline,
+                dynamic_state,
+                vec![vertex_buffer],
+                buffer.clone(),
+                indirect_buffer,
+                descriptor_sets,
+                pc,
+            ),
+        }
+        .unwrap()
+    }
+}
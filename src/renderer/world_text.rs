@@ -0,0 +1,325 @@
+use crate::{
+    components::GlobalTransform,
+    renderer::{
+        memory::{BufferAllocator, MemoryUse, SubBuffer},
+        overlay::Overlay,
+        shaders::{PushConstants, ShaderSet},
+    },
+};
+use nalgebra::Point3;
+use specs::{Component, HashMapStorage};
+use specs_derive::Component;
+use std::sync::Arc;
+use vulkano::{
+    command_buffer::{AutoCommandBufferBuilder, DynamicState},
+    descriptor::{descriptor_set::PersistentDescriptorSet, DescriptorSet},
+    device::Device,
+    format::Format,
+    framebuffer::{RenderPassAbstract, Subpass},
+    image::ImmutableImage,
+    impl_vertex,
+    pipeline::{
+        depth_stencil::{Compare, DepthStencil},
+        GraphicsPipeline, GraphicsPipelineAbstract,
+    },
+    sampler::{Filter, MipmapMode, Sampler, SamplerAddressMode},
+};
+
+/// The pixel size glyphs of world text are rasterized at, whatever size they are drawn at
+const RASTER_SIZE: f32 = 48.0;
+
+/// Text drawn into the scene at an entity, like signs and debug labels
+///
+/// The text is centered on the entity, and is hidden behind anything in front of it. It lies in
+/// the XY plane of the entity, read from its +Z side, or always faces the camera as a billboard.
+/// Glyphs come from the fonts of the Overlay.
+#[derive(Component, Debug, Clone, PartialEq)]
+#[storage(HashMapStorage)]
+pub struct WorldTextComponent {
+    pub text: String,
+    /// Height of the text, in world units before the scale of the entity
+    pub size: f32,
+    /// Linear, like the scene
+    pub color: [f32; 4],
+    /// Whether the text turns to face the camera. Billboards are not rotated or scaled with the
+    /// entity
+    pub billboard: bool,
+}
+
+impl WorldTextComponent {
+    pub fn new(text: &str) -> Self {
+        Self {
+            text: text.to_string(),
+            ..Self::default()
+        }
+    }
+
+    pub fn with_size(mut self, size: f32) -> Self {
+        self.size = size;
+        self
+    }
+
+    pub fn with_color(mut self, color: [f32; 4]) -> Self {
+        self.color = color;
+        self
+    }
+
+    pub fn billboard(mut self) -> Self {
+        self.billboard = true;
+        self
+    }
+}
+
+impl Default for WorldTextComponent {
+    fn default() -> Self {
+        Self {
+            text: String::new(),
+            size: 0.5,
+            color: [1.0, 1.0, 1.0, 1.0],
+            billboard: false,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Default)]
+pub struct WorldTextVertex {
+    /// The corner in world space, or the position of the entity for billboards
+    pub position: [f32; 3],
+    /// Of the corner from the entity, along the axes of the camera, for billboards
+    pub offset: [f32; 2],
+    pub uv: [f32; 2],
+    /// The page of the atlas
+    pub layer: f32,
+    pub color: [f32; 4],
+}
+
+impl_vertex!(WorldTextVertex, position, offset, uv, layer, color);
+
+/// Lays out the text of an entity, and returns the vertices of its glyphs
+///
+/// Glyphs that are not in the overlay atlas yet are rasterized into it, so this has to happen
+/// before the atlas is uploaded for the frame.
+pub fn layout(
+    overlay: &mut Overlay,
+    text: &WorldTextComponent,
+    global: &GlobalTransform,
+) -> Vec<WorldTextVertex> {
+    let (glyphs, [width, height]) = overlay.layout_text(&text.text, RASTER_SIZE);
+    let scale = text.size / RASTER_SIZE;
+    let model = global.to_matrix();
+    let center = global.translation();
+
+    // From pixels with y pointing down, to world units around the center of the text
+    let corner = |x: f32, y: f32, uv: [f32; 2], layer: f32| {
+        let local = [(x - width * 0.5) * scale, (height * 0.5 - y) * scale];
+
+        let (position, offset) = if text.billboard {
+            ([center.x, center.y, center.z], local)
+        } else {
+            let position = model.transform_point(&Point3::new(local[0], local[1], 0.0));
+            ([position.x, position.y, position.z], [0.0, 0.0])
+        };
+
+        WorldTextVertex {
+            position,
+            offset,
+            uv,
+            layer,
+            color: text.color,
+        }
+    };
+
+    let mut vertices = Vec::with_capacity(glyphs.len() * 6);
+    for glyph in glyphs {
+        let [x, y] = glyph.position;
+        let [w, h] = glyph.size;
+        let [u0, v0] = glyph.region.uv_min;
+        let [u1, v1] = glyph.region.uv_max;
+        let layer = glyph.region.page as f32;
+
+        let top_left = corner(x, y, [u0, v0], layer);
+        let top_right = corner(x + w, y, [u1, v0], layer);
+        let bottom_left = corner(x, y + h, [u0, v1], layer);
+        let bottom_right = corner(x + w, y + h, [u1, v1], layer);
+
+        vertices.extend_from_slice(&[
+            top_left.clone(),
+            bottom_left.clone(),
+            top_right.clone(),
+            top_right,
+            bottom_left,
+            bottom_right,
+        ]);
+    }
+
+    vertices
+}
+
+/// Draws the glyphs of every WorldTextComponent into the main pass
+///
+/// Text is depth tested against the scene without writing depth, and blended over it, so it is
+/// drawn along with the ghosts, after everything opaque. It samples the overlay atlas.
+pub struct WorldTextPass {
+    device: Arc<Device>,
+    memory: BufferAllocator,
+    pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+    sampler: Arc<Sampler>,
+    atlas: Option<Arc<ImmutableImage<Format>>>,
+    descriptor_set: Option<Arc<dyn DescriptorSet + Send + Sync>>,
+}
+
+impl WorldTextPass {
+    pub fn new(
+        device: Arc<Device>,
+        memory: BufferAllocator,
+        render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+        shaders: &ShaderSet,
+        reversed_z: bool,
+    ) -> Self {
+        let pipeline = build_pipeline(device.clone(), render_pass, shaders, reversed_z);
+
+        let sampler = Sampler::new(
+            device.clone(),
+            Filter::Linear,
+            Filter::Linear,
+            MipmapMode::Nearest,
+            SamplerAddressMode::ClampToEdge,
+            SamplerAddressMode::ClampToEdge,
+            SamplerAddressMode::ClampToEdge,
+            0.0,
+            1.0,
+            0.0,
+            0.0,
+        )
+        .unwrap();
+
+        Self {
+            device,
+            memory,
+            pipeline,
+            sampler,
+            atlas: None,
+            descriptor_set: None,
+        }
+    }
+
+    /// Rebuilds the pipeline, after the main render pass has been rebuilt
+    pub fn set_render_pass(
+        &mut self,
+        render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+        shaders: &ShaderSet,
+        reversed_z: bool,
+    ) {
+        self.pipeline = build_pipeline(self.device.clone(), render_pass, shaders, reversed_z);
+        self.update_descriptor_set();
+    }
+
+    /// Samples the atlas last uploaded by the OverlayPass
+    pub fn set_atlas(&mut self, atlas: Option<Arc<ImmutableImage<Format>>>) {
+        let changed = match (&self.atlas, &atlas) {
+            (Some(old), Some(new)) => !Arc::ptr_eq(old, new),
+            (None, None) => false,
+            _ => true,
+        };
+
+        if changed {
+            self.atlas = atlas;
+            self.update_descriptor_set();
+        }
+    }
+
+    fn update_descriptor_set(&mut self) {
+        let atlas = match self.atlas.clone() {
+            Some(atlas) => atlas,
+            None => return,
+        };
+
+        self.descriptor_set = Some(Arc::new(
+            PersistentDescriptorSet::start(self.pipeline.clone(), 0)
+                .add_sampled_image(atlas, self.sampler.clone())
+                .unwrap()
+                .build()
+                .unwrap(),
+        ));
+    }
+
+    pub fn pipeline(&self) -> Arc<dyn GraphicsPipelineAbstract + Send + Sync> {
+        self.pipeline.clone()
+    }
+
+    /// Uploads the vertices of this frame, to be drawn into every view
+    ///
+    /// None if there is nothing to draw, or nothing to draw it with yet.
+    pub fn upload(
+        &self,
+        vertices: Vec<WorldTextVertex>,
+    ) -> Option<Arc<SubBuffer<[WorldTextVertex]>>> {
+        if vertices.is_empty() || self.descriptor_set.is_none() {
+            return None;
+        }
+
+        let buffer = self
+            .memory
+            .sub_array(MemoryUse::Transient, vertices.into_iter())
+            .unwrap();
+
+        Some(buffer)
+    }
+
+    /// Records drawing the uploaded vertices into a view
+    pub fn draw(
+        &self,
+        builder: AutoCommandBufferBuilder,
+        dynamic_state: &DynamicState,
+        vertex_buffer: Arc<SubBuffer<[WorldTextVertex]>>,
+        pc: PushConstants,
+    ) -> AutoCommandBufferBuilder {
+        let descriptor_set = match &self.descriptor_set {
+            Some(descriptor_set) => descriptor_set.clone(),
+            None => return builder,
+        };
+
+        builder
+            .draw(
+                self.pipeline.clone(),
+                dynamic_state,
+                vec![vertex_buffer],
+                descriptor_set,
+                pc,
+            )
+            .unwrap()
+    }
+}
+
+fn build_pipeline(
+    device: Arc<Device>,
+    render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+    shaders: &ShaderSet,
+    reversed_z: bool,
+) -> Arc<dyn GraphicsPipelineAbstract + Send + Sync> {
+    let depth_stencil = DepthStencil {
+        depth_write: false,
+        depth_compare: if reversed_z {
+            Compare::Greater
+        } else {
+            Compare::Less
+        },
+        ..DepthStencil::simple_depth_test()
+    };
+
+    // Not culled, so text can be seen from behind, mirrored like a sign on a window
+    Arc::new(
+        GraphicsPipeline::start()
+            .vertex_input_single_buffer::<WorldTextVertex>()
+            .vertex_shader(shaders.world_text_vertex.main_entry_point(), ())
+            .triangle_list()
+            .viewports_scissors_dynamic(1)
+            .fragment_shader(shaders.world_text_fragment.main_entry_point(), ())
+            .depth_stencil(depth_stencil)
+            .blend_alpha_blending()
+            .render_pass(Subpass::from(render_pass, 0).unwrap())
+            .build(device)
+            .unwrap(),
+    )
+}
@@ -1,8 +1,14 @@
+use rand::{Rng as _, RngCore, SeedableRng};
+use rand_pcg::Pcg32;
 use sdl2::keyboard::Mod;
 use shrev::EventChannel;
-use specs::BitSet;
-use std::ops::{Deref, DerefMut};
+use std::{
+    collections::HashSet,
+    ops::{Deref, DerefMut},
+    path::PathBuf,
+};
 
+pub use crate::components::{DirtyEntities, HiddenEntities};
 pub use sdl2::{
     controller::{Axis as ControllerAxis, Button as ControllerButton},
     keyboard::Keycode,
@@ -15,6 +21,7 @@ pub struct Time {
     pub first_frame: f32,
     delta: f32,
     timescale: f32,
+    alpha: f32,
 }
 
 impl Time {
@@ -23,9 +30,22 @@ impl Time {
             first_frame,
             delta,
             timescale,
+            alpha: 1.0,
         }
     }
 
+    /// How far the frame is between the previous and the current simulation step, from 0 to 1
+    ///
+    /// The renderer uses this to interpolate transforms. It is always 1 while the simulation
+    /// steps once per frame.
+    pub fn alpha(&self) -> f32 {
+        self.alpha
+    }
+
+    pub fn set_alpha(&mut self, alpha: f32) {
+        self.alpha = alpha.max(0.0).min(1.0);
+    }
+
     pub fn delta(&self) -> f32 {
         self.delta * self.timescale
     }
@@ -33,6 +53,11 @@ impl Time {
     pub fn timescale(&self) -> f32 {
         self.timescale
     }
+
+    /// Delta time unaffected by the timescale
+    pub fn real_delta(&self) -> f32 {
+        self.delta
+    }
 }
 
 impl Default for Time {
@@ -41,13 +66,51 @@ impl Default for Time {
             delta: 1.,
             first_frame: 0.,
             timescale: 1.,
+            alpha: 1.,
         }
     }
 }
 
-#[derive(Default)]
-pub struct DirtyEntities {
-    pub dirty: BitSet,
+/// Resource every system draws random numbers from, like the jitter of placed objects
+///
+/// Seeded from the DeterminismConfig, so a run can be reproduced from its seed as long as nothing
+/// random comes from anywhere else.
+#[derive(Debug, Clone)]
+pub struct Rng(Pcg32);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Rng(Pcg32::seed_from_u64(seed))
+    }
+
+    /// A number from `low` up to, but not including, `high`
+    pub fn range(&mut self, low: f32, high: f32) -> f32 {
+        self.0.gen_range(low, high)
+    }
+}
+
+impl Default for Rng {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
+
+impl RngCore for Rng {
+    fn next_u32(&mut self) -> u32 {
+        self.0.next_u32()
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0.next_u64()
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.0.fill_bytes(dest)
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.0.try_fill_bytes(dest)
+    }
 }
 
 /// Resource for signaling that the user has asked to close the game
@@ -58,6 +121,129 @@ pub struct ShouldClose(pub bool);
 #[derive(Debug, Default)]
 pub struct FocusGained(pub bool);
 
+/// Can the main window be seen, or is it hidden or minimized?
+///
+/// Nothing is drawn while it is hidden, so the main loop slows down to save power.
+#[derive(Debug)]
+pub struct WindowVisible(pub bool);
+
+impl Default for WindowVisible {
+    fn default() -> Self {
+        WindowVisible(true)
+    }
+}
+
+/// Frames mouse motion is ignored for after the cursor is grabbed
+///
+/// Grabbing warps the cursor, and SDL reports the jump as motion over the next frame or two.
+const GRAB_SETTLE_FRAMES: u32 = 2;
+
+/// Resource for grabbing the mouse to look around, or releasing it to use the cursor
+///
+/// The SDLSystem applies changes on its next run. It also releases the mouse while the window is
+/// not focused, and grabs it again on focus if it should be.
+#[derive(Debug)]
+pub struct CursorState {
+    grabbed: bool,
+    /// Frames left in which mouse motion is not used to look around
+    settling: u32,
+    /// From (0, 0) at the top left of the window to (1, 1) at the bottom right
+    position: Option<[f32; 2]>,
+}
+
+impl CursorState {
+    pub fn is_grabbed(&self) -> bool {
+        self.grabbed
+    }
+
+    pub fn toggle_grab(&mut self) {
+        self.grabbed = !self.grabbed;
+    }
+
+    /// Where the cursor is in the window, from (0, 0) at the top left to (1, 1) at the bottom
+    /// right, or None while it is grabbed or has not moved yet
+    pub fn position(&self) -> Option<[f32; 2]> {
+        if self.grabbed {
+            None
+        } else {
+            self.position
+        }
+    }
+
+    pub fn set_position(&mut self, position: [f32; 2]) {
+        self.position = Some(position);
+    }
+
+    /// Whether mouse motion should turn the view, which it doesn't while the cursor is free
+    pub fn look_enabled(&self) -> bool {
+        self.grabbed && self.settling == 0
+    }
+
+    /// Ignores mouse motion for a few frames, after the SDLSystem grabbed the cursor
+    pub fn settle(&mut self) {
+        self.settling = GRAB_SETTLE_FRAMES;
+    }
+
+    pub fn new_frame(&mut self) {
+        self.settling = self.settling.saturating_sub(1);
+    }
+}
+
+impl Default for CursorState {
+    fn default() -> Self {
+        CursorState {
+            grabbed: true,
+            settling: 0,
+            position: None,
+        }
+    }
+}
+
+/// Resource for switching the window between borderless fullscreen and windowed
+///
+/// Changing the mode while the GPU is still presenting to the window crashes in the driver, so a
+/// change goes in steps. The renderer stops submitting frames and waits for the GPU once one is
+/// requested, and the SDLSystem changes the mode on its next run and has the swapchain resized.
+#[derive(Debug, Default)]
+pub struct WindowMode {
+    fullscreen: bool,
+    requested: Option<bool>,
+    gpu_idle: bool,
+}
+
+impl WindowMode {
+    pub fn toggle_fullscreen(&mut self) {
+        let fullscreen = self.requested.unwrap_or(self.fullscreen);
+        self.requested = Some(!fullscreen);
+    }
+
+    /// Whether a change has been requested and not been made yet
+    pub fn is_pending(&self) -> bool {
+        self.requested.is_some()
+    }
+
+    /// Tells the SDLSystem that nothing is being drawn to the window anymore
+    pub fn set_gpu_idle(&mut self) {
+        self.gpu_idle = true;
+    }
+
+    /// The requested mode, once the GPU is idle and it is safe to change to it
+    pub fn take_ready(&mut self) -> Option<bool> {
+        if !self.gpu_idle {
+            return None;
+        }
+
+        let fullscreen = self.requested.take()?;
+        self.fullscreen = fullscreen;
+        self.gpu_idle = false;
+        Some(fullscreen)
+    }
+}
+
+/// Resource for asking the SDLSystem to change the window title
+#[derive(Debug, Default)]
+pub struct WindowTitle(pub Option<String>);
+
 #[derive(Debug)]
 pub struct KeyboardEvent {
     pub pressed: bool,
@@ -83,6 +269,241 @@ impl DerefMut for KeyboardEvents {
     }
 }
 
+/// Resource with the keys held down, for systems that poll the keyboard instead of reading events
+///
+/// The SDLSystem updates it along with the KeyboardEvents. Key repeats are not presses, and every
+/// key is released when the window loses focus, as the key up would go to another window.
+#[derive(Debug, Default)]
+pub struct KeyboardState {
+    pressed: HashSet<Keycode>,
+    just_pressed: HashSet<Keycode>,
+    just_released: HashSet<Keycode>,
+}
+
+impl KeyboardState {
+    /// Whether a key is held down
+    pub fn is_down(&self, keycode: Keycode) -> bool {
+        self.pressed.contains(&keycode)
+    }
+
+    /// Whether a key went down since the last frame
+    pub fn just_pressed(&self, keycode: Keycode) -> bool {
+        self.just_pressed.contains(&keycode)
+    }
+
+    /// Whether a key went up since the last frame
+    pub fn just_released(&self, keycode: Keycode) -> bool {
+        self.just_released.contains(&keycode)
+    }
+
+    pub fn pressed(&self) -> impl Iterator<Item = &Keycode> {
+        self.pressed.iter()
+    }
+
+    /// Forgets what went down or up in the last frame, before the events of the next are handled
+    pub fn new_frame(&mut self) {
+        self.just_pressed.clear();
+        self.just_released.clear();
+    }
+
+    pub fn press(&mut self, keycode: Keycode) {
+        if self.pressed.insert(keycode) {
+            self.just_pressed.insert(keycode);
+        }
+    }
+
+    pub fn release(&mut self, keycode: Keycode) {
+        if self.pressed.remove(&keycode) {
+            self.just_released.insert(keycode);
+        }
+    }
+
+    pub fn release_all(&mut self) {
+        self.just_released.extend(self.pressed.drain());
+    }
+}
+
+/// A named action triggered by a chord or a double tap, as configured in the InputBindings
+#[derive(Debug, Clone, PartialEq)]
+pub struct ActionEvent(pub String);
+
+#[derive(Default)]
+pub struct ActionEvents(EventChannel<ActionEvent>);
+
+impl Deref for ActionEvents {
+    type Target = EventChannel<ActionEvent>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl DerefMut for ActionEvents {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+/// A one-shot sound, played by the AudioSystem
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SoundEffect {
+    /// An object was placed or removed
+    Click,
+    /// Something that was asked for failed
+    Error,
+}
+
+impl SoundEffect {
+    /// The name of its file in the sounds directory, without the extension
+    pub fn name(self) -> &'static str {
+        match self {
+            SoundEffect::Click => "click",
+            SoundEffect::Error => "error",
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct SoundEffects(EventChannel<SoundEffect>);
+
+impl Deref for SoundEffects {
+    type Target = EventChannel<SoundEffect>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl DerefMut for SoundEffects {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+/// Resource mirroring the system clipboard
+///
+/// The SDLSystem keeps the contents up to date, and writes text given to `set` back to the system
+/// clipboard on its next run.
+#[derive(Debug, Default)]
+pub struct Clipboard {
+    text: Option<String>,
+    pending: Option<String>,
+}
+
+impl Clipboard {
+    /// The text on the clipboard, if there is any
+    pub fn get(&self) -> Option<&str> {
+        self.pending
+            .as_ref()
+            .or_else(|| self.text.as_ref())
+            .map(String::as_str)
+    }
+
+    pub fn set(&mut self, text: String) {
+        self.pending = Some(text);
+    }
+
+    /// Takes the text waiting to be put on the system clipboard
+    pub fn take_pending(&mut self) -> Option<String> {
+        self.pending.take()
+    }
+
+    /// Updates the mirrored text after the system clipboard changed
+    pub fn update(&mut self, text: Option<String>) {
+        self.text = text;
+    }
+}
+
+/// A file dropped on the window
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileDropEvent(pub PathBuf);
+
+#[derive(Default)]
+pub struct FileDropEvents(EventChannel<FileDropEvent>);
+
+impl Deref for FileDropEvents {
+    type Target = EventChannel<FileDropEvent>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl DerefMut for FileDropEvents {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+/// IME composition text, the text the user is typing before it is committed
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Composition {
+    pub text: String,
+    /// Cursor position in the composition
+    pub start: i32,
+    /// Length of the selection in the composition
+    pub length: i32,
+}
+
+/// Resource for starting and stopping text input, used by the console and UI text fields
+///
+/// SDL only sends text events while text input is enabled, and the SDLSystem applies changes to
+/// it on its next run.
+#[derive(Debug, Default)]
+pub struct TextInput {
+    enabled: bool,
+    composition: Option<Composition>,
+}
+
+impl TextInput {
+    pub fn start(&mut self) {
+        self.enabled = true;
+    }
+
+    pub fn stop(&mut self) {
+        self.enabled = false;
+        self.composition = None;
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// The text currently being composed with an IME, if any
+    pub fn composition(&self) -> Option<&Composition> {
+        self.composition.as_ref()
+    }
+
+    pub fn set_composition(&mut self, composition: Option<Composition>) {
+        self.composition = composition;
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TextInputEvent {
+    /// Committed text
+    Input(String),
+    /// The IME composition changed
+    Editing(Composition),
+}
+
+#[derive(Default)]
+pub struct TextInputEvents(EventChannel<TextInputEvent>);
+
+impl Deref for TextInputEvents {
+    type Target = EventChannel<TextInputEvent>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl DerefMut for TextInputEvents {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
 #[derive(Debug)]
 pub enum MouseEvent {
     Button {
@@ -149,3 +570,42 @@ impl DerefMut for ControllerEvents {
         &mut self.0
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{KeyboardState, Keycode, Rng};
+
+    #[test]
+    fn keyboard_state() {
+        let mut state = KeyboardState::default();
+        state.press(Keycode::W);
+        state.press(Keycode::W);
+        assert!(state.is_down(Keycode::W));
+        assert!(state.just_pressed(Keycode::W));
+
+        // Held down into the next frame
+        state.new_frame();
+        assert!(state.is_down(Keycode::W));
+        assert!(!state.just_pressed(Keycode::W));
+
+        state.release(Keycode::W);
+        state.release(Keycode::A);
+        assert!(!state.is_down(Keycode::W));
+        assert!(state.just_released(Keycode::W));
+        assert!(!state.just_released(Keycode::A));
+    }
+
+    #[test]
+    fn seeded_rng() {
+        let mut a = Rng::new(7);
+        let mut b = Rng::new(7);
+        let first = (0..16).map(|_| a.range(0.0, 1.0)).collect::<Vec<_>>();
+        let second = (0..16).map(|_| b.range(0.0, 1.0)).collect::<Vec<_>>();
+        assert_eq!(first, second);
+        assert!(first.iter().all(|x| *x >= 0.0 && *x < 1.0));
+
+        let mut c = Rng::new(8);
+        let third = (0..16).map(|_| c.range(0.0, 1.0)).collect::<Vec<_>>();
+        assert_ne!(first, third);
+    }
+}
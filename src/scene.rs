@@ -0,0 +1,726 @@
+use crate::{
+    components::{CoordinateSystem, Name, Transform},
+    renderer::{
+        geometry::{MeshBuilder, Shape},
+        lights::PointLightComponent,
+        RenderEvent, RenderEvents,
+    },
+    systems::{Anchor, EditHistory, LoadMesh, ScaleMode, UiAnchor, UiText},
+};
+use log::{info, warn};
+use nalgebra::{UnitQuaternion, Vector3};
+use serde::Deserialize;
+use specs::{prelude::*, NullStorage};
+use specs_derive::Component;
+use std::{
+    collections::{HashMap, HashSet},
+    fs::File,
+    mem,
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{channel, Receiver, Sender},
+        Mutex,
+    },
+    thread::{self, JoinHandle},
+};
+
+/// Keeps an entity around when scenes are unloaded, like the camera
+#[derive(Component, Debug, Default)]
+#[storage(NullStorage)]
+pub struct Persistent;
+
+/// Identifies a loaded scene file, to unload it again
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SceneHandle(u32);
+
+/// The scene an entity was loaded from
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq)]
+#[storage(DenseVecStorage)]
+pub struct InScene(pub SceneHandle);
+
+#[derive(Debug)]
+enum SceneRequest {
+    Unload(SceneHandle),
+    UnloadAll,
+}
+
+/// A scene file to read, and the file once it has been read
+type Job = (SceneHandle, PathBuf);
+type Parsed = (SceneHandle, PathBuf, Result<SceneFile, String>);
+
+/// Resource keeping track of the loaded scenes, and the scenes to load and unload
+///
+/// Several scenes can be loaded at once, like the chunks of a bigger world, and each one unloaded
+/// on its own. Scene files are read and parsed on a worker thread, and their entities created by
+/// `apply` between frames, which is also when scenes are unloaded, as that deletes entities right
+/// away.
+#[derive(Debug)]
+pub struct Scenes {
+    next: u32,
+    loaded: HashMap<SceneHandle, PathBuf>,
+    /// Scenes being read by the worker, which are dropped when they arrive if they are not in here
+    /// anymore
+    reading: HashSet<SceneHandle>,
+    /// Scenes whose files could not be read or parsed, until they are taken or removed
+    failed: HashSet<SceneHandle>,
+    requests: Vec<SceneRequest>,
+    // Resources have to be Sync, which channels are not
+    sender: Mutex<Sender<Job>>,
+    receiver: Mutex<Receiver<Parsed>>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl Scenes {
+    pub fn new() -> Self {
+        let (sender, jobs) = channel::<Job>();
+        let (results, receiver) = channel();
+
+        // Returns once the resource, and with it the sender, is dropped
+        let worker = thread::spawn(move || {
+            for (handle, path) in jobs {
+                let scene = read(&path);
+                if results.send((handle, path, scene)).is_err() {
+                    return;
+                }
+            }
+        });
+
+        Self {
+            next: 0,
+            loaded: HashMap::new(),
+            reading: HashSet::new(),
+            failed: HashSet::new(),
+            requests: Vec::new(),
+            sender: Mutex::new(sender),
+            receiver: Mutex::new(receiver),
+            worker: Some(worker),
+        }
+    }
+
+    fn allocate(&mut self) -> SceneHandle {
+        self.next += 1;
+        SceneHandle(self.next)
+    }
+
+    /// Loads a scene file next to the scenes already loaded
+    ///
+    /// The handle is valid right away, but the entities are only created once the file has been
+    /// read, between frames.
+    pub fn add<P: Into<PathBuf>>(&mut self, path: P) -> SceneHandle {
+        let handle = self.allocate();
+        self.reading.insert(handle);
+
+        self.sender
+            .lock()
+            .unwrap()
+            .send((handle, path.into()))
+            .expect("The scene reader has stopped");
+
+        handle
+    }
+
+    /// Unloads a scene added earlier, along with every entity that came from it
+    ///
+    /// A scene that is still being read is never loaded.
+    pub fn remove(&mut self, handle: SceneHandle) {
+        self.reading.remove(&handle);
+        self.failed.remove(&handle);
+        self.requests.push(SceneRequest::Unload(handle));
+    }
+
+    /// Unloads every scene, and everything else that is not Persistent, and loads another one
+    pub fn switch<P: Into<PathBuf>>(&mut self, path: P) -> SceneHandle {
+        self.reading.clear();
+        self.requests.push(SceneRequest::UnloadAll);
+        self.add(path)
+    }
+
+    /// Whether the scene has been added and not removed since, even if it is still being read
+    pub fn contains(&self, handle: SceneHandle) -> bool {
+        self.loaded.contains_key(&handle) || self.reading.contains(&handle)
+    }
+
+    /// Whether the file of the scene could not be read or parsed, which is only reported once
+    ///
+    /// Failed scenes are no longer contained, so they can be told apart from scenes unloaded by
+    /// something else.
+    pub fn take_failed(&mut self, handle: SceneHandle) -> bool {
+        self.failed.remove(&handle)
+    }
+
+    /// Whether the entities of the scene exist, which they do from the end of the frame its file
+    /// was read in until it is removed
+    pub fn is_loaded(&self, handle: SceneHandle) -> bool {
+        self.loaded.contains_key(&handle)
+    }
+
+    /// Runs the arguments of a "scene" console command, "<path>", "add <path>" or "remove <id>"
+    ///
+    /// Returns a line to show in the console. Until there is one, scene commands are bound to keys
+    /// in the bindings file.
+    pub fn command(&mut self, args: &str) -> Result<String, String> {
+        let args = args.split_whitespace().collect::<Vec<_>>();
+        match args.as_slice() {
+            ["add", path] => {
+                let SceneHandle(id) = self.add(*path);
+                Ok(format!("Adding scene {} as {}", path, id))
+            }
+            ["remove", id] => {
+                let id = id
+                    .parse()
+                    .map_err(|_| format!("Expected a scene id, got {:?}", id))?;
+                let handle = SceneHandle(id);
+                if !self.contains(handle) {
+                    return Err(format!("No scene {}", id));
+                }
+
+                let loaded = self.is_loaded(handle);
+                self.remove(handle);
+                if loaded {
+                    Ok(format!("Removing scene {}", id))
+                } else {
+                    Ok(format!(
+                        "Scene {} was still being read, it is not loaded",
+                        id
+                    ))
+                }
+            }
+            [path] => {
+                let SceneHandle(id) = self.switch(*path);
+                Ok(format!("Switching to scene {} as {}", path, id))
+            }
+            _ => Err("Usage: scene [add] <path> | scene remove <id>".to_string()),
+        }
+    }
+}
+
+impl Default for Scenes {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for Scenes {
+    /// Waits for the worker, so it does not outlive the world
+    fn drop(&mut self) {
+        // Closing both channels stops the worker after the file it is reading, instead of after
+        // every queued one
+        *self.sender.get_mut().unwrap() = channel().0;
+        *self.receiver.get_mut().unwrap() = channel().1;
+
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// The contents of a scene file, a list of entities in ron
+#[derive(Debug, Deserialize)]
+struct SceneFile {
+    entities: Vec<SceneEntity>,
+    /// Scales the whole scene, like 0.01 for a scene written in centimeters
+    #[serde(default = "meters")]
+    scale: f32,
+}
+
+#[derive(Debug, Deserialize)]
+struct SceneEntity {
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    position: [f32; 3],
+    /// Euler angles in degrees, roll, pitch and yaw
+    #[serde(default)]
+    rotation: [f32; 3],
+    #[serde(default = "unit_scale")]
+    scale: [f32; 3],
+    #[serde(default)]
+    mesh: Option<SceneMesh>,
+    /// The axes the glTF file of the mesh was exported with
+    #[serde(default)]
+    coordinates: SceneCoordinates,
+    /// Meters per unit in the glTF file of the mesh
+    #[serde(default = "meters")]
+    units: f32,
+    #[serde(default)]
+    light: Option<SceneLight>,
+    #[serde(default)]
+    text: Option<SceneText>,
+}
+
+impl SceneEntity {
+    /// The coordinate system to import the glTF file of the mesh from
+    fn coordinate_system(&self) -> CoordinateSystem {
+        CoordinateSystem::from(self.coordinates).with_unit_scale(self.units)
+    }
+}
+
+fn unit_scale() -> [f32; 3] {
+    [1.0, 1.0, 1.0]
+}
+
+fn meters() -> f32 {
+    1.0
+}
+
+fn white() -> [f32; 4] {
+    [1.0, 1.0, 1.0, 1.0]
+}
+
+#[derive(Debug, Deserialize)]
+enum SceneMesh {
+    Shape(SceneShape),
+    /// A glTF file relative to the resources directory
+    Gltf(String),
+}
+
+/// The shapes that can be written down in a scene file, see `Shape`
+#[derive(Debug, Clone, Copy, Deserialize)]
+enum SceneShape {
+    Sphere(u32, u32),
+    Cone(u32),
+    Cube,
+    Cylinder(u32),
+    Quad(u32, u32),
+    Capsule(u32, u32),
+    Torus(f32, f32, u32),
+    IcoSphere(u32),
+}
+
+impl From<SceneShape> for Shape {
+    fn from(shape: SceneShape) -> Self {
+        match shape {
+            SceneShape::Sphere(around, across) => Shape::Sphere(around, across),
+            SceneShape::Cone(subdivisions) => Shape::Cone(subdivisions),
+            SceneShape::Cube => Shape::Cube,
+            SceneShape::Cylinder(points) => Shape::Cylinder(points),
+            SceneShape::Quad(x, y) => Shape::Quad(x, y),
+            SceneShape::Capsule(around, across) => Shape::Capsule(around, across),
+            SceneShape::Torus(major, minor, segments) => Shape::Torus(major, minor, segments),
+            SceneShape::IcoSphere(subdivisions) => Shape::IcoSphere(subdivisions),
+        }
+    }
+}
+
+/// The coordinate systems that can be written down in a scene file, see `CoordinateSystem`
+#[derive(Debug, Clone, Copy, Deserialize)]
+enum SceneCoordinates {
+    Engine,
+    ZUp,
+    LeftHandedYUp,
+}
+
+impl Default for SceneCoordinates {
+    fn default() -> Self {
+        SceneCoordinates::Engine
+    }
+}
+
+impl From<SceneCoordinates> for CoordinateSystem {
+    fn from(coordinates: SceneCoordinates) -> Self {
+        match coordinates {
+            SceneCoordinates::Engine => CoordinateSystem::ENGINE,
+            SceneCoordinates::ZUp => CoordinateSystem::Z_UP,
+            SceneCoordinates::LeftHandedYUp => CoordinateSystem::LEFT_HANDED_Y_UP,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct SceneLight {
+    color: [f32; 3],
+    lumens: f32,
+}
+
+/// Text over the screen, laid out for a screen 720 pixels high like the rest of the HUD, see
+/// `UiAnchor`
+#[derive(Debug, Deserialize)]
+struct SceneText {
+    text: String,
+    anchor: SceneAnchor,
+    #[serde(default)]
+    offset: [f32; 2],
+    size: f32,
+    #[serde(default = "white")]
+    color: [f32; 4],
+}
+
+/// The anchors that can be written down in a scene file, see `Anchor`
+#[derive(Debug, Clone, Copy, Deserialize)]
+enum SceneAnchor {
+    TopLeft,
+    Top,
+    TopRight,
+    Left,
+    Center,
+    Right,
+    BottomLeft,
+    Bottom,
+    BottomRight,
+}
+
+impl From<SceneAnchor> for Anchor {
+    fn from(anchor: SceneAnchor) -> Self {
+        match anchor {
+            SceneAnchor::TopLeft => Anchor::TopLeft,
+            SceneAnchor::Top => Anchor::Top,
+            SceneAnchor::TopRight => Anchor::TopRight,
+            SceneAnchor::Left => Anchor::Left,
+            SceneAnchor::Center => Anchor::Center,
+            SceneAnchor::Right => Anchor::Right,
+            SceneAnchor::BottomLeft => Anchor::BottomLeft,
+            SceneAnchor::Bottom => Anchor::Bottom,
+            SceneAnchor::BottomRight => Anchor::BottomRight,
+        }
+    }
+}
+
+/// Carries out the requests made through `Scenes` since the last call, and creates the entities of
+/// the scene files that have been read since
+pub fn apply(world: &mut World) {
+    let (requests, parsed) = {
+        let mut scenes = world.write_resource::<Scenes>();
+        let requests = mem::replace(&mut scenes.requests, Vec::new());
+        let parsed = scenes
+            .receiver
+            .lock()
+            .unwrap()
+            .try_iter()
+            .collect::<Vec<_>>();
+        (requests, parsed)
+    };
+
+    for request in requests {
+        match request {
+            SceneRequest::Unload(handle) => unload_scene(world, handle),
+            SceneRequest::UnloadAll => unload(world),
+        }
+    }
+
+    for (handle, path, scene) in parsed {
+        // Removed while it was being read
+        if !world.write_resource::<Scenes>().reading.remove(&handle) {
+            continue;
+        }
+
+        match scene {
+            Ok(scene) => load(world, handle, path, scene),
+            Err(err) => {
+                warn!("Failed to load scene {}: {}", path.display(), err);
+                world.write_resource::<Scenes>().failed.insert(handle);
+            }
+        }
+    }
+}
+
+/// Runs on the worker thread of Scenes
+fn read(path: &Path) -> Result<SceneFile, String> {
+    let file = File::open(path).map_err(|err| err.to_string())?;
+    ron::de::from_reader(file).map_err(|err| err.to_string())
+}
+
+/// Creates the entities of a scene file, next to those of the scenes already loaded
+fn load(world: &mut World, handle: SceneHandle, path: PathBuf, scene: SceneFile) {
+    let count = scene.entities.len();
+    for entity in scene.entities {
+        create_entity(world, handle, entity, scene.scale);
+    }
+
+    info!("Loaded {} entities from {}", count, path.display());
+    world.write_resource::<Scenes>().loaded.insert(handle, path);
+}
+
+/// Creates an entity of a scene file, in a scene scaled by `scale`
+fn create_entity(
+    world: &mut World,
+    handle: SceneHandle,
+    entity: SceneEntity,
+    scale: f32,
+) -> Entity {
+    let [roll, pitch, yaw] = entity.rotation;
+    let transform = Transform::from_parts(
+        Vector3::from(entity.position) * scale,
+        UnitQuaternion::from_euler_angles(roll.to_radians(), pitch.to_radians(), yaw.to_radians()),
+        Vector3::from(entity.scale) * scale,
+    );
+
+    let coordinates = entity.coordinate_system();
+    let mut builder = world.create_entity().with(transform).with(InScene(handle));
+
+    if let Some(name) = entity.name {
+        builder = builder.with(Name(name));
+    }
+
+    match entity.mesh {
+        Some(SceneMesh::Shape(shape)) => {
+            builder = builder.with(MeshBuilder::new().with_shape(shape.into()));
+        }
+        Some(SceneMesh::Gltf(file)) => {
+            builder = builder.with(LoadMesh::resource(&file).with_coordinates(coordinates));
+        }
+        None => (),
+    }
+
+    if let Some(light) = entity.light {
+        builder = builder.with(
+            PointLightComponent::from_lumens(Vector3::from(light.color), light.lumens)
+                .scaled(scale),
+        );
+    }
+
+    if let Some(text) = entity.text {
+        builder = builder
+            .with(
+                UiAnchor::new(text.anchor.into())
+                    .with_offset(text.offset)
+                    .with_scale_mode(ScaleMode::WithHeight(720.0)),
+            )
+            .with(UiText::new(&text.text, text.size).with_color(text.color));
+    }
+
+    builder.build()
+}
+
+/// Deletes every entity that is not Persistent, whichever scene it came from, and has the renderer
+/// drop what it kept of them
+///
+/// Children of persistent entities are deleted too, unless they are persistent themselves. The
+/// edit history is forgotten, as there is nothing left for it to undo.
+pub fn unload(world: &mut World) {
+    let doomed = {
+        let entities = world.entities();
+        let persistent = world.read_storage::<Persistent>();
+
+        (&entities, !&persistent)
+            .join()
+            .map(|(entity, _)| entity)
+            .collect::<Vec<_>>()
+    };
+
+    world.write_resource::<Scenes>().loaded.clear();
+    world.write_resource::<EditHistory>().clear();
+    delete(world, &doomed);
+}
+
+/// Deletes the entities loaded from one scene, apart from those that have been made Persistent
+pub fn unload_scene(world: &mut World, handle: SceneHandle) {
+    let doomed = {
+        let entities = world.entities();
+        let in_scenes = world.read_storage::<InScene>();
+        let persistent = world.read_storage::<Persistent>();
+
+        (&entities, &in_scenes, !&persistent)
+            .join()
+            .filter(|(_, in_scene, _)| in_scene.0 == handle)
+            .map(|(entity, _, _)| entity)
+            .collect::<Vec<_>>()
+    };
+
+    world.write_resource::<Scenes>().loaded.remove(&handle);
+    delete(world, &doomed);
+}
+
+fn delete(world: &mut World, doomed: &[Entity]) {
+    world.delete_entities(doomed).unwrap();
+    world.maintain();
+
+    world
+        .write_resource::<RenderEvents>()
+        .single_write(RenderEvent::SceneUnloaded);
+
+    info!("Unloaded {} entities", doomed.len());
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        apply, create_entity, unload, unload_scene, InScene, Persistent, SceneEntity, SceneFile,
+        Scenes,
+    };
+    use crate::{
+        components::{CoordinateSystem, Name, Transform},
+        renderer::{geometry::MeshBuilder, lights::PointLightComponent, RenderEvents},
+        systems::{Anchor, EditHistory, UiAnchor, UiText},
+    };
+    use nalgebra::Vector3;
+    use specs::prelude::*;
+    use std::{thread, time::Duration};
+
+    const SCENE: &str = r#"(
+        entities: [
+            (name: Some("floor"), position: (0.0, -1.0, 0.0), mesh: Some(Shape(Quad(4, 4)))),
+            (position: (1.0, 2.0, 3.0), light: Some((color: (1.0, 0.5, 0.0), lumens: 100.0))),
+        ],
+    )"#;
+
+    fn world() -> World {
+        let mut world = World::new();
+        world.register::<Transform>();
+        world.register::<Name>();
+        world.register::<MeshBuilder>();
+        world.register::<PointLightComponent>();
+        world.register::<Persistent>();
+        world.register::<InScene>();
+        world.add_resource(RenderEvents::default());
+        world.add_resource(Scenes::default());
+        world.add_resource(EditHistory::default());
+        world
+    }
+
+    /// Loads SCENE the way `apply` would, without a file
+    fn load_scene(world: &mut World) -> super::SceneHandle {
+        let handle = world.write_resource::<Scenes>().allocate();
+        let scene: SceneFile = ron::de::from_str(SCENE).unwrap();
+        for entity in scene.entities {
+            create_entity(world, handle, entity, scene.scale);
+        }
+
+        handle
+    }
+
+    #[test]
+    fn load_and_unload() {
+        let mut world = world();
+        let camera = world.create_entity().with(Persistent).build();
+        load_scene(&mut world);
+
+        assert_eq!(world.read_storage::<Transform>().join().count(), 2);
+        assert_eq!(world.read_storage::<MeshBuilder>().join().count(), 1);
+        assert_eq!(
+            world.read_storage::<PointLightComponent>().join().count(),
+            1
+        );
+        assert_eq!(
+            world.read_storage::<Name>().join().next(),
+            Some(&Name("floor".to_string()))
+        );
+
+        unload(&mut world);
+
+        let entities = world.entities();
+        assert_eq!(entities.join().collect::<Vec<_>>(), vec![camera]);
+    }
+
+    #[test]
+    fn additive() {
+        let mut world = world();
+        let first = load_scene(&mut world);
+        let second = load_scene(&mut world);
+        assert_ne!(first, second);
+        assert_eq!(world.read_storage::<Transform>().join().count(), 4);
+
+        unload_scene(&mut world, first);
+
+        let in_scenes = world.read_storage::<InScene>();
+        assert_eq!(in_scenes.join().count(), 2);
+        assert!(in_scenes.join().all(|in_scene| in_scene.0 == second));
+    }
+
+    #[test]
+    fn failed_read() {
+        let mut world = world();
+        let handle = world
+            .write_resource::<Scenes>()
+            .add("scenes/does_not_exist.ron");
+
+        // Read in the background
+        for _ in 0..100 {
+            apply(&mut world);
+            if !world.read_resource::<Scenes>().contains(handle) {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        let mut scenes = world.write_resource::<Scenes>();
+        assert!(!scenes.contains(handle));
+        assert!(scenes.take_failed(handle));
+        assert!(!scenes.take_failed(handle));
+    }
+
+    #[test]
+    fn coordinates() {
+        let entity: SceneEntity = ron::de::from_str(r#"(mesh: Some(Gltf("tree.gltf")))"#).unwrap();
+        assert_eq!(entity.coordinate_system(), CoordinateSystem::ENGINE);
+
+        let entity: SceneEntity =
+            ron::de::from_str(r#"(mesh: Some(Gltf("tree.gltf")), coordinates: ZUp, units: 0.01)"#)
+                .unwrap();
+        assert_eq!(
+            entity.coordinate_system(),
+            CoordinateSystem::Z_UP.with_unit_scale(0.01)
+        );
+    }
+
+    #[test]
+    fn text() {
+        let mut world = world();
+        world.register::<UiAnchor>();
+        world.register::<UiText>();
+
+        let handle = world.write_resource::<Scenes>().allocate();
+        let entity: SceneEntity = ron::de::from_str(
+            r#"(text: Some((text: "Shapes", anchor: Top, size: 24.0, color: (1.0, 0.0, 0.0, 1.0))))"#,
+        )
+        .unwrap();
+        let entity = create_entity(&mut world, handle, entity, 1.0);
+
+        let anchors = world.read_storage::<UiAnchor>();
+        assert_eq!(anchors.get(entity).unwrap().anchor, Anchor::Top);
+        let texts = world.read_storage::<UiText>();
+        assert_eq!(texts.get(entity).unwrap().text, "Shapes");
+        assert_eq!(texts.get(entity).unwrap().color, [1.0, 0.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn scaled() {
+        let mut world = world();
+        let handle = world.write_resource::<Scenes>().allocate();
+        let scene: SceneFile = ron::de::from_str(
+            r#"(
+                entities: [(position: (2.0, 0.0, 0.0), light: Some((color: (1.0, 1.0, 1.0), lumens: 100.0)))],
+                scale: 0.5,
+            )"#,
+        )
+        .unwrap();
+        let scale = scene.scale;
+        let entities = scene
+            .entities
+            .into_iter()
+            .map(|entity| create_entity(&mut world, handle, entity, scale))
+            .collect::<Vec<_>>();
+
+        let transforms = world.read_storage::<Transform>();
+        let transform = transforms.get(entities[0]).unwrap();
+        assert_eq!(transform.translation(), &Vector3::new(1.0, 0.0, 0.0));
+        assert_eq!(transform.scale(), &Vector3::new(0.5, 0.5, 0.5));
+
+        let unscaled = PointLightComponent::from_lumens(Vector3::new(1.0, 1.0, 1.0), 100.0);
+        let lights = world.read_storage::<PointLightComponent>();
+        assert!((lights.get(entities[0]).unwrap().range() - unscaled.range() * 0.5).abs() < 1e-3);
+    }
+
+    #[test]
+    fn commands() {
+        let world = world();
+        let mut scenes = world.write_resource::<Scenes>();
+
+        assert!(scenes.command("add scenes/does_not_exist.ron").is_ok());
+        assert!(scenes
+            .command("remove 1")
+            .unwrap()
+            .contains("still being read"));
+        assert!(scenes.command("remove 1").is_err());
+        assert!(scenes.command("remove one").is_err());
+        assert!(scenes.command("").is_err());
+
+        // Loaded scenes are removed along with their entities
+        let handle = scenes.allocate();
+        scenes.loaded.insert(handle, "scenes/loaded.ron".into());
+        assert!(scenes.is_loaded(handle));
+        assert_eq!(
+            scenes.command(&format!("remove {}", handle.0)),
+            Ok(format!("Removing scene {}", handle.0))
+        );
+    }
+}
@@ -0,0 +1,386 @@
+use crate::{
+    components::{CoordinateSystem, GlobalTransform, Transform},
+    renderer::{camera::ActiveCamera, geometry::MeshBuilder},
+    resources::{SoundEffect, SoundEffects},
+};
+use log::warn;
+use nalgebra::Vector3;
+use specs::prelude::*;
+use specs_derive::Component;
+use std::{
+    cmp::Ordering,
+    collections::{BinaryHeap, HashSet},
+    env,
+    path::PathBuf,
+    sync::{
+        mpsc::{channel, Receiver, Sender},
+        Arc, Condvar, Mutex,
+    },
+    thread::{self, JoinHandle},
+};
+
+/// Threads decoding assets
+const WORKERS: usize = 2;
+/// Decoded assets waiting to be handed to the world, before the workers stop taking new jobs
+const MAX_READY: usize = 4;
+/// Decoded assets handed to the world each frame, so a burst of them is spread over frames
+const MAX_APPLIED_PER_FRAME: usize = 2;
+
+/// Resource with the state of the asset queue, as of the last frame
+#[derive(Debug, Default, Clone)]
+pub struct AssetStats {
+    /// Waiting for a worker
+    pub queued: usize,
+    /// Being decoded right now
+    pub active: usize,
+    /// Decoded and handed to the world since startup
+    pub completed: usize,
+}
+
+/// A glTF file to load in the background, replaced by a MeshBuilder once it has been decoded
+///
+/// Meshes closer to the active camera are decoded first.
+#[derive(Component, Debug, Clone)]
+#[storage(DenseVecStorage)]
+pub struct LoadMesh {
+    pub path: PathBuf,
+    batched: bool,
+    quantized: bool,
+    coordinates: CoordinateSystem,
+}
+
+impl LoadMesh {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            batched: false,
+            quantized: false,
+            coordinates: CoordinateSystem::ENGINE,
+        }
+    }
+
+    /// A glTF file relative to the resources directory
+    pub fn resource(file: &str) -> Self {
+        Self::new(
+            PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap())
+                .join("resources")
+                .join(file),
+        )
+    }
+
+    /// See `MeshBuilder::batched`
+    pub fn batched(mut self) -> Self {
+        self.batched = true;
+        self
+    }
+
+    /// See `MeshBuilder::quantized`
+    pub fn quantized(mut self) -> Self {
+        self.quantized = true;
+        self
+    }
+
+    /// The coordinate system the file was authored in, see `MeshBuilder::converted_from`
+    pub fn with_coordinates(mut self, coordinates: CoordinateSystem) -> Self {
+        self.coordinates = coordinates;
+        self
+    }
+}
+
+/// A queued decode, ordered so the job closest to the camera comes out of the heap first
+struct Job {
+    entity: Entity,
+    path: PathBuf,
+    distance: f32,
+    /// Keeps jobs at the same distance in the order they were queued
+    sequence: u64,
+}
+
+impl PartialEq for Job {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Job {}
+
+impl PartialOrd for Job {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Job {
+    /// Reversed, as BinaryHeap pops the greatest job
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .distance
+            .partial_cmp(&self.distance)
+            .unwrap_or(Ordering::Equal)
+            .then_with(|| other.sequence.cmp(&self.sequence))
+    }
+}
+
+#[derive(Default)]
+struct Shared {
+    jobs: BinaryHeap<Job>,
+    active: usize,
+    ready: usize,
+    shutdown: bool,
+}
+
+type Decoded = (Entity, Result<MeshBuilder, String>);
+
+/// Runs on each worker thread until the queue shuts down
+fn work(shared: Arc<(Mutex<Shared>, Condvar)>, results: Sender<Decoded>) {
+    let (lock, condvar) = &*shared;
+
+    loop {
+        let job = {
+            let mut shared = lock.lock().unwrap();
+
+            // Waits for a job, and for the world to catch up with what has been decoded already
+            while !shared.shutdown && (shared.jobs.is_empty() || shared.ready >= MAX_READY) {
+                shared = condvar.wait(shared).unwrap();
+            }
+
+            if shared.shutdown {
+                return;
+            }
+
+            shared.active += 1;
+            shared.jobs.pop().unwrap()
+        };
+
+        let result = MeshBuilder::new().try_with_gltf_path(&job.path);
+
+        {
+            let mut shared = lock.lock().unwrap();
+            shared.active -= 1;
+            shared.ready += 1;
+        }
+
+        if results.send((job.entity, result)).is_err() {
+            return;
+        }
+    }
+}
+
+/// Decodes the glTF files of LoadMesh components on worker threads
+///
+/// Jobs are prioritized by their distance to the active camera, which is updated every frame as
+/// the camera moves. The workers stop while a few decoded meshes are waiting, and only a couple
+/// are handed to the world each frame, so loading many meshes at once never stalls a frame.
+/// Progress is written to the AssetStats resource.
+pub struct AssetLoaderSystem {
+    shared: Arc<(Mutex<Shared>, Condvar)>,
+    results: Receiver<Decoded>,
+    workers: Vec<JoinHandle<()>>,
+    /// Entities with a job queued or being decoded
+    submitted: HashSet<Entity>,
+    sequence: u64,
+    completed: usize,
+}
+
+impl AssetLoaderSystem {
+    pub fn new() -> Self {
+        let shared = Arc::new((Mutex::new(Shared::default()), Condvar::new()));
+        let (sender, results) = channel();
+
+        let workers = (0..WORKERS)
+            .map(|_| {
+                let shared = shared.clone();
+                let sender = sender.clone();
+                thread::spawn(move || work(shared, sender))
+            })
+            .collect();
+
+        Self {
+            shared,
+            results,
+            workers,
+            submitted: HashSet::new(),
+            sequence: 0,
+            completed: 0,
+        }
+    }
+}
+
+impl Default for AssetLoaderSystem {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a> System<'a> for AssetLoaderSystem {
+    type SystemData = (
+        Entities<'a>,
+        ReadStorage<'a, ActiveCamera>,
+        ReadStorage<'a, GlobalTransform>,
+        ReadStorage<'a, Transform>,
+        WriteStorage<'a, LoadMesh>,
+        WriteStorage<'a, MeshBuilder>,
+        Write<'a, AssetStats>,
+        Write<'a, SoundEffects>,
+    );
+
+    fn run(
+        &mut self,
+        (
+            entities,
+            active_cameras,
+            globals,
+            transforms,
+            mut loads,
+            mut mesh_builders,
+            mut stats,
+            mut sound_effects,
+        ): Self::SystemData,
+    ) {
+        let camera = (&globals, &active_cameras)
+            .join()
+            .next()
+            .map(|(global, _)| *global.translation())
+            .unwrap_or_else(Vector3::zeros);
+
+        // New entities might not have a global transform yet
+        let distance = |entity: Entity| {
+            globals
+                .get(entity)
+                .map(|global| global.translation())
+                .or_else(|| transforms.get(entity).map(Transform::translation))
+                .map(|position| (position - camera).norm())
+                .unwrap_or(0.0)
+        };
+
+        // Queue new loads, and reorder the queue for where the camera is now
+        // -----------------------------------------------------------------------------------------------------
+        {
+            let (lock, condvar) = &*self.shared;
+            let mut shared = lock.lock().unwrap();
+
+            let jobs = shared
+                .jobs
+                .drain()
+                .filter(|job| entities.is_alive(job.entity) && loads.contains(job.entity))
+                .map(|job| Job {
+                    distance: distance(job.entity),
+                    ..job
+                })
+                .collect::<Vec<_>>();
+            shared.jobs.extend(jobs);
+
+            for (entity, load) in (&entities, &loads).join() {
+                if self.submitted.insert(entity) {
+                    shared.jobs.push(Job {
+                        entity,
+                        path: load.path.clone(),
+                        distance: distance(entity),
+                        sequence: self.sequence,
+                    });
+                    self.sequence += 1;
+                }
+            }
+
+            // Jobs of deleted entities are dropped above
+            self.submitted.retain(|entity| entities.is_alive(*entity));
+
+            condvar.notify_all();
+        }
+
+        // Hand decoded meshes to the world
+        // -----------------------------------------------------------------------------------------------------
+        let decoded = self
+            .results
+            .try_iter()
+            .take(MAX_APPLIED_PER_FRAME)
+            .collect::<Vec<_>>();
+
+        if !decoded.is_empty() {
+            let (lock, condvar) = &*self.shared;
+            lock.lock().unwrap().ready -= decoded.len();
+            condvar.notify_all();
+        }
+
+        for (entity, result) in decoded {
+            self.submitted.remove(&entity);
+            self.completed += 1;
+
+            let load = match loads.remove(entity) {
+                Some(load) => load,
+                // Deleted while it was being decoded
+                None => continue,
+            };
+
+            let mut builder = match result {
+                Ok(builder) => builder.converted_from(&load.coordinates),
+                Err(err) => {
+                    warn!("Failed to load mesh: {}", err);
+                    sound_effects.single_write(SoundEffect::Error);
+                    continue;
+                }
+            };
+            if load.batched {
+                builder = builder.batched();
+            }
+            if load.quantized {
+                builder = builder.quantized();
+            }
+
+            mesh_builders.insert(entity, builder).unwrap();
+        }
+
+        let shared = self.shared.0.lock().unwrap();
+        *stats = AssetStats {
+            queued: shared.jobs.len(),
+            active: shared.active,
+            completed: self.completed,
+        };
+    }
+}
+
+impl Drop for AssetLoaderSystem {
+    /// Stops the workers once they are done with what they are decoding
+    fn drop(&mut self) {
+        {
+            let (lock, condvar) = &*self.shared;
+            lock.lock().unwrap().shutdown = true;
+            condvar.notify_all();
+        }
+
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Job;
+    use specs::prelude::*;
+    use std::{collections::BinaryHeap, path::PathBuf};
+
+    #[test]
+    fn nearest_job_first() {
+        let mut world = World::new();
+        let entity = world.create_entity().build();
+
+        let job = |distance, sequence| Job {
+            entity,
+            path: PathBuf::new(),
+            distance,
+            sequence,
+        };
+
+        let mut jobs = BinaryHeap::new();
+        jobs.push(job(10.0, 0));
+        jobs.push(job(1.0, 1));
+        jobs.push(job(5.0, 2));
+        jobs.push(job(1.0, 3));
+
+        let order = (0..4)
+            .map(|_| jobs.pop().unwrap().sequence)
+            .collect::<Vec<_>>();
+        assert_eq!(order, vec![1, 3, 2, 0]);
+    }
+}
@@ -0,0 +1,135 @@
+use crate::resources::{SoundEffect, SoundEffects};
+use log::{info, warn};
+use rodio::{source::Buffered, Decoder, Source};
+use shrev::ReaderId;
+use specs::prelude::*;
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+    sync::mpsc::{channel, Receiver, Sender},
+    thread::{self, JoinHandle},
+};
+
+/// A decoded sound, which is cheap to clone and play again
+type Sound = Buffered<Decoder<BufReader<File>>>;
+
+/// Plays the SoundEffects sent this frame
+///
+/// The sounds are decoded and played by a worker thread, which loads each effect from the sounds
+/// directory the first time it is played. This is only meant for UI sounds, there is no
+/// positional audio yet.
+pub struct AudioSystem {
+    // Taken when dropped, which closes the channel so the worker returns
+    sender: Option<Sender<SoundEffect>>,
+    worker: Option<JoinHandle<()>>,
+    reader: Option<ReaderId<SoundEffect>>,
+}
+
+impl AudioSystem {
+    /// Starts the worker thread, playing sounds from `dir`
+    pub fn new<P: Into<PathBuf>>(dir: P) -> Self {
+        let dir = dir.into();
+        let (sender, receiver) = channel();
+
+        // Returns once the system, and with it the sender, is dropped
+        let worker = thread::spawn(move || play_sounds(receiver, dir));
+
+        Self {
+            sender: Some(sender),
+            worker: Some(worker),
+            reader: None,
+        }
+    }
+}
+
+impl<'a> System<'a> for AudioSystem {
+    type SystemData = Read<'a, SoundEffects>;
+
+    fn run(&mut self, sound_effects: Self::SystemData) {
+        let sender = self.sender.as_ref().unwrap();
+
+        for effect in sound_effects.read(self.reader.as_mut().unwrap()) {
+            // The worker is gone if there is no output device
+            let _ = sender.send(*effect);
+        }
+    }
+
+    fn setup(&mut self, res: &mut Resources) {
+        Self::SystemData::setup(res);
+
+        let mut sound_effects = res.fetch_mut::<SoundEffects>();
+        self.reader = Some(sound_effects.register_reader());
+    }
+}
+
+impl Drop for AudioSystem {
+    /// Closes the channel and waits for the worker, so it does not outlive the world
+    fn drop(&mut self) {
+        self.sender = None;
+
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// Runs on the worker thread until the channel is closed
+fn play_sounds(receiver: Receiver<SoundEffect>, dir: PathBuf) {
+    let device = match rodio::default_output_device() {
+        Some(device) => device,
+        None => {
+            warn!("No audio output device, sounds will not be played");
+            return;
+        }
+    };
+
+    // None for effects that failed to load, so they are not tried again
+    let mut sounds = HashMap::new();
+
+    for effect in receiver {
+        let sound = sounds
+            .entry(effect)
+            .or_insert_with(|| load_sound(&dir, effect));
+
+        if let Some(sound) = sound {
+            rodio::play_raw(&device, sound.clone().convert_samples());
+        }
+    }
+}
+
+/// Loads the WAV or OGG file of an effect
+fn load_sound(dir: &Path, effect: SoundEffect) -> Option<Sound> {
+    let path = ["wav", "ogg"]
+        .iter()
+        .map(|extension| dir.join(effect.name()).with_extension(extension))
+        .find(|path| path.is_file());
+
+    let path = match path {
+        Some(path) => path,
+        None => {
+            warn!("No sound for {:?} in {}", effect, dir.display());
+            return None;
+        }
+    };
+
+    let file = match File::open(&path) {
+        Ok(file) => file,
+        Err(err) => {
+            warn!("Failed to open {}: {}", path.display(), err);
+            return None;
+        }
+    };
+
+    match Decoder::new(BufReader::new(file)) {
+        Ok(decoder) => {
+            info!("Loaded sound {}", path.display());
+            Some(decoder.buffered())
+        }
+        Err(err) => {
+            warn!("Failed to decode {}: {:?}", path.display(), err);
+            None
+        }
+    }
+}
@@ -0,0 +1,274 @@
+use crate::{
+    renderer::{camera::ActiveCamera, stats::RenderStats},
+    resources::ShouldClose,
+    systems::{AssetStats, CameraPath},
+};
+use float_duration::TimePoint;
+use log::{info, warn};
+use specs::prelude::*;
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write as IoWrite},
+    path::PathBuf,
+    time::Instant,
+};
+
+/// Frames recorded when `--benchmark` is not given a number
+const DEFAULT_FRAMES: u32 = 1000;
+
+/// How a benchmark run is set up, from the command line
+///
+/// `--benchmark [frames]` turns benchmark mode on, and `--benchmark-report <path>` changes where
+/// the report is written.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BenchmarkConfig {
+    /// Frames recorded, after the scene has loaded
+    pub frames: u32,
+    /// Simulation step of every frame, in seconds, so every run sees the same frames
+    pub timestep: f32,
+    /// Where the CSV report is written
+    pub report: PathBuf,
+}
+
+impl BenchmarkConfig {
+    /// Reads the config from command line arguments, or None if benchmark mode is not asked for
+    pub fn from_args<I>(args: I) -> Option<Self>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        let mut config = None;
+        let mut report = PathBuf::from("benchmark.csv");
+
+        let mut args = args.into_iter().peekable();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--benchmark" => {
+                    let frames = match args.peek().and_then(|frames| frames.parse().ok()) {
+                        Some(frames) => {
+                            args.next();
+                            frames
+                        }
+                        None => DEFAULT_FRAMES,
+                    };
+                    config = Some(frames);
+                }
+                "--benchmark-report" => match args.next() {
+                    Some(path) => report = PathBuf::from(path),
+                    None => warn!("--benchmark-report needs a path"),
+                },
+                _ => (),
+            }
+        }
+
+        config.map(|frames| Self {
+            frames,
+            timestep: 1.0 / 60.0,
+            report,
+        })
+    }
+}
+
+/// A recorded frame
+#[derive(Debug, Clone)]
+struct Sample {
+    /// Wall clock time since the previous frame, in milliseconds
+    frame_time: f32,
+    stats: RenderStats,
+}
+
+/// Plays the camera path of the active camera and records every frame, then writes a report and
+/// closes the engine
+///
+/// Recording starts once the assets of the scene are loaded, so loading does not show up in the
+/// frame times. GPU times lag a few frames behind the frame they are recorded with. Has to run
+/// after the renderer.
+pub struct BenchmarkSystem {
+    config: BenchmarkConfig,
+    started: bool,
+    /// Set once the report is written, so the frame rendered while shutting down isn't recorded
+    finished: bool,
+    last_frame: Option<Instant>,
+    samples: Vec<Sample>,
+}
+
+impl BenchmarkSystem {
+    pub fn new(config: BenchmarkConfig) -> Self {
+        Self {
+            samples: Vec::with_capacity(config.frames as usize),
+            config,
+            started: false,
+            finished: false,
+            last_frame: None,
+        }
+    }
+
+    fn write_report(&self) -> io::Result<()> {
+        let mut file = BufWriter::new(File::create(&self.config.report)?);
+
+        writeln!(
+            file,
+            "frame,frame_ms,gpu_ms,gpu_culling_ms,gpu_main_ms,gpu_light_shafts_ms,gpu_post_ms,meshes,batched_meshes,point_lights,triangles,occlusion_queries,occluded_meshes,memory_bytes"
+        )?;
+        for (frame, sample) in self.samples.iter().enumerate() {
+            let stats = &sample.stats;
+            writeln!(
+                file,
+                "{},{:.3},{:.3},{:.3},{:.3},{:.3},{:.3},{},{},{},{},{},{},{}",
+                frame,
+                sample.frame_time,
+                stats.gpu_times.total(),
+                stats.gpu_times.culling,
+                stats.gpu_times.main,
+                stats.gpu_times.light_shafts,
+                stats.gpu_times.post,
+                stats.meshes,
+                stats.batched_meshes,
+                stats.point_lights,
+                stats.triangles,
+                stats.occlusion_queries,
+                stats.occluded_meshes,
+                stats.memory.total_bytes(),
+            )?;
+        }
+
+        file.flush()
+    }
+}
+
+/// The frame time below which a fraction of the frames are, in milliseconds
+fn percentile(sorted: &[f32], fraction: f32) -> f32 {
+    if sorted.is_empty() {
+        return 0.0;
+    }
+
+    let index = ((sorted.len() - 1) as f32 * fraction).round() as usize;
+    sorted[index]
+}
+
+impl<'a> System<'a> for BenchmarkSystem {
+    type SystemData = (
+        Read<'a, AssetStats>,
+        Read<'a, RenderStats>,
+        Write<'a, ShouldClose>,
+        ReadStorage<'a, ActiveCamera>,
+        WriteStorage<'a, CameraPath>,
+    );
+
+    fn run(
+        &mut self,
+        (assets, stats, mut should_close, active_cameras, mut paths): Self::SystemData,
+    ) {
+        if self.finished {
+            return;
+        }
+
+        // Wait for the scene to load, then play the path from the start
+        // -----------------------------------------------------------------------------------------------------
+        if !self.started {
+            if assets.queued > 0 || assets.active > 0 {
+                return;
+            }
+
+            let mut playing = 0;
+            for (path, _) in (&mut paths, &active_cameras).join() {
+                path.looping = true;
+                path.play();
+                playing += 1;
+            }
+            if playing == 0 {
+                warn!("Benchmarking without a camera path, the camera stands still");
+            }
+
+            info!("Benchmark started, recording {} frames", self.config.frames);
+            self.started = true;
+            self.last_frame = Some(Instant::now());
+            return;
+        }
+
+        // Record the frame
+        // -----------------------------------------------------------------------------------------------------
+        let now = Instant::now();
+        let frame_time = now
+            .float_duration_since(self.last_frame.unwrap())
+            .unwrap()
+            .as_seconds() as f32
+            * 1000.0;
+        self.last_frame = Some(now);
+
+        self.samples.push(Sample {
+            frame_time,
+            stats: stats.clone(),
+        });
+
+        if self.samples.len() < self.config.frames as usize {
+            return;
+        }
+
+        // Report and close
+        // -----------------------------------------------------------------------------------------------------
+        let mut frame_times = self
+            .samples
+            .iter()
+            .map(|sample| sample.frame_time)
+            .collect::<Vec<_>>();
+        frame_times.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let mean = frame_times.iter().sum::<f32>() / frame_times.len() as f32;
+
+        info!(
+            "Benchmark done: mean {:.2} ms, median {:.2} ms, 95th percentile {:.2} ms, 99th percentile {:.2} ms",
+            mean,
+            percentile(&frame_times, 0.5),
+            percentile(&frame_times, 0.95),
+            percentile(&frame_times, 0.99),
+        );
+
+        match self.write_report() {
+            Ok(()) => info!("Benchmark report written to {:?}", self.config.report),
+            Err(err) => warn!(
+                "Failed to write benchmark report to {:?}: {}",
+                self.config.report, err
+            ),
+        }
+
+        self.finished = true;
+        should_close.0 = true;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{percentile, BenchmarkConfig, DEFAULT_FRAMES};
+    use std::path::PathBuf;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn config_from_args() {
+        assert_eq!(BenchmarkConfig::from_args(args(&["vkengine"])), None);
+
+        let config = BenchmarkConfig::from_args(args(&["vkengine", "--benchmark"])).unwrap();
+        assert_eq!(config.frames, DEFAULT_FRAMES);
+        assert_eq!(config.report, PathBuf::from("benchmark.csv"));
+
+        let config = BenchmarkConfig::from_args(args(&[
+            "vkengine",
+            "--benchmark",
+            "500",
+            "--benchmark-report",
+            "out.csv",
+        ]))
+        .unwrap();
+        assert_eq!(config.frames, 500);
+        assert_eq!(config.report, PathBuf::from("out.csv"));
+    }
+
+    #[test]
+    fn percentiles() {
+        let sorted = (1..=100).map(|i| i as f32).collect::<Vec<_>>();
+        assert_eq!(percentile(&sorted, 0.5), 51.0);
+        assert_eq!(percentile(&sorted, 0.99), 99.0);
+        assert_eq!(percentile(&[], 0.5), 0.0);
+    }
+}
@@ -0,0 +1,622 @@
+use crate::resources::{ActionEvent, ControllerAxis, KeyboardEvent, Keycode};
+use log::{info, warn};
+use sdl2::keyboard::Mod;
+use serde::Deserialize;
+use std::{collections::HashMap, fs::File, path::Path};
+
+/// Input bindings as they are written in the bindings file
+///
+/// Keys are written with their SDL names, like "W" or "Left Shift". "Ctrl", "Shift" and "Alt"
+/// match the modifier on either side of the keyboard.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct InputBindingsFile {
+    /// Max seconds between the first and the last key of a chord
+    pub chord_window: f32,
+    /// Max seconds between the two presses of a double tap
+    pub double_tap_window: f32,
+    pub chords: Vec<ChordDesc>,
+    pub double_taps: Vec<DoubleTapDesc>,
+    /// Overrides of the default settings of controller axes
+    pub axes: Vec<AxisDesc>,
+}
+
+impl Default for InputBindingsFile {
+    fn default() -> Self {
+        Self {
+            chord_window: 0.2,
+            double_tap_window: 0.25,
+            chords: Vec::new(),
+            double_taps: Vec::new(),
+            axes: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ChordDesc {
+    pub action: String,
+    pub keys: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DoubleTapDesc {
+    pub action: String,
+    pub key: String,
+}
+
+/// Settings of a controller axis, with the axis by its SDL name, like "leftx" or "triggerright"
+#[derive(Debug, Deserialize)]
+pub struct AxisDesc {
+    pub axis: String,
+    pub dead_zone: f32,
+    #[serde(default)]
+    pub curve: ResponseCurve,
+    #[serde(default)]
+    pub invert: bool,
+}
+
+/// How the output of an axis grows as it moves away from the dead zone
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub enum ResponseCurve {
+    Linear,
+    /// Finer control close to the center, at the cost of less near the edge
+    Quadratic,
+}
+
+impl Default for ResponseCurve {
+    fn default() -> Self {
+        ResponseCurve::Linear
+    }
+}
+
+/// How the raw values of a controller axis are turned into values from -1 to 1
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AxisSettings {
+    /// Fraction of the range around the center that reads as 0, for sticks that do not center
+    pub dead_zone: f32,
+    pub curve: ResponseCurve,
+    pub invert: bool,
+}
+
+impl AxisSettings {
+    /// The defaults of an axis, with the dead zones recommended for Xbox controllers
+    pub fn default_for(axis: ControllerAxis) -> Self {
+        let dead_zone = match axis {
+            ControllerAxis::LeftX | ControllerAxis::LeftY => 7849,
+            ControllerAxis::RightX | ControllerAxis::RightY => 8689,
+            ControllerAxis::TriggerLeft | ControllerAxis::TriggerRight => 30,
+        };
+
+        Self {
+            dead_zone: f32::from(dead_zone) / f32::from(std::i16::MAX),
+            curve: ResponseCurve::Linear,
+            invert: false,
+        }
+    }
+
+    /// Normalizes a raw SDL axis value
+    ///
+    /// The range outside of the dead zone is stretched to cover 0 to 1, so the output does not
+    /// jump as the axis leaves the dead zone.
+    pub fn apply(&self, value: i16) -> f32 {
+        let value = (f32::from(value) / f32::from(std::i16::MAX))
+            .max(-1.0)
+            .min(1.0);
+
+        let magnitude = value.abs();
+        if magnitude <= self.dead_zone {
+            return 0.0;
+        }
+
+        let magnitude = (magnitude - self.dead_zone) / (1.0 - self.dead_zone);
+        let magnitude = match self.curve {
+            ResponseCurve::Linear => magnitude,
+            ResponseCurve::Quadratic => magnitude * magnitude,
+        };
+
+        let value = magnitude * value.signum();
+        if self.invert {
+            -value
+        } else {
+            value
+        }
+    }
+}
+
+/// A single part of a chord
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ChordKey {
+    Key(Keycode),
+    Mod(Mod),
+}
+
+impl ChordKey {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "Ctrl" => Some(ChordKey::Mod(Mod::LCTRLMOD | Mod::RCTRLMOD)),
+            "Shift" => Some(ChordKey::Mod(Mod::LSHIFTMOD | Mod::RSHIFTMOD)),
+            "Alt" => Some(ChordKey::Mod(Mod::LALTMOD | Mod::RALTMOD)),
+            name => Keycode::from_name(name).map(ChordKey::Key),
+        }
+    }
+}
+
+/// The Ctrl, Shift and Alt modifiers held down, with either side of the keyboard counting as both
+///
+/// Lock keys and the other modifiers are left out, so they never stop a chord from matching.
+fn chord_modifiers(keymod: Mod) -> Mod {
+    let pairs = [
+        Mod::LCTRLMOD | Mod::RCTRLMOD,
+        Mod::LSHIFTMOD | Mod::RSHIFTMOD,
+        Mod::LALTMOD | Mod::RALTMOD,
+    ];
+
+    pairs
+        .iter()
+        .filter(|&&pair| keymod.intersects(pair))
+        .fold(Mod::NOMOD, |modifiers, &pair| modifiers | pair)
+}
+
+#[derive(Debug)]
+struct Chord {
+    action: String,
+    keys: Vec<ChordKey>,
+}
+
+#[derive(Debug)]
+struct DoubleTap {
+    action: String,
+    key: Keycode,
+}
+
+/// Resource with the chords and double taps the input system turns into action events, and the
+/// settings of the controller axes
+#[derive(Debug)]
+pub struct InputBindings {
+    chord_window: f32,
+    double_tap_window: f32,
+    chords: Vec<Chord>,
+    double_taps: Vec<DoubleTap>,
+    axes: HashMap<ControllerAxis, AxisSettings>,
+}
+
+impl InputBindings {
+    /// Loads the bindings from a ron file, falling back to the defaults if it can't be read
+    pub fn load<P: AsRef<Path>>(path: P) -> Self {
+        let path = path.as_ref();
+
+        let file = File::open(path)
+            .map_err(|err| err.to_string())
+            .and_then(|file| ron::de::from_reader(file).map_err(|err| err.to_string()));
+
+        match file {
+            Ok(file) => {
+                info!("Loaded input bindings from {}", path.display());
+                Self::from(file)
+            }
+            Err(err) => {
+                warn!(
+                    "Failed to load input bindings from {}: {}. Using defaults",
+                    path.display(),
+                    err
+                );
+                Self::default()
+            }
+        }
+    }
+
+    /// The settings of a controller axis, the defaults unless the bindings file overrides them
+    pub fn axis(&self, axis: ControllerAxis) -> AxisSettings {
+        self.axes
+            .get(&axis)
+            .cloned()
+            .unwrap_or_else(|| AxisSettings::default_for(axis))
+    }
+
+    /// Whether double tapping `keycode` is bound to `action`
+    pub fn is_double_tap(&self, action: &str, keycode: Keycode) -> bool {
+        self.double_taps
+            .iter()
+            .any(|tap| tap.action == action && tap.key == keycode)
+    }
+}
+
+impl Default for InputBindings {
+    fn default() -> Self {
+        Self::from(InputBindingsFile::default())
+    }
+}
+
+impl From<InputBindingsFile> for InputBindings {
+    fn from(file: InputBindingsFile) -> Self {
+        let chords = file
+            .chords
+            .into_iter()
+            .filter_map(|desc| {
+                let keys = desc
+                    .keys
+                    .iter()
+                    .map(|name| ChordKey::from_name(name))
+                    .collect::<Option<Vec<_>>>();
+
+                match keys {
+                    Some(keys) => Some(Chord {
+                        action: desc.action,
+                        keys,
+                    }),
+                    None => {
+                        warn!("Unknown key in chord {:?}: {:?}", desc.action, desc.keys);
+                        None
+                    }
+                }
+            })
+            .collect();
+
+        let double_taps = file
+            .double_taps
+            .into_iter()
+            .filter_map(|desc| match Keycode::from_name(&desc.key) {
+                Some(key) => Some(DoubleTap {
+                    action: desc.action,
+                    key,
+                }),
+                None => {
+                    warn!(
+                        "Unknown key in double tap {:?}: {:?}",
+                        desc.action, desc.key
+                    );
+                    None
+                }
+            })
+            .collect();
+
+        let axes = file
+            .axes
+            .into_iter()
+            .filter_map(|desc| match ControllerAxis::from_string(&desc.axis) {
+                Some(axis) => {
+                    let settings = AxisSettings {
+                        dead_zone: desc.dead_zone,
+                        curve: desc.curve,
+                        invert: desc.invert,
+                    };
+                    Some((axis, settings))
+                }
+                None => {
+                    warn!("Unknown controller axis: {:?}", desc.axis);
+                    None
+                }
+            })
+            .collect();
+
+        Self {
+            chord_window: file.chord_window,
+            double_tap_window: file.double_tap_window,
+            chords,
+            double_taps,
+            axes,
+        }
+    }
+}
+
+/// Keeps track of key timings in order to detect chords and double taps
+#[derive(Debug, Default)]
+pub struct KeySequenceDetector {
+    // When each held key was pressed
+    held: HashMap<Keycode, f32>,
+    // When each key was last tapped
+    last_tap: HashMap<Keycode, f32>,
+}
+
+impl KeySequenceDetector {
+    /// Feeds a keyboard event to the detector, returning the actions it completes
+    ///
+    /// `now` is the time of the event in seconds.
+    pub fn handle(
+        &mut self,
+        bindings: &InputBindings,
+        event: &KeyboardEvent,
+        now: f32,
+    ) -> Vec<ActionEvent> {
+        if !event.pressed {
+            self.held.remove(&event.keycode);
+            return Vec::new();
+        }
+
+        // Holding a key down is not tapping it again
+        if event.repeat {
+            return Vec::new();
+        }
+
+        self.held.insert(event.keycode, now);
+
+        let mut actions = Vec::new();
+
+        // Double taps
+        let double_tapped = match self.last_tap.get(&event.keycode) {
+            Some(last) => now - last <= bindings.double_tap_window,
+            None => false,
+        };
+
+        if double_tapped {
+            // A third tap should start a new double tap
+            self.last_tap.remove(&event.keycode);
+
+            actions.extend(
+                bindings
+                    .double_taps
+                    .iter()
+                    .filter(|tap| tap.key == event.keycode)
+                    .map(|tap| ActionEvent(tap.action.clone())),
+            );
+        } else {
+            self.last_tap.insert(event.keycode, now);
+        }
+
+        // Chords, completed by the key that was just pressed
+        for chord in bindings.chords.iter() {
+            if !chord.keys.contains(&ChordKey::Key(event.keycode)) {
+                continue;
+            }
+
+            // Exactly the chord's modifiers have to be held, so Ctrl+S is not Ctrl+Shift+S
+            let modifiers = chord
+                .keys
+                .iter()
+                .filter_map(|key| match key {
+                    ChordKey::Mod(keymod) => Some(*keymod),
+                    ChordKey::Key(_) => None,
+                })
+                .fold(Mod::NOMOD, |modifiers, keymod| modifiers | keymod);
+
+            let complete = chord_modifiers(event.keymod) == modifiers
+                && chord.keys.iter().all(|key| match key {
+                    ChordKey::Key(keycode) => match self.held.get(keycode) {
+                        Some(pressed) => now - pressed <= bindings.chord_window,
+                        None => false,
+                    },
+                    ChordKey::Mod(_) => true,
+                });
+
+            if complete {
+                actions.push(ActionEvent(chord.action.clone()));
+            }
+        }
+
+        actions
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        AxisSettings, ChordDesc, DoubleTapDesc, InputBindings, InputBindingsFile,
+        KeySequenceDetector, ResponseCurve,
+    };
+    use crate::resources::{ActionEvent, KeyboardEvent, Keycode};
+    use sdl2::keyboard::Mod;
+
+    fn bindings() -> InputBindings {
+        InputBindings::from(InputBindingsFile {
+            chord_window: 0.2,
+            double_tap_window: 0.25,
+            chords: vec![
+                ChordDesc {
+                    action: "combo".to_string(),
+                    keys: vec!["J".to_string(), "K".to_string()],
+                },
+                ChordDesc {
+                    action: "save".to_string(),
+                    keys: vec!["Ctrl".to_string(), "S".to_string()],
+                },
+            ],
+            double_taps: vec![DoubleTapDesc {
+                action: "sprint".to_string(),
+                key: "W".to_string(),
+            }],
+            axes: Vec::new(),
+        })
+    }
+
+    fn press(keycode: Keycode) -> KeyboardEvent {
+        KeyboardEvent {
+            pressed: true,
+            keycode,
+            keymod: Mod::NOMOD,
+            repeat: false,
+        }
+    }
+
+    fn release(keycode: Keycode) -> KeyboardEvent {
+        KeyboardEvent {
+            pressed: false,
+            ..press(keycode)
+        }
+    }
+
+    fn action(name: &str) -> Vec<ActionEvent> {
+        vec![ActionEvent(name.to_string())]
+    }
+
+    #[test]
+    fn chords() {
+        let bindings = bindings();
+
+        // Within the chord window, the last key completes the chord
+        let mut detector = KeySequenceDetector::default();
+        assert!(detector
+            .handle(&bindings, &press(Keycode::J), 0.0)
+            .is_empty());
+        assert_eq!(
+            detector.handle(&bindings, &press(Keycode::K), 0.1),
+            action("combo")
+        );
+
+        // Outside of it, the first key has been held too long
+        let mut detector = KeySequenceDetector::default();
+        detector.handle(&bindings, &press(Keycode::J), 0.0);
+        assert!(detector
+            .handle(&bindings, &press(Keycode::K), 0.5)
+            .is_empty());
+
+        // A released key is no longer part of a chord
+        let mut detector = KeySequenceDetector::default();
+        detector.handle(&bindings, &press(Keycode::J), 0.0);
+        detector.handle(&bindings, &release(Keycode::J), 0.05);
+        assert!(detector
+            .handle(&bindings, &press(Keycode::K), 0.1)
+            .is_empty());
+    }
+
+    #[test]
+    fn modifier_chords() {
+        let bindings = bindings();
+        let mut detector = KeySequenceDetector::default();
+
+        assert!(detector
+            .handle(&bindings, &press(Keycode::S), 0.0)
+            .is_empty());
+
+        // Either side of the keyboard
+        for &keymod in &[Mod::LCTRLMOD, Mod::RCTRLMOD] {
+            let event = KeyboardEvent {
+                keymod,
+                ..press(Keycode::S)
+            };
+            assert_eq!(detector.handle(&bindings, &event, 1.0), action("save"));
+        }
+
+        // Another modifier is not enough
+        let event = KeyboardEvent {
+            keymod: Mod::LSHIFTMOD,
+            ..press(Keycode::S)
+        };
+        assert!(detector.handle(&bindings, &event, 2.0).is_empty());
+
+        // Lock keys do not get in the way
+        let event = KeyboardEvent {
+            keymod: Mod::LCTRLMOD | Mod::NUMMOD | Mod::CAPSMOD,
+            ..press(Keycode::S)
+        };
+        assert_eq!(detector.handle(&bindings, &event, 3.0), action("save"));
+    }
+
+    #[test]
+    fn extra_modifiers() {
+        let bindings = bindings();
+        let mut detector = KeySequenceDetector::default();
+
+        // Ctrl+S is bound, Ctrl+Shift+S and Ctrl+Alt+S are not
+        for &extra in &[Mod::LSHIFTMOD, Mod::RALTMOD] {
+            let event = KeyboardEvent {
+                keymod: Mod::LCTRLMOD | extra,
+                ..press(Keycode::S)
+            };
+            assert!(detector.handle(&bindings, &event, 0.0).is_empty());
+        }
+
+        // Nor are chords without modifiers completed while one is held
+        detector.handle(&bindings, &press(Keycode::J), 1.0);
+        let event = KeyboardEvent {
+            keymod: Mod::LCTRLMOD,
+            ..press(Keycode::K)
+        };
+        assert!(detector.handle(&bindings, &event, 1.1).is_empty());
+    }
+
+    #[test]
+    fn double_taps() {
+        let bindings = bindings();
+        let mut detector = KeySequenceDetector::default();
+
+        assert!(detector
+            .handle(&bindings, &press(Keycode::W), 0.0)
+            .is_empty());
+        detector.handle(&bindings, &release(Keycode::W), 0.05);
+        assert_eq!(
+            detector.handle(&bindings, &press(Keycode::W), 0.1),
+            action("sprint")
+        );
+        detector.handle(&bindings, &release(Keycode::W), 0.15);
+
+        // A third tap starts a new double tap instead of completing another
+        assert!(detector
+            .handle(&bindings, &press(Keycode::W), 0.2)
+            .is_empty());
+        detector.handle(&bindings, &release(Keycode::W), 0.25);
+        assert_eq!(
+            detector.handle(&bindings, &press(Keycode::W), 0.3),
+            action("sprint")
+        );
+        detector.handle(&bindings, &release(Keycode::W), 0.35);
+
+        // Taps too far apart are two single taps
+        assert!(detector
+            .handle(&bindings, &press(Keycode::W), 1.0)
+            .is_empty());
+        detector.handle(&bindings, &release(Keycode::W), 1.05);
+        assert!(detector
+            .handle(&bindings, &press(Keycode::W), 2.0)
+            .is_empty());
+    }
+
+    #[test]
+    fn key_repeat() {
+        let bindings = bindings();
+        let mut detector = KeySequenceDetector::default();
+
+        // Holding the key down repeats it, which is not a second tap
+        assert!(detector
+            .handle(&bindings, &press(Keycode::W), 0.0)
+            .is_empty());
+        let repeat = KeyboardEvent {
+            repeat: true,
+            ..press(Keycode::W)
+        };
+        assert!(detector.handle(&bindings, &repeat, 0.1).is_empty());
+        assert!(detector.handle(&bindings, &repeat, 0.15).is_empty());
+
+        // Nor does it move when the key was pressed, so the chord window still runs out
+        detector.handle(&bindings, &press(Keycode::J), 1.0);
+        let repeat = KeyboardEvent {
+            repeat: true,
+            ..press(Keycode::J)
+        };
+        detector.handle(&bindings, &repeat, 1.4);
+        assert!(detector
+            .handle(&bindings, &press(Keycode::K), 1.5)
+            .is_empty());
+    }
+
+    #[test]
+    fn double_tap_bindings() {
+        let bindings = bindings();
+        assert!(bindings.is_double_tap("sprint", Keycode::W));
+        assert!(!bindings.is_double_tap("sprint", Keycode::S));
+        assert!(!bindings.is_double_tap("jump", Keycode::W));
+    }
+
+    #[test]
+    fn axis_settings() {
+        let settings = AxisSettings {
+            dead_zone: 0.5,
+            curve: ResponseCurve::Linear,
+            invert: false,
+        };
+
+        assert_eq!(settings.apply(8000), 0.0);
+        assert_eq!(settings.apply(std::i16::MAX), 1.0);
+        assert_eq!(settings.apply(std::i16::MIN), -1.0);
+        let half = settings.apply(std::i16::MAX / 4 * 3);
+        assert!((half - 0.5).abs() < 0.001);
+
+        let quadratic = AxisSettings {
+            curve: ResponseCurve::Quadratic,
+            invert: true,
+            ..settings
+        };
+        let quarter = quadratic.apply(std::i16::MAX / 4 * 3);
+        assert!((quarter + 0.25).abs() < 0.001);
+    }
+}
@@ -0,0 +1,18 @@
+use crate::{renderer::camera::CameraEffects, resources::Time};
+use specs::prelude::*;
+
+/// Moves the shake and sway of cameras with CameraEffects along, and lets their trauma wear off
+///
+/// Uses scaled time, so the camera stops shaking while the game is paused.
+#[derive(Debug, Default)]
+pub struct CameraEffectsSystem;
+
+impl<'a> System<'a> for CameraEffectsSystem {
+    type SystemData = (Read<'a, Time>, WriteStorage<'a, CameraEffects>);
+
+    fn run(&mut self, (time, mut effects): Self::SystemData) {
+        for effects in (&mut effects).join() {
+            effects.advance(time.delta());
+        }
+    }
+}
@@ -0,0 +1,266 @@
+use crate::{
+    components::{Transform, TransformStorageExt},
+    renderer::camera::ActiveCamera,
+    resources::{ActionEvent, ActionEvents, Time},
+};
+use log::info;
+use nalgebra::{Point3, UnitQuaternion, Vector3};
+use shrev::ReaderId;
+use specs::prelude::*;
+use specs_derive::Component;
+
+/// Where a CameraPath passes through, and when
+#[derive(Debug, Clone)]
+pub struct Keyframe {
+    /// Seconds from the start of the path
+    pub time: f32,
+    pub transform: Transform,
+}
+
+impl Keyframe {
+    /// A camera at `eye` looking at `target`
+    pub fn look_at(time: f32, eye: Point3<f32>, target: Point3<f32>) -> Self {
+        // The view rotation maps the direction to -z, the camera's rotation maps -z back to it
+        let rotation = UnitQuaternion::look_at_rh(&(target - eye), &Vector3::y()).inverse();
+
+        Self {
+            time,
+            transform: Transform::from_parts(eye.coords, rotation, Vector3::new(1.0, 1.0, 1.0)),
+        }
+    }
+}
+
+/// Moves a camera along a Catmull-Rom spline through keyframes, for cinematics and benchmarks
+///
+/// The spline passes through the position of every keyframe, and the rotation is interpolated
+/// between the two keyframes around the current time. Keyframes are in world space, so the camera
+/// should have no parent. A path only moves the camera while it is playing, which it starts out
+/// not doing.
+#[derive(Component, Debug, Clone)]
+#[storage(HashMapStorage)]
+pub struct CameraPath {
+    /// Sorted by time
+    keyframes: Vec<Keyframe>,
+    /// Start over at the end, instead of stopping
+    pub looping: bool,
+    time: f32,
+    playing: bool,
+}
+
+impl CameraPath {
+    pub fn new(mut keyframes: Vec<Keyframe>) -> Self {
+        assert!(!keyframes.is_empty(), "Camera path without keyframes");
+        keyframes.sort_by(|a, b| a.time.partial_cmp(&b.time).unwrap());
+
+        Self {
+            keyframes,
+            looping: false,
+            time: 0.0,
+            playing: false,
+        }
+    }
+
+    pub fn looping(mut self) -> Self {
+        self.looping = true;
+        self
+    }
+
+    pub fn keyframes(&self) -> &[Keyframe] {
+        &self.keyframes
+    }
+
+    /// Seconds from the first keyframe to the last
+    pub fn duration(&self) -> f32 {
+        self.keyframes.last().unwrap().time - self.keyframes[0].time
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.playing
+    }
+
+    /// Starts playing from the beginning
+    pub fn play(&mut self) {
+        self.time = 0.0;
+        self.playing = true;
+    }
+
+    pub fn stop(&mut self) {
+        self.playing = false;
+    }
+
+    /// Moves playback forward, returning where the camera is now if the path is playing
+    pub fn advance(&mut self, delta: f32) -> Option<Transform> {
+        if !self.playing {
+            return None;
+        }
+
+        self.time += delta;
+        if self.time >= self.duration() {
+            if self.looping && self.duration() > 0.0 {
+                self.time %= self.duration();
+            } else {
+                self.time = self.duration();
+                self.playing = false;
+            }
+        }
+
+        Some(self.sample(self.keyframes[0].time + self.time))
+    }
+
+    /// The transform at a time, clamped to the keyframes
+    pub fn sample(&self, time: f32) -> Transform {
+        let keyframes = &self.keyframes;
+        if keyframes.len() == 1 {
+            return keyframes[0].transform.clone();
+        }
+
+        let last = keyframes.len() - 1;
+        let time = time.max(keyframes[0].time).min(keyframes[last].time);
+
+        // The segment from keyframe i to i + 1, and the keyframes around it for the tangents
+        let i = keyframes
+            .iter()
+            .rposition(|keyframe| keyframe.time <= time)
+            .unwrap()
+            .min(last - 1);
+        let (k0, k1, k2, k3) = (i.saturating_sub(1), i, i + 1, (i + 2).min(last));
+
+        let span = keyframes[k2].time - keyframes[k1].time;
+        let t = if span > std::f32::EPSILON {
+            (time - keyframes[k1].time) / span
+        } else {
+            1.0
+        };
+
+        // Tangents scaled to the length of the segment, so uneven keyframes do not overshoot
+        let tangent = |a: usize, b: usize| {
+            let dt = keyframes[b].time - keyframes[a].time;
+            if dt > std::f32::EPSILON {
+                (keyframes[b].transform.translation() - keyframes[a].transform.translation())
+                    * (span / dt)
+            } else {
+                Vector3::zeros()
+            }
+        };
+        let (m1, m2) = (tangent(k0, k2), tangent(k1, k3));
+        let (p1, p2) = (
+            keyframes[k1].transform.translation(),
+            keyframes[k2].transform.translation(),
+        );
+
+        // Cubic Hermite basis
+        let (t2, t3) = (t * t, t * t * t);
+        let position = p1 * (2.0 * t3 - 3.0 * t2 + 1.0)
+            + m1 * (t3 - 2.0 * t2 + t)
+            + p2 * (-2.0 * t3 + 3.0 * t2)
+            + m2 * (t3 - t2);
+
+        let mut transform = keyframes[k1]
+            .transform
+            .interpolate(&keyframes[k2].transform, t);
+        transform.set_translation(position);
+        transform
+    }
+}
+
+/// Plays the CameraPaths of active cameras
+///
+/// The "play_camera_path" action starts the paths from the beginning, or stops them if they are
+/// playing. Runs after the controllers, so a playing path overrides them.
+#[derive(Debug, Default)]
+pub struct CameraPathSystem {
+    action_read_id: Option<ReaderId<ActionEvent>>,
+}
+
+impl<'a> System<'a> for CameraPathSystem {
+    type SystemData = (
+        Read<'a, Time>,
+        Read<'a, ActionEvents>,
+        Entities<'a>,
+        ReadStorage<'a, ActiveCamera>,
+        WriteStorage<'a, CameraPath>,
+        WriteStorage<'a, Transform>,
+    );
+
+    fn run(
+        &mut self,
+        (time, action_events, entities, active_cameras, mut paths, mut transforms): Self::SystemData,
+    ) {
+        for ActionEvent(action) in action_events.read(self.action_read_id.as_mut().unwrap()) {
+            if action != "play_camera_path" {
+                continue;
+            }
+
+            for (path, _) in (&mut paths, &active_cameras).join() {
+                if path.is_playing() {
+                    path.stop();
+                    info!("Camera path stopped");
+                } else {
+                    path.play();
+                    info!("Playing camera path of {} seconds", path.duration());
+                }
+            }
+        }
+
+        for (camera, path, _) in (&entities, &mut paths, &active_cameras).join() {
+            if let Some(transform) = path.advance(time.delta()) {
+                transforms.modify(camera, |camera_t| *camera_t = transform);
+            }
+        }
+    }
+
+    fn setup(&mut self, res: &mut Resources) {
+        Self::SystemData::setup(res);
+
+        let mut actions = res.fetch_mut::<ActionEvents>();
+        self.action_read_id = Some(actions.register_reader());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{CameraPath, Keyframe};
+    use nalgebra::{Point3, Vector3};
+
+    #[test]
+    fn spline() {
+        let target = Point3::origin();
+        let mut path = CameraPath::new(vec![
+            Keyframe::look_at(2.0, Point3::new(10.0, 0.0, 0.0), target),
+            Keyframe::look_at(0.0, Point3::new(0.0, 0.0, 10.0), target),
+            Keyframe::look_at(3.0, Point3::new(0.0, 0.0, -10.0), target),
+        ]);
+        assert_eq!(path.duration(), 3.0);
+
+        // Passes through every keyframe, sorted by time
+        for keyframe in path.keyframes() {
+            let sampled = path.sample(keyframe.time);
+            assert!((sampled.translation() - keyframe.transform.translation()).norm() < 1e-4);
+        }
+
+        // Curves outwards between keyframes, where a straight line would cut the corner
+        let between = path.sample(1.0);
+        assert!(between.translation().norm() > 50.0f32.sqrt() + 0.1);
+
+        // Looks at the target along the way
+        let forward = between.rotation() * -Vector3::z();
+        let to_target = -between.translation().normalize();
+        assert!(forward.dot(&to_target) > 0.9);
+
+        // Clamped outside of the keyframes
+        assert_eq!(path.sample(-1.0), path.sample(0.0));
+
+        // Playback stops at the end, unless the path loops
+        path.play();
+        assert!(path.advance(2.0).is_some());
+        assert!(path.advance(2.0).is_some());
+        assert!(!path.is_playing());
+        assert!(path.advance(1.0).is_none());
+
+        let mut path = path.looping();
+        path.play();
+        path.advance(4.0);
+        assert!(path.is_playing());
+        assert!((path.time - 1.0).abs() < 1e-4);
+    }
+}
@@ -0,0 +1,382 @@
+use crate::{
+    components::{GlobalTransform, PlayerId, Transform, TransformStorageExt},
+    renderer::{
+        camera::ActiveCamera,
+        geometry::{Bounds, Ghost},
+    },
+    resources::{ActionEvent, ActionEvents, FocusGained, Time},
+    systems::{FlyControlSystem, GameInputs, SpatialIndex},
+};
+use log::info;
+use nalgebra::{Point3, Vector3};
+use ncollide3d::bounding_volume::BoundingSphere;
+use shrev::ReaderId;
+use specs::prelude::*;
+use specs_derive::Component;
+
+/// Distance kept between a character and the bounds it stops at
+const CHARACTER_SKIN: f32 = 0.001;
+
+/// Walks an entity around on its feet, instead of flying it
+///
+/// The character is an upright capsule that collides with the bounds of meshes, falls with
+/// gravity, steps onto low ledges and jumps on the "jump" action. The transform of the entity is
+/// at the eyes, and has to have no parent.
+#[derive(Component, Debug, Clone)]
+#[storage(HashMapStorage)]
+pub struct CharacterController {
+    /// From the feet to the top of the head
+    pub height: f32,
+    pub radius: f32,
+    /// Height of the eyes, and so of the transform, above the feet
+    pub eye_height: f32,
+    /// Highest ledge walked onto without jumping
+    pub step_height: f32,
+    /// In meters per second, tripled when sprinting
+    pub speed: f32,
+    /// Upwards speed at the start of a jump
+    pub jump_speed: f32,
+    /// In meters per second squared
+    pub gravity: f32,
+    vertical_speed: f32,
+    grounded: bool,
+}
+
+impl CharacterController {
+    /// Center of the capsule of a character with its eyes at `eye`
+    fn center(&self, eye: &Point3<f32>) -> Point3<f32> {
+        eye + Vector3::y() * (self.height * 0.5 - self.eye_height)
+    }
+
+    /// Half the length of the segment the capsule is rounded around
+    fn half_height(&self) -> f32 {
+        (self.height * 0.5 - self.radius).max(0.0)
+    }
+
+    /// Moves the capsule centered at `center` by `motion`, stopping short of the first obstacle
+    ///
+    /// `cast` sweeps the capsule, returning the fraction of the motion made before it touches an
+    /// obstacle. Returns where the capsule stopped, and whether it hit anything.
+    fn sweep<F>(center: &Point3<f32>, motion: &Vector3<f32>, cast: &F) -> (Point3<f32>, bool)
+    where
+        F: Fn(&Point3<f32>, &Vector3<f32>) -> Option<f32>,
+    {
+        let length = motion.norm();
+        if length <= std::f32::EPSILON {
+            return (*center, false);
+        }
+
+        match cast(center, motion) {
+            Some(toi) => (
+                center + motion * (toi - CHARACTER_SKIN / length).max(0.0),
+                true,
+            ),
+            None => (center + motion, false),
+        }
+    }
+
+    /// Walks the capsule by a horizontal `motion`, sliding along walls and stepping onto ledges
+    fn walk<F>(&self, center: &Point3<f32>, motion: &Vector3<f32>, cast: &F) -> Point3<f32>
+    where
+        F: Fn(&Point3<f32>, &Vector3<f32>) -> Option<f32>,
+    {
+        let (moved, hit) = Self::sweep(center, motion, cast);
+        if !hit {
+            return moved;
+        }
+
+        // Slides along whatever is in the way, one axis at a time
+        let remaining = motion - (moved - center);
+        let slid = [Vector3::x(), Vector3::z()]
+            .iter()
+            .fold(moved, |position, axis| {
+                Self::sweep(&position, &remaining.component_mul(axis), cast).0
+            });
+
+        // Steps up, over and back down again, in case the obstacle is low enough to step onto
+        let (up, _) = Self::sweep(center, &(Vector3::y() * self.step_height), cast);
+        let (over, _) = Self::sweep(&up, motion, cast);
+        let (stepped, _) = Self::sweep(&over, &(Vector3::y() * (center.y - up.y)), cast);
+
+        let walked = |position: &Point3<f32>| (position - center).xz().norm();
+        if walked(&stepped) > walked(&slid) + CHARACTER_SKIN {
+            stepped
+        } else {
+            slid
+        }
+    }
+
+    /// Moves the capsule centered at `center` by a frame's worth of walking and falling
+    ///
+    /// `motion` is the horizontal distance walked this frame. Jumping only works on the ground.
+    fn step<F>(
+        &mut self,
+        center: &Point3<f32>,
+        motion: &Vector3<f32>,
+        jump: bool,
+        delta: f32,
+        cast: &F,
+    ) -> Point3<f32>
+    where
+        F: Fn(&Point3<f32>, &Vector3<f32>) -> Option<f32>,
+    {
+        if jump && self.grounded {
+            self.vertical_speed = self.jump_speed;
+        }
+        self.vertical_speed -= self.gravity * delta;
+
+        let walked = self.walk(center, motion, cast);
+        let (position, hit) =
+            Self::sweep(&walked, &(Vector3::y() * self.vertical_speed * delta), cast);
+
+        // Landing, or hitting the ceiling, stops the vertical motion
+        self.grounded = hit && self.vertical_speed <= 0.0;
+        if hit {
+            self.vertical_speed = 0.0;
+        }
+
+        position
+    }
+}
+
+impl Default for CharacterController {
+    fn default() -> Self {
+        Self {
+            height: 1.8,
+            radius: 0.3,
+            eye_height: 1.6,
+            step_height: 0.3,
+            speed: 1.5,
+            jump_speed: 4.0,
+            gravity: 9.81,
+            vertical_speed: 0.0,
+            grounded: false,
+        }
+    }
+}
+
+/// Character control system
+///
+/// Walks every active camera with a CharacterController, which the FlyControlSystem leaves alone,
+/// looking around the same way. The "toggle_walking" action switches the cameras of player 0
+/// between walking and flying.
+#[derive(Debug, Default)]
+pub struct CharacterControlSystem {
+    action_read_id: Option<ReaderId<ActionEvent>>,
+}
+
+impl<'a> System<'a> for CharacterControlSystem {
+    type SystemData = (
+        Read<'a, Time>,
+        Read<'a, FocusGained>,
+        Read<'a, ActionEvents>,
+        Write<'a, GameInputs>,
+        Read<'a, SpatialIndex>,
+        Entities<'a>,
+        ReadStorage<'a, ActiveCamera>,
+        ReadStorage<'a, PlayerId>,
+        ReadStorage<'a, Bounds>,
+        ReadStorage<'a, Ghost>,
+        ReadStorage<'a, GlobalTransform>,
+        WriteStorage<'a, CharacterController>,
+        WriteStorage<'a, Transform>,
+    );
+
+    fn run(
+        &mut self,
+        (
+            time,
+            input_enabled,
+            action_events,
+            mut inputs,
+            index,
+            entities,
+            active_camera,
+            players,
+            bounds,
+            ghosts,
+            globals,
+            mut characters,
+            mut transforms,
+        ): Self::SystemData,
+    ) {
+        // Switch between walking and flying
+        // -----------------------------------------------------------------------------------------------------
+        for ActionEvent(action) in action_events.read(self.action_read_id.as_mut().unwrap()) {
+            if action != "toggle_walking" {
+                continue;
+            }
+
+            let cameras = (&entities, &active_camera, players.maybe())
+                .join()
+                .filter(|(_, _, player)| player.cloned().unwrap_or_default() == PlayerId::default())
+                .map(|(camera, _, _)| camera)
+                .collect::<Vec<_>>();
+            for camera in cameras {
+                if characters.remove(camera).is_some() {
+                    info!("Flying");
+                } else {
+                    characters
+                        .insert(camera, CharacterController::default())
+                        .unwrap();
+                    info!("Walking");
+                }
+            }
+        }
+
+        // Walk the characters
+        // -----------------------------------------------------------------------------------------------------
+        for (entity, _, character, player) in
+            (&entities, &active_camera, &mut characters, players.maybe()).join()
+        {
+            let player = player.cloned().unwrap_or_default();
+            let jump = input_enabled.0 && inputs.get_mut(player).jump;
+            inputs.get_mut(player).jump = false;
+
+            // Characters keep falling while the window is not focused, they just stop walking
+            let input = inputs.get(player).filter(|_| input_enabled.0);
+            let (radius, half_height) = (character.radius, character.half_height());
+
+            transforms.modify(entity, |transform| {
+                let motion = match input {
+                    Some(input) => {
+                        FlyControlSystem::look(transform, input);
+
+                        let speed = if input.sprint { 3.0 } else { 1.0 } * character.speed;
+                        let forward = transform.rotation() * -Vector3::z();
+                        let right = transform.rotation() * Vector3::x();
+                        let direction = Vector3::new(forward.x, 0.0, forward.z)
+                            * input.forward.get()
+                            + Vector3::new(right.x, 0.0, right.z) * input.right.get();
+
+                        if direction.norm() > std::f32::EPSILON {
+                            direction.normalize() * speed * time.delta()
+                        } else {
+                            Vector3::zeros()
+                        }
+                    }
+                    None => Vector3::zeros(),
+                };
+
+                let eye = Point3::from(*transform.translation());
+                let center = character.center(&eye);
+
+                // Only meshes near the path of the character can be in the way
+                let reach = motion.norm()
+                    + (character.vertical_speed * time.delta()).abs()
+                    + character.step_height;
+                let path = BoundingSphere::new(center, reach + half_height + radius);
+                let obstacles = index
+                    .sphere(&path)
+                    .into_iter()
+                    .filter(|obstacle| *obstacle != entity && !ghosts.contains(*obstacle))
+                    .filter_map(|obstacle| Some((bounds.get(obstacle)?, globals.get(obstacle)?)))
+                    .collect::<Vec<_>>();
+
+                let cast = |center: &Point3<f32>, motion: &Vector3<f32>| {
+                    obstacles
+                        .iter()
+                        .filter_map(|(bounds, global)| {
+                            bounds.cast_capsule(global, center, half_height, radius, motion)
+                        })
+                        .min_by(|a, b| a.partial_cmp(b).unwrap())
+                };
+
+                let moved = character.step(&center, &motion, jump, time.delta(), &cast);
+                transform.set_translation(transform.translation() + (moved - center));
+            });
+        }
+    }
+
+    fn setup(&mut self, res: &mut Resources) {
+        Self::SystemData::setup(res);
+
+        let mut actions = res.fetch_mut::<ActionEvents>();
+        self.action_read_id = Some(actions.register_reader());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::CharacterController;
+    use crate::{components::Transform, renderer::geometry::Bounds};
+    use nalgebra::{Point3, UnitQuaternion, Vector3};
+    use ncollide3d::bounding_volume::{BoundingSphere, AABB};
+
+    /// A unit cube scaled and moved into place
+    fn block(center: Vector3<f32>, size: Vector3<f32>) -> (Bounds, Transform) {
+        let bounds = Bounds {
+            aabb: AABB::new(Point3::new(-0.5, -0.5, -0.5), Point3::new(0.5, 0.5, 0.5)),
+            sphere: BoundingSphere::new(Point3::origin(), 0.75f32.sqrt()),
+        };
+        (
+            bounds,
+            Transform::from_parts(center, UnitQuaternion::identity(), size),
+        )
+    }
+
+    #[test]
+    fn walking() {
+        // A floor with its top at 0, a ledge 0.2 high in front and a wall behind
+        let obstacles = vec![
+            block(
+                Vector3::new(0.0, -0.5, 0.0),
+                Vector3::new(100.0, 1.0, 100.0),
+            ),
+            block(Vector3::new(3.0, 0.1, 0.0), Vector3::new(2.0, 0.2, 10.0)),
+            block(Vector3::new(-3.0, 2.0, 0.0), Vector3::new(2.0, 4.0, 10.0)),
+        ];
+
+        let mut character = CharacterController::default();
+        let (radius, half_height) = (character.radius, character.half_height());
+        let cast = |center: &Point3<f32>, motion: &Vector3<f32>| {
+            obstacles
+                .iter()
+                .filter_map(|(bounds, global)| {
+                    bounds.cast_capsule(global, center, half_height, radius, motion)
+                })
+                .min_by(|a, b| a.partial_cmp(b).unwrap())
+        };
+        let feet = |center: &Point3<f32>| center.y - character.height * 0.5;
+
+        // Falls onto the floor
+        let mut center = Point3::new(0.0, 3.0, 0.0);
+        for _ in 0..120 {
+            center = character.step(&center, &Vector3::zeros(), false, 1.0 / 60.0, &cast);
+        }
+        assert!(character.grounded);
+        assert!(feet(&center).abs() < 0.01);
+
+        // Steps onto the ledge
+        for _ in 0..60 {
+            let motion = Vector3::new(0.05, 0.0, 0.0);
+            center = character.step(&center, &motion, false, 1.0 / 60.0, &cast);
+        }
+        assert!(center.x > 2.5);
+        assert!((feet(&center) - 0.2).abs() < 0.01);
+
+        // Stops at the wall
+        for _ in 0..200 {
+            let motion = Vector3::new(-0.05, 0.0, 0.0);
+            center = character.step(&center, &motion, false, 1.0 / 60.0, &cast);
+        }
+        assert!((center.x - (-2.0 + radius)).abs() < 0.01);
+
+        // Slides along the wall
+        let motion = Vector3::new(-0.05, 0.0, 0.05);
+        let before = center;
+        center = character.step(&center, &motion, false, 1.0 / 60.0, &cast);
+        assert!((center.z - before.z - 0.05).abs() < 0.01);
+        assert!((center.x - before.x).abs() < 0.01);
+
+        // Jumps off the floor, and lands again
+        center = character.step(&center, &Vector3::zeros(), true, 1.0 / 60.0, &cast);
+        assert!(!character.grounded);
+        assert!(feet(&center) > 0.0);
+        for _ in 0..120 {
+            center = character.step(&center, &Vector3::zeros(), false, 1.0 / 60.0, &cast);
+        }
+        assert!(character.grounded);
+        assert!(feet(&center).abs() < 0.01);
+    }
+}
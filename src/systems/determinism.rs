@@ -0,0 +1,116 @@
+use log::warn;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Simulation step of every frame in deterministic mode, in seconds
+const DEFAULT_TIMESTEP: f32 = 1.0 / 60.0;
+
+/// How the simulation is seeded and stepped, from the command line
+///
+/// `--seed <n>` seeds the Rng resource, which is seeded from the clock otherwise. `--deterministic`
+/// steps every frame by the same timestep instead of the time that has passed, and seeds with 0
+/// unless a seed is given, so runs with the same input play out the same. `--sim-rate <hz>` steps
+/// the simulation that many times a second instead of once per frame, see FixedStep.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeterminismConfig {
+    pub seed: u64,
+    /// Whether every frame is stepped by `timestep`, ignoring the wall clock
+    pub deterministic: bool,
+    pub timestep: f32,
+    /// Simulation steps per second, when the simulation is stepped apart from rendering
+    pub sim_rate: Option<f32>,
+}
+
+impl DeterminismConfig {
+    pub fn from_args<I>(args: I) -> Self
+    where
+        I: IntoIterator<Item = String>,
+    {
+        let mut seed = None;
+        let mut deterministic = false;
+        let mut sim_rate = None;
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--seed" => match args.next().and_then(|seed| seed.parse().ok()) {
+                    Some(value) => seed = Some(value),
+                    None => warn!("--seed needs a number"),
+                },
+                "--deterministic" => deterministic = true,
+                "--sim-rate" => match args.next().and_then(|rate| rate.parse().ok()) {
+                    Some(rate) if rate > 0.0 => sim_rate = Some(rate),
+                    _ => warn!("--sim-rate needs a number of steps per second"),
+                },
+                _ => (),
+            }
+        }
+
+        let seed = seed.unwrap_or_else(|| {
+            if deterministic {
+                0
+            } else {
+                SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|time| time.as_secs() ^ u64::from(time.subsec_nanos()))
+                    .unwrap_or(0)
+            }
+        });
+
+        Self {
+            seed,
+            deterministic,
+            timestep: DEFAULT_TIMESTEP,
+            sim_rate,
+        }
+    }
+
+    /// Seconds per simulation step when it runs at its own rate
+    ///
+    /// Deterministic runs step once per frame instead, whatever the rate.
+    pub fn fixed_step(&self) -> Option<f32> {
+        if self.deterministic {
+            None
+        } else {
+            self.sim_rate.map(|rate| 1.0 / rate)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{DeterminismConfig, DEFAULT_TIMESTEP};
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn config_from_args() {
+        let config = DeterminismConfig::from_args(args(&["vkengine"]));
+        assert!(!config.deterministic);
+
+        let config = DeterminismConfig::from_args(args(&["vkengine", "--deterministic"]));
+        assert!(config.deterministic);
+        assert_eq!(config.seed, 0);
+        assert_eq!(config.timestep, DEFAULT_TIMESTEP);
+
+        let config = DeterminismConfig::from_args(args(&["vkengine", "--seed", "42"]));
+        assert!(!config.deterministic);
+        assert_eq!(config.seed, 42);
+
+        let config = DeterminismConfig::from_args(args(&["vkengine", "--sim-rate", "30"]));
+        assert_eq!(config.sim_rate, Some(30.0));
+        assert_eq!(config.fixed_step(), Some(1.0 / 30.0));
+
+        let config = DeterminismConfig::from_args(args(&[
+            "vkengine",
+            "--sim-rate",
+            "30",
+            "--deterministic",
+        ]));
+        assert_eq!(config.fixed_step(), None);
+
+        let config = DeterminismConfig::from_args(args(&["vkengine", "--sim-rate", "0"]));
+        assert_eq!(config.sim_rate, None);
+    }
+}
@@ -0,0 +1,25 @@
+use crate::{
+    renderer::camera::{AutoExposure, Camera},
+    resources::Time,
+};
+use specs::prelude::*;
+
+/// Adapts the exposure of cameras with AutoExposure to the luminance the renderer measured
+///
+/// Uses real time, so the exposure keeps adapting while the game is paused or slowed down.
+#[derive(Debug, Default)]
+pub struct AutoExposureSystem;
+
+impl<'a> System<'a> for AutoExposureSystem {
+    type SystemData = (
+        Read<'a, Time>,
+        ReadStorage<'a, AutoExposure>,
+        WriteStorage<'a, Camera>,
+    );
+
+    fn run(&mut self, (time, auto_exposures, mut cameras): Self::SystemData) {
+        for (auto_exposure, camera) in (&auto_exposures, &mut cameras).join() {
+            camera.exposure = auto_exposure.adapt(camera.exposure, time.real_delta());
+        }
+    }
+}
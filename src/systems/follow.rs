@@ -0,0 +1,185 @@
+use crate::{
+    components::{
+        damp_rotation, damp_spring, Link, PlayerId, Transform, TransformQuery, TransformStorageExt,
+    },
+    renderer::camera::ActiveCamera,
+    resources::{ActionEvent, ActionEvents, Time},
+};
+use log::info;
+use nalgebra::{Point3, UnitQuaternion, Vector3};
+use shrev::ReaderId;
+use specs::prelude::*;
+use specs_derive::Component;
+
+/// Makes a camera follow an entity around
+///
+/// The camera is kept at `offset` from the target, in the space of the target, so it stays
+/// behind the target as it turns, and further back from bigger targets. It is pulled there by a critically damped spring, which catches
+/// up as fast as it can without overshooting, and keeps looking at the target. The camera should
+/// have no parent, and should not be walked by a CharacterController at the same time.
+///
+/// While not following, the camera is flown as usual. The "toggle_follow" action switches the
+/// cameras of player 0 between the two.
+#[derive(Component, Debug, Clone)]
+#[storage(HashMapStorage)]
+pub struct FollowTarget {
+    pub entity: Entity,
+    /// Where the camera is kept, in the space of the target
+    pub offset: Vector3<f32>,
+    /// How fast the camera catches up, higher is faster. It gets half way there in about
+    /// 1.7 / stiffness seconds
+    pub stiffness: f32,
+    /// Whether the camera follows the target, or is flown
+    pub following: bool,
+    /// How fast the camera is moving towards the target
+    velocity: Vector3<f32>,
+}
+
+impl FollowTarget {
+    /// Follows `entity` from behind and above
+    pub fn new(entity: Entity) -> Self {
+        Self {
+            entity,
+            offset: Vector3::new(0.0, 2.0, 6.0),
+            stiffness: 4.0,
+            following: true,
+            velocity: Vector3::zeros(),
+        }
+    }
+
+    /// Starts out flown, until switched to following
+    pub fn flown(mut self) -> Self {
+        self.following = false;
+        self
+    }
+
+    /// Switches between following and flying, starting from rest
+    pub fn toggle(&mut self) {
+        self.following = !self.following;
+        self.velocity = Vector3::zeros();
+    }
+}
+
+/// Moves cameras with a FollowTarget after their targets, while they are following
+///
+/// Runs after the controllers, which leave following cameras alone. Targets are looked up with a
+/// TransformQuery, so the camera sees where the controllers moved them this frame.
+#[derive(Debug, Default)]
+pub struct FollowSystem {
+    action_read_id: Option<ReaderId<ActionEvent>>,
+}
+
+impl<'a> System<'a> for FollowSystem {
+    type SystemData = (
+        Read<'a, Time>,
+        Read<'a, ActionEvents>,
+        Entities<'a>,
+        ReadStorage<'a, ActiveCamera>,
+        ReadStorage<'a, PlayerId>,
+        ReadStorage<'a, Link>,
+        WriteStorage<'a, FollowTarget>,
+        WriteStorage<'a, Transform>,
+    );
+
+    fn run(
+        &mut self,
+        (
+            time,
+            action_events,
+            entities,
+            active_cameras,
+            players,
+            links,
+            mut follows,
+            mut transforms,
+        ): Self::SystemData,
+    ) {
+        for ActionEvent(action) in action_events.read(self.action_read_id.as_mut().unwrap()) {
+            if action != "toggle_follow" {
+                continue;
+            }
+
+            for (follow, _, player) in (&mut follows, &active_cameras, players.maybe()).join() {
+                if player.cloned().unwrap_or_default() != PlayerId::default() {
+                    continue;
+                }
+
+                follow.toggle();
+                if follow.following {
+                    info!("Following");
+                } else {
+                    info!("Flying");
+                }
+            }
+        }
+
+        for (camera, follow) in (&entities, &mut follows).join() {
+            if !follow.following || !entities.is_alive(follow.entity) {
+                continue;
+            }
+
+            let query = TransformQuery::new(&transforms, &links);
+            let (target, goal) = match (
+                query.global(follow.entity),
+                query.local_to_world(follow.entity, &Point3::from(follow.offset)),
+            ) {
+                (Some(target), Some(goal)) => (target, goal.coords),
+                _ => continue,
+            };
+            let delta = time.delta();
+
+            transforms.modify(camera, |camera_t| {
+                let position = damp_spring(
+                    camera_t.translation(),
+                    &goal,
+                    &mut follow.velocity,
+                    follow.stiffness,
+                    delta,
+                );
+                camera_t.set_translation(position);
+
+                if let Some(look) = looking_at(&position, target.translation()) {
+                    let rotation =
+                        damp_rotation(camera_t.rotation(), &look, follow.stiffness, delta);
+                    camera_t.set_rotation(rotation);
+                }
+            });
+        }
+    }
+
+    fn setup(&mut self, res: &mut Resources) {
+        Self::SystemData::setup(res);
+
+        let mut actions = res.fetch_mut::<ActionEvents>();
+        self.action_read_id = Some(actions.register_reader());
+    }
+}
+
+/// The rotation of a camera at `eye` looking at `target`, if it is not straight up or down
+fn looking_at(eye: &Vector3<f32>, target: &Vector3<f32>) -> Option<UnitQuaternion<f32>> {
+    let direction = target - eye;
+    if direction.cross(&Vector3::y()).norm() < 1e-4 {
+        return None;
+    }
+
+    // The view rotation maps the direction to -z, the camera's rotation maps -z back to it
+    Some(UnitQuaternion::look_at_rh(&direction, &Vector3::y()).inverse())
+}
+
+#[cfg(test)]
+mod test {
+    use super::looking_at;
+    use nalgebra::Vector3;
+
+    #[test]
+    fn looking() {
+        let rotation = looking_at(&Vector3::new(0.0, 0.0, 5.0), &Vector3::zeros()).unwrap();
+        assert!((rotation * -Vector3::z() - -Vector3::z()).norm() < 1e-5);
+
+        let rotation = looking_at(&Vector3::zeros(), &Vector3::new(3.0, 0.0, 0.0)).unwrap();
+        assert!((rotation * -Vector3::z() - Vector3::x()).norm() < 1e-5);
+
+        // No rotation looks straight down with y up
+        assert!(looking_at(&Vector3::new(0.0, 5.0, 0.0), &Vector3::zeros()).is_none());
+    }
+}
@@ -0,0 +1,252 @@
+use crate::{
+    components::{Link, Transform, TransformStorageExt},
+    renderer::{
+        csg::CsgOp,
+        geometry::{Ghost, MeshBuilder, Shape},
+        lights::{DirectionalLightRes, PointLightComponent},
+        settings::RenderSettings,
+    },
+    systems::CameraPath,
+};
+use nalgebra::{UnitQuaternion, Vector3};
+use specs::prelude::*;
+use specs_derive::Component;
+use std::{collections::HashMap, f32::consts::PI};
+
+/// Where the arrow showing the directional light is placed, as the light itself has no position
+const SUN_GIZMO_POSITION: [f32; 3] = [0.0, 5.0, -10.0];
+/// Size of the spheres showing point lights
+const POINT_GIZMO_SCALE: f32 = 0.15;
+/// Size of the beads showing the keyframes of camera paths
+const KEYFRAME_GIZMO_SCALE: f32 = 0.2;
+/// Size of the beads trailing along camera paths between keyframes
+const PATH_GIZMO_SCALE: f32 = 0.05;
+/// Beads between two keyframes of a camera path
+const PATH_GIZMO_BEADS: usize = 8;
+
+/// Marks an entity as a gizmo created by the LightGizmoSystem
+#[derive(Component, Debug, Default)]
+#[storage(NullStorage)]
+pub struct LightGizmo;
+
+/// An arrow along +y, a cylinder shaft with a cone for the head
+pub fn arrow() -> MeshBuilder {
+    let head = MeshBuilder::new()
+        .with_shape(Shape::Cone(16))
+        .transformed(&Transform::from(Vector3::new(0.0, 0.7, 0.0)));
+
+    MeshBuilder::new()
+        .with_shape(Shape::Cylinder(16))
+        .transformed(&Transform::from_parts(
+            Vector3::new(0.0, 0.0, 0.0),
+            UnitQuaternion::identity(),
+            Vector3::new(0.2, 1.0, 0.2),
+        ))
+        .with_csg(CsgOp::Union, head)
+}
+
+/// Rotation turning the arrow to point along a direction
+pub fn arrow_rotation(direction: &Vector3<f32>) -> UnitQuaternion<f32> {
+    // There is no single rotation between opposite vectors
+    UnitQuaternion::rotation_between(&Vector3::y(), direction)
+        .unwrap_or_else(|| UnitQuaternion::from_axis_angle(&Vector3::x_axis(), PI))
+}
+
+/// Shows where the lights are while the show_light_gizmos debug flag is on
+///
+/// The directional light gets an arrow pointing the way the light shines, and every point light
+/// a small sphere attached to it. The gizmos are unlit ghosts, so they stand out from the scene.
+/// Rotating the arrow rotates the directional light with it.
+#[derive(Debug)]
+pub struct LightGizmoSystem {
+    sun: Option<Entity>,
+    /// The rotation last given to the arrow, to tell if something else has rotated it since
+    sun_rotation: UnitQuaternion<f32>,
+    points: HashMap<Entity, Entity>,
+}
+
+impl Default for LightGizmoSystem {
+    fn default() -> Self {
+        Self {
+            sun: None,
+            sun_rotation: UnitQuaternion::identity(),
+            points: HashMap::new(),
+        }
+    }
+}
+
+impl<'a> System<'a> for LightGizmoSystem {
+    type SystemData = (
+        Entities<'a>,
+        Read<'a, LazyUpdate>,
+        Read<'a, RenderSettings>,
+        Write<'a, DirectionalLightRes>,
+        ReadStorage<'a, PointLightComponent>,
+        WriteStorage<'a, Transform>,
+    );
+
+    fn run(
+        &mut self,
+        (entities, lazy, settings, mut sun_light, point_lights, mut transforms): Self::SystemData,
+    ) {
+        if !settings.show_light_gizmos {
+            if let Some(sun) = self.sun.take() {
+                let _ = entities.delete(sun);
+            }
+            for (_, gizmo) in self.points.drain() {
+                let _ = entities.delete(gizmo);
+            }
+            return;
+        }
+
+        // Directional light
+        // -----------------------------------------------------------------------------------------------------
+        match self.sun {
+            // Deleted by something else, so it is made again next frame
+            Some(sun) if !entities.is_alive(sun) => self.sun = None,
+            // Not built yet
+            Some(sun) if !transforms.contains(sun) => (),
+            Some(sun) => {
+                let rotation = *transforms.get(sun).unwrap().rotation();
+
+                if sun_light.dirty {
+                    self.sun_rotation = arrow_rotation(&sun_light.direction());
+                    transforms.set_rotation(sun, self.sun_rotation);
+                } else if rotation != self.sun_rotation {
+                    self.sun_rotation = rotation;
+                    sun_light.set_direction(rotation * Vector3::y());
+                }
+            }
+            None => {
+                self.sun_rotation = arrow_rotation(&sun_light.direction());
+
+                let sun = lazy
+                    .create_entity(&entities)
+                    .with(Transform::from_parts(
+                        Vector3::from(SUN_GIZMO_POSITION),
+                        self.sun_rotation,
+                        Vector3::new(1.0, 1.0, 1.0),
+                    ))
+                    .with(arrow())
+                    .with(Ghost)
+                    .with(LightGizmo)
+                    .build();
+
+                self.sun = Some(sun);
+            }
+        }
+
+        // Point lights
+        // -----------------------------------------------------------------------------------------------------
+        // Gizmos of lights that are gone, or are no longer lights
+        let stale = self
+            .points
+            .iter()
+            .filter(|(light, _)| !entities.is_alive(**light) || !point_lights.contains(**light))
+            .map(|(light, gizmo)| (*light, *gizmo))
+            .collect::<Vec<_>>();
+        for (light, gizmo) in stale {
+            self.points.remove(&light);
+            // Might have been deleted along with the light already
+            let _ = entities.delete(gizmo);
+        }
+
+        for (light, _) in (&entities, &point_lights).join() {
+            if self.points.contains_key(&light) {
+                continue;
+            }
+
+            // As a child of the light, the gizmo follows it around
+            let gizmo = lazy
+                .create_entity(&entities)
+                .with(Link::new(light))
+                .with(Transform::from_parts(
+                    Vector3::new(0.0, 0.0, 0.0),
+                    UnitQuaternion::identity(),
+                    Vector3::new(POINT_GIZMO_SCALE, POINT_GIZMO_SCALE, POINT_GIZMO_SCALE),
+                ))
+                .with(MeshBuilder::new().with_shape(Shape::IcoSphere(1)))
+                .with(Ghost)
+                .with(LightGizmo)
+                .build();
+
+            self.points.insert(light, gizmo);
+        }
+    }
+}
+
+/// Shows the CameraPaths in the scene while the show_path_gizmos debug flag is on
+///
+/// Every keyframe gets a bead, with a trail of smaller beads along the spline between them. The
+/// beads of a path are made once, so replacing its keyframes only shows once the gizmos are
+/// toggled again.
+#[derive(Debug, Default)]
+pub struct PathGizmoSystem {
+    beads: HashMap<Entity, Vec<Entity>>,
+}
+
+impl<'a> System<'a> for PathGizmoSystem {
+    type SystemData = (
+        Entities<'a>,
+        Read<'a, LazyUpdate>,
+        Read<'a, RenderSettings>,
+        ReadStorage<'a, CameraPath>,
+    );
+
+    fn run(&mut self, (entities, lazy, settings, paths): Self::SystemData) {
+        if !settings.show_path_gizmos {
+            for (_, beads) in self.beads.drain() {
+                for bead in beads {
+                    let _ = entities.delete(bead);
+                }
+            }
+            return;
+        }
+
+        // Beads of paths that are gone
+        let stale = self
+            .beads
+            .keys()
+            .filter(|path| !entities.is_alive(**path) || !paths.contains(**path))
+            .cloned()
+            .collect::<Vec<_>>();
+        for path in stale {
+            for bead in self.beads.remove(&path).unwrap() {
+                let _ = entities.delete(bead);
+            }
+        }
+
+        for (entity, path) in (&entities, &paths).join() {
+            if self.beads.contains_key(&entity) {
+                continue;
+            }
+
+            let keyframes = path.keyframes();
+            let trail = keyframes.windows(2).flat_map(|pair| {
+                let (start, end) = (pair[0].time, pair[1].time);
+                (1..PATH_GIZMO_BEADS).map(move |i| {
+                    let time = start + (end - start) * i as f32 / PATH_GIZMO_BEADS as f32;
+                    (time, PATH_GIZMO_SCALE)
+                })
+            });
+            let beads = keyframes
+                .iter()
+                .map(|keyframe| (keyframe.time, KEYFRAME_GIZMO_SCALE))
+                .chain(trail)
+                .map(|(time, scale)| {
+                    lazy.create_entity(&entities)
+                        .with(Transform::from_parts(
+                            *path.sample(time).translation(),
+                            UnitQuaternion::identity(),
+                            Vector3::new(scale, scale, scale),
+                        ))
+                        .with(MeshBuilder::new().with_shape(Shape::IcoSphere(1)))
+                        .with(Ghost)
+                        .build()
+                })
+                .collect();
+
+            self.beads.insert(entity, beads);
+        }
+    }
+}
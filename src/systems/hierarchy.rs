@@ -0,0 +1,239 @@
+use crate::components::{GlobalTransform, Link, Transform};
+use specs::{prelude::*, world::WrongGeneration};
+use specs_hierarchy::{Hierarchy, HierarchyEvent, Parent};
+
+/// Deletes an entity along with all of its children, grandchildren and so on
+///
+/// The hierarchy has to be up to date, which it is as long as the HierarchySystem has run since
+/// the last change to any Link.
+pub fn despawn_recursive(world: &World, entity: Entity) -> Result<(), WrongGeneration> {
+    let entities = world.entities();
+    let hierarchy = world.read_resource::<Hierarchy<Link>>();
+
+    despawn_recursive_with(&entities, &hierarchy, entity)
+}
+
+/// Same as `despawn_recursive`, but for use from inside systems
+pub fn despawn_recursive_with(
+    entities: &Entities<'_>,
+    hierarchy: &Hierarchy<Link>,
+    entity: Entity,
+) -> Result<(), WrongGeneration> {
+    for (child, _) in (entities, &hierarchy.all_children(entity)).join() {
+        entities.delete(child)?;
+    }
+
+    entities.delete(entity)
+}
+
+/// What happens to children when their parent is deleted
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OrphanPolicy {
+    /// Delete the children too
+    Despawn,
+    /// Make the children roots of their own hierarchies, keeping them where they are in the world
+    Reparent,
+}
+
+impl Default for OrphanPolicy {
+    fn default() -> Self {
+        OrphanPolicy::Despawn
+    }
+}
+
+/// Cleans up children whose parent has disappeared, so no Link is left pointing at a dead entity
+///
+/// Has to run after the HierarchySystem and before the TransformSystem.
+#[derive(Default)]
+pub struct HierarchyCleanupSystem {
+    policy: OrphanPolicy,
+    hierarchy_reader_id: Option<ReaderId<HierarchyEvent>>,
+}
+
+impl HierarchyCleanupSystem {
+    pub fn new(policy: OrphanPolicy) -> Self {
+        Self {
+            policy,
+            hierarchy_reader_id: None,
+        }
+    }
+}
+
+impl<'a> System<'a> for HierarchyCleanupSystem {
+    type SystemData = (
+        Entities<'a>,
+        ReadExpect<'a, Hierarchy<Link>>,
+        ReadStorage<'a, GlobalTransform>,
+        WriteStorage<'a, Link>,
+        WriteStorage<'a, Transform>,
+    );
+
+    fn run(&mut self, (entities, hierarchy, globals, mut links, mut transforms): Self::SystemData) {
+        // The hierarchy removes every descendant of a deleted entity, not just the direct children
+        let removed = hierarchy
+            .changed()
+            .read(self.hierarchy_reader_id.as_mut().unwrap())
+            .filter_map(|event| match *event {
+                HierarchyEvent::Removed(entity) => Some(entity),
+                HierarchyEvent::Modified(_) => None,
+            })
+            .filter(|entity| entities.is_alive(*entity))
+            .collect::<Vec<_>>();
+
+        for entity in removed {
+            let link = match links.get(entity) {
+                Some(link) => *link,
+                // The Link was removed on purpose, so the entity is already a root
+                None => continue,
+            };
+
+            match self.policy {
+                OrphanPolicy::Despawn => {
+                    entities.delete(entity).unwrap();
+                }
+                OrphanPolicy::Reparent if !entities.is_alive(link.parent_entity()) => {
+                    // Bake the last known global transform into the local one
+                    if let (Some(global), Some(transform)) =
+                        (globals.get(entity), transforms.get_mut(entity))
+                    {
+                        *transform = global.global.clone();
+                    }
+
+                    links.remove(entity);
+                }
+                OrphanPolicy::Reparent => {
+                    // Grandchildren still have a living parent, but were dropped from the
+                    // hierarchy along with it. Inserting the Link again adds them back
+                    links.insert(entity, link).unwrap();
+                }
+            }
+        }
+    }
+
+    fn setup(&mut self, res: &mut Resources) {
+        Self::SystemData::setup(res);
+
+        let mut hierarchy = res.fetch_mut::<Hierarchy<Link>>();
+        self.hierarchy_reader_id = Some(hierarchy.track());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{despawn_recursive, HierarchyCleanupSystem, OrphanPolicy};
+    use crate::{
+        components::{GlobalTransform, Link, Transform},
+        systems::TransformSystem,
+    };
+    use nalgebra::Vector3;
+    use specs::prelude::*;
+    use specs_hierarchy::{HierarchySystem, Parent};
+
+    fn world<'a, 'b>(policy: OrphanPolicy) -> (World, Dispatcher<'a, 'b>) {
+        let mut world = World::new();
+
+        world.register::<Transform>();
+        world.register::<GlobalTransform>();
+        world.register::<Link>();
+
+        let mut dispatcher = DispatcherBuilder::new()
+            .with(HierarchySystem::<Link>::new(), "hs", &[])
+            .with(HierarchyCleanupSystem::new(policy), "hcs", &["hs"])
+            .with(TransformSystem::default(), "ts", &["hcs"])
+            .build();
+
+        dispatcher.setup(&mut world.res);
+
+        (world, dispatcher)
+    }
+
+    fn step(world: &mut World, dispatcher: &mut Dispatcher<'_, '_>) {
+        dispatcher.dispatch(&world.res);
+        world.maintain();
+    }
+
+    /// Creates a parent with a child, which has a child of its own
+    fn family(world: &mut World) -> (Entity, Entity, Entity) {
+        let tra = Transform::from(Vector3::new(1.0, 2.0, 3.0));
+
+        let parent = world.create_entity().with(tra.clone()).build();
+        let child = world
+            .create_entity()
+            .with(tra.clone())
+            .with(Link::new(parent))
+            .build();
+        let grandchild = world
+            .create_entity()
+            .with(tra.clone())
+            .with(Link::new(child))
+            .build();
+
+        (parent, child, grandchild)
+    }
+
+    #[test]
+    fn despawn_deletes_descendants() {
+        let (mut world, mut dispatcher) = world(OrphanPolicy::Despawn);
+        let (parent, child, grandchild) = family(&mut world);
+        let other = world.create_entity().with(Transform::default()).build();
+
+        step(&mut world, &mut dispatcher);
+
+        despawn_recursive(&world, parent).unwrap();
+        world.maintain();
+
+        assert!(!world.is_alive(parent));
+        assert!(!world.is_alive(child));
+        assert!(!world.is_alive(grandchild));
+        assert!(world.is_alive(other));
+    }
+
+    #[test]
+    fn orphans_are_despawned() {
+        let (mut world, mut dispatcher) = world(OrphanPolicy::Despawn);
+        let (parent, child, grandchild) = family(&mut world);
+
+        step(&mut world, &mut dispatcher);
+
+        world.delete_entity(parent).unwrap();
+        step(&mut world, &mut dispatcher);
+
+        assert!(!world.is_alive(child));
+        assert!(!world.is_alive(grandchild));
+    }
+
+    #[test]
+    fn orphans_are_reparented() {
+        let (mut world, mut dispatcher) = world(OrphanPolicy::Reparent);
+        let (parent, child, grandchild) = family(&mut world);
+
+        step(&mut world, &mut dispatcher);
+
+        let child_global = world
+            .read_storage::<GlobalTransform>()
+            .get(child)
+            .unwrap()
+            .to_matrix();
+
+        world.delete_entity(parent).unwrap();
+        step(&mut world, &mut dispatcher);
+        // Let the hierarchy pick up the removed and reinserted links
+        step(&mut world, &mut dispatcher);
+
+        assert!(world.is_alive(child));
+        assert!(world.is_alive(grandchild));
+
+        // The child is a root now, and has not moved
+        assert!(world.read_storage::<Link>().get(child).is_none());
+        let global = world
+            .read_storage::<GlobalTransform>()
+            .get(child)
+            .unwrap()
+            .to_matrix();
+        assert_eq!(global, child_global);
+
+        // The grandchild is still linked to the child
+        let link = *world.read_storage::<Link>().get(grandchild).unwrap();
+        assert_eq!(link.parent_entity(), child);
+    }
+}
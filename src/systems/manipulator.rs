@@ -0,0 +1,364 @@
+use crate::{
+    components::{GlobalTransform, Hidden, Transform, TransformStorageExt},
+    renderer::{
+        camera::{ActiveCamera, Camera, Viewport},
+        geometry::{Bounds, Ghost},
+    },
+    resources::{ActionEvent, ActionEvents, CursorState, MouseButton, MouseEvent, MouseEvents},
+    systems::{
+        gizmos::{arrow, arrow_rotation},
+        screen::screen_ray,
+        EngineState, SpatialIndex,
+    },
+};
+use nalgebra::{Matrix4, Point3, Vector2, Vector3};
+use ncollide3d::query::Ray;
+use shrev::ReaderId;
+use specs::prelude::*;
+use specs_derive::Component;
+
+/// Size of the translation gizmo, as a fraction of its distance to the camera, so it stays the
+/// same size on the screen
+const GIZMO_SCALE: f32 = 0.15;
+/// Length of an arrow of the gizmo, from the center of the gizmo to the tip of the head, before
+/// scaling
+const HANDLE_LENGTH: f32 = 1.7;
+/// How close the cursor has to be to an arrow to grab it, before scaling
+const HANDLE_RADIUS: f32 = 0.15;
+
+/// Marks the entity picked in the editor
+#[derive(Component, Debug, Default)]
+#[storage(NullStorage)]
+pub struct Selected;
+
+/// An arrow of the gizmo being dragged
+#[derive(Debug, Clone)]
+struct Drag {
+    axis: Vector3<f32>,
+    /// Where along the axis the arrow was grabbed
+    grabbed_at: f32,
+    /// The translation of the entity when it was grabbed
+    start: Vector3<f32>,
+}
+
+/// Picks entities with the cursor in the editor, and moves them with a translation gizmo
+///
+/// Clicking an entity selects it, and clicking nothing deselects it again. The selected entity
+/// gets an arrow along each axis, which moves it along that axis while dragged. The cursor has to
+/// be released with "toggle_grab" first. Moves go through the Transform, so the GlobalTransform
+/// and the uniforms of the mesh follow. "toggle_hidden" hides the selected entity, or shows it
+/// again.
+#[derive(Debug, Default)]
+pub struct ManipulatorSystem {
+    /// Arrows along x, y and z
+    handles: Vec<Entity>,
+    drag: Option<Drag>,
+    mouse_read_id: Option<ReaderId<MouseEvent>>,
+    action_read_id: Option<ReaderId<ActionEvent>>,
+}
+
+impl<'a> System<'a> for ManipulatorSystem {
+    type SystemData = (
+        Entities<'a>,
+        Read<'a, LazyUpdate>,
+        Read<'a, EngineState>,
+        Read<'a, CursorState>,
+        Read<'a, MouseEvents>,
+        Read<'a, ActionEvents>,
+        Read<'a, SpatialIndex>,
+        ReadStorage<'a, ActiveCamera>,
+        ReadStorage<'a, Camera>,
+        ReadStorage<'a, Viewport>,
+        ReadStorage<'a, GlobalTransform>,
+        ReadStorage<'a, Bounds>,
+        WriteStorage<'a, Selected>,
+        WriteStorage<'a, Hidden>,
+        WriteStorage<'a, Transform>,
+    );
+
+    fn run(
+        &mut self,
+        (
+            entities,
+            lazy,
+            state,
+            cursor,
+            mouse_events,
+            action_events,
+            index,
+            active_cameras,
+            cameras,
+            viewports,
+            globals,
+            bounds,
+            mut selected,
+            mut hidden,
+            mut transforms,
+        ): Self::SystemData,
+    ) {
+        let mut pressed = false;
+        let mut released = false;
+        for event in mouse_events.read(self.mouse_read_id.as_mut().unwrap()) {
+            if let MouseEvent::Button {
+                pressed: down,
+                button: MouseButton::Left,
+                ..
+            } = event
+            {
+                pressed |= *down;
+                released |= !*down;
+            }
+        }
+
+        let toggle_hidden = action_events
+            .read(self.action_read_id.as_mut().unwrap())
+            .any(|ActionEvent(action)| action == "toggle_hidden");
+
+        // Nothing stays selected outside of the editor
+        if *state != EngineState::Editor {
+            selected.clear();
+            self.drag = None;
+            self.remove_handles(&entities);
+            return;
+        }
+
+        let camera = (&cameras, &globals, &active_cameras, viewports.maybe())
+            .join()
+            .next()
+            .map(|(camera, global, _, viewport)| {
+                (
+                    Matrix4::from(camera.projection()) * global.to_view_matrix(),
+                    viewport.cloned().unwrap_or_default(),
+                    *global.translation(),
+                )
+            });
+
+        let (view_projection, viewport, camera_position) = match camera {
+            Some(camera) => camera,
+            None => return,
+        };
+
+        let ray = cursor
+            .position()
+            .and_then(|[x, y]| screen_ray(&view_projection, &viewport, &Vector2::new(x, y)));
+
+        let target = (&entities, &selected, &globals)
+            .join()
+            .next()
+            .map(|(entity, _, global)| (entity, *global.translation()));
+
+        if let (true, Some((entity, _))) = (toggle_hidden, target) {
+            if hidden.remove(entity).is_none() {
+                hidden.insert(entity, Hidden).unwrap();
+            }
+        }
+
+        let scale = target
+            .map(|(_, center)| (center - camera_position).norm() * GIZMO_SCALE)
+            .unwrap_or(1.0);
+
+        // Picking
+        // -----------------------------------------------------------------------------------------------------
+        if let (true, Some(ray)) = (pressed, ray) {
+            let grabbed = target.and_then(|(entity, center)| {
+                let (axis, grabbed_at) = grab_handle(&ray, &Point3::from(center), scale)?;
+                Some((entity, axis, grabbed_at))
+            });
+
+            match grabbed {
+                Some((entity, axis, grabbed_at)) => {
+                    self.drag = Some(Drag {
+                        axis,
+                        grabbed_at,
+                        start: *transforms.get(entity).unwrap().translation(),
+                    });
+                }
+                None => {
+                    selected.clear();
+                    if let Some(entity) = self.pick(&ray, &index, &bounds, &globals) {
+                        selected.insert(entity, Selected).unwrap();
+                    }
+                }
+            }
+        }
+
+        if released {
+            self.drag = None;
+        }
+
+        // Dragging
+        // -----------------------------------------------------------------------------------------------------
+        if let (Some(drag), Some(ray), Some((entity, center))) = (&self.drag, ray, target) {
+            if let Some((along, _)) = closest_on_axis(&ray, &Point3::from(center), &drag.axis) {
+                let translation = drag.start + drag.axis * (along - drag.grabbed_at);
+                transforms.set_translation(entity, translation);
+            }
+        }
+
+        // Gizmo
+        // -----------------------------------------------------------------------------------------------------
+        let center = match target {
+            Some((_, center)) => center,
+            None => {
+                self.drag = None;
+                self.remove_handles(&entities);
+                return;
+            }
+        };
+
+        if self.handles.is_empty() {
+            self.handles = axes()
+                .iter()
+                .map(|_| {
+                    lazy.create_entity(&entities)
+                        .with(Transform::default())
+                        .with(arrow())
+                        .with(Ghost)
+                        .build()
+                })
+                .collect();
+        }
+
+        for (handle, axis) in self.handles.iter().zip(axes().iter()) {
+            // The arrow is centered on its shaft, which is 1 long, so this puts its tail at the center
+            let transform = Transform::from_parts(
+                center + axis * (0.5 * scale),
+                arrow_rotation(axis),
+                Vector3::new(scale, scale, scale),
+            );
+
+            transforms.set(*handle, transform);
+        }
+    }
+
+    fn setup(&mut self, res: &mut Resources) {
+        Self::SystemData::setup(res);
+
+        let mut mouse = res.fetch_mut::<MouseEvents>();
+        self.mouse_read_id = Some(mouse.register_reader());
+
+        let mut actions = res.fetch_mut::<ActionEvents>();
+        self.action_read_id = Some(actions.register_reader());
+    }
+}
+
+impl ManipulatorSystem {
+    /// The closest entity the ray hits, apart from the gizmo
+    fn pick(
+        &self,
+        ray: &Ray<f32>,
+        index: &SpatialIndex,
+        bounds: &ReadStorage<'_, Bounds>,
+        globals: &ReadStorage<'_, GlobalTransform>,
+    ) -> Option<Entity> {
+        index
+            .ray(ray)
+            .into_iter()
+            .filter(|entity| !self.handles.contains(entity))
+            .filter_map(|entity| {
+                let hit = bounds.get(entity)?.cast_ray(globals.get(entity)?, ray)?;
+                Some((entity, hit.toi))
+            })
+            .filter(|(_, toi)| *toi > 0.0)
+            .min_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap())
+            .map(|(entity, _)| entity)
+    }
+
+    fn remove_handles(&mut self, entities: &Entities<'_>) {
+        for handle in self.handles.drain(..) {
+            let _ = entities.delete(handle);
+        }
+    }
+}
+
+fn axes() -> [Vector3<f32>; 3] {
+    [Vector3::x(), Vector3::y(), Vector3::z()]
+}
+
+/// The arrow of the gizmo the ray passes closest to, if it is close enough to grab, and where
+/// along its axis it was grabbed
+fn grab_handle(ray: &Ray<f32>, center: &Point3<f32>, scale: f32) -> Option<(Vector3<f32>, f32)> {
+    axes()
+        .iter()
+        .filter_map(|axis| {
+            let (along, distance) = closest_on_axis(ray, center, axis)?;
+            let on_handle = along >= 0.0 && along <= HANDLE_LENGTH * scale;
+
+            if on_handle && distance <= HANDLE_RADIUS * scale {
+                Some((*axis, along, distance))
+            } else {
+                None
+            }
+        })
+        .min_by(|(_, _, a), (_, _, b)| a.partial_cmp(b).unwrap())
+        .map(|(axis, along, _)| (axis, along))
+}
+
+/// The point on the line through `origin` along `axis` closest to the ray, as a distance along
+/// the axis, and how far the ray passes from it
+///
+/// None if the ray is parallel to the axis, or the closest point is behind the ray.
+fn closest_on_axis(
+    ray: &Ray<f32>,
+    origin: &Point3<f32>,
+    axis: &Vector3<f32>,
+) -> Option<(f32, f32)> {
+    let w = ray.origin - origin;
+    let a = ray.dir.dot(&ray.dir);
+    let b = ray.dir.dot(axis);
+    let c = axis.dot(axis);
+    let d = ray.dir.dot(&w);
+    let e = axis.dot(&w);
+
+    let denominator = a * c - b * b;
+    if denominator.abs() < 1e-6 {
+        return None;
+    }
+
+    let along_ray = (b * e - c * d) / denominator;
+    let along_axis = (a * e - b * d) / denominator;
+    if along_ray < 0.0 {
+        return None;
+    }
+
+    let distance = (ray.point_at(along_ray) - (origin + axis * along_axis)).norm();
+    Some((along_axis, distance))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{closest_on_axis, grab_handle};
+    use nalgebra::{Point3, Vector3};
+    use ncollide3d::query::Ray;
+
+    #[test]
+    fn closest_point() {
+        // Looking down at the x axis from above, 2 units along it
+        let ray = Ray::new(Point3::new(2.0, 5.0, 0.5), -Vector3::y());
+        let (along, distance) = closest_on_axis(&ray, &Point3::origin(), &Vector3::x()).unwrap();
+        assert!((along - 2.0).abs() < 1e-4);
+        assert!((distance - 0.5).abs() < 1e-4);
+
+        // Parallel, and behind
+        assert!(closest_on_axis(&ray, &Point3::origin(), &Vector3::y()).is_none());
+        let away = Ray::new(Point3::new(2.0, 5.0, 0.5), Vector3::y());
+        assert!(closest_on_axis(&away, &Point3::origin(), &Vector3::x()).is_none());
+    }
+
+    #[test]
+    fn grab() {
+        let center = Point3::new(0.0, 0.0, -5.0);
+
+        // Right over the middle of the x arrow
+        let ray = Ray::new(Point3::new(0.5, 5.0, -5.0), -Vector3::y());
+        let (axis, along) = grab_handle(&ray, &center, 1.0).unwrap();
+        assert_eq!(axis, Vector3::x());
+        assert!((along - 0.5).abs() < 1e-4);
+
+        // Past the tip, or beside the arrow
+        let past = Ray::new(Point3::new(2.5, 5.0, -5.0), -Vector3::y());
+        assert!(grab_handle(&past, &center, 1.0).is_none());
+        let beside = Ray::new(Point3::new(0.5, 5.0, -4.0), -Vector3::y());
+        assert!(grab_handle(&beside, &center, 1.0).is_none());
+    }
+}
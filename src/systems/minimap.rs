@@ -0,0 +1,68 @@
+use crate::{
+    components::{Transform, TransformStorageExt},
+    renderer::{
+        camera::{ActiveCamera, Camera},
+        minimap::Minimap,
+    },
+};
+use nalgebra::{UnitQuaternion, Vector3};
+use specs::prelude::*;
+use std::f32::consts::FRAC_PI_2;
+
+/// Points minimap cameras straight down, zooms them, and keeps those following over the active
+/// camera
+///
+/// The top of the minimap faces -z. The active camera is followed in the space of its parent, so
+/// it should have none, like the minimap camera itself.
+#[derive(Debug, Default)]
+pub struct MinimapSystem;
+
+impl<'a> System<'a> for MinimapSystem {
+    type SystemData = (
+        Entities<'a>,
+        ReadStorage<'a, ActiveCamera>,
+        ReadStorage<'a, Minimap>,
+        WriteStorage<'a, Camera>,
+        WriteStorage<'a, Transform>,
+    );
+
+    fn run(
+        &mut self,
+        (entities, active_cameras, minimaps, mut cameras, mut transforms): Self::SystemData,
+    ) {
+        let followed = (&active_cameras, &transforms)
+            .join()
+            .next()
+            .map(|(_, transform)| *transform.translation());
+
+        for (entity, minimap, camera) in (&entities, &minimaps, &mut cameras).join() {
+            camera.set_half_height(minimap.zoom);
+
+            transforms.modify(entity, |transform| {
+                transform.set_rotation(looking_down());
+
+                if let (true, Some(followed)) = (minimap.follow, followed) {
+                    transform.set_translation(followed + Vector3::new(0.0, minimap.height, 0.0));
+                }
+            });
+        }
+    }
+}
+
+/// Turns the -z the camera looks along to -y, and its up to -z
+fn looking_down() -> UnitQuaternion<f32> {
+    UnitQuaternion::from_axis_angle(&Vector3::x_axis(), -FRAC_PI_2)
+}
+
+#[cfg(test)]
+mod test {
+    use super::looking_down;
+    use nalgebra::Vector3;
+
+    #[test]
+    fn top_down() {
+        let rotation = looking_down();
+        assert!((rotation * -Vector3::z() - -Vector3::y()).norm() < 1e-5);
+        assert!((rotation * Vector3::y() - -Vector3::z()).norm() < 1e-5);
+    }
+}
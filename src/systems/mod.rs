@@ -1,29 +1,95 @@
+mod assets;
+mod audio;
+mod benchmark;
+mod bindings;
+mod camera_effects;
+mod camera_path;
+mod character;
+mod determinism;
+mod exposure;
+mod follow;
+mod gizmos;
+mod hierarchy;
+mod manipulator;
+mod minimap;
+mod placer;
+mod reload;
+mod screen;
+mod spatial;
+mod stages;
+mod state;
+mod stats;
+mod streaming;
 mod transform;
-
-pub use crate::systems::transform::TransformSystem;
+mod ui;
+mod visibility;
+
+pub use crate::systems::{
+    assets::{AssetLoaderSystem, AssetStats, LoadMesh},
+    audio::AudioSystem,
+    benchmark::{BenchmarkConfig, BenchmarkSystem},
+    bindings::InputBindings,
+    camera_effects::CameraEffectsSystem,
+    camera_path::{CameraPath, CameraPathSystem, Keyframe},
+    character::{CharacterControlSystem, CharacterController},
+    determinism::DeterminismConfig,
+    exposure::AutoExposureSystem,
+    follow::{FollowSystem, FollowTarget},
+    gizmos::{LightGizmo, LightGizmoSystem, PathGizmoSystem},
+    hierarchy::{despawn_recursive, despawn_recursive_with, HierarchyCleanupSystem, OrphanPolicy},
+    manipulator::{ManipulatorSystem, Selected},
+    minimap::MinimapSystem,
+    placer::{EditHistory, Placed, PlacerSystem},
+    reload::{MeshReloadSystem, MeshSource},
+    screen::{ScreenLabel, ScreenPosition, ScreenProjectionSystem},
+    spatial::{SpatialIndex, SpatialIndexSystem},
+    stages::{EnabledStages, FixedStep, Stage, StagedDispatcher, StagedDispatcherBuilder},
+    state::{EngineState, EngineStateSystem, InStates},
+    stats::FrameStatsSystem,
+    streaming::{ChunkStreamingSystem, StreamingSettings},
+    transform::TransformSystem,
+    ui::{crosshair, Anchor, ScaleMode, UiAnchor, UiLayoutSystem, UiSprite, UiText},
+    visibility::VisibilitySystem,
+};
 
 use crate::{
-    components::{Transform, GlobalTransform},
-    renderer::{camera::ActiveCamera, lights::PointLightComponent, RenderEvent, RenderEvents},
+    components::{damp, GlobalTransform, PlayerId, Transform, TransformStorageExt},
+    logging::LogLevels,
+    renderer::{
+        camera::{ActiveCamera, Camera, DEFAULT_FAR},
+        geometry::{Bounds, Ghost},
+        grading::ColorGrading,
+        settings::RenderSettings,
+        MeshReadyEvent, MeshReadyEvents, RenderEvent, RenderEvents,
+    },
     resources::{
-        ControllerAxis, ControllerEvent, ControllerEvents, FocusGained, KeyboardEvent,
-        KeyboardEvents, Keycode, MouseEvent, MouseEvents, ShouldClose, Time,
+        ActionEvent, ActionEvents, Clipboard, Composition, ControllerAxis, ControllerEvent,
+        ControllerEvents, CursorState, FileDropEvent, FileDropEvents, FocusGained, KeyboardEvent,
+        KeyboardEvents, KeyboardState, Keycode, MouseEvent, MouseEvents, ShouldClose, SoundEffect,
+        SoundEffects, TextInput, TextInputEvent, TextInputEvents, Time, WindowMode, WindowTitle,
+        WindowVisible,
     },
+    scene::Scenes,
+    systems::bindings::KeySequenceDetector,
 };
 use float_duration::TimePoint;
-use log::info;
-use nalgebra::{UnitQuaternion, Vector3};
+use log::{info, warn};
+use nalgebra::{Matrix4, Point3, UnitQuaternion, Vector3};
+use ncollide3d::bounding_volume::BoundingSphere;
 use sdl2::{
     controller::GameController,
     event::{Event, WindowEvent},
-    video::Window as SdlWindow,
+    mouse::MouseUtil,
+    video::{FullscreenType, Window as SdlWindow},
     EventPump, GameControllerSubsystem, Sdl, VideoSubsystem,
 };
 use shrev::ReaderId;
 use specs::prelude::*;
 use std::{
+    collections::HashMap,
     mem,
     ops::{AddAssign, SubAssign},
+    path::PathBuf,
     time::Instant,
 };
 
@@ -31,6 +97,20 @@ use std::{
 pub struct TimeSystem {
     first_frame: Instant,
     last_frame: Instant,
+    /// Seconds every frame is stepped by, instead of the time that has passed
+    fixed_delta: Option<f32>,
+    /// Fixed steps taken so far
+    elapsed: f32,
+}
+
+impl TimeSystem {
+    /// Steps every frame by the same delta, so runs are repeatable however fast frames are drawn
+    pub fn fixed(delta: f32) -> Self {
+        Self {
+            fixed_delta: Some(delta),
+            ..Self::default()
+        }
+    }
 }
 
 impl Default for TimeSystem {
@@ -38,6 +118,8 @@ impl Default for TimeSystem {
         TimeSystem {
             first_frame: Instant::now(),
             last_frame: Instant::now(),
+            fixed_delta: None,
+            elapsed: 0.0,
         }
     }
 }
@@ -46,6 +128,12 @@ impl<'a> System<'a> for TimeSystem {
     type SystemData = Write<'a, Time>;
 
     fn run(&mut self, mut time: Self::SystemData) {
+        if let Some(delta) = self.fixed_delta {
+            self.elapsed += delta;
+            *time = Time::new(self.elapsed, delta, time.timescale());
+            return;
+        }
+
         let now = Instant::now();
 
         let delta = now
@@ -66,9 +154,12 @@ impl<'a> System<'a> for TimeSystem {
 #[derive(Debug, Default)]
 pub struct Axis {
     value: f32,
+    /// Where the value is ramping to, see `AxisSmoothing`
+    target: f32,
 }
 
 impl Axis {
+    /// Sets the value right away, for analog input that is smooth already
     pub fn set(&mut self, value: f32) {
         let value = if value > 1. {
             1.
@@ -79,6 +170,27 @@ impl Axis {
         };
 
         self.value = value;
+        self.target = value;
+    }
+
+    /// Ramps the value to the target over the next steps, for digital input like keys
+    pub fn set_target(&mut self, target: f32) {
+        self.target = target.max(-1.).min(1.);
+    }
+
+    /// Moves the value towards the target by `delta` seconds of acceleration or deceleration
+    pub fn step(&mut self, smoothing: &AxisSmoothing, delta: f32) {
+        let difference = self.target - self.value;
+
+        // Speeding up in the same direction accelerates, slowing down or turning around decelerates
+        let speeding_up = self.target.abs() > self.value.abs() && self.target * self.value >= 0.;
+        let rate = if speeding_up {
+            smoothing.acceleration
+        } else {
+            smoothing.deceleration
+        };
+
+        self.value += difference.signum() * difference.abs().min(rate * delta);
     }
 
     pub fn get(&self) -> f32 {
@@ -110,6 +222,24 @@ impl SubAssign<f32> for Axis {
     }
 }
 
+/// Resource with how fast axes driven by keys ramp up and down, in units per second
+///
+/// An axis goes from 0 to 1 in `1 / acceleration` seconds. Infinity snaps straight to the target.
+#[derive(Debug, Clone)]
+pub struct AxisSmoothing {
+    pub acceleration: f32,
+    pub deceleration: f32,
+}
+
+impl Default for AxisSmoothing {
+    fn default() -> Self {
+        Self {
+            acceleration: 6.0,
+            deceleration: 10.0,
+        }
+    }
+}
+
 //TODO Decide if this or events is the best option for input
 #[derive(Debug, Default)]
 pub struct GameInput {
@@ -119,92 +249,226 @@ pub struct GameInput {
     controller_view_ver: Axis,
     mouse_view_hor: f32,
     mouse_view_ver: f32,
+    /// Mouse motion of this frame after sensitivity, acceleration and smoothing
+    mouse_look: (f32, f32),
     action_pressed: bool,
+    remove_pressed: bool,
+    sprint: bool,
+    /// Set by the "jump" action, until a character jumps
+    jump: bool,
 }
 
 impl GameInput {
     pub fn view(&self) -> (f32, f32) {
         (
-            self.controller_view_hor.get() + self.mouse_view_hor,
-            self.controller_view_ver.get() + self.mouse_view_ver,
+            self.controller_view_hor.get() + self.mouse_look.0,
+            self.controller_view_ver.get() + self.mouse_look.1,
         )
     }
+
+    /// Ramps the movement axes to where the keys have set them
+    fn step_axes(&mut self, smoothing: &AxisSmoothing, delta: f32) {
+        self.forward.step(smoothing, delta);
+        self.right.step(smoothing, delta);
+    }
+
+    /// Updates the mouse look from this frame's mouse motion, or no motion unless `enabled`
+    fn filter_mouse(&mut self, settings: &MouseSettings, enabled: bool, delta: f32) {
+        let motion = if enabled {
+            (self.mouse_view_hor, self.mouse_view_ver)
+        } else {
+            (0., 0.)
+        };
+        self.mouse_look = settings.filter(motion, self.mouse_look, delta);
+    }
+}
+
+/// Resource holding the GameInput of every local player
+///
+/// Controllers are given to players in the order they connect, starting with player 0, who also
+/// gets the keyboard and mouse.
+#[derive(Debug, Default)]
+pub struct GameInputs {
+    players: HashMap<PlayerId, GameInput>,
+    // Player of each connected controller, by controller instance id
+    controllers: HashMap<i32, PlayerId>,
+}
+
+impl GameInputs {
+    pub fn get(&self, player: PlayerId) -> Option<&GameInput> {
+        self.players.get(&player)
+    }
+
+    pub fn get_mut(&mut self, player: PlayerId) -> &mut GameInput {
+        self.players.entry(player).or_default()
+    }
+
+    /// The input of the player owning a controller
+    pub fn controller_mut(&mut self, id: i32) -> Option<&mut GameInput> {
+        let player = *self.controllers.get(&id)?;
+        Some(self.get_mut(player))
+    }
+
+    fn players_mut(&mut self) -> impl Iterator<Item = &mut GameInput> {
+        self.players.values_mut()
+    }
+
+    /// Gives a newly connected controller to the first player without one
+    fn connect(&mut self, id: i32) -> PlayerId {
+        let player = (0..)
+            .map(PlayerId)
+            .find(|player| !self.controllers.values().any(|p| p == player))
+            .unwrap();
+
+        self.controllers.insert(id, player);
+        player
+    }
+
+    fn disconnect(&mut self, id: i32) {
+        if let Some(player) = self.controllers.remove(&id) {
+            // Don't leave the player moving with whatever the sticks were at
+            self.players.insert(player, GameInput::default());
+        }
+    }
 }
 
 /// Turns keyboard events into game data
+///
+/// Chords and double taps are read from the events, keys that are held from the KeyboardState.
 #[derive(Debug, Default)]
 pub struct GameInputSystem {
     keyboard_read_id: Option<ReaderId<KeyboardEvent>>,
     mouse_read_id: Option<ReaderId<MouseEvent>>,
     controller_read_id: Option<ReaderId<ControllerEvent>>,
+    key_sequences: KeySequenceDetector,
 }
 
 impl<'a> System<'a> for GameInputSystem {
     type SystemData = (
-        Write<'a, GameInput>,
+        Read<'a, Time>,
+        Read<'a, InputBindings>,
+        Read<'a, AxisSmoothing>,
+        Write<'a, GameInputs>,
+        Write<'a, CursorState>,
+        Write<'a, WindowMode>,
         Write<'a, ShouldClose>,
+        Write<'a, ActionEvents>,
         Read<'a, KeyboardEvents>,
+        Read<'a, KeyboardState>,
         Read<'a, MouseEvents>,
         Read<'a, ControllerEvents>,
     );
 
     fn run(
         &mut self,
-        (mut input, mut should_close, keyboard_events, mouse_events, controller_events): Self::SystemData,
+        (
+            time,
+            bindings,
+            smoothing,
+            mut inputs,
+            mut cursor,
+            mut window_mode,
+            mut should_close,
+            mut action_events,
+            keyboard_events,
+            keyboard,
+            mouse_events,
+            controller_events,
+        ): Self::SystemData,
     ) {
         // Handle controller event
         // -----------------------------------------------------------------------------------------------------
         controller_events
             .read(self.controller_read_id.as_mut().unwrap())
             .for_each(|event| match event {
-                ControllerEvent::AxisMotion { axis, value, .. } => match axis {
-                    ControllerAxis::LeftX => input.right.set(*value),
-                    ControllerAxis::LeftY => input.forward.set(-value),
-                    ControllerAxis::RightX => input.controller_view_hor.set(*value),
-                    ControllerAxis::RightY => input.controller_view_ver.set(*value),
-                    _ => (),
-                },
+                ControllerEvent::Connected(id) => {
+                    let player = inputs.connect(*id);
+                    info!("Controller {} belongs to player {}", id, player.0);
+                }
+                ControllerEvent::Disconnected(id) => inputs.disconnect(*id),
+                ControllerEvent::AxisMotion { id, axis, value } => {
+                    let input = match inputs.controller_mut(*id) {
+                        Some(input) => input,
+                        None => return,
+                    };
+
+                    match axis {
+                        ControllerAxis::LeftX => input.right.set(*value),
+                        ControllerAxis::LeftY => input.forward.set(-value),
+                        ControllerAxis::RightX => input.controller_view_hor.set(*value),
+                        ControllerAxis::RightY => input.controller_view_ver.set(*value),
+                        _ => (),
+                    }
+                }
                 _ => (),
             });
 
+        // The keyboard and mouse control the first player
+        let input = inputs.get_mut(PlayerId::default());
+
         // Handle keyboard events
         // -----------------------------------------------------------------------------------------------------
-        keyboard_events
-            .read(self.keyboard_read_id.as_mut().unwrap())
-            .for_each(|event| match event {
-                // Quit the game with q
-                KeyboardEvent {
-                    pressed: true,
-                    keycode: Keycode::Q,
-                    ..
-                } => {
-                    should_close.0 = true;
-                }
-                KeyboardEvent {
-                    pressed: true,
-                    keycode,
-                    ..
-                } => match keycode {
-                    Keycode::W => input.forward.set(1.),
-                    Keycode::S => input.forward.set(-1.),
-                    Keycode::D => input.right.set(1.),
-                    Keycode::A => input.right.set(-1.),
-                    Keycode::E => input.action_pressed = true,
-                    _ => (),
-                },
-                KeyboardEvent {
-                    pressed: false,
-                    keycode,
-                    ..
-                } => match keycode {
-                    Keycode::W => input.forward.set(0.),
-                    Keycode::S => input.forward.set(0.),
-                    Keycode::D => input.right.set(0.),
-                    Keycode::A => input.right.set(0.),
-                    Keycode::E => input.action_pressed = false,
-                    _ => (),
-                },
-            });
+        let key_sequences = &mut self.key_sequences;
+        let mut actions = Vec::new();
+
+        // Chords and double taps
+        for event in keyboard_events.read(self.keyboard_read_id.as_mut().unwrap()) {
+            actions.extend(key_sequences.handle(&bindings, event, time.first_frame));
+        }
+
+        // Ctrl+key is a chord, not the key on its own, like Ctrl+E toggling the editor instead of
+        // placing an object
+        let ctrl = keyboard.is_down(Keycode::LCtrl) || keyboard.is_down(Keycode::RCtrl);
+        let held = |keycode| !ctrl && keyboard.is_down(keycode);
+        // Only set when the keys change, so a controller of the same player is not overridden
+        let changed = |keycodes: &[Keycode]| {
+            keycodes
+                .iter()
+                .chain(&[Keycode::LCtrl, Keycode::RCtrl])
+                .any(|keycode| keyboard.just_pressed(*keycode) || keyboard.just_released(*keycode))
+        };
+
+        // Polled, so releasing one of two opposite keys moves towards the one still held
+        if changed(&[Keycode::W, Keycode::S]) {
+            input
+                .forward
+                .set_target(key_axis(held(Keycode::W), held(Keycode::S)));
+        }
+        if changed(&[Keycode::D, Keycode::A]) {
+            input
+                .right
+                .set_target(key_axis(held(Keycode::D), held(Keycode::A)));
+        }
+        if changed(&[Keycode::E]) {
+            input.action_pressed = held(Keycode::E);
+        }
+        if changed(&[Keycode::X]) {
+            input.remove_pressed = held(Keycode::X);
+        }
+
+        // Sprinting lasts while the key that was double tapped is held
+        input.sprint &= keyboard
+            .pressed()
+            .any(|keycode| bindings.is_double_tap("sprint", *keycode));
+
+        // Quit the game with q
+        if !ctrl && keyboard.just_pressed(Keycode::Q) {
+            should_close.0 = true;
+        }
+
+        // Handle action events
+        // -----------------------------------------------------------------------------------------------------
+        for action in actions {
+            match action.0.as_str() {
+                "sprint" => input.sprint = true,
+                "jump" => input.jump = true,
+                "toggle_grab" => cursor.toggle_grab(),
+                "toggle_fullscreen" => window_mode.toggle_fullscreen(),
+                _ => (),
+            }
+
+            action_events.single_write(action);
+        }
 
         // Handle mouse events
         // -----------------------------------------------------------------------------------------------------
@@ -220,6 +484,12 @@ impl<'a> System<'a> for GameInputSystem {
                 }
                 _ => (),
             });
+
+        // Ramp the axes
+        // -----------------------------------------------------------------------------------------------------
+        for input in inputs.players_mut() {
+            input.step_axes(&smoothing, time.delta());
+        }
     }
 
     fn setup(&mut self, res: &mut Resources) {
@@ -242,66 +512,465 @@ impl<'a> System<'a> for GameInputSystem {
     }
 }
 
+/// 1 with only the positive key held, -1 with only the negative one, and 0 with both or neither
+fn key_axis(positive: bool, negative: bool) -> f32 {
+    match (positive, negative) {
+        (true, false) => 1.,
+        (false, true) => -1.,
+        _ => 0.,
+    }
+}
+
+/// How far cameras stay from the meshes they collide with, beyond their radius
+const CAMERA_SKIN: f32 = 0.001;
+
+/// Resource with the settings of the FlyControlSystem
+#[derive(Debug, Clone)]
+pub struct FlySettings {
+    /// Stop cameras before they fly into the bounds of a mesh
+    pub collision: bool,
+    /// Radius of the sphere colliding with meshes, which keeps the near plane out of them
+    pub collision_radius: f32,
+}
+
+impl Default for FlySettings {
+    fn default() -> Self {
+        Self {
+            collision: false,
+            collision_radius: 0.25,
+        }
+    }
+}
+
+/// Resource with how mouse motion turns cameras, read by the FlyControlSystem
+#[derive(Debug, Clone)]
+pub struct MouseSettings {
+    /// Multiplies all mouse motion
+    pub sensitivity: f32,
+    /// Seconds the smoothed motion takes to catch up about two thirds of the way with the mouse,
+    /// 0 for no smoothing
+    ///
+    /// Evens out jittery mice, at the cost of some lag, the same at any frame rate.
+    pub smoothing: f32,
+    /// Extra sensitivity for every pixel the mouse moves in a frame, so fast flicks turn further
+    pub acceleration: f32,
+    /// Read raw motion from the mouse in relative mode
+    ///
+    /// Otherwise SDL warps the cursor back to the center of the window and reads how far it
+    /// moved, which goes through the acceleration of the OS and works over remote desktops.
+    pub raw_input: bool,
+}
+
+impl MouseSettings {
+    /// Turns the mouse motion of a frame into view motion, given the result of the last frame
+    /// and the seconds since it
+    pub fn filter(&self, motion: (f32, f32), last: (f32, f32), delta: f32) -> (f32, f32) {
+        let speed = (motion.0 * motion.0 + motion.1 * motion.1).sqrt();
+        let scale = self.sensitivity * (1.0 + self.acceleration.max(0.0) * speed);
+
+        // No smoothing is an infinite rate, which snaps to the motion
+        let rate = 1.0 / self.smoothing.max(0.0);
+
+        (
+            damp(last.0, motion.0 * scale, rate, delta),
+            damp(last.1, motion.1 * scale, rate, delta),
+        )
+    }
+}
+
+impl Default for MouseSettings {
+    fn default() -> Self {
+        Self {
+            sensitivity: 1.0,
+            smoothing: 0.0,
+            acceleration: 0.0,
+            raw_input: true,
+        }
+    }
+}
+
 /// Fly control system
+///
+/// Every active camera is flown by the player it belongs to, or by player 0 if it has no PlayerId,
+/// unless it is a walking character or following a FollowTarget.
+/// Mouse motion is filtered by the MouseSettings here, for walking characters as well.
+/// With collision on, cameras stop where a sphere around them would touch the bounds of a mesh.
+/// Ghosts are not solid.
+#[derive(Debug, Default)]
 pub struct FlyControlSystem;
 
+impl FlyControlSystem {
+    fn fly(camera_t: &mut Transform, input: &GameInput, delta: f32) {
+        // Rotation
+        // ------------------------------------------------------------------------------------------------------------
+        Self::look(camera_t, input);
+
+        // Translation
+        // ------------------------------------------------------------------------------------------------------------
+        let speed = if input.sprint { 3.0 } else { 1.0 };
+
+        camera_t.translate_forward(input.forward.get() * speed * delta);
+        camera_t.translate_right(input.right.get() * speed * delta);
+    }
+
+    /// Turns the camera around the world's up axis and pitches it, as walking characters look too
+    fn look(camera_t: &mut Transform, input: &GameInput) {
+        let (yaw, pitch) = input.view();
+        let (yaw, pitch) = (yaw * -0.001, pitch * -0.001);
+
+        camera_t.rotate_local(UnitQuaternion::from_scaled_axis(Vector3::x() * pitch));
+        camera_t.rotate_global(UnitQuaternion::from_scaled_axis(Vector3::y() * yaw));
+    }
+
+    /// The fraction of a camera's motion it makes before touching the bounds of a mesh
+    fn allowed_motion<'a>(
+        position: &Point3<f32>,
+        motion: &Vector3<f32>,
+        radius: f32,
+        obstacles: impl Iterator<Item = (&'a Bounds, &'a GlobalTransform)>,
+    ) -> f32 {
+        let length = motion.norm();
+        if length <= std::f32::EPSILON {
+            return 1.0;
+        }
+
+        let toi = obstacles
+            .filter_map(|(bounds, global)| bounds.cast_sphere(global, position, radius, motion))
+            .min_by(|a, b| a.partial_cmp(b).unwrap());
+
+        match toi {
+            // Stops just short, so the next motion does not start inside the bounds
+            Some(toi) => (toi - CAMERA_SKIN / length).max(0.0),
+            None => 1.0,
+        }
+    }
+}
+
 impl<'a> System<'a> for FlyControlSystem {
     type SystemData = (
         Read<'a, Time>,
         Read<'a, FocusGained>,
-        Read<'a, GameInput>,
+        Write<'a, GameInputs>,
+        Read<'a, FlySettings>,
+        Read<'a, MouseSettings>,
+        Read<'a, CursorState>,
+        Read<'a, SpatialIndex>,
+        Entities<'a>,
         ReadStorage<'a, ActiveCamera>,
+        ReadStorage<'a, PlayerId>,
+        ReadStorage<'a, Bounds>,
+        ReadStorage<'a, Ghost>,
+        ReadStorage<'a, GlobalTransform>,
+        ReadStorage<'a, CharacterController>,
+        ReadStorage<'a, FollowTarget>,
         WriteStorage<'a, Transform>,
     );
 
     fn run(
         &mut self,
-        (time, input_enabled, input, active_camera, mut transform): Self::SystemData,
+        (
+            time,
+            input_enabled,
+            mut inputs,
+            settings,
+            mouse,
+            cursor,
+            index,
+            entities,
+            active_camera,
+            players,
+            bounds,
+            ghosts,
+            globals,
+            characters,
+            follows,
+            mut transforms,
+        ): Self::SystemData,
     ) {
+        // Smoothing carries on while unfocused, so it settles instead of picking up where it was.
+        // The free cursor doesn't turn the view, nor does the jump when it is grabbed again.
+        for input in inputs.players_mut() {
+            input.filter_mouse(&mouse, cursor.look_enabled(), time.real_delta());
+        }
+
         // Only handle input if the window is focused
         if !input_enabled.0 {
             return;
         }
 
-        // Get the camera transform
-        let (_, camera_t) = (&active_camera, &mut transform).join().next().unwrap();
+        // Characters are walked by the CharacterControlSystem instead, and following cameras are
+        // moved by the FollowSystem
+        for (camera, _, player, _, follow) in (
+            &entities,
+            &active_camera,
+            players.maybe(),
+            !&characters,
+            follows.maybe(),
+        )
+            .join()
+        {
+            if follow.map_or(false, |follow| follow.following) {
+                continue;
+            }
 
-        // Rotation
-        // ------------------------------------------------------------------------------------------------------------
-        let (yaw, pitch) = input.view();
-        let (yaw, pitch) = (yaw * -0.001, pitch * -0.001);
+            let input = match inputs.get(player.cloned().unwrap_or_default()) {
+                Some(input) => input,
+                None => continue,
+            };
+
+            // Only flags the camera as modified if it actually moved
+            transforms.modify(camera, |camera_t| {
+                let before = camera_t.clone();
+                Self::fly(camera_t, input, time.delta());
+
+                let global = match globals.get(camera) {
+                    Some(global) if settings.collision => global,
+                    _ => return,
+                };
+
+                // The transform is relative to the parent of the camera, if it has one
+                let local_motion = camera_t.translation() - before.translation();
+                let parent = global.to_matrix()
+                    * before
+                        .to_matrix()
+                        .try_inverse()
+                        .unwrap_or_else(Matrix4::identity);
+                let motion = parent.transform_vector(&local_motion);
+                let position = Point3::from(*global.translation());
+
+                // Only meshes near the path of the camera can be in the way
+                let path = BoundingSphere::new(
+                    position + motion * 0.5,
+                    motion.norm() * 0.5 + settings.collision_radius,
+                );
+                let obstacles = index
+                    .sphere(&path)
+                    .into_iter()
+                    .filter(|entity| *entity != camera && !ghosts.contains(*entity))
+                    .filter_map(|entity| Some((bounds.get(entity)?, globals.get(entity)?)));
+
+                let fraction =
+                    Self::allowed_motion(&position, &motion, settings.collision_radius, obstacles);
+                camera_t.set_translation(before.translation() + local_motion * fraction);
+            });
+        }
+    }
+}
 
-        camera_t.rotate_local(UnitQuaternion::from_scaled_axis(Vector3::x() * pitch));
-        camera_t.rotate_global(UnitQuaternion::from_scaled_axis(Vector3::y() * yaw));
+/// Toggles debug rendering modes, and runs log and scene commands, from action events
+#[derive(Debug, Default)]
+pub struct DebugToggleSystem {
+    action_read_id: Option<ReaderId<ActionEvent>>,
+}
 
-        // Translation
-        // ------------------------------------------------------------------------------------------------------------
-        camera_t.translate_forward(input.forward.get() * time.delta() as f32);
-        camera_t.translate_right(input.right.get() * time.delta() as f32);
+impl<'a> System<'a> for DebugToggleSystem {
+    type SystemData = (
+        Read<'a, ActionEvents>,
+        ReadStorage<'a, ActiveCamera>,
+        WriteStorage<'a, Camera>,
+        Write<'a, RenderSettings>,
+        Write<'a, ColorGrading>,
+        Write<'a, Scenes>,
+        ReadExpect<'a, LogLevels>,
+    );
+
+    fn run(
+        &mut self,
+        (
+            action_events,
+            active_cameras,
+            mut cameras,
+            mut settings,
+            mut grading,
+            mut scenes,
+            log_levels,
+        ): Self::SystemData,
+    ) {
+        for ActionEvent(action) in action_events.read(self.action_read_id.as_mut().unwrap()) {
+            match action.as_str() {
+                "toggle_normals" => {
+                    settings.show_normals = !settings.show_normals;
+                    info!("Showing normals: {}", settings.show_normals);
+                }
+                "toggle_light_gizmos" => {
+                    settings.show_light_gizmos = !settings.show_light_gizmos;
+                    info!("Showing light gizmos: {}", settings.show_light_gizmos);
+                }
+                "toggle_path_gizmos" => {
+                    settings.show_path_gizmos = !settings.show_path_gizmos;
+                    info!("Showing path gizmos: {}", settings.show_path_gizmos);
+                }
+                "toggle_stats" => {
+                    settings.show_stats = !settings.show_stats;
+                    info!("Showing stats: {}", settings.show_stats);
+                }
+                "toggle_recording" => {
+                    settings.recording = !settings.recording;
+                    info!("Recording: {}", settings.recording);
+                }
+                "cycle_aa" => {
+                    settings.aa_mode = settings.aa_mode.next();
+                    info!("Anti-aliasing: {:?}", settings.aa_mode);
+                }
+                "cycle_quality" => {
+                    settings.quality = settings.quality.next();
+                    info!("Quality: {:?}", settings.quality);
+                }
+                "cycle_debug_view" => {
+                    settings.debug_view = settings.debug_view.next();
+                    info!("Debug view: {:?}", settings.debug_view);
+                }
+                "toggle_reversed_z" => {
+                    settings.reversed_z = !settings.reversed_z;
+                    info!("Reversed depth: {}", settings.reversed_z);
+                }
+                "toggle_hi_z" => {
+                    settings.hi_z = !settings.hi_z;
+                    info!("Hi-Z occlusion culling: {}", settings.hi_z);
+                }
+                "toggle_infinite_far" => {
+                    for (camera, _) in (&mut cameras, &active_cameras).join() {
+                        let far = match camera.far() {
+                            Some(_) => None,
+                            None => Some(DEFAULT_FAR),
+                        };
+                        camera.set_clip_planes(camera.near(), far);
+                        info!("Camera far plane: {:?}", far);
+                    }
+                }
+                "cycle_lut" => {
+                    grading.cycle();
+                    match grading.active() {
+                        Some((name, _)) => info!("Color grading: {}", name),
+                        None => info!("Color grading: off"),
+                    }
+                }
+                // Log commands are bound like "log debug" or "log vkengine::renderer warn"
+                action if action.starts_with("log ") => {
+                    match log_levels.command(&action["log ".len()..]) {
+                        Ok(line) => info!("{}", line),
+                        Err(err) => warn!("{}", err),
+                    }
+                }
+                // Scene commands are bound like "scene add resources/scenes/shapes.ron"
+                action if action.starts_with("scene ") => {
+                    match scenes.command(&action["scene ".len()..]) {
+                        Ok(line) => info!("{}", line),
+                        Err(err) => warn!("{}", err),
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+
+    fn setup(&mut self, res: &mut Resources) {
+        Self::SystemData::setup(res);
+
+        let mut actions = res.fetch_mut::<ActionEvents>();
+        self.action_read_id = Some(actions.register_reader());
     }
 }
 
-pub struct PlacerSystem;
+/// Imports glTF files dropped on the window in front of the camera, and switches to dropped scene
+/// files
+///
+/// Clicks once the mesh of an imported file is ready, which takes a few frames of decoding.
+#[derive(Debug, Default)]
+pub struct FileDropLoaderSystem {
+    file_drop_read_id: Option<ReaderId<FileDropEvent>>,
+    mesh_ready_read_id: Option<ReaderId<MeshReadyEvent>>,
+    /// Entities created for dropped files whose meshes are not ready yet
+    importing: HashMap<Entity, PathBuf>,
+}
 
-impl<'a> System<'a> for PlacerSystem {
-    type SystemData = (Entities<'a>, Read<'a, LazyUpdate>, Write<'a, GameInput>, ReadStorage<'a, ActiveCamera>, ReadStorage<'a, GlobalTransform>);
+impl<'a> System<'a> for FileDropLoaderSystem {
+    type SystemData = (
+        Entities<'a>,
+        Read<'a, LazyUpdate>,
+        Read<'a, FileDropEvents>,
+        Read<'a, MeshReadyEvents>,
+        Write<'a, Scenes>,
+        Write<'a, SoundEffects>,
+        ReadStorage<'a, ActiveCamera>,
+        ReadStorage<'a, GlobalTransform>,
+    );
 
-    fn run(&mut self, (entities, lazy, mut input, active_camera, globals): Self::SystemData) {
-        if input.action_pressed {
-            input.action_pressed = false;
+    fn run(
+        &mut self,
+        (
+            entities,
+            lazy,
+            file_drop_events,
+            mesh_ready_events,
+            mut scenes,
+            mut sound_effects,
+            active_camera,
+            globals,
+        ): Self::SystemData,
+    ) {
+        for MeshReadyEvent(entity) in
+            mesh_ready_events.read(self.mesh_ready_read_id.as_mut().unwrap())
+        {
+            if let Some(path) = self.importing.remove(entity) {
+                info!("Imported dropped file: {}", path.display());
+                sound_effects.single_write(SoundEffect::Click);
+            }
+        }
+        // Failed to load, or deleted before the mesh was ready
+        self.importing
+            .retain(|entity, _| entities.is_alive(*entity));
+
+        let drops = file_drop_events.read(self.file_drop_read_id.as_mut().unwrap());
+
+        for FileDropEvent(path) in drops {
+            // Scene files replace the current scene once the frame is done
+            if path.extension().map(|ext| ext == "ron").unwrap_or(false) {
+                info!("Switching to dropped scene: {}", path.display());
+                scenes.switch(path.clone());
+                continue;
+            }
+
+            let is_gltf = path
+                .extension()
+                .map(|ext| ext == "gltf" || ext == "glb")
+                .unwrap_or(false);
 
-            let (camera_t, _) = (&globals, &active_camera).join().next().unwrap();
-            let mut transform = camera_t.clone();
-            transform.translate_forward(5.0);
+            if !is_gltf {
+                warn!("Can not import dropped file: {}", path.display());
+                continue;
+            }
+
+            // In front of the camera, or at the origin if there is none
+            let transform = match (&globals, &active_camera).join().next() {
+                Some((camera_t, _)) => {
+                    let mut transform = camera_t.global.clone();
+                    transform.translate_forward(5.0);
+                    transform
+                }
+                None => Transform::default(),
+            };
 
-            use crate::renderer::geometry::{MeshBuilder, Shape};
-            lazy.create_entity(&entities)
+            info!("Importing dropped file: {}", path.display());
+
+            let entity = lazy
+                .create_entity(&entities)
                 .with(transform)
-                .with(MeshBuilder::new().with_shape(Shape::Cube))
-                .with(PointLightComponent::from_color(Vector3::new(0.0, 1.0, 0.0)))
+                .with(LoadMesh::new(path.clone()).quantized())
                 .build();
+            self.importing.insert(entity, path.clone());
         }
     }
+
+    fn setup(&mut self, res: &mut Resources) {
+        Self::SystemData::setup(res);
+
+        let mut file_drops = res.fetch_mut::<FileDropEvents>();
+        self.file_drop_read_id = Some(file_drops.register_reader());
+
+        let mut mesh_ready = res.fetch_mut::<MeshReadyEvents>();
+        self.mesh_ready_read_id = Some(mesh_ready.register_reader());
+    }
 }
 
 // pub struct SendSyncWindow(pub SdlWindow);
@@ -309,64 +978,114 @@ impl<'a> System<'a> for PlacerSystem {
 // unsafe impl Send for SendSyncWindow {}
 // unsafe impl Sync for SendSyncWindow {}
 
-static LEFT_THUMB_DEADZONE: i16 = 7849;
-static RIGHT_THUMB_DEADZONE: i16 = 8689;
-static TRIGGER_THRESHOLD: i16 = 30;
-
 /// System for turning sdl events into ecs data
 pub struct SDLSystem {
     context: Sdl,
-    _video_subsystem: VideoSubsystem,
+    video_subsystem: VideoSubsystem,
     window: SdlWindow,
     controller_subsystem: GameControllerSubsystem,
     controllers: Vec<GameController>,
     event_pump: EventPump,
+    /// The display the window was last seen on
+    display: Option<i32>,
+    /// Whether relative mouse mode reads raw motion, see `MouseSettings::raw_input`
+    raw_input: bool,
+    /// Whether the mouse is grabbed right now, see `CursorState`
+    grabbed: bool,
 }
 
 impl SDLSystem {
     pub fn new() -> Self {
+        Self::with_window(true, |video_subsystem| {
+            video_subsystem
+                .window("vkengine", 1600, 900)
+                .resizable()
+                .position_centered()
+                .input_grabbed()
+                .allow_highdpi()
+                .vulkan()
+                .build()
+                .unwrap()
+        })
+    }
+
+    /// A window of exactly `width` by `height` pixels that is never shown, for rendering off
+    /// screen, like the reference image tests do
+    #[cfg(test)]
+    pub fn hidden(width: u32, height: u32) -> Self {
+        Self::with_window(false, |video_subsystem| {
+            video_subsystem
+                .window("vkengine", width, height)
+                .hidden()
+                .vulkan()
+                .build()
+                .unwrap()
+        })
+    }
+
+    fn with_window<F>(grabbed: bool, build: F) -> Self
+    where
+        F: FnOnce(&VideoSubsystem) -> SdlWindow,
+    {
         let context = sdl2::init().unwrap();
-        let _video_subsystem = context.video().unwrap();
+        let video_subsystem = context.video().unwrap();
         let controller_subsystem = context.game_controller().unwrap();
         let controllers = Vec::with_capacity(4);
         let event_pump = context.event_pump().unwrap();
 
-        context.mouse().set_relative_mouse_mode(true);
+        context.mouse().set_relative_mouse_mode(grabbed);
 
-        let window = _video_subsystem
-            .window("vkengine", 1600, 900)
-            .resizable()
-            .position_centered()
-            .input_grabbed()
-            .allow_highdpi()
-            .vulkan()
-            .build()
-            .unwrap();
+        // Text input is enabled by default, but we only want it when something asks for it
+        video_subsystem.text_input().stop();
+
+        let window = build(&video_subsystem);
+        let display = window.display_index().ok();
 
         Self {
             context,
-            _video_subsystem,
+            video_subsystem,
             window,
             controller_subsystem,
             controllers,
             event_pump,
+            display,
+            raw_input: true,
+            grabbed,
         }
     }
 
     pub fn window(&self) -> &SdlWindow {
         &self.window
     }
+
+    /// Grabs the mouse and hides the cursor to look around, or frees the cursor
+    fn grab(mouse_util: &MouseUtil, window: &mut SdlWindow, grab: bool) {
+        mouse_util.set_relative_mouse_mode(grab);
+        mouse_util.capture(grab);
+        mouse_util.show_cursor(!grab);
+        window.set_grab(grab);
+    }
 }
 
-// FIXME Fullscreen currently crashes in forign code
 impl<'a> System<'a> for SDLSystem {
     type SystemData = (
         Write<'a, ShouldClose>,
         Write<'a, FocusGained>,
+        Write<'a, WindowVisible>,
         Write<'a, RenderEvents>,
         Write<'a, KeyboardEvents>,
+        Write<'a, KeyboardState>,
         Write<'a, MouseEvents>,
+        Write<'a, CursorState>,
+        Write<'a, WindowMode>,
         Write<'a, ControllerEvents>,
+        Write<'a, WindowTitle>,
+        Write<'a, TextInput>,
+        Write<'a, TextInputEvents>,
+        Write<'a, Clipboard>,
+        Write<'a, FileDropEvents>,
+        Read<'a, InputBindings>,
+        Read<'a, MouseSettings>,
     );
 
     fn run(
@@ -374,37 +1093,140 @@ impl<'a> System<'a> for SDLSystem {
         (
             mut should_close,
             mut window_focus,
+            mut window_visible,
             mut render_events,
             mut keyboard_events,
+            mut keyboard_state,
             mut mouse_events,
+            mut cursor,
+            mut window_mode,
             mut controller_events,
+            mut window_title,
+            mut text_input,
+            mut text_input_events,
+            mut clipboard,
+            mut file_drop_events,
+            bindings,
+            mouse_settings,
         ): Self::SystemData,
     ) {
         let mouse_util = &self.context.mouse();
 
+        // The hint is only read when relative mode is turned on
+        if mouse_settings.raw_input != self.raw_input {
+            self.raw_input = mouse_settings.raw_input;
+            let warp = if self.raw_input { "0" } else { "1" };
+            sdl2::hint::set("SDL_MOUSE_RELATIVE_MODE_WARP", warp);
+            if self.grabbed {
+                mouse_util.set_relative_mouse_mode(false);
+                mouse_util.set_relative_mouse_mode(true);
+            }
+            info!("Raw mouse input: {}", self.raw_input);
+        }
+
+        // The renderer has waited for the GPU by now, see `WindowMode`
+        if let Some(fullscreen) = window_mode.take_ready() {
+            let mode = if fullscreen {
+                FullscreenType::Desktop
+            } else {
+                FullscreenType::Off
+            };
+
+            match self.window.set_fullscreen(mode) {
+                Ok(()) => info!("Fullscreen: {}", fullscreen),
+                Err(err) => warn!("Failed to change fullscreen mode: {}", err),
+            }
+
+            let (width, height) = self.window.drawable_size();
+            render_events.single_write(RenderEvent::WindowResized(width, height));
+        }
+
+        // Grab or free the mouse if it has been requested, it is always free while unfocused
+        cursor.new_frame();
+        if window_focus.0 && cursor.is_grabbed() != self.grabbed {
+            self.grabbed = cursor.is_grabbed();
+            Self::grab(mouse_util, &mut self.window, self.grabbed);
+            if self.grabbed {
+                cursor.settle();
+            }
+        }
+
+        if let Some(title) = window_title.0.take() {
+            if let Err(err) = self.window.set_title(&title) {
+                warn!("Failed to set window title: {}", err);
+            }
+        }
+
+        // Put text on the system clipboard
+        let clipboard_util = self.video_subsystem.clipboard();
+        if let Some(text) = clipboard.take_pending() {
+            if let Err(err) = clipboard_util.set_clipboard_text(&text) {
+                warn!("Failed to set clipboard text: {}", err);
+            }
+
+            clipboard.update(Some(text));
+        }
+
+        // Start or stop text input if it has been requested
+        let text_input_util = self.video_subsystem.text_input();
+        if text_input.is_enabled() != text_input_util.is_active() {
+            if text_input.is_enabled() {
+                text_input_util.start();
+            } else {
+                text_input_util.stop();
+            }
+        }
+
+        keyboard_state.new_frame();
+
         for event in self.event_pump.poll_iter() {
             match event {
                 Event::Quit { .. } => should_close.0 = true,
+                Event::ClipboardUpdate { .. } => {
+                    clipboard.update(clipboard_util.clipboard_text().ok());
+                }
+                Event::DropFile { filename, .. } => {
+                    info!("File dropped on window: {}", filename);
+                    file_drop_events.single_write(FileDropEvent(filename.into()));
+                }
                 // Window event
                 // ---------------------------------------------------------------------------------------------------------------
                 Event::Window { win_event, .. } => match win_event {
                     WindowEvent::FocusGained => {
                         window_focus.0 = true;
-                        mouse_util.capture(true);
-                        mouse_util.show_cursor(false);
+                        if cursor.is_grabbed() {
+                            self.grabbed = true;
+                            Self::grab(mouse_util, &mut self.window, true);
+                            cursor.settle();
+                        }
                     }
                     WindowEvent::FocusLost => {
                         window_focus.0 = false;
-                        mouse_util.capture(false);
-                        mouse_util.show_cursor(true);
+                        keyboard_state.release_all();
+                        self.grabbed = false;
+                        Self::grab(mouse_util, &mut self.window, false);
                     }
                     WindowEvent::Resized(_, _) => {
-                        render_events.single_write(RenderEvent::WindowResized);
+                        let (width, height) = self.window.drawable_size();
+                        render_events.single_write(RenderEvent::WindowResized(width, height));
+                    }
+                    // Another display might support other swapchain formats
+                    WindowEvent::Moved(_, _) => {
+                        let display = self.window.display_index().ok();
+                        if display != self.display {
+                            self.display = display;
+                            render_events.single_write(RenderEvent::DisplayChanged);
+                        }
                     }
                     WindowEvent::Hidden | WindowEvent::Minimized => {
+                        window_visible.0 = false;
                         render_events.single_write(RenderEvent::StopRendering);
                     }
-                    WindowEvent::Shown | WindowEvent::Exposed => {
+                    WindowEvent::Shown
+                    | WindowEvent::Exposed
+                    | WindowEvent::Restored
+                    | WindowEvent::Maximized => {
+                        window_visible.0 = true;
                         render_events.single_write(RenderEvent::StartRendering);
                     }
                     _ => (),
@@ -419,6 +1241,9 @@ impl<'a> System<'a> for SDLSystem {
                         absolute: (x, y),
                     };
 
+                    let (width, height) = self.window.size();
+                    cursor.set_position([x as f32 / width as f32, y as f32 / height as f32]);
+
                     mouse_events.single_write(event);
                 }
                 Event::MouseButtonDown {
@@ -456,6 +1281,7 @@ impl<'a> System<'a> for SDLSystem {
                     repeat,
                     ..
                 } => {
+                    keyboard_state.press(keycode);
                     let event = KeyboardEvent {
                         pressed: true,
                         keycode,
@@ -471,6 +1297,7 @@ impl<'a> System<'a> for SDLSystem {
                     repeat,
                     ..
                 } => {
+                    keyboard_state.release(keycode);
                     let event = KeyboardEvent {
                         pressed: false,
                         keycode,
@@ -480,6 +1307,39 @@ impl<'a> System<'a> for SDLSystem {
 
                     keyboard_events.single_write(event);
                 }
+                // Text input event
+                // ---------------------------------------------------------------------------------------------------------------
+                Event::TextInput { text, .. } => {
+                    if !text_input.is_enabled() {
+                        continue;
+                    }
+
+                    text_input.set_composition(None);
+                    text_input_events.single_write(TextInputEvent::Input(text));
+                }
+                Event::TextEditing {
+                    text,
+                    start,
+                    length,
+                    ..
+                } => {
+                    if !text_input.is_enabled() {
+                        continue;
+                    }
+
+                    let composition = Composition {
+                        text,
+                        start,
+                        length,
+                    };
+
+                    text_input.set_composition(if composition.text.is_empty() {
+                        None
+                    } else {
+                        Some(composition.clone())
+                    });
+                    text_input_events.single_write(TextInputEvent::Editing(composition));
+                }
                 // Controller event
                 // ---------------------------------------------------------------------------------------------------------------
                 Event::ControllerDeviceAdded { which, .. } => {
@@ -487,19 +1347,20 @@ impl<'a> System<'a> for SDLSystem {
                     info!("Found game controller: {}", name);
 
                     let controller = self.controller_subsystem.open(which).unwrap();
-                    self.controllers.insert(which as usize, controller);
 
-                    let event = ControllerEvent::Connected(which as i32);
+                    // Added events use the device index, every other event the instance id
+                    let event = ControllerEvent::Connected(controller.instance_id());
                     controller_events.single_write(event);
+
+                    self.controllers.push(controller);
                 }
                 Event::ControllerDeviceRemoved { which, .. } => {
-                    let name = self
-                        .controller_subsystem
-                        .name_for_index(which as u32)
-                        .unwrap();
-                    info!("Game controller removed: {}", name);
+                    for controller in self.controllers.iter().filter(|c| c.instance_id() == which) {
+                        info!("Game controller removed: {}", controller.name());
+                    }
 
-                    self.controllers.remove(which as usize);
+                    self.controllers
+                        .retain(|controller| controller.instance_id() != which);
 
                     let event = ControllerEvent::Disconnected(which);
                     controller_events.single_write(event);
@@ -507,36 +1368,8 @@ impl<'a> System<'a> for SDLSystem {
                 Event::ControllerAxisMotion {
                     which, axis, value, ..
                 } => {
-                    // If the value is inside deadzone: then value is 0
-                    let value = match axis {
-                        // Left
-                        ControllerAxis::LeftX | ControllerAxis::LeftY => {
-                            if value > LEFT_THUMB_DEADZONE || value < -LEFT_THUMB_DEADZONE {
-                                value
-                            } else {
-                                0
-                            }
-                        }
-                        // Right
-                        ControllerAxis::RightX | ControllerAxis::RightY => {
-                            if value > RIGHT_THUMB_DEADZONE || value < -RIGHT_THUMB_DEADZONE {
-                                value
-                            } else {
-                                0
-                            }
-                        }
-                        // Triggers
-                        ControllerAxis::TriggerLeft | ControllerAxis::TriggerRight => {
-                            if value > TRIGGER_THRESHOLD {
-                                value
-                            } else {
-                                0
-                            }
-                        }
-                    };
-
-                    // Normalize
-                    let value = value as f32 / std::i16::MAX as f32;
+                    // Dead zone, response curve and inversion of the axis
+                    let value = bindings.axis(axis).apply(value);
 
                     let event = ControllerEvent::AxisMotion {
                         id: which,
@@ -568,4 +1401,108 @@ impl<'a> System<'a> for SDLSystem {
             }
         }
     }
+
+    fn setup(&mut self, res: &mut Resources) {
+        Self::SystemData::setup(res);
+
+        // Start out with whatever is on the clipboard already
+        let text = self.video_subsystem.clipboard().clipboard_text().ok();
+        res.fetch_mut::<Clipboard>().update(text);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Axis, AxisSmoothing, GameInputSystem, GameInputs, MouseSettings};
+    use crate::{
+        components::PlayerId,
+        resources::{KeyboardState, Keycode},
+    };
+    use specs::prelude::*;
+
+    #[test]
+    fn axis_smoothing() {
+        let smoothing = AxisSmoothing {
+            acceleration: 4.0,
+            deceleration: 8.0,
+        };
+
+        // The same time in more steps gets just as far
+        let (mut a, mut b) = (Axis::default(), Axis::default());
+        a.set_target(1.);
+        b.set_target(1.);
+        a.step(&smoothing, 0.1);
+        b.step(&smoothing, 0.05);
+        b.step(&smoothing, 0.05);
+        assert!((a.get() - 0.4).abs() < 1e-5);
+        assert!((a.get() - b.get()).abs() < 1e-5);
+
+        // Never overshoots
+        a.step(&smoothing, 1.0);
+        assert_eq!(a.get(), 1.);
+
+        // Turning around decelerates
+        a.set_target(-1.);
+        a.step(&smoothing, 0.1);
+        assert!((a.get() - 0.2).abs() < 1e-5);
+
+        // Analog input is not smoothed
+        a.set(0.5);
+        a.step(&smoothing, 0.1);
+        assert_eq!(a.get(), 0.5);
+    }
+
+    #[test]
+    fn mouse_smoothing() {
+        let mut settings = MouseSettings::default();
+
+        // Without smoothing the motion comes straight through
+        assert_eq!(settings.filter((4.0, -2.0), (1.0, 1.0), 0.016), (4.0, -2.0));
+
+        // The same time in more frames catches up just as far
+        settings.smoothing = 0.1;
+        let once = settings.filter((10.0, 0.0), (0.0, 0.0), 0.02);
+        let half = settings.filter((10.0, 0.0), (0.0, 0.0), 0.01);
+        let twice = settings.filter((10.0, 0.0), half, 0.01);
+        assert!(once.0 > 0.0 && once.0 < 10.0);
+        assert!((once.0 - twice.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn held_keys() {
+        let mut world = World::new();
+        let mut dispatcher = DispatcherBuilder::new()
+            .with(GameInputSystem::default(), "input", &[])
+            .build();
+        dispatcher.setup(&mut world.res);
+
+        // Changes the held keys, the way the SDLSystem does between frames
+        let mut frame = |pressed: &[Keycode], released: &[Keycode]| {
+            {
+                let mut keyboard = world.write_resource::<KeyboardState>();
+                keyboard.new_frame();
+                pressed.iter().for_each(|keycode| keyboard.press(*keycode));
+                released
+                    .iter()
+                    .for_each(|keycode| keyboard.release(*keycode));
+            }
+            dispatcher.dispatch(&world.res);
+
+            world
+                .read_resource::<GameInputs>()
+                .get(PlayerId::default())
+                .unwrap()
+                .forward
+                .target
+        };
+
+        assert_eq!(frame(&[Keycode::W], &[]), 1.);
+        assert_eq!(frame(&[Keycode::S], &[]), 0.);
+        // The key still held takes over
+        assert_eq!(frame(&[], &[Keycode::W]), -1.);
+        // Ctrl+S is a chord
+        assert_eq!(frame(&[Keycode::LCtrl], &[]), 0.);
+        assert_eq!(frame(&[], &[Keycode::LCtrl]), -1.);
+        assert_eq!(frame(&[], &[Keycode::S]), 0.);
+    }
 }
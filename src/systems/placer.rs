@@ -0,0 +1,361 @@
+use crate::{
+    components::{GlobalTransform, PlayerId, Transform, TransformStorageExt},
+    renderer::{
+        camera::ActiveCamera,
+        geometry::{Bounds, Ghost, MeshBuilder, Shape},
+        lights::PointLightComponent,
+    },
+    resources::{ActionEvent, ActionEvents, Rng, SoundEffect, SoundEffects},
+    systems::{GameInputs, SpatialIndex},
+};
+use log::info;
+use nalgebra::{Point3, UnitQuaternion, Vector3};
+use ncollide3d::query::Ray;
+use shrev::ReaderId;
+use specs::prelude::*;
+use specs_derive::Component;
+
+/// How far in front of the camera objects are placed if the crosshair is not over anything
+const PLACER_DISTANCE: f32 = 5.0;
+/// How far placed objects are pushed out of the surface they hit, half the size of the cube
+const PLACER_SURFACE_OFFSET: f32 = 0.5;
+/// Most placed objects are turned away from the camera's heading around the up axis, in radians,
+/// so rows of them don't look stamped out
+const PLACER_JITTER: f32 = 0.1;
+/// How many operations the edit history remembers
+const HISTORY_LENGTH: usize = 100;
+
+/// Marks an entity as created by the placement tools, and remembers its shape so the entity can
+/// be re-created after it has been deleted
+#[derive(Component, Debug, Clone, Copy)]
+#[storage(DenseVecStorage)]
+pub struct Placed(pub Shape);
+
+/// Everything needed to re-create a placed entity
+#[derive(Debug, Clone)]
+pub struct EntitySnapshot {
+    pub transform: Transform,
+    pub shape: Shape,
+    pub point_light: Option<PointLightComponent>,
+}
+
+impl EntitySnapshot {
+    fn create(&self, entities: &Entities<'_>, lazy: &LazyUpdate) -> Entity {
+        let mut builder = lazy
+            .create_entity(entities)
+            .with(self.transform.clone())
+            .with(MeshBuilder::new().with_shape(self.shape))
+            .with(Placed(self.shape));
+
+        if let Some(point_light) = self.point_light.clone() {
+            builder = builder.with(point_light);
+        }
+
+        builder.build()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum EditOperation {
+    Place {
+        entity: Entity,
+        snapshot: EntitySnapshot,
+    },
+    Remove {
+        entity: Entity,
+        snapshot: EntitySnapshot,
+    },
+}
+
+impl EditOperation {
+    /// Placing is the inverse of removing, and the other way around
+    fn inverted(self) -> Self {
+        match self {
+            EditOperation::Place { entity, snapshot } => EditOperation::Remove { entity, snapshot },
+            EditOperation::Remove { entity, snapshot } => EditOperation::Place { entity, snapshot },
+        }
+    }
+
+    fn entity_mut(&mut self) -> &mut Entity {
+        match self {
+            EditOperation::Place { entity, .. } | EditOperation::Remove { entity, .. } => entity,
+        }
+    }
+}
+
+/// Resource recording the operations of the placement tools, so they can be undone and redone
+#[derive(Debug, Default)]
+pub struct EditHistory {
+    undo: Vec<EditOperation>,
+    redo: Vec<EditOperation>,
+}
+
+impl EditHistory {
+    /// Records a new operation. Anything that could be redone is forgotten
+    pub fn record(&mut self, operation: EditOperation) {
+        if self.undo.len() == HISTORY_LENGTH {
+            self.undo.remove(0);
+        }
+
+        self.undo.push(operation);
+        self.redo.clear();
+    }
+
+    /// Forgets every operation, when the entities they refer to are gone
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+    }
+
+    /// Re-created entities get a new id, so every operation referring to the old one is updated
+    fn remap(&mut self, old: Entity, new: Entity) {
+        for operation in self.undo.iter_mut().chain(self.redo.iter_mut()) {
+            let entity = operation.entity_mut();
+            if *entity == old {
+                *entity = new;
+            }
+        }
+    }
+
+    /// Reverts the last operation
+    pub fn undo(&mut self, entities: &Entities<'_>, lazy: &LazyUpdate) {
+        let operation = match self.undo.pop() {
+            Some(operation) => operation,
+            None => return,
+        };
+
+        info!("Undo: {:?}", operation);
+
+        let operation = self.revert(operation, entities, lazy);
+        self.redo.push(operation);
+    }
+
+    /// Applies the last undone operation again
+    pub fn redo(&mut self, entities: &Entities<'_>, lazy: &LazyUpdate) {
+        let operation = match self.redo.pop() {
+            Some(operation) => operation,
+            None => return,
+        };
+
+        info!("Redo: {:?}", operation);
+
+        let operation = self.revert(operation.inverted(), entities, lazy);
+        self.undo.push(operation.inverted());
+    }
+
+    /// Undoes an operation, returning it with the entity it now refers to
+    fn revert(
+        &mut self,
+        operation: EditOperation,
+        entities: &Entities<'_>,
+        lazy: &LazyUpdate,
+    ) -> EditOperation {
+        match operation {
+            EditOperation::Place { entity, snapshot } => {
+                if entities.is_alive(entity) {
+                    entities.delete(entity).unwrap();
+                }
+                EditOperation::Place { entity, snapshot }
+            }
+            EditOperation::Remove { entity, snapshot } => {
+                let new = snapshot.create(entities, lazy);
+                self.remap(entity, new);
+                EditOperation::Remove {
+                    entity: new,
+                    snapshot,
+                }
+            }
+        }
+    }
+}
+
+/// Places objects where the crosshair hits, with a ghost previewing the placement
+///
+/// Objects under the crosshair can be removed again, and every operation is recorded in the
+/// EditHistory so it can be undone with Ctrl+Z and redone with Ctrl+Y.
+#[derive(Debug, Default)]
+pub struct PlacerSystem {
+    preview: Option<Entity>,
+    action_read_id: Option<ReaderId<ActionEvent>>,
+}
+
+impl PlacerSystem {
+    /// Finds where the next object should go and what is under the crosshair, by casting a ray
+    /// from the center of the camera against the bounds of the meshes the index finds along it
+    fn target(
+        &self,
+        camera_t: &GlobalTransform,
+        index: &SpatialIndex,
+        bounds: &ReadStorage<'_, Bounds>,
+        globals: &ReadStorage<'_, GlobalTransform>,
+    ) -> (Transform, Option<Entity>) {
+        let origin = Point3::from(*camera_t.translation());
+        let dir = camera_t.rotation() * -Vector3::z();
+        let ray = Ray::new(origin, dir);
+
+        let hit = index
+            .ray(&ray)
+            .into_iter()
+            .filter(|entity| Some(*entity) != self.preview)
+            .filter_map(|entity| {
+                let hit = bounds.get(entity)?.cast_ray(globals.get(entity)?, &ray)?;
+                Some((entity, hit))
+            })
+            // Ignore hits from inside a mesh
+            .filter(|(_, hit)| hit.toi > 0.0)
+            .min_by(|(_, a), (_, b)| a.toi.partial_cmp(&b.toi).unwrap());
+
+        let (translation, entity) = match hit {
+            Some((entity, hit)) => (
+                ray.point_at(hit.toi).coords + hit.normal * PLACER_SURFACE_OFFSET,
+                Some(entity),
+            ),
+            None => (ray.point_at(PLACER_DISTANCE).coords, None),
+        };
+
+        let transform = Transform::from_parts(
+            translation,
+            *camera_t.rotation(),
+            Vector3::new(1.0, 1.0, 1.0),
+        );
+
+        (transform, entity)
+    }
+}
+
+impl<'a> System<'a> for PlacerSystem {
+    type SystemData = (
+        Entities<'a>,
+        Read<'a, LazyUpdate>,
+        Read<'a, ActionEvents>,
+        Read<'a, SpatialIndex>,
+        Write<'a, GameInputs>,
+        Write<'a, EditHistory>,
+        Write<'a, SoundEffects>,
+        Write<'a, Rng>,
+        ReadStorage<'a, ActiveCamera>,
+        ReadStorage<'a, Bounds>,
+        ReadStorage<'a, Placed>,
+        ReadStorage<'a, PointLightComponent>,
+        ReadStorage<'a, GlobalTransform>,
+        WriteStorage<'a, Transform>,
+    );
+
+    fn run(
+        &mut self,
+        (
+            entities,
+            lazy,
+            action_events,
+            index,
+            mut inputs,
+            mut history,
+            mut sound_effects,
+            mut rng,
+            active_camera,
+            bounds,
+            placed,
+            point_lights,
+            globals,
+            mut transforms,
+        ): Self::SystemData,
+    ) {
+        // Undo and redo
+        // -----------------------------------------------------------------------------------------------------
+        for ActionEvent(action) in action_events.read(self.action_read_id.as_mut().unwrap()) {
+            match action.as_str() {
+                "undo" => history.undo(&entities, &lazy),
+                "redo" => history.redo(&entities, &lazy),
+                _ => (),
+            }
+        }
+
+        // The placement tools belong to the first player
+        let input = inputs.get_mut(PlayerId::default());
+
+        // Nothing to aim with without a camera
+        let (camera_t, _) = match (&globals, &active_camera).join().next() {
+            Some(camera) => camera,
+            None => return,
+        };
+        let (target, hit) = self.target(camera_t, &index, &bounds, &globals);
+
+        // Move the ghost to where the object would be placed
+        // -----------------------------------------------------------------------------------------------------
+        // Unloading the scene deletes the ghost along with everything else
+        match self.preview {
+            Some(preview) if entities.is_alive(preview) => {
+                transforms.set(preview, target.clone());
+            }
+            _ => {
+                let preview = lazy
+                    .create_entity(&entities)
+                    .with(target.clone())
+                    .with(MeshBuilder::new().with_shape(Shape::Cube))
+                    .with(Ghost)
+                    .build();
+
+                self.preview = Some(preview);
+            }
+        }
+
+        // Placing
+        // -----------------------------------------------------------------------------------------------------
+        if input.action_pressed {
+            input.action_pressed = false;
+
+            let mut transform = target;
+            transform.rotate_global(UnitQuaternion::from_axis_angle(
+                &Vector3::y_axis(),
+                rng.range(-PLACER_JITTER, PLACER_JITTER),
+            ));
+
+            let snapshot = EntitySnapshot {
+                transform,
+                shape: Shape::Cube,
+                point_light: Some(PointLightComponent::from_color(Vector3::new(0.0, 1.0, 0.0))),
+            };
+
+            let entity = snapshot.create(&entities, &lazy);
+            history.record(EditOperation::Place { entity, snapshot });
+            sound_effects.single_write(SoundEffect::Click);
+        }
+
+        // Removing
+        // -----------------------------------------------------------------------------------------------------
+        if input.remove_pressed {
+            input.remove_pressed = false;
+
+            // Only entities created by the placement tools can be re-created by undo
+            let target = hit.and_then(|entity| {
+                let transform = transforms.get(entity)?;
+                let Placed(shape) = placed.get(entity)?;
+
+                Some((
+                    entity,
+                    EntitySnapshot {
+                        transform: transform.clone(),
+                        shape: *shape,
+                        point_light: point_lights.get(entity).cloned(),
+                    },
+                ))
+            });
+
+            match target {
+                Some((entity, snapshot)) => {
+                    entities.delete(entity).unwrap();
+                    history.record(EditOperation::Remove { entity, snapshot });
+                    sound_effects.single_write(SoundEffect::Click);
+                }
+                None => sound_effects.single_write(SoundEffect::Error),
+            }
+        }
+    }
+
+    fn setup(&mut self, res: &mut Resources) {
+        Self::SystemData::setup(res);
+
+        let mut actions = res.fetch_mut::<ActionEvents>();
+        self.action_read_id = Some(actions.register_reader());
+    }
+}
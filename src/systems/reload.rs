@@ -0,0 +1,214 @@
+use crate::{components::CoordinateSystem, renderer::geometry::MeshBuilder};
+use log::{info, warn};
+use notify::{watcher, DebouncedEvent, RecommendedWatcher, RecursiveMode, Watcher};
+use specs::prelude::*;
+use specs_derive::Component;
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+    sync::mpsc::{channel, Receiver, Sender},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+/// How long the watcher waits for writes to a file to settle before reporting it
+const WATCH_DELAY: Duration = Duration::from_millis(200);
+
+/// The file an entity's mesh was loaded from, and how it was built, so it can be built again
+#[derive(Component, Debug, Clone)]
+#[storage(DenseVecStorage)]
+pub struct MeshSource {
+    pub path: PathBuf,
+    batched: bool,
+    quantized: bool,
+    coordinates: CoordinateSystem,
+}
+
+/// Paths from the watcher and from the builders are compared in their canonical form
+fn canonical(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+type Loaded = (PathBuf, Result<MeshBuilder, String>);
+
+/// Reloads meshes when the files they were loaded from change on disk
+///
+/// Every MeshBuilder loaded from a file leaves a MeshSource behind, and the directory of the
+/// file is watched. When the file changes it is imported again on a loader thread, and every
+/// entity using it gets a new MeshBuilder, which the renderer builds over the old mesh. Transforms
+/// and everything else on the entities are left as they are.
+///
+/// Has to run before the renderer, which consumes the MeshBuilders.
+pub struct MeshReloadSystem {
+    watcher: Option<RecommendedWatcher>,
+    watch_events: Receiver<DebouncedEvent>,
+    watched_dirs: HashSet<PathBuf>,
+    loaded_sender: Sender<Loaded>,
+    loaded: Receiver<Loaded>,
+    loaders: HashMap<PathBuf, JoinHandle<()>>,
+    /// Files that changed again while they were being loaded
+    reload_again: HashSet<PathBuf>,
+}
+
+impl MeshReloadSystem {
+    pub fn new() -> Self {
+        let (watch_sender, watch_events) = channel();
+        let (loaded_sender, loaded) = channel();
+
+        let watcher = match watcher(watch_sender, WATCH_DELAY) {
+            Ok(watcher) => Some(watcher),
+            Err(err) => {
+                warn!(
+                    "Failed to start file watcher, meshes will not be reloaded: {}",
+                    err
+                );
+                None
+            }
+        };
+
+        Self {
+            watcher,
+            watch_events,
+            watched_dirs: HashSet::new(),
+            loaded_sender,
+            loaded,
+            loaders: HashMap::new(),
+            reload_again: HashSet::new(),
+        }
+    }
+
+    fn watch(&mut self, file: &Path) {
+        let (watcher, dir) = match (self.watcher.as_mut(), file.parent()) {
+            (Some(watcher), Some(dir)) => (watcher, dir),
+            _ => return,
+        };
+
+        // Editors often save by replacing the file, so the directory is watched, not the file
+        if self.watched_dirs.insert(dir.to_path_buf()) {
+            if let Err(err) = watcher.watch(dir, RecursiveMode::NonRecursive) {
+                warn!("Failed to watch {}: {}", dir.display(), err);
+            }
+        }
+    }
+
+    fn load(&mut self, path: PathBuf) {
+        if self.loaders.contains_key(&path) {
+            self.reload_again.insert(path);
+            return;
+        }
+
+        info!("Reloading mesh: {}", path.display());
+
+        let sender = self.loaded_sender.clone();
+        let file = path.clone();
+        let loader = thread::spawn(move || {
+            let result = MeshBuilder::new().try_with_gltf_path(&file);
+            // The system is gone if this fails, and the result is not needed anymore
+            let _ = sender.send((file, result));
+        });
+
+        self.loaders.insert(path, loader);
+    }
+}
+
+impl Default for MeshReloadSystem {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a> System<'a> for MeshReloadSystem {
+    type SystemData = (
+        Entities<'a>,
+        WriteStorage<'a, MeshSource>,
+        WriteStorage<'a, MeshBuilder>,
+    );
+
+    fn run(&mut self, (entities, mut sources, mut mesh_builders): Self::SystemData) {
+        // Remember where new meshes come from
+        // -----------------------------------------------------------------------------------------------------
+        let new = (&entities, &mesh_builders, !sources.mask().clone())
+            .join()
+            .filter_map(|(entity, builder, _)| {
+                let source = MeshSource {
+                    path: canonical(builder.source()?),
+                    batched: builder.is_batched(),
+                    quantized: builder.is_quantized(),
+                    coordinates: *builder.coordinates(),
+                };
+                Some((entity, source))
+            })
+            .collect::<Vec<_>>();
+
+        for (entity, source) in new {
+            self.watch(&source.path);
+            sources.insert(entity, source).unwrap();
+        }
+
+        // Start loading changed files
+        // -----------------------------------------------------------------------------------------------------
+        let changed = self
+            .watch_events
+            .try_iter()
+            .filter_map(|event| match event {
+                DebouncedEvent::Create(path)
+                | DebouncedEvent::Write(path)
+                | DebouncedEvent::Rename(_, path) => Some(canonical(&path)),
+                _ => None,
+            })
+            .filter(|path| (&sources).join().any(|source| source.path == *path))
+            .collect::<HashSet<_>>();
+
+        for path in changed {
+            self.load(path);
+        }
+
+        // Hand loaded meshes to the renderer
+        // -----------------------------------------------------------------------------------------------------
+        let loaded = self.loaded.try_iter().collect::<Vec<_>>();
+
+        for (path, result) in loaded {
+            if let Some(loader) = self.loaders.remove(&path) {
+                loader.join().unwrap();
+            }
+
+            if self.reload_again.remove(&path) {
+                self.load(path.clone());
+            }
+
+            let builder = match result {
+                Ok(builder) => builder,
+                Err(err) => {
+                    warn!("Keeping the old mesh: {}", err);
+                    continue;
+                }
+            };
+
+            for (entity, source) in (&entities, &sources).join() {
+                if source.path != path {
+                    continue;
+                }
+
+                let mut builder = builder.clone().converted_from(&source.coordinates);
+                if source.batched {
+                    builder = builder.batched();
+                }
+                if source.quantized {
+                    builder = builder.quantized();
+                }
+
+                mesh_builders.insert(entity, builder).unwrap();
+            }
+        }
+    }
+}
+
+impl Drop for MeshReloadSystem {
+    /// Waits for the loader threads, so none outlive the world
+    fn drop(&mut self) {
+        for (_, loader) in self.loaders.drain() {
+            let _ = loader.join();
+        }
+    }
+}
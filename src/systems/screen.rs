@@ -0,0 +1,296 @@
+use crate::{
+    components::GlobalTransform,
+    renderer::{
+        camera::{ActiveCamera, Camera, Viewport},
+        overlay::Overlay,
+    },
+};
+use nalgebra::{Matrix4, Point3, Vector2, Vector3};
+use ncollide3d::query::Ray;
+use specs::prelude::*;
+use specs_derive::Component;
+use std::cmp::Ordering;
+
+/// Height of the text of labels, in pixels
+const LABEL_TEXT_SIZE: f32 = 16.0;
+
+/// Projects an entity onto the screen every frame, for labels and health bars drawn over it
+#[derive(Component, Debug, Clone)]
+#[storage(HashMapStorage)]
+pub struct ScreenLabel {
+    /// Added to the position of the entity, to place the label above it
+    pub offset: Vector3<f32>,
+    /// How far from the edges of the view a clamped label stays, as a fraction of its size
+    pub margin: f32,
+    /// Drawn in the Overlay, centered above the position of the label
+    pub text: Option<String>,
+}
+
+impl ScreenLabel {
+    /// A label `height` above the entity
+    pub fn above(height: f32) -> Self {
+        Self {
+            offset: Vector3::new(0.0, height, 0.0),
+            ..Self::default()
+        }
+    }
+
+    pub fn with_text(mut self, text: &str) -> Self {
+        self.text = Some(text.to_string());
+        self
+    }
+}
+
+impl Default for ScreenLabel {
+    fn default() -> Self {
+        Self {
+            offset: Vector3::zeros(),
+            margin: 0.02,
+            text: None,
+        }
+    }
+}
+
+/// Where a ScreenLabel is on the screen, as of the last frame
+///
+/// Labels outside of the view, or behind the camera, are clamped to the edge of the view in the
+/// direction of the entity, so they can point the way to it.
+#[derive(Component, Debug, Clone, Copy, PartialEq)]
+#[storage(HashMapStorage)]
+pub struct ScreenPosition {
+    /// In the normalized coordinates of Viewport, (0, 0) being the top left of the screen
+    pub position: Vector2<f32>,
+    /// From the camera, for scaling and sorting labels
+    pub distance: f32,
+    /// Whether the label was moved to the edge of the view
+    pub clamped: bool,
+    pub behind: bool,
+}
+
+/// Projects a point in world space into a view of the screen
+///
+/// `view_projection` has to use the -1 to 1 depth of `Camera::projection`.
+pub fn project_to_screen(
+    view_projection: &Matrix4<f32>,
+    viewport: &Viewport,
+    point: &Point3<f32>,
+    margin: f32,
+) -> ScreenPosition {
+    let clip = view_projection * point.to_homogeneous();
+    let behind = clip.w <= std::f32::EPSILON;
+
+    // Dividing by a negative w would mirror points behind the camera
+    let mut ndc = clip.xy() / clip.w.abs().max(std::f32::EPSILON);
+
+    // Clamped along the line from the center of the view, so the label still points the way
+    let limit = 1.0 - 2.0 * margin;
+    let extent = ndc.x.abs().max(ndc.y.abs());
+    let clamped = behind || extent > limit;
+    if clamped {
+        ndc = if extent > std::f32::EPSILON {
+            ndc * (limit / extent)
+        } else {
+            // Straight behind the camera, where there is no direction to point in
+            Vector2::new(0.0, limit)
+        };
+    }
+
+    let uv = (ndc + Vector2::new(1.0, 1.0)) * 0.5;
+
+    ScreenPosition {
+        position: Vector2::new(
+            viewport.x + uv.x * viewport.width,
+            viewport.y + uv.y * viewport.height,
+        ),
+        distance: clip.w.abs(),
+        clamped,
+        behind,
+    }
+}
+
+/// The ray from the camera through a position in a view of the screen, for picking with the cursor
+///
+/// The position is in the normalized coordinates of Viewport. `view_projection` has to use the -1
+/// to 1 depth of `Camera::projection`. None if the matrix can't be inverted.
+pub fn screen_ray(
+    view_projection: &Matrix4<f32>,
+    viewport: &Viewport,
+    position: &Vector2<f32>,
+) -> Option<Ray<f32>> {
+    let inverse = view_projection.try_inverse()?;
+
+    let uv = Vector2::new(
+        (position.x - viewport.x) / viewport.width,
+        (position.y - viewport.y) / viewport.height,
+    );
+    let ndc = uv * 2.0 - Vector2::new(1.0, 1.0);
+
+    // The far plane may be at infinity, so the direction goes through the middle of the range
+    let near = inverse.transform_point(&Point3::new(ndc.x, ndc.y, -1.0));
+    let middle = inverse.transform_point(&Point3::new(ndc.x, ndc.y, 0.0));
+
+    Some(Ray::new(near, (middle - near).normalize()))
+}
+
+/// Draws the text of a label at its position on a screen of `dimensions` pixels
+///
+/// The text is centered above the position, and kept on the screen. Labels pointing the way to
+/// something out of view are faded, more so if it is behind the camera.
+fn draw_label(overlay: &mut Overlay, text: &str, position: &ScreenPosition, dimensions: [f32; 2]) {
+    let [width, height] = overlay.measure_text(text, LABEL_TEXT_SIZE);
+
+    let x = position.position.x * dimensions[0] - width * 0.5;
+    let y = position.position.y * dimensions[1] - height;
+    let x = x.max(0.0).min((dimensions[0] - width).max(0.0));
+    let y = y.max(0.0).min((dimensions[1] - height).max(0.0));
+
+    let alpha = if position.behind {
+        0.4
+    } else if position.clamped {
+        0.7
+    } else {
+        1.0
+    };
+
+    overlay.text(text, [x, y], LABEL_TEXT_SIZE, [1.0, 1.0, 1.0, alpha]);
+}
+
+/// Writes the ScreenPosition of every ScreenLabel, as seen by the first active camera, and draws
+/// the text of the labels that have some
+#[derive(Debug, Default)]
+pub struct ScreenProjectionSystem;
+
+impl<'a> System<'a> for ScreenProjectionSystem {
+    type SystemData = (
+        Entities<'a>,
+        Write<'a, Overlay>,
+        ReadStorage<'a, ActiveCamera>,
+        ReadStorage<'a, Camera>,
+        ReadStorage<'a, Viewport>,
+        ReadStorage<'a, GlobalTransform>,
+        ReadStorage<'a, ScreenLabel>,
+        WriteStorage<'a, ScreenPosition>,
+    );
+
+    fn run(
+        &mut self,
+        (
+            entities,
+            mut overlay,
+            active_cameras,
+            cameras,
+            viewports,
+            globals,
+            labels,
+            mut positions,
+        ): Self::SystemData,
+    ) {
+        // Entities that are no longer labels keep no stale position
+        let stale = (&entities, !&labels, &positions)
+            .join()
+            .map(|(entity, _, _)| entity)
+            .collect::<Vec<_>>();
+        for entity in stale {
+            positions.remove(entity);
+        }
+
+        let camera = (&cameras, &globals, &active_cameras, viewports.maybe())
+            .join()
+            .next()
+            .map(|(camera, global, _, viewport)| {
+                (
+                    Matrix4::from(camera.projection()) * global.to_view_matrix(),
+                    viewport.cloned().unwrap_or_default(),
+                )
+            });
+
+        let (view_projection, viewport) = match camera {
+            Some(camera) => camera,
+            None => {
+                positions.clear();
+                return;
+            }
+        };
+
+        let mut texts = Vec::new();
+        for (entity, label, global) in (&entities, &labels, &globals).join() {
+            let point = Point3::from(global.translation() + label.offset);
+            let position = project_to_screen(&view_projection, &viewport, &point, label.margin);
+            positions.insert(entity, position).unwrap();
+
+            if let Some(text) = &label.text {
+                texts.push((text, position));
+            }
+        }
+
+        // Nothing has been drawn yet to know the size of the screen from
+        let dimensions = overlay.dimensions();
+        if dimensions[0] == 0.0 || dimensions[1] == 0.0 {
+            return;
+        }
+
+        // Far labels first, so the near ones are drawn over them
+        texts.sort_by(|(_, a), (_, b)| {
+            b.distance
+                .partial_cmp(&a.distance)
+                .unwrap_or(Ordering::Equal)
+        });
+        for (text, position) in texts {
+            draw_label(&mut overlay, text, &position, dimensions);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{project_to_screen, screen_ray};
+    use crate::renderer::camera::{Camera, Viewport};
+    use nalgebra::{Matrix4, Point3, Vector2};
+
+    #[test]
+    fn clamp_to_view() {
+        let camera = Camera::with_clip_planes(1.0, std::f32::consts::FRAC_PI_2, 0.1, Some(100.0));
+        let proj = Matrix4::from(camera.projection());
+        let viewport = Viewport::new(0.5, 0.0, 0.5, 1.0);
+
+        // Straight ahead is the center of the viewport
+        let ahead = project_to_screen(&proj, &viewport, &Point3::new(0.0, 0.0, -10.0), 0.0);
+        assert!((ahead.position.x - 0.75).abs() < 1e-4);
+        assert!((ahead.position.y - 0.5).abs() < 1e-4);
+        assert!((ahead.distance - 10.0).abs() < 1e-4);
+        assert!(!ahead.clamped && !ahead.behind);
+
+        // Up in the world is up on the screen
+        let up = project_to_screen(&proj, &viewport, &Point3::new(0.0, 5.0, -10.0), 0.0);
+        assert!((up.position.y - 0.25).abs() < 1e-4);
+
+        // Far to the right is clamped to the right edge, keeping the direction
+        let right = project_to_screen(&proj, &viewport, &Point3::new(40.0, 0.0, -10.0), 0.1);
+        assert!(right.clamped && !right.behind);
+        assert!((right.position.x - 0.95).abs() < 1e-4);
+        assert!((right.position.y - 0.5).abs() < 1e-4);
+
+        // Behind and to the right is still on the right edge, not mirrored to the left
+        let behind = project_to_screen(&proj, &viewport, &Point3::new(1.0, 0.0, 10.0), 0.0);
+        assert!(behind.clamped && behind.behind);
+        assert!((behind.position.x - 1.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn ray_through_projection() {
+        let camera = Camera::with_clip_planes(1.5, std::f32::consts::FRAC_PI_3, 0.1, None);
+        let proj = Matrix4::from(camera.projection());
+        let viewport = Viewport::new(0.0, 0.5, 1.0, 0.5);
+
+        let point = Point3::new(2.0, -1.0, -8.0);
+        let projected = project_to_screen(&proj, &viewport, &point, 0.0);
+        let ray = screen_ray(&proj, &viewport, &projected.position).unwrap();
+
+        // The ray starts at the camera and goes through the point
+        let toi = (point - ray.origin).norm();
+        assert!((ray.point_at(toi) - point).norm() < 1e-3);
+
+        let center = screen_ray(&proj, &viewport, &Vector2::new(0.5, 0.75)).unwrap();
+        assert!((center.dir.z + 1.0).abs() < 1e-4);
+    }
+}
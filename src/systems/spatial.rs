@@ -0,0 +1,230 @@
+use crate::{
+    components::GlobalTransform,
+    renderer::{culling::Frustum, geometry::Bounds},
+    resources::DirtyEntities,
+};
+use nalgebra::Vector3;
+use ncollide3d::{
+    bounding_volume::{BoundingSphere, AABB},
+    partitioning::{DBVTLeaf, DBVTLeafId, VisitStatus, Visitor, BVH, DBVT},
+    query::{
+        visitors::{BoundingVolumeInterferencesCollector, RayInterferencesCollector},
+        Ray,
+    },
+};
+use specs::prelude::*;
+use std::collections::HashMap;
+
+/// An entity in the tree, with the bounds it was inserted with
+struct Indexed {
+    leaf: DBVTLeafId,
+    local: AABB<f32>,
+    world: AABB<f32>,
+}
+
+/// Resource with a bounding volume tree over the world space boxes of every entity with Bounds
+///
+/// The queries only find the entities whose box might be hit, which are then tested exactly,
+/// instead of testing every entity in the world. The SpatialIndexSystem keeps it up to date, as
+/// of the last frame.
+pub struct SpatialIndex {
+    tree: DBVT<f32, Entity, AABB<f32>>,
+    entities: HashMap<Entity, Indexed>,
+}
+
+impl SpatialIndex {
+    pub fn new() -> Self {
+        Self {
+            tree: DBVT::new(),
+            entities: HashMap::new(),
+        }
+    }
+
+    /// Entities whose box the ray passes through
+    pub fn ray(&self, ray: &Ray<f32>) -> Vec<Entity> {
+        let mut hits = Vec::new();
+        self.tree
+            .visit(&mut RayInterferencesCollector::new(ray, &mut hits));
+        hits
+    }
+
+    /// Entities whose box overlaps the sphere
+    pub fn sphere(&self, sphere: &BoundingSphere<f32>) -> Vec<Entity> {
+        let center = sphere.center();
+        let radius = sphere.radius();
+        let aabb = AABB::new(
+            center - Vector3::repeat(radius),
+            center + Vector3::repeat(radius),
+        );
+
+        let mut hits = Vec::new();
+        self.tree
+            .visit(&mut BoundingVolumeInterferencesCollector::new(
+                &aabb, &mut hits,
+            ));
+
+        // The box around the sphere overlaps more than the sphere itself does
+        hits.retain(|entity| {
+            let world = &self.entities[entity].world;
+            let closest = center
+                .coords
+                .sup(&world.mins().coords)
+                .inf(&world.maxs().coords);
+            (closest - center.coords).norm() <= radius
+        });
+        hits
+    }
+
+    /// Entities whose box might be inside the frustum
+    pub fn frustum(&self, frustum: &Frustum) -> Vec<Entity> {
+        let mut hits = Vec::new();
+        self.tree.visit(&mut FrustumCollector {
+            frustum,
+            hits: &mut hits,
+        });
+        hits
+    }
+
+    /// Adds the entity, or moves it if it is in the tree already
+    fn insert(&mut self, entity: Entity, local: AABB<f32>, world: AABB<f32>) {
+        self.remove(entity);
+
+        let leaf = self.tree.insert(DBVTLeaf::new(world.clone(), entity));
+        self.entities.insert(entity, Indexed { leaf, local, world });
+    }
+
+    fn remove(&mut self, entity: Entity) {
+        if let Some(indexed) = self.entities.remove(&entity) {
+            self.tree.remove(indexed.leaf);
+        }
+    }
+}
+
+impl Default for SpatialIndex {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Collects the entities of the leaves whose bounding sphere intersects a frustum
+struct FrustumCollector<'a> {
+    frustum: &'a Frustum,
+    hits: &'a mut Vec<Entity>,
+}
+
+impl<'a> Visitor<Entity, AABB<f32>> for FrustumCollector<'a> {
+    fn visit(&mut self, aabb: &AABB<f32>, entity: Option<&Entity>) -> VisitStatus {
+        let sphere = BoundingSphere::new(aabb.center(), aabb.half_extents().norm());
+        if !self.frustum.intersects_sphere(&sphere) {
+            return VisitStatus::Stop;
+        }
+
+        if let Some(entity) = entity {
+            self.hits.push(*entity);
+        }
+
+        VisitStatus::Continue
+    }
+}
+
+/// Keeps the SpatialIndex in sync with the Bounds and GlobalTransforms in the world
+///
+/// Has to run after the TransformSystem, whose DirtyEntities tell which entities moved. Entities
+/// are also reinserted when their Bounds change, like when their mesh is reloaded.
+#[derive(Debug, Default)]
+pub struct SpatialIndexSystem;
+
+impl<'a> System<'a> for SpatialIndexSystem {
+    type SystemData = (
+        Entities<'a>,
+        Read<'a, DirtyEntities>,
+        Write<'a, SpatialIndex>,
+        ReadStorage<'a, Bounds>,
+        ReadStorage<'a, GlobalTransform>,
+    );
+
+    fn run(&mut self, (entities, dirty_entities, mut index, bounds, globals): Self::SystemData) {
+        // Entities that were deleted, or lost their bounds
+        let removed = index
+            .entities
+            .keys()
+            .filter(|entity| {
+                !entities.is_alive(**entity)
+                    || !bounds.contains(**entity)
+                    || !globals.contains(**entity)
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+        for entity in removed {
+            index.remove(entity);
+        }
+
+        for (entity, bounds, global) in (&entities, &bounds, &globals).join() {
+            let changed = match index.entities.get(&entity) {
+                Some(indexed) => {
+                    dirty_entities.dirty.contains(entity.id()) || indexed.local != bounds.aabb
+                }
+                None => true,
+            };
+
+            if changed {
+                index.insert(entity, bounds.aabb.clone(), bounds.world_aabb(global));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::SpatialIndex;
+    use crate::renderer::{camera::Camera, culling::Frustum};
+    use nalgebra::{Matrix4, Point3, Vector3};
+    use ncollide3d::{
+        bounding_volume::{BoundingSphere, AABB},
+        query::Ray,
+    };
+    use specs::prelude::*;
+
+    fn cube(center: Point3<f32>) -> AABB<f32> {
+        AABB::new(center - Vector3::repeat(0.5), center + Vector3::repeat(0.5))
+    }
+
+    #[test]
+    fn queries() {
+        let mut world = World::new();
+        let mut index = SpatialIndex::new();
+
+        let ahead = world.create_entity().build();
+        let behind = world.create_entity().build();
+        let above = world.create_entity().build();
+
+        let local = cube(Point3::origin());
+        index.insert(ahead, local.clone(), cube(Point3::new(0.0, 0.0, -5.0)));
+        index.insert(behind, local.clone(), cube(Point3::new(0.0, 0.0, 5.0)));
+        index.insert(above, local.clone(), cube(Point3::new(0.0, 10.0, -5.0)));
+
+        let ray = Ray::new(Point3::origin(), -Vector3::z());
+        assert_eq!(index.ray(&ray), vec![ahead]);
+
+        let sphere = BoundingSphere::new(Point3::new(0.0, 0.0, 6.0), 1.0);
+        assert_eq!(index.sphere(&sphere), vec![behind]);
+
+        // Overlapping the corner of the box around the sphere, but not the sphere
+        let sphere = BoundingSphere::new(Point3::new(1.2, 1.2, -3.8), 1.0);
+        assert!(index.sphere(&sphere).is_empty());
+
+        let camera = Camera::with_clip_planes(1.0, 1.0, 0.1, Some(100.0));
+        let frustum = Frustum::from_matrix(&Matrix4::from(camera.projection()));
+        assert_eq!(index.frustum(&frustum), vec![ahead]);
+
+        // Moving an entity replaces its leaf
+        index.insert(behind, local.clone(), cube(Point3::new(0.0, 0.0, -20.0)));
+        let mut visible = index.frustum(&frustum);
+        visible.sort();
+        assert_eq!(visible, vec![ahead, behind]);
+        assert_eq!(index.entities.len(), 3);
+
+        index.remove(ahead);
+        assert_eq!(index.ray(&ray), vec![behind]);
+    }
+}
@@ -0,0 +1,284 @@
+use crate::resources::Time;
+use float_duration::TimePoint;
+use specs::prelude::*;
+use std::{mem, time::Instant};
+
+/// Most simulation steps taken in one frame, so a stall doesn't leave the simulation ever
+/// further behind trying to catch up
+const MAX_STEPS: u32 = 8;
+
+/// The stages of a frame, dispatched in this order
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Stage {
+    /// Time, window events and player input
+    Input,
+    /// Everything that moves the game forward, like controllers and the placement tools
+    Simulation,
+    /// Derived state, like the hierarchy and global transforms
+    PostSimulation,
+    /// Rendering and frame statistics
+    Render,
+}
+
+impl Stage {
+    pub const ALL: [Stage; 4] = [
+        Stage::Input,
+        Stage::Simulation,
+        Stage::PostSimulation,
+        Stage::Render,
+    ];
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// Resource deciding which stages are dispatched
+///
+/// Pausing the game is disabling the Simulation stage, while a loading screen would only keep
+/// Input and Render running.
+#[derive(Debug, Clone)]
+pub struct EnabledStages([bool; 4]);
+
+impl EnabledStages {
+    pub fn is_enabled(&self, stage: Stage) -> bool {
+        self.0[stage.index()]
+    }
+
+    pub fn set(&mut self, stage: Stage, enabled: bool) {
+        self.0[stage.index()] = enabled;
+    }
+}
+
+impl Default for EnabledStages {
+    fn default() -> Self {
+        EnabledStages([true; 4])
+    }
+}
+
+/// Accumulates the time between frames into simulation steps of a fixed length
+///
+/// Every whole step runs the stages before Render once, which can be none or several times a
+/// frame. What is left over is how far the frame is towards the next step, see Time::alpha.
+#[derive(Debug, Clone)]
+pub struct FixedStep {
+    step: f32,
+    accumulator: f32,
+    last_frame: Option<Instant>,
+}
+
+impl FixedStep {
+    pub fn new(step: f32) -> Self {
+        Self {
+            step,
+            accumulator: 0.0,
+            last_frame: None,
+        }
+    }
+
+    /// Adds the seconds that have passed, and returns how many whole steps to take for them
+    pub fn advance(&mut self, elapsed: f32) -> u32 {
+        self.accumulator += elapsed;
+        let steps = (self.accumulator / self.step).floor();
+        self.accumulator -= steps * self.step;
+
+        // Steps over the cap are dropped, the simulation slows down instead
+        (steps as u32).min(MAX_STEPS)
+    }
+
+    /// How far the accumulated time is towards the next step, from 0 to 1
+    pub fn alpha(&self) -> f32 {
+        self.accumulator / self.step
+    }
+
+    /// Seconds since the last frame, a single step on the first one
+    fn elapsed(&mut self) -> f32 {
+        let now = Instant::now();
+        let elapsed = match self.last_frame {
+            Some(last_frame) => now.float_duration_since(last_frame).unwrap().as_seconds() as f32,
+            None => self.step,
+        };
+        self.last_frame = Some(now);
+        elapsed
+    }
+}
+
+/// Builds a StagedDispatcher, one DispatcherBuilder per stage
+///
+/// Dependencies only work between systems in the same stage, earlier stages always finish
+/// before later ones start.
+pub struct StagedDispatcherBuilder<'a, 'b> {
+    stages: Vec<(Stage, DispatcherBuilder<'a, 'b>)>,
+}
+
+impl<'a, 'b> StagedDispatcherBuilder<'a, 'b> {
+    pub fn new() -> Self {
+        Self {
+            stages: Stage::ALL
+                .iter()
+                .map(|stage| (*stage, DispatcherBuilder::new()))
+                .collect(),
+        }
+    }
+
+    /// Sets up the systems of a stage
+    pub fn with_stage<F>(mut self, stage: Stage, f: F) -> Self
+    where
+        F: FnOnce(DispatcherBuilder<'a, 'b>) -> DispatcherBuilder<'a, 'b>,
+    {
+        let builder = &mut self.stages[stage.index()].1;
+        *builder = f(mem::replace(builder, DispatcherBuilder::new()));
+        self
+    }
+
+    pub fn build(self) -> StagedDispatcher<'a, 'b> {
+        StagedDispatcher {
+            stages: self
+                .stages
+                .into_iter()
+                .map(|(stage, builder)| (stage, builder.build()))
+                .collect(),
+        }
+    }
+}
+
+impl<'a, 'b> Default for StagedDispatcherBuilder<'a, 'b> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Dispatches the systems of every enabled stage, one stage after the other
+pub struct StagedDispatcher<'a, 'b> {
+    stages: Vec<(Stage, Dispatcher<'a, 'b>)>,
+}
+
+impl<'a, 'b> StagedDispatcher<'a, 'b> {
+    pub fn setup(&mut self, res: &mut Resources) {
+        res.entry::<EnabledStages>()
+            .or_insert_with(EnabledStages::default);
+
+        for (_, dispatcher) in &mut self.stages {
+            dispatcher.setup(res);
+        }
+    }
+
+    /// Dispatches every stage enabled in the EnabledStages resource
+    pub fn dispatch(&mut self, res: &Resources) {
+        for (stage, dispatcher) in &mut self.stages {
+            // Read for every stage, so a stage can toggle the ones after it
+            if res.fetch::<EnabledStages>().is_enabled(*stage) {
+                dispatcher.dispatch(res);
+            }
+        }
+    }
+
+    /// Dispatches the stages before Render once for every whole step that has passed, and Render
+    /// once with the rest of the step as Time::alpha
+    ///
+    /// The world is maintained after every step, so the next one sees what it created.
+    pub fn dispatch_fixed(&mut self, world: &mut World, fixed: &mut FixedStep) {
+        let steps = fixed.advance(fixed.elapsed());
+
+        for _ in 0..steps {
+            for (stage, dispatcher) in &mut self.stages {
+                if *stage != Stage::Render && world.res.fetch::<EnabledStages>().is_enabled(*stage)
+                {
+                    dispatcher.dispatch(&world.res);
+                }
+            }
+            world.maintain();
+        }
+
+        world.write_resource::<Time>().set_alpha(fixed.alpha());
+
+        if world
+            .read_resource::<EnabledStages>()
+            .is_enabled(Stage::Render)
+        {
+            self.dispatch_stage(Stage::Render, &world.res);
+        }
+    }
+
+    /// Dispatches a single stage, whether it is enabled or not
+    pub fn dispatch_stage(&mut self, stage: Stage, res: &Resources) {
+        self.stages[stage.index()].1.dispatch(res);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{EnabledStages, FixedStep, Stage, StagedDispatcherBuilder, MAX_STEPS};
+    use crate::resources::Time;
+    use specs::prelude::*;
+
+    #[derive(Default)]
+    struct Count(u32);
+
+    struct CountSystem;
+
+    impl<'a> System<'a> for CountSystem {
+        type SystemData = Write<'a, Count>;
+
+        fn run(&mut self, mut count: Self::SystemData) {
+            count.0 += 1;
+        }
+    }
+
+    #[test]
+    fn disabled_stages_are_skipped() {
+        let mut world = World::new();
+        let mut dispatcher = StagedDispatcherBuilder::new()
+            .with_stage(Stage::Simulation, |builder| {
+                builder.with(CountSystem, "count", &[])
+            })
+            .build();
+        dispatcher.setup(&mut world.res);
+
+        dispatcher.dispatch(&world.res);
+        assert_eq!(world.read_resource::<Count>().0, 1);
+
+        world
+            .write_resource::<EnabledStages>()
+            .set(Stage::Simulation, false);
+        dispatcher.dispatch(&world.res);
+        assert_eq!(world.read_resource::<Count>().0, 1);
+
+        // Still runs when asked for directly
+        dispatcher.dispatch_stage(Stage::Simulation, &world.res);
+        assert_eq!(world.read_resource::<Count>().0, 2);
+    }
+
+    #[test]
+    fn fixed_steps() {
+        let mut fixed = FixedStep::new(0.25);
+
+        assert_eq!(fixed.advance(0.1), 0);
+        assert!((fixed.alpha() - 0.4).abs() < 1e-5);
+
+        // The leftover carries over into the next frame
+        assert_eq!(fixed.advance(0.45), 2);
+        assert!((fixed.alpha() - 0.2).abs() < 1e-5);
+
+        // A stall only catches up so far
+        assert_eq!(fixed.advance(10.0), MAX_STEPS);
+        assert!(fixed.alpha() < 1.0);
+    }
+
+    #[test]
+    fn first_fixed_frame_takes_a_step() {
+        let mut world = World::new();
+        world.add_resource(Time::default());
+        let mut dispatcher = StagedDispatcherBuilder::new()
+            .with_stage(Stage::Simulation, |builder| {
+                builder.with(CountSystem, "count", &[])
+            })
+            .build();
+        dispatcher.setup(&mut world.res);
+
+        let mut fixed = FixedStep::new(1.0);
+        dispatcher.dispatch_fixed(&mut world, &mut fixed);
+        assert_eq!(world.read_resource::<Count>().0, 1);
+        assert!(world.read_resource::<Time>().alpha() < 1.0);
+    }
+}
@@ -0,0 +1,206 @@
+use crate::{
+    renderer::geometry::{MeshBuilder, PendingMesh},
+    resources::{ActionEvent, ActionEvents, Time, WindowTitle},
+    systems::{
+        assets::LoadMesh,
+        stages::{EnabledStages, Stage},
+    },
+};
+use log::info;
+use shrev::ReaderId;
+use specs::prelude::*;
+
+/// Characters cycled through while loading
+const SPINNER: &[char] = &['|', '/', '-', '\\'];
+/// Seconds each spinner character is shown
+const SPINNER_INTERVAL: f32 = 0.1;
+
+/// Resource holding what the engine is doing as a whole
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EngineState {
+    /// Assets are still being loaded, nothing is simulated
+    Loading,
+    Running,
+    /// The world is frozen, but still rendered
+    Paused,
+    /// The simulation runs, along with the editing tools
+    Editor,
+}
+
+impl EngineState {
+    /// The stages dispatched in this state
+    fn runs_stage(self, stage: Stage) -> bool {
+        match (self, stage) {
+            (EngineState::Loading, Stage::Simulation) => false,
+            (EngineState::Paused, Stage::Simulation) => false,
+            _ => true,
+        }
+    }
+}
+
+impl Default for EngineState {
+    fn default() -> Self {
+        EngineState::Loading
+    }
+}
+
+/// Runs a system only while the engine is in one of the given states
+///
+/// Use `InStates::new(system, &[EngineState::Running])` in place of the system when adding it
+/// to a dispatcher.
+pub struct InStates<S> {
+    system: S,
+    states: Vec<EngineState>,
+}
+
+impl<S> InStates<S> {
+    pub fn new(system: S, states: &[EngineState]) -> Self {
+        Self {
+            system,
+            states: states.to_vec(),
+        }
+    }
+}
+
+impl<'a, S> System<'a> for InStates<S>
+where
+    S: System<'a>,
+    S::SystemData: SystemData<'a>,
+{
+    type SystemData = (Read<'a, EngineState>, S::SystemData);
+
+    fn run(&mut self, (state, data): Self::SystemData) {
+        if self.states.contains(&state) {
+            self.system.run(data);
+        }
+    }
+
+    fn setup(&mut self, res: &mut Resources) {
+        Read::<EngineState>::setup(res);
+        self.system.setup(res);
+    }
+}
+
+/// Moves the engine between states, and enables the dispatcher stages each state needs
+///
+/// Loading lasts until every LoadMesh has been decoded and every MeshBuilder, including those still
+/// being generated, turned into a mesh, with a spinner in the window title in the meantime. After that the "pause" and "toggle_editor" actions switch
+/// between Running, Paused and Editor.
+#[derive(Debug, Default)]
+pub struct EngineStateSystem {
+    spinner_time: f32,
+    action_read_id: Option<ReaderId<ActionEvent>>,
+}
+
+impl<'a> System<'a> for EngineStateSystem {
+    type SystemData = (
+        Read<'a, Time>,
+        Read<'a, ActionEvents>,
+        ReadStorage<'a, LoadMesh>,
+        ReadStorage<'a, MeshBuilder>,
+        ReadStorage<'a, PendingMesh>,
+        Write<'a, EngineState>,
+        Write<'a, EnabledStages>,
+        Write<'a, WindowTitle>,
+    );
+
+    fn run(
+        &mut self,
+        (time, action_events, loads, mesh_builders, pending, mut state, mut stages, mut title): Self::SystemData,
+    ) {
+        let previous = *state;
+
+        // Loading
+        // -----------------------------------------------------------------------------------------------------
+        if *state == EngineState::Loading {
+            let remaining = (&loads).join().count()
+                + (&mesh_builders).join().count()
+                + (&pending).join().count();
+
+            if remaining == 0 {
+                *state = EngineState::Running;
+            } else {
+                self.spinner_time += time.real_delta();
+                let frame = (self.spinner_time / SPINNER_INTERVAL) as usize % SPINNER.len();
+
+                title.0 = Some(format!(
+                    "Loading {} ({} meshes left)",
+                    SPINNER[frame], remaining
+                ));
+            }
+        }
+
+        // Actions
+        // -----------------------------------------------------------------------------------------------------
+        for ActionEvent(action) in action_events.read(self.action_read_id.as_mut().unwrap()) {
+            *state = match (action.as_str(), *state) {
+                // Nothing can interrupt loading
+                (_, EngineState::Loading) => EngineState::Loading,
+                ("pause", EngineState::Paused) => EngineState::Running,
+                ("pause", _) => EngineState::Paused,
+                ("toggle_editor", EngineState::Editor) => EngineState::Running,
+                ("toggle_editor", _) => EngineState::Editor,
+                (_, state) => state,
+            };
+        }
+
+        if *state != previous {
+            info!("Engine state: {:?} -> {:?}", previous, *state);
+        }
+
+        for stage in Stage::ALL.iter() {
+            stages.set(*stage, state.runs_stage(*stage));
+        }
+    }
+
+    fn setup(&mut self, res: &mut Resources) {
+        Self::SystemData::setup(res);
+
+        let mut actions = res.fetch_mut::<ActionEvents>();
+        self.action_read_id = Some(actions.register_reader());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{EngineState, InStates};
+    use specs::prelude::*;
+
+    #[derive(Default)]
+    struct Count(u32);
+
+    struct CountSystem;
+
+    impl<'a> System<'a> for CountSystem {
+        type SystemData = Write<'a, Count>;
+
+        fn run(&mut self, mut count: Self::SystemData) {
+            count.0 += 1;
+        }
+    }
+
+    #[test]
+    fn runs_only_in_given_states() {
+        let mut world = World::new();
+        let mut dispatcher = DispatcherBuilder::new()
+            .with(
+                InStates::new(CountSystem, &[EngineState::Running, EngineState::Editor]),
+                "count",
+                &[],
+            )
+            .build();
+        dispatcher.setup(&mut world.res);
+
+        // Starts out loading
+        dispatcher.dispatch(&world.res);
+        assert_eq!(world.read_resource::<Count>().0, 0);
+
+        *world.write_resource::<EngineState>() = EngineState::Editor;
+        dispatcher.dispatch(&world.res);
+        assert_eq!(world.read_resource::<Count>().0, 1);
+
+        *world.write_resource::<EngineState>() = EngineState::Paused;
+        dispatcher.dispatch(&world.res);
+        assert_eq!(world.read_resource::<Count>().0, 1);
+    }
+}
@@ -0,0 +1,99 @@
+use crate::{
+    renderer::{overlay::Overlay, settings::RenderSettings, stats::RenderStats},
+    resources::{Time, WindowTitle},
+};
+use specs::prelude::*;
+
+/// How often the window title is updated, in seconds
+const UPDATE_INTERVAL: f32 = 1.0;
+
+/// Height of the text of the stats overlay, in pixels
+const TEXT_SIZE: f32 = 16.0;
+
+/// Distance of the stats overlay from the corner of the window, and of its text from the edges of
+/// its background, in pixels
+const MARGIN: f32 = 8.0;
+
+/// Shows frame statistics in the window title
+///
+/// Averages the frame time over a second and then writes FPS, frame time, entity count and what
+/// the renderer drew to the WindowTitle resource. With `RenderSettings::show_stats`, the frame
+/// time and what was drawn in each pass are shown over the scene as well.
+#[derive(Debug, Default)]
+pub struct FrameStatsSystem {
+    elapsed: f32,
+    frames: u32,
+    /// The text of the overlay, as of the last update
+    overlay_text: String,
+}
+
+impl<'a> System<'a> for FrameStatsSystem {
+    type SystemData = (
+        Entities<'a>,
+        Read<'a, Time>,
+        Read<'a, RenderStats>,
+        Read<'a, RenderSettings>,
+        Write<'a, Overlay>,
+        Write<'a, WindowTitle>,
+    );
+
+    fn run(&mut self, (entities, time, stats, settings, mut overlay, mut title): Self::SystemData) {
+        self.elapsed += time.real_delta();
+        self.frames += 1;
+
+        // The overlay is cleared every frame, so it is drawn every frame
+        if settings.show_stats && !self.overlay_text.is_empty() {
+            let position = [MARGIN * 2.0, MARGIN * 2.0];
+            let color = [1.0, 1.0, 1.0, 1.0];
+
+            let [width, height] = overlay.measure_text(&self.overlay_text, TEXT_SIZE);
+            overlay.rect(
+                [MARGIN, MARGIN],
+                [width + MARGIN * 2.0, height + MARGIN * 2.0],
+                [0.0, 0.0, 0.0, 0.6],
+            );
+            overlay.text(&self.overlay_text, position, TEXT_SIZE, color);
+        }
+
+        if self.elapsed < UPDATE_INTERVAL {
+            return;
+        }
+
+        let fps = self.frames as f32 / self.elapsed;
+        let frame_time = self.elapsed * 1000.0 / self.frames as f32;
+        let entity_count = (&entities).join().count();
+
+        title.0 = Some(format!(
+            "vkengine | {:.0} fps | {:.2} ms | gpu {:.2} ms (cull {:.2}, main {:.2}, shafts {:.2}, post {:.2}) | {} entities | {} meshes, {} batched, {} lights, {} triangles | {:.1} MiB in {} buffers, {} blocks {:.0}% fragmented | {} swapchain images",
+            fps,
+            frame_time,
+            stats.gpu_times.total(),
+            stats.gpu_times.culling,
+            stats.gpu_times.main,
+            stats.gpu_times.light_shafts,
+            stats.gpu_times.post,
+            entity_count,
+            stats.meshes,
+            stats.batched_meshes,
+            stats.point_lights,
+            stats.triangles,
+            stats.memory.total_bytes() as f32 / (1024.0 * 1024.0),
+            stats.memory.buffers,
+            stats.memory.blocks,
+            stats.memory.fragmentation() * 100.0,
+            stats.swapchain_images,
+        ));
+
+        let mut lines = vec![format!(
+            "{:.0} fps, {:.2} ms, gpu {:.2} ms",
+            fps,
+            frame_time,
+            stats.gpu_times.total()
+        )];
+        lines.extend(stats.overlay_lines());
+        self.overlay_text = lines.join("\n");
+
+        self.elapsed = 0.0;
+        self.frames = 0;
+    }
+}
@@ -0,0 +1,243 @@
+use crate::{
+    components::GlobalTransform,
+    renderer::camera::ActiveCamera,
+    scene::{SceneHandle, Scenes},
+};
+use log::{info, warn};
+use specs::prelude::*;
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// The grid position of a chunk, along x and z
+pub type ChunkCoord = (i32, i32);
+
+/// How the ChunkStreamingSystem splits the world up
+#[derive(Debug, Clone)]
+pub struct StreamingSettings {
+    /// Directory of the chunk files, named "chunk_<x>_<z>.ron"
+    pub dir: PathBuf,
+    /// Width of a chunk along x and z
+    pub chunk_size: f32,
+    /// Chunks with any part closer to the camera than this are loaded
+    pub load_radius: f32,
+    /// Chunks are only unloaded once every part of them is further away than this, so moving back
+    /// and forth over the edge of the load radius doesn't load and unload them again every time
+    pub unload_radius: f32,
+}
+
+impl Default for StreamingSettings {
+    fn default() -> Self {
+        Self {
+            dir: PathBuf::from("chunks"),
+            chunk_size: 64.0,
+            load_radius: 128.0,
+            unload_radius: 160.0,
+        }
+    }
+}
+
+/// Loads the scene chunks around the active camera, and unloads those it has left behind
+///
+/// Every chunk is a scene file covering a square of the grid, with its entities at their world
+/// positions. The directory is only looked through once, so chunks added later are not found.
+/// Chunks are loaded additively through Scenes, which reads them in the background.
+pub struct ChunkStreamingSystem {
+    settings: StreamingSettings,
+    /// The chunk files in the directory
+    available: HashMap<ChunkCoord, PathBuf>,
+    loaded: HashMap<ChunkCoord, SceneHandle>,
+    /// Chunks whose files failed to load, not tried again until the camera has left them behind
+    failed: HashSet<ChunkCoord>,
+}
+
+impl ChunkStreamingSystem {
+    pub fn new(settings: StreamingSettings) -> Self {
+        let available = find_chunks(&settings.dir);
+        info!(
+            "Streaming {} chunks from {}",
+            available.len(),
+            settings.dir.display()
+        );
+
+        Self {
+            settings,
+            available,
+            loaded: HashMap::new(),
+            failed: HashSet::new(),
+        }
+    }
+}
+
+impl<'a> System<'a> for ChunkStreamingSystem {
+    type SystemData = (
+        ReadStorage<'a, ActiveCamera>,
+        ReadStorage<'a, GlobalTransform>,
+        Write<'a, Scenes>,
+    );
+
+    fn run(&mut self, (active_cameras, globals, mut scenes): Self::SystemData) {
+        if self.available.is_empty() {
+            return;
+        }
+
+        let position = match (&active_cameras, &globals).join().next() {
+            Some((_, global)) => global.translation(),
+            None => return,
+        };
+        let position = [position.x, position.z];
+        let size = self.settings.chunk_size;
+
+        // Chunks unloaded by anything else, like switching scenes, are loaded again, but not those
+        // that failed to load
+        let failed = &mut self.failed;
+        self.loaded.retain(|chunk, handle| {
+            if scenes.take_failed(*handle) {
+                failed.insert(*chunk);
+            }
+            scenes.contains(*handle)
+        });
+
+        let unload_radius = self.settings.unload_radius;
+        self.failed
+            .retain(|chunk| distance_to_chunk(position, *chunk, size) <= unload_radius);
+
+        let far = self
+            .loaded
+            .keys()
+            .filter(|chunk| distance_to_chunk(position, **chunk, size) > unload_radius)
+            .cloned()
+            .collect::<Vec<_>>();
+
+        for chunk in far {
+            let handle = self.loaded.remove(&chunk).unwrap();
+            scenes.remove(handle);
+        }
+
+        for chunk in chunks_within(position, self.settings.load_radius, size) {
+            if self.loaded.contains_key(&chunk) || self.failed.contains(&chunk) {
+                continue;
+            }
+
+            if let Some(path) = self.available.get(&chunk) {
+                let handle = scenes.add(path.clone());
+                self.loaded.insert(chunk, handle);
+            }
+        }
+    }
+}
+
+/// The chunk files in a directory, by their grid position
+fn find_chunks(dir: &Path) -> HashMap<ChunkCoord, PathBuf> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) => {
+            warn!("Failed to read chunks from {}: {}", dir.display(), err);
+            return HashMap::new();
+        }
+    };
+
+    entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter_map(|path| {
+            let name = path.file_name()?.to_str()?;
+            let chunk = parse_chunk_name(name)?;
+            Some((chunk, path))
+        })
+        .collect()
+}
+
+/// Reads the grid position from a file named "chunk_<x>_<z>.ron"
+fn parse_chunk_name(name: &str) -> Option<ChunkCoord> {
+    if !name.starts_with("chunk_") || !name.ends_with(".ron") {
+        return None;
+    }
+
+    let name = &name["chunk_".len()..name.len() - ".ron".len()];
+    let mut parts = name.split('_');
+    let x = parts.next()?.parse().ok()?;
+    let z = parts.next()?.parse().ok()?;
+
+    if parts.next().is_some() {
+        return None;
+    }
+
+    Some((x, z))
+}
+
+/// The chunk a position along x and z is in
+fn chunk_of(position: [f32; 2], size: f32) -> ChunkCoord {
+    (
+        (position[0] / size).floor() as i32,
+        (position[1] / size).floor() as i32,
+    )
+}
+
+/// Distance along x and z from a position to the closest point of a chunk, 0 inside of it
+fn distance_to_chunk(position: [f32; 2], chunk: ChunkCoord, size: f32) -> f32 {
+    let min = [chunk.0 as f32 * size, chunk.1 as f32 * size];
+    let dx = (min[0] - position[0])
+        .max(position[0] - (min[0] + size))
+        .max(0.0);
+    let dz = (min[1] - position[1])
+        .max(position[1] - (min[1] + size))
+        .max(0.0);
+
+    (dx * dx + dz * dz).sqrt()
+}
+
+/// Every chunk with a part closer to the position than the radius
+fn chunks_within(position: [f32; 2], radius: f32, size: f32) -> HashSet<ChunkCoord> {
+    let min = chunk_of([position[0] - radius, position[1] - radius], size);
+    let max = chunk_of([position[0] + radius, position[1] + radius], size);
+
+    let mut chunks = HashSet::new();
+    for x in min.0..=max.0 {
+        for z in min.1..=max.1 {
+            if distance_to_chunk(position, (x, z), size) <= radius {
+                chunks.insert((x, z));
+            }
+        }
+    }
+
+    chunks
+}
+
+#[cfg(test)]
+mod test {
+    use super::{chunk_of, chunks_within, distance_to_chunk, parse_chunk_name};
+
+    #[test]
+    fn chunk_names() {
+        assert_eq!(parse_chunk_name("chunk_0_0.ron"), Some((0, 0)));
+        assert_eq!(parse_chunk_name("chunk_-3_12.ron"), Some((-3, 12)));
+        assert_eq!(parse_chunk_name("chunk_1.ron"), None);
+        assert_eq!(parse_chunk_name("chunk_1_2_3.ron"), None);
+        assert_eq!(parse_chunk_name("chunk_1_2.gltf"), None);
+    }
+
+    #[test]
+    fn chunk_distances() {
+        assert_eq!(chunk_of([10.0, -10.0], 64.0), (0, -1));
+        assert_eq!(distance_to_chunk([10.0, 10.0], (0, 0), 64.0), 0.0);
+        assert_eq!(distance_to_chunk([10.0, 10.0], (1, 0), 64.0), 54.0);
+        assert_eq!(distance_to_chunk([-3.0, -4.0], (0, 0), 64.0), 5.0);
+    }
+
+    #[test]
+    fn hysteresis() {
+        let near = chunks_within([32.0, 32.0], 40.0, 64.0);
+        assert_eq!(near.len(), 5);
+        assert!(near.contains(&(0, 0)));
+        assert!(near.contains(&(-1, 0)));
+        assert!(!near.contains(&(1, 1)));
+
+        // Moving a little past the load radius keeps the chunk within the unload radius
+        let chunk = (1, 0);
+        let position = [20.0, 32.0];
+        assert!(distance_to_chunk(position, chunk, 64.0) > 40.0);
+        assert!(distance_to_chunk(position, chunk, 64.0) <= 50.0);
+    }
+}
@@ -1,36 +1,113 @@
 use crate::{
-    components::{GlobalTransform, Link, Transform},
+    components::{GlobalTransform, Link, PreviousGlobalTransform, Transform},
     resources::DirtyEntities,
 };
-use specs::prelude::*;
+use specs::{prelude::*, rayon::prelude::*};
 use specs_hierarchy::{Hierarchy, HierarchyEvent, Parent};
 
 /// Syncs Transform and GobalTransform per entity
 ///
 /// For every Transform, whether relative or absolute, there should be a GlobalTransform
 /// that contains the global transform for said Transform.
+///
+/// Roots are synced first. Then every subtree below a root is walked in hierarchy order, so each
+/// GlobalTransform is built from the already synced GlobalTransform of its parent. Subtrees are
+/// independent of each other and synced in parallel.
+///
+/// Entities that move get a PreviousGlobalTransform with where they were before, so the renderer
+/// can interpolate between steps.
 pub struct TransformSystem {
     transform_reader_id: Option<ReaderId<ComponentEvent>>,
     hierarchy_reader_id: Option<ReaderId<HierarchyEvent>>,
 }
 
+/// What is needed to sync a subtree, shared between the threads syncing them
+struct SubtreeSync<'s, 'a> {
+    hierarchy: &'s Hierarchy<Link>,
+    transforms: &'s ReadStorage<'a, Transform>,
+    globals: &'s WriteStorage<'a, GlobalTransform>,
+    dirty: &'s BitSet,
+}
+
+impl<'s, 'a> SubtreeSync<'s, 'a> {
+    /// Computes the new global transforms of an entity and its descendants
+    ///
+    /// Only entities that are dirty, or have a dirty ancestor, get a new global transform. The
+    /// rest just pass their current one down to their children.
+    fn sync(
+        &self,
+        entity: Entity,
+        parent_global: Option<&Transform>,
+        parent_changed: bool,
+        out: &mut Vec<(Entity, Transform)>,
+    ) {
+        let changed = parent_changed || self.dirty.contains(entity.id());
+
+        let global = match self.transforms.get(entity) {
+            Some(transform) if changed => {
+                let mut global = transform.clone();
+                if let Some(parent_global) = parent_global {
+                    global += parent_global.clone();
+                }
+
+                out.push((entity, global.clone()));
+                Some(global)
+            }
+            Some(_) => self.globals.get(entity).map(|global| global.global.clone()),
+            // Entities without a Transform pass their parent's along
+            None => parent_global.cloned(),
+        };
+
+        for child in self.hierarchy.children(entity) {
+            self.sync(*child, global.as_ref(), changed, out);
+        }
+    }
+}
+
 impl TransformSystem {
-    /// Add a GlobalTransform to any entity with a Transform component
-    fn add_globals(
+    /// Mark any entity with a Transform but no GlobalTransform as dirty, so it gets one
+    fn mark_new(
         entities: &Entities<'_>,
         transforms: &ReadStorage<'_, Transform>,
-        globals: &mut WriteStorage<'_, GlobalTransform>,
+        globals: &WriteStorage<'_, GlobalTransform>,
         dirty_entities: &mut Write<'_, DirtyEntities>,
     ) {
         (entities, transforms, !globals.mask().clone())
             .join()
-            .for_each(|(entity, transform, _)| {
-                globals
-                    .insert(entity, GlobalTransform::from(transform.clone()))
-                    .unwrap();
+            .for_each(|(entity, _, _)| {
                 dirty_entities.dirty.add(entity.id());
             });
     }
+
+    /// Writes new global transforms
+    ///
+    /// Each GlobalTransform is written once, so it only gets a single modification event
+    fn write_globals(
+        globals: &mut WriteStorage<'_, GlobalTransform>,
+        previous: &mut WriteStorage<'_, PreviousGlobalTransform>,
+        dirty_entities: &mut DirtyEntities,
+        changed: Vec<(Entity, Transform)>,
+    ) {
+        for (entity, global) in changed {
+            dirty_entities.dirty.add(entity.id());
+
+            match globals.get_mut(entity) {
+                Some(existing) => {
+                    let old = PreviousGlobalTransform {
+                        global: existing.global.clone(),
+                    };
+                    previous.insert(entity, old).unwrap();
+
+                    existing.global = global;
+                }
+                None => {
+                    globals
+                        .insert(entity, GlobalTransform::from(global))
+                        .unwrap();
+                }
+            }
+        }
+    }
 }
 
 impl<'a> System<'a> for TransformSystem {
@@ -41,14 +118,35 @@ impl<'a> System<'a> for TransformSystem {
         ReadStorage<'a, Link>,
         ReadStorage<'a, Transform>,
         WriteStorage<'a, GlobalTransform>,
+        WriteStorage<'a, PreviousGlobalTransform>,
     );
 
     fn run(
         &mut self,
-        (entities, mut dirty_entities, hierarchy, links, transforms, mut globals): Self::SystemData,
+        (
+            entities,
+            mut dirty_entities,
+            hierarchy,
+            links,
+            transforms,
+            mut globals,
+            mut previous,
+        ): Self::SystemData,
     ) {
-        // Add GlobalTransforms to entities with Transforms
-        Self::add_globals(&entities, &transforms, &mut globals, &mut dirty_entities);
+        // Every system that cares has seen last frame's changes by now
+        dirty_entities.dirty.clear();
+
+        // Previous transforms are only kept for entities that move in this step
+        let stale = (&entities, previous.mask())
+            .join()
+            .map(|(entity, _)| entity)
+            .collect::<Vec<_>>();
+        for entity in stale {
+            previous.remove(entity);
+        }
+
+        // Entities with Transforms need GlobalTransforms
+        Self::mark_new(&entities, &transforms, &globals, &mut dirty_entities);
 
         // Read events
         // Add new or modified entities to dirty bitset
@@ -65,67 +163,85 @@ impl<'a> System<'a> for TransformSystem {
             });
 
         // If there are new or different parents, we need to resync
+        // Orphaned children are dealt with by the HierarchyCleanupSystem
         hierarchy
             .changed()
             .read(self.hierarchy_reader_id.as_mut().unwrap())
             .for_each(|event| match *event {
-                HierarchyEvent::Removed(entity) => {
-                    let _ = entities.delete(entity);
-                }
-                HierarchyEvent::Modified(entity) => {
+                HierarchyEvent::Removed(entity) | HierarchyEvent::Modified(entity) => {
                     dirty_entities.dirty.add(entity.id());
                 }
             });
 
-        // Children of dirty entities are also dirty and need their transforms updated
-        (
+        // Roots
+        // -----------------------------------------------------------------------------------------------------
+        let roots = (
             &entities,
             &transforms,
-            &globals,
-            &dirty_entities.dirty.clone(),
+            &dirty_entities.dirty,
+            !links.mask().clone(),
         )
-            .join()
-            .for_each(|(entity, _, _, _)| {
-                let children = hierarchy.all_children(entity);
-                dirty_entities.dirty |= &children;
-            });
-
-        // Sync all dirty entities and their children
-        (&entities, &transforms, &mut globals, &dirty_entities.dirty)
-            .join()
-            .for_each(|(entity, transform, global, _)| {
-                global.global = transform.clone();
-
-                let mut parent_entity = entity;
-                while let Some(link) = links.get(parent_entity) {
-                    parent_entity = link.parent_entity();
-                    if let Some(p_trans) = transforms.get(parent_entity) {
-                        global.global += p_trans.clone();
-                    }
+            .par_join()
+            .map(|(entity, transform, _, _)| (entity, transform.clone()))
+            .collect::<Vec<_>>();
+
+        Self::write_globals(&mut globals, &mut previous, &mut dirty_entities, roots);
+
+        // Children
+        // -----------------------------------------------------------------------------------------------------
+        // The children of roots, each of which is the top of an independent subtree.
+        // Hierarchy::all is sorted so parents always come before their children
+        let subtrees = hierarchy
+            .all()
+            .iter()
+            .filter_map(|entity| {
+                let parent = links.get(*entity)?.parent_entity();
+                if links.contains(parent) {
+                    None
+                } else {
+                    Some((*entity, parent))
                 }
-            });
+            })
+            .collect::<Vec<_>>();
+
+        let changed = {
+            let sync = SubtreeSync {
+                hierarchy: &hierarchy,
+                transforms: &transforms,
+                globals: &globals,
+                dirty: &dirty_entities.dirty,
+            };
+
+            subtrees
+                .par_iter()
+                .flat_map(|(entity, parent)| {
+                    let parent_global = sync.globals.get(*parent).map(|global| &global.global);
+                    let parent_changed = sync.dirty.contains(parent.id());
+
+                    let mut out = Vec::new();
+                    sync.sync(*entity, parent_global, parent_changed, &mut out);
+                    out
+                })
+                .collect::<Vec<_>>()
+        };
+
+        Self::write_globals(&mut globals, &mut previous, &mut dirty_entities, changed);
+
+        // Kept until the renderer takes them, however many steps that is
+        let DirtyEntities { dirty, moved } = &mut *dirty_entities;
+        *moved |= &*dirty;
     }
 
     fn setup(&mut self, res: &mut Resources) {
         Self::SystemData::setup(res);
 
         // Register readers
-        {
-            let mut transforms = WriteStorage::<Transform>::fetch(res);
-            let mut hierarchy = res.fetch_mut::<Hierarchy<Link>>();
-
-            self.transform_reader_id = Some(transforms.register_reader());
-            self.hierarchy_reader_id = Some(hierarchy.track());
-        }
+        // GlobalTransforms are added on the first run, along with everything else that is new
+        let mut transforms = WriteStorage::<Transform>::fetch(res);
+        let mut hierarchy = res.fetch_mut::<Hierarchy<Link>>();
 
-        // Add GlobalTransforms
-        {
-            let entities = Entities::fetch(res);
-            let transforms = ReadStorage::<Transform>::fetch(res);
-            let mut globals = WriteStorage::<GlobalTransform>::fetch(res);
-            let mut dirty_entities = Write::<DirtyEntities>::fetch(res);
-            Self::add_globals(&entities, &transforms, &mut globals, &mut dirty_entities);
-        }
+        self.transform_reader_id = Some(transforms.register_reader());
+        self.hierarchy_reader_id = Some(hierarchy.track());
     }
 }
 
@@ -141,12 +257,18 @@ impl Default for TransformSystem {
 #[cfg(test)]
 mod test {
     use crate::{
-        components::{GlobalTransform, Link, Transform},
+        components::{
+            DirtyEntities, GlobalTransform, Link, PreviousGlobalTransform, Transform,
+            TransformQuery, TransformStorageExt,
+        },
         systems::TransformSystem,
     };
-    use nalgebra::Vector3;
+    use nalgebra::{Matrix4, Point3, UnitQuaternion, Vector3};
     use specs::prelude::*;
     use specs_hierarchy::HierarchySystem;
+    use std::f32::consts::{FRAC_1_SQRT_2, FRAC_PI_2, FRAC_PI_4, FRAC_PI_6};
+
+    const EPSILON: f32 = 1e-4;
 
     fn world<'a, 'b>() -> (World, Dispatcher<'a, 'b>) {
         let mut world = World::new();
@@ -226,4 +348,377 @@ mod test {
         // Actual result should be the same as simulated result
         assert_eq!(abs_tra_e1, abs_tra);
     }
+
+    // Test if writes that do not change anything are ignored, and that real changes are seen once
+    #[test]
+    fn change_detection() {
+        let (mut world, mut dispatcher) = world();
+
+        let tra = Transform::from(Vector3::new(5.9, 3.9, 1.0));
+        let e1 = world.create_entity().with(tra.clone()).build();
+
+        dispatcher.dispatch(&world.res);
+        assert!(world
+            .read_resource::<DirtyEntities>()
+            .dirty
+            .contains(e1.id()));
+
+        // Same transform, so nothing should be flagged
+        assert!(!world.write_storage::<Transform>().set(e1, tra.clone()));
+        dispatcher.dispatch(&world.res);
+        assert!(!world
+            .read_resource::<DirtyEntities>()
+            .dirty
+            .contains(e1.id()));
+
+        // Actually moved
+        assert!(world
+            .write_storage::<Transform>()
+            .set_translation(e1, Vector3::new(1.0, 2.0, 3.0)));
+        dispatcher.dispatch(&world.res);
+        assert!(world
+            .read_resource::<DirtyEntities>()
+            .dirty
+            .contains(e1.id()));
+
+        // Only once
+        dispatcher.dispatch(&world.res);
+        assert!(!world
+            .read_resource::<DirtyEntities>()
+            .dirty
+            .contains(e1.id()));
+    }
+
+    // Test if queries see moves the TransformSystem has not synced yet, and match it once it has
+    #[test]
+    fn query() {
+        let (mut world, mut dispatcher) = world();
+
+        let parent = world
+            .create_entity()
+            .with(Transform::from(Vector3::new(1.0, 0.0, 0.0)))
+            .build();
+        let mut scaled = Transform::from(Vector3::new(0.0, 2.0, 0.0));
+        scaled.set_scale(Vector3::new(2.0, 2.0, 2.0));
+        let child = world
+            .create_entity()
+            .with(scaled)
+            .with(Link::new(parent))
+            .build();
+
+        world.maintain();
+        dispatcher.dispatch(&world.res);
+
+        world
+            .write_storage::<Transform>()
+            .set_translation(parent, Vector3::new(5.0, 0.0, 0.0));
+
+        let query = TransformQuery::from_world(&world);
+        let global = query.global(child).unwrap();
+        assert_eq!(global.translation(), &Vector3::new(5.0, 2.0, 0.0));
+
+        let local = Point3::new(1.0, 1.0, 1.0);
+        let world_point = query.local_to_world(child, &local).unwrap();
+        assert_eq!(world_point, Point3::new(7.0, 4.0, 2.0));
+        assert_eq!(query.world_to_local(child, &world_point).unwrap(), local);
+        drop(query);
+
+        dispatcher.dispatch(&world.res);
+        assert_eq!(
+            world
+                .read_storage::<GlobalTransform>()
+                .get(child)
+                .unwrap()
+                .global,
+            global
+        );
+    }
+
+    // Test if moving only a parent marks every descendant dirty in the same frame, through
+    // entities without a Transform of their own
+    #[test]
+    fn parent_moves_descendants() {
+        let (mut world, mut dispatcher) = world();
+
+        let parent = world.create_entity().with(Transform::default()).build();
+        let group = world.create_entity().with(Link::new(parent)).build();
+        let child = world
+            .create_entity()
+            .with(Transform::default())
+            .with(Link::new(group))
+            .build();
+        let grandchild = world
+            .create_entity()
+            .with(Transform::from(Vector3::new(0.0, 1.0, 0.0)))
+            .with(Link::new(child))
+            .build();
+
+        world.maintain();
+        dispatcher.dispatch(&world.res);
+        dispatcher.dispatch(&world.res);
+        assert!(!world
+            .read_resource::<DirtyEntities>()
+            .dirty
+            .contains(grandchild.id()));
+
+        world
+            .write_storage::<Transform>()
+            .set_translation(parent, Vector3::new(3.0, 0.0, 0.0));
+        dispatcher.dispatch(&world.res);
+
+        let dirty = &world.read_resource::<DirtyEntities>().dirty;
+        assert!(dirty.contains(parent.id()));
+        assert!(dirty.contains(child.id()));
+        assert!(dirty.contains(grandchild.id()));
+
+        let globals = world.read_storage::<GlobalTransform>();
+        assert_eq!(
+            globals.get(grandchild).unwrap().translation(),
+            &Vector3::new(3.0, 1.0, 0.0)
+        );
+    }
+
+    // Test if entities that moved in earlier steps are kept in moved until it is taken, even
+    // though dirty only holds the last step
+    #[test]
+    fn moved_over_steps() {
+        let (mut world, mut dispatcher) = world();
+
+        let first = world.create_entity().with(Transform::default()).build();
+        let second = world.create_entity().with(Transform::default()).build();
+        world.maintain();
+        dispatcher.dispatch(&world.res);
+        world.write_resource::<DirtyEntities>().moved.clear();
+
+        world
+            .write_storage::<Transform>()
+            .set_translation(first, Vector3::new(1.0, 0.0, 0.0));
+        dispatcher.dispatch(&world.res);
+        world
+            .write_storage::<Transform>()
+            .set_translation(second, Vector3::new(2.0, 0.0, 0.0));
+        dispatcher.dispatch(&world.res);
+
+        let dirty_entities = world.read_resource::<DirtyEntities>();
+        assert!(!dirty_entities.dirty.contains(first.id()));
+        assert!(dirty_entities.dirty.contains(second.id()));
+        assert!(dirty_entities.moved.contains(first.id()));
+        assert!(dirty_entities.moved.contains(second.id()));
+    }
+
+    // Hierarchies
+    // -----------------------------------------------------------------------------------------------------
+
+    fn assert_matrix_eq(actual: &Matrix4<f32>, expected: &Matrix4<f32>) {
+        assert!(
+            (actual - expected).norm() < EPSILON,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    /// Checks the GlobalTransform of an entity, and that a TransformQuery agrees with it
+    fn assert_global(world: &World, entity: Entity, expected: &Matrix4<f32>) {
+        let globals = world.read_storage::<GlobalTransform>();
+        assert_matrix_eq(&globals.get(entity).unwrap().to_matrix(), expected);
+
+        let query = TransformQuery::from_world(world);
+        assert_matrix_eq(&query.global(entity).unwrap().to_matrix(), expected);
+    }
+
+    fn grandparent_t() -> Transform {
+        Transform::from_parts(
+            Vector3::new(1.0, 2.0, 3.0),
+            UnitQuaternion::from_axis_angle(&Vector3::y_axis(), FRAC_PI_2),
+            Vector3::new(2.0, 2.0, 2.0),
+        )
+    }
+
+    fn parent_t() -> Transform {
+        Transform::from_parts(
+            Vector3::new(0.0, 1.0, 0.0),
+            UnitQuaternion::from_axis_angle(&Vector3::x_axis(), FRAC_PI_4),
+            Vector3::new(0.5, 0.5, 0.5),
+        )
+    }
+
+    fn child_t() -> Transform {
+        Transform::from_parts(
+            Vector3::new(0.0, 0.0, -1.0),
+            UnitQuaternion::from_axis_angle(&Vector3::z_axis(), FRAC_PI_6),
+            Vector3::new(3.0, 1.0, 2.0),
+        )
+    }
+
+    /// Builds grandparent, parent and child, created from the bottom up so the hierarchy has to
+    /// order them
+    fn three_generations(world: &mut World) -> (Entity, Entity, Entity) {
+        let child = world.create_entity().with(child_t()).build();
+        let parent = world.create_entity().with(parent_t()).build();
+        let grandparent = world.create_entity().with(grandparent_t()).build();
+
+        {
+            let mut links = world.write_storage::<Link>();
+            links.insert(child, Link::new(parent)).unwrap();
+            links.insert(parent, Link::new(grandparent)).unwrap();
+        }
+        world.maintain();
+
+        (grandparent, parent, child)
+    }
+
+    // Test if every level of a deep hierarchy with rotations and scales is composed correctly
+    #[test]
+    fn deep_hierarchy() {
+        let (mut world, mut dispatcher) = world();
+        let (grandparent, parent, child) = three_generations(&mut world);
+
+        dispatcher.dispatch(&world.res);
+
+        let (gp, p, c) = (grandparent_t(), parent_t(), child_t());
+        let child_matrix = gp.to_matrix() * p.to_matrix() * c.to_matrix();
+        assert_global(&world, grandparent, &gp.to_matrix());
+        assert_global(&world, parent, &(gp.to_matrix() * p.to_matrix()));
+        assert_global(&world, child, &child_matrix);
+
+        // Worked out by hand, in case the matrices themselves change. The child's offset is
+        // turned by the parent and then the grandparent, and scaled by both
+        let global = world
+            .read_storage::<GlobalTransform>()
+            .get(child)
+            .unwrap()
+            .global
+            .clone();
+        let translation = Vector3::new(1.0 - FRAC_1_SQRT_2, 4.0 + FRAC_1_SQRT_2, 3.0);
+        assert!((global.translation() - translation).norm() < EPSILON);
+        assert!((global.scale() - Vector3::new(3.0, 1.0, 2.0)).norm() < EPSILON);
+        let rotation = gp.rotation() * p.rotation() * c.rotation();
+        assert_matrix_eq(
+            &global.rotation().to_homogeneous(),
+            &rotation.to_homogeneous(),
+        );
+
+        // Nothing moves, so nothing changes on later frames
+        dispatcher.dispatch(&world.res);
+        dispatcher.dispatch(&world.res);
+        assert!(world.read_resource::<DirtyEntities>().dirty.is_empty());
+        assert_global(&world, child, &child_matrix);
+    }
+
+    // Test if moving an ancestor over several frames is followed every frame, with the
+    // descendants flagged as dirty in every one of them, and their previous global transform kept
+    // for interpolation
+    #[test]
+    fn moving_over_frames() {
+        let (mut world, mut dispatcher) = world();
+        let (grandparent, parent, child) = three_generations(&mut world);
+        dispatcher.dispatch(&world.res);
+
+        let (mut gp, p, c) = (grandparent_t(), parent_t(), child_t());
+        for frame in 1..=5 {
+            let before = world
+                .read_storage::<GlobalTransform>()
+                .get(child)
+                .unwrap()
+                .to_matrix();
+
+            gp.set_translation(Vector3::new(frame as f32, 2.0, 3.0));
+            gp.rotate_local(UnitQuaternion::from_axis_angle(&Vector3::x_axis(), 0.1));
+            world
+                .write_storage::<Transform>()
+                .set(grandparent, gp.clone());
+            dispatcher.dispatch(&world.res);
+
+            assert_global(&world, parent, &(gp.to_matrix() * p.to_matrix()));
+            assert_global(
+                &world,
+                child,
+                &(gp.to_matrix() * p.to_matrix() * c.to_matrix()),
+            );
+
+            let dirty_entities = world.read_resource::<DirtyEntities>();
+            assert!(dirty_entities.dirty.contains(child.id()));
+
+            let previous = world.read_storage::<PreviousGlobalTransform>();
+            assert_matrix_eq(&previous.get(child).unwrap().global.to_matrix(), &before);
+        }
+
+        // A frame without movement leaves them alone again, and drops the previous transforms
+        dispatcher.dispatch(&world.res);
+        assert!(!world
+            .read_resource::<DirtyEntities>()
+            .dirty
+            .contains(child.id()));
+        assert!(world
+            .read_storage::<PreviousGlobalTransform>()
+            .get(child)
+            .is_none());
+    }
+
+    // Test if reparenting, moving the new parent in the same frame, and unparenting are all
+    // picked up
+    #[test]
+    fn reparenting() {
+        let (mut world, mut dispatcher) = world();
+        let (grandparent, parent, child) = three_generations(&mut world);
+
+        let other_t = Transform::from_parts(
+            Vector3::new(-4.0, 0.0, 0.0),
+            UnitQuaternion::from_axis_angle(&Vector3::z_axis(), FRAC_PI_2),
+            Vector3::new(0.5, 0.5, 0.5),
+        );
+        let other = world.create_entity().with(other_t.clone()).build();
+        world.maintain();
+        dispatcher.dispatch(&world.res);
+
+        let (gp, p, c) = (grandparent_t(), parent_t(), child_t());
+
+        // The child moves over to another root
+        world
+            .write_storage::<Link>()
+            .insert(child, Link::new(other))
+            .unwrap();
+        dispatcher.dispatch(&world.res);
+        assert_global(&world, child, &(other_t.to_matrix() * c.to_matrix()));
+        assert_global(&world, parent, &(gp.to_matrix() * p.to_matrix()));
+
+        // The old parent moving no longer moves the child
+        world
+            .write_storage::<Transform>()
+            .set_translation(parent, Vector3::new(10.0, 0.0, 0.0));
+        dispatcher.dispatch(&world.res);
+        assert!(!world
+            .read_resource::<DirtyEntities>()
+            .dirty
+            .contains(child.id()));
+        assert_global(&world, child, &(other_t.to_matrix() * c.to_matrix()));
+
+        // Reparented below the moved parent, and the new chain moved in the same frame
+        let mut moved_gp = gp.clone();
+        moved_gp.set_scale(Vector3::new(1.0, 1.0, 1.0));
+        world
+            .write_storage::<Link>()
+            .insert(child, Link::new(parent))
+            .unwrap();
+        world
+            .write_storage::<Transform>()
+            .set(grandparent, moved_gp.clone());
+        dispatcher.dispatch(&world.res);
+        let moved_p = world
+            .read_storage::<Transform>()
+            .get(parent)
+            .unwrap()
+            .clone();
+        assert_global(
+            &world,
+            child,
+            &(moved_gp.to_matrix() * moved_p.to_matrix() * c.to_matrix()),
+        );
+
+        // Without a parent, the child is a root again
+        world.write_storage::<Link>().remove(child);
+        dispatcher.dispatch(&world.res);
+        assert_global(&world, child, &c.to_matrix());
+    }
 }
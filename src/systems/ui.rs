@@ -0,0 +1,272 @@
+use crate::renderer::overlay::Overlay;
+use image::{Rgba, RgbaImage};
+use specs::prelude::*;
+use specs_derive::Component;
+
+/// A point of the screen a HUD element is kept at
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Anchor {
+    TopLeft,
+    Top,
+    TopRight,
+    Left,
+    Center,
+    Right,
+    BottomLeft,
+    Bottom,
+    BottomRight,
+}
+
+impl Anchor {
+    /// Where the anchor is, as a fraction of the screen from the top left
+    pub fn fraction(self) -> [f32; 2] {
+        match self {
+            Anchor::TopLeft => [0.0, 0.0],
+            Anchor::Top => [0.5, 0.0],
+            Anchor::TopRight => [1.0, 0.0],
+            Anchor::Left => [0.0, 0.5],
+            Anchor::Center => [0.5, 0.5],
+            Anchor::Right => [1.0, 0.5],
+            Anchor::BottomLeft => [0.0, 1.0],
+            Anchor::Bottom => [0.5, 1.0],
+            Anchor::BottomRight => [1.0, 1.0],
+        }
+    }
+
+    /// The same point of the element itself, so an element anchored to a corner sits inside it
+    pub fn pivot(self) -> [f32; 2] {
+        self.fraction()
+    }
+}
+
+/// How the size and offset of a HUD element follow the size of the screen
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ScaleMode {
+    /// The same number of pixels on any screen
+    Constant,
+    /// Laid out for a screen this many pixels high, and scaled with the actual height, so the
+    /// element covers as much of a bigger or denser screen
+    WithHeight(f32),
+}
+
+/// Lays out a HUD element relative to a point of the screen, resolved every frame against the
+/// size of the swapchain
+///
+/// The `pivot` of the element, as a fraction of its size from its top left, is put at the
+/// `anchor`, moved by `offset` pixels. Sizes and offsets are in the pixels of the `scale_mode`.
+#[derive(Component, Debug, Clone)]
+#[storage(HashMapStorage)]
+pub struct UiAnchor {
+    pub anchor: Anchor,
+    pub offset: [f32; 2],
+    pub pivot: [f32; 2],
+    pub scale_mode: ScaleMode,
+}
+
+impl UiAnchor {
+    /// Kept at `anchor`, inside the screen
+    pub fn new(anchor: Anchor) -> Self {
+        Self {
+            anchor,
+            offset: [0.0, 0.0],
+            pivot: anchor.pivot(),
+            scale_mode: ScaleMode::Constant,
+        }
+    }
+
+    pub fn with_offset(mut self, offset: [f32; 2]) -> Self {
+        self.offset = offset;
+        self
+    }
+
+    pub fn with_scale_mode(mut self, scale_mode: ScaleMode) -> Self {
+        self.scale_mode = scale_mode;
+        self
+    }
+
+    /// How many screen pixels a pixel of the element is on a screen of `dimensions`
+    pub fn scale(&self, dimensions: [f32; 2]) -> f32 {
+        match self.scale_mode {
+            ScaleMode::Constant => 1.0,
+            ScaleMode::WithHeight(height) => dimensions[1] / height,
+        }
+    }
+
+    /// The top left of an element `size` screen pixels big, on a screen of `dimensions`
+    ///
+    /// The size is already scaled, see `scale`.
+    pub fn resolve(&self, size: [f32; 2], dimensions: [f32; 2]) -> [f32; 2] {
+        let scale = self.scale(dimensions);
+        let anchor = self.anchor.fraction();
+
+        [
+            anchor[0] * dimensions[0] + self.offset[0] * scale - self.pivot[0] * size[0],
+            anchor[1] * dimensions[1] + self.offset[1] * scale - self.pivot[1] * size[1],
+        ]
+    }
+}
+
+/// Text drawn over the screen where its UiAnchor puts it
+#[derive(Component, Debug, Clone)]
+#[storage(HashMapStorage)]
+pub struct UiText {
+    pub text: String,
+    /// Height in the pixels of the UiAnchor's scale mode
+    pub size: f32,
+    /// Gamma encoded, like the overlay
+    pub color: [f32; 4],
+}
+
+impl UiText {
+    pub fn new(text: &str, size: f32) -> Self {
+        Self {
+            text: text.to_string(),
+            size,
+            color: [1.0, 1.0, 1.0, 1.0],
+        }
+    }
+
+    pub fn with_color(mut self, color: [f32; 4]) -> Self {
+        self.color = color;
+        self
+    }
+}
+
+/// A sprite added to the Overlay, drawn over the screen where its UiAnchor puts it
+#[derive(Component, Debug, Clone)]
+#[storage(HashMapStorage)]
+pub struct UiSprite {
+    /// The name the sprite was added to the Overlay with
+    pub name: String,
+    /// Size in the pixels of the UiAnchor's scale mode
+    pub size: [f32; 2],
+    /// Tints the sprite, gamma encoded like the overlay
+    pub color: [f32; 4],
+}
+
+impl UiSprite {
+    pub fn new(name: &str, size: [f32; 2]) -> Self {
+        Self {
+            name: name.to_string(),
+            size,
+            color: [1.0, 1.0, 1.0, 1.0],
+        }
+    }
+}
+
+/// A white cross `size` pixels wide, with arms `thickness` pixels thick, to aim with
+pub fn crosshair(size: u32, thickness: u32) -> RgbaImage {
+    let start = (size - thickness) / 2;
+    let on_arm = |i| i >= start && i < start + thickness;
+
+    RgbaImage::from_fn(size, size, |x, y| {
+        if on_arm(x) || on_arm(y) {
+            Rgba([255, 255, 255, 255])
+        } else {
+            Rgba([0, 0, 0, 0])
+        }
+    })
+}
+
+/// Draws every UiText and UiSprite into the Overlay, laid out by its UiAnchor
+///
+/// The overlay knows the size of the swapchain as of the last frame, so elements follow a resize
+/// from the frame after it.
+#[derive(Debug, Default)]
+pub struct UiLayoutSystem;
+
+impl<'a> System<'a> for UiLayoutSystem {
+    type SystemData = (
+        Write<'a, Overlay>,
+        ReadStorage<'a, UiAnchor>,
+        ReadStorage<'a, UiText>,
+        ReadStorage<'a, UiSprite>,
+    );
+
+    fn run(&mut self, (mut overlay, anchors, texts, sprites): Self::SystemData) {
+        // Nothing has been drawn yet to know the size of the screen from
+        let dimensions = overlay.dimensions();
+        if dimensions[0] == 0.0 || dimensions[1] == 0.0 {
+            return;
+        }
+
+        for (anchor, text) in (&anchors, &texts).join() {
+            let size = text.size * anchor.scale(dimensions);
+            let extent = overlay.measure_text(&text.text, size);
+            let position = anchor.resolve(extent, dimensions);
+
+            overlay.text(&text.text, position, size, text.color);
+        }
+
+        for (anchor, sprite) in (&anchors, &sprites).join() {
+            let scale = anchor.scale(dimensions);
+            let size = [sprite.size[0] * scale, sprite.size[1] * scale];
+            let position = anchor.resolve(size, dimensions);
+
+            overlay.sprite(&sprite.name, position, size, sprite.color);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{crosshair, Anchor, ScaleMode, UiAnchor};
+
+    fn assert_near(actual: [f32; 2], expected: [f32; 2]) {
+        assert!(
+            (actual[0] - expected[0]).abs() < 1e-4 && (actual[1] - expected[1]).abs() < 1e-4,
+            "{:?} != {:?}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn resolve() {
+        let dimensions = [800.0, 600.0];
+        let size = [100.0, 20.0];
+
+        // Corners keep the element inside the screen
+        assert_near(
+            UiAnchor::new(Anchor::TopLeft).resolve(size, dimensions),
+            [0.0, 0.0],
+        );
+        assert_near(
+            UiAnchor::new(Anchor::BottomRight).resolve(size, dimensions),
+            [700.0, 580.0],
+        );
+        assert_near(
+            UiAnchor::new(Anchor::Center).resolve(size, dimensions),
+            [350.0, 290.0],
+        );
+
+        // The offset moves it away from the anchor, the pivot picks the point put there
+        let anchor = UiAnchor::new(Anchor::BottomRight).with_offset([-8.0, -8.0]);
+        assert_near(anchor.resolve(size, dimensions), [692.0, 572.0]);
+        let anchor = UiAnchor {
+            pivot: [0.0, 0.0],
+            ..UiAnchor::new(Anchor::Top)
+        };
+        assert_near(anchor.resolve(size, dimensions), [400.0, 0.0]);
+
+        // Scaled with the height, the offset is scaled too, but the size is given scaled already
+        let anchor = UiAnchor::new(Anchor::TopRight)
+            .with_offset([-10.0, 10.0])
+            .with_scale_mode(ScaleMode::WithHeight(300.0));
+        assert!((anchor.scale(dimensions) - 2.0).abs() < 1e-4);
+        assert_near(anchor.resolve(size, dimensions), [680.0, 20.0]);
+
+        // And follows a resize
+        assert_near(anchor.resolve(size, [1600.0, 1200.0]), [1460.0, 40.0]);
+    }
+
+    #[test]
+    fn cross() {
+        let image = crosshair(9, 1);
+        assert_eq!((image.width(), image.height()), (9, 9));
+        assert_eq!(image.get_pixel(4, 0).data[3], 255);
+        assert_eq!(image.get_pixel(0, 4).data[3], 255);
+        assert_eq!(image.get_pixel(0, 0).data[3], 0);
+        assert_eq!(image.get_pixel(3, 3).data[3], 0);
+    }
+}
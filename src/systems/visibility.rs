@@ -0,0 +1,139 @@
+use crate::{
+    components::{Hidden, Link, VisibilityInherit},
+    resources::HiddenEntities,
+};
+use log::warn;
+use specs::prelude::*;
+use specs_hierarchy::Parent;
+
+/// Finds the entities that are hidden, see `Hidden` and `VisibilityInherit`
+///
+/// Has to run after the HierarchySystem, so the parents are up to date.
+#[derive(Debug, Default)]
+pub struct VisibilitySystem;
+
+impl<'a> System<'a> for VisibilitySystem {
+    type SystemData = (
+        Entities<'a>,
+        ReadStorage<'a, Hidden>,
+        ReadStorage<'a, VisibilityInherit>,
+        ReadStorage<'a, Link>,
+        Write<'a, HiddenEntities>,
+    );
+
+    fn run(&mut self, (entities, hidden, inherit, links, mut hidden_entities): Self::SystemData) {
+        let mut set = hidden.mask().clone();
+
+        for (entity, _) in (&entities, &inherit).join() {
+            // Up the hierarchy until a parent is hidden, or one does not inherit
+            let mut visited = BitSet::new();
+            let mut current = entity;
+            while inherit.contains(current) {
+                current = match links.get(current) {
+                    Some(link) => link.parent_entity(),
+                    None => break,
+                };
+
+                // Links that loop back have no root to inherit from, so the entity stays visible
+                if visited.add(current.id()) {
+                    warn!("{:?} inherits its visibility from a cycle of links", entity);
+                    break;
+                }
+
+                if hidden.contains(current) {
+                    set.add(entity.id());
+                    break;
+                }
+            }
+        }
+
+        hidden_entities.hidden = set;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::VisibilitySystem;
+    use crate::{
+        components::{Hidden, Link, VisibilityInherit},
+        resources::HiddenEntities,
+    };
+    use specs::prelude::*;
+
+    #[test]
+    fn inherit() {
+        let mut world = World::new();
+        world.register::<Hidden>();
+        world.register::<VisibilityInherit>();
+        world.register::<Link>();
+
+        let mut dispatcher = DispatcherBuilder::new()
+            .with(VisibilitySystem, "visibility", &[])
+            .build();
+        dispatcher.setup(&mut world.res);
+
+        let root = world.create_entity().with(Hidden).build();
+        let child = world
+            .create_entity()
+            .with(Link::new(root))
+            .with(VisibilityInherit)
+            .build();
+        let grandchild = world
+            .create_entity()
+            .with(Link::new(child))
+            .with(VisibilityInherit)
+            .build();
+        let independent = world.create_entity().with(Link::new(root)).build();
+        let below_independent = world
+            .create_entity()
+            .with(Link::new(independent))
+            .with(VisibilityInherit)
+            .build();
+
+        dispatcher.dispatch(&world.res);
+        {
+            let hidden = &world.read_resource::<HiddenEntities>().hidden;
+            assert!(hidden.contains(root.id()));
+            assert!(hidden.contains(child.id()));
+            assert!(hidden.contains(grandchild.id()));
+            assert!(!hidden.contains(independent.id()));
+            assert!(!hidden.contains(below_independent.id()));
+        }
+
+        // Showing the root shows everything inheriting from it
+        world.write_storage::<Hidden>().remove(root);
+        dispatcher.dispatch(&world.res);
+        let hidden = &world.read_resource::<HiddenEntities>().hidden;
+        assert!(!hidden.contains(grandchild.id()));
+    }
+
+    #[test]
+    fn cycle() {
+        let mut world = World::new();
+        world.register::<Hidden>();
+        world.register::<VisibilityInherit>();
+        world.register::<Link>();
+
+        let mut dispatcher = DispatcherBuilder::new()
+            .with(VisibilitySystem, "visibility", &[])
+            .build();
+        dispatcher.setup(&mut world.res);
+
+        // Linked to each other without the HierarchySystem to catch it
+        let first = world.create_entity().with(VisibilityInherit).build();
+        let second = world
+            .create_entity()
+            .with(Link::new(first))
+            .with(VisibilityInherit)
+            .build();
+        world
+            .write_storage::<Link>()
+            .insert(first, Link::new(second))
+            .unwrap();
+
+        dispatcher.dispatch(&world.res);
+        let hidden = &world.read_resource::<HiddenEntities>().hidden;
+        assert!(!hidden.contains(first.id()));
+        assert!(!hidden.contains(second.id()));
+    }
+}